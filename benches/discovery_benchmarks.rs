@@ -1,6 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use auto_discovery::{
     config::DiscoveryConfig,
+    registry::{ServiceFilter, ServiceRegistry},
     service::ServiceInfo,
     types::{ProtocolType, ServiceType},
 };
@@ -90,10 +91,67 @@ fn config_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+/// Services in the registry used by the lookup benchmarks
+const REGISTRY_SIZE: usize = 5000;
+
+/// Benchmark registry lookups on a registry of thousands of services
+fn registry_benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+
+    let registry = ServiceRegistry::with_settings(Duration::from_secs(3600), REGISTRY_SIZE);
+    rt.block_on(async {
+        for i in 0..REGISTRY_SIZE {
+            // 50 service types, two protocols and ten teams
+            let service = ServiceInfo::new(
+                format!("service-{i}"),
+                format!("_type{}._tcp", i % 50),
+                8080,
+                None,
+            )
+            .unwrap()
+            .with_tag(format!("team={}", i % 10));
+            let protocol = if i % 2 == 0 { ProtocolType::Mdns } else { ProtocolType::Upnp };
+            registry.register_local_service(service, protocol).await.unwrap();
+        }
+    });
+    let service_type = ServiceType::new("_type7._tcp").unwrap();
+
+    let mut group = c.benchmark_group("registry");
+    group.measurement_time(BENCH_MEASUREMENT_TIME);
+    group.sample_size(100);
+
+    group.bench_function("get_services_by_type", |b| {
+        b.to_async(&rt).iter(|| registry.get_services_by_type(&service_type));
+    });
+
+    group.bench_function("get_services_by_name", |b| {
+        b.to_async(&rt).iter(|| registry.get_services_by_name("service-4242"));
+    });
+
+    group.bench_function("get_services_by_tags", |b| {
+        b.to_async(&rt).iter(|| registry.get_services_by_tags(["team=3"]));
+    });
+
+    group.bench_function("find_services_type_and_protocol", |b| {
+        let filter = ServiceFilter::new()
+            .with_service_types(vec![service_type.clone()])
+            .with_protocols(vec![ProtocolType::Upnp]);
+        b.to_async(&rt).iter(|| registry.find_services(&filter));
+    });
+
+    group.bench_function("find_services_unindexed", |b| {
+        let filter = ServiceFilter::new().with_name_contains("4242".to_string());
+        b.to_async(&rt).iter(|| registry.find_services(&filter));
+    });
+
+    group.finish();
+}
+
 criterion_group!(
     benches,
     service_creation_benchmark,
     service_type_benchmark,
-    config_benchmark
+    config_benchmark,
+    registry_benchmark
 );
 criterion_main!(benches);
@@ -0,0 +1,276 @@
+//! Typed access to service attributes
+//!
+//! TXT attributes are plain strings. [`Attributes::deserialize_into`] turns
+//! them into a user struct with `serde`: values are parsed into whatever type
+//! each field asks for, booleans accept the usual TXT spellings (including a
+//! bare key), and sequences are comma separated.
+//!
+//! ```rust
+//! use auto_discovery::{attributes::Attributes, ServiceInfo};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Printer {
+//!     rp: String,
+//!     pages: u32,
+//!     duplex: bool,
+//!     note: Option<String>,
+//! }
+//!
+//! # fn main() -> auto_discovery::Result<()> {
+//! let service = ServiceInfo::new(
+//!     "printer",
+//!     "_ipp._tcp",
+//!     631,
+//!     Some(vec![("rp", "ipp/print"), ("pages", "20"), ("duplex", "T")]),
+//! )?;
+//! let printer: Printer = service.attributes.deserialize_into()?;
+//! assert_eq!(printer.pages, 20);
+//! assert!(printer.duplex && printer.note.is_none());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{DiscoveryError, Result},
+    types::ServiceAttributes,
+};
+use serde::de::{
+    self,
+    value::{MapDeserializer, SeqDeserializer},
+    DeserializeOwned, IntoDeserializer, Visitor,
+};
+use std::{fmt, str::FromStr};
+
+/// Typed views of a set of service attributes
+pub trait Attributes {
+    /// Deserialize the attributes into `T`, one attribute per field
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::Attribute`] naming the offending key if an
+    /// attribute is missing or does not parse as its field's type
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T>;
+}
+
+impl Attributes for ServiceAttributes {
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T> {
+        let entries = self
+            .iter()
+            .map(|(key, value)| (key.as_str(), ValueDeserializer { key, value }));
+        T::deserialize(MapDeserializer::new(entries)).map_err(|e: AttributeError| {
+            DiscoveryError::attribute(e.key.unwrap_or_default(), e.reason)
+        })
+    }
+}
+
+/// Parse a TXT boolean
+///
+/// A bare key (empty value) is true, as in RFC 6763.
+pub(crate) fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "1" | "t" | "true" | "y" | "yes" | "on" => Some(true),
+        "0" | "f" | "false" | "n" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct AttributeError {
+    key: Option<String>,
+    reason: String,
+}
+
+impl fmt::Display for AttributeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "attribute '{key}': {}", self.reason),
+            None => f.write_str(&self.reason),
+        }
+    }
+}
+
+impl std::error::Error for AttributeError {}
+
+impl de::Error for AttributeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self {
+            key: None,
+            reason: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self {
+            key: Some(field.to_string()),
+            reason: "missing".to_string(),
+        }
+    }
+}
+
+/// Deserializes one attribute value, parsing it on demand
+#[derive(Clone, Copy)]
+struct ValueDeserializer<'de> {
+    key: &'de str,
+    value: &'de str,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn error(&self, reason: impl fmt::Display) -> AttributeError {
+        AttributeError {
+            key: Some(self.key.to_string()),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn parse<T: FromStr>(&self, expected: &str) -> std::result::Result<T, AttributeError> {
+        self.value
+            .trim()
+            .parse()
+            .map_err(|_| self.error(format!("expected {expected}, found '{}'", self.value)))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, AttributeError> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident($ty:ty, $expected:literal),)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Self::Error> {
+                visitor.$visit(self.parse::<$ty>($expected)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = AttributeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.value)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Self::Error> {
+        match parse_bool(self.value) {
+            Some(value) => visitor.visit_bool(value),
+            None => Err(self.error(format!("expected a boolean, found '{}'", self.value))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8(i8, "an integer"),
+        deserialize_i16 => visit_i16(i16, "an integer"),
+        deserialize_i32 => visit_i32(i32, "an integer"),
+        deserialize_i64 => visit_i64(i64, "an integer"),
+        deserialize_i128 => visit_i128(i128, "an integer"),
+        deserialize_u8 => visit_u8(u8, "an unsigned integer"),
+        deserialize_u16 => visit_u16(u16, "an unsigned integer"),
+        deserialize_u32 => visit_u32(u32, "an unsigned integer"),
+        deserialize_u64 => visit_u64(u64, "an unsigned integer"),
+        deserialize_u128 => visit_u128(u128, "an unsigned integer"),
+        deserialize_f32 => visit_f32(f32, "a number"),
+        deserialize_f64 => visit_f64(f64, "a number"),
+        deserialize_char => visit_char(char, "a single character"),
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> std::result::Result<V::Value, Self::Error> {
+        let key = self.key;
+        let items = self
+            .value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(move |value| ValueDeserializer { key, value });
+        visitor.visit_seq(SeqDeserializer::new(items))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> std::result::Result<V::Value, Self::Error> {
+        visitor.visit_enum(self.value.into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Fast,
+        Safe,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        port_alt: u16,
+        secure: bool,
+        tags: Vec<String>,
+        mode: Mode,
+        ratio: Option<f64>,
+    }
+
+    fn attributes(pairs: &[(&str, &str)]) -> ServiceAttributes {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>()
+    }
+
+    #[test]
+    fn test_deserialize_into() {
+        let attrs = attributes(&[
+            ("port_alt", "8443"),
+            ("secure", ""),
+            ("tags", "a, b,c"),
+            ("mode", "safe"),
+            ("extra", "ignored"),
+        ]);
+        let settings: Settings = attrs.deserialize_into().unwrap();
+        assert_eq!(settings.port_alt, 8443);
+        assert!(settings.secure);
+        assert_eq!(settings.tags, ["a", "b", "c"]);
+        assert_eq!(settings.mode, Mode::Safe);
+        assert_eq!(settings.ratio, None);
+    }
+
+    #[test]
+    fn test_deserialize_errors_name_the_key() {
+        let attrs = attributes(&[("port_alt", "99999"), ("secure", "1"), ("tags", ""), ("mode", "fast")]);
+        match attrs.deserialize_into::<Settings>() {
+            Err(DiscoveryError::Attribute { key, .. }) => assert_eq!(key, "port_alt"),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        let attrs = attributes(&[("port_alt", "1"), ("tags", ""), ("mode", "fast")]);
+        match attrs.deserialize_into::<Settings>() {
+            Err(DiscoveryError::Attribute { key, reason }) => assert_eq!((key.as_str(), reason.as_str()), ("secure", "missing")),
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+}
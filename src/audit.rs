@@ -0,0 +1,294 @@
+//! Append-only audit log of discovery activity
+//!
+//! When enabled, [`ServiceDiscovery`](crate::ServiceDiscovery) records every
+//! registration, unregistration, discovery query, verification and security
+//! alert as an [`AuditRecord`] with a timestamp and the peer addresses
+//! involved. Records go to a JSON lines file set with
+//! [`DiscoveryConfig::with_audit_log`](crate::config::DiscoveryConfig::with_audit_log),
+//! to sinks added with
+//! [`ServiceDiscoveryBuilder::with_audit_sink`](crate::discovery::ServiceDiscoveryBuilder::with_audit_sink),
+//! or both.
+//!
+//! Auditing never fails the operation being audited: a sink that cannot
+//! record is reported through `tracing` at error level.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    service::{ServiceEvent, ServiceInfo},
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+use tracing::error;
+
+/// What an [`AuditRecord`] records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// A service was registered
+    Registration,
+    /// A service was unregistered
+    Unregistration,
+    /// Services were queried for
+    DiscoveryQuery,
+    /// A service was verified
+    Verification,
+    /// A protocol received a suspicious answer
+    SecurityEvent,
+}
+
+/// One entry of the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// When the action completed
+    pub timestamp: DateTime<Utc>,
+    /// What happened
+    pub action: AuditAction,
+    /// Protocol the action went through, if it was a single one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<ProtocolType>,
+    /// Name of the service acted on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Service types acted on or queried for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub service_types: Vec<ServiceType>,
+    /// Addresses of the peers involved
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peers: Vec<IpAddr>,
+    /// Whether the action succeeded
+    ///
+    /// For verifications, whether the service was healthy; for security
+    /// events, whether the answer was let through.
+    pub success: bool,
+    /// Error or other details
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditRecord {
+    /// Create a successful record of `action`, timestamped now
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            action,
+            protocol: None,
+            service: None,
+            service_types: Vec::new(),
+            peers: Vec::new(),
+            success: true,
+            detail: None,
+        }
+    }
+
+    /// Record of an action on `service`, with the service's address as peer
+    pub fn for_service(action: AuditAction, service: &ServiceInfo) -> Self {
+        Self::new(action)
+            .with_protocol(service.protocol_type())
+            .with_service(service.name())
+            .with_service_types(vec![service.service_type().clone()])
+            .with_peers(vec![service.address])
+    }
+
+    /// Record of a security alert, or `None` for other events
+    pub fn from_event(event: &ServiceEvent) -> Option<Self> {
+        let ServiceEvent::SecurityAlert { protocol, source, reason, quarantined, .. } = event else {
+            return None;
+        };
+        let record = Self::new(AuditAction::SecurityEvent)
+            .with_protocol(*protocol)
+            .with_peers(vec![*source])
+            .with_detail(reason.clone());
+        Some(if *quarantined { record.failed(reason.clone()) } else { record })
+    }
+
+    /// Set the protocol
+    pub fn with_protocol(mut self, protocol: ProtocolType) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Set the service name
+    pub fn with_service<S: Into<String>>(mut self, service: S) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Set the service types
+    pub fn with_service_types(mut self, service_types: Vec<ServiceType>) -> Self {
+        self.service_types = service_types;
+        self
+    }
+
+    /// Set the peer addresses
+    pub fn with_peers(mut self, peers: Vec<IpAddr>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Set the details
+    pub fn with_detail<S: Into<String>>(mut self, detail: S) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Mark the action as failed, with `detail` saying why
+    pub fn failed<S: Into<String>>(mut self, detail: S) -> Self {
+        self.success = false;
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Mark the action as failed if `result` is an error
+    pub fn with_result<T>(self, result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => self,
+            Err(e) => self.failed(e.to_string()),
+        }
+    }
+}
+
+/// Destination for audit records
+///
+/// Implement this to ship records somewhere other than a local file, and add
+/// it with
+/// [`ServiceDiscoveryBuilder::with_audit_sink`](crate::discovery::ServiceDiscoveryBuilder::with_audit_sink).
+/// Records arrive in the order the actions completed.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Store one record
+    async fn record(&self, record: &AuditRecord) -> Result<()>;
+}
+
+/// Appends records to a file, one JSON object per line
+///
+/// The file is opened in append mode and never truncated or rewritten.
+/// Each record is flushed before [`record`](AuditSink::record) returns.
+#[derive(Debug)]
+pub struct JsonLinesAuditSink {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl JsonLinesAuditSink {
+    /// Open `path` for appending, creating it if needed
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| {
+                DiscoveryError::configuration(format!("Cannot open audit log {}: {e}", path.display()))
+            })?;
+        Ok(Self { path, file: Mutex::new(file) })
+    }
+
+    /// Path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[async_trait]
+impl AuditSink for JsonLinesAuditSink {
+    async fn record(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Cannot encode audit record: {e}")))?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// The sinks a discovery instance records to
+#[derive(Clone, Default)]
+pub(crate) struct AuditLog {
+    sinks: Vec<Arc<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(sinks: Vec<Arc<dyn AuditSink>>) -> Self {
+        Self { sinks }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.sinks.is_empty()
+    }
+
+    /// Hand `record` to every sink, logging those that fail
+    pub(crate) async fn record(&self, record: AuditRecord) {
+        for sink in &self.sinks {
+            if let Err(e) = sink.record(&record).await {
+                error!("Failed to write audit record for {:?}: {}", record.action, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn test_json_lines_sink_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let service = ServiceInfo::new("API", "_http._tcp", 8080, None).unwrap();
+
+        let sink = JsonLinesAuditSink::open(&path).await.unwrap();
+        sink.record(&AuditRecord::for_service(AuditAction::Registration, &service))
+            .await
+            .unwrap();
+        drop(sink);
+
+        let sink = JsonLinesAuditSink::open(&path).await.unwrap();
+        let failure: Result<()> = Err(DiscoveryError::timeout("no answer"));
+        sink.record(&AuditRecord::for_service(AuditAction::Verification, &service).with_result(&failure))
+            .await
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<AuditRecord> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].action, AuditAction::Registration);
+        assert_eq!(records[0].service.as_deref(), Some("API"));
+        assert_eq!(records[0].peers, vec![service.address]);
+        assert!(records[0].success);
+        assert_eq!(records[1].action, AuditAction::Verification);
+        assert!(!records[1].success);
+        assert!(records[1].detail.as_deref().unwrap().contains("no answer"));
+        assert!(contents.lines().next().unwrap().contains(r#""action":"registration""#));
+    }
+
+    #[test]
+    fn test_only_security_alerts_become_records() {
+        let alert = ServiceEvent::security_alert(
+            ProtocolType::Upnp,
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)),
+            "LOCATION points elsewhere",
+            true,
+        );
+        let record = AuditRecord::from_event(&alert).unwrap();
+        assert_eq!(record.action, AuditAction::SecurityEvent);
+        assert_eq!(record.peers, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))]);
+        assert!(!record.success);
+
+        let service = ServiceInfo::new("API", "_http._tcp", 8080, None).unwrap();
+        assert!(AuditRecord::from_event(&ServiceEvent::new(service)).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+//! `autodisc`: discover, advertise and watch services from the command line
+//!
+//! ```text
+//! autodisc browse _http._tcp
+//! autodisc register --name "My API" --port 8080 --attr version=1.2
+//! autodisc watch _ipp._tcp --format json
+//! autodisc enumerate-types
+//! ```
+//!
+//! Built with the `cli` feature.
+
+use auto_discovery::{
+    config::{ConflictPolicy, DiscoveryConfig, RegistrationConfig},
+    report::{ReportFormat, ServiceReport},
+    types::{ProtocolType, ServiceType},
+    utils::network,
+    ServiceDiscovery, ServiceInfo,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::{collections::HashSet, error::Error, net::IpAddr, time::Duration};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Parser)]
+#[command(name = "autodisc", version, about = "Discover and advertise network services")]
+struct Cli {
+    #[command(flatten)]
+    options: Options,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Options {
+    /// Protocols to use; repeat for several [default: all built-in protocols]
+    #[arg(long = "protocol", short, global = true, value_enum)]
+    protocols: Vec<Protocol>,
+    /// How long each discovery round listens for answers, in seconds
+    #[arg(long, short, global = true, default_value_t = 3)]
+    timeout: u64,
+    /// Output format
+    #[arg(long, short, global = true, default_value = "table", value_parser = parse_format)]
+    format: ReportFormat,
+    /// Log what the library is doing
+    #[arg(long, short, global = true)]
+    verbose: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Discover services of the given types and print them
+    Browse {
+        /// Service types, such as `_http._tcp` or a UPnP URN
+        #[arg(required = true)]
+        service_types: Vec<String>,
+    },
+    /// Advertise a service until interrupted
+    Register {
+        /// Instance name
+        #[arg(long)]
+        name: String,
+        /// Port the service listens on
+        #[arg(long)]
+        port: u16,
+        /// Service type
+        #[arg(long = "type", default_value = "_http._tcp")]
+        service_type: String,
+        /// Address to advertise [default: the first non-loopback address]
+        #[arg(long)]
+        address: Option<IpAddr>,
+        /// Attribute as `key=value`; repeat for several
+        #[arg(long = "attr", value_parser = parse_attribute)]
+        attributes: Vec<(String, String)>,
+        /// Register with every protocol that supports registration
+        #[arg(long)]
+        everywhere: bool,
+    },
+    /// Rediscover periodically and print services as they come and go
+    Watch {
+        /// Service types [default: `_http._tcp`]
+        service_types: Vec<String>,
+        /// Seconds between discovery rounds
+        #[arg(long, default_value_t = 10)]
+        interval: u64,
+    },
+    /// List the service types present on the network
+    EnumerateTypes,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Protocol {
+    Mdns,
+    Upnp,
+    DnsSd,
+}
+
+impl From<Protocol> for ProtocolType {
+    fn from(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Mdns => ProtocolType::Mdns,
+            Protocol::Upnp => ProtocolType::Upnp,
+            Protocol::DnsSd => ProtocolType::DnsSd,
+        }
+    }
+}
+
+fn parse_format(s: &str) -> Result<ReportFormat, String> {
+    s.parse().map_err(|e: auto_discovery::DiscoveryError| e.to_string())
+}
+
+fn parse_attribute(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got '{s}'"))
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if cli.options.verbose {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("auto_discovery=debug")))
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    if let Err(e) = run(cli).await {
+        eprintln!("autodisc: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let options = cli.options;
+    match cli.command {
+        Command::Browse { service_types } => {
+            let discovery = discovery(&options, &service_types).await?;
+            let services = discovery.discover_services(None).await?;
+            print!("{}", ServiceReport::from_services(&services).render(options.format)?);
+        }
+        Command::Register { name, port, service_type, address, attributes, everywhere } => {
+            let discovery = discovery(&options, &[]).await?;
+            let address = match address {
+                Some(address) => address,
+                None => default_address()?,
+            };
+            let protocol = options.protocols.first().copied().map_or(ProtocolType::Mdns, ProtocolType::from);
+            let mut service = ServiceInfo::new(name, service_type, port, None)?
+                .with_address(address)
+                .with_protocol_type(protocol);
+            for (key, value) in attributes {
+                service = service.with_attribute(key, value);
+            }
+
+            let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::AutoRename { max_attempts: 10 });
+            let registered = if everywhere {
+                let outcome = discovery.register_everywhere(service.clone(), &registration).await?;
+                for (protocol, e) in outcome.failures() {
+                    eprintln!("autodisc: not registered with {protocol}: {e}");
+                }
+                let protocols: Vec<String> = outcome.succeeded().iter().map(ToString::to_string).collect();
+                eprintln!("Registered with {}", protocols.join(", "));
+                outcome.successes()[0].1.clone()
+            } else {
+                discovery.register_service_with_config(service, &registration).await?
+            };
+            print!("{}", ServiceReport::from_services(&[registered]).render(options.format)?);
+            eprintln!("Advertising until interrupted (Ctrl-C)");
+
+            tokio::signal::ctrl_c().await?;
+            discovery.close().await?;
+        }
+        Command::Watch { service_types, interval } => {
+            let service_types = if service_types.is_empty() { vec!["_http._tcp".to_string()] } else { service_types };
+            let discovery = discovery(&options, &service_types).await?;
+            let mut events = discovery.subscribe();
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        if let Err(e) = discovery.discover_services(None).await {
+                            eprintln!("autodisc: discovery failed: {e}");
+                        }
+                    }
+                    event = events.recv() => match event {
+                        Ok(event) if options.format == ReportFormat::Json => println!("{}", serde_json::to_string(&event)?),
+                        Ok(event) => println!("{event}"),
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                            eprintln!("autodisc: missed {missed} events");
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = tokio::signal::ctrl_c() => break,
+                }
+            }
+            discovery.close().await?;
+        }
+        Command::EnumerateTypes => {
+            let discovery = discovery(&options, &[]).await?;
+            let service_types = discovery.enumerate_service_types().await?;
+            match options.format {
+                ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&service_types)?),
+                ReportFormat::Csv | ReportFormat::Table => {
+                    for service_type in service_types {
+                        println!("{service_type}");
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Discovery instance for the command line options
+async fn discovery(options: &Options, service_types: &[String]) -> Result<ServiceDiscovery, Box<dyn Error>> {
+    let mut config = DiscoveryConfig::new().with_timeout(Duration::from_secs(options.timeout));
+    if !options.protocols.is_empty() {
+        let protocols: HashSet<ProtocolType> = options.protocols.iter().copied().map(ProtocolType::from).collect();
+        config = config.with_protocols(protocols);
+    }
+    for service_type in service_types {
+        config = config.with_service_type(ServiceType::new(service_type)?);
+    }
+    Ok(ServiceDiscovery::new(config).await?)
+}
+
+/// First non-loopback address of a multicast-capable interface, IPv4 preferred
+fn default_address() -> Result<IpAddr, Box<dyn Error>> {
+    let addresses: Vec<IpAddr> = network::get_multicast_interfaces()?
+        .iter()
+        .flat_map(|interface| interface.networks.iter().map(|network| network.addr()))
+        .filter(|address| !address.is_loopback())
+        .collect();
+    addresses
+        .iter()
+        .find(|address| address.is_ipv4())
+        .or_else(|| addresses.first())
+        .copied()
+        .ok_or_else(|| "no non-loopback address to advertise; pass --address".into())
+}
@@ -0,0 +1,201 @@
+//! Caching of discovery results with stale-while-revalidate
+//!
+//! Results are cached per service type and protocol. Within the cache
+//! duration an entry is fresh and returned as is; for a further
+//! stale-while-revalidate window it is still returned, but the caller is told
+//! to refresh it in the background. Older entries are misses.
+
+use crate::{
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Key of a cache entry
+pub type CacheKey = (ServiceType, ProtocolType);
+
+/// Result of a cache lookup
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheLookup {
+    /// Entry is within the cache duration
+    Fresh(Vec<ServiceInfo>),
+    /// Entry has expired but may be served while it is refreshed
+    ///
+    /// `refresh` is `true` for exactly one caller until the entry is stored
+    /// again or the refresh is abandoned, so only one refresh runs at a time.
+    Stale {
+        /// Cached services
+        services: Vec<ServiceInfo>,
+        /// Whether this caller should start the refresh
+        refresh: bool,
+    },
+    /// No usable entry
+    Miss,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    services: Vec<ServiceInfo>,
+    fetched: Instant,
+    refreshing: bool,
+}
+
+/// Cache of discovery results keyed by service type and protocol
+#[derive(Debug)]
+pub struct DiscoveryCache {
+    ttl: Duration,
+    stale_while_revalidate: Duration,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+}
+
+impl DiscoveryCache {
+    /// Create a cache; a zero `ttl` disables caching
+    pub fn new(ttl: Duration, stale_while_revalidate: Duration) -> Self {
+        Self {
+            ttl,
+            stale_while_revalidate,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether results are cached at all
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<CacheKey, CacheEntry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Look up the services of one type found through one protocol
+    pub fn lookup(&self, service_type: &ServiceType, protocol: ProtocolType) -> CacheLookup {
+        let mut entries = self.lock();
+        let key = (service_type.clone(), protocol);
+        let lookup = match entries.get_mut(&key) {
+            Some(entry) if entry.fetched.elapsed() < self.ttl => CacheLookup::Fresh(entry.services.clone()),
+            Some(entry) if entry.fetched.elapsed() < self.ttl + self.stale_while_revalidate => {
+                let refresh = !entry.refreshing;
+                entry.refreshing = true;
+                CacheLookup::Stale { services: entry.services.clone(), refresh }
+            }
+            Some(_) => {
+                entries.remove(&key);
+                CacheLookup::Miss
+            }
+            None => CacheLookup::Miss,
+        };
+
+        #[cfg(feature = "metrics")]
+        {
+            let protocol = protocol.to_string();
+            match lookup {
+                CacheLookup::Miss => metrics::counter!("cache_misses_total", "protocol" => protocol).increment(1),
+                _ => metrics::counter!("cache_hits_total", "protocol" => protocol).increment(1),
+            }
+            metrics::gauge!("cache_size").set(entries.len() as f64);
+        }
+
+        lookup
+    }
+
+    /// Store the result of querying `service_types` through `protocol`
+    ///
+    /// Services are split by their type; requested types with no services are
+    /// cached as empty so repeated lookups don't hit the network.
+    pub fn store(&self, protocol: ProtocolType, service_types: &[ServiceType], services: &[ServiceInfo]) {
+        let fetched = Instant::now();
+        let mut entries = self.lock();
+        for service_type in service_types {
+            let matching = services
+                .iter()
+                .filter(|service| same_type(&service.service_type, service_type))
+                .cloned()
+                .collect();
+            entries.insert(
+                (service_type.clone(), protocol),
+                CacheEntry { services: matching, fetched, refreshing: false },
+            );
+        }
+
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("cache_size").set(entries.len() as f64);
+    }
+
+    /// Allow another refresh of entries whose background refresh failed
+    pub fn abandon_refresh(&self, protocol: ProtocolType, service_types: &[ServiceType]) {
+        let mut entries = self.lock();
+        for service_type in service_types {
+            if let Some(entry) = entries.get_mut(&(service_type.clone(), protocol)) {
+                entry.refreshing = false;
+            }
+        }
+    }
+
+    /// Drop all entries
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Number of cached entries
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+}
+
+/// Service types match regardless of domain
+fn same_type(a: &ServiceType, b: &ServiceType) -> bool {
+    a.service_name() == b.service_name() && a.protocol() == b.protocol()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http() -> ServiceType {
+        ServiceType::new("_http._tcp").unwrap()
+    }
+
+    #[test]
+    fn test_fresh_stale_and_expired() {
+        let cache = DiscoveryCache::new(Duration::from_millis(20), Duration::from_millis(200));
+        let printer = ServiceInfo::new("printer", "_ipp._tcp", 631, None).unwrap();
+        let web = ServiceInfo::new("web", "_http._tcp", 80, None).unwrap();
+        let ipp = ServiceType::new("_ipp._tcp").unwrap();
+
+        assert_eq!(cache.lookup(&http(), ProtocolType::Mdns), CacheLookup::Miss);
+        cache.store(ProtocolType::Mdns, &[http(), ipp.clone()], &[printer.clone(), web.clone()]);
+        assert_eq!(cache.lookup(&http(), ProtocolType::Mdns), CacheLookup::Fresh(vec![web.clone()]));
+        assert_eq!(cache.lookup(&ipp, ProtocolType::Mdns), CacheLookup::Fresh(vec![printer]));
+        assert_eq!(cache.lookup(&http(), ProtocolType::Upnp), CacheLookup::Miss);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(
+            cache.lookup(&http(), ProtocolType::Mdns),
+            CacheLookup::Stale { services: vec![web.clone()], refresh: true }
+        );
+        assert_eq!(
+            cache.lookup(&http(), ProtocolType::Mdns),
+            CacheLookup::Stale { services: vec![web], refresh: false }
+        );
+        cache.abandon_refresh(ProtocolType::Mdns, &[http()]);
+        assert!(matches!(
+            cache.lookup(&http(), ProtocolType::Mdns),
+            CacheLookup::Stale { refresh: true, .. }
+        ));
+
+        let no_stale = DiscoveryCache::new(Duration::from_millis(1), Duration::ZERO);
+        no_stale.store(ProtocolType::Mdns, &[http()], &[]);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(no_stale.lookup(&http(), ProtocolType::Mdns), CacheLookup::Miss);
+        assert!(no_stale.is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+//! Time sources for expiry, circuit breakers and uptime
+//!
+//! Registry TTLs, circuit breaker reset timeouts, rate limits and health
+//! monitor uptime read the time through a [`Clock`] instead of calling
+//! [`Instant::now`] themselves, so tests can control it.
+//!
+//! [`SystemClock`], the default, reads tokio's clock: in a runtime whose time
+//! is paused (`#[tokio::test(start_paused = true)]` or
+//! [`tokio::time::pause`]), TTLs run out as the test advances time rather
+//! than after real sleeps. [`ManualClock`] moves only when told to and needs
+//! no runtime at all.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Source of the current time
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Current monotonic time
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time, for timestamps that leave the process
+    fn system_time(&self) -> SystemTime;
+}
+
+/// Tokio's clock, which is the system clock unless a test paused it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when advanced
+///
+/// Clones share their time, so a test can keep one and hand another to the
+/// code under test.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    time: Arc<Mutex<(Instant, SystemTime)>>,
+}
+
+impl ManualClock {
+    /// A clock stopped at the current time
+    pub fn new() -> Self {
+        Self { time: Arc::new(Mutex::new((Instant::now(), SystemTime::now()))) }
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.time.lock().unwrap_or_else(|e| e.into_inner());
+        time.0 += duration;
+        time.1 += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.time.lock().unwrap_or_else(|e| e.into_inner()).0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time.lock().unwrap_or_else(|e| e.into_inner()).1
+    }
+}
+
+/// The default clock, shared
+pub(crate) fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock_moves_when_advanced() {
+        let clock = ManualClock::new();
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        let (start, wall) = (shared.now(), shared.system_time());
+        assert_eq!(shared.now(), start);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(shared.now() - start, Duration::from_secs(90));
+        assert_eq!(shared.system_time().duration_since(wall).unwrap(), Duration::from_secs(90));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_system_clock_follows_paused_tokio_time() {
+        let start = SystemClock.now();
+        tokio::time::advance(Duration::from_secs(3600)).await;
+        assert!(SystemClock.now() - start >= Duration::from_secs(3600));
+    }
+}
@@ -2,8 +2,20 @@
 
 use crate::types::{ProtocolType, ServiceType, DiscoveryFilter};
 use crate::error::Result;
+use crate::health::HealthConfig;
+use crate::logging::LoggingConfig;
+use crate::naming::NamingStrategy;
+use crate::resolver::ResolverConfig;
+use crate::safety::{load_balancer::LoadBalancerConfig, SafetyConfig};
+use crate::schema::{MetadataSchema, SchemaValidator};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, time::Duration};
+use ipnet::IpNet;
+use std::{
+    collections::{BTreeSet, HashSet},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 /// Configuration for the service discovery system
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,12 +28,18 @@ pub struct DiscoveryConfig {
     verify_services: bool,
     /// Network interfaces to use
     interfaces: Option<HashSet<String>>,
+    /// Networks services must be on; empty allows every network
+    #[serde(default)]
+    allowed_networks: Vec<IpNet>,
     /// Maximum number of services to track
     max_services: usize,
     /// Maximum number of retries
     max_retries: u32,
     /// Cache duration
     cache_duration: Duration,
+    /// How long expired cache entries may still be served while refreshing
+    #[serde(default = "default_stale_while_revalidate")]
+    stale_while_revalidate: Duration,
     /// Rate limit for discovery
     rate_limit: Option<Duration>,
     /// Whether metrics are enabled
@@ -30,12 +48,62 @@ pub struct DiscoveryConfig {
     enabled_protocols: HashSet<ProtocolType>,
     /// Whether to allow cross-protocol discovery
     allow_cross_protocol: bool,
+    /// Whether failing to start every enabled protocol is an error
+    #[serde(default)]
+    require_at_least_one_protocol: bool,
+    /// Whether built-in protocols are started on first use
+    #[serde(default)]
+    lazy_protocols: bool,
+    /// Detection of missing multicast and the fallbacks used then
+    #[serde(default)]
+    container_mode: Option<ContainerConfig>,
     /// Whether to enable IPv4 support
     enable_ipv4: bool,
     /// Whether to enable IPv6 support
     enable_ipv6: bool,
     /// Discovery filter
     filter: Option<DiscoveryFilter>,
+    /// mDNS protocol settings
+    #[serde(default)]
+    mdns: MdnsConfig,
+    /// UPnP/SSDP protocol settings
+    #[serde(default)]
+    upnp: UpnpConfig,
+    /// DNS-SD protocol settings
+    #[serde(default)]
+    dns_sd: DnsSdConfig,
+    /// Health check and service verification settings
+    #[serde(default)]
+    health: HealthConfig,
+    /// Rate limits, circuit breakers and retries
+    #[serde(default)]
+    safety: SafetyConfig,
+    /// How [`ServiceDiscovery::load_balanced_endpoint`](crate::ServiceDiscovery::load_balanced_endpoint) picks instances
+    #[serde(default)]
+    load_balancing: LoadBalancerConfig,
+    /// Redaction of sensitive attributes in logs
+    #[serde(default)]
+    logging: LoggingConfig,
+    /// Caching of hostname resolutions
+    #[serde(default)]
+    resolver: ResolverConfig,
+    /// Seed for retry jitter, load balancing and query delays
+    #[serde(default)]
+    seed: Option<u64>,
+    /// JSON lines file the audit log is appended to
+    #[serde(default)]
+    audit_log: Option<PathBuf>,
+    /// Metadata schemas checked against discovered services
+    #[serde(default)]
+    metadata_schemas: Vec<MetadataSchema>,
+    /// Access policy applied to discovered services
+    #[cfg(feature = "secure")]
+    #[serde(default)]
+    access_policy: Option<crate::security::policy::AccessPolicy>,
+}
+
+fn default_stale_while_revalidate() -> Duration {
+    Duration::from_secs(60)
 }
 
 impl Default for DiscoveryConfig {
@@ -45,16 +113,34 @@ impl Default for DiscoveryConfig {
             timeout: Some(Duration::from_secs(30)),
             verify_services: false,
             interfaces: None,
+            allowed_networks: Vec::new(),
             max_services: 1000,
             max_retries: 3,
             cache_duration: Duration::from_secs(300),
+            stale_while_revalidate: default_stale_while_revalidate(),
             rate_limit: Some(Duration::from_secs(1)),
             metrics_enabled: false,
             enabled_protocols: [ProtocolType::Mdns].into_iter().collect(),
             allow_cross_protocol: false,
+            require_at_least_one_protocol: false,
+            lazy_protocols: false,
+            container_mode: None,
             enable_ipv4: true,
             enable_ipv6: false,
             filter: None,
+            mdns: MdnsConfig::default(),
+            upnp: UpnpConfig::default(),
+            dns_sd: DnsSdConfig::default(),
+            health: HealthConfig::default(),
+            safety: SafetyConfig::default(),
+            load_balancing: LoadBalancerConfig::default(),
+            logging: LoggingConfig::default(),
+            resolver: ResolverConfig::default(),
+            seed: None,
+            audit_log: None,
+            metadata_schemas: Vec::new(),
+            #[cfg(feature = "secure")]
+            access_policy: None,
         }
     }
 }
@@ -82,6 +168,11 @@ impl DiscoveryConfig {
         self
     }
 
+    /// Add a service type to discover
+    pub fn add_service_type(&mut self, service_type: ServiceType) {
+        self.service_types.push(service_type);
+    }
+
     /// Get service types
     pub fn service_types(&self) -> &[ServiceType] {
         &self.service_types
@@ -109,6 +200,27 @@ impl DiscoveryConfig {
         self.interfaces.as_ref()
     }
 
+    /// Only discover services on these networks
+    ///
+    /// Services whose address lies outside every allowed network are
+    /// dropped, and multicast queries only go out of interfaces attached to
+    /// an allowed network. Useful on multi-homed hosts where only one
+    /// network should be discovered. An empty list allows every network.
+    pub fn with_allowed_networks(mut self, networks: Vec<IpNet>) -> Self {
+        self.allowed_networks = networks;
+        self
+    }
+
+    /// Get the networks services must be on
+    pub fn allowed_networks(&self) -> &[IpNet] {
+        &self.allowed_networks
+    }
+
+    /// Whether `addr` is on an allowed network
+    pub fn is_address_allowed(&self, addr: &IpAddr) -> bool {
+        self.allowed_networks.is_empty() || self.allowed_networks.iter().any(|network| network.contains(addr))
+    }
+
     /// Set maximum number of services
     pub fn with_max_services(mut self, max: usize) -> Self {
         self.max_services = max;
@@ -196,6 +308,48 @@ impl DiscoveryConfig {
         self.allow_cross_protocol
     }
 
+    /// Fail to start when no protocol can be started
+    ///
+    /// By default a protocol that fails to start is left out, and discovery
+    /// runs with whichever protocols did start, even none; see
+    /// [`ServiceDiscovery::init_report`](crate::ServiceDiscovery::init_report).
+    pub fn with_require_at_least_one_protocol(mut self, require: bool) -> Self {
+        self.require_at_least_one_protocol = require;
+        self
+    }
+
+    /// Whether starting without any protocol is an error
+    pub fn require_at_least_one_protocol(&self) -> bool {
+        self.require_at_least_one_protocol
+    }
+
+    /// Start built-in protocols on first use instead of up front
+    ///
+    /// Failed starts are retried in the background on the
+    /// [`SafetyConfig::retry`] schedule; see [`crate::protocols::lazy`].
+    pub fn with_lazy_protocols(mut self, lazy: bool) -> Self {
+        self.lazy_protocols = lazy;
+        self
+    }
+
+    /// Whether built-in protocols are started on first use
+    pub fn lazy_protocols(&self) -> bool {
+        self.lazy_protocols
+    }
+
+    /// Check for multicast at start-up and fall back when it is missing
+    ///
+    /// See [`ContainerConfig`] and [`crate::container`].
+    pub fn with_container_mode(mut self, container: ContainerConfig) -> Self {
+        self.container_mode = Some(container);
+        self
+    }
+
+    /// Get the container mode settings, if enabled
+    pub fn container_mode(&self) -> Option<&ContainerConfig> {
+        self.container_mode.as_ref()
+    }
+
     /// Enable metrics
     pub fn with_metrics(mut self, enable: bool) -> Self {
         self.metrics_enabled = enable;
@@ -229,6 +383,20 @@ impl DiscoveryConfig {
         self.cache_duration
     }
 
+    /// Set how long expired results may be served while a background refresh runs
+    ///
+    /// A zero duration disables stale-while-revalidate, so expired entries
+    /// are always refetched before returning.
+    pub fn with_stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.stale_while_revalidate = duration;
+        self
+    }
+
+    /// Get stale-while-revalidate window
+    pub fn stale_while_revalidate(&self) -> Duration {
+        self.stale_while_revalidate
+    }
+
     /// Get protocol timeout
     pub fn protocol_timeout(&self) -> Duration {
         self.timeout.unwrap_or(Duration::from_secs(30))
@@ -250,6 +418,142 @@ impl DiscoveryConfig {
         self
     }
 
+    /// Set mDNS protocol settings
+    pub fn with_mdns(mut self, mdns: MdnsConfig) -> Self {
+        self.mdns = mdns;
+        self
+    }
+
+    /// Get mDNS protocol settings
+    pub fn mdns(&self) -> &MdnsConfig {
+        &self.mdns
+    }
+
+    /// Set UPnP/SSDP protocol settings
+    pub fn with_upnp(mut self, upnp: UpnpConfig) -> Self {
+        self.upnp = upnp;
+        self
+    }
+
+    /// Get UPnP/SSDP protocol settings
+    pub fn upnp(&self) -> &UpnpConfig {
+        &self.upnp
+    }
+
+    /// Set DNS-SD protocol settings
+    pub fn with_dns_sd(mut self, dns_sd: DnsSdConfig) -> Self {
+        self.dns_sd = dns_sd;
+        self
+    }
+
+    /// Get DNS-SD protocol settings
+    pub fn dns_sd(&self) -> &DnsSdConfig {
+        &self.dns_sd
+    }
+
+    /// Set health check settings used for service verification
+    pub fn with_health(mut self, health: HealthConfig) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Get health check settings
+    pub fn health(&self) -> &HealthConfig {
+        &self.health
+    }
+
+    /// Set rate limits, circuit breaker thresholds and retry behavior
+    pub fn with_safety(mut self, safety: SafetyConfig) -> Self {
+        self.safety = safety;
+        self
+    }
+
+    /// Get safety settings
+    pub fn safety(&self) -> &SafetyConfig {
+        &self.safety
+    }
+
+    /// Set load balancing settings
+    pub fn with_load_balancing(mut self, load_balancing: LoadBalancerConfig) -> Self {
+        self.load_balancing = load_balancing;
+        self
+    }
+
+    /// Get load balancing settings
+    pub fn load_balancing(&self) -> &LoadBalancerConfig {
+        &self.load_balancing
+    }
+
+    /// Set how sensitive attributes are redacted in logs
+    pub fn with_logging(mut self, logging: LoggingConfig) -> Self {
+        self.logging = logging;
+        self
+    }
+
+    /// Get logging settings
+    pub fn logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
+    /// Set how hostname resolutions are cached
+    pub fn with_resolver(mut self, resolver: ResolverConfig) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Get hostname resolution cache settings
+    pub fn resolver(&self) -> &ResolverConfig {
+        &self.resolver
+    }
+
+    /// Seed retry jitter, random load balancing and mDNS query delays, so
+    /// that runs with the same seed make the same choices
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Get the seed of the random choices, if set
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Append an audit record of every registration, discovery query,
+    /// verification and security event to the JSON lines file at `path`
+    pub fn with_audit_log<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.audit_log = Some(path.into());
+        self
+    }
+
+    /// Get the audit log file
+    pub fn audit_log(&self) -> Option<&Path> {
+        self.audit_log.as_deref()
+    }
+
+    /// Check discovered services of the schema's type against `schema`
+    pub fn with_metadata_schema(mut self, schema: MetadataSchema) -> Self {
+        self.metadata_schemas.push(schema);
+        self
+    }
+
+    /// Get the metadata schemas
+    pub fn metadata_schemas(&self) -> &[MetadataSchema] {
+        &self.metadata_schemas
+    }
+
+    /// Filter discovered services through an access policy
+    #[cfg(feature = "secure")]
+    pub fn with_access_policy(mut self, policy: crate::security::policy::AccessPolicy) -> Self {
+        self.access_policy = Some(policy);
+        self
+    }
+
+    /// Get the access policy
+    #[cfg(feature = "secure")]
+    pub fn access_policy(&self) -> Option<&crate::security::policy::AccessPolicy> {
+        self.access_policy.as_ref()
+    }
+
     /// Validate configuration
     pub fn validate(&self) -> Result<()> {
         if self.timeout.is_some_and(|t| t.as_secs() == 0) {
@@ -270,149 +574,1128 @@ impl DiscoveryConfig {
             ));
         }
 
+        if self.is_protocol_enabled(ProtocolType::Mdns) {
+            self.mdns.validate()?;
+        }
+
+        if self.is_protocol_enabled(ProtocolType::Upnp) {
+            self.upnp.validate()?;
+        }
+
+        if self.is_protocol_enabled(ProtocolType::DnsSd) {
+            self.dns_sd.validate()?;
+        }
+
+        if let Some(container) = &self.container_mode {
+            container.validate()?;
+        }
+
+        self.safety.validate()?;
+
+        self.health.validate()?;
+
+        self.logging.validate()?;
+
+        self.resolver.validate()?;
+
+        SchemaValidator::new(&self.metadata_schemas)?;
+
         Ok(())
     }
 }
 
-/// Configuration for service registration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RegistrationConfig {
-    /// Time-to-live for the service record
-    pub ttl: Duration,
-    /// Whether to auto-refresh the service registration
-    pub auto_refresh: bool,
-    /// Refresh interval (only used if auto_refresh is true)
-    pub refresh_interval: Duration,
-    /// Network interfaces to register on
-    pub interfaces: Vec<String>,
-    /// Protocols to use for registration
-    pub protocols: HashSet<ProtocolType>,
-    /// Whether to enable IPv6 registration
-    pub enable_ipv6: bool,
-    /// Whether to enable IPv4 registration
-    pub enable_ipv4: bool,
-    /// Priority for the service (used in some protocols)
-    pub priority: u16,
-    /// Weight for the service (used in some protocols)
-    pub weight: u16,
+/// mDNS protocol settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MdnsConfig {
+    /// Interfaces to run mDNS on (falls back to the global interface selection)
+    interfaces: Option<HashSet<String>>,
+    /// Hostname advertised for registered services (defaults to `<instance>.local.`)
+    hostname: Option<String>,
+    /// Whether to ask responders for unicast replies (QU bit)
+    unicast_response: bool,
+    /// Repeating of mDNS traffic between interfaces
+    #[serde(default)]
+    reflector: Option<ReflectorConfig>,
+    /// Options of the mDNS sockets
+    #[serde(default)]
+    socket: SocketOptions,
+    /// Implementation used for mDNS
+    #[serde(default)]
+    backend: MdnsBackend,
 }
 
-impl Default for RegistrationConfig {
-    fn default() -> Self {
-        let mut protocols = HashSet::new();
-        protocols.insert(ProtocolType::Mdns);
+/// Implementation used for mDNS
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MdnsBackend {
+    /// The crate's own implementation, chosen by the enabled features
+    #[default]
+    Builtin,
+    /// The system mDNS responder, through `DnsServiceBrowse` and
+    /// `DnsServiceRegister` on Windows 10 and later
+    ///
+    /// Avoids competing with the system responder for port 5353. Needs the
+    /// `windows-dns` feature; elsewhere mDNS fails to start.
+    WindowsDnsApi,
+}
 
-        Self {
-            ttl: Duration::from_secs(120),
-            auto_refresh: true,
-            refresh_interval: Duration::from_secs(60),
-            interfaces: Vec::new(),
-            protocols,
-            enable_ipv6: true,
-            enable_ipv4: true,
-            priority: 0,
-            weight: 0,
+impl MdnsConfig {
+    /// Create mDNS settings with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restrict mDNS to the given interfaces
+    pub fn with_interfaces(mut self, interfaces: HashSet<String>) -> Self {
+        self.interfaces = Some(interfaces);
+        self
+    }
+
+    /// Get the mDNS interface selection
+    pub fn interfaces(&self) -> Option<&HashSet<String>> {
+        self.interfaces.as_ref()
+    }
+
+    /// Set the hostname advertised for registered services
+    pub fn with_hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Get the advertised hostname
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+
+    /// Request unicast responses to queries
+    ///
+    /// Only honoured by backends that build their own queries; the
+    /// `mdns-sd` backend schedules its queries internally.
+    pub fn with_unicast_response(mut self, enable: bool) -> Self {
+        self.unicast_response = enable;
+        self
+    }
+
+    /// Get unicast response preference
+    pub fn unicast_response(&self) -> bool {
+        self.unicast_response
+    }
+
+    /// Reflect services between interfaces
+    pub fn with_reflector(mut self, reflector: ReflectorConfig) -> Self {
+        self.reflector = Some(reflector);
+        self
+    }
+
+    /// Get the reflector settings
+    pub fn reflector(&self) -> Option<&ReflectorConfig> {
+        self.reflector.as_ref()
+    }
+
+    /// Set the options of the mDNS sockets
+    ///
+    /// The reflector applies all of them except the outgoing interface, as it
+    /// sends out of every reflected interface. The `mdns-sd` backend opens its
+    /// own sockets and only honours the loopback and outgoing interface
+    /// settings, the latter by running on that interface alone; the others
+    /// are logged as ignored.
+    pub fn with_socket_options(mut self, socket: SocketOptions) -> Self {
+        self.socket = socket;
+        self
+    }
+
+    /// Get the mDNS socket options
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket
+    }
+
+    /// Choose the mDNS implementation
+    pub fn with_backend(mut self, backend: MdnsBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Get the mDNS implementation
+    pub fn backend(&self) -> MdnsBackend {
+        self.backend
+    }
+
+    /// Validate mDNS settings
+    pub fn validate(&self) -> Result<()> {
+        self.socket.validate()?;
+        match &self.reflector {
+            Some(reflector) => reflector.validate(),
+            None => Ok(()),
         }
     }
 }
 
-impl RegistrationConfig {
-    /// Create a new registration configuration with defaults
+/// Options applied to the multicast sockets of a protocol
+///
+/// Unset options keep the operating system's defaults, which suit a single
+/// host on a flat network. In containers and on multi-homed hosts it is
+/// often necessary to pick the outgoing interface, raise the hop limit or
+/// share the port with another responder.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SocketOptions {
+    /// TTL of outgoing IPv4 multicast, or hop limit of IPv6 multicast
+    multicast_ttl: Option<u32>,
+    /// Whether our own multicast is looped back to local sockets
+    multicast_loop: Option<bool>,
+    /// Name of the interface multicast is sent from
+    outgoing_interface: Option<String>,
+    /// Size of the receive buffer in bytes
+    recv_buffer_size: Option<usize>,
+    /// Whether SO_REUSEPORT is set before binding
+    reuse_port: bool,
+}
+
+impl SocketOptions {
+    /// Create socket options that keep the system defaults
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Set the TTL for service records
-    pub fn ttl(mut self, ttl: Duration) -> Self {
-        self.ttl = ttl;
+    /// Set the TTL (IPv4) or hop limit (IPv6) of outgoing multicast
+    pub fn with_multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = Some(ttl);
         self
     }
 
-    /// Enable or disable auto-refresh
-    pub fn auto_refresh(mut self, auto_refresh: bool) -> Self {
-        self.auto_refresh = auto_refresh;
-        self
+    /// Get the multicast TTL or hop limit
+    pub fn multicast_ttl(&self) -> Option<u32> {
+        self.multicast_ttl
     }
 
-    /// Set the refresh interval
-    pub fn refresh_interval(mut self, interval: Duration) -> Self {
-        self.refresh_interval = interval;
+    /// Enable or disable looping our own multicast back to local sockets
+    pub fn with_multicast_loop(mut self, enable: bool) -> Self {
+        self.multicast_loop = Some(enable);
         self
     }
 
-    /// Set network interfaces for registration
-    pub fn interfaces<I, S>(mut self, interfaces: I) -> Self
-    where
-        I: IntoIterator<Item = S>,
-        S: Into<String>,
-    {
-        self.interfaces = interfaces.into_iter().map(|s| s.into()).collect();
-        self
+    /// Get the multicast loopback setting
+    pub fn multicast_loop(&self) -> Option<bool> {
+        self.multicast_loop
     }
 
-    /// Set registration protocols
-    pub fn protocols<I>(mut self, protocols: I) -> Self
-    where
-        I: IntoIterator<Item = ProtocolType>,
-    {
-        self.protocols = protocols.into_iter().collect();
+    /// Send multicast out of the named interface only
+    pub fn with_outgoing_interface<S: Into<String>>(mut self, interface: S) -> Self {
+        self.outgoing_interface = Some(interface.into());
         self
     }
 
-    /// Set service priority
-    pub fn priority(mut self, priority: u16) -> Self {
-        self.priority = priority;
+    /// Get the outgoing interface
+    pub fn outgoing_interface(&self) -> Option<&str> {
+        self.outgoing_interface.as_deref()
+    }
+
+    /// Set the receive buffer size in bytes
+    ///
+    /// The operating system may round or cap the size.
+    pub fn with_recv_buffer_size(mut self, bytes: usize) -> Self {
+        self.recv_buffer_size = Some(bytes);
         self
     }
 
-    /// Set service weight
-    pub fn weight(mut self, weight: u16) -> Self {
-        self.weight = weight;
+    /// Get the receive buffer size
+    pub fn recv_buffer_size(&self) -> Option<usize> {
+        self.recv_buffer_size
+    }
+
+    /// Set SO_REUSEPORT so the port can be shared with other responders
+    ///
+    /// Only available on Unix; elsewhere it is logged as ignored.
+    pub fn with_reuse_port(mut self, enable: bool) -> Self {
+        self.reuse_port = enable;
         self
     }
 
-    /// Validate the configuration
-    pub fn validate(&self) -> crate::Result<()> {
-        if self.ttl.is_zero() {
-            return Err(crate::error::DiscoveryError::configuration(
-                "TTL cannot be zero",
-            ));
-        }
+    /// Whether SO_REUSEPORT is set
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port
+    }
 
-        if self.auto_refresh && self.refresh_interval.is_zero() {
+    /// Validate the options
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ttl) = self.multicast_ttl
+            && !(1..=255).contains(&ttl)
+        {
             return Err(crate::error::DiscoveryError::configuration(
-                "Refresh interval cannot be zero when auto-refresh is enabled",
+                "Multicast TTL must be between 1 and 255",
             ));
         }
-
-        if self.auto_refresh && self.refresh_interval >= self.ttl {
+        if self.recv_buffer_size == Some(0) {
             return Err(crate::error::DiscoveryError::configuration(
-                "Refresh interval must be less than TTL",
+                "Receive buffer size must be greater than 0",
             ));
         }
-
-        if self.protocols.is_empty() {
+        if self.outgoing_interface.as_deref().is_some_and(str::is_empty) {
             return Err(crate::error::DiscoveryError::configuration(
-                "At least one protocol must be enabled",
+                "Outgoing interface name must not be empty",
             ));
         }
+        Ok(())
+    }
+}
 
-        if !self.enable_ipv4 && !self.enable_ipv6 {
-            return Err(crate::error::DiscoveryError::configuration(
-                "At least one IP version must be enabled",
-            ));
-        }
+/// Settings of container mode
+///
+/// Containers on bridge networks, Docker's default, get an interface that
+/// looks multicast-capable but never sees the LAN's multicast traffic, so
+/// mDNS and SSDP quietly find nothing. In container mode multicast is
+/// checked at start-up; when it is missing, mDNS and UPnP are replaced by
+/// the configured fallbacks:
+///
+/// - DNS-SD over unicast, when [`DnsSdConfig::with_dns_server`] is set
+/// - a discovery gateway, when [`with_gateway_url`](Self::with_gateway_url)
+///   is set; see [`crate::gateway`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerConfig {
+    /// How long to wait for an answer to the multicast probe
+    probe_timeout: Duration,
+    /// URL of the discovery gateway to fall back to
+    #[serde(default)]
+    gateway_url: Option<String>,
+    /// Known multicast availability, which skips the check
+    #[serde(default)]
+    multicast_available: Option<bool>,
+}
 
-        Ok(())
+impl Default for ContainerConfig {
+    fn default() -> Self {
+        Self {
+            probe_timeout: Duration::from_secs(2),
+            gateway_url: None,
+            multicast_available: None,
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::Duration;
+impl ContainerConfig {
+    /// Create container mode settings with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    #[test]
+    /// Set how long the multicast probe waits for an answer
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Get the multicast probe timeout
+    pub fn probe_timeout(&self) -> Duration {
+        self.probe_timeout
+    }
+
+    /// Fall back to the discovery gateway at `url`, e.g. `http://host.docker.internal:8380`
+    pub fn with_gateway_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.gateway_url = Some(url.into());
+        self
+    }
+
+    /// Get the gateway URL
+    pub fn gateway_url(&self) -> Option<&str> {
+        self.gateway_url.as_deref()
+    }
+
+    /// Skip the check, taking multicast to be available or not
+    pub fn with_multicast_available(mut self, available: bool) -> Self {
+        self.multicast_available = Some(available);
+        self
+    }
+
+    /// Get the configured multicast availability
+    pub fn multicast_available(&self) -> Option<bool> {
+        self.multicast_available
+    }
+
+    /// Validate the settings
+    pub fn validate(&self) -> Result<()> {
+        if self.probe_timeout.is_zero() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Multicast probe timeout must be greater than 0",
+            ));
+        }
+        if let Some(url) = &self.gateway_url {
+            crate::remote::RemoteDiscovery::new(url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings of the mDNS reflector
+///
+/// The reflector repeats mDNS traffic about the allowed service types from
+/// each interface onto the others, so services are visible across subnets
+/// that don't pass multicast to each other. See
+/// [`MdnsReflector`](crate::protocols::reflector::MdnsReflector).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReflectorConfig {
+    /// Names of the interfaces to reflect between
+    interfaces: Vec<String>,
+    /// Service types that are reflected; nothing else is
+    service_types: Vec<ServiceType>,
+}
+
+impl ReflectorConfig {
+    /// Reflect `service_types` between `interfaces`
+    pub fn new<I, S, T>(interfaces: I, service_types: T) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+        T: IntoIterator<Item = ServiceType>,
+    {
+        Self {
+            interfaces: interfaces.into_iter().map(Into::into).collect(),
+            service_types: service_types.into_iter().collect(),
+        }
+    }
+
+    /// Get the interfaces reflected between
+    pub fn interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
+    /// Get the reflected service types
+    pub fn service_types(&self) -> &[ServiceType] {
+        &self.service_types
+    }
+
+    /// Validate reflector settings
+    pub fn validate(&self) -> Result<()> {
+        let distinct: HashSet<_> = self.interfaces.iter().collect();
+        if distinct.len() < 2 {
+            return Err(crate::error::DiscoveryError::configuration(
+                "The mDNS reflector needs at least two distinct interfaces",
+            ));
+        }
+        if self.service_types.is_empty() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "The mDNS reflector needs at least one allowed service type",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Re-browse schedule of continuous watching
+///
+/// Each watched service type is queried at once, then again after
+/// `initial_interval`, with the interval multiplied by `multiplier` after
+/// every query until it reaches `max_interval`, as RFC 6762 section 5.2
+/// describes for continuous mDNS queries. A network change starts every
+/// schedule over. See
+/// [`ServiceDiscovery::watch_services`](crate::ServiceDiscovery::watch_services).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchConfig {
+    /// Interval between the first and second query
+    initial_interval: Duration,
+    /// Longest interval between queries
+    max_interval: Duration,
+    /// Factor the interval grows by after each query
+    multiplier: u32,
+    /// Service types to watch; the configured ones if empty
+    #[serde(default)]
+    service_types: Vec<ServiceType>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            initial_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(3600),
+            multiplier: 2,
+            service_types: Vec::new(),
+        }
+    }
+}
+
+impl WatchConfig {
+    /// Create a watch schedule with default values: 1s, 2s, 4s and so on,
+    /// capped at an hour
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the interval between the first and second query
+    pub fn with_initial_interval(mut self, interval: Duration) -> Self {
+        self.initial_interval = interval;
+        self
+    }
+
+    /// Get the interval between the first and second query
+    pub fn initial_interval(&self) -> Duration {
+        self.initial_interval
+    }
+
+    /// Set the longest interval between queries
+    pub fn with_max_interval(mut self, interval: Duration) -> Self {
+        self.max_interval = interval;
+        self
+    }
+
+    /// Get the longest interval between queries
+    pub fn max_interval(&self) -> Duration {
+        self.max_interval
+    }
+
+    /// Set the factor the interval grows by after each query
+    pub fn with_multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Get the factor the interval grows by after each query
+    pub fn multiplier(&self) -> u32 {
+        self.multiplier
+    }
+
+    /// Watch `service_type` instead of the configured service types
+    pub fn with_service_type(mut self, service_type: ServiceType) -> Self {
+        self.service_types.push(service_type);
+        self
+    }
+
+    /// Get the watched service types, if not the configured ones
+    pub fn service_types(&self) -> &[ServiceType] {
+        &self.service_types
+    }
+
+    /// Validate the schedule
+    pub fn validate(&self) -> Result<()> {
+        if self.initial_interval.is_zero() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Watch interval must be greater than zero",
+            ));
+        }
+        if self.max_interval < self.initial_interval {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Maximum watch interval cannot be less than the initial interval",
+            ));
+        }
+        if self.multiplier == 0 {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Watch interval multiplier must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// UPnP/SSDP protocol settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpnpConfig {
+    /// Maximum wait (MX) in seconds requested from responders
+    mx: u8,
+    /// Advertisement lifetime sent in CACHE-CONTROL max-age
+    max_age: Duration,
+    /// LOCATION URL advertised for registered services
+    location_url: Option<String>,
+    /// Inbound packets per second accepted from a single source IP
+    #[serde(default = "default_peer_rate_limit")]
+    peer_rate_limit: u32,
+    /// Whether suspicious search responses are withheld from results
+    #[serde(default)]
+    strict_validation: bool,
+    /// Options of the SSDP sockets
+    #[serde(default)]
+    socket: SocketOptions,
+}
+
+fn default_peer_rate_limit() -> u32 {
+    20
+}
+
+impl Default for UpnpConfig {
+    fn default() -> Self {
+        Self {
+            mx: 3,
+            max_age: Duration::from_secs(1800),
+            location_url: None,
+            peer_rate_limit: default_peer_rate_limit(),
+            strict_validation: false,
+            socket: SocketOptions::default(),
+        }
+    }
+}
+
+impl UpnpConfig {
+    /// Create UPnP settings with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the MX value used in M-SEARCH requests
+    pub fn with_mx(mut self, mx: u8) -> Self {
+        self.mx = mx;
+        self
+    }
+
+    /// Get the MX value
+    pub fn mx(&self) -> u8 {
+        self.mx
+    }
+
+    /// Set the advertisement max-age
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Get the advertisement max-age
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// Set the LOCATION URL advertised for registered services
+    ///
+    /// Defaults to `http://<address>:<port>/` of each service.
+    pub fn with_location_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.location_url = Some(url.into());
+        self
+    }
+
+    /// Get the advertised LOCATION URL
+    pub fn location_url(&self) -> Option<&str> {
+        self.location_url.as_deref()
+    }
+
+    /// Set how many inbound packets per second the listener accepts from one source IP
+    ///
+    /// Packets above the limit are dropped before they are parsed.
+    pub fn with_peer_rate_limit(mut self, packets_per_second: u32) -> Self {
+        self.peer_rate_limit = packets_per_second;
+        self
+    }
+
+    /// Get the per-peer inbound rate limit
+    pub fn peer_rate_limit(&self) -> u32 {
+        self.peer_rate_limit
+    }
+
+    /// Withhold suspicious search responses from discovery results
+    ///
+    /// Responses for a search target that wasn't asked for, or whose
+    /// LOCATION points outside the local and allowed networks, are always
+    /// reported with [`ServiceEvent::SecurityAlert`](crate::service::ServiceEvent::SecurityAlert).
+    /// In strict mode they are also dropped; otherwise they are still returned.
+    pub fn with_strict_validation(mut self, strict: bool) -> Self {
+        self.strict_validation = strict;
+        self
+    }
+
+    /// Whether suspicious search responses are withheld
+    pub fn strict_validation(&self) -> bool {
+        self.strict_validation
+    }
+
+    /// Set the options of the SSDP listener, search and announcement sockets
+    ///
+    /// With an outgoing interface set, searches and announcements are only
+    /// sent from that interface.
+    pub fn with_socket_options(mut self, socket: SocketOptions) -> Self {
+        self.socket = socket;
+        self
+    }
+
+    /// Get the SSDP socket options
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket
+    }
+
+    /// Validate the settings
+    pub fn validate(&self) -> Result<()> {
+        // UPnP Device Architecture limits MX to 1..=5 seconds
+        if !(1..=5).contains(&self.mx) {
+            return Err(crate::error::DiscoveryError::configuration(
+                "UPnP MX must be between 1 and 5 seconds",
+            ));
+        }
+
+        if self.max_age.as_secs() == 0 {
+            return Err(crate::error::DiscoveryError::configuration(
+                "UPnP max-age must be greater than 0",
+            ));
+        }
+
+        if self.peer_rate_limit == 0 {
+            return Err(crate::error::DiscoveryError::configuration(
+                "UPnP peer rate limit must be greater than 0",
+            ));
+        }
+
+        self.socket.validate()?;
+
+        if let Some(url) = &self.location_url {
+            url::Url::parse(url).map_err(|e| {
+                crate::error::DiscoveryError::configuration(format!("Invalid UPnP location URL: {e}"))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// DNS-SD protocol settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsSdConfig {
+    /// Unicast DNS server to query and update
+    dns_server: Option<SocketAddr>,
+    /// Zone services are browsed in and registered to
+    zone: String,
+    /// Name of the TSIG key used to sign updates
+    tsig_key_name: Option<String>,
+    /// Name of the SIG(0) key used to sign updates instead of TSIG
+    #[serde(default)]
+    sig0_key_name: Option<String>,
+    /// Transport used to reach the DNS server
+    #[serde(default)]
+    transport: DnsTransport,
+    /// Name expected in the server certificate for TLS and HTTPS transports
+    #[serde(default)]
+    tls_server_name: Option<String>,
+    /// Domains browsed in addition to the zone
+    #[serde(default)]
+    browse_domains: Vec<String>,
+    /// Whether to also browse the domains the zone advertises (`b._dns-sd._udp`)
+    #[serde(default = "default_enumerate_browse_domains")]
+    enumerate_browse_domains: bool,
+    /// Lease requested for registered records before they must be refreshed
+    #[serde(default = "default_lease")]
+    lease: Duration,
+}
+
+fn default_enumerate_browse_domains() -> bool {
+    true
+}
+
+fn default_lease() -> Duration {
+    Duration::from_secs(3600)
+}
+
+impl Default for DnsSdConfig {
+    fn default() -> Self {
+        Self {
+            dns_server: None,
+            zone: "local.".to_string(),
+            tsig_key_name: None,
+            sig0_key_name: None,
+            transport: DnsTransport::default(),
+            tls_server_name: None,
+            browse_domains: Vec::new(),
+            enumerate_browse_domains: default_enumerate_browse_domains(),
+            lease: default_lease(),
+        }
+    }
+}
+
+impl DnsSdConfig {
+    /// Create DNS-SD settings with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the DNS server
+    pub fn with_dns_server(mut self, server: SocketAddr) -> Self {
+        self.dns_server = Some(server);
+        self
+    }
+
+    /// Get the DNS server
+    pub fn dns_server(&self) -> Option<SocketAddr> {
+        self.dns_server
+    }
+
+    /// Set the zone
+    pub fn with_zone<S: Into<String>>(mut self, zone: S) -> Self {
+        self.zone = zone.into();
+        self
+    }
+
+    /// Get the zone
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    /// Set the TSIG key name used to sign updates
+    pub fn with_tsig_key_name<S: Into<String>>(mut self, key_name: S) -> Self {
+        self.tsig_key_name = Some(key_name.into());
+        self
+    }
+
+    /// Get the TSIG key name
+    pub fn tsig_key_name(&self) -> Option<&str> {
+        self.tsig_key_name.as_deref()
+    }
+
+    /// Set the SIG(0) key name used to sign updates, instead of a TSIG key
+    pub fn with_sig0_key_name<S: Into<String>>(mut self, key_name: S) -> Self {
+        self.sig0_key_name = Some(key_name.into());
+        self
+    }
+
+    /// Get the SIG(0) key name
+    pub fn sig0_key_name(&self) -> Option<&str> {
+        self.sig0_key_name.as_deref()
+    }
+
+    /// Set the transport used to reach the DNS server
+    pub fn with_transport(mut self, transport: DnsTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Get the transport
+    pub fn transport(&self) -> DnsTransport {
+        self.transport
+    }
+
+    /// Set the name the server certificate must match (TLS and HTTPS only)
+    pub fn with_tls_server_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.tls_server_name = Some(name.into());
+        self
+    }
+
+    /// Get the TLS server name
+    pub fn tls_server_name(&self) -> Option<&str> {
+        self.tls_server_name.as_deref()
+    }
+
+    /// Browse `domain` as well as the zone, e.g. `services.example.com`
+    pub fn with_browse_domain<S: Into<String>>(mut self, domain: S) -> Self {
+        self.browse_domains.push(domain.into());
+        self
+    }
+
+    /// Get the additional browse domains
+    pub fn browse_domains(&self) -> &[String] {
+        &self.browse_domains
+    }
+
+    /// Set whether the browse domains advertised by the zone are browsed too
+    pub fn with_browse_domain_enumeration(mut self, enabled: bool) -> Self {
+        self.enumerate_browse_domains = enabled;
+        self
+    }
+
+    /// Whether the browse domains advertised by the zone are browsed too
+    pub fn enumerates_browse_domains(&self) -> bool {
+        self.enumerate_browse_domains
+    }
+
+    /// Set the lease of registered records
+    ///
+    /// Registrations are refreshed at half the lease; servers supporting
+    /// update leases remove the records if the lease runs out.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Get the lease of registered records
+    pub fn lease(&self) -> Duration {
+        self.lease
+    }
+
+    /// Validate DNS-SD settings
+    pub fn validate(&self) -> Result<()> {
+        if self.transport.is_encrypted() && self.tls_server_name.is_none() {
+            return Err(crate::error::DiscoveryError::configuration(format!(
+                "DNS transport {:?} requires a TLS server name",
+                self.transport
+            )));
+        }
+        if self.tsig_key_name.is_some() && self.sig0_key_name.is_some() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "DNS updates are signed with either a TSIG or a SIG(0) key, not both",
+            ));
+        }
+        if self.lease < Duration::from_secs(2) {
+            return Err(crate::error::DiscoveryError::configuration(
+                "DNS-SD lease must be at least two seconds",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Transport used by the DNS-SD backend to talk to its DNS server
+///
+/// `Tls` (DNS-over-TLS) and `Https` (DNS-over-HTTPS) keep queries from being
+/// observed or altered on the local network. They require the
+/// `dns-over-tls` and `dns-over-https` features respectively.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DnsTransport {
+    /// Plain DNS over UDP
+    #[default]
+    Udp,
+    /// Plain DNS over TCP
+    Tcp,
+    /// DNS-over-TLS (RFC 7858)
+    Tls,
+    /// DNS-over-HTTPS (RFC 8484)
+    Https,
+}
+
+impl DnsTransport {
+    /// Whether the transport encrypts queries
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, Self::Tls | Self::Https)
+    }
+}
+
+/// Policy applied when probing finds another instance with the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// Skip probing and announce the name as-is
+    Ignore,
+    /// Fail the registration with a conflict error
+    Fail,
+    /// Rename the service ("My Service (2)") until a free name is found
+    AutoRename {
+        /// Maximum number of alternative names to try
+        max_attempts: u32,
+    },
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> Self {
+        Self::AutoRename { max_attempts: 10 }
+    }
+}
+
+/// Configuration for service registration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationConfig {
+    /// Time-to-live for the service record
+    pub ttl: Duration,
+    /// Whether to auto-refresh the service registration
+    pub auto_refresh: bool,
+    /// Refresh interval (only used if auto_refresh is true)
+    pub refresh_interval: Duration,
+    /// Network interfaces to register on
+    pub interfaces: Vec<String>,
+    /// Protocols to use for registration
+    pub protocols: HashSet<ProtocolType>,
+    /// Whether to enable IPv6 registration
+    pub enable_ipv6: bool,
+    /// Whether to enable IPv4 registration
+    pub enable_ipv4: bool,
+    /// SRV priority for services that do not set their own
+    pub priority: u16,
+    /// SRV weight for services that do not set their own
+    pub weight: u16,
+    /// How the announced instance name is derived from the service's name
+    ///
+    /// Not read from configuration files, since custom strategies are code.
+    #[serde(skip)]
+    pub naming: NamingStrategy,
+    /// How to handle instance name conflicts found while probing
+    pub conflict_policy: ConflictPolicy,
+    /// How long to probe the network for conflicting names before announcing
+    pub probe_timeout: Duration,
+    /// Tags added to every service registered with this configuration
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Withdraw the service after this many consecutive failed local health
+    /// probes, and announce it again once a probe succeeds
+    #[serde(default)]
+    pub auto_deregister_on_unhealthy: Option<u32>,
+    /// How often the service is probed when auto-deregistration is enabled
+    #[serde(default = "default_health_probe_interval")]
+    pub health_probe_interval: Duration,
+}
+
+fn default_health_probe_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        let mut protocols = HashSet::new();
+        protocols.insert(ProtocolType::Mdns);
+
+        Self {
+            ttl: Duration::from_secs(120),
+            auto_refresh: true,
+            refresh_interval: Duration::from_secs(60),
+            interfaces: Vec::new(),
+            protocols,
+            enable_ipv6: true,
+            enable_ipv4: true,
+            priority: 0,
+            weight: 0,
+            naming: NamingStrategy::default(),
+            conflict_policy: ConflictPolicy::default(),
+            probe_timeout: Duration::from_millis(250),
+            tags: BTreeSet::new(),
+            auto_deregister_on_unhealthy: None,
+            health_probe_interval: default_health_probe_interval(),
+        }
+    }
+}
+
+impl RegistrationConfig {
+    /// Create a new registration configuration with defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the TTL for service records
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Enable or disable auto-refresh
+    pub fn auto_refresh(mut self, auto_refresh: bool) -> Self {
+        self.auto_refresh = auto_refresh;
+        self
+    }
+
+    /// Set the refresh interval
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Set network interfaces for registration
+    pub fn interfaces<I, S>(mut self, interfaces: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.interfaces = interfaces.into_iter().map(|s| s.into()).collect();
+        self
+    }
+
+    /// Set registration protocols
+    pub fn protocols<I>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = ProtocolType>,
+    {
+        self.protocols = protocols.into_iter().collect();
+        self
+    }
+
+    /// Set service priority
+    pub fn priority(mut self, priority: u16) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set service weight
+    pub fn weight(mut self, weight: u16) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Set how instance names are derived
+    pub fn naming(mut self, naming: NamingStrategy) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Set the name conflict policy
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Set how long to probe for conflicting names
+    pub fn probe_timeout(mut self, timeout: Duration) -> Self {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Add tags to the registered services
+    pub fn tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Withdraw the service after `failures` consecutive failed health probes
+    ///
+    /// The service is probed every
+    /// [`health_probe_interval`](Self::health_probe_interval) with the
+    /// discovery instance's health probe. Once withdrawn, it is announced
+    /// again with the same protocols as soon as a probe succeeds.
+    pub fn auto_deregister_on_unhealthy(mut self, failures: u32) -> Self {
+        self.auto_deregister_on_unhealthy = Some(failures);
+        self
+    }
+
+    /// Set how often the service is probed for auto-deregistration
+    pub fn health_probe_interval(mut self, interval: Duration) -> Self {
+        self.health_probe_interval = interval;
+        self
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.ttl.is_zero() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "TTL cannot be zero",
+            ));
+        }
+
+        if self.auto_refresh && self.refresh_interval.is_zero() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Refresh interval cannot be zero when auto-refresh is enabled",
+            ));
+        }
+
+        if self.auto_refresh && self.refresh_interval >= self.ttl {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Refresh interval must be less than TTL",
+            ));
+        }
+
+        if self.protocols.is_empty() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "At least one protocol must be enabled",
+            ));
+        }
+
+        if !self.enable_ipv4 && !self.enable_ipv6 {
+            return Err(crate::error::DiscoveryError::configuration(
+                "At least one IP version must be enabled",
+            ));
+        }
+
+        if self.conflict_policy != ConflictPolicy::Ignore && self.probe_timeout.is_zero() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Probe timeout cannot be zero when conflict detection is enabled",
+            ));
+        }
+
+        if self.auto_deregister_on_unhealthy == Some(0) {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Auto-deregistration needs at least one failed health probe",
+            ));
+        }
+
+        if self.auto_deregister_on_unhealthy.is_some() && self.health_probe_interval.is_zero() {
+            return Err(crate::error::DiscoveryError::configuration(
+                "Health probe interval cannot be zero when auto-deregistration is enabled",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
     fn test_config_defaults() {
         let config = DiscoveryConfig::new();
         assert_eq!(config.service_types().len(), 0);
@@ -449,6 +1732,99 @@ mod tests {
             .with_ipv6(false);
         assert!(invalid_config.validate().is_err());
 
+        // Auto-deregistration needs a threshold and a probe interval
+        assert!(RegistrationConfig::new().auto_deregister_on_unhealthy(3).validate().is_ok());
+        assert!(RegistrationConfig::new().auto_deregister_on_unhealthy(0).validate().is_err());
+        let registration = RegistrationConfig::new()
+            .auto_deregister_on_unhealthy(3)
+            .health_probe_interval(Duration::ZERO);
+        assert!(registration.validate().is_err());
+
         Ok(())
     }
+
+    #[test]
+    fn test_protocol_sections() {
+        let config = DiscoveryConfig::new()
+            .with_protocol(ProtocolType::Upnp)
+            .with_mdns(MdnsConfig::new().with_hostname("myhost.local."))
+            .with_upnp(UpnpConfig::new().with_mx(2).with_location_url("http://10.0.0.1:8080/desc.xml"))
+            .with_dns_sd(DnsSdConfig::new().with_zone("services.example.com."));
+
+        assert_eq!(config.mdns().hostname(), Some("myhost.local."));
+        assert_eq!(config.upnp().mx(), 2);
+        assert_eq!(config.dns_sd().zone(), "services.example.com.");
+        assert!(config.validate().is_ok());
+
+        let invalid = config.with_upnp(UpnpConfig::new().with_mx(0));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_allowed_networks() {
+        let config = DiscoveryConfig::new();
+        assert!(config.is_address_allowed(&"203.0.113.7".parse().unwrap()));
+
+        let config = config.with_allowed_networks(vec!["10.1.0.0/16".parse().unwrap(), "fd00::/8".parse().unwrap()]);
+        assert!(config.is_address_allowed(&"10.1.4.2".parse().unwrap()));
+        assert!(config.is_address_allowed(&"fd12::1".parse().unwrap()));
+        assert!(!config.is_address_allowed(&"192.168.1.20".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_reflector_validation() -> Result<()> {
+        let ipp = ServiceType::new("_ipp._tcp")?;
+        let config = DiscoveryConfig::new()
+            .with_mdns(MdnsConfig::new().with_reflector(ReflectorConfig::new(["eth0", "eth1"], [ipp.clone()])));
+        assert_eq!(config.mdns().reflector().map(|r| r.interfaces().len()), Some(2));
+        assert!(config.validate().is_ok());
+
+        let one_interface = DiscoveryConfig::new()
+            .with_mdns(MdnsConfig::new().with_reflector(ReflectorConfig::new(["eth0", "eth0"], [ipp])));
+        assert!(one_interface.validate().is_err());
+
+        let no_types = DiscoveryConfig::new()
+            .with_mdns(MdnsConfig::new().with_reflector(ReflectorConfig::new(["eth0", "eth1"], [])));
+        assert!(no_types.validate().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_mode_validation() {
+        let config = DiscoveryConfig::new()
+            .with_container_mode(ContainerConfig::new().with_gateway_url("http://gateway.lan:8380"));
+        assert_eq!(config.container_mode().and_then(|c| c.gateway_url()), Some("http://gateway.lan:8380"));
+        assert!(config.validate().is_ok());
+
+        let https = DiscoveryConfig::new()
+            .with_container_mode(ContainerConfig::new().with_gateway_url("https://gateway.lan"));
+        assert!(https.validate().is_err());
+        let no_wait = DiscoveryConfig::new()
+            .with_container_mode(ContainerConfig::new().with_probe_timeout(Duration::ZERO));
+        assert!(no_wait.validate().is_err());
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let socket = SocketOptions::new()
+            .with_multicast_ttl(4)
+            .with_multicast_loop(false)
+            .with_outgoing_interface("eth1")
+            .with_reuse_port(true);
+        let config = DiscoveryConfig::new().with_upnp(UpnpConfig::new().with_socket_options(socket.clone()));
+        assert_eq!(config.upnp().socket_options(), &socket);
+        assert!(config.validate().is_ok());
+
+        // Sections written before socket options existed still load
+        let upnp: UpnpConfig = serde_json::from_str(r#"{"mx":3,"max_age":{"secs":1800,"nanos":0},"location_url":null}"#).unwrap();
+        assert_eq!(upnp.socket_options(), &SocketOptions::default());
+
+        let invalid = DiscoveryConfig::new()
+            .with_mdns(MdnsConfig::new().with_socket_options(SocketOptions::new().with_multicast_ttl(256)));
+        assert!(invalid.validate().is_err());
+        let invalid = DiscoveryConfig::new()
+            .with_protocol(ProtocolType::Upnp)
+            .with_upnp(UpnpConfig::new().with_socket_options(SocketOptions::new().with_recv_buffer_size(0)));
+        assert!(invalid.validate().is_err());
+    }
 }
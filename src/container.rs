@@ -0,0 +1,155 @@
+//! Detection of container networks without multicast
+//!
+//! mDNS and SSDP depend on multicast reaching the LAN. Inside a container on
+//! a bridge network it does not, yet the container's interface still claims
+//! multicast support, so discovery returns nothing and nothing reports why.
+//! [`check_multicast`] tells the two apart; with
+//! [`DiscoveryConfig::with_container_mode`](crate::config::DiscoveryConfig::with_container_mode)
+//! the [`ProtocolManager`](crate::protocols::ProtocolManager) runs it at
+//! start-up and falls back to unicast DNS-SD or a discovery gateway.
+//!
+//! Detection is a heuristic. Outside a container, multicast is taken to work
+//! whenever a multicast-capable interface is up. Inside one, an mDNS query
+//! for the service types on the link must be answered within the probe
+//! timeout, so a container with host networking on a LAN without any mDNS
+//! responder is reported as lacking multicast.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    utils::network,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+use tracing::debug;
+use trust_dns_proto::{
+    op::{Message, MessageType, Query},
+    rr::{Name, RecordType},
+    serialize::binary::BinEncodable,
+};
+
+/// mDNS IPv4 multicast group and port (RFC 6762 §3)
+const MDNS_GROUP: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+/// Whether multicast discovery can work here
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MulticastCheck {
+    /// Multicast reaches other hosts
+    Available,
+    /// Multicast does not reach other hosts, for the given reason
+    Unavailable(String),
+}
+
+impl MulticastCheck {
+    /// Whether multicast is available
+    pub fn is_available(&self) -> bool {
+        matches!(self, Self::Available)
+    }
+}
+
+impl fmt::Display for MulticastCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Available => write!(f, "multicast available"),
+            Self::Unavailable(reason) => write!(f, "multicast unavailable: {reason}"),
+        }
+    }
+}
+
+/// Whether this process runs inside a container
+///
+/// Looks for the marker files of Docker and Podman, the cgroup of PID 1 and
+/// the environment Kubernetes sets. Always `false` off Linux.
+pub fn in_container() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        if ["/.dockerenv", "/run/.containerenv"].iter().any(|marker| std::path::Path::new(marker).exists())
+            || std::env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+        {
+            return true;
+        }
+        std::fs::read_to_string("/proc/1/cgroup").is_ok_and(|cgroup| is_container_cgroup(&cgroup))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        false
+    }
+}
+
+/// Whether a `/proc/<pid>/cgroup` listing belongs to a container
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn is_container_cgroup(cgroup: &str) -> bool {
+    ["docker", "kubepods", "containerd", "libpod", "lxc"]
+        .iter()
+        .any(|runtime| cgroup.contains(runtime))
+}
+
+/// Check whether multicast discovery can work, waiting up to `timeout`
+pub async fn check_multicast(timeout: Duration) -> MulticastCheck {
+    match network::get_multicast_interfaces() {
+        Ok(interfaces) if interfaces.is_empty() => {
+            return MulticastCheck::Unavailable("no multicast-capable network interface is up".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => return MulticastCheck::Unavailable(format!("network interfaces cannot be listed: {e}")),
+    }
+    if !in_container() {
+        return MulticastCheck::Available;
+    }
+
+    match probe(timeout).await {
+        Ok(true) => MulticastCheck::Available,
+        Ok(false) => MulticastCheck::Unavailable(format!(
+            "running in a container and no mDNS responder answered within {timeout:?}; \
+             bridge networks do not pass multicast"
+        )),
+        Err(e) => MulticastCheck::Unavailable(format!("the multicast probe failed: {e}")),
+    }
+}
+
+/// Ask the link for its service types and report whether anyone answered
+async fn probe(timeout: Duration) -> Result<bool> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_multicast_loop_v4(false)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())?;
+    let socket = UdpSocket::from_std(socket.into())?;
+
+    let name = Name::from_ascii("_services._dns-sd._udp.local.").expect("valid service enumeration name");
+    let mut query = Query::query(name, RecordType::PTR);
+    // Responders answer a query from a port other than 5353 by unicast
+    query.set_mdns_unicast_response(true);
+    let mut message = Message::new();
+    message.set_message_type(MessageType::Query).add_query(query);
+    let packet = message
+        .to_bytes()
+        .map_err(|e| DiscoveryError::network(format!("Failed to encode the multicast probe: {e}")))?;
+    socket.send_to(&packet, MDNS_GROUP).await?;
+
+    let mut buf = [0u8; 1500];
+    match tokio::time::timeout(timeout, socket.recv_from(&mut buf)).await {
+        Ok(Ok((_, from))) => {
+            debug!("Multicast probe answered by {}", from);
+            Ok(true)
+        }
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_cgroup() {
+        assert!(is_container_cgroup("0::/system.slice/docker-3f2a.scope\n"));
+        assert!(is_container_cgroup("12:pids:/kubepods/besteffort/pod1234\n"));
+        assert!(!is_container_cgroup("0::/init.scope\n"));
+    }
+}
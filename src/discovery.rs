@@ -1,25 +1,146 @@
 //! Main service discovery implementation
 
 use crate::{
-    config::DiscoveryConfig,
+    audit::{AuditAction, AuditLog, AuditRecord, AuditSink, JsonLinesAuditSink},
+    cache::{CacheLookup, DiscoveryCache},
+    clock::{self, Clock},
+    config::{ConflictPolicy, DiscoveryConfig, RegistrationConfig, WatchConfig},
     error::{DiscoveryError, Result},
-    protocols::ProtocolManager,
-    service::ServiceInfo,
-    types::ProtocolType,
+    events::{Changes, EventChannelConfig, EventReceiver},
+    fleet::{FleetHandle, ServiceTemplate},
+    health::{HealthConfig, HealthMonitor, HealthProbe},
+    linked::LinkedService,
+    protocols::{
+        lazy::{LazyProtocol, ProtocolFactory},
+        port_mapping::{PortMapper, PortMapping, TransportProtocol},
+        Capabilities, DiscoveryProtocol, InitReport, MultiProtocolResult, ProtocolManager,
+    },
+    query::{DiscoveryOptions, Page, QueryOptions},
+    ranking::{DefaultRanker, RankingContext, ServiceRanker, VERSION_ATTRIBUTE},
+    registration::{self, HealthWatch, RegistrationHandle, RegistrationStatus, Registrations},
+    registry::{RegistrySnapshot, ServiceFilter, ServiceRegistry},
+    resolver::ResolutionCache,
+    rng::SharedRng,
+    safety::{
+        load_balancer::LoadBalancer,
+        Operation, SafetyManager,
+    },
+    schema::{SchemaAction, SchemaValidator},
+    service::{AttributeUpdate, ServiceChange, ServiceEvent, ServiceId, ServiceInfo},
+    types::{DiscoveryFilter, ProtocolType, ServiceType},
+    utils::{network, string::increment_instance_name},
+    verification::{grpc, ConnectivityVerifier, ServiceVerifier, VerificationResult},
 };
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
 use std::{
     collections::HashMap,
-    sync::Arc,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
 };
-use tokio::sync::Mutex;
-use tracing::{debug, info};
+use tokio::{
+    sync::{broadcast::{self, error::RecvError}, mpsc, Mutex},
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{debug, error, info, warn};
+
+mod failover;
+mod network_watch;
+mod service_watch;
+
+use network_watch::NetworkWatch;
+
+/// Discoveries in flight, keyed by service types and protocol
+type InflightQueries = StdMutex<HashMap<(Vec<ServiceType>, Option<ProtocolType>), broadcast::Sender<Result<Vec<ServiceInfo>>>>>;
+
+/// Removes an in-flight entry when its query completes or is cancelled
+struct InflightGuard<'a> {
+    inflight: &'a InflightQueries,
+    key: Option<(Vec<ServiceType>, Option<ProtocolType>)>,
+}
+
+impl InflightGuard<'_> {
+    /// Hand the result to every waiting caller
+    fn complete(mut self, result: &Result<Vec<ServiceInfo>>) {
+        let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(sender) = self.key.take().and_then(|key| inflight.remove(&key)) {
+            let _ = sender.send(result.clone());
+        }
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.inflight.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+        }
+    }
+}
+
+/// Check `service` against its metadata schema, publishing any violation
+///
+/// Returns whether the service should be kept.
+async fn conforms_to_schema(schemas: &SchemaValidator, registry: &ServiceRegistry, service: &ServiceInfo) -> bool {
+    let Some(check) = schemas.check(service) else {
+        return true;
+    };
+    warn!("Service {} violates its metadata schema: {}", service.name, check.violations.join("; "));
+    registry.publish(ServiceEvent::schema_violation(service.clone(), check.violations)).await;
+    check.action == SchemaAction::Flag
+}
+
+/// Keep the services that [`conforms_to_schema`] keeps
+async fn retain_conforming(
+    schemas: &SchemaValidator,
+    registry: &ServiceRegistry,
+    services: Vec<ServiceInfo>,
+) -> Vec<ServiceInfo> {
+    let mut kept = Vec::with_capacity(services.len());
+    for service in services {
+        if conforms_to_schema(schemas, registry, &service).await {
+            kept.push(service);
+        }
+    }
+    kept
+}
+
+/// How long [`ServiceDiscovery::await_dependencies`] waits between attempts
+pub const DEPENDENCY_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Main service discovery interface
 pub struct ServiceDiscovery {
     config: DiscoveryConfig,
     protocol_manager: ProtocolManager,
-    discovered_services: Arc<Mutex<HashMap<String, ServiceInfo>>>,
-    registered_services: Arc<Mutex<HashMap<String, ServiceInfo>>>,
+    registry: Arc<ServiceRegistry>,
+    health: Arc<HealthMonitor>,
+    health_probe: HealthProbe,
+    health_tasks: StdMutex<Vec<JoinHandle<()>>>,
+    /// Network watch task and its interval
+    network_watch: StdMutex<Option<(Duration, JoinHandle<()>)>>,
+    /// Service watch task and its schedule
+    service_watch: StdMutex<Option<(WatchConfig, JoinHandle<()>)>>,
+    verifiers: Vec<Arc<dyn ServiceVerifier>>,
+    ranker: Arc<dyn ServiceRanker>,
+    safety: SafetyManager,
+    cache: Arc<DiscoveryCache>,
+    inflight: InflightQueries,
+    custom_protocols: Vec<Arc<dyn DiscoveryProtocol + Send + Sync>>,
+    port_mapper: Arc<PortMapper>,
+    balancers: StdMutex<HashMap<ServiceType, Arc<LoadBalancer>>>,
+    schemas: Arc<SchemaValidator>,
+    #[cfg(feature = "secure")]
+    policy: Option<Arc<crate::security::policy::PolicyEngine>>,
+    audit_log: AuditLog,
+    /// Writes security alerts to the audit log
+    audit_task: Option<JoinHandle<()>>,
+    discovered_services: Arc<Mutex<HashMap<ServiceId, ServiceInfo>>>,
+    registered_services: Arc<Mutex<HashMap<ServiceId, ServiceInfo>>>,
+    registrations: Registrations,
+    clock: Arc<dyn Clock>,
+    /// Source of retry jitter and random load balancing
+    rng: SharedRng,
 }
 
 impl ServiceDiscovery {
@@ -33,17 +154,12 @@ impl ServiceDiscovery {
     /// 
     /// Returns an error if the configuration is invalid or if protocol initialization fails
     pub async fn new(config: DiscoveryConfig) -> Result<Self> {
-        // Validate configuration before proceeding
-        config.validate()?;
-        
-        let protocol_manager = ProtocolManager::new(config.clone()).await?;
+        ServiceDiscoveryBuilder::new(config).build().await
+    }
 
-        Ok(Self {
-            config,
-            protocol_manager,
-            discovered_services: Arc::new(Mutex::new(HashMap::new())),
-            registered_services: Arc::new(Mutex::new(HashMap::new())),
-        })
+    /// Start building a service discovery instance with injected components
+    pub fn builder(config: DiscoveryConfig) -> ServiceDiscoveryBuilder {
+        ServiceDiscoveryBuilder::new(config)
     }
 
     /// Discover services with optional protocol type filter
@@ -56,20 +172,14 @@ impl ServiceDiscovery {
         }
 
         let timeout = Some(self.config.protocol_timeout());
-        let mut services = match protocol_type {
-            Some(protocol) => {
-                if !self.config.is_protocol_enabled(protocol) {
-                    return Err(DiscoveryError::protocol(format!("Protocol {protocol:?} is not enabled")));
-                }
-                self.protocol_manager.discover_services_with_protocol(protocol, service_types, timeout).await?
-            }
-            None => self.protocol_manager.discover_services(service_types, timeout).await?,
-        };
-
-        // Apply service filtering
-        if let Some(filter) = self.config.filter() {
-            services.retain(|service| filter.matches(service));
+        if let Some(protocol) = protocol_type
+            && !self.config.is_protocol_enabled(protocol)
+        {
+            return Err(DiscoveryError::protocol(format!("Protocol {protocol:?} is not enabled")));
         }
+        let result = self.query(service_types.clone(), protocol_type, timeout).await;
+        self.audit_query(service_types, protocol_type, &result).await;
+        let mut services = self.screen(result?, self.config.filter()).await?;
 
         // Limit number of services if configured
         let max_services = self.config.max_services();
@@ -78,9 +188,10 @@ impl ServiceDiscovery {
         }
 
         // Update discovered services cache
+        self.remember_discovered(&services).await;
         let mut discovered = self.discovered_services.lock().await;
         for service in &services {
-            discovered.insert(service.name().to_string(), service.clone());
+            discovered.insert(service.service_id(), service.clone());
         }
 
         info!("Discovered {} services", services.len());
@@ -88,6 +199,42 @@ impl ServiceDiscovery {
         Ok(services)
     }
 
+    /// Discover services and return one page of them
+    ///
+    /// Runs [`discover_services`](Self::discover_services), so the results
+    /// are ranked and capped at the configured
+    /// [`max_services`](DiscoveryConfig::max_services), then sorted and paged
+    /// as `options` ask. Each call discovers afresh, subject to the result
+    /// cache.
+    ///
+    /// ```rust,no_run
+    /// use auto_discovery::{ServiceDiscovery, config::DiscoveryConfig, query::QueryOptions};
+    ///
+    /// # async fn example() -> auto_discovery::Result<()> {
+    /// let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+    /// let mut options = QueryOptions::new().with_limit(50);
+    /// loop {
+    ///     let page = discovery.discover_services_page(None, &options).await?;
+    ///     // Show page.items()
+    ///     let Some(next) = page.next_token() else { break };
+    ///     options = options.with_continuation(next);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if discovery fails
+    pub async fn discover_services_page(
+        &self,
+        protocol_type: Option<ProtocolType>,
+        options: &QueryOptions,
+    ) -> Result<Page> {
+        let services = self.discover_services(protocol_type).await?;
+        Ok(options.paginate(services))
+    }
+
     /// Discover services with filtering by service types
     /// 
     /// This provides more granular control over service discovery than the basic
@@ -137,150 +284,3276 @@ impl ServiceDiscovery {
         }
 
         let timeout = Some(self.config.protocol_timeout());
-        let mut services = match protocol_type {
-            Some(protocol) => {
-                if !self.config.is_protocol_enabled(protocol) {
-                    return Err(DiscoveryError::protocol(format!("Protocol {protocol:?} is not enabled")));
-                }
-                self.protocol_manager.discover_services_with_protocol(protocol, target_service_types, timeout).await?
+        if let Some(protocol) = protocol_type
+            && !self.config.is_protocol_enabled(protocol)
+        {
+            return Err(DiscoveryError::protocol(format!("Protocol {protocol:?} is not enabled")));
+        }
+        let result = self.query(target_service_types.clone(), protocol_type, timeout).await;
+        self.audit_query(target_service_types, protocol_type, &result).await;
+        let services = self.screen(result?, self.config.filter()).await?;
+
+        // Update discovered services cache
+        self.remember_discovered(&services).await;
+        {
+            let mut discovered = self.discovered_services.lock().await;
+            for service in &services {
+                discovered.insert(service.service_id(), service.clone());
             }
-            None => self.protocol_manager.discover_services(target_service_types, timeout).await?,
+        }
+
+        info!("Discovered {} filtered services", services.len());
+        Ok(services)
+    }
+
+    /// Discover services once with settings that override the configuration
+    ///
+    /// Service types, protocols, timeout and filter set in `options` replace
+    /// the configured ones for this call only, so an app can look for a type
+    /// it was not configured with without building another instance. Results
+    /// are checked, ranked, capped and remembered like those of
+    /// [`discover_services`](Self::discover_services).
+    ///
+    /// ```rust,no_run
+    /// use auto_discovery::{ServiceDiscovery, ServiceType, ProtocolType, config::DiscoveryConfig, query::DiscoveryOptions};
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> auto_discovery::Result<()> {
+    /// let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+    /// let options = DiscoveryOptions::new()
+    ///     .with_service_type(ServiceType::new("_ipp._tcp")?)
+    ///     .with_protocol(ProtocolType::Mdns)
+    ///     .with_timeout(Duration::from_secs(2));
+    /// let printers = discovery.discover_once(&options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no service types are given or configured, a
+    /// requested protocol is not enabled, or every protocol queried fails
+    pub async fn discover_once(&self, options: &DiscoveryOptions) -> Result<Vec<ServiceInfo>> {
+        debug!("Starting one-off service discovery");
+
+        let service_types = if options.service_types().is_empty() {
+            self.config.service_types().to_vec()
+        } else {
+            options.service_types().to_vec()
         };
+        if service_types.is_empty() {
+            return Err(DiscoveryError::configuration("No service types given or configured for discovery"));
+        }
+        if let Some(protocol) = options.protocols().iter().find(|protocol| !self.config.is_protocol_enabled(**protocol)) {
+            return Err(DiscoveryError::protocol(format!("Protocol {protocol:?} is not enabled")));
+        }
+        let timeout = Some(options.timeout().unwrap_or_else(|| self.config.protocol_timeout()));
 
-        // Apply service filtering
-        if let Some(filter) = self.config.filter() {
-            services.retain(|service| filter.matches(service));
+        let mut protocols: Vec<Option<ProtocolType>> = Vec::new();
+        for protocol in options.protocols() {
+            if !protocols.contains(&Some(*protocol)) {
+                protocols.push(Some(*protocol));
+            }
+        }
+        if protocols.is_empty() {
+            protocols.push(None);
         }
+        let results = futures::future::join_all(protocols.into_iter().map(|protocol| {
+            let service_types = service_types.clone();
+            async move {
+                let result = self.query(service_types.clone(), protocol, timeout).await;
+                self.audit_query(service_types, protocol, &result).await;
+                result
+            }
+        }))
+        .await;
 
-        // Update discovered services cache
+        // One protocol failing does not spoil the others' results
+        let mut services = Vec::new();
+        let mut first_error = None;
+        let mut answered = false;
+        for result in results {
+            match result {
+                Ok(found) => {
+                    answered = true;
+                    services.extend(found);
+                }
+                Err(e) => {
+                    warn!("One-off discovery failed: {}", e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if !answered && let Some(e) = first_error {
+            return Err(e);
+        }
+
+        let mut services = self.screen(services, options.filter().or(self.config.filter())).await?;
+        let max_services = self.config.max_services();
+        if max_services > 0 && services.len() > max_services {
+            services.truncate(max_services);
+        }
+
+        self.remember_discovered(&services).await;
         {
             let mut discovered = self.discovered_services.lock().await;
             for service in &services {
-                discovered.insert(service.id.to_string(), service.clone());
+                discovered.insert(service.service_id(), service.clone());
             }
         }
 
-        info!("Discovered {} filtered services", services.len());
+        info!("Discovered {} services in a one-off query", services.len());
         Ok(services)
     }
 
-    /// Register a service
-    pub async fn register_service(&self, service: ServiceInfo) -> Result<()> {
-        let service_name = service.name().to_string();
-        debug!("Registering service: {}", service_name);
+    /// Discover services of `service_type` whose version satisfies `requirement`
+    ///
+    /// Versions are read as semver from the `version` attribute
+    /// ([`VERSION_ATTRIBUTE`](crate::ranking::VERSION_ATTRIBUTE)); services
+    /// without one, or with one that does not parse, are not compatible.
+    /// Compatible services are returned best first. When services are found
+    /// but none is compatible, a [`ServiceEvent::IncompatibleVersions`] is
+    /// published listing them.
+    ///
+    /// ```rust,no_run
+    /// use auto_discovery::{ServiceDiscovery, config::DiscoveryConfig, types::ServiceType};
+    /// use semver::VersionReq;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+    /// let requirement = VersionReq::parse("^2.1")?;
+    /// let apis = discovery.find_compatible(ServiceType::new("_api._tcp")?, &requirement).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if discovery fails
+    pub async fn find_compatible(
+        &self,
+        service_type: ServiceType,
+        requirement: &semver::VersionReq,
+    ) -> Result<Vec<ServiceInfo>> {
+        let services = self.discover_services_filtered(Some(vec![service_type.clone()]), None).await?;
+        let (compatible, incompatible): (Vec<ServiceInfo>, Vec<ServiceInfo>) =
+            services.into_iter().partition(|service| {
+                service
+                    .get_version(VERSION_ATTRIBUTE)
+                    .ok()
+                    .flatten()
+                    .is_some_and(|version| requirement.matches(&version))
+            });
 
-        self.protocol_manager.register_service(service.clone()).await?;
+        if compatible.is_empty() && !incompatible.is_empty() {
+            warn!(
+                "Found {} {} services, none with a version satisfying {}",
+                incompatible.len(),
+                service_type,
+                requirement
+            );
+            self.registry.publish(ServiceEvent::incompatible_versions(
+                service_type,
+                requirement.to_string(),
+                incompatible,
+            )).await;
+        }
+        Ok(compatible)
+    }
 
-        let mut registered = self.registered_services.lock().await;
-        registered.insert(service_name.clone(), service);
+    /// List the service types advertised on the network
+    ///
+    /// Uses the DNS-SD meta-query `_services._dns-sd._udp.local.`, so tools
+    /// can find out what to browse without prior configuration. Types are
+    /// collected for the configured protocol timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if discovery is rate limited
+    pub async fn enumerate_service_types(&self) -> Result<Vec<ServiceType>> {
+        self.safety.check(Operation::Discovery)?;
+        let result = self
+            .protocol_manager
+            .enumerate_service_types(self.config.protocol_timeout())
+            .await
+            .flatten()
+            .map(|mut service_types| {
+                service_types.sort_by_key(|service_type| service_type.to_string());
+                service_types.dedup();
+                service_types
+            });
+        self.track(Operation::Discovery, result)
+    }
 
-        info!("Successfully registered service: {}", service_name);
-        Ok(())
+    /// Discover services as a stream that yields each service as soon as its
+    /// protocol resolves it
+    ///
+    /// Unlike [`discover_services`](Self::discover_services) results are not
+    /// held back until every protocol has finished, so a UI can show devices
+    /// immediately. Services are queried for the filter's service types, or
+    /// the configured ones if it has none, and bypass the result cache. The
+    /// stream ends when all protocols have finished or the timeout expires.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use auto_discovery::{ServiceDiscovery, config::DiscoveryConfig, types::DiscoveryFilter};
+    /// use futures::StreamExt;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+    /// let mut services = Box::pin(discovery.discover_stream(DiscoveryFilter::new()));
+    /// while let Some(service) = services.next().await {
+    ///     println!("Found {} at {}:{}", service.name, service.address, service.port);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn discover_stream(&self, filter: DiscoveryFilter) -> impl Stream<Item = ServiceInfo> + Send + 'static {
+        let service_types = if filter.service_type_filters.is_empty() {
+            self.config.service_types().to_vec()
+        } else {
+            filter.service_type_filters.clone()
+        };
+        let timeout = Some(self.config.protocol_timeout());
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let protocol_manager = self.protocol_manager.clone();
+        let safety = self.safety.clone();
+        tokio::spawn(async move {
+            if service_types.is_empty() {
+                warn!("No service types configured for discovery");
+                return;
+            }
+            if let Err(e) = safety.check(Operation::Discovery) {
+                warn!("Streaming discovery not started: {}", e);
+                return;
+            }
+            let result = protocol_manager
+                .discover_services_with_callback(service_types, timeout, |_, services| {
+                    for service in services {
+                        let _ = sender.send(service.clone());
+                    }
+                })
+                .await
+                .into_result();
+            match result {
+                Ok(_) => safety.record_success(Operation::Discovery),
+                Err(_) => safety.record_failure(Operation::Discovery),
+            }
+        });
+
+        let filters = Arc::new((filter, self.config.filter().cloned()));
+        let discovered = Arc::clone(&self.discovered_services);
+        let registry = Arc::clone(&self.registry);
+        let schemas = Arc::clone(&self.schemas);
+        #[cfg(feature = "secure")]
+        let policy = self.policy.clone();
+        let max_services = match self.config.max_services() {
+            0 => usize::MAX,
+            max => max,
+        };
+
+        UnboundedReceiverStream::new(receiver)
+            .filter_map(move |service| {
+                let filters = Arc::clone(&filters);
+                let discovered = Arc::clone(&discovered);
+                let registry = Arc::clone(&registry);
+                let schemas = Arc::clone(&schemas);
+                #[cfg(feature = "secure")]
+                let policy = policy.clone();
+                async move {
+                    let (filter, config_filter) = &*filters;
+                    if !filter.matches(&service) || config_filter.as_ref().is_some_and(|f| !f.matches(&service)) {
+                        return None;
+                    }
+                    if !conforms_to_schema(&schemas, &registry, &service).await {
+                        return None;
+                    }
+                    #[cfg(feature = "secure")]
+                    if let Some(policy) = policy {
+                        match policy.permits(&service).await {
+                            Ok(true) => {}
+                            Ok(false) => return None,
+                            Err(e) => {
+                                debug!("Access policy check for {} failed: {}", service.name, e);
+                                return None;
+                            }
+                        }
+                    }
+                    discovered.lock().await.insert(service.service_id(), service.clone());
+                    let _ = registry.add_discovered_service(service.clone(), service.protocol_type, None).await;
+                    Some(service)
+                }
+            })
+            .take(max_services)
     }
 
-    /// Unregister a service
-    pub async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
-        let service_name = service.name().to_string();
-        debug!("Unregistering service: {}", service_name);
+    /// Add a custom verifier used by [`verify_service`](Self::verify_service)
+    ///
+    /// Once any verifier is registered, a service is verified only if every
+    /// registered verifier passes; the built-in protocol connectivity check is
+    /// no longer used.
+    pub fn with_verifier<V: ServiceVerifier + 'static>(mut self, verifier: V) -> Self {
+        self.verifiers.push(Arc::new(verifier));
+        self
+    }
 
-        self.protocol_manager.unregister_service(service).await?;
+    /// Order discovery results with `ranker` instead of the [`DefaultRanker`]
+    pub fn with_ranker<R: ServiceRanker + 'static>(mut self, ranker: R) -> Self {
+        self.ranker = Arc::new(ranker);
+        self
+    }
 
-        let mut registered = self.registered_services.lock().await;
-        registered.remove(&service_name);
+    /// Sort `services` best first with the configured ranker
+    async fn rank(&self, services: &mut [ServiceInfo]) {
+        if services.len() < 2 {
+            return;
+        }
+        let local_networks = match network::get_network_interfaces() {
+            Ok(interfaces) => interfaces.into_iter().flat_map(|interface| interface.networks).collect(),
+            Err(e) => {
+                debug!("Ranking without local networks: {}", e);
+                Vec::new()
+            }
+        };
+        let latencies = self
+            .health
+            .get_report()
+            .await
+            .services()
+            .iter()
+            .filter_map(|(name, check)| Some((name.clone(), check.latency()?)))
+            .collect();
+        let context = RankingContext::new()
+            .with_local_networks(local_networks)
+            .with_latencies(latencies);
+        self.ranker.rank(services, &context);
+    }
 
-        info!("Successfully unregistered service: {}", service_name);
-        Ok(())
+    /// Check signatures for the configured access policy with `verifier`
+    ///
+    /// By default keys are trusted on first use; pass a verifier with pinned
+    /// keys to enforce a fixed set of publishers.
+    #[cfg(feature = "secure")]
+    pub fn with_signature_verifier(
+        mut self,
+        verifier: Arc<crate::security::signing::SignatureVerifier>,
+    ) -> Self {
+        if let Some(policy) = self.config.access_policy() {
+            self.policy = Some(Arc::new(crate::security::policy::PolicyEngine::new(policy.clone(), verifier)));
+        }
+        self
     }
 
-    /// Verify a service is still available
-    pub async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
-        debug!("Verifying service: {}", service.name());
+    /// Get the safety manager guarding discovery, registration and verification
+    pub fn safety(&self) -> &SafetyManager {
+        &self.safety
+    }
 
-        self.protocol_manager.verify_service(service).await
+    /// Which protocols started, and why the others did not
+    ///
+    /// Reflects the latest [`update_config`](Self::update_config).
+    pub fn init_report(&self) -> &InitReport {
+        self.protocol_manager.init_report()
     }
 
-    /// Get all discovered services
-    pub async fn get_discovered_services(&self) -> Vec<ServiceInfo> {
-        self.discovered_services.lock().await
-            .values()
-            .cloned()
-            .collect()
+    /// What each enabled protocol can do
+    pub fn protocol_capabilities(&self) -> HashMap<ProtocolType, Capabilities> {
+        self.protocol_manager.capabilities()
     }
 
-    /// Get all registered services
-    pub async fn get_registered_services(&self) -> Vec<ServiceInfo> {
-        self.registered_services.lock().await
-            .values()
-            .cloned()
-            .collect()
+    /// Feed the outcome of a protocol operation into its circuit breaker
+    fn track<T>(&self, operation: Operation, result: Result<T>) -> Result<T> {
+        match &result {
+            Ok(_) => self.safety.record_success(operation),
+            Err(_) => self.safety.record_failure(operation),
+        }
+        result
     }
 
-    /// Check if a service exists
-    pub async fn service_exists(&self, service_name: &str) -> bool {
-        self.discovered_services.lock().await.contains_key(service_name) ||
-        self.registered_services.lock().await.contains_key(service_name)
+    /// Record a discovery query in the audit log, with the answering peers
+    async fn audit_query(
+        &self,
+        service_types: Vec<ServiceType>,
+        protocol_type: Option<ProtocolType>,
+        result: &Result<Vec<ServiceInfo>>,
+    ) {
+        if !self.audit_log.is_enabled() {
+            return;
+        }
+        let mut record = AuditRecord::new(AuditAction::DiscoveryQuery).with_service_types(service_types);
+        if let Some(protocol) = protocol_type {
+            record = record.with_protocol(protocol);
+        }
+        if let Ok(services) = result {
+            let mut peers: Vec<_> = services.iter().map(|service| service.address).collect();
+            peers.sort();
+            peers.dedup();
+            record = record.with_peers(peers).with_detail(format!("{} services found", services.len()));
+        }
+        self.audit_log.record(record.with_result(result)).await;
     }
 
-    /// Update discovery configuration
-    pub async fn update_config(&mut self, config: DiscoveryConfig) -> Result<()> {
-        self.config = config.clone();
-        self.protocol_manager = ProtocolManager::new(config).await?;
-        Ok(())
+    /// Query the protocols, sharing the result with identical queries already in flight
+    ///
+    /// The first caller for a set of service types and protocol runs the
+    /// query; callers arriving before it completes wait for its result
+    /// instead of sending their own network traffic.
+    async fn query(
+        &self,
+        service_types: Vec<ServiceType>,
+        protocol_type: Option<ProtocolType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        let mut key_types = service_types.clone();
+        key_types.sort_by_key(|service_type| service_type.to_string());
+        key_types.dedup();
+        let key = (key_types, protocol_type);
+
+        let follower = {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            match inflight.get(&key) {
+                Some(sender) => Some(sender.subscribe()),
+                None => {
+                    inflight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+
+        if let Some(mut receiver) = follower {
+            debug!("Joining in-flight discovery for {:?}", key.0);
+            #[cfg(feature = "metrics")]
+            metrics::counter!("discovery_queries_coalesced_total").increment(1);
+            if let Ok(result) = receiver.recv().await {
+                return result;
+            }
+            // The leading query was cancelled before it finished
+            return self.query_cached(service_types, protocol_type, timeout).await;
+        }
+
+        let leader = InflightGuard { inflight: &self.inflight, key: Some(key) };
+        let result = self.query_cached(service_types, protocol_type, timeout).await;
+        leader.complete(&result);
+        result
     }
-}
 
+    /// Query the protocols, serving results from the cache where possible
+    ///
+    /// Only uncached service types hit the network. Expired entries within the
+    /// stale-while-revalidate window are returned immediately and refreshed in
+    /// the background.
+    async fn query_cached(
+        &self,
+        service_types: Vec<ServiceType>,
+        protocol_type: Option<ProtocolType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        if !self.cache.is_enabled() {
+            self.safety.check(Operation::Discovery)?;
+            let result = match protocol_type {
+                Some(protocol) => {
+                    self.protocol_manager.discover_services_with_protocol(protocol, service_types, timeout).await
+                }
+                None => self.protocol_manager.discover_services(service_types, timeout).await.flatten(),
+            };
+            return self.track(Operation::Discovery, result);
+        }
+
+        let protocols = match protocol_type {
+            Some(protocol) => vec![protocol],
+            None => self.protocol_manager.protocols_supporting(|c| c.supports_browsing),
+        };
 
+        let mut services = Vec::new();
+        let mut misses: HashMap<ProtocolType, Vec<ServiceType>> = HashMap::new();
+        let mut refreshes: HashMap<ProtocolType, Vec<ServiceType>> = HashMap::new();
+        for protocol in protocols {
+            for service_type in &service_types {
+                match self.cache.lookup(service_type, protocol) {
+                    CacheLookup::Fresh(cached) => services.extend(cached),
+                    CacheLookup::Stale { services: cached, refresh } => {
+                        services.extend(cached);
+                        if refresh {
+                            refreshes.entry(protocol).or_default().push(service_type.clone());
+                        }
+                    }
+                    CacheLookup::Miss => misses.entry(protocol).or_default().push(service_type.clone()),
+                }
+            }
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::types::ServiceType;
-    use std::time::Duration;
+        for (protocol, service_types) in refreshes {
+            let protocol_manager = self.protocol_manager.clone();
+            let cache = Arc::clone(&self.cache);
+            tokio::spawn(async move {
+                match protocol_manager
+                    .discover_services_with_protocol(protocol, service_types.clone(), timeout)
+                    .await
+                {
+                    Ok(found) => cache.store(protocol, &service_types, &found),
+                    Err(e) => {
+                        debug!("Background refresh with {:?} failed: {}", protocol, e);
+                        cache.abandon_refresh(protocol, &service_types);
+                    }
+                }
+            });
+        }
 
-    #[tokio::test]
-    async fn test_service_discovery_creation() {
-        let config = DiscoveryConfig::new()
-            .with_service_type(ServiceType::new("_test._tcp").unwrap())
-            .with_timeout(Duration::from_secs(1));
+        if misses.is_empty() {
+            return Ok(services);
+        }
 
-        let discovery = ServiceDiscovery::new(config).await;
-        assert!(discovery.is_ok());
+        self.safety.check(Operation::Discovery)?;
+        let mut pending: FuturesUnordered<_> = misses
+            .into_iter()
+            .map(|(protocol, service_types)| async move {
+                let result = self
+                    .protocol_manager
+                    .discover_services_with_protocol(protocol, service_types.clone(), timeout)
+                    .await;
+                (protocol, service_types, result)
+            })
+            .collect();
+        while let Some((protocol, service_types, result)) = pending.next().await {
+            match result {
+                Ok(found) => {
+                    self.cache.store(protocol, &service_types, &found);
+                    services.extend(found);
+                }
+                Err(e) if protocol_type.is_some() => return self.track(Operation::Discovery, Err(e)),
+                Err(e) => warn!("Error discovering services with protocol {:?}: {}", protocol, e),
+            }
+        }
+        self.track(Operation::Discovery, Ok(services))
     }
 
-    #[tokio::test]
-    async fn test_service_registration() {
-        let config = DiscoveryConfig::new();
-        let discovery = ServiceDiscovery::new(config).await.unwrap();
+    /// Keep the services matching `filter`, their schemas and the access
+    /// policy, best first
+    async fn screen(&self, mut services: Vec<ServiceInfo>, filter: Option<&DiscoveryFilter>) -> Result<Vec<ServiceInfo>> {
+        if let Some(filter) = filter {
+            services.retain(|service| filter.matches(service));
+        }
+        let services = retain_conforming(&self.schemas, &self.registry, services).await;
+        let mut services = self.apply_access_policy(services).await?;
+        self.rank(&mut services).await;
+        Ok(services)
+    }
 
-        let service = ServiceInfo::new("Test Service", "_test._tcp", 8080, None).unwrap();
-        let result = discovery.register_service(service).await;
-        
-        // Registration might fail due to missing protocol implementation
-        // This is expected in unit tests
-        match result {
-            Ok(_) => {
-                assert_eq!(discovery.get_registered_services().await.len(), 1);
+    /// Track discovered services in the registry so their health can be checked
+    async fn remember_discovered(&self, services: &[ServiceInfo]) {
+        for service in services {
+            if let Err(e) = self.registry.add_discovered_service(service.clone(), service.protocol_type, None).await {
+                debug!("Not tracking {} in the registry: {}", service.name(), e);
             }
-            Err(_) => {
-                // Expected in unit test environment
+        }
+    }
+
+    /// Drop all cached discovery results
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
+    /// Pick an instance of `service_type` for the next request
+    ///
+    /// Instances come from the discovered services in the registry, so they
+    /// follow discovery and expiry, and carry the results of health checks:
+    /// unhealthy instances are skipped. Among the rest the configured
+    /// strategy decides. A discovery is run first if no instance is known.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if discovery fails or no usable instance is found
+    pub async fn load_balanced_endpoint(&self, service_type: &ServiceType) -> Result<ServiceInfo> {
+        self.select_endpoint(service_type, None).await
+    }
+
+    /// Pick an instance of `service_type` for the client identified by `client_key`
+    ///
+    /// Like [`load_balanced_endpoint`](Self::load_balanced_endpoint), but with
+    /// the [`ConsistentHash`](crate::safety::load_balancer::LoadBalancingStrategy::ConsistentHash)
+    /// strategy a key keeps getting the same instance while it stays usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if discovery fails or no usable instance is found
+    pub async fn load_balanced_endpoint_for(
+        &self,
+        service_type: &ServiceType,
+        client_key: &str,
+    ) -> Result<ServiceInfo> {
+        self.select_endpoint(service_type, Some(client_key)).await
+    }
+
+    /// Connect to the best instance of `service_type`, failing over to the next
+    ///
+    /// Instances come from the registry, like those of
+    /// [`load_balanced_endpoint`](Self::load_balanced_endpoint), and are tried
+    /// in SRV order: lowest priority first and, among equal priorities, in a
+    /// random order weighted by SRV weight. Degraded instances follow the
+    /// healthy ones of their priority; unhealthy ones are tried last.
+    /// `connect` is called with each instance until it succeeds, and every
+    /// attempt is recorded with the service type's load balancer. Returns the
+    /// instance that accepted the connection along with the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if discovery fails, no instance is found or every
+    /// attempt fails; the error lists why each instance failed
+    pub async fn connect_best<F, Fut, T, E>(&self, service_type: &ServiceType, mut connect: F) -> Result<(ServiceInfo, T)>
+    where
+        F: FnMut(ServiceInfo) -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+        E: fmt::Display,
+    {
+        let balancer = self.load_balancer(service_type);
+        self.sync_load_balancer(&balancer, service_type).await;
+        if balancer.is_empty() {
+            self.discover_services_filtered(Some(vec![service_type.clone()]), None).await?;
+            self.sync_load_balancer(&balancer, service_type).await;
+        }
+        let filter = ServiceFilter::new()
+            .with_service_types(vec![service_type.clone()])
+            .discovered_only();
+        let candidates = self
+            .registry
+            .find_entries(&filter)
+            .await
+            .into_iter()
+            .map(|entry| (entry.service, entry.health))
+            .collect();
+        let candidates = failover::failover_order(candidates, &self.rng);
+        if candidates.is_empty() {
+            return Err(DiscoveryError::service_not_found(format!("No instance of {service_type}")));
+        }
+
+        let mut failures = Vec::with_capacity(candidates.len());
+        for service in candidates {
+            let id = service.service_id();
+            let started = Instant::now();
+            match connect(service.clone()).await {
+                Ok(connection) => {
+                    balancer.record_request(&id, started.elapsed(), true);
+                    debug!("Connected to {} after {} failed attempts", service.name(), failures.len());
+                    return Ok((service, connection));
+                }
+                Err(e) => {
+                    balancer.record_request(&id, started.elapsed(), false);
+                    debug!("Connecting to {} failed, trying the next instance: {}", service.name(), e);
+                    failures.push(format!("{}: {e}", service.name()));
+                }
             }
         }
+        Err(DiscoveryError::network(format!(
+            "Could not connect to any instance of {service_type}: {}",
+            failures.join("; ")
+        )))
     }
 
-    #[tokio::test]
-    async fn test_event_subscription() {
-        let config = DiscoveryConfig::new();
-        let discovery = ServiceDiscovery::new(config).await.unwrap();
+    async fn select_endpoint(&self, service_type: &ServiceType, client_key: Option<&str>) -> Result<ServiceInfo> {
+        let balancer = self.load_balancer(service_type);
+        self.sync_load_balancer(&balancer, service_type).await;
+        if balancer.is_empty() {
+            self.discover_services_filtered(Some(vec![service_type.clone()]), None).await?;
+            self.sync_load_balancer(&balancer, service_type).await;
+        }
+        match client_key {
+            Some(key) => balancer.select_service_for(key),
+            None => balancer.select_service(),
+        }
+        .ok_or_else(|| DiscoveryError::service_not_found(format!("No usable instance of {service_type}")))
+    }
 
-        // Event subscription is not explicitly tested here as it depends on the underlying protocol implementation
-        // Just ensure it can be called without error
-        let _ = discovery.discover_services(None).await;
+    /// Load balancer for `service_type`, created on first use
+    ///
+    /// Use it to report request outcomes with [`LoadBalancer::record_request`].
+    pub fn load_balancer(&self, service_type: &ServiceType) -> Arc<LoadBalancer> {
+        let mut balancers = self.balancers.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(
+            balancers
+                .entry(service_type.clone())
+                .or_insert_with(|| {
+                    Arc::new(LoadBalancer::new(self.config.load_balancing().clone()).with_rng(self.rng.clone()))
+                }),
+        )
     }
 
-    #[tokio::test]
-    async fn test_config_validation() {
-        let invalid_config = DiscoveryConfig::new().with_timeout(Duration::ZERO);
-        let discovery = ServiceDiscovery::new(invalid_config).await;
-        assert!(discovery.is_err());
+    /// Bring a balancer in line with the registry
+    async fn sync_load_balancer(&self, balancer: &LoadBalancer, service_type: &ServiceType) {
+        let filter = ServiceFilter::new()
+            .with_service_types(vec![service_type.clone()])
+            .discovered_only();
+        let entries = self.registry.find_entries(&filter).await;
+        let ids: Vec<ServiceId> = entries.iter().map(|entry| entry.service_id()).collect();
+        balancer.retain(|id| ids.contains(id));
+        for (entry, id) in entries.into_iter().zip(&ids) {
+            balancer.update_service(entry.service);
+            balancer.set_health(id, entry.health);
+            if let Some(latency) = entry.latency {
+                balancer.set_response_time(id, latency);
+            }
+        }
+    }
+
+    /// Ask the gateway to forward an external port to `internal_port`
+    ///
+    /// Tries PCP, NAT-PMP and UPnP IGD in turn and returns the mapping with
+    /// the external address peers should use. Mappings expire after the lease
+    /// the gateway granted unless requested again.
+    pub async fn request_port_mapping(
+        &self,
+        internal_port: u16,
+        protocol: TransportProtocol,
+        lease: Duration,
+    ) -> Result<PortMapping> {
+        let mapping = self.port_mapper.request(internal_port, protocol, lease).await?;
+        info!(
+            "Mapped {} port {} to {} via {}",
+            protocol, internal_port, mapping.external, mapping.method
+        );
+        Ok(mapping)
+    }
+
+    /// Remove a port mapping created by [`Self::request_port_mapping`]
+    pub async fn release_port_mapping(&self, mapping: &PortMapping) -> Result<()> {
+        self.port_mapper.release(mapping).await
+    }
+
+    /// Public IP address of the network, as reported by its UPnP Internet
+    /// Gateway Device
+    #[cfg(feature = "upnp")]
+    pub async fn external_address(&self) -> Result<std::net::IpAddr> {
+        self.port_mapper.external_address().await
+    }
+
+    /// Drop services rejected by the configured access policy
+    async fn apply_access_policy(&self, services: Vec<ServiceInfo>) -> Result<Vec<ServiceInfo>> {
+        #[cfg(feature = "secure")]
+        if let Some(policy) = &self.policy {
+            return policy.filter(services).await;
+        }
+        Ok(services)
+    }
+
+    /// Subscribe to service events
+    pub fn subscribe(&self) -> broadcast::Receiver<ServiceEvent> {
+        self.registry.subscribe()
+    }
+
+    /// Subscribe to service events through a bounded queue
+    ///
+    /// See [`ServiceRegistry::subscribe_bounded`].
+    pub fn subscribe_bounded(&self, config: EventChannelConfig) -> EventReceiver {
+        self.registry.subscribe_bounded(config)
+    }
+
+    /// Service events published after `sequence`, for replicating discovery
+    /// state elsewhere
+    ///
+    /// See [`ServiceRegistry::changes_since`].
+    pub fn changes_since(&self, sequence: u64) -> Changes {
+        self.registry.changes_since(sequence)
+    }
+
+    /// Copy the services known to this instance, with the sequence number to
+    /// follow [`changes_since`](Self::changes_since) from
+    pub async fn snapshot(&self) -> RegistrySnapshot {
+        self.registry.export_snapshot().await
+    }
+
+    /// Exchange this instance's services with other nodes, so services
+    /// found on their networks show up here and the other way round
+    ///
+    /// See [`crate::gossip`].
+    #[cfg(feature = "gossip")]
+    pub async fn start_gossip(&self, config: crate::gossip::GossipConfig) -> Result<crate::gossip::GossipHandle> {
+        crate::gossip::GossipNode::new(config, Arc::clone(&self.registry))?.bind().await
+    }
+
+    /// Discovered instances of `service_type` as a
+    /// [`tower::discover::Discover`], for balancing hyper or tonic clients
+    /// over them
+    ///
+    /// `make` builds the service for each instance. See [`crate::tower`].
+    #[cfg(feature = "tower")]
+    pub fn tower_discover<F, S>(&self, service_type: &ServiceType, make: F) -> crate::tower::ServiceDiscover<S>
+    where
+        F: Fn(&ServiceInfo) -> S + Send + Sync + 'static,
+        S: Send + 'static,
+    {
+        crate::tower::ServiceDiscover::new(Arc::clone(&self.registry), service_type.clone(), make)
+    }
+
+    /// Register a service using the default registration configuration
+    pub async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        self.register_service_with_config(service, &RegistrationConfig::default())
+            .await
+            .map(|_| ())
+    }
+
+    /// Register a service and return a handle on the live registration
+    ///
+    /// Like [`register_service_with_config`](Self::register_service_with_config);
+    /// the handle reports the registration's status, changes its attributes
+    /// and withdraws it.
+    pub async fn register(
+        &self,
+        service: ServiceInfo,
+        registration: &RegistrationConfig,
+    ) -> Result<RegistrationHandle<'_>> {
+        let service = self.register_service_with_config(service, registration).await?;
+        Ok(RegistrationHandle::new(self, service.service_id()))
+    }
+
+    /// Status of the registration of `id`, if this instance registered it
+    pub fn registration_status(&self, id: &ServiceId) -> Option<RegistrationStatus> {
+        self.registrations.get(id)
+    }
+
+    /// Announce the registered service `id` again now
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service is not registered or no protocol
+    /// accepts the announcement
+    pub async fn refresh_registration(&self, id: &ServiceId) -> Result<RegistrationStatus> {
+        registration::reannounce(&self.protocol_manager, &self.registered_services, &self.registrations, id).await
+    }
+
+    /// Set attributes of the registered service `id` and announce the change
+    ///
+    /// Attributes not mentioned keep their values. See
+    /// [`update_service`](Self::update_service).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service is not registered or no protocol
+    /// accepts the announcement
+    pub async fn update_attributes<I, K, V>(&self, id: &ServiceId, attributes: I) -> Result<RegistrationStatus>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.update_service(id, attributes.into_iter().collect()).await
+    }
+
+    /// Change the attributes or port of the registered service `id` and
+    /// announce the change
+    ///
+    /// The service is announced again with every protocol advertising it:
+    /// mDNS sends the new TXT and SRV records, SSDP a fresh `ssdp:alive`
+    /// NOTIFY and DNS-SD a DNS UPDATE replacing the old records. Subscribers
+    /// get a [`ServiceEvent::Updated`] listing what changed. An update that
+    /// changes nothing announces nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service is not registered, the update is
+    /// invalid or no protocol accepts the announcement
+    pub async fn update_service(&self, id: &ServiceId, update: AttributeUpdate) -> Result<RegistrationStatus> {
+        let not_registered = || DiscoveryError::service_not_found(format!("{id} is not registered"));
+        let mut registered = self.registered_services.lock().await;
+        let service = registered.get_mut(id).ok_or_else(not_registered)?;
+        let previous = service.clone();
+        update.apply(service)?;
+        let changes = ServiceChange::between(&previous, service);
+        if changes.is_empty() {
+            drop(registered);
+            return self.registrations.get(id).ok_or_else(not_registered);
+        }
+        let service = service.clone();
+        drop(registered);
+
+        debug!("Announcing changes to {}", service.name());
+        let result = registration::announce(&self.protocol_manager, &self.registrations, service.clone()).await;
+        self.audit_log
+            .record(AuditRecord::for_service(AuditAction::Registration, &service).with_result(&result))
+            .await;
+        let status = result?;
+        self.registry.publish(ServiceEvent::updated(service, changes)).await;
+        Ok(status)
+    }
+
+    /// Register a service, probing for name conflicts before announcing it
+    ///
+    /// Depending on [`RegistrationConfig::conflict_policy`] a conflicting name
+    /// either fails the registration or is replaced by the next free
+    /// "Name (n)" variant, in which case a [`ServiceEvent::Renamed`] is emitted.
+    /// Returns the service as it was finally registered.
+    pub async fn register_service_with_config(
+        &self,
+        service: ServiceInfo,
+        registration: &RegistrationConfig,
+    ) -> Result<ServiceInfo> {
+        self.register_prepared(service, registration, |_| Ok(())).await
+    }
+
+    /// Register a service whose advertisement is signed with `signer`
+    ///
+    /// The signature is computed after conflict resolution, so it covers the
+    /// final instance name.
+    #[cfg(feature = "secure")]
+    pub async fn register_signed_service(
+        &self,
+        service: ServiceInfo,
+        registration: &RegistrationConfig,
+        signer: &crate::security::signing::ServiceSigner,
+    ) -> Result<ServiceInfo> {
+        self.register_prepared(service, registration, |service| signer.sign(service)).await
+    }
+
+    /// Register a service with every enabled protocol that supports registration
+    ///
+    /// The name is probed and, depending on the conflict policy, renamed
+    /// once, using the service's own protocol; every protocol then
+    /// advertises the same final name. Registration succeeds if at least one
+    /// protocol accepts the service, and the result lists those that didn't.
+    /// Unregistering the service withdraws it from all of them.
+    pub async fn register_everywhere(
+        &self,
+        service: ServiceInfo,
+        registration: &RegistrationConfig,
+    ) -> Result<MultiProtocolResult<ServiceInfo>> {
+        let service = self.named(service, registration)?;
+        let requested_name = service.name().to_string();
+        let service = self.resolve_audited(service, registration, |_| Ok(())).await?;
+
+        let outcome = self.protocol_manager.register_everywhere(service.clone()).await;
+        for (protocol, registered) in outcome.successes() {
+            let record = AuditRecord::for_service(AuditAction::Registration, registered).with_protocol(*protocol);
+            self.audit_log.record(record).await;
+        }
+        for (protocol, e) in outcome.failures() {
+            let record = AuditRecord::for_service(AuditAction::Registration, &service)
+                .with_protocol(*protocol)
+                .failed(e.to_string());
+            self.audit_log.record(record).await;
+        }
+        if outcome.successes().is_empty() {
+            let error = match outcome.into_result() {
+                Err(e) => e,
+                Ok(_) => DiscoveryError::protocol(format!(
+                    "No enabled protocol supports registering '{}'",
+                    service.name()
+                )),
+            };
+            return self.track(Operation::Registration, Err(error));
+        }
+        self.track(Operation::Registration, Ok(()))?;
+        for (protocol, e) in outcome.failures() {
+            warn!("'{}' is not registered with {:?}: {}", service.name(), protocol, e);
+        }
+
+        self.finish_registration(requested_name, service, &outcome, registration).await;
+        Ok(outcome)
+    }
+
+    /// Register `count` instances of `template`, each on its own free port
+    ///
+    /// Ports are taken in order from the template's range, skipping those
+    /// that cannot be bound on this host. Instances are registered like
+    /// [`register_service`](Self::register_service); if one fails, those
+    /// already registered are unregistered again and the error is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the range runs out of free ports or an instance
+    /// cannot be registered
+    pub async fn register_fleet(&self, template: &ServiceTemplate, count: usize) -> Result<FleetHandle<'_>> {
+        let mut services = Vec::with_capacity(count);
+        let mut next_port = Some(*template.ports().start());
+        for index in 1..=count {
+            match self.register_fleet_instance(template, index, &mut next_port).await {
+                Ok(service) => services.push(service),
+                Err(e) => {
+                    warn!("Fleet registration failed at instance {}, rolling back: {}", index, e);
+                    let _ = FleetHandle::new(self, services).unregister().await;
+                    return Err(e);
+                }
+            }
+        }
+        info!("Registered a fleet of {} {} services", count, template.service_type());
+        Ok(FleetHandle::new(self, services))
+    }
+
+    /// Register each endpoint of `service` as a linked instance
+    ///
+    /// See [`linked`](crate::linked). Instances are registered like
+    /// [`register_service`](Self::register_service); if one fails, those
+    /// already registered are unregistered again and the error is returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service has no endpoints, or an instance is
+    /// invalid or cannot be registered
+    pub async fn register_linked(&self, service: &LinkedService) -> Result<FleetHandle<'_>> {
+        let instances = service.instances()?;
+        let mut registered = Vec::with_capacity(instances.len());
+        for instance in instances {
+            match self.register_service_with_config(instance, &RegistrationConfig::default()).await {
+                Ok(instance) => registered.push(instance),
+                Err(e) => {
+                    warn!("Registering linked service '{}' failed, rolling back: {}", service.name(), e);
+                    let _ = FleetHandle::new(self, registered).unregister().await;
+                    return Err(e);
+                }
+            }
+        }
+        info!("Registered '{}' on {} endpoints", service.name(), registered.len());
+        Ok(FleetHandle::new(self, registered))
+    }
+
+    /// Register instance `index` of a fleet on the first free port from `next_port`
+    async fn register_fleet_instance(
+        &self,
+        template: &ServiceTemplate,
+        index: usize,
+        next_port: &mut Option<u16>,
+    ) -> Result<ServiceInfo> {
+        let end = *template.ports().end();
+        let port = match *next_port {
+            Some(start) => network::find_available_port(start, end).await,
+            None => None,
+        }
+        .ok_or_else(|| {
+            DiscoveryError::network(format!(
+                "No free port left in {}..={end} for instance {index}",
+                template.ports().start()
+            ))
+        })?;
+        *next_port = port.checked_add(1);
+        self.register_service_with_config(template.instance(index, port)?, &RegistrationConfig::default())
+            .await
+    }
+
+    async fn register_prepared<F>(
+        &self,
+        service: ServiceInfo,
+        registration: &RegistrationConfig,
+        prepare: F,
+    ) -> Result<ServiceInfo>
+    where
+        F: FnOnce(&mut ServiceInfo) -> Result<()>,
+    {
+        let service = self.named(service, registration)?;
+        let requested_name = service.name().to_string();
+        let service = self.resolve_audited(service, registration, prepare).await?;
+        let result = self.protocol_manager.register_service(service.clone()).await;
+        self.audit_log
+            .record(AuditRecord::for_service(AuditAction::Registration, &service).with_result(&result))
+            .await;
+        self.track(Operation::Registration, result)?;
+
+        let mut outcome = MultiProtocolResult::new();
+        outcome.push(service.protocol_type(), Ok(service.clone()));
+        self.finish_registration(requested_name, service.clone(), &outcome, registration).await;
+        Ok(service)
+    }
+
+    /// `service` under the name its registration's naming strategy gives it
+    fn named(&self, mut service: ServiceInfo, registration: &RegistrationConfig) -> Result<ServiceInfo> {
+        let hostname = match service.hostname().or(self.config.mdns().hostname()) {
+            Some(hostname) => hostname.to_string(),
+            None => gethostname::gethostname().to_string_lossy().into_owned(),
+        };
+        let name = registration.naming.instance_name(&service, &hostname);
+        if name.trim().is_empty() {
+            return Err(DiscoveryError::configuration(format!(
+                "Naming strategy {:?} left no instance name for '{}'",
+                registration.naming,
+                service.name()
+            )));
+        }
+        service.name = name;
+        Ok(service)
+    }
+
+    /// [`resolve_registration`](Self::resolve_registration), recording
+    /// registrations it refuses in the audit log
+    async fn resolve_audited<F>(
+        &self,
+        service: ServiceInfo,
+        registration: &RegistrationConfig,
+        prepare: F,
+    ) -> Result<ServiceInfo>
+    where
+        F: FnOnce(&mut ServiceInfo) -> Result<()>,
+    {
+        let requested = self.audit_log.is_enabled().then(|| service.clone());
+        let result = self.resolve_registration(service, registration, prepare).await;
+        if let (Some(requested), Err(e)) = (requested, &result) {
+            let record = AuditRecord::for_service(AuditAction::Registration, &requested).failed(e.to_string());
+            self.audit_log.record(record).await;
+        }
+        result
+    }
+
+    /// Settle the name and SRV parameters a service will be registered with
+    async fn resolve_registration<F>(
+        &self,
+        mut service: ServiceInfo,
+        registration: &RegistrationConfig,
+        prepare: F,
+    ) -> Result<ServiceInfo>
+    where
+        F: FnOnce(&mut ServiceInfo) -> Result<()>,
+    {
+        registration.validate()?;
+        self.safety.check(Operation::Registration)?;
+
+        let requested_name = service.name().to_string();
+        debug!("Registering service: {}", requested_name);
+
+        match registration.conflict_policy {
+            ConflictPolicy::Ignore => {}
+            ConflictPolicy::Fail => {
+                if self.has_name_conflict(&service, registration).await? {
+                    return Err(DiscoveryError::conflict(format!(
+                        "Service name '{requested_name}' is already in use"
+                    )));
+                }
+            }
+            ConflictPolicy::AutoRename { max_attempts } => {
+                let mut attempts = 0;
+                while self.has_name_conflict(&service, registration).await? {
+                    if attempts >= max_attempts {
+                        return Err(DiscoveryError::conflict(format!(
+                            "No free name found for '{requested_name}' after {max_attempts} attempts"
+                        )));
+                    }
+                    attempts += 1;
+                    service.name = increment_instance_name(service.name());
+                    warn!("Name conflict for '{}', trying '{}'", requested_name, service.name());
+                }
+            }
+        }
+
+        // Services without their own SRV priority and weight get the configured ones
+        if (service.priority, service.weight) == (0, 0) {
+            service = service.with_srv(registration.priority, registration.weight);
+        }
+        service.tags.extend(registration.tags.iter().cloned());
+
+        prepare(&mut service)?;
+        Ok(service)
+    }
+
+    /// Record a service the protocols accepted and start refreshing it
+    async fn finish_registration(
+        &self,
+        requested_name: String,
+        service: ServiceInfo,
+        outcome: &MultiProtocolResult<ServiceInfo>,
+        registration: &RegistrationConfig,
+    ) {
+        let service_name = service.name().to_string();
+        let mut registered = self.registered_services.lock().await;
+        registered.insert(service.service_id(), service.clone());
+        drop(registered);
+
+        let refresh_interval = registration.auto_refresh.then_some(registration.refresh_interval);
+        self.registrations.registered(&service, outcome, refresh_interval);
+        if let Some(interval) = refresh_interval {
+            self.registrations.start_refresh(
+                service.service_id(),
+                interval,
+                self.protocol_manager.clone(),
+                Arc::clone(&self.registered_services),
+            );
+        }
+        if let Some(threshold) = registration.auto_deregister_on_unhealthy {
+            let watch = HealthWatch {
+                threshold,
+                interval: registration.health_probe_interval,
+                probe: self.health_probe.clone(),
+                config: self.config.health().clone(),
+            };
+            self.registrations.start_health_watch(
+                service.service_id(),
+                watch,
+                self.protocol_manager.clone(),
+                Arc::clone(&self.registered_services),
+            );
+        }
+
+        if service_name != requested_name {
+            self.registry.publish(ServiceEvent::renamed(requested_name, service)).await;
+        }
+
+        info!("Successfully registered service: {}", service_name);
+    }
+
+    /// Check local registrations and probe the network for a conflicting name
+    async fn has_name_conflict(&self, service: &ServiceInfo, registration: &RegistrationConfig) -> Result<bool> {
+        let local_conflict = self
+            .registered_services
+            .lock()
+            .await
+            .get(&service.service_id())
+            .is_some_and(|existing| existing.id != service.id);
+        if local_conflict {
+            return Ok(true);
+        }
+
+        self.protocol_manager
+            .probe_name_conflict(service, registration.probe_timeout)
+            .await
+    }
+
+    /// Unregister a service
+    ///
+    /// The service is withdrawn from every protocol the registry records as
+    /// advertising it, so a service registered everywhere is withdrawn
+    /// everywhere, whichever protocol's copy is passed. If some protocols
+    /// fail, the service stays registered with them and can be unregistered
+    /// again.
+    pub async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        // The service may have been renamed during registration
+        let registered_copy = self
+            .registered_services
+            .lock()
+            .await
+            .values()
+            .find(|s| s.id == service.id)
+            .cloned();
+        let service = registered_copy.as_ref().unwrap_or(service);
+
+        let service_name = service.name().to_string();
+        debug!("Unregistering service: {}", service_name);
+
+        let result = self.protocol_manager.unregister_service(service).await;
+        self.audit_log
+            .record(AuditRecord::for_service(AuditAction::Unregistration, service).with_result(&result))
+            .await;
+        result?;
+
+        let mut registered = self.registered_services.lock().await;
+        registered.remove(&service.service_id());
+        drop(registered);
+        self.registrations.remove(&service.service_id());
+
+        info!("Successfully unregistered service: {}", service_name);
+        Ok(())
+    }
+
+    /// Unregister every service this instance registered and stop its
+    /// background tasks
+    ///
+    /// Peers are told the services are gone (mDNS goodbyes with TTL 0, SSDP
+    /// `ssdp:byebye`) before this returns. Dropping the instance sends the
+    /// same goodbyes, but only on a best-effort basis: they are spawned onto
+    /// the current Tokio runtime, if there is one, and may not go out if the
+    /// runtime shuts down first. All services are attempted even if some
+    /// fail; the first error is returned.
+    pub async fn close(&self) -> Result<()> {
+        let services: Vec<ServiceInfo> = self.registered_services.lock().await.values().cloned().collect();
+        let mut first_error = None;
+        for service in services {
+            if let Err(e) = self.unregister_service(&service).await {
+                warn!("Failed to unregister {} on close: {}", service.name(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+        self.stop_background_tasks();
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Abort the background health checks and watches
+    fn stop_background_tasks(&self) {
+        for task in self.health_tasks.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            task.abort();
+        }
+        if let Some((_, task)) = self.network_watch.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+        if let Some((_, task)) = self.service_watch.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+        if let Some(task) = &self.audit_task {
+            task.abort();
+        }
+        self.registrations.stop_tasks();
+    }
+
+    /// Verify a service is still available
+    ///
+    /// Runs the registered [`ServiceVerifier`]s. If there are none, services
+    /// advertising `proto=grpc` get a gRPC health check and others the
+    /// service protocol's connectivity check. The outcome is recorded in the
+    /// health monitor.
+    pub async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        debug!("Verifying service: {}", service.name());
+        let result = self.run_verification(service).await;
+        let record = AuditRecord::for_service(AuditAction::Verification, service);
+        let record = match &result {
+            Ok(result) if result.healthy => record.with_detail(format!("{:?}", result.latency)),
+            Ok(result) => record.failed(result.detail.clone().unwrap_or_else(|| "unhealthy".to_string())),
+            Err(e) => record.failed(e.to_string()),
+        };
+        self.audit_log.record(record).await;
+        let result = result?;
+        self.health.record_service_result(service, &result).await;
+
+        if result.healthy {
+            self.registry
+                .set_latency(&service.service_id(), result.latency)
+                .await;
+        } else {
+            self.registry.publish(ServiceEvent::verification_failed(service.clone())).await;
+        }
+        Ok(result.healthy)
+    }
+
+    /// Check a service with the verifiers, gRPC health check or protocol
+    async fn run_verification(&self, service: &ServiceInfo) -> Result<VerificationResult> {
+        self.safety.check(Operation::Verification)?;
+
+        let result = if self.verifiers.is_empty() && grpc::is_grpc(service) {
+            let _permit = self.protocol_manager.concurrency().acquire(Operation::Verification, service.protocol_type()).await;
+            // Checks gRPC services with the gRPC health protocol
+            ConnectivityVerifier::from_health_config(self.config.health())
+                .with_resolution_cache(Arc::clone(self.registry.resolution_cache()))
+                .verify(service)
+                .await
+        } else if self.verifiers.is_empty() {
+            let start = Instant::now();
+            let result = self.protocol_manager.verify_service(service).await;
+            let healthy = self.track(Operation::Verification, result)?;
+            VerificationResult {
+                healthy,
+                latency: start.elapsed(),
+                detail: None,
+            }
+        } else {
+            let _permit = self.protocol_manager.concurrency().acquire(Operation::Verification, service.protocol_type()).await;
+            self.run_verifiers(service).await?
+        };
+        Ok(result)
+    }
+
+    /// Run custom verifiers in order, stopping at the first failure
+    async fn run_verifiers(&self, service: &ServiceInfo) -> Result<VerificationResult> {
+        let mut latency = Duration::ZERO;
+        for verifier in &self.verifiers {
+            let result = verifier.verify(service).await?;
+            latency += result.latency;
+            if !result.healthy {
+                return Ok(VerificationResult { latency, ..result });
+            }
+        }
+        Ok(VerificationResult::success(latency))
+    }
+
+    /// Wait until a healthy service of each of `dependencies` is discovered
+    ///
+    /// Dependencies are browsed for, bypassing the result cache, and
+    /// candidates checked with [`verify_service`](Self::verify_service)
+    /// until every one is satisfied, retrying every
+    /// [`DEPENDENCY_RETRY_INTERVAL`]. A [`ServiceEvent::DependencyReady`] is
+    /// published as each is satisfied. Returns the service found for each
+    /// dependency.
+    ///
+    /// ```rust,no_run
+    /// use auto_discovery::{ServiceDiscovery, config::DiscoveryConfig, service::ServiceInfo, types::ServiceType};
+    /// use std::time::Duration;
+    ///
+    /// # async fn example() -> auto_discovery::Result<()> {
+    /// let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+    /// let api = ServiceInfo::new("API", "_http._tcp", 8080, None)?
+    ///     .with_dependency(ServiceType::new("_postgres._tcp")?);
+    /// let ready = discovery.await_dependencies(api.dependencies(), Duration::from_secs(60)).await?;
+    /// discovery.register_service(api).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns a timeout error naming the dependencies still missing if they
+    /// are not all ready within `timeout`
+    pub async fn await_dependencies(
+        &self,
+        dependencies: &[ServiceType],
+        timeout: Duration,
+    ) -> Result<HashMap<ServiceType, ServiceInfo>> {
+        let mut pending: Vec<ServiceType> = Vec::new();
+        for dependency in dependencies {
+            if !pending.contains(dependency) {
+                pending.push(dependency.clone());
+            }
+        }
+        let mut ready = HashMap::new();
+        if tokio::time::timeout(timeout, self.resolve_dependencies(&mut pending, &mut ready))
+            .await
+            .is_err()
+        {
+            let missing: Vec<String> = pending.iter().map(ToString::to_string).collect();
+            return Err(DiscoveryError::timeout(format!(
+                "Dependencies not ready after {timeout:?}: {}",
+                missing.join(", ")
+            )));
+        }
+        Ok(ready)
+    }
+
+    /// Move dependencies from `pending` to `ready` as healthy services turn up
+    async fn resolve_dependencies(
+        &self,
+        pending: &mut Vec<ServiceType>,
+        ready: &mut HashMap<ServiceType, ServiceInfo>,
+    ) {
+        while !pending.is_empty() {
+            let found = match self.browse_uncached(pending.clone()).await {
+                Ok(found) => found,
+                Err(e) => {
+                    debug!("Browsing for dependencies failed: {}", e);
+                    Vec::new()
+                }
+            };
+            for service_type in pending.clone() {
+                for candidate in found.iter().filter(|service| service.service_type == service_type) {
+                    if !self.verify_service(candidate).await.unwrap_or(false) {
+                        continue;
+                    }
+                    pending.retain(|waiting| *waiting != service_type);
+                    info!("Dependency {} ready: {}", service_type, candidate);
+                    self.registry.publish(ServiceEvent::dependency_ready(
+                        service_type.clone(),
+                        candidate.clone(),
+                        pending.clone(),
+                    )).await;
+                    ready.insert(service_type, candidate.clone());
+                    break;
+                }
+            }
+            if !pending.is_empty() {
+                tokio::time::sleep(DEPENDENCY_RETRY_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Browse all protocols for `service_types` without consulting the cache
+    async fn browse_uncached(&self, service_types: Vec<ServiceType>) -> Result<Vec<ServiceInfo>> {
+        self.safety.check(Operation::Discovery)?;
+        let result = self
+            .protocol_manager
+            .discover_services(service_types, Some(self.config.protocol_timeout()))
+            .await
+            .flatten();
+        let services = self.track(Operation::Discovery, result)?;
+        let services = retain_conforming(&self.schemas, &self.registry, services).await;
+        let services = self.apply_access_policy(services).await?;
+        self.remember_discovered(&services).await;
+        Ok(services)
+    }
+
+    /// Check discovered services with `probe` once health monitoring is enabled
+    pub fn with_health_probe(mut self, probe: HealthProbe) -> Self {
+        self.health_probe = probe;
+        self
+    }
+
+    /// Check protocol and service health periodically according to `config`
+    ///
+    /// Replaces the health monitor with one using `config`, so earlier
+    /// results are dropped, and restarts checking if it was already running.
+    /// Discovered services are probed with the probe set by
+    /// [`Self::with_health_probe`]; those that fail are demoted in the
+    /// registry and reported with [`ServiceEvent::VerificationFailed`].
+    /// Checks follow the protocols across [`Self::update_config`] and stop
+    /// when this instance is dropped.
+    pub fn enable_health_monitoring(&mut self, config: HealthConfig) -> Result<Arc<HealthMonitor>> {
+        config.validate()?;
+        self.health = Arc::new(
+            HealthMonitor::new(config)
+                .with_probe(self.health_probe.clone())
+                .with_clock(Arc::clone(&self.clock)),
+        );
+        self.restart_health_checks(true);
+        Ok(Arc::clone(&self.health))
+    }
+
+    /// Start health checks against the current protocols, or only restart
+    /// them if they were running and `start` is false
+    fn restart_health_checks(&self, start: bool) {
+        let mut tasks = self.health_tasks.lock().unwrap_or_else(|e| e.into_inner());
+        let running = !tasks.is_empty();
+        for task in tasks.drain(..) {
+            task.abort();
+        }
+        if start || running {
+            tasks.push(Arc::clone(&self.health).start_health_checks(self.protocol_manager.clone()));
+            tasks.push(Arc::clone(&self.health).start_service_checks(Arc::clone(&self.registry)));
+        }
+    }
+
+    /// Follow the host between networks, checking its addresses every `interval`
+    ///
+    /// When the addresses change, for instance because a laptop joined
+    /// another network, [`ServiceEvent::NetworkChanged`] is published, the
+    /// registered services are announced again, moved to a current address
+    /// if theirs went away, and the configured service types are discovered
+    /// afresh. Replaces an earlier watch; the watch follows
+    /// [`Self::update_config`] and stops on [`Self::close`] or when this
+    /// instance is dropped.
+    pub fn watch_network(&self, interval: Duration) -> Result<()> {
+        if interval.is_zero() {
+            return Err(DiscoveryError::configuration("Network watch interval must be greater than zero"));
+        }
+        let task = self.network_watch_parts().start(interval);
+        let previous = self
+            .network_watch
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace((interval, task));
+        if let Some((_, previous)) = previous {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    /// Keep discovering service types in the background, on the re-browse
+    /// schedule of `config`
+    ///
+    /// Services found are recorded in the registry, so
+    /// [`subscribe`](Self::subscribe) sees them come and go. Queries start
+    /// often and back off to [`WatchConfig::max_interval`]; every schedule
+    /// starts over when [`watch_network`](Self::watch_network) sees the
+    /// network change. Replaces an earlier watch; the watch follows
+    /// [`Self::update_config`] and stops on [`Self::close`] or when this
+    /// instance is dropped.
+    pub fn watch_services(&self, config: WatchConfig) -> Result<()> {
+        config.validate()?;
+        let task = service_watch::start(self.network_watch_parts(), config.clone());
+        let previous = self
+            .service_watch
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace((config, task));
+        if let Some((_, previous)) = previous {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    fn network_watch_parts(&self) -> NetworkWatch {
+        NetworkWatch {
+            config: self.config.clone(),
+            protocol_manager: self.protocol_manager.clone(),
+            registry: Arc::clone(&self.registry),
+            cache: Arc::clone(&self.cache),
+            schemas: Arc::clone(&self.schemas),
+            #[cfg(feature = "secure")]
+            policy: self.policy.clone(),
+            discovered_services: Arc::clone(&self.discovered_services),
+            registered_services: Arc::clone(&self.registered_services),
+            registrations: self.registrations.clone(),
+        }
+    }
+
+    /// Get the health monitor that collects verification results
+    pub fn health_monitor(&self) -> Arc<HealthMonitor> {
+        Arc::clone(&self.health)
+    }
+
+    /// Get all discovered services
+    pub async fn get_discovered_services(&self) -> Vec<ServiceInfo> {
+        self.discovered_services.lock().await
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Get all registered services
+    pub async fn get_registered_services(&self) -> Vec<ServiceInfo> {
+        self.registered_services.lock().await
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Check if a service exists
+    pub async fn service_exists(&self, service_name: &str) -> bool {
+        self.discovered_services.lock().await.values().any(|s| s.name == service_name) ||
+        self.registered_services.lock().await.values().any(|s| s.name == service_name)
+    }
+
+    /// Start a protocol without rebuilding the instance
+    ///
+    /// A protocol given to the builder for this type is used again;
+    /// otherwise the built-in implementation is started. Does nothing if the
+    /// protocol is running already. Services registered before are not
+    /// advertised with it.
+    pub async fn enable_protocol(&mut self, protocol_type: ProtocolType) -> Result<()> {
+        let custom = self
+            .custom_protocols
+            .iter()
+            .find(|protocol| protocol.protocol_type() == protocol_type)
+            .cloned();
+        self.protocol_manager.enable_protocol(protocol_type, custom).await?;
+        self.config.enable_protocol(protocol_type);
+        Ok(())
+    }
+
+    /// Stop a protocol without rebuilding the instance
+    ///
+    /// Services registered with the protocol are withdrawn from it first,
+    /// with goodbyes where the protocol sends them; services left without any
+    /// protocol are no longer listed as registered. The protocol's listeners
+    /// are then stopped and cached results are dropped. Withdrawal failures
+    /// do not stop the protocol from being disabled; the first one is
+    /// returned.
+    pub async fn disable_protocol(&mut self, protocol_type: ProtocolType) -> Result<()> {
+        let services: Vec<ServiceInfo> = self.registered_services.lock().await.values().cloned().collect();
+        let mut first_error = None;
+        for service in services {
+            if !self.protocol_manager.registered_protocols(&service).contains(&protocol_type) {
+                continue;
+            }
+            if let Err(e) = self.protocol_manager.unregister_from(&service, protocol_type).await {
+                warn!("Failed to withdraw {} from {}: {}", service.name(), protocol_type, e);
+                first_error.get_or_insert(e);
+            }
+            if self.protocol_manager.registered_protocols(&service).is_empty() {
+                self.registered_services.lock().await.remove(&service.service_id());
+            }
+        }
+
+        self.protocol_manager.disable_protocol(protocol_type).await;
+        self.config.disable_protocol(protocol_type);
+        self.cache.clear();
+        self.balancers.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Update discovery configuration
+    pub async fn update_config(&mut self, config: DiscoveryConfig) -> Result<()> {
+        self.schemas = Arc::new(SchemaValidator::new(config.metadata_schemas())?);
+        self.cache = Arc::new(DiscoveryCache::new(config.cache_duration(), config.stale_while_revalidate()));
+        if config.seed() != self.config.seed() {
+            self.rng = SharedRng::from_seed(config.seed());
+        }
+        let safety = SafetyManager::new(config.safety().clone())
+            .with_clock(Arc::clone(&self.clock))
+            .with_rng(self.rng.clone());
+        self.protocol_manager =
+            ProtocolManager::with_parts(config, self.registry.clone(), safety, self.custom_protocols.clone()).await?;
+        self.config = self.protocol_manager.config().clone();
+        self.safety = self.protocol_manager.safety().clone();
+        self.balancers.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.restart_health_checks(false);
+        let watch_interval = self
+            .network_watch
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|(interval, _)| *interval);
+        if let Some(interval) = watch_interval {
+            self.watch_network(interval)?;
+        }
+        let watch_config = self
+            .service_watch
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|(config, _)| config.clone());
+        if let Some(config) = watch_config {
+            self.watch_services(config)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ServiceDiscovery {
+    fn drop(&mut self) {
+        self.stop_background_tasks();
+
+        // Best-effort goodbyes for services that were never unregistered
+        let services: Vec<ServiceInfo> = match self.registered_services.try_lock() {
+            Ok(mut registered) => registered.drain().map(|(_, service)| service).collect(),
+            Err(_) => return,
+        };
+        if services.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            debug!("No runtime to send goodbyes for {} services on drop", services.len());
+            return;
+        };
+        let protocol_manager = self.protocol_manager.clone();
+        runtime.spawn(async move {
+            for service in services {
+                if let Err(e) = protocol_manager.unregister_service(&service).await {
+                    debug!("Failed to send goodbye for {}: {}", service.name(), e);
+                }
+            }
+        });
+    }
+}
+
+
+/// Write the security alerts published on `registry` to `audit_log`
+fn spawn_security_audit(registry: &ServiceRegistry, audit_log: AuditLog) -> JoinHandle<()> {
+    let mut events = registry.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Some(record) = AuditRecord::from_event(&event) {
+                        audit_log.record(record).await;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    error!("Audit log fell behind and may have missed security events among {} events", missed);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Builds a [`ServiceDiscovery`] from injected components
+///
+/// Anything not supplied is created from the configuration, exactly as
+/// [`ServiceDiscovery::new`] does. Injecting protocols is mainly useful for
+/// tests that replace the network with mocks.
+///
+/// ```no_run
+/// use auto_discovery::{DiscoveryConfig, ServiceDiscovery, registry::ServiceRegistry};
+/// use std::sync::Arc;
+///
+/// # async fn example() -> auto_discovery::Result<()> {
+/// let registry = Arc::new(ServiceRegistry::new());
+/// let discovery = ServiceDiscovery::builder(DiscoveryConfig::new())
+///     .with_registry(registry.clone())
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServiceDiscoveryBuilder {
+    config: DiscoveryConfig,
+    registry: Option<Arc<ServiceRegistry>>,
+    safety: Option<SafetyManager>,
+    clock: Option<Arc<dyn Clock>>,
+    protocols: Vec<Box<dyn DiscoveryProtocol + Send + Sync>>,
+    audit_sinks: Vec<Arc<dyn AuditSink>>,
+    #[cfg(feature = "metrics")]
+    recorder: Option<Box<dyn metrics::Recorder + Send + Sync>>,
+}
+
+impl ServiceDiscoveryBuilder {
+    /// Start from a configuration
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            registry: None,
+            safety: None,
+            clock: None,
+            protocols: Vec::new(),
+            audit_sinks: Vec::new(),
+            #[cfg(feature = "metrics")]
+            recorder: None,
+        }
+    }
+
+    /// Share this registry with the instance and its protocols
+    ///
+    /// Its [resolution cache](ServiceRegistry::resolution_cache) is used in
+    /// place of one built from the configured
+    /// [`ResolverConfig`](crate::resolver::ResolverConfig).
+    pub fn with_registry(mut self, registry: Arc<ServiceRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Use this safety manager instead of one built from the safety config
+    ///
+    /// Clones of a [`SafetyManager`] share their limiters and breakers, so
+    /// several instances can be guarded by one budget.
+    pub fn with_safety_manager(mut self, safety: SafetyManager) -> Self {
+        self.safety = Some(safety);
+        self
+    }
+
+    /// Read the time from `clock` instead of tokio's clock
+    ///
+    /// The clock ages registry entries and times rate limits, circuit
+    /// breakers and health monitor uptime. A registry or safety manager
+    /// supplied with [`with_registry`](Self::with_registry) or
+    /// [`with_safety_manager`](Self::with_safety_manager) keeps its own clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Use `protocol` instead of the built-in implementation of its type
+    ///
+    /// The protocol is handed the instance's registry when it is built and
+    /// stays in use across [`ServiceDiscovery::update_config`].
+    pub fn with_protocol<P: DiscoveryProtocol + 'static>(mut self, protocol: P) -> Self {
+        self.protocols.push(Box::new(protocol));
+        self
+    }
+
+    /// Use a protocol created by `factory` when it is first needed
+    ///
+    /// Takes the place of a protocol of the same type like
+    /// [`with_protocol`](Self::with_protocol). Failed creations are retried
+    /// on the configuration's [`SafetyConfig::retry`](crate::safety::SafetyConfig::retry)
+    /// schedule.
+    pub fn with_protocol_factory<F: ProtocolFactory + 'static>(mut self, factory: F) -> Self {
+        let protocol = LazyProtocol::new(Arc::new(factory), self.config.safety().retry)
+            .with_rng(SharedRng::from_seed(self.config.seed()));
+        self.protocols.push(Box::new(protocol));
+        self
+    }
+
+    /// Record registrations, discovery queries, verifications and security
+    /// events to `sink`
+    ///
+    /// Sinks are used alongside the file set with
+    /// [`DiscoveryConfig::with_audit_log`], if any.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit_sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Install `recorder` as the metrics recorder when building
+    ///
+    /// The `metrics` crate has a single process-wide recorder, so building
+    /// fails if one is already installed.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics_recorder<R: metrics::Recorder + Send + Sync + 'static>(mut self, recorder: R) -> Self {
+        self.recorder = Some(Box::new(recorder));
+        self
+    }
+
+    /// Create the service discovery instance
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is invalid, if protocol
+    /// initialization fails, if the audit log cannot be opened, or if a
+    /// metrics recorder cannot be installed
+    pub async fn build(self) -> Result<ServiceDiscovery> {
+        let config = self.config;
+        config.validate()?;
+
+        #[cfg(feature = "metrics")]
+        if let Some(recorder) = self.recorder {
+            metrics::set_global_recorder(recorder)
+                .map_err(|e| DiscoveryError::configuration(format!("Cannot install metrics recorder: {e}")))?;
+        }
+
+        let mut audit_sinks = self.audit_sinks;
+        if let Some(path) = config.audit_log() {
+            audit_sinks.push(Arc::new(JsonLinesAuditSink::open(path).await?));
+        }
+        let audit_log = AuditLog::new(audit_sinks);
+
+        let clock = self.clock.unwrap_or_else(clock::system);
+        let rng = SharedRng::from_seed(config.seed());
+        let registry = self.registry.unwrap_or_else(|| {
+            let resolutions = Arc::new(ResolutionCache::new(config.resolver().clone()));
+            Arc::new(
+                ServiceRegistry::new()
+                    .with_resolution_cache(resolutions)
+                    .with_clock(Arc::clone(&clock)),
+            )
+        });
+        let audit_task = audit_log.is_enabled().then(|| spawn_security_audit(&registry, audit_log.clone()));
+        let custom_protocols: Vec<Arc<dyn DiscoveryProtocol + Send + Sync>> = self
+            .protocols
+            .into_iter()
+            .map(|mut protocol| {
+                protocol.set_registry(registry.clone());
+                Arc::from(protocol)
+            })
+            .collect();
+        let safety = self
+            .safety
+            .unwrap_or_else(|| {
+                SafetyManager::new(config.safety().clone())
+                    .with_clock(Arc::clone(&clock))
+                    .with_rng(rng.clone())
+            });
+
+        let protocol_manager = ProtocolManager::with_parts(config, registry.clone(), safety, custom_protocols.clone()).await?;
+        let config = protocol_manager.config().clone();
+        let health = Arc::new(HealthMonitor::new(config.health().clone()).with_clock(Arc::clone(&clock)));
+        let safety = protocol_manager.safety().clone();
+        let cache = Arc::new(DiscoveryCache::new(config.cache_duration(), config.stale_while_revalidate()));
+        let port_mapper = Arc::new(PortMapper::new().with_timeout(config.protocol_timeout()));
+        let schemas = Arc::new(SchemaValidator::new(config.metadata_schemas())?);
+        #[cfg(feature = "secure")]
+        let policy = config.access_policy().map(|policy| {
+            use crate::security::signing::{SignatureVerifier, TrustPolicy};
+            Arc::new(crate::security::policy::PolicyEngine::new(
+                policy.clone(),
+                Arc::new(SignatureVerifier::new(TrustPolicy::TrustOnFirstUse)),
+            ))
+        });
+
+        Ok(ServiceDiscovery {
+            config,
+            protocol_manager,
+            registry,
+            health,
+            health_probe: HealthProbe::default(),
+            health_tasks: StdMutex::new(Vec::new()),
+            network_watch: StdMutex::new(None),
+            service_watch: StdMutex::new(None),
+            verifiers: Vec::new(),
+            ranker: Arc::new(DefaultRanker::new()),
+            safety,
+            cache,
+            inflight: StdMutex::new(HashMap::new()),
+            custom_protocols,
+            port_mapper,
+            balancers: StdMutex::new(HashMap::new()),
+            schemas,
+            #[cfg(feature = "secure")]
+            policy,
+            audit_log,
+            audit_task,
+            discovered_services: Arc::new(Mutex::new(HashMap::new())),
+            registered_services: Arc::new(Mutex::new(HashMap::new())),
+            registrations: Registrations::default(),
+            clock,
+            rng,
+        })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::ErrorKind, registration::ProtocolStatus, types::ServiceType};
+
+    #[tokio::test]
+    async fn test_service_discovery_creation() {
+        let config = DiscoveryConfig::new()
+            .with_service_type(ServiceType::new("_test._tcp").unwrap())
+            .with_timeout(Duration::from_secs(1));
+
+        let discovery = ServiceDiscovery::new(config).await;
+        assert!(discovery.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_service_registration() {
+        let config = DiscoveryConfig::new();
+        let discovery = ServiceDiscovery::new(config).await.unwrap();
+
+        let service = ServiceInfo::new("Test Service", "_test._tcp", 8080, None).unwrap();
+        let result = discovery.register_service(service).await;
+        
+        // Registration might fail due to missing protocol implementation
+        // This is expected in unit tests
+        match result {
+            Ok(_) => {
+                assert_eq!(discovery.get_registered_services().await.len(), 1);
+            }
+            Err(_) => {
+                // Expected in unit test environment
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_name_is_renamed() {
+        let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await.unwrap();
+        let mut events = discovery.subscribe();
+
+        let first = ServiceInfo::new("Conflict Test", "_conflict._tcp", 8080, None).unwrap();
+        let second = ServiceInfo::new("Conflict Test", "_conflict._tcp", 8081, None).unwrap();
+
+        let registration = RegistrationConfig::new().probe_timeout(Duration::from_millis(50));
+        if discovery.register_service_with_config(first, &registration).await.is_err() {
+            // mDNS may be unavailable in the test environment
+            return;
+        }
+        let renamed = discovery.register_service_with_config(second, &registration).await.unwrap();
+        assert_eq!(renamed.name(), "Conflict Test (2)");
+
+        match events.try_recv().unwrap() {
+            ServiceEvent::Renamed { previous_name, service, .. } => {
+                assert_eq!(previous_name, "Conflict Test");
+                assert_eq!(service.name(), "Conflict Test (2)");
+            }
+            other => panic!("unexpected event: {other}"),
+        }
+
+        let strict = registration.conflict_policy(ConflictPolicy::Fail);
+        let third = ServiceInfo::new("Conflict Test", "_conflict._tcp", 8082, None).unwrap();
+        assert!(matches!(
+            discovery.register_service_with_config(third, &strict).await,
+            Err(DiscoveryError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_verification_feeds_health_monitor() {
+        let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await.unwrap();
+        let port = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let service = ServiceInfo::new("Unreachable", "_test._tcp", port, None)
+            .unwrap()
+            .with_address("127.0.0.1".parse().unwrap());
+
+        assert!(!discovery.verify_service(&service).await.unwrap());
+        assert_eq!(
+            discovery.health_monitor().service_status("Unreachable").await,
+            Some(crate::health::HealthStatus::Degraded)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_verifiers_replace_default_check() {
+        struct RequireAttribute(&'static str);
+
+        #[async_trait::async_trait]
+        impl ServiceVerifier for RequireAttribute {
+            async fn verify(&self, service: &ServiceInfo) -> Result<VerificationResult> {
+                Ok(match service.get_attribute(self.0) {
+                    Some(_) => VerificationResult::success(Duration::from_millis(1)),
+                    None => VerificationResult::failure(Duration::from_millis(1), format!("missing {}", self.0)),
+                })
+            }
+        }
+
+        let discovery = ServiceDiscovery::new(DiscoveryConfig::new())
+            .await
+            .unwrap()
+            .with_verifier(RequireAttribute("token"))
+            .with_verifier(RequireAttribute("version"));
+
+        // Nothing listens on port 9, so only the custom verifiers can pass this
+        let service = ServiceInfo::new("Custom", "_test._tcp", 9, None)
+            .unwrap()
+            .with_attribute("token", "abc");
+        assert!(!discovery.verify_service(&service).await.unwrap());
+
+        let report = discovery.health_monitor().get_report().await;
+        assert_eq!(report.services()["Custom"].message(), Some("missing version"));
+
+        let service = service.with_attribute("version", "1.0");
+        assert!(discovery.verify_service(&service).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_safety_limits_from_config() {
+        let mut safety = crate::safety::SafetyConfig::default();
+        safety.verification.per_second = 1;
+        let discovery = ServiceDiscovery::new(DiscoveryConfig::new().with_safety(safety)).await.unwrap();
+        let service = ServiceInfo::new("Limited", "_test._tcp", 9, None)
+            .unwrap()
+            .with_address("127.0.0.1".parse().unwrap());
+
+        assert!(discovery.verify_service(&service).await.is_ok());
+        let error = discovery.verify_service(&service).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
+    }
+
+    #[tokio::test]
+    async fn test_cached_results_skip_the_network() {
+        let config = DiscoveryConfig::new()
+            .with_service_type(ServiceType::new("_cachetest._tcp").unwrap())
+            .with_timeout(Duration::from_secs(1));
+        let discovery = ServiceDiscovery::new(config).await.unwrap();
+        discovery.discover_services(Some(ProtocolType::Mdns)).await.unwrap();
+
+        // With mDNS cut off by its circuit breaker only cached results can be served
+        let threshold = discovery.safety().config().circuit_breaker.failure_threshold;
+        for _ in 0..threshold {
+            discovery.safety().record_protocol_result(ProtocolType::Mdns, false);
+        }
+        discovery.discover_services(Some(ProtocolType::Mdns)).await.unwrap();
+
+        discovery.clear_cache();
+        let error = discovery.discover_services(Some(ProtocolType::Mdns)).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
+        assert_eq!(error.context().and_then(|context| context.protocol), Some(ProtocolType::Mdns));
+        assert!(error.retry_after().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_identical_queries_are_coalesced() {
+        let mut safety = crate::safety::SafetyConfig::default();
+        safety.discovery.per_second = 1;
+        let config = DiscoveryConfig::new()
+            .with_service_type(ServiceType::new("_coalesce._tcp").unwrap())
+            .with_timeout(Duration::from_secs(1))
+            .with_cache_duration(Duration::ZERO)
+            .with_safety(safety);
+        let discovery = ServiceDiscovery::new(config).await.unwrap();
+
+        // Only one network query runs, so the second caller isn't rate limited
+        let (first, second) = tokio::join!(discovery.discover_services(None), discovery.discover_services(None));
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+        assert!(discovery.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_discover_stream_yields_services() {
+        let config = DiscoveryConfig::new()
+            .with_service_type(ServiceType::new("_streamtest._tcp").unwrap())
+            .with_timeout(Duration::from_secs(1));
+        let discovery = ServiceDiscovery::new(config).await.unwrap();
+        let service = ServiceInfo::new("Streamed", "_streamtest._tcp", 8080, None)
+            .unwrap()
+            .with_address("127.0.0.1".parse().unwrap());
+        discovery.register_service(service).await.unwrap();
+
+        let found: Vec<_> = discovery.discover_stream(DiscoveryFilter::new()).collect().await;
+        assert!(found.iter().any(|s| s.name == "Streamed"));
+
+        let excluded = DiscoveryFilter::new().with_protocol(ProtocolType::Upnp);
+        assert!(discovery.discover_stream(excluded).collect::<Vec<_>>().await.is_empty());
+    }
+
+    /// Protocol answering every query with a fixed service
+    struct StaticProtocol {
+        registry: Option<Arc<ServiceRegistry>>,
+    }
+
+    #[async_trait::async_trait]
+    impl DiscoveryProtocol for StaticProtocol {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn discover_services(
+            &self,
+            _service_types: Vec<ServiceType>,
+            _timeout: Option<Duration>,
+        ) -> Result<Vec<ServiceInfo>> {
+            assert!(self.registry.is_some());
+            Ok(vec![ServiceInfo::new("Mocked", "_mock._tcp", 9000, None)?.with_protocol_type(ProtocolType::Upnp)])
+        }
+
+        async fn register_service(&self, _service: ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unregister_service(&self, _service: &ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn verify_service(&self, _service: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, registry: Arc<ServiceRegistry>) {
+            self.registry = Some(registry);
+        }
+
+        async fn enumerate_service_types(&self, _timeout: Duration) -> Result<Vec<ServiceType>> {
+            Ok(vec![ServiceType::new("_mock._tcp")?, ServiceType::new("_mock._tcp")?, ServiceType::new("_a._udp")?])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enumerate_service_types() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+
+        let service_types = discovery.enumerate_service_types().await.unwrap();
+        assert_eq!(service_types, [ServiceType::new("_a._udp").unwrap(), ServiceType::new("_mock._tcp").unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_init_report_lists_failed_protocols() {
+        // DNS-SD cannot start without a DNS server
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp, ProtocolType::DnsSd].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config.clone())
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+        let report = discovery.init_report();
+        assert_eq!(report.started(), [ProtocolType::Upnp]);
+        assert_eq!(report.failures().len(), 1);
+        assert_eq!(report.failures()[0].0, ProtocolType::DnsSd);
+        assert!(report.to_string().contains("DNS-SD failed: "), "{report}");
+
+        let config = config
+            .with_protocols([ProtocolType::DnsSd].into_iter().collect())
+            .with_require_at_least_one_protocol(true);
+        let err = ServiceDiscovery::builder(config.clone()).build().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Configuration);
+        assert!(err.to_string().contains("DNS server"), "{err}");
+        let lenient = ServiceDiscovery::builder(config.clone().with_require_at_least_one_protocol(false))
+            .build()
+            .await
+            .unwrap();
+        assert!(lenient.init_report().is_empty());
+
+        // Lazily started protocols only fail once used
+        let lazy = ServiceDiscovery::builder(config.with_lazy_protocols(true))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(lazy.init_report().deferred(), [ProtocolType::DnsSd]);
+        let dns_sd = &lazy.protocol_manager.protocols()[&ProtocolType::DnsSd];
+        assert!(!dns_sd.is_available().await);
+        let err = dns_sd.discover_services(Vec::new(), None).await.unwrap_err();
+        assert!(err.to_string().contains("DNS server"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_await_dependencies() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+        let mut events = discovery.subscribe();
+        let mock = ServiceType::new("_mock._tcp").unwrap();
+        let api = ServiceInfo::new("API", "_http._tcp", 8080, None)
+            .unwrap()
+            .with_dependency(mock.clone())
+            .with_dependency(mock.clone());
+        assert_eq!(api.dependencies(), std::slice::from_ref(&mock));
+
+        let ready = discovery.await_dependencies(api.dependencies(), Duration::from_secs(5)).await.unwrap();
+        assert_eq!(ready[&mock].name, "Mocked");
+        let progress = loop {
+            match events.recv().await.unwrap() {
+                ServiceEvent::DependencyReady { service_type, pending, .. } => break (service_type, pending),
+                _ => continue,
+            }
+        };
+        assert_eq!(progress, (mock.clone(), Vec::new()));
+
+        let missing = ServiceType::new("_postgres._tcp").unwrap();
+        let err = discovery
+            .await_dependencies(&[mock, missing], Duration::from_millis(300))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Timeout);
+        assert!(err.to_string().contains("_postgres._tcp"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_registration_sets_srv_priority_and_weight() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+        let registration = RegistrationConfig::new()
+            .conflict_policy(ConflictPolicy::Ignore)
+            .priority(10)
+            .weight(60);
+        let service = |name: &str| {
+            ServiceInfo::new(name, "_mock._tcp", 9000, None)
+                .unwrap()
+                .with_protocol_type(ProtocolType::Upnp)
+        };
+
+        let registered = discovery.register_service_with_config(service("a"), &registration).await.unwrap();
+        assert_eq!((registered.priority, registered.weight), (10, 60));
+
+        // The service's own values win
+        let own = service("b").with_srv(1, 5);
+        let registered = discovery.register_service_with_config(own, &registration).await.unwrap();
+        assert_eq!((registered.priority, registered.weight), (1, 5));
+
+        // Registration tags are added to the service's own
+        let tagged = registration.tags(["team=payments"]);
+        let registered = discovery
+            .register_service_with_config(service("c").with_tag("canary"), &tagged)
+            .await
+            .unwrap();
+        assert!(registered.has_tag("team=payments") && registered.has_tag("canary"));
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_activity() {
+        use crate::audit::{AuditAction, AuditRecord};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_mock._tcp").unwrap())
+            .with_audit_log(&path);
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+
+        let service = ServiceInfo::new("Audited", "_mock._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+        discovery.register_service_with_config(service.clone(), &registration).await.unwrap();
+        let found = discovery.discover_services(None).await.unwrap();
+        assert!(discovery.verify_service(&found[0]).await.unwrap());
+        discovery.unregister_service(&service).await.unwrap();
+        let intruder = "192.0.2.9".parse().unwrap();
+        discovery.registry.publish(ServiceEvent::security_alert(ProtocolType::Upnp, intruder, "spoofed", true)).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let records: Vec<AuditRecord> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let actions: Vec<_> = records.iter().map(|record| record.action).collect();
+        assert_eq!(
+            actions,
+            [
+                AuditAction::Registration,
+                AuditAction::DiscoveryQuery,
+                AuditAction::Verification,
+                AuditAction::Unregistration,
+                AuditAction::SecurityEvent,
+            ]
+        );
+        assert!(records[..4].iter().all(|record| record.success));
+        assert_eq!(records[0].service.as_deref(), Some("Audited"));
+        assert_eq!(records[1].peers, [found[0].address]);
+        assert_eq!(records[4].peers, [intruder]);
+        assert!(!records[4].success);
+    }
+
+    /// Protocol recording the services it sent goodbyes for
+    struct GoodbyeProtocol {
+        protocol_type: ProtocolType,
+        accepts: bool,
+        goodbyes: Arc<StdMutex<Vec<String>>>,
+    }
+
+    impl GoodbyeProtocol {
+        fn upnp(goodbyes: Arc<StdMutex<Vec<String>>>) -> Self {
+            Self { protocol_type: ProtocolType::Upnp, accepts: true, goodbyes }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl DiscoveryProtocol for GoodbyeProtocol {
+        fn protocol_type(&self) -> ProtocolType {
+            self.protocol_type
+        }
+
+        async fn discover_services(
+            &self,
+            _service_types: Vec<ServiceType>,
+            _timeout: Option<Duration>,
+        ) -> Result<Vec<ServiceInfo>> {
+            Ok(Vec::new())
+        }
+
+        async fn register_service(&self, _service: ServiceInfo) -> Result<()> {
+            if self.accepts {
+                Ok(())
+            } else {
+                Err(DiscoveryError::protocol("registration refused"))
+            }
+        }
+
+        async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+            self.goodbyes
+                .lock()
+                .unwrap()
+                .push(format!("{}/{:?}", service.name(), service.protocol_type()));
+            Ok(())
+        }
+
+        async fn verify_service(&self, _service: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+    }
+
+    #[tokio::test]
+    async fn test_naming_strategy_names_registrations() {
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_mdns(crate::config::MdnsConfig::new().with_hostname("web-01.local."));
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(goodbyes.clone()))
+            .build()
+            .await
+            .unwrap();
+        let service = ServiceInfo::new("Orders", "_http._tcp", 8080, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+
+        let registration = RegistrationConfig::new()
+            .conflict_policy(ConflictPolicy::Ignore)
+            .naming(crate::naming::NamingStrategy::Hostname);
+        let registered = discovery.register_service_with_config(service.clone(), &registration).await.unwrap();
+        assert_eq!(registered.name, "Orders @ web-01");
+        discovery.unregister_service(&service).await.unwrap();
+        assert_eq!(*goodbyes.lock().unwrap(), ["Orders @ web-01/Upnp"]);
+
+        let nameless = registration.naming(crate::naming::NamingStrategy::custom(|_| "\n".to_string()));
+        let error = discovery.register_service_with_config(service, &nameless).await.unwrap_err();
+        assert!(matches!(error, DiscoveryError::Configuration(_)), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_goodbyes_on_close_and_drop() {
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let registered = |name: &'static str| {
+            let goodbyes = goodbyes.clone();
+            async move {
+                let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+                let discovery = ServiceDiscovery::builder(config)
+                    .with_protocol(GoodbyeProtocol::upnp(goodbyes))
+                    .build()
+                    .await
+                    .unwrap();
+                let service = ServiceInfo::new(name, "_mock._tcp", 9000, None)
+                    .unwrap()
+                    .with_protocol_type(ProtocolType::Upnp);
+                let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+                discovery.register_service_with_config(service, &registration).await.unwrap();
+                discovery
+            }
+        };
+
+        let discovery = registered("closed").await;
+        discovery.close().await.unwrap();
+        assert_eq!(*goodbyes.lock().unwrap(), ["closed/Upnp"]);
+        assert!(discovery.get_registered_services().await.is_empty());
+
+        // Closed instances have nothing left to say goodbye for
+        drop(discovery);
+        drop(registered("dropped").await);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(*goodbyes.lock().unwrap(), ["closed/Upnp", "dropped/Upnp"]);
+    }
+
+    #[tokio::test]
+    async fn test_disable_and_enable_protocols_at_runtime() {
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp, ProtocolType::Mdns].into_iter().collect());
+        let mut discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(goodbyes.clone()))
+            .with_protocol(GoodbyeProtocol { protocol_type: ProtocolType::Mdns, accepts: true, goodbyes: goodbyes.clone() })
+            .build()
+            .await
+            .unwrap();
+        let service = ServiceInfo::new("Runtime", "_mock._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+        discovery.register_everywhere(service, &registration).await.unwrap();
+
+        // Still advertised with the protocol left
+        discovery.disable_protocol(ProtocolType::Mdns).await.unwrap();
+        assert_eq!(*goodbyes.lock().unwrap(), ["Runtime/Mdns"]);
+        assert_eq!(discovery.get_registered_services().await.len(), 1);
+        assert!(!discovery.config.is_protocol_enabled(ProtocolType::Mdns));
+        assert_eq!(discovery.init_report().started(), [ProtocolType::Upnp]);
+
+        discovery.disable_protocol(ProtocolType::Upnp).await.unwrap();
+        assert_eq!(*goodbyes.lock().unwrap(), ["Runtime/Mdns", "Runtime/Upnp"]);
+        assert!(discovery.get_registered_services().await.is_empty());
+        assert!(discovery.protocol_capabilities().is_empty());
+
+        // The protocol handed to the builder comes back
+        discovery.enable_protocol(ProtocolType::Upnp).await.unwrap();
+        discovery.enable_protocol(ProtocolType::Upnp).await.unwrap();
+        assert_eq!(discovery.init_report().started(), [ProtocolType::Upnp]);
+        let service = ServiceInfo::new("Again", "_mock._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        discovery.register_service_with_config(service, &registration).await.unwrap();
+        assert_eq!(discovery.get_registered_services().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_register_everywhere_reports_partial_failure() {
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let protocols = [ProtocolType::Upnp, ProtocolType::Mdns, ProtocolType::DnsSd];
+        let config = DiscoveryConfig::new().with_protocols(protocols.into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(goodbyes.clone()))
+            .with_protocol(GoodbyeProtocol { protocol_type: ProtocolType::Mdns, accepts: true, goodbyes: goodbyes.clone() })
+            .with_protocol(GoodbyeProtocol { protocol_type: ProtocolType::DnsSd, accepts: false, goodbyes: goodbyes.clone() })
+            .build()
+            .await
+            .unwrap();
+
+        let service = ServiceInfo::new("Everywhere", "_mock._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+        let outcome = discovery.register_everywhere(service.clone(), &registration).await.unwrap();
+
+        let registered = outcome.succeeded();
+        assert_eq!(registered.len(), 2);
+        assert!(registered.contains(&ProtocolType::Mdns));
+        assert!(registered.contains(&ProtocolType::Upnp));
+        assert!(outcome.successes().iter().all(|(protocol, copy)| copy.protocol_type() == *protocol));
+        assert_eq!(outcome.failed(), [ProtocolType::DnsSd]);
+        assert!(!outcome.is_complete());
+        assert_eq!(discovery.get_registered_services().await.len(), 1);
+
+        // Withdrawn from every protocol that accepted it
+        discovery.unregister_service(&service).await.unwrap();
+        let mut withdrawn = goodbyes.lock().unwrap().clone();
+        withdrawn.sort();
+        assert_eq!(withdrawn, ["Everywhere/Mdns", "Everywhere/Upnp"]);
+        assert!(discovery.get_registered_services().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unregister_withdraws_exactly_the_registered_protocols() {
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let protocols = [ProtocolType::Upnp, ProtocolType::Mdns, ProtocolType::DnsSd];
+        let config = DiscoveryConfig::new().with_protocols(protocols.into_iter().collect());
+        let mut discovery = ServiceDiscovery::builder(config.clone())
+            .with_protocol(GoodbyeProtocol::upnp(goodbyes.clone()))
+            .with_protocol(GoodbyeProtocol { protocol_type: ProtocolType::Mdns, accepts: true, goodbyes: goodbyes.clone() })
+            .with_protocol(GoodbyeProtocol { protocol_type: ProtocolType::DnsSd, accepts: true, goodbyes: goodbyes.clone() })
+            .build()
+            .await
+            .unwrap();
+        let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+
+        // Never registered: no protocol is asked to withdraw it
+        let stranger = ServiceInfo::new("Stranger", "_mock._tcp", 9000, None).unwrap();
+        let error = discovery.unregister_service(&stranger).await.unwrap_err();
+        assert!(matches!(error, DiscoveryError::ServiceNotFound(_)), "{error}");
+        assert!(goodbyes.lock().unwrap().is_empty());
+
+        let service = ServiceInfo::new("Twice", "_mock._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        discovery.register_everywhere(service.clone(), &registration).await.unwrap();
+        discovery.protocol_manager.unregister_from(&service, ProtocolType::DnsSd).await.unwrap();
+        goodbyes.lock().unwrap().clear();
+        let mut held = discovery.registry.registered_protocols(&service);
+        held.sort_by_key(ToString::to_string);
+        assert_eq!(held, [ProtocolType::Upnp, ProtocolType::Mdns]);
+
+        // The records are the registry's, so a new protocol manager keeps them,
+        // and any protocol's copy of the service withdraws all of them
+        discovery.update_config(config).await.unwrap();
+        discovery.unregister_service(&service.clone().with_protocol_type(ProtocolType::DnsSd)).await.unwrap();
+        let mut withdrawn = goodbyes.lock().unwrap().clone();
+        withdrawn.sort();
+        assert_eq!(withdrawn, ["Twice/Mdns", "Twice/Upnp"]);
+        assert!(discovery.registry.registered_protocols(&service).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_register_everywhere_fails_when_no_protocol_accepts() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::DnsSd].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol {
+                protocol_type: ProtocolType::DnsSd,
+                accepts: false,
+                goodbyes: Arc::default(),
+            })
+            .build()
+            .await
+            .unwrap();
+        let service = ServiceInfo::new("Nowhere", "_mock._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::DnsSd);
+        let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+
+        assert!(discovery.register_everywhere(service, &registration).await.is_err());
+        assert!(discovery.get_registered_services().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_builder_injects_components() {
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_mock._tcp").unwrap())
+            .with_timeout(Duration::from_secs(1));
+        let registry = Arc::new(ServiceRegistry::new());
+        let safety = SafetyManager::new(config.safety().clone());
+
+        let discovery = ServiceDiscovery::builder(config)
+            .with_registry(registry.clone())
+            .with_safety_manager(safety.clone())
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&discovery.registry, &registry));
+
+        let services = discovery.discover_services(None).await.unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "Mocked");
+
+        // The injected manager is the one guarding discovery
+        let threshold = safety.config().circuit_breaker.failure_threshold;
+        for _ in 0..threshold {
+            safety.record_protocol_result(ProtocolType::Upnp, false);
+        }
+        assert_eq!(discovery.safety().protocol_state(ProtocolType::Upnp), crate::safety::CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_enable_health_monitoring() {
+        let mut discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await.unwrap();
+        let config = HealthConfig::builder().interval(Duration::from_millis(20)).build().unwrap();
+        let monitor = discovery.enable_health_monitoring(config).unwrap();
+        assert!(Arc::ptr_eq(&monitor, &discovery.health_monitor()));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!monitor.get_report().await.components().is_empty());
+
+        discovery.update_config(DiscoveryConfig::new()).await.unwrap();
+        assert_eq!(discovery.health_tasks.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_load_balanced_endpoint_skips_unhealthy() {
+        let mock = ServiceType::new("_mock._tcp").unwrap();
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(mock.clone())
+            .with_timeout(Duration::from_secs(1));
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+
+        let endpoint = discovery.load_balanced_endpoint(&mock).await.unwrap();
+        assert_eq!(endpoint.name, "Mocked");
+        assert_eq!(discovery.load_balancer(&mock).len(), 1);
+
+        // Verification round trips reach the balancer
+        assert!(discovery.verify_service(&endpoint).await.unwrap());
+        discovery.load_balanced_endpoint(&mock).await.unwrap();
+        let entries = discovery.registry.find_entries(&ServiceFilter::new().discovered_only()).await;
+        let latency = entries[0].latency.expect("verification latency recorded");
+        assert_eq!(discovery.load_balancer(&mock).services()[0].response_time, latency);
+
+        let id = endpoint.service_id();
+        discovery.registry.set_health(&id, crate::health::HealthStatus::Unhealthy).await;
+        assert!(matches!(
+            discovery.load_balanced_endpoint(&mock).await,
+            Err(DiscoveryError::ServiceNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_connect_best_fails_over_in_srv_order() {
+        let db = ServiceType::new("_db._tcp").unwrap();
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(Arc::default()))
+            .build()
+            .await
+            .unwrap();
+        let instances = [("primary", 5432, 10), ("backup", 5433, 20), ("sick", 5434, 0)];
+        for (name, port, priority) in instances {
+            let service = ServiceInfo::new(name, "_db._tcp", port, None).unwrap().with_srv(priority, 1);
+            discovery.registry.add_discovered_service(service, ProtocolType::Upnp, None).await.unwrap();
+        }
+        let sick = discovery.registry.get_services_by_name("sick").await[0].service_id();
+        discovery.registry.set_health(&sick, crate::health::HealthStatus::Unhealthy).await;
+
+        let mut attempts = Vec::new();
+        let (service, port) = discovery
+            .connect_best(&db, |service| {
+                attempts.push(service.name.clone());
+                async move {
+                    match service.name.as_str() {
+                        "primary" => Err("connection refused"),
+                        _ => Ok(service.port),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(attempts, ["primary", "backup"]);
+        assert_eq!((service.name.as_str(), port), ("backup", 5433));
+        let balancer = discovery.load_balancer(&db);
+        let primary = balancer.services().into_iter().find(|load| load.service.name == "primary").unwrap();
+        assert!(primary.success_rate < 1.0);
+
+        // Unhealthy instances are the last resort
+        let error = discovery
+            .connect_best(&db, |service| async move { Err::<(), _>(format!("{} is down", service.port)) })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Network error: Could not connect to any instance of _db._tcp: \
+             primary: 5432 is down; backup: 5433 is down; sick: 5434 is down"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_schema_violations() {
+        use crate::schema::{AttributeRule, MetadataSchema};
+
+        let mock = ServiceType::new("_mock._tcp").unwrap();
+        let schema = MetadataSchema::new(mock.clone()).with_attribute("version", AttributeRule::required());
+        let base = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(mock.clone());
+        let mut discovery = ServiceDiscovery::builder(base.clone().with_metadata_schema(schema.clone()))
+            .with_protocol(StaticProtocol { registry: None })
+            .build()
+            .await
+            .unwrap();
+        let mut events = discovery.subscribe();
+
+        let services = discovery.discover_services_filtered(Some(vec![mock.clone()]), None).await.unwrap();
+        assert!(services.is_empty());
+        assert!(discovery.registry.get_discovered_services().await.is_empty());
+        match events.recv().await.unwrap() {
+            ServiceEvent::SchemaViolation { service, violations, .. } => {
+                assert_eq!(service.name, "Mocked");
+                assert_eq!(violations.len(), 1);
+            }
+            event => panic!("unexpected event {event}"),
+        }
+
+        // Flagged services are kept
+        let config = base.with_metadata_schema(schema.with_action(SchemaAction::Flag));
+        discovery.update_config(config).await.unwrap();
+        let services = discovery.discover_services_filtered(Some(vec![mock]), None).await.unwrap();
+        assert_eq!(services.len(), 1);
+    }
+
+    /// Finds the same fixed list of services every time
+    struct ListProtocol(Vec<ServiceInfo>);
+
+    #[async_trait::async_trait]
+    impl DiscoveryProtocol for ListProtocol {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn discover_services(&self, _: Vec<ServiceType>, _: Option<Duration>) -> Result<Vec<ServiceInfo>> {
+            Ok(self.0.clone())
+        }
+
+        async fn register_service(&self, _: ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unregister_service(&self, _: &ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn verify_service(&self, _: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _: Arc<ServiceRegistry>) {}
+    }
+
+    #[tokio::test]
+    async fn test_discovery_results_ranked() {
+        /// Prefers the highest port
+        struct HighestPort;
+
+        impl ServiceRanker for HighestPort {
+            fn score(&self, service: &ServiceInfo, _context: &RankingContext) -> f64 {
+                f64::from(service.port)
+            }
+        }
+
+        let services: Vec<ServiceInfo> = [("low", 80), ("high", 9000), ("mid", 8080)]
+            .into_iter()
+            .map(|(name, port)| ServiceInfo::new(name, "_mock._tcp", port, None).unwrap().with_protocol_type(ProtocolType::Upnp))
+            .collect();
+        let mut verified = ServiceInfo::new("verified", "_mock._tcp", 81, None).unwrap().with_protocol_type(ProtocolType::Upnp);
+        verified.verified = true;
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_mock._tcp").unwrap());
+
+        // Verified services come first by default
+        let discovery = ServiceDiscovery::builder(config.clone())
+            .with_protocol(ListProtocol([services.clone(), vec![verified]].concat()))
+            .build()
+            .await
+            .unwrap();
+        let found = discovery.discover_services(None).await.unwrap();
+        assert_eq!(found[0].name, "verified");
+
+        // The best services are kept when results are limited
+        let discovery = ServiceDiscovery::builder(config.with_max_services(2))
+            .with_protocol(ListProtocol(services))
+            .build()
+            .await
+            .unwrap()
+            .with_ranker(HighestPort);
+        let found = discovery.discover_services(None).await.unwrap();
+        let names: Vec<&str> = found.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["high", "mid"]);
+    }
+
+    #[tokio::test]
+    async fn test_find_compatible() {
+        let service = |name: &str, version: Option<&str>| {
+            let service = ServiceInfo::new(name, "_mock._tcp", 9000, None).unwrap().with_protocol_type(ProtocolType::Upnp);
+            match version {
+                Some(version) => service.with_attribute("version", version),
+                None => service,
+            }
+        };
+        let mock = ServiceType::new("_mock._tcp").unwrap();
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(ListProtocol(vec![
+                service("old", Some("1.4.2")),
+                service("new", Some("2.3.0")),
+                service("unversioned", None),
+                service("garbled", Some("two")),
+            ]))
+            .build()
+            .await
+            .unwrap();
+        let mut events = discovery.subscribe();
+
+        let found = discovery.find_compatible(mock.clone(), &semver::VersionReq::parse("^2.1").unwrap()).await.unwrap();
+        let names: Vec<&str> = found.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["new"]);
+        while let Ok(event) = events.try_recv() {
+            assert!(!matches!(event, ServiceEvent::IncompatibleVersions { .. }), "{event}");
+        }
+
+        let found = discovery.find_compatible(mock, &semver::VersionReq::parse(">=3").unwrap()).await.unwrap();
+        assert!(found.is_empty());
+        let event = std::iter::from_fn(|| events.try_recv().ok())
+            .find(|event| matches!(event, ServiceEvent::IncompatibleVersions { .. }))
+            .unwrap();
+        let ServiceEvent::IncompatibleVersions { requirement, services, .. } = event else { unreachable!() };
+        assert_eq!(requirement, ">=3");
+        assert_eq!(services.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_register_fleet() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(Arc::clone(&goodbyes)))
+            .build()
+            .await
+            .unwrap();
+        let blocker = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy = blocker.local_addr().unwrap().port();
+        let template = ServiceTemplate::new("worker-{index}", "_worker._tcp", busy..=busy.saturating_add(50))
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp)
+            .with_attribute("queue", "images");
+
+        let fleet = discovery.register_fleet(&template, 3).await.unwrap();
+        let names: Vec<&str> = fleet.services().iter().map(ServiceInfo::name).collect();
+        assert_eq!(names, ["worker-1", "worker-2", "worker-3"]);
+        let mut ports: Vec<u16> = fleet.services().iter().map(|service| service.port).collect();
+        assert!(!ports.contains(&busy));
+        ports.dedup();
+        assert_eq!(ports.len(), 3);
+        assert!(fleet.services().iter().all(|service| service.attributes["queue"] == "images"));
+
+        fleet.unregister().await.unwrap();
+        assert_eq!(goodbyes.lock().unwrap().len(), 3);
+        assert!(discovery.registered_services.lock().await.is_empty());
+
+        // A range too small for the fleet registers nothing
+        let template = ServiceTemplate::new("worker", "_worker._tcp", busy..=busy).unwrap();
+        assert!(discovery.register_fleet(&template, 1).await.is_err());
+        assert!(discovery.registered_services.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registration_handle() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(Arc::clone(&goodbyes)))
+            .build()
+            .await
+            .unwrap();
+        let service = ServiceInfo::new("Worker", "_worker._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let registration = RegistrationConfig::default()
+            .ttl(Duration::from_secs(1))
+            .refresh_interval(Duration::from_millis(20));
+
+        let handle = discovery.register(service, &registration).await.unwrap();
+        let status = handle.status().unwrap();
+        assert!(status.is_registered());
+        assert_eq!(status.protocols()[&ProtocolType::Upnp], ProtocolStatus::Registered);
+        assert_eq!(status.refresh_interval(), Some(Duration::from_millis(20)));
+
+        // The registration is refreshed in the background
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while handle.status().unwrap().announcements() < 3 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let status = handle.update_attributes([("state", "busy")]).await.unwrap();
+        assert_eq!(status.service().attributes["state"], "busy");
+        assert!(status.last_announced() >= status.registered_at());
+        let registered = discovery.registered_services.lock().await[handle.service_id()].clone();
+        assert_eq!(registered.attributes["state"], "busy");
+
+        let id = handle.service_id().clone();
+        handle.unregister().await.unwrap();
+        assert!(discovery.registration_status(&id).is_none());
+        assert_eq!(goodbyes.lock().unwrap().as_slice(), ["Worker/Upnp"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unhealthy_registrations_are_withdrawn_and_reinstated() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct Switch(Arc<AtomicBool>);
+
+        #[async_trait::async_trait]
+        impl ServiceVerifier for Switch {
+            async fn verify(&self, _service: &ServiceInfo) -> Result<VerificationResult> {
+                Ok(if self.0.load(Ordering::SeqCst) {
+                    VerificationResult::success(Duration::from_millis(1))
+                } else {
+                    VerificationResult::failure(Duration::from_millis(1), "switched off")
+                })
+            }
+        }
+
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let healthy = Arc::new(AtomicBool::new(false));
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(Arc::clone(&goodbyes)))
+            .build()
+            .await
+            .unwrap()
+            .with_health_probe(HealthProbe::Custom(Arc::new(Switch(Arc::clone(&healthy)))));
+        let service = ServiceInfo::new("Flaky", "_worker._tcp", 9000, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let registration = RegistrationConfig::default()
+            .auto_refresh(false)
+            .auto_deregister_on_unhealthy(3)
+            .health_probe_interval(Duration::from_secs(5));
+        let mut events = discovery.subscribe();
+        let handle = discovery.register(service.clone(), &registration).await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        let event = events.recv().await.unwrap();
+        assert!(matches!(&event, ServiceEvent::Withdrawn { service, failures: 3, .. } if service.name == "Flaky"));
+        assert_eq!(started.elapsed(), Duration::from_secs(15));
+        assert_eq!(goodbyes.lock().unwrap().as_slice(), ["Flaky/Upnp"]);
+        assert!(discovery.registry.registered_protocols(&service).is_empty());
+        let status = handle.status().unwrap();
+        assert!(status.is_withdrawn() && !status.is_registered());
+
+        // Updates wait for the service to be reinstated
+        let status = handle.update_attributes([("state", "recovering")]).await.unwrap();
+        assert_eq!(status.announcements(), 1);
+        assert!(matches!(events.recv().await.unwrap(), ServiceEvent::Updated { .. }));
+
+        healthy.store(true, Ordering::SeqCst);
+        let event = events.recv().await.unwrap();
+        assert!(matches!(&event, ServiceEvent::Reinstated { service, .. } if service.attributes["state"] == "recovering"));
+        assert_eq!(discovery.registry.registered_protocols(&service), [ProtocolType::Upnp]);
+        let status = handle.status().unwrap();
+        assert!(status.is_registered());
+        assert_eq!(status.announcements(), 2);
+
+        handle.unregister().await.unwrap();
+        assert_eq!(goodbyes.lock().unwrap().as_slice(), ["Flaky/Upnp", "Flaky/Upnp"]);
+    }
+
+    #[tokio::test]
+    async fn test_update_service() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(Arc::new(StdMutex::new(Vec::new()))))
+            .build()
+            .await
+            .unwrap();
+        let service = ServiceInfo::new("API", "_http._tcp", 8080, Some(vec![("version", "1"), ("state", "idle")]))
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let id = service.service_id();
+        discovery.register_service(service).await.unwrap();
+        let mut events = discovery.subscribe();
+
+        let update = AttributeUpdate::new().with_attribute("version", "2").without_attribute("state").with_port(8081);
+        let status = discovery.update_service(&id, update).await.unwrap();
+        assert_eq!(status.service().port, 8081);
+        assert_eq!(status.announcements(), 2);
+        match events.try_recv().unwrap() {
+            ServiceEvent::Updated { service, changes, .. } => {
+                assert_eq!(service.port, 8081);
+                assert_eq!(changes, [
+                    ServiceChange::Port { previous: 8080, current: 8081 },
+                    ServiceChange::Attribute { key: "state".into(), previous: Some("idle".into()), current: None },
+                    ServiceChange::Attribute { key: "version".into(), previous: Some("1".into()), current: Some("2".into()) },
+                ]);
+            }
+            event => panic!("unexpected event {event}"),
+        }
+
+        // Nothing changes, so nothing is announced
+        let status = discovery.update_attributes(&id, [("version", "2")]).await.unwrap();
+        assert_eq!(status.announcements(), 2);
+        assert!(events.try_recv().is_err());
+
+        let unknown = ServiceInfo::new("Other", "_http._tcp", 80, None).unwrap().service_id();
+        let err = discovery.update_service(&unknown, AttributeUpdate::new()).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_register_linked() {
+        use crate::linked::{self, Endpoint};
+
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let goodbyes = Arc::new(StdMutex::new(Vec::new()));
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(GoodbyeProtocol::upnp(Arc::clone(&goodbyes)))
+            .build()
+            .await
+            .unwrap();
+        let api = LinkedService::new("API")
+            .with_protocol_type(ProtocolType::Upnp)
+            .with_endpoint(Endpoint::new("http", "_http._tcp", 8080).unwrap())
+            .with_endpoint(Endpoint::new("https", "_http._tcp", 8443).unwrap().secure());
+
+        let registration = discovery.register_linked(&api).await.unwrap();
+        assert_eq!(registration.len(), 2);
+        assert!(registration.services().iter().all(|service| linked::link_of(service) == Some(api.link())));
+        let preferred = linked::prefer_secure(registration.services().to_vec());
+        assert_eq!(preferred.len(), 1);
+        assert_eq!(preferred[0].port, 8443);
+
+        registration.unregister().await.unwrap();
+        assert_eq!(goodbyes.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_discover_once_overrides_config() {
+        /// Finds one service of each requested type, on a port telling the timeout in seconds
+        struct EchoProtocol;
+
+        #[async_trait::async_trait]
+        impl DiscoveryProtocol for EchoProtocol {
+            fn protocol_type(&self) -> ProtocolType {
+                ProtocolType::Upnp
+            }
+
+            async fn discover_services(&self, service_types: Vec<ServiceType>, timeout: Option<Duration>) -> Result<Vec<ServiceInfo>> {
+                let port = timeout.map_or(1, |timeout| timeout.as_secs() as u16);
+                service_types
+                    .into_iter()
+                    .map(|service_type| {
+                        Ok(ServiceInfo::new(service_type.to_string(), service_type, port, None)?
+                            .with_protocol_type(ProtocolType::Upnp))
+                    })
+                    .collect()
+            }
+
+            async fn register_service(&self, _: ServiceInfo) -> Result<()> {
+                Ok(())
+            }
+
+            async fn unregister_service(&self, _: &ServiceInfo) -> Result<()> {
+                Ok(())
+            }
+
+            async fn verify_service(&self, _: &ServiceInfo) -> Result<bool> {
+                Ok(true)
+            }
+
+            async fn is_available(&self) -> bool {
+                true
+            }
+
+            fn set_registry(&mut self, _: Arc<ServiceRegistry>) {}
+        }
+
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config).with_protocol(EchoProtocol).build().await.unwrap();
+        assert!(discovery.discover_services(None).await.is_err());
+        assert!(discovery.discover_once(&DiscoveryOptions::new()).await.is_err());
+
+        let options = DiscoveryOptions::new()
+            .with_service_type(ServiceType::new("_ipp._tcp").unwrap())
+            .with_service_type(ServiceType::new("_http._tcp").unwrap())
+            .with_protocol(ProtocolType::Upnp)
+            .with_timeout(Duration::from_secs(7));
+        let mut services = discovery.discover_once(&options).await.unwrap();
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        let found: Vec<(&str, u16)> = services.iter().map(|service| (service.name(), service.port)).collect();
+        assert_eq!(found, [("_http._tcp", 7), ("_ipp._tcp", 7)]);
+        assert_eq!(discovery.get_discovered_services().await.len(), 2);
+
+        let filter = DiscoveryFilter::new().with_service_type(ServiceType::new("_ipp._tcp").unwrap());
+        let services = discovery.discover_once(&options.clone().with_filter(filter)).await.unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "_ipp._tcp");
+
+        let mdns = options.with_protocol(ProtocolType::Mdns);
+        assert!(matches!(discovery.discover_once(&mdns).await, Err(DiscoveryError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn test_discover_services_page() {
+        let services: Vec<ServiceInfo> = ["c", "a", "b"]
+            .into_iter()
+            .map(|name| ServiceInfo::new(name, "_mock._tcp", 9000, None).unwrap().with_protocol_type(ProtocolType::Upnp))
+            .collect();
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_mock._tcp").unwrap());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(ListProtocol(services))
+            .build()
+            .await
+            .unwrap();
+
+        let options = QueryOptions::new().with_limit(2).with_sort(crate::query::SortOrder::Name);
+        let page = discovery.discover_services_page(None, &options).await.unwrap();
+        let names: Vec<&str> = page.items().iter().map(ServiceInfo::name).collect();
+        assert_eq!((names.as_slice(), page.total()), (["a", "b"].as_slice(), 3));
+
+        let options = options.with_continuation(page.next_token().unwrap());
+        let page = discovery.discover_services_page(None, &options).await.unwrap();
+        assert_eq!(page.items()[0].name, "c");
+        assert!(page.is_last());
+
+        // The registry pages through what was discovered
+        let page = discovery.registry.find_services_page(&ServiceFilter::new(), &QueryOptions::new().with_offset(1)).await;
+        let names: Vec<&str> = page.items().iter().map(ServiceInfo::name).collect();
+        assert_eq!(names, ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_event_subscription() {
+        let config = DiscoveryConfig::new();
+        let discovery = ServiceDiscovery::new(config).await.unwrap();
+
+        // Event subscription is not explicitly tested here as it depends on the underlying protocol implementation
+        // Just ensure it can be called without error
+        let _ = discovery.discover_services(None).await;
+    }
+
+    #[tokio::test]
+    async fn test_config_validation() {
+        let invalid_config = DiscoveryConfig::new().with_timeout(Duration::ZERO);
+        let discovery = ServiceDiscovery::new(invalid_config).await;
+        assert!(discovery.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_backs_off_and_restarts_on_network_change() {
+        /// Counts the queries it answers
+        struct CountingProtocol(Arc<std::sync::atomic::AtomicUsize>);
+
+        #[async_trait::async_trait]
+        impl DiscoveryProtocol for CountingProtocol {
+            fn protocol_type(&self) -> ProtocolType {
+                ProtocolType::Upnp
+            }
+
+            async fn discover_services(&self, _: Vec<ServiceType>, _: Option<Duration>) -> Result<Vec<ServiceInfo>> {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(vec![ServiceInfo::new("Watched", "_mock._tcp", 9000, None)?.with_protocol_type(ProtocolType::Upnp)])
+            }
+
+            async fn register_service(&self, _: ServiceInfo) -> Result<()> {
+                Ok(())
+            }
+
+            async fn unregister_service(&self, _: &ServiceInfo) -> Result<()> {
+                Ok(())
+            }
+
+            async fn verify_service(&self, _: &ServiceInfo) -> Result<bool> {
+                Ok(true)
+            }
+
+            async fn is_available(&self) -> bool {
+                true
+            }
+
+            fn set_registry(&mut self, _: Arc<ServiceRegistry>) {}
+        }
+
+        let queries = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let count = || queries.load(std::sync::atomic::Ordering::SeqCst);
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_mock._tcp").unwrap());
+        let discovery = ServiceDiscovery::builder(config)
+            .with_protocol(CountingProtocol(Arc::clone(&queries)))
+            .build()
+            .await
+            .unwrap();
+        assert!(discovery.watch_services(WatchConfig::new().with_max_interval(Duration::ZERO)).is_err());
+        discovery
+            .watch_services(WatchConfig::new().with_max_interval(Duration::from_secs(4)))
+            .unwrap();
+
+        // Queries at 0s, 1s, 3s, 7s and 11s
+        let mut counts = Vec::new();
+        for wait in [500, 1000, 2000, 4000, 4000] {
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+            counts.push(count());
+        }
+        assert_eq!(counts, [1, 2, 3, 4, 5]);
+        assert_eq!(discovery.registry.find_services(&ServiceFilter::new().with_name("Watched")).await.len(), 1);
+
+        // A network change starts the schedule over
+        discovery.registry.publish(ServiceEvent::network_changed(Vec::new(), Vec::new())).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(count(), 6);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(count(), 7);
+
+        discovery.close().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(count(), 7);
     }
 }
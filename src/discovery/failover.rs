@@ -0,0 +1,97 @@
+//! Order in which instances are tried by `connect_best`
+//!
+//! Instances are ordered as RFC 2782 orders SRV targets: lowest priority
+//! first and, among equal priorities, a random order in which heavier
+//! instances tend to come first. Health refines that: degraded instances
+//! follow the healthy ones of their priority, and instances known to be
+//! unhealthy are only tried once every other instance has failed.
+
+use crate::{health::HealthStatus, rng::SharedRng, service::ServiceInfo};
+
+/// `candidates` in the order they should be tried
+pub(super) fn failover_order(mut candidates: Vec<(ServiceInfo, HealthStatus)>, rng: &SharedRng) -> Vec<ServiceInfo> {
+    let rank = |(service, health): &(ServiceInfo, HealthStatus)| {
+        (
+            *health == HealthStatus::Unhealthy,
+            service.priority,
+            *health == HealthStatus::Degraded,
+        )
+    };
+    candidates.sort_by_key(rank);
+
+    let mut ordered = Vec::with_capacity(candidates.len());
+    let mut candidates = candidates.into_iter().peekable();
+    while let Some(first) = candidates.next() {
+        let key = rank(&first);
+        let mut group = vec![first.0];
+        while let Some(next) = candidates.next_if(|candidate| rank(candidate) == key) {
+            group.push(next.0);
+        }
+        ordered.extend(weighted_order(group, rng));
+    }
+    ordered
+}
+
+/// Shuffle `group` so that each instance comes next with a chance
+/// proportional to its weight, as RFC 2782 selects among equal priorities
+fn weighted_order(mut group: Vec<ServiceInfo>, rng: &SharedRng) -> Vec<ServiceInfo> {
+    // Zero weights first, so they are only picked when the draw is 0
+    group.sort_by_key(|service| service.weight > 0);
+    let mut ordered = Vec::with_capacity(group.len());
+    while !group.is_empty() {
+        let total: u32 = group.iter().map(|service| u32::from(service.weight)).sum();
+        let draw = rng.random_range(0..=total);
+        let mut running = 0;
+        let index = group
+            .iter()
+            .position(|service| {
+                running += u32::from(service.weight);
+                running >= draw
+            })
+            .unwrap_or(0);
+        ordered.push(group.remove(index));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, priority: u16, weight: u16) -> ServiceInfo {
+        ServiceInfo::new(name, "_db._tcp", 5432, None).unwrap().with_srv(priority, weight)
+    }
+
+    fn names(services: &[ServiceInfo]) -> Vec<&str> {
+        services.iter().map(|service| service.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_priority_then_health() {
+        let candidates = vec![
+            (service("backup", 20, 10), HealthStatus::Healthy),
+            (service("sick", 10, 10), HealthStatus::Unhealthy),
+            (service("shaky", 10, 10), HealthStatus::Degraded),
+            (service("primary", 10, 10), HealthStatus::Healthy),
+        ];
+        let ordered = failover_order(candidates, &SharedRng::seeded(7));
+        assert_eq!(names(&ordered), ["primary", "shaky", "backup", "sick"]);
+    }
+
+    #[test]
+    fn test_heavier_instances_tend_to_come_first() {
+        let rng = SharedRng::seeded(42);
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let candidates = vec![
+                (service("light", 10, 1), HealthStatus::Healthy),
+                (service("heavy", 10, 9), HealthStatus::Healthy),
+                (service("idle", 10, 0), HealthStatus::Healthy),
+            ];
+            let ordered = failover_order(candidates, &rng);
+            assert_eq!(ordered.len(), 3);
+            heavy_first += usize::from(ordered[0].name == "heavy");
+        }
+        assert!((800..=980).contains(&heavy_first), "heavy came first {heavy_first} times");
+    }
+}
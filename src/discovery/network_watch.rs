@@ -0,0 +1,265 @@
+//! Following the host between networks
+//!
+//! The watch polls the local interface addresses. When they change, as when a
+//! laptop moves to another network or an interface goes up or down, it
+//! publishes [`ServiceEvent::NetworkChanged`], re-announces the registered
+//! services, moving those whose address went away to a current one, and
+//! re-runs discovery so the registry reflects the new network. When all the
+//! changed addresses belong to one interface, the event's origin names it.
+
+use super::retain_conforming;
+use crate::{
+    cache::DiscoveryCache,
+    config::DiscoveryConfig,
+    protocols::ProtocolManager,
+    registration::Registrations,
+    registry::ServiceRegistry,
+    schema::SchemaValidator,
+    service::{ServiceEvent, ServiceId, ServiceInfo},
+    types::{NetworkInterface, ServiceType},
+    utils::network,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+/// What the watch needs from the discovery instance
+#[derive(Clone)]
+pub(super) struct NetworkWatch {
+    pub(super) config: DiscoveryConfig,
+    pub(super) protocol_manager: ProtocolManager,
+    pub(super) registry: Arc<ServiceRegistry>,
+    pub(super) cache: Arc<DiscoveryCache>,
+    pub(super) schemas: Arc<SchemaValidator>,
+    #[cfg(feature = "secure")]
+    pub(super) policy: Option<Arc<crate::security::policy::PolicyEngine>>,
+    pub(super) discovered_services: Arc<Mutex<HashMap<ServiceId, ServiceInfo>>>,
+    pub(super) registered_services: Arc<Mutex<HashMap<ServiceId, ServiceInfo>>>,
+    pub(super) registrations: Registrations,
+}
+
+impl NetworkWatch {
+    /// Check the addresses every `interval` until the task is aborted
+    pub(super) fn start(self, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut known = local_addresses();
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let current = local_addresses();
+                if current == known {
+                    continue;
+                }
+                let added: Vec<IpAddr> = current.keys().filter(|a| !known.contains_key(a)).copied().collect();
+                let removed: Vec<IpAddr> = known.keys().filter(|a| !current.contains_key(a)).copied().collect();
+                info!("Network changed: {:?} added, {:?} removed", added, removed);
+                let interface = changed_interface(&added, &current, &removed, &known);
+                known = current;
+
+                let mut event = ServiceEvent::network_changed(added, removed);
+                if let Some(interface) = interface {
+                    event = event.with_interface(interface);
+                }
+                self.registry.publish(event).await;
+                self.reannounce(&known.keys().copied().collect()).await;
+                self.rediscover().await;
+            }
+        })
+    }
+
+    /// Announce the registered services again, with a current address
+    async fn reannounce(&self, addresses: &BTreeSet<IpAddr>) {
+        let services: Vec<ServiceInfo> = self.registered_services.lock().await.values().cloned().collect();
+        for service in services {
+            let protocols = self.protocol_manager.registered_protocols(&service);
+            let announced = match readdress(&service, addresses) {
+                Some(moved) => {
+                    // Peers still on the old network should forget the old address
+                    if let Err(e) = self.protocol_manager.unregister_service(&service).await {
+                        debug!("Failed to withdraw {} from {}: {}", service.name(), service.address, e);
+                    }
+                    moved
+                }
+                None => service,
+            };
+            let protocols = if protocols.is_empty() { vec![announced.protocol_type()] } else { protocols };
+            let outcome = self.protocol_manager.register_with(announced.clone(), protocols).await;
+            self.registrations.announced(&announced, &outcome);
+            for (protocol, e) in outcome.failures() {
+                warn!("Failed to re-announce {} with {:?}: {}", announced.name(), protocol, e);
+            }
+            if !outcome.successes().is_empty() {
+                debug!("Re-announced {} at {}", announced.name(), announced.address);
+                self.registered_services
+                    .lock()
+                    .await
+                    .insert(announced.service_id(), announced);
+            }
+        }
+    }
+
+    /// Drop cached results from the old network and discover on the new one
+    async fn rediscover(&self) {
+        self.cache.clear();
+        let service_types = self.config.service_types().to_vec();
+        if service_types.is_empty() {
+            return;
+        }
+        self.discover(service_types).await;
+    }
+
+    /// Browse for `service_types` with every protocol that can, recording
+    /// what passes the configured filter, schemas and policy
+    pub(super) async fn discover(&self, service_types: Vec<ServiceType>) {
+        let timeout = Some(self.config.protocol_timeout());
+        let mut services = Vec::new();
+        for protocol in self.protocol_manager.protocols_supporting(|c| c.supports_browsing) {
+            match self
+                .protocol_manager
+                .discover_services_with_protocol(protocol, service_types.clone(), timeout)
+                .await
+            {
+                Ok(found) => {
+                    self.cache.store(protocol, &service_types, &found);
+                    services.extend(found);
+                }
+                Err(e) => debug!("Discovery with {:?} failed: {}", protocol, e),
+            }
+        }
+
+        if let Some(filter) = self.config.filter() {
+            services.retain(|service| filter.matches(service));
+        }
+        services = retain_conforming(&self.schemas, &self.registry, services).await;
+        #[cfg(feature = "secure")]
+        if let Some(policy) = &self.policy {
+            services = match policy.filter(services).await {
+                Ok(services) => services,
+                Err(e) => {
+                    warn!("Access policy check of rediscovered services failed: {}", e);
+                    return;
+                }
+            };
+        }
+
+        let mut discovered = self.discovered_services.lock().await;
+        for service in services {
+            if let Err(e) = self.registry.add_discovered_service(service.clone(), service.protocol_type, None).await {
+                debug!("Not tracking {} in the registry: {}", service.name(), e);
+            }
+            discovered.insert(service.service_id(), service);
+        }
+    }
+}
+
+/// Addresses of the interfaces that are up, or none if they can't be listed
+fn local_addresses() -> BTreeMap<IpAddr, String> {
+    match network::get_network_interfaces() {
+        Ok(interfaces) => addresses_of(&interfaces),
+        Err(e) => {
+            debug!("Cannot list network interfaces: {}", e);
+            BTreeMap::new()
+        }
+    }
+}
+
+/// Addresses of `interfaces` that are up, with the name of their interface
+fn addresses_of(interfaces: &[NetworkInterface]) -> BTreeMap<IpAddr, String> {
+    interfaces
+        .iter()
+        .filter(|interface| interface.is_up)
+        .flat_map(|interface| interface.networks.iter().map(|network| (network.addr(), interface.name.clone())))
+        .collect()
+}
+
+/// The one interface all of `added` and `removed` belong to, if there is one
+fn changed_interface(
+    added: &[IpAddr],
+    current: &BTreeMap<IpAddr, String>,
+    removed: &[IpAddr],
+    known: &BTreeMap<IpAddr, String>,
+) -> Option<String> {
+    let mut names = added
+        .iter()
+        .filter_map(|address| current.get(address))
+        .chain(removed.iter().filter_map(|address| known.get(address)));
+    let first = names.next()?;
+    names.all(|name| name == first).then(|| first.clone())
+}
+
+/// The service moved to a current address, if its own went away
+///
+/// Services without a specific address, or on loopback, stay as they are.
+/// The replacement is a non-loopback address of the same family; when there
+/// is none the service is left alone until the network comes back.
+fn readdress(service: &ServiceInfo, addresses: &BTreeSet<IpAddr>) -> Option<ServiceInfo> {
+    let address = service.address;
+    if address.is_unspecified() || address.is_loopback() || addresses.contains(&address) {
+        return None;
+    }
+    let replacement = addresses
+        .iter()
+        .find(|candidate| candidate.is_ipv4() == address.is_ipv4() && !candidate.is_loopback())?;
+    let mut moved = service.clone();
+    moved.address = *replacement;
+    Some(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipnet::IpNet;
+
+    fn service(address: &str) -> ServiceInfo {
+        ServiceInfo::new("Laptop", "_http._tcp", 8080, None)
+            .unwrap()
+            .with_address(address.parse().unwrap())
+    }
+
+    #[test]
+    fn test_readdress_moves_services_off_vanished_addresses() {
+        let addresses: BTreeSet<IpAddr> = ["127.0.0.1", "10.1.0.5", "fe80::1"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+
+        let moved = readdress(&service("192.168.1.20"), &addresses).unwrap();
+        assert_eq!(moved.address, "10.1.0.5".parse::<IpAddr>().unwrap());
+        assert_eq!(moved.name(), "Laptop");
+
+        assert!(readdress(&service("10.1.0.5"), &addresses).is_none());
+        assert!(readdress(&service("0.0.0.0"), &addresses).is_none());
+        assert!(readdress(&service("127.0.0.1"), &addresses).is_none());
+        // Nothing of the same family to move to
+        assert!(readdress(&service("192.168.1.20"), &BTreeSet::from(["fe80::1".parse().unwrap()])).is_none());
+    }
+
+    #[test]
+    fn test_addresses_of_skips_interfaces_that_are_down() {
+        let network = |network: &str| network.parse::<IpNet>().unwrap();
+        let interfaces = [
+            NetworkInterface::new("wlan0").with_status(true, true).with_network(network("10.1.0.5/24")),
+            NetworkInterface::new("eth0").with_status(false, true).with_network(network("192.168.1.20/24")),
+        ];
+        assert_eq!(addresses_of(&interfaces), BTreeMap::from([("10.1.0.5".parse().unwrap(), "wlan0".to_string())]));
+    }
+
+    #[test]
+    fn test_changed_interface_names_a_single_interface() {
+        let address = |address: &str| address.parse::<IpAddr>().unwrap();
+        let known = BTreeMap::from([(address("10.1.0.5"), "wlan0".to_string()), (address("10.2.0.7"), "eth0".to_string())]);
+        let current = BTreeMap::from([(address("10.1.0.9"), "wlan0".to_string()), (address("10.2.0.7"), "eth0".to_string())]);
+        let (added, removed) = ([address("10.1.0.9")], [address("10.1.0.5")]);
+        assert_eq!(changed_interface(&added, &current, &removed, &known).as_deref(), Some("wlan0"));
+
+        let removed = [address("10.1.0.5"), address("10.2.0.7")];
+        assert_eq!(changed_interface(&added, &current, &removed, &known), None);
+        assert_eq!(changed_interface(&[], &current, &[], &known), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Continuous watching of service types
+//!
+//! The watch queries each service type on the schedule of a [`WatchConfig`]:
+//! often at first, when answers are most likely to be missing, then less and
+//! less often. When the network watch publishes
+//! [`ServiceEvent::NetworkChanged`], every schedule starts over, since what
+//! was known about the old network says little about the new one.
+
+use super::network_watch::NetworkWatch;
+use crate::{config::WatchConfig, service::ServiceEvent, types::ServiceType};
+use std::{collections::HashMap, time::Duration};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tracing::debug;
+
+/// When each service type is next queried
+#[derive(Debug)]
+struct BrowseSchedule {
+    config: WatchConfig,
+    /// Next query and the interval after it, by service type
+    next: HashMap<ServiceType, (Instant, Duration)>,
+}
+
+impl BrowseSchedule {
+    /// Query every type in `service_types` at `now`
+    fn new(config: WatchConfig, service_types: &[ServiceType], now: Instant) -> Self {
+        let mut schedule = Self { config, next: HashMap::new() };
+        for service_type in service_types {
+            schedule.next.insert(service_type.clone(), (now, schedule.config.initial_interval()));
+        }
+        schedule
+    }
+
+    /// Service types whose query is due at `now`
+    fn due(&self, now: Instant) -> Vec<ServiceType> {
+        let mut due: Vec<ServiceType> = self
+            .next
+            .iter()
+            .filter(|(_, (at, _))| *at <= now)
+            .map(|(service_type, _)| service_type.clone())
+            .collect();
+        due.sort_by_cached_key(ToString::to_string);
+        due
+    }
+
+    /// Record that `service_type` was queried at `now`
+    fn queried(&mut self, service_type: &ServiceType, now: Instant) {
+        let (max, multiplier) = (self.config.max_interval(), self.config.multiplier());
+        if let Some((at, interval)) = self.next.get_mut(service_type) {
+            *at = now + *interval;
+            *interval = interval.saturating_mul(multiplier).min(max);
+        }
+    }
+
+    /// Query every type again at `now` and restart the backoff
+    fn reset(&mut self, now: Instant) {
+        let initial = self.config.initial_interval();
+        for next in self.next.values_mut() {
+            *next = (now, initial);
+        }
+    }
+
+    /// Time of the next query of any type
+    fn next_due(&self) -> Option<Instant> {
+        self.next.values().map(|(at, _)| *at).min()
+    }
+}
+
+/// Query the service types on `config`'s schedule until the task is aborted
+pub(super) fn start(parts: NetworkWatch, config: WatchConfig) -> JoinHandle<()> {
+    let service_types = if config.service_types().is_empty() {
+        parts.config.service_types().to_vec()
+    } else {
+        config.service_types().to_vec()
+    };
+    tokio::spawn(async move {
+        let mut events = parts.registry.subscribe();
+        let mut schedule = BrowseSchedule::new(config, &service_types, Instant::now());
+        let Some(mut next) = schedule.next_due() else {
+            debug!("No service types to watch");
+            return;
+        };
+        loop {
+            tokio::select! {
+                _ = time::sleep_until(next) => {
+                    for service_type in schedule.due(Instant::now()) {
+                        parts.discover(vec![service_type.clone()]).await;
+                        schedule.queried(&service_type, Instant::now());
+                    }
+                }
+                event = events.recv() => match event {
+                    Ok(ServiceEvent::NetworkChanged { .. }) => {
+                        debug!("Network changed; watching service types from the start");
+                        schedule.reset(Instant::now());
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                },
+            }
+            next = schedule.next_due().unwrap_or(next);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule_backs_off_and_resets() {
+        let config = WatchConfig::new().with_max_interval(Duration::from_secs(5));
+        let (http, ipp) = (ServiceType::new("_http._tcp").unwrap(), ServiceType::new("_ipp._tcp").unwrap());
+        let start = Instant::now();
+        let mut schedule = BrowseSchedule::new(config, &[http.clone(), ipp.clone()], start);
+        assert_eq!(schedule.due(start), vec![http.clone(), ipp.clone()]);
+
+        // 1s, 2s, 4s, then capped at 5s
+        let mut now = start;
+        let mut intervals = Vec::new();
+        for _ in 0..5 {
+            schedule.queried(&http, now);
+            let next = schedule.next[&http].0;
+            intervals.push((next - now).as_secs());
+            now = next;
+        }
+        assert_eq!(intervals, [1, 2, 4, 5, 5]);
+        assert_eq!(schedule.next_due(), Some(start));
+        assert_eq!(schedule.due(start), vec![ipp.clone()]);
+
+        schedule.reset(now);
+        assert_eq!(schedule.due(now), vec![http.clone(), ipp]);
+        schedule.queried(&http, now);
+        assert_eq!(schedule.next[&http].0 - now, Duration::from_secs(1));
+    }
+}
@@ -5,9 +5,14 @@ use std::{
     fmt,
     io,
     num::ParseIntError,
-    time::SystemTimeError,
+    time::{Duration, SystemTimeError},
 };
 use base64::DecodeError;
+use serde::Serialize;
+use crate::{
+    safety::Operation,
+    types::{ProtocolType, ServiceType},
+};
 #[cfg(feature = "secure")]
 use ring::error::{KeyRejected, Unspecified};
 
@@ -25,6 +30,13 @@ pub enum DiscoveryError {
         /// The reason why the field is invalid
         reason: String 
     },
+    /// A service attribute is missing or has the wrong form
+    Attribute {
+        /// The attribute key
+        key: String,
+        /// What is wrong with it
+        reason: String,
+    },
     /// Service not found error
     ServiceNotFound(String),
     /// DNS resolution error
@@ -47,8 +59,158 @@ pub enum DiscoveryError {
     Io(io::Error),
     /// Security error
     Security(String),
+    /// Service name conflict error
+    Conflict(String),
+    /// Operation rejected by a rate limiter or open circuit breaker
+    RateLimited(String),
     /// Other error types
     Other(String),
+    /// An error annotated with where it happened
+    ///
+    /// Created by [`DiscoveryError::with_context`]; [`kind`](Self::kind),
+    /// [`retry_after`](Self::retry_after) and the other accessors look through
+    /// it to the underlying error.
+    Contextual {
+        /// The underlying error
+        error: Box<DiscoveryError>,
+        /// Where it happened
+        context: ErrorContext,
+    },
+}
+
+/// Where an error happened
+///
+/// Fields that don't apply, or aren't known where the error is raised, are
+/// left empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// Protocol the operation ran with
+    pub protocol: Option<ProtocolType>,
+    /// Operation that failed
+    pub operation: Option<Operation>,
+    /// Service type the operation concerned
+    pub service_type: Option<ServiceType>,
+    /// How long to wait before retrying, when the cause knows
+    pub retry_after: Option<Duration>,
+}
+
+impl ErrorContext {
+    /// Create an empty context
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the protocol
+    pub fn protocol(mut self, protocol: ProtocolType) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Set the operation
+    pub fn operation(mut self, operation: Operation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    /// Set the service type
+    pub fn service_type(mut self, service_type: ServiceType) -> Self {
+        self.service_type = Some(service_type);
+        self
+    }
+
+    /// Set how long to wait before retrying
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after = Some(delay);
+        self
+    }
+
+    /// Fill the fields this context lacks from `other`
+    fn merge(&mut self, other: ErrorContext) {
+        self.protocol = self.protocol.or(other.protocol);
+        self.operation = self.operation.or(other.operation);
+        self.service_type = self.service_type.take().or(other.service_type);
+        self.retry_after = self.retry_after.or(other.retry_after);
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(operation) = self.operation {
+            parts.push(operation.to_string());
+        }
+        if let Some(protocol) = self.protocol {
+            parts.push(format!("via {protocol}"));
+        }
+        if let Some(service_type) = &self.service_type {
+            parts.push(format!("for {service_type}"));
+        }
+        if let Some(delay) = self.retry_after {
+            parts.push(format!("retry after {delay:?}"));
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// Broad category of an error, for deciding how to react to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The configuration is invalid
+    Configuration,
+    /// An argument or received data is malformed
+    InvalidInput,
+    /// The requested service or resource does not exist
+    NotFound,
+    /// The network or a peer failed, including DNS resolution
+    Network,
+    /// The operation did not finish in time
+    Timeout,
+    /// A discovery protocol failed
+    Protocol,
+    /// A service did not pass verification
+    Verification,
+    /// Rejected by a rate limiter or an open circuit breaker
+    RateLimited,
+    /// A signature, key or policy check failed
+    Security,
+    /// The name is already in use
+    Conflict,
+    /// A local I/O operation failed
+    Io,
+    /// Anything else
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether the condition is likely to pass, so the operation is worth
+    /// retrying later
+    ///
+    /// Multicast hiccups, timeouts and rate limiting are transient; invalid
+    /// configuration, bad input and security failures are not.
+    pub fn is_transient(self) -> bool {
+        matches!(self, Self::Network | Self::Timeout | Self::Protocol | Self::RateLimited)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Configuration => "configuration",
+            Self::InvalidInput => "invalid_input",
+            Self::NotFound => "not_found",
+            Self::Network => "network",
+            Self::Timeout => "timeout",
+            Self::Protocol => "protocol",
+            Self::Verification => "verification",
+            Self::RateLimited => "rate_limited",
+            Self::Security => "security",
+            Self::Conflict => "conflict",
+            Self::Io => "io",
+            Self::Other => "other",
+        };
+        f.write_str(name)
+    }
 }
 
 impl fmt::Display for DiscoveryError {
@@ -59,6 +221,7 @@ impl fmt::Display for DiscoveryError {
             Self::InvalidServiceInfo { field, reason } => {
                 write!(f, "Invalid service info ({field}): {reason}")
             }
+            Self::Attribute { key, reason } => write!(f, "Invalid attribute '{key}': {reason}"),
             Self::ServiceNotFound(msg) => write!(f, "Service not found: {msg}"),
             Self::DnsResolution(msg) => write!(f, "DNS resolution error: {msg}"),
             Self::Mdns(msg) => write!(f, "mDNS error: {msg}"),
@@ -70,7 +233,46 @@ impl fmt::Display for DiscoveryError {
             Self::Protocol(msg) => write!(f, "Protocol error: {msg}"),
             Self::Io(err) => write!(f, "I/O error: {err}"),
             Self::Security(msg) => write!(f, "Security error: {msg}"),
+            Self::Conflict(msg) => write!(f, "Name conflict: {msg}"),
+            Self::RateLimited(msg) => write!(f, "Rate limited: {msg}"),
             Self::Other(msg) => write!(f, "Error: {msg}"),
+            Self::Contextual { error, context } => write!(f, "{error} ({context})"),
+        }
+    }
+}
+
+impl Clone for DiscoveryError {
+    /// I/O errors are cloned by kind and message, dropping any inner source
+    fn clone(&self) -> Self {
+        match self {
+            Self::Configuration(msg) => Self::Configuration(msg.clone()),
+            Self::InvalidData(msg) => Self::InvalidData(msg.clone()),
+            Self::InvalidServiceInfo { field, reason } => Self::InvalidServiceInfo {
+                field: field.clone(),
+                reason: reason.clone(),
+            },
+            Self::Attribute { key, reason } => Self::Attribute {
+                key: key.clone(),
+                reason: reason.clone(),
+            },
+            Self::ServiceNotFound(msg) => Self::ServiceNotFound(msg.clone()),
+            Self::DnsResolution(msg) => Self::DnsResolution(msg.clone()),
+            Self::Mdns(msg) => Self::Mdns(msg.clone()),
+            Self::Upnp(msg) => Self::Upnp(msg.clone()),
+            Self::DnsSd(msg) => Self::DnsSd(msg.clone()),
+            Self::Network(msg) => Self::Network(msg.clone()),
+            Self::Timeout(msg) => Self::Timeout(msg.clone()),
+            Self::Verification(msg) => Self::Verification(msg.clone()),
+            Self::Protocol(msg) => Self::Protocol(msg.clone()),
+            Self::Io(err) => Self::Io(io::Error::new(err.kind(), err.to_string())),
+            Self::Security(msg) => Self::Security(msg.clone()),
+            Self::Conflict(msg) => Self::Conflict(msg.clone()),
+            Self::RateLimited(msg) => Self::RateLimited(msg.clone()),
+            Self::Other(msg) => Self::Other(msg.clone()),
+            Self::Contextual { error, context } => Self::Contextual {
+                error: error.clone(),
+                context: context.clone(),
+            },
         }
     }
 }
@@ -79,6 +281,7 @@ impl StdError for DiscoveryError {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
             Self::Io(err) => Some(err),
+            Self::Contextual { error, .. } => error.source(),
             _ => None,
         }
     }
@@ -155,6 +358,14 @@ impl DiscoveryError {
         Self::InvalidData(msg.into())
     }
 
+    /// Create a new attribute error
+    pub fn attribute<K: Into<String>, R: Into<String>>(key: K, reason: R) -> Self {
+        Self::Attribute {
+            key: key.into(),
+            reason: reason.into(),
+        }
+    }
+
     /// Create a new service not found error
     pub fn service_not_found<S: Into<String>>(msg: S) -> Self {
         Self::ServiceNotFound(msg.into())
@@ -205,6 +416,16 @@ impl DiscoveryError {
         Self::Security(msg.into())
     }
 
+    /// Create a new name conflict error
+    pub fn conflict<S: Into<String>>(msg: S) -> Self {
+        Self::Conflict(msg.into())
+    }
+
+    /// Create a new rate limited error
+    pub fn rate_limited<S: Into<String>>(msg: S) -> Self {
+        Self::RateLimited(msg.into())
+    }
+
     /// Create a new other error
     pub fn other<S: Into<String>>(msg: S) -> Self {
         Self::Other(msg.into())
@@ -215,17 +436,99 @@ impl DiscoveryError {
         Self::InvalidData(msg.into())
     }
 
+    /// Annotate the error with where it happened
+    ///
+    /// Context added to an error that already has some only fills in the
+    /// fields it lacks, so the innermost, most specific context wins.
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        match self {
+            Self::Contextual { error, context: mut existing } => {
+                existing.merge(context);
+                Self::Contextual { error, context: existing }
+            }
+            error => Self::Contextual {
+                error: Box::new(error),
+                context,
+            },
+        }
+    }
+
+    /// Where the error happened, if known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Contextual { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context
+    pub fn root(&self) -> &DiscoveryError {
+        match self {
+            Self::Contextual { error, .. } => error.root(),
+            error => error,
+        }
+    }
+
+    /// Broad category of the error
+    pub fn kind(&self) -> ErrorKind {
+        match self.root() {
+            Self::Configuration(_) => ErrorKind::Configuration,
+            Self::InvalidData(_) | Self::InvalidServiceInfo { .. } | Self::Attribute { .. } => ErrorKind::InvalidInput,
+            Self::ServiceNotFound(_) => ErrorKind::NotFound,
+            Self::DnsResolution(_) | Self::Network(_) => ErrorKind::Network,
+            Self::Mdns(_) | Self::Upnp(_) | Self::DnsSd(_) | Self::Protocol(_) => ErrorKind::Protocol,
+            Self::Timeout(_) => ErrorKind::Timeout,
+            Self::Verification(_) => ErrorKind::Verification,
+            Self::Io(err) => match err.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::AddrNotAvailable
+                | io::ErrorKind::NetworkUnreachable
+                | io::ErrorKind::HostUnreachable
+                | io::ErrorKind::NetworkDown => ErrorKind::Network,
+                _ => ErrorKind::Io,
+            },
+            Self::Security(_) => ErrorKind::Security,
+            Self::Conflict(_) => ErrorKind::Conflict,
+            Self::RateLimited(_) => ErrorKind::RateLimited,
+            Self::Other(_) | Self::Contextual { .. } => ErrorKind::Other,
+        }
+    }
+
+    /// How long to wait before retrying, or `None` if retrying won't help
+    ///
+    /// Uses the delay recorded where the error was raised when there is one,
+    /// such as the time until a circuit breaker half-opens. Otherwise
+    /// timeouts may be retried at once and other transient errors after a
+    /// second.
+    pub fn retry_after(&self) -> Option<Duration> {
+        if let Some(delay) = self.context().and_then(|context| context.retry_after) {
+            return Some(delay);
+        }
+        match self.kind() {
+            ErrorKind::Timeout => Some(Duration::ZERO),
+            kind if kind.is_transient() => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+
     /// Check if error is retryable
+    ///
+    /// Rate limiting is transient too, but not worth retrying straight away;
+    /// see [`retry_after`](Self::retry_after).
     pub fn is_retryable(&self) -> bool {
         matches!(
-            self,
-            Self::Network(_) | Self::Timeout(_) | Self::Protocol(_)
+            self.kind(),
+            ErrorKind::Network | ErrorKind::Timeout | ErrorKind::Protocol
         )
     }
 
     /// Get error severity
     pub fn severity(&self) -> ErrorSeverity {
-        match self {
+        match self.root() {
             Self::Configuration(_) | Self::InvalidData(_) => ErrorSeverity::Fatal,
             Self::Security(_) | Self::Verification(_) => ErrorSeverity::Error,
             Self::Network(_) | Self::DnsResolution(_) | Self::Protocol(_) => ErrorSeverity::Warning,
@@ -257,4 +560,37 @@ mod tests {
         assert!(DiscoveryError::Timeout("5".to_string()).is_retryable());
         assert!(!DiscoveryError::invalid_service("test".to_string()).is_retryable());
     }
+
+    #[test]
+    fn test_error_kind_and_retry_after() {
+        let hiccup = DiscoveryError::mdns("send failed");
+        assert_eq!(hiccup.kind(), ErrorKind::Protocol);
+        assert!(hiccup.kind().is_transient());
+        assert_eq!(hiccup.retry_after(), Some(Duration::from_secs(1)));
+
+        let misconfigured = DiscoveryError::configuration("no DNS server");
+        assert!(!misconfigured.kind().is_transient());
+        assert_eq!(misconfigured.retry_after(), None);
+
+        let refused = DiscoveryError::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(refused.kind(), ErrorKind::Network);
+    }
+
+    #[test]
+    fn test_error_context() {
+        let service_type = ServiceType::new("_http._tcp").unwrap();
+        let error = DiscoveryError::rate_limited("discovery circuit breaker is open")
+            .with_context(ErrorContext::new().operation(Operation::Discovery).retry_after(Duration::from_secs(7)))
+            .with_context(ErrorContext::new().protocol(ProtocolType::Mdns).service_type(service_type.clone()));
+
+        // The accessors see through the context, which is merged rather than nested
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
+        assert!(matches!(error.root(), DiscoveryError::RateLimited(_)));
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(7)));
+        let context = error.context().unwrap();
+        assert_eq!(context.protocol, Some(ProtocolType::Mdns));
+        assert_eq!(context.operation, Some(Operation::Discovery));
+        assert_eq!(context.service_type, Some(service_type));
+        assert!(error.to_string().contains("discovery, via mDNS, for _http._tcp"), "{error}");
+    }
 }
@@ -0,0 +1,459 @@
+//! Delivery of service events to subscribers
+//!
+//! [`ServiceRegistry::subscribe`](crate::registry::ServiceRegistry::subscribe)
+//! hands out broadcast receivers that skip events when they fall more than
+//! the channel capacity behind. Subscribers that need to decide what happens
+//! when they cannot keep up use
+//! [`ServiceRegistry::subscribe_bounded`](crate::registry::ServiceRegistry::subscribe_bounded)
+//! instead: each gets its own queue of [`EventChannelConfig::capacity`]
+//! events, and its [`OverflowPolicy`] says whether a full queue drops its
+//! oldest event, drops the new one, or makes the publisher wait.
+//!
+//! Dropped events are counted per receiver and for the registry, and as the
+//! `discovery_events_dropped_total` metric.
+//!
+//! Every event also gets the next sequence number and is kept in a bounded
+//! change log. [`ServiceRegistry::changes_since`](crate::registry::ServiceRegistry::changes_since)
+//! returns the events after a sequence number, so a dashboard or config syncer
+//! can replicate the registry incrementally and pick up where it left off
+//! after a disconnect. When it was away so long that the log no longer
+//! reaches back far enough, [`Changes::truncated`] is set and it starts over
+//! from a [`RegistrySnapshot`](crate::registry::RegistrySnapshot), which
+//! records the sequence number to continue from.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    service::ServiceEvent,
+};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::{broadcast, Notify};
+use tracing::debug;
+
+/// What happens to an event published while a subscriber's queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued event to make room
+    #[default]
+    DropOldest,
+    /// Drop the new event
+    DropNewest,
+    /// Make the publisher wait until the subscriber takes an event
+    ///
+    /// A subscriber that stops reading holds up discovery for everyone, so
+    /// this suits consumers that must see every event and keep up on average.
+    Block,
+}
+
+#[cfg(feature = "metrics")]
+impl OverflowPolicy {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::DropOldest => "drop_oldest",
+            Self::DropNewest => "drop_newest",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// Queue size and overflow policy of a bounded subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventChannelConfig {
+    /// Events queued for the subscriber before the overflow policy applies
+    pub capacity: usize,
+    /// What happens to events published while the queue is full
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for EventChannelConfig {
+    fn default() -> Self {
+        Self { capacity: 256, overflow: OverflowPolicy::default() }
+    }
+}
+
+impl EventChannelConfig {
+    /// Queue up to `capacity` events, then apply `overflow`
+    pub fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+        Self { capacity, overflow }
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        if self.capacity == 0 {
+            return Err(DiscoveryError::configuration("Event channel capacity must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// Queue of one bounded subscriber
+#[derive(Debug)]
+struct Queue {
+    config: EventChannelConfig,
+    events: Mutex<VecDeque<ServiceEvent>>,
+    dropped: AtomicU64,
+    /// Signalled when an event is queued or the bus goes away
+    readable: Notify,
+    /// Signalled when an event is taken or the receiver goes away
+    writable: Notify,
+    /// Set when either end goes away
+    closed: AtomicBool,
+}
+
+impl Queue {
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.readable.notify_one();
+        self.writable.notify_waiters();
+    }
+}
+
+/// A registry event with its position in the change log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequencedEvent {
+    /// Position of the event; the first event is 1
+    pub sequence: u64,
+    /// The event
+    pub event: ServiceEvent,
+}
+
+/// Events after a sequence number, oldest first
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+    /// The events still in the log after the requested sequence number
+    pub events: Vec<SequencedEvent>,
+    /// Sequence number of the latest event, to ask for changes since next
+    pub latest: u64,
+    /// Whether events after the requested sequence number have already left
+    /// the log, or the sequence number is from before a restart, so
+    /// replicating from `events` alone would miss changes
+    pub truncated: bool,
+}
+
+/// The latest events, numbered in publishing order
+#[derive(Debug)]
+struct ChangeLog {
+    events: VecDeque<SequencedEvent>,
+    capacity: usize,
+    latest: u64,
+}
+
+/// Fans events out to broadcast receivers and bounded subscribers, and
+/// keeps them in the change log
+#[derive(Debug)]
+pub(crate) struct EventBus {
+    broadcast: broadcast::Sender<ServiceEvent>,
+    queues: Mutex<Vec<Arc<Queue>>>,
+    dropped: AtomicU64,
+    log: Mutex<ChangeLog>,
+}
+
+impl EventBus {
+    /// Create a bus whose broadcast receivers lag after `capacity` events
+    /// and whose change log keeps the latest `log_capacity` events
+    pub(crate) fn new(capacity: usize, log_capacity: usize) -> Self {
+        Self {
+            broadcast: broadcast::channel(capacity).0,
+            queues: Mutex::new(Vec::new()),
+            dropped: AtomicU64::new(0),
+            log: Mutex::new(ChangeLog {
+                events: VecDeque::new(),
+                capacity: log_capacity,
+                latest: 0,
+            }),
+        }
+    }
+
+    /// Sequence number of the latest event, 0 before the first
+    pub(crate) fn latest_sequence(&self) -> u64 {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).latest
+    }
+
+    /// The logged events with sequence numbers above `sequence`
+    pub(crate) fn changes_since(&self, sequence: u64) -> Changes {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        let oldest = log.events.front().map_or(log.latest + 1, |event| event.sequence);
+        Changes {
+            events: log
+                .events
+                .iter()
+                .skip_while(|event| event.sequence <= sequence)
+                .cloned()
+                .collect(),
+            latest: log.latest,
+            // Ahead of the log means the registry was restarted since
+            truncated: sequence + 1 < oldest || sequence > log.latest,
+        }
+    }
+
+    /// A broadcast receiver of every event published from now on
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ServiceEvent> {
+        self.broadcast.subscribe()
+    }
+
+    /// A receiver with its own queue, handling overflow as `config` says
+    pub(crate) fn subscribe_bounded(&self, config: EventChannelConfig) -> EventReceiver {
+        let queue = Arc::new(Queue {
+            config: EventChannelConfig { capacity: config.capacity.max(1), ..config },
+            events: Mutex::new(VecDeque::new()),
+            dropped: AtomicU64::new(0),
+            readable: Notify::new(),
+            writable: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+        self.queues.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::clone(&queue));
+        EventReceiver { queue }
+    }
+
+    /// Events dropped across all bounded subscribers
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Deliver `event` to every subscriber
+    ///
+    /// Waits while a subscriber with [`OverflowPolicy::Block`] has a full
+    /// queue, so must not be called with locks held that subscribers take.
+    pub(crate) async fn publish(&self, event: ServiceEvent) {
+        {
+            // Numbered and broadcast under the log lock, so broadcast
+            // receivers see the events in sequence order
+            let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+            log.latest += 1;
+            let sequence = log.latest;
+            if log.capacity > 0 {
+                if log.events.len() >= log.capacity {
+                    log.events.pop_front();
+                }
+                log.events.push_back(SequencedEvent { sequence, event: event.clone() });
+            }
+            // Nobody listening is not an error
+            let _ = self.broadcast.send(event.clone());
+        }
+
+        let queues: Vec<Arc<Queue>> = {
+            let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+            queues.retain(|queue| !queue.closed.load(Ordering::SeqCst));
+            queues.clone()
+        };
+        for queue in queues {
+            self.deliver(&queue, event.clone()).await;
+        }
+    }
+
+    async fn deliver(&self, queue: &Queue, event: ServiceEvent) {
+        loop {
+            let writable = queue.writable.notified();
+            tokio::pin!(writable);
+            // Registered before checking, so a wakeup in between is not lost
+            writable.as_mut().enable();
+            {
+                let mut events = queue.events.lock().unwrap_or_else(|e| e.into_inner());
+                if queue.closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                if events.len() < queue.config.capacity {
+                    events.push_back(event);
+                    queue.readable.notify_one();
+                    return;
+                }
+                match queue.config.overflow {
+                    OverflowPolicy::DropOldest => {
+                        events.pop_front();
+                        events.push_back(event);
+                        queue.readable.notify_one();
+                        self.count_drop(queue);
+                        return;
+                    }
+                    OverflowPolicy::DropNewest => {
+                        self.count_drop(queue);
+                        return;
+                    }
+                    OverflowPolicy::Block => {}
+                }
+            }
+            debug!("Waiting for a slow event subscriber");
+            writable.await;
+        }
+    }
+
+    fn count_drop(&self, queue: &Queue) {
+        queue.dropped.fetch_add(1, Ordering::Relaxed);
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("discovery_events_dropped_total", "policy" => queue.config.overflow.as_str()).increment(1);
+    }
+}
+
+impl Drop for EventBus {
+    fn drop(&mut self) {
+        for queue in self.queues.lock().unwrap_or_else(|e| e.into_inner()).drain(..) {
+            queue.close();
+        }
+    }
+}
+
+/// Receiving end of a bounded event subscription
+#[derive(Debug)]
+pub struct EventReceiver {
+    queue: Arc<Queue>,
+}
+
+impl EventReceiver {
+    /// Wait for the next event
+    ///
+    /// Returns `None` once the registry is gone and every queued event has
+    /// been taken.
+    pub async fn recv(&mut self) -> Option<ServiceEvent> {
+        let queue = Arc::clone(&self.queue);
+        loop {
+            let readable = queue.readable.notified();
+            if let Some(event) = self.try_recv() {
+                return Some(event);
+            }
+            if queue.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            readable.await;
+        }
+    }
+
+    /// Take the next event if one is queued
+    pub fn try_recv(&mut self) -> Option<ServiceEvent> {
+        let event = self.queue.events.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+        if event.is_some() {
+            self.queue.writable.notify_one();
+        }
+        event
+    }
+
+    /// Events dropped from this subscription because its queue was full
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of queued events
+    pub fn len(&self) -> usize {
+        self.queue.events.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Whether no event is queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The queue size and overflow policy of the subscription
+    pub fn config(&self) -> EventChannelConfig {
+        self.queue.config
+    }
+
+    /// Turn the receiver into a stream of events
+    pub fn into_stream(self) -> impl Stream<Item = ServiceEvent> + Send + 'static {
+        futures::stream::unfold(self, |mut receiver| async move {
+            let event = receiver.recv().await?;
+            Some((event, receiver))
+        })
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::ServiceInfo;
+    use std::time::Duration;
+
+    fn event(name: &str) -> ServiceEvent {
+        ServiceEvent::new(ServiceInfo::new(name, "_http._tcp", 8080, None).unwrap())
+    }
+
+    fn names(receiver: &mut EventReceiver) -> Vec<String> {
+        std::iter::from_fn(|| receiver.try_recv())
+            .map(|event| match event {
+                ServiceEvent::New { service, .. } => service.name,
+                other => panic!("unexpected event {other:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_change_log() {
+        let bus = EventBus::new(16, 3);
+        assert_eq!(bus.changes_since(0), Changes { events: Vec::new(), latest: 0, truncated: false });
+        for name in ["a", "b", "c", "d"] {
+            bus.publish(event(name)).await;
+        }
+
+        let changes = bus.changes_since(2);
+        assert_eq!(changes.events.iter().map(|e| e.sequence).collect::<Vec<_>>(), [3, 4]);
+        assert!(matches!(&changes.events[0].event, ServiceEvent::New { service, .. } if service.name == "c"));
+        assert_eq!((changes.latest, changes.truncated), (4, false));
+        // Event 1 is gone, but a reader at 1 only needs 2 onwards
+        assert!(!bus.changes_since(1).truncated);
+        assert!(bus.changes_since(0).truncated);
+        assert!(bus.changes_since(4).events.is_empty());
+        assert!(bus.changes_since(9).truncated);
+    }
+
+    #[tokio::test]
+    async fn test_drop_policies() {
+        let bus = EventBus::new(16, 0);
+        let mut oldest = bus.subscribe_bounded(EventChannelConfig::new(2, OverflowPolicy::DropOldest));
+        let mut newest = bus.subscribe_bounded(EventChannelConfig::new(2, OverflowPolicy::DropNewest));
+        for name in ["a", "b", "c", "d"] {
+            bus.publish(event(name)).await;
+        }
+
+        assert_eq!(names(&mut oldest), ["c", "d"]);
+        assert_eq!(names(&mut newest), ["a", "b"]);
+        assert_eq!((oldest.dropped(), newest.dropped(), bus.dropped()), (2, 2, 4));
+    }
+
+    #[tokio::test]
+    async fn test_block_policy_waits_for_subscriber() {
+        let bus = Arc::new(EventBus::new(16, 0));
+        let mut receiver = bus.subscribe_bounded(EventChannelConfig::new(1, OverflowPolicy::Block));
+        bus.publish(event("a")).await;
+
+        let publisher = tokio::spawn({
+            let bus = Arc::clone(&bus);
+            async move { bus.publish(event("b")).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!publisher.is_finished());
+
+        assert!(matches!(receiver.recv().await, Some(ServiceEvent::New { service, .. }) if service.name == "a"));
+        publisher.await.unwrap();
+        assert!(matches!(receiver.recv().await, Some(ServiceEvent::New { service, .. }) if service.name == "b"));
+        assert_eq!(receiver.dropped(), 0);
+
+        // A subscriber that goes away no longer holds up publishers
+        bus.publish(event("c")).await;
+        drop(receiver);
+        tokio::time::timeout(Duration::from_secs(1), bus.publish(event("d"))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_receiver_ends_with_bus() {
+        let bus = EventBus::new(16, 0);
+        let mut receiver = bus.subscribe_bounded(EventChannelConfig::default());
+        bus.publish(event("a")).await;
+        drop(bus);
+        assert!(receiver.recv().await.is_some());
+        assert!(receiver.recv().await.is_none());
+    }
+}
@@ -0,0 +1,563 @@
+//! C ABI for embedding the crate in non-Rust applications
+//!
+//! Enabled by the `ffi` feature. Build a shared or static library with
+//!
+//! ```text
+//! cargo rustc --release --features ffi --crate-type cdylib
+//! cbindgen --config cbindgen.toml --output auto_discovery.h
+//! ```
+//!
+//! A handle created with [`ad_discovery_new`] owns its own async runtime, so
+//! every function blocks until it is done and may be called from any thread.
+//! The handle browses its service types in the background; what it finds is
+//! reported through [`ad_discovery_poll_event`].
+//!
+//! Functions returning pointers return null on failure, functions returning
+//! [`AdStatus`] return a non-zero status; either way [`ad_last_error`]
+//! describes what went wrong. Everything the library allocates is released
+//! with the matching `*_free` function.
+
+use crate::{
+    config::DiscoveryConfig,
+    discovery::ServiceDiscovery,
+    error::{DiscoveryError, Result},
+    service::{ServiceEvent, ServiceInfo},
+    types::ServiceType,
+};
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    runtime::Runtime,
+    sync::broadcast::{self, error::TryRecvError},
+    task::JoinHandle,
+};
+
+/// Pause between background browses
+const BROWSE_INTERVAL: Duration = Duration::from_secs(30);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Result of a call that does not return a pointer
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdStatus {
+    /// The call succeeded
+    Ok = 0,
+    /// An argument was null or not valid UTF-8
+    InvalidArgument = 1,
+    /// The operation failed; see [`ad_last_error`]
+    Failed = 2,
+}
+
+/// What happened to a service
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdEventKind {
+    /// The service was discovered
+    New = 0,
+    /// The service changed
+    Updated = 1,
+    /// The service went away
+    Removed = 2,
+}
+
+/// A TXT attribute
+#[repr(C)]
+#[derive(Debug)]
+pub struct AdAttribute {
+    /// Attribute key
+    pub key: *const c_char,
+    /// Attribute value
+    pub value: *const c_char,
+}
+
+/// A service
+#[repr(C)]
+#[derive(Debug)]
+pub struct AdService {
+    /// Instance name
+    pub name: *const c_char,
+    /// Service type, e.g. `_http._tcp`
+    pub service_type: *const c_char,
+    /// Advertised hostname, or null
+    pub host: *const c_char,
+    /// IP address in text form
+    pub address: *const c_char,
+    /// Port number
+    pub port: u16,
+    /// TXT attributes
+    pub attributes: *const AdAttribute,
+    /// Number of entries in `attributes`
+    pub attribute_count: usize,
+}
+
+/// Services returned by [`ad_discovery_discover`]
+#[repr(C)]
+#[derive(Debug)]
+pub struct AdServiceList {
+    /// The services
+    pub services: *const AdService,
+    /// Number of entries in `services`
+    pub count: usize,
+}
+
+/// An event returned by [`ad_discovery_poll_event`]
+#[repr(C)]
+#[derive(Debug)]
+pub struct AdEvent {
+    /// What happened
+    pub kind: AdEventKind,
+    /// The service it happened to
+    pub service: AdService,
+}
+
+/// Opaque discovery handle
+pub struct AdDiscovery {
+    runtime: Runtime,
+    discovery: Arc<ServiceDiscovery>,
+    events: Mutex<broadcast::Receiver<ServiceEvent>>,
+    browser: Option<JoinHandle<()>>,
+}
+
+/// Create a discovery handle browsing `service_types`
+///
+/// Each discovery pass waits `timeout_ms` milliseconds for answers; 0 keeps
+/// the default. Returns null on failure.
+///
+/// # Safety
+///
+/// `service_types` must point to `service_type_count` valid NUL-terminated
+/// strings, or be null if `service_type_count` is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_discovery_new(
+    service_types: *const *const c_char,
+    service_type_count: usize,
+    timeout_ms: u32,
+) -> *mut AdDiscovery {
+    let mut config = DiscoveryConfig::new();
+    if timeout_ms > 0 {
+        config = config.with_timeout(Duration::from_millis(timeout_ms.into()));
+    }
+    if service_type_count > 0 {
+        if service_types.is_null() {
+            set_last_error("service_types is null");
+            return ptr::null_mut();
+        }
+        // SAFETY: the caller passes `service_type_count` string pointers
+        let names = unsafe { std::slice::from_raw_parts(service_types, service_type_count) };
+        for &name in names {
+            // SAFETY: each entry is a valid C string per the contract above
+            let service_type = unsafe { to_str(name, "service type") }.and_then(ServiceType::new);
+            match service_type {
+                Ok(service_type) => config = config.with_service_type(service_type),
+                Err(e) => {
+                    set_last_error(e);
+                    return ptr::null_mut();
+                }
+            }
+        }
+    }
+
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            set_last_error(format!("Cannot start runtime: {}", e));
+            return ptr::null_mut();
+        }
+    };
+    let discovery = match runtime.block_on(ServiceDiscovery::new(config)) {
+        Ok(discovery) => discovery,
+        Err(e) => {
+            set_last_error(e);
+            return ptr::null_mut();
+        }
+    };
+    let discovery = Arc::new(discovery);
+    let events = Mutex::new(discovery.subscribe());
+
+    // Browse results reach the registry, whose events the handle hands out
+    let browser = (service_type_count > 0).then(|| {
+        let discovery = discovery.clone();
+        runtime.spawn(async move {
+            loop {
+                if let Err(e) = discovery.discover_services(None).await {
+                    tracing::debug!("Background browse failed: {}", e);
+                }
+                tokio::time::sleep(BROWSE_INTERVAL).await;
+            }
+        })
+    });
+
+    Box::into_raw(Box::new(AdDiscovery {
+        runtime,
+        discovery,
+        events,
+        browser,
+    }))
+}
+
+/// Unregister the handle's services and destroy it
+///
+/// # Safety
+///
+/// `handle` must come from [`ad_discovery_new`] and not be used afterwards.
+/// Null is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_discovery_free(handle: *mut AdDiscovery) {
+    if handle.is_null() {
+        return;
+    }
+    // SAFETY: the caller gives up a handle created by `ad_discovery_new`
+    let handle = unsafe { Box::from_raw(handle) };
+    if let Some(browser) = &handle.browser {
+        browser.abort();
+    }
+    handle.runtime.block_on(async {
+        for service in handle.discovery.get_registered_services().await {
+            if let Err(e) = handle.discovery.unregister_service(&service).await {
+                tracing::warn!("Failed to unregister {}: {}", service.name, e);
+            }
+        }
+    });
+    let AdDiscovery { runtime, discovery, .. } = *handle;
+    {
+        let _guard = runtime.enter();
+        drop(discovery);
+    }
+    runtime.shutdown_background();
+}
+
+/// Register a service on every enabled protocol
+///
+/// The service stays registered until the handle is freed.
+///
+/// # Safety
+///
+/// `handle` must be a live handle; `name` and `service_type` valid
+/// NUL-terminated strings; `attributes` must point to `attribute_count`
+/// attributes with valid strings, or be null if `attribute_count` is 0.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_discovery_register(
+    handle: *mut AdDiscovery,
+    name: *const c_char,
+    service_type: *const c_char,
+    port: u16,
+    attributes: *const AdAttribute,
+    attribute_count: usize,
+) -> AdStatus {
+    // SAFETY: the caller passes a live handle
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        set_last_error("handle is null");
+        return AdStatus::InvalidArgument;
+    };
+    // SAFETY: the strings and attributes are valid per the contract above
+    let service = match unsafe { to_service(name, service_type, port, attributes, attribute_count) } {
+        Ok(service) => service,
+        Err(e) => {
+            set_last_error(e);
+            return AdStatus::InvalidArgument;
+        }
+    };
+
+    match handle.runtime.block_on(handle.discovery.register_service(service)) {
+        Ok(()) => AdStatus::Ok,
+        Err(e) => {
+            set_last_error(e);
+            AdStatus::Failed
+        }
+    }
+}
+
+/// Run a discovery pass and return the services found
+///
+/// Returns null on failure. Free the list with [`ad_service_list_free`].
+///
+/// # Safety
+///
+/// `handle` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_discovery_discover(handle: *mut AdDiscovery) -> *mut AdServiceList {
+    // SAFETY: the caller passes a live handle
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        set_last_error("handle is null");
+        return ptr::null_mut();
+    };
+    match handle.runtime.block_on(handle.discovery.discover_services(None)) {
+        Ok(services) => {
+            let services: Box<[AdService]> = services.iter().map(AdService::new).collect();
+            let count = services.len();
+            Box::into_raw(Box::new(AdServiceList {
+                services: Box::into_raw(services) as *const AdService,
+                count,
+            }))
+        }
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Take the next pending service event without waiting
+///
+/// Returns null if no event is pending. Free the event with
+/// [`ad_event_free`].
+///
+/// # Safety
+///
+/// `handle` must be a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_discovery_poll_event(handle: *mut AdDiscovery) -> *mut AdEvent {
+    // SAFETY: the caller passes a live handle
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        set_last_error("handle is null");
+        return ptr::null_mut();
+    };
+    let mut events = handle.events.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        let (kind, service) = match events.try_recv() {
+            Ok(ServiceEvent::New { service, .. }) => (AdEventKind::New, service),
+            Ok(ServiceEvent::Updated { service, .. }) => (AdEventKind::Updated, service),
+            Ok(ServiceEvent::Removed { service, .. }) => (AdEventKind::Removed, service),
+            Ok(_) | Err(TryRecvError::Lagged(_)) => continue,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return ptr::null_mut(),
+        };
+        return Box::into_raw(Box::new(AdEvent {
+            kind,
+            service: AdService::new(&service),
+        }));
+    }
+}
+
+/// Free a list returned by [`ad_discovery_discover`]
+///
+/// # Safety
+///
+/// `list` must come from [`ad_discovery_discover`] and not be used
+/// afterwards. Null is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_service_list_free(list: *mut AdServiceList) {
+    if list.is_null() {
+        return;
+    }
+    // SAFETY: the list and its array were allocated by `ad_discovery_discover`
+    unsafe {
+        let list = Box::from_raw(list);
+        let services = Box::from_raw(ptr::slice_from_raw_parts_mut(list.services as *mut AdService, list.count));
+        for service in services.iter() {
+            service.free();
+        }
+    }
+}
+
+/// Free an event returned by [`ad_discovery_poll_event`]
+///
+/// # Safety
+///
+/// `event` must come from [`ad_discovery_poll_event`] and not be used
+/// afterwards. Null is ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ad_event_free(event: *mut AdEvent) {
+    if event.is_null() {
+        return;
+    }
+    // SAFETY: the event was allocated by `ad_discovery_poll_event`
+    unsafe { Box::from_raw(event).service.free() }
+}
+
+/// Describe the last failure on the calling thread
+///
+/// Returns null if nothing failed yet. The string stays valid until the next
+/// failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn ad_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ref().map_or(ptr::null(), |error| error.as_ptr()))
+}
+
+impl AdService {
+    fn new(service: &ServiceInfo) -> Self {
+        let attributes: Box<[AdAttribute]> = service
+            .attributes
+            .iter()
+            .map(|(key, value)| AdAttribute {
+                key: to_c_string(key),
+                value: to_c_string(value),
+            })
+            .collect();
+        Self {
+            name: to_c_string(&service.name),
+            service_type: to_c_string(&service.service_type.to_string()),
+            host: service.host.as_deref().map_or(ptr::null(), to_c_string),
+            address: to_c_string(&service.address.to_string()),
+            port: service.port,
+            attribute_count: attributes.len(),
+            attributes: Box::into_raw(attributes) as *const AdAttribute,
+        }
+    }
+
+    /// Release the strings and attributes of a service built by [`Self::new`]
+    ///
+    /// # Safety
+    ///
+    /// Must be called once, on a service built by [`Self::new`].
+    unsafe fn free(&self) {
+        // SAFETY: every pointer was allocated by `AdService::new`
+        unsafe {
+            let attributes = Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.attributes as *mut AdAttribute,
+                self.attribute_count,
+            ));
+            for attribute in attributes.iter() {
+                free_c_string(attribute.key);
+                free_c_string(attribute.value);
+            }
+            for string in [self.name, self.service_type, self.host, self.address] {
+                free_c_string(string);
+            }
+        }
+    }
+}
+
+/// Build a service from C arguments
+///
+/// # Safety
+///
+/// Same contract as [`ad_discovery_register`].
+unsafe fn to_service(
+    name: *const c_char,
+    service_type: *const c_char,
+    port: u16,
+    attributes: *const AdAttribute,
+    attribute_count: usize,
+) -> Result<ServiceInfo> {
+    let attributes = match attribute_count {
+        0 => &[][..],
+        _ if attributes.is_null() => return Err(DiscoveryError::configuration("attributes is null")),
+        // SAFETY: the caller passes `attribute_count` attributes
+        count => unsafe { std::slice::from_raw_parts(attributes, count) },
+    };
+    // SAFETY: all strings are valid C strings per the contract above
+    unsafe {
+        let attributes = attributes
+            .iter()
+            .map(|attribute| Ok((to_str(attribute.key, "attribute key")?, to_str(attribute.value, "attribute value")?)))
+            .collect::<Result<Vec<_>>>()?;
+        ServiceInfo::new(to_str(name, "name")?, to_str(service_type, "service type")?, port, Some(attributes))
+    }
+}
+
+fn set_last_error(error: impl ToString) {
+    let error = CString::new(error.to_string().replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(error));
+}
+
+/// Borrow a C string argument as UTF-8
+///
+/// # Safety
+///
+/// `string` must be null or a valid NUL-terminated string that outlives `'a`.
+unsafe fn to_str<'a>(string: *const c_char, what: &str) -> Result<&'a str> {
+    if string.is_null() {
+        return Err(DiscoveryError::configuration(format!("{} is null", what)));
+    }
+    // SAFETY: non-null and valid per the contract above
+    unsafe { CStr::from_ptr(string) }
+        .to_str()
+        .map_err(|_| DiscoveryError::configuration(format!("{} is not valid UTF-8", what)))
+}
+
+fn to_c_string(string: &str) -> *const c_char {
+    // Interior NULs cannot be represented in C strings
+    CString::new(string.replace('\0', "")).unwrap_or_default().into_raw()
+}
+
+/// Free a string from [`to_c_string`]; null is ignored
+///
+/// # Safety
+///
+/// `string` must be null or come from [`to_c_string`], and not be freed twice.
+unsafe fn free_c_string(string: *const c_char) {
+    if !string.is_null() {
+        // SAFETY: allocated by `CString::into_raw` in `to_c_string`
+        drop(unsafe { CString::from_raw(string as *mut c_char) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ProtocolType;
+
+    #[test]
+    fn test_service_conversion_round_trip() {
+        let mut service = ServiceInfo::new("printer\0 one", "_ipp._tcp", 631, Some(vec![("rp", "ipp/print")])).unwrap();
+        service.protocol_type = ProtocolType::Mdns;
+        let converted = AdService::new(&service);
+        unsafe {
+            assert_eq!(CStr::from_ptr(converted.name).to_str().unwrap(), "printer one");
+            assert_eq!(CStr::from_ptr(converted.service_type).to_str().unwrap(), "_ipp._tcp");
+            assert!(converted.host.is_null());
+            assert_eq!(converted.port, 631);
+            assert_eq!(converted.attribute_count, 1);
+            let attribute = &*converted.attributes;
+            assert_eq!(CStr::from_ptr(attribute.key).to_str().unwrap(), "rp");
+            assert_eq!(CStr::from_ptr(attribute.value).to_str().unwrap(), "ipp/print");
+            converted.free();
+        }
+    }
+
+    #[test]
+    fn test_invalid_arguments_set_last_error() {
+        unsafe {
+            let bad = c"not a service type";
+            assert!(ad_discovery_new(&bad.as_ptr(), 1, 0).is_null());
+            assert!(!ad_last_error().is_null());
+
+            assert_eq!(
+                ad_discovery_register(ptr::null_mut(), c"x".as_ptr(), c"_http._tcp".as_ptr(), 80, ptr::null(), 0),
+                AdStatus::InvalidArgument
+            );
+            assert_eq!(CStr::from_ptr(ad_last_error()).to_str().unwrap(), "handle is null");
+            assert!(ad_discovery_poll_event(ptr::null_mut()).is_null());
+            ad_discovery_free(ptr::null_mut());
+            ad_service_list_free(ptr::null_mut());
+            ad_event_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_handle_lifecycle() {
+        unsafe {
+            let service_type = c"_adffi._tcp";
+            let handle = ad_discovery_new(&service_type.as_ptr(), 1, 1000);
+            assert!(!handle.is_null(), "{:?}", CStr::from_ptr(ad_last_error()));
+
+            let attribute = AdAttribute {
+                key: c"version".as_ptr(),
+                value: c"1".as_ptr(),
+            };
+            let status = ad_discovery_register(handle, c"ffi test".as_ptr(), service_type.as_ptr(), 18080, &attribute, 1);
+            assert_eq!(status, AdStatus::Ok, "{:?}", CStr::from_ptr(ad_last_error()));
+
+            let list = ad_discovery_discover(handle);
+            assert!(!list.is_null(), "{:?}", CStr::from_ptr(ad_last_error()));
+            ad_service_list_free(list);
+
+            loop {
+                let event = ad_discovery_poll_event(handle);
+                if event.is_null() {
+                    break;
+                }
+                ad_event_free(event);
+            }
+            ad_discovery_free(handle);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Registration of many instances of one service
+//!
+//! A [`ServiceTemplate`] describes the instances of a pool of identical
+//! services, such as workers; [`ServiceDiscovery::register_fleet`] registers a
+//! number of them, each on a free port from the template's range, and returns
+//! a [`FleetHandle`] that unregisters them all together.
+//!
+//! ```rust,no_run
+//! use auto_discovery::{ServiceDiscovery, config::DiscoveryConfig, fleet::ServiceTemplate};
+//!
+//! # async fn example() -> auto_discovery::Result<()> {
+//! let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+//! let template = ServiceTemplate::new("worker-{index}", "_worker._tcp", 9000..=9099)?
+//!     .with_attribute("queue", "images");
+//! let fleet = discovery.register_fleet(&template, 8).await?;
+//! // ...
+//! fleet.unregister().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    discovery::ServiceDiscovery,
+    error::{DiscoveryError, Result},
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use std::{collections::HashMap, ops::RangeInclusive};
+use tracing::warn;
+
+/// Placeholder in a name pattern replaced by the instance number
+pub const INDEX_PLACEHOLDER: &str = "{index}";
+
+/// Description of the instances of a service pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceTemplate {
+    name_pattern: String,
+    service_type: ServiceType,
+    ports: RangeInclusive<u16>,
+    attributes: HashMap<String, String>,
+    protocol_type: ProtocolType,
+}
+
+impl ServiceTemplate {
+    /// Create a template for instances of `service_type` on ports in `ports`
+    ///
+    /// Instances are named after `name_pattern` with [`INDEX_PLACEHOLDER`]
+    /// replaced by the instance number, counting from 1. A pattern without
+    /// the placeholder gets `-{index}` appended.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name pattern is empty, the service type is
+    /// invalid, or the port range is empty or includes port 0
+    pub fn new(
+        name_pattern: impl Into<String>,
+        service_type: impl Into<String>,
+        ports: RangeInclusive<u16>,
+    ) -> Result<Self> {
+        let mut name_pattern = name_pattern.into();
+        if name_pattern.trim().is_empty() {
+            return Err(DiscoveryError::configuration("Service template name pattern cannot be empty"));
+        }
+        if ports.is_empty() || *ports.start() == 0 {
+            return Err(DiscoveryError::configuration(format!(
+                "Service template port range {}..={} must be non-empty and exclude port 0",
+                ports.start(),
+                ports.end()
+            )));
+        }
+        if !name_pattern.contains(INDEX_PLACEHOLDER) {
+            name_pattern = format!("{name_pattern}-{INDEX_PLACEHOLDER}");
+        }
+        Ok(Self {
+            name_pattern,
+            service_type: ServiceType::new(service_type)?,
+            ports,
+            attributes: HashMap::new(),
+            protocol_type: ProtocolType::default(),
+        })
+    }
+
+    /// Add an attribute advertised by every instance
+    pub fn with_attribute<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Register instances with `protocol_type`
+    pub fn with_protocol_type(mut self, protocol_type: ProtocolType) -> Self {
+        self.protocol_type = protocol_type;
+        self
+    }
+
+    /// Pattern instance names are made from
+    pub fn name_pattern(&self) -> &str {
+        &self.name_pattern
+    }
+
+    /// Type of the instances
+    pub fn service_type(&self) -> &ServiceType {
+        &self.service_type
+    }
+
+    /// Ports instances are allocated from
+    pub fn ports(&self) -> &RangeInclusive<u16> {
+        &self.ports
+    }
+
+    /// Attributes advertised by every instance
+    pub fn attributes(&self) -> &HashMap<String, String> {
+        &self.attributes
+    }
+
+    /// Name of instance number `index`
+    pub fn instance_name(&self, index: usize) -> String {
+        self.name_pattern.replace(INDEX_PLACEHOLDER, &index.to_string())
+    }
+
+    /// Instance number `index`, listening on `port`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `port` is 0
+    pub fn instance(&self, index: usize, port: u16) -> Result<ServiceInfo> {
+        let mut service = ServiceInfo::new(self.instance_name(index), self.service_type.clone(), port, None)?
+            .with_protocol_type(self.protocol_type);
+        service.attributes.extend(self.attributes.clone());
+        Ok(service)
+    }
+}
+
+/// Instances registered together by [`ServiceDiscovery::register_fleet`]
+/// or [`ServiceDiscovery::register_linked`]
+///
+/// Dropping the handle leaves the instances registered; they are withdrawn
+/// by [`unregister`](Self::unregister) or when the discovery instance closes.
+pub struct FleetHandle<'a> {
+    discovery: &'a ServiceDiscovery,
+    services: Vec<ServiceInfo>,
+}
+
+impl<'a> FleetHandle<'a> {
+    pub(crate) fn new(discovery: &'a ServiceDiscovery, services: Vec<ServiceInfo>) -> Self {
+        Self { discovery, services }
+    }
+
+    /// The instances, as registered
+    pub fn services(&self) -> &[ServiceInfo] {
+        &self.services
+    }
+
+    /// Number of instances
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    /// Whether the fleet has no instances
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+
+    /// Unregister every instance
+    ///
+    /// All instances are attempted even if some fail; the first error is
+    /// returned.
+    pub async fn unregister(self) -> Result<()> {
+        let mut first_error = None;
+        for service in &self.services {
+            if let Err(e) = self.discovery.unregister_service(service).await {
+                warn!("Failed to unregister fleet instance {}: {}", service.name(), e);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_template() {
+        let template = ServiceTemplate::new("worker", "_worker._tcp", 9000..=9010)
+            .unwrap()
+            .with_attribute("queue", "images");
+        assert_eq!(template.name_pattern(), "worker-{index}");
+
+        let service = template.instance(3, 9002).unwrap();
+        assert_eq!(service.name(), "worker-3");
+        assert_eq!(service.port, 9002);
+        assert_eq!(service.attributes.get("queue").map(String::as_str), Some("images"));
+
+        assert!(ServiceTemplate::new("", "_worker._tcp", 9000..=9010).is_err());
+        assert!(ServiceTemplate::new("worker", "_worker._tcp", 0..=10).is_err());
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 9010..=9000;
+        assert!(ServiceTemplate::new("worker", "_worker._tcp", empty).is_err());
+    }
+}
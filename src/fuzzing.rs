@@ -0,0 +1,77 @@
+//! Entry points for the fuzz targets in `fuzz/`
+//!
+//! Enabled with the `fuzzing` feature. Each function feeds arbitrary bytes to
+//! a parser that sees untrusted network data. None of them may panic,
+//! whatever the input.
+
+use crate::{
+    protocols::upnp::{
+        validation::{ResponseValidator, Verdict},
+        SsdpProtocol,
+    },
+    service::ServiceInfo,
+    types::ServiceType,
+    utils::string,
+};
+use std::net::SocketAddr;
+
+/// Validate a search response, as received from a device on the LAN
+pub fn ssdp_response(data: &[u8]) {
+    let response = String::from_utf8_lossy(data);
+    let searched = ServiceType::new("urn:schemas-upnp-org:device:MediaRenderer:1").expect("valid search target");
+    let local = "192.168.1.0/24".parse().expect("valid network");
+    let mut validator = ResponseValidator::new(&[searched], vec![local], Vec::new());
+    let source = SocketAddr::from(([192, 168, 1, 30], 1900));
+
+    // The second look takes the duplicate path
+    if let Verdict::Accept(service) = validator.check(&response, source) {
+        let _ = service.to_string();
+        let _ = validator.check(&response, source);
+    }
+}
+
+/// Parse the search target of an M-SEARCH and match it against a service
+pub fn ssdp_search_target(data: &[u8]) {
+    let message = String::from_utf8_lossy(data);
+    if let Some(target) = SsdpProtocol::parse_search_target(&message)
+        && let Ok(service) = ServiceInfo::new("fuzz", "_http._tcp", 80, None)
+    {
+        let _ = SsdpProtocol::service_matches_search(&target, &service);
+    }
+}
+
+/// Parse a TXT record and format the attributes again
+pub fn txt_record(data: &[u8]) {
+    let attributes = string::parse_txt_record(&String::from_utf8_lossy(data));
+    let _ = string::format_txt_record(&attributes);
+}
+
+/// Parse a service type and display it again
+pub fn service_type(data: &[u8]) {
+    if let Ok(service_type) = ServiceType::new(String::from_utf8_lossy(data)) {
+        let _ = ServiceType::new(service_type.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_points_survive_hostile_input() {
+        let inputs: [&[u8]; 6] = [
+            b"",
+            b"\xff\xfe\x00",
+            b"HTTP/1.1 200 OK\r\nST: urn:schemas-upnp-org:device:MediaRenderer:1\r\nLOCATION: http://192.168.1.30/\r\nUSN: uuid:a\r\n\r\n",
+            b"M-SEARCH * HTTP/1.1\r\nST:\r\n\r\n",
+            b";=;==;\xc3\xa9=\xc3",
+            b"._.._tcp.\xc3\xa9",
+        ];
+        for input in inputs {
+            ssdp_response(input);
+            ssdp_search_target(input);
+            txt_record(input);
+            service_type(input);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Discovery gateway for clients without multicast access
+//!
+//! A [`GatewayServer`] runs next to a [`ServiceDiscovery`] on a host that can
+//! reach the LAN and exposes it to clients that cannot, such as containers
+//! on a Docker bridge network. One port serves two APIs:
+//!
+//! - HTTP/JSON, the agent API described in [`remote`](crate::remote) and
+//!   spoken by [`RemoteDiscovery`](crate::remote::RemoteDiscovery)
+//! - gRPC, the `autodiscovery.v1.Discovery` service described in [`grpc`]
+//!
+//! Both list services, stream events, and register and unregister services
+//! on behalf of their clients. Clients can only unregister services that
+//! were registered through the gateway.
+
+use crate::{
+    config::RegistrationConfig,
+    discovery::ServiceDiscovery,
+    error::{DiscoveryError, Result},
+    remote::API_PREFIX,
+    service::{ServiceEvent, ServiceInfo},
+    types::ServiceType,
+};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full, StreamBody};
+use hyper::{body::{Frame, Incoming}, header, service::service_fn, Method, Request, Response, StatusCode};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::TcpListener,
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+pub mod grpc;
+
+/// Interval of keep-alive comments on idle event streams
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+/// Server exposing a [`ServiceDiscovery`] over HTTP/JSON and gRPC
+pub struct GatewayServer {
+    discovery: Arc<ServiceDiscovery>,
+    registered: Mutex<HashMap<Uuid, ServiceInfo>>,
+}
+
+impl GatewayServer {
+    /// Serve `discovery`
+    pub fn new(discovery: Arc<ServiceDiscovery>) -> Self {
+        Self {
+            discovery,
+            registered: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Listen on `addr`; port `0` picks a free port
+    pub async fn bind(self, addr: SocketAddr) -> Result<GatewayHandle> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("Discovery gateway listening on {}", local_addr);
+
+        let server = Arc::new(self);
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Gateway failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(|request| {
+                        let server = server.clone();
+                        async move { Ok::<_, Infallible>(server.respond(request).await) }
+                    });
+                    // HTTP/1 for JSON clients, HTTP/2 without TLS for gRPC
+                    let builder = auto::Builder::new(TokioExecutor::new());
+                    if let Err(e) = builder.serve_connection(TokioIo::new(stream), service).await {
+                        debug!("Gateway connection from {} failed: {}", peer, e);
+                    }
+                });
+            }
+        });
+
+        Ok(GatewayHandle { local_addr, task })
+    }
+
+    async fn respond(&self, request: Request<Incoming>) -> Response<Body> {
+        if grpc::is_grpc_request(&request) {
+            return grpc::respond(self, request).await;
+        }
+
+        let Some(path) = request.uri().path().strip_prefix(API_PREFIX) else {
+            return text(StatusCode::NOT_FOUND, "not found");
+        };
+        match (request.method(), path) {
+            (&Method::GET, "/services") => {
+                let service_type = request
+                    .uri()
+                    .query()
+                    .and_then(|query| url::form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "type"))
+                    .map(|(_, value)| ServiceType::new(value.as_ref()));
+                match service_type.transpose() {
+                    Ok(service_type) => match self.list_services(service_type).await {
+                        Ok(services) => json(StatusCode::OK, &services),
+                        Err(e) => error(&e),
+                    },
+                    Err(e) => text(StatusCode::BAD_REQUEST, &e.to_string()),
+                }
+            }
+            (&Method::POST, "/services") => {
+                let service = match request.into_body().collect().await {
+                    Ok(body) => serde_json::from_slice::<ServiceInfo>(&body.to_bytes()),
+                    Err(e) => return text(StatusCode::BAD_REQUEST, &e.to_string()),
+                };
+                match service {
+                    Ok(service) => match self.register(service).await {
+                        Ok(service) => json(StatusCode::CREATED, &service),
+                        Err(e) => error(&e),
+                    },
+                    Err(e) => text(StatusCode::BAD_REQUEST, &format!("invalid service: {e}")),
+                }
+            }
+            (&Method::DELETE, path) if path.starts_with("/services/") => {
+                match path["/services/".len()..].parse::<Uuid>() {
+                    Ok(id) => match self.unregister(id).await {
+                        Ok(()) => response(StatusCode::NO_CONTENT, "text/plain", Full::new(Bytes::new()).boxed_unsync()),
+                        Err(e) => error(&e),
+                    },
+                    Err(_) => text(StatusCode::NOT_FOUND, "unknown service"),
+                }
+            }
+            (&Method::GET, "/events") => {
+                let events = self.events().filter_map(|event| async move {
+                    let frame = match event {
+                        Some(event) => format!("data: {}\n\n", serde_json::to_string(&event).ok()?),
+                        None => ": keep-alive\n\n".to_string(),
+                    };
+                    Some(Ok(Frame::data(Bytes::from(frame))))
+                });
+                response(StatusCode::OK, "text/event-stream", StreamBody::new(events).boxed_unsync())
+            }
+            (&Method::GET, "/changes") => {
+                let since = request
+                    .uri()
+                    .query()
+                    .and_then(|query| url::form_urlencoded::parse(query.as_bytes()).find(|(key, _)| key == "since"))
+                    .map(|(_, value)| value.parse::<u64>());
+                match since.transpose() {
+                    Ok(since) => json(StatusCode::OK, &self.discovery.changes_since(since.unwrap_or(0))),
+                    Err(e) => text(StatusCode::BAD_REQUEST, &format!("invalid sequence number: {e}")),
+                }
+            }
+            (&Method::GET, "/snapshot") => json(StatusCode::OK, &self.discovery.snapshot().await),
+            (_, "/services" | "/events" | "/changes" | "/snapshot") => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
+    }
+
+    /// Discover services of `service_type`, or of the configured types
+    async fn list_services(&self, service_type: Option<ServiceType>) -> Result<Vec<ServiceInfo>> {
+        self.discovery
+            .discover_services_filtered(service_type.map(|service_type| vec![service_type]), None)
+            .await
+    }
+
+    /// Register `service`, returning it as registered, possibly renamed
+    async fn register(&self, service: ServiceInfo) -> Result<ServiceInfo> {
+        let service = self
+            .discovery
+            .register_service_with_config(service, &RegistrationConfig::default())
+            .await?;
+        self.registered
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(service.id, service.clone());
+        Ok(service)
+    }
+
+    async fn unregister(&self, id: Uuid) -> Result<()> {
+        let service = self
+            .registered
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| DiscoveryError::service_not_found(format!("No service {id} was registered through the gateway")))?;
+        self.discovery.unregister_service(&service).await?;
+        self.registered.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+        Ok(())
+    }
+
+    /// Registry events, with `None` after each idle keep-alive interval
+    fn events(&self) -> impl Stream<Item = Option<ServiceEvent>> + Send + 'static {
+        futures::stream::unfold(self.discovery.subscribe(), |mut events: broadcast::Receiver<ServiceEvent>| async move {
+            loop {
+                match tokio::time::timeout(KEEP_ALIVE_INTERVAL, events.recv()).await {
+                    Ok(Ok(event)) => return Some((Some(event), events)),
+                    Ok(Err(RecvError::Lagged(missed))) => debug!("Gateway event stream skipped {} events", missed),
+                    Ok(Err(RecvError::Closed)) => return None,
+                    Err(_) => return Some((None, events)),
+                }
+            }
+        })
+    }
+}
+
+/// A running [`GatewayServer`]
+#[derive(Debug)]
+pub struct GatewayHandle {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl GatewayHandle {
+    /// Address the gateway is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections
+    pub fn shutdown(self) {
+        self.task.abort();
+    }
+}
+
+fn response(status: StatusCode, content_type: &str, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    if let Ok(value) = content_type.parse() {
+        response.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    response
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    response(status, "text/plain", Full::new(Bytes::from(body.to_string())).boxed_unsync())
+}
+
+fn json<T: serde::Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => response(status, "application/json", Full::new(Bytes::from(body)).boxed_unsync()),
+        Err(e) => text(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn error(error: &DiscoveryError) -> Response<Body> {
+    let status = match error.root() {
+        DiscoveryError::ServiceNotFound(_) => StatusCode::NOT_FOUND,
+        DiscoveryError::InvalidServiceInfo { .. } | DiscoveryError::Configuration(_) => StatusCode::BAD_REQUEST,
+        DiscoveryError::Conflict(_) => StatusCode::CONFLICT,
+        DiscoveryError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    text(status, &error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{ContainerConfig, DiscoveryConfig}, protocols::DiscoveryProtocol, registry::ServiceRegistry, remote::RemoteDiscovery,
+        types::ProtocolType,
+    };
+    use std::net::{IpAddr, Ipv4Addr};
+
+    /// Protocol finding one printer and accepting every registration
+    pub(super) struct LanProtocol;
+
+    #[async_trait::async_trait]
+    impl DiscoveryProtocol for LanProtocol {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn discover_services(
+            &self,
+            _service_types: Vec<ServiceType>,
+            _timeout: Option<Duration>,
+        ) -> Result<Vec<ServiceInfo>> {
+            Ok(vec![ServiceInfo::new("Printer", "_ipp._tcp", 631, Some(vec![("rp", "ipp/print")]))?
+                .with_address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)))
+                .with_protocol_type(ProtocolType::Upnp)])
+        }
+
+        async fn register_service(&self, _service: ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unregister_service(&self, _service: &ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn verify_service(&self, _service: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+    }
+
+    pub(super) async fn gateway() -> GatewayHandle {
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_ipp._tcp").unwrap());
+        let discovery = ServiceDiscovery::builder(config).with_protocol(LanProtocol).build().await.unwrap();
+        GatewayServer::new(Arc::new(discovery))
+            .bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gateway_json_api() {
+        let gateway = gateway().await;
+        let remote = RemoteDiscovery::new(&format!("http://{}", gateway.local_addr())).unwrap();
+        let mut events = Box::pin(remote.events().await.unwrap());
+
+        let services = remote.discover_services(Some(&ServiceType::new("_ipp._tcp").unwrap())).await.unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].get_attribute("rp").map(String::as_str), Some("ipp/print"));
+        match events.next().await {
+            Some(ServiceEvent::New { service, .. }) => assert_eq!(service.name, "Printer"),
+            other => panic!("expected a new service event, got {other:?}"),
+        }
+
+        let service = ServiceInfo::new("container app", "_http._tcp", 8080, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        remote.register_service(&service).await.unwrap();
+        remote.unregister_service(&service).await.unwrap();
+        assert!(matches!(remote.unregister_service(&service).await, Err(DiscoveryError::ServiceNotFound(_))));
+        gateway.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_gateway_change_feed() {
+        let gateway = gateway().await;
+        let remote = RemoteDiscovery::new(&format!("http://{}", gateway.local_addr())).unwrap();
+        let snapshot = remote.snapshot().await.unwrap();
+        assert!(snapshot.entries.is_empty());
+
+        remote.discover_services(None).await.unwrap();
+        let changes = remote.changes_since(snapshot.sequence).await.unwrap();
+        assert!(!changes.truncated);
+        assert!(changes
+            .events
+            .iter()
+            .any(|change| matches!(&change.event, ServiceEvent::New { service, .. } if service.name == "Printer")));
+        assert!(remote.changes_since(changes.latest).await.unwrap().events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_container_mode_falls_back_to_gateway() {
+        let gateway = gateway().await;
+        let container = ContainerConfig::new()
+            .with_multicast_available(false)
+            .with_gateway_url(format!("http://{}", gateway.local_addr()));
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Mdns, ProtocolType::Upnp].into_iter().collect())
+            .with_service_type(ServiceType::new("_ipp._tcp").unwrap())
+            .with_container_mode(container);
+        let discovery = ServiceDiscovery::new(config).await.unwrap();
+
+        // The gateway takes mDNS's place; UPnP is reported as unavailable
+        let report = discovery.init_report();
+        assert_eq!(report.started(), [ProtocolType::Mdns]);
+        assert_eq!(report.failures().len(), 1);
+        assert_eq!(report.failures()[0].0, ProtocolType::Upnp);
+        assert!(report.failures()[0].1.contains("Multicast is unavailable"), "{report}");
+
+        let services = discovery.discover_services(None).await.unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "Printer");
+        gateway.shutdown();
+    }
+}
@@ -0,0 +1,441 @@
+//! gRPC API of the discovery gateway
+//!
+//! Served over HTTP/2 without TLS on the gateway port:
+//!
+//! ```proto
+//! syntax = "proto3";
+//! package autodiscovery.v1;
+//!
+//! service Discovery {
+//!   rpc ListServices(ListServicesRequest) returns (ListServicesResponse);
+//!   rpc Register(Service) returns (Service);
+//!   rpc Unregister(UnregisterRequest) returns (UnregisterResponse);
+//!   rpc Watch(WatchRequest) returns (stream Event);
+//! }
+//!
+//! message Service {
+//!   string id = 1;
+//!   string name = 2;
+//!   string service_type = 3;
+//!   string address = 4;
+//!   uint32 port = 5;
+//!   map<string, string> attributes = 6;
+//!   string host = 7;
+//!   // "mDNS", "DNS-SD" or "UPnP"; empty registers over mDNS
+//!   string protocol = 8;
+//! }
+//!
+//! // Empty service_type lists the gateway's configured types
+//! message ListServicesRequest { string service_type = 1; }
+//! message ListServicesResponse { repeated Service services = 1; }
+//! message UnregisterRequest { string id = 1; }
+//! message UnregisterResponse {}
+//! message WatchRequest {}
+//!
+//! message Event {
+//!   enum Kind { NEW = 0; UPDATED = 1; REMOVED = 2; }
+//!   Kind kind = 1;
+//!   Service service = 2;
+//! }
+//! ```
+//!
+//! `Register` returns the service as registered, possibly renamed to resolve
+//! a name conflict; its `id`, assigned by the gateway if left empty, is what
+//! `Unregister` takes.
+
+use super::{Body, GatewayServer};
+use crate::{
+    error::DiscoveryError,
+    service::{ServiceEvent, ServiceInfo},
+    types::{ProtocolType, ServiceType},
+    verification::grpc::{get_varint, put_varint},
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::StreamExt;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::{body::{Frame, Incoming}, header, HeaderMap, Request, Response};
+use std::{convert::Infallible, net::IpAddr};
+use uuid::Uuid;
+
+/// Path prefix of the gateway's gRPC methods
+pub const SERVICE_PATH: &str = "/autodiscovery.v1.Discovery/";
+
+/// gRPC status codes used by the gateway
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const NOT_FOUND: u32 = 5;
+const ALREADY_EXISTS: u32 = 6;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+
+type Status = (u32, String);
+
+/// Whether a request is a gRPC call
+pub(super) fn is_grpc_request<B>(request: &Request<B>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+pub(super) async fn respond(gateway: &GatewayServer, request: Request<Incoming>) -> Response<Body> {
+    let method = request.uri().path().strip_prefix(SERVICE_PATH).map(str::to_string);
+    let message = match request.into_body().collect().await {
+        Ok(body) => unframe(body.to_bytes()),
+        Err(e) => Err((INTERNAL, format!("reading request failed: {e}"))),
+    };
+    let message = match message {
+        Ok(message) => message,
+        Err(status) => return status_response(status),
+    };
+
+    let result = match method.as_deref() {
+        Some("ListServices") => list_services(gateway, message).await,
+        Some("Register") => register(gateway, message).await,
+        Some("Unregister") => unregister(gateway, message).await,
+        Some("Watch") => return watch(gateway),
+        _ => Err((UNIMPLEMENTED, "unknown method".to_string())),
+    };
+    match result {
+        Ok(reply) => {
+            let frames = futures::stream::iter([Ok(Frame::data(frame(&reply))), Ok(Frame::trailers(trailers(OK, "")))]);
+            grpc_response(StreamBody::new(frames).boxed_unsync(), HeaderMap::new())
+        }
+        Err(status) => status_response(status),
+    }
+}
+
+async fn list_services(gateway: &GatewayServer, message: Bytes) -> Result<BytesMut, Status> {
+    let mut service_type = None;
+    for (field, value) in fields(message)? {
+        if let (1, Value::Bytes(name)) = (field, value)
+            && !name.is_empty()
+        {
+            let name = std::str::from_utf8(&name).map_err(|_| invalid("service_type is not UTF-8"))?;
+            service_type = Some(ServiceType::new(name).map_err(|e| invalid(e.to_string()))?);
+        }
+    }
+    let services = gateway.list_services(service_type).await.map_err(|e| status(&e))?;
+
+    let mut reply = BytesMut::new();
+    for service in &services {
+        put_message(&mut reply, 1, &encode_service(service));
+    }
+    Ok(reply)
+}
+
+async fn register(gateway: &GatewayServer, message: Bytes) -> Result<BytesMut, Status> {
+    let service = decode_service(message)?;
+    let service = gateway.register(service).await.map_err(|e| status(&e))?;
+    Ok(encode_service(&service))
+}
+
+async fn unregister(gateway: &GatewayServer, message: Bytes) -> Result<BytesMut, Status> {
+    let mut id = None;
+    for (field, value) in fields(message)? {
+        if let (1, Value::Bytes(value)) = (field, value) {
+            id = std::str::from_utf8(&value).ok().and_then(|value| value.parse::<Uuid>().ok());
+        }
+    }
+    let id = id.ok_or_else(|| (NOT_FOUND, "unknown service".to_string()))?;
+    gateway.unregister(id).await.map_err(|e| status(&e))?;
+    Ok(BytesMut::new())
+}
+
+fn watch(gateway: &GatewayServer) -> Response<Body> {
+    let events = gateway.events().filter_map(|event| async move {
+        let (kind, service) = match event? {
+            ServiceEvent::New { service, .. } => (0, service),
+            ServiceEvent::Updated { service, .. } => (1, service),
+            ServiceEvent::Removed { service, .. } => (2, service),
+            _ => return None,
+        };
+        let mut message = BytesMut::new();
+        if kind != 0 {
+            put_key(&mut message, 1, 0);
+            put_varint(&mut message, kind);
+        }
+        put_message(&mut message, 2, &encode_service(&service));
+        Some(Ok::<_, Infallible>(Frame::data(frame(&message))))
+    });
+    grpc_response(StreamBody::new(events).boxed_unsync(), HeaderMap::new())
+}
+
+fn encode_service(service: &ServiceInfo) -> BytesMut {
+    let mut message = BytesMut::new();
+    put_string(&mut message, 1, &service.id.to_string());
+    put_string(&mut message, 2, &service.name);
+    put_string(&mut message, 3, &service.service_type.to_string());
+    put_string(&mut message, 4, &service.address.to_string());
+    if service.port != 0 {
+        put_key(&mut message, 5, 0);
+        put_varint(&mut message, service.port.into());
+    }
+    let mut attributes: Vec<_> = service.attributes.iter().collect();
+    attributes.sort();
+    for (key, value) in attributes {
+        let mut entry = BytesMut::new();
+        put_string(&mut entry, 1, key);
+        put_string(&mut entry, 2, value);
+        put_message(&mut message, 6, &entry);
+    }
+    if let Some(host) = &service.host {
+        put_string(&mut message, 7, host);
+    }
+    put_string(&mut message, 8, &service.protocol_type.to_string());
+    message
+}
+
+fn decode_service(message: Bytes) -> Result<ServiceInfo, Status> {
+    let (mut name, mut service_type, mut address, mut host) = (String::new(), String::new(), None, None);
+    let mut protocol = ProtocolType::default();
+    let mut id = None;
+    let mut port = 0u64;
+    let mut attributes = Vec::new();
+    for (field, value) in fields(message)? {
+        match (field, value) {
+            (1, Value::Bytes(value)) if !value.is_empty() => {
+                let value = string(value, "id")?;
+                id = Some(value.parse::<Uuid>().map_err(|_| invalid(format!("invalid id {value}")))?);
+            }
+            (2, Value::Bytes(value)) => name = string(value, "name")?,
+            (3, Value::Bytes(value)) => service_type = string(value, "service_type")?,
+            (4, Value::Bytes(value)) if !value.is_empty() => {
+                let value = string(value, "address")?;
+                address = Some(value.parse::<IpAddr>().map_err(|_| invalid(format!("invalid address {value}")))?);
+            }
+            (5, Value::Varint(value)) => port = value,
+            (6, Value::Bytes(entry)) => {
+                let (mut key, mut value) = (String::new(), String::new());
+                for (field, part) in fields(entry)? {
+                    match (field, part) {
+                        (1, Value::Bytes(part)) => key = string(part, "attribute key")?,
+                        (2, Value::Bytes(part)) => value = string(part, "attribute value")?,
+                        _ => {}
+                    }
+                }
+                attributes.push((key, value));
+            }
+            (7, Value::Bytes(value)) if !value.is_empty() => host = Some(string(value, "host")?),
+            (8, Value::Bytes(value)) if !value.is_empty() => {
+                let value = string(value, "protocol")?;
+                protocol = [ProtocolType::Mdns, ProtocolType::DnsSd, ProtocolType::Upnp]
+                    .into_iter()
+                    .find(|protocol| protocol.to_string().eq_ignore_ascii_case(&value))
+                    .ok_or_else(|| invalid(format!("unknown protocol {value}")))?;
+            }
+            _ => {}
+        }
+    }
+    let port = u16::try_from(port).map_err(|_| invalid(format!("invalid port {port}")))?;
+
+    let attributes = attributes.iter().map(|(key, value)| (key.as_str(), value.as_str())).collect();
+    let mut service = ServiceInfo::new(name, service_type, port, Some(attributes))
+        .map_err(|e| invalid(e.to_string()))?
+        .with_protocol_type(protocol);
+    if let Some(id) = id {
+        service.id = id;
+    }
+    if let Some(address) = address {
+        service = service.with_address(address);
+    }
+    if let Some(host) = host {
+        service = service.with_host(host);
+    }
+    Ok(service)
+}
+
+/// A decoded protobuf field value; fixed-width values are skipped
+enum Value {
+    Varint(u64),
+    Bytes(Bytes),
+}
+
+fn fields(mut message: Bytes) -> Result<Vec<(u64, Value)>, Status> {
+    let malformed = || invalid("malformed message");
+    let mut fields = Vec::new();
+    while message.has_remaining() {
+        let key = get_varint(&mut message).ok_or_else(malformed)?;
+        let skip = match key & 7 {
+            0 => {
+                fields.push((key >> 3, Value::Varint(get_varint(&mut message).ok_or_else(malformed)?)));
+                continue;
+            }
+            2 => {
+                let len = get_varint(&mut message).ok_or_else(malformed)? as usize;
+                if message.remaining() < len {
+                    return Err(malformed());
+                }
+                fields.push((key >> 3, Value::Bytes(message.split_to(len))));
+                continue;
+            }
+            1 => 8,
+            5 => 4,
+            _ => return Err(malformed()),
+        };
+        if message.remaining() < skip {
+            return Err(malformed());
+        }
+        message.advance(skip);
+    }
+    Ok(fields)
+}
+
+fn string(value: Bytes, field: &str) -> Result<String, Status> {
+    String::from_utf8(value.to_vec()).map_err(|_| invalid(format!("{field} is not UTF-8")))
+}
+
+fn put_key(buf: &mut BytesMut, field: u64, wire_type: u64) {
+    put_varint(buf, field << 3 | wire_type);
+}
+
+fn put_string(buf: &mut BytesMut, field: u64, value: &str) {
+    if !value.is_empty() {
+        put_message(buf, field, value.as_bytes());
+    }
+}
+
+fn put_message(buf: &mut BytesMut, field: u64, value: &[u8]) {
+    put_key(buf, field, 2);
+    put_varint(buf, value.len() as u64);
+    buf.put_slice(value);
+}
+
+fn frame(message: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0); // not compressed
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(message);
+    frame.freeze()
+}
+
+fn unframe(mut frame: Bytes) -> Result<Bytes, Status> {
+    if frame.remaining() < 5 {
+        return Err(invalid("missing message"));
+    }
+    if frame.get_u8() != 0 {
+        return Err((UNIMPLEMENTED, "compressed messages are not supported".to_string()));
+    }
+    let len = frame.get_u32() as usize;
+    if frame.remaining() < len {
+        return Err(invalid("truncated message"));
+    }
+    Ok(frame.split_to(len))
+}
+
+fn invalid(message: impl Into<String>) -> Status {
+    (INVALID_ARGUMENT, message.into())
+}
+
+fn status(error: &DiscoveryError) -> Status {
+    let code = match error.root() {
+        DiscoveryError::ServiceNotFound(_) => NOT_FOUND,
+        DiscoveryError::InvalidServiceInfo { .. } | DiscoveryError::Configuration(_) => INVALID_ARGUMENT,
+        DiscoveryError::Conflict(_) => ALREADY_EXISTS,
+        DiscoveryError::RateLimited(_) => RESOURCE_EXHAUSTED,
+        _ => INTERNAL,
+    };
+    (code, error.to_string())
+}
+
+fn trailers(code: u32, message: &str) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", code.into());
+    // Messages are sent as-is; ones that are not valid header values are dropped
+    if !message.is_empty()
+        && let Ok(message) = message.parse()
+    {
+        trailers.insert("grpc-message", message);
+    }
+    trailers
+}
+
+/// Answer with a status only ("Trailers-Only")
+fn status_response((code, message): Status) -> Response<Body> {
+    grpc_response(Full::new(Bytes::new()).boxed_unsync(), trailers(code, &message))
+}
+
+fn grpc_response(body: Body, headers: HeaderMap) -> Response<Body> {
+    let mut response = Response::new(body);
+    response.headers_mut().extend(headers);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/grpc"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::tests::gateway;
+    use hyper::{client::conn::http2, Method};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::net::SocketAddr;
+    use tokio::net::TcpStream;
+
+    /// Make a unary call, returning the reply message or the gRPC status
+    async fn call(addr: SocketAddr, method: &str, message: &[u8]) -> Result<Bytes, (String, String)> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{addr}{SERVICE_PATH}{method}"))
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .body(Full::new(frame(message)))
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        let mut headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap();
+        if let Some(trailers) = body.trailers() {
+            headers.extend(trailers.clone());
+        }
+        let header = |name| headers.get(name).map(|value: &header::HeaderValue| value.to_str().unwrap().to_string());
+        match header("grpc-status").as_deref() {
+            Some("0") => Ok(unframe(body.to_bytes()).unwrap()),
+            _ => Err((header("grpc-status").unwrap_or_default(), header("grpc-message").unwrap_or_default())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_api() {
+        let gateway = gateway().await;
+        let addr = gateway.local_addr();
+
+        let mut request = BytesMut::new();
+        put_string(&mut request, 1, "_ipp._tcp");
+        let reply = call(addr, "ListServices", &request).await.unwrap();
+        let services: Vec<_> = fields(reply)
+            .unwrap()
+            .into_iter()
+            .map(|(field, value)| match (field, value) {
+                (1, Value::Bytes(service)) => decode_service(service).unwrap(),
+                _ => panic!("unexpected field {field}"),
+            })
+            .collect();
+        assert_eq!(services.len(), 1);
+        assert_eq!((services[0].name.as_str(), services[0].port), ("Printer", 631));
+        assert_eq!(services[0].address.to_string(), "192.168.1.20");
+        assert_eq!(services[0].get_attribute("rp").map(String::as_str), Some("ipp/print"));
+
+        let service = ServiceInfo::new("grpc client", "_http._tcp", 8080, Some(vec![("path", "/")]))
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        let registered = decode_service(call(addr, "Register", &encode_service(&service)).await.unwrap()).unwrap();
+        assert_eq!(registered.id, service.id);
+        assert_eq!(registered.get_attribute("path").map(String::as_str), Some("/"));
+        let mut request = BytesMut::new();
+        put_string(&mut request, 1, &registered.id.to_string());
+        assert!(call(addr, "Unregister", &request).await.is_ok());
+        assert_eq!(call(addr, "Unregister", &request).await.unwrap_err().0, NOT_FOUND.to_string());
+        assert_eq!(call(addr, "Resolve", &[]).await.unwrap_err().0, UNIMPLEMENTED.to_string());
+
+        let mut bad_port = encode_service(&service);
+        put_key(&mut bad_port, 5, 0);
+        put_varint(&mut bad_port, 70000);
+        assert_eq!(call(addr, "Register", &bad_port).await.unwrap_err().0, INVALID_ARGUMENT.to_string());
+        gateway.shutdown();
+    }
+}
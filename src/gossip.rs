@@ -0,0 +1,689 @@
+//! Registry exchange between discovery nodes
+//!
+//! Multicast discovery stops at the edge of a network segment. A
+//! [`GossipNode`] runs next to a [`ServiceRegistry`] on each segment and
+//! periodically swaps registry contents with its peers over TCP, so services
+//! found on one segment show up in the registries of the others: in
+//! [`ServiceDiscovery::subscribe`](crate::ServiceDiscovery::subscribe), the
+//! change feed and load balancers.
+//!
+//! Each round, a node connects to every configured peer and both sides send
+//! the services they know. Services a node found or registered itself travel
+//! with a hop count of zero; services it learned by gossip are passed on only
+//! while their hop count is below [`GossipConfig::with_max_hops`], which keeps
+//! a service from circling a ring of peers after it is gone. Learned services
+//! are added as discovered ones with [`GossipConfig::with_entry_ttl`] and
+//! expire unless a later round refreshes them, so removals reach peers within
+//! one TTL.
+//!
+//! Peers authenticate each other with a shared secret: each proves knowledge
+//! of it with an HMAC-SHA256 over fresh nonces from both sides, and every
+//! exchanged service list carries a tag over the same nonces. The traffic is
+//! authenticated but not encrypted. QUIC transport and certificate
+//! authentication are not available yet.
+//!
+//! Requires the `gossip` feature.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    registry::ServiceRegistry,
+    service::{ServiceId, ServiceInfo},
+    types::ProtocolType,
+};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Length of the nonces each side contributes to a session
+const NONCE_LEN: usize = 16;
+
+/// Length of the HMAC-SHA256 tag on service lists
+const TAG_LEN: usize = 32;
+
+/// Settings for a [`GossipNode`]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GossipConfig {
+    /// Address to accept peer connections on
+    listen: SocketAddr,
+    /// Peers to exchange registries with
+    #[serde(default)]
+    peers: Vec<SocketAddr>,
+    /// Time between exchange rounds
+    interval: Duration,
+    /// TTL of services learned from peers
+    entry_ttl: Duration,
+    /// Hops a service travels from the node that found it
+    max_hops: u8,
+    /// Largest message accepted from a peer, in bytes
+    max_message_size: usize,
+    /// Time allowed for one exchange with a peer
+    timeout: Duration,
+    /// Secret shared by all nodes of the mesh; never written out
+    #[serde(skip_serializing, default)]
+    secret: String,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 7946)),
+            peers: Vec::new(),
+            interval: Duration::from_secs(10),
+            entry_ttl: Duration::from_secs(30),
+            max_hops: 1,
+            max_message_size: 4 * 1024 * 1024,
+            timeout: Duration::from_secs(5),
+            secret: String::new(),
+        }
+    }
+}
+
+impl fmt::Debug for GossipConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GossipConfig")
+            .field("listen", &self.listen)
+            .field("peers", &self.peers)
+            .field("interval", &self.interval)
+            .field("entry_ttl", &self.entry_ttl)
+            .field("max_hops", &self.max_hops)
+            .field("max_message_size", &self.max_message_size)
+            .field("timeout", &self.timeout)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+impl GossipConfig {
+    /// Create gossip settings with default values and the mesh's shared secret
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Self {
+            secret: secret.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Accept peer connections on `addr`; port `0` picks a free port
+    pub fn with_listen(mut self, addr: SocketAddr) -> Self {
+        self.listen = addr;
+        self
+    }
+
+    /// Get the address peer connections are accepted on
+    pub fn listen(&self) -> SocketAddr {
+        self.listen
+    }
+
+    /// Exchange registries with `peer` every round
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peers.push(peer);
+        self
+    }
+
+    /// Get the peers exchanged with every round
+    pub fn peers(&self) -> &[SocketAddr] {
+        &self.peers
+    }
+
+    /// Set the time between exchange rounds
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Get the time between exchange rounds
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Set how long services learned from peers live without a refresh
+    pub fn with_entry_ttl(mut self, ttl: Duration) -> Self {
+        self.entry_ttl = ttl;
+        self
+    }
+
+    /// Get the TTL of services learned from peers
+    pub fn entry_ttl(&self) -> Duration {
+        self.entry_ttl
+    }
+
+    /// Set how many hops a service travels from the node that found it
+    ///
+    /// With the default of `1`, services reach direct peers only, so every
+    /// node should list every other one. Larger values let services cross
+    /// chains of peers.
+    pub fn with_max_hops(mut self, hops: u8) -> Self {
+        self.max_hops = hops;
+        self
+    }
+
+    /// Get how many hops a service travels
+    pub fn max_hops(&self) -> u8 {
+        self.max_hops
+    }
+
+    /// Set the largest message accepted from a peer, in bytes
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Get the largest message accepted from a peer
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Set the time allowed for one exchange with a peer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Get the time allowed for one exchange with a peer
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Validate the settings
+    pub fn validate(&self) -> Result<()> {
+        if self.secret.is_empty() {
+            return Err(DiscoveryError::configuration("Gossip needs a shared secret"));
+        }
+        if self.interval.is_zero() {
+            return Err(DiscoveryError::configuration("Gossip interval must be greater than zero"));
+        }
+        if self.entry_ttl < self.interval {
+            return Err(DiscoveryError::configuration(
+                "Gossip entry TTL must be at least one interval",
+            ));
+        }
+        if self.max_hops == 0 {
+            return Err(DiscoveryError::configuration("Gossip max hops must be at least 1"));
+        }
+        if self.max_message_size == 0 {
+            return Err(DiscoveryError::configuration("Gossip max message size must be greater than zero"));
+        }
+        Ok(())
+    }
+}
+
+/// A service as sent to a peer
+#[derive(Debug, Serialize, Deserialize)]
+struct GossipEntry {
+    service: ServiceInfo,
+    protocol: ProtocolType,
+    /// Peers the service passed through before reaching the receiver
+    hops: u8,
+}
+
+/// Messages of the exchange, in the order they are sent
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    /// Connecting node introduces itself
+    Hello { node: Uuid, nonce: String },
+    /// Accepting node proves it knows the secret
+    Challenge { node: Uuid, nonce: String, proof: String },
+    /// Connecting node proves it knows the secret
+    Proof { proof: String },
+    /// Services the sender knows, in a tagged frame
+    Entries { entries: Vec<GossipEntry> },
+}
+
+/// Which side of a session sent a message
+#[derive(Debug, Clone, Copy)]
+enum Side {
+    Connecting,
+    Accepting,
+}
+
+impl Side {
+    fn label(self) -> &'static [u8] {
+        match self {
+            Side::Connecting => b"connecting",
+            Side::Accepting => b"accepting",
+        }
+    }
+}
+
+/// Nonces of one exchange, binding proofs and tags to it
+struct Session<'a> {
+    key: &'a hmac::Key,
+    connecting: [u8; NONCE_LEN],
+    accepting: [u8; NONCE_LEN],
+}
+
+impl Session<'_> {
+    fn tag(&self, side: Side, payload: &[u8]) -> hmac::Tag {
+        let mut context = hmac::Context::with_key(self.key);
+        context.update(side.label());
+        context.update(&self.connecting);
+        context.update(&self.accepting);
+        context.update(payload);
+        context.sign()
+    }
+
+    fn proof(&self, side: Side) -> String {
+        hex::encode(self.tag(side, b"proof"))
+    }
+
+    fn check_proof(&self, side: Side, proof: &str) -> Result<()> {
+        let proof = hex::decode(proof).map_err(|_| DiscoveryError::security("Malformed gossip proof"))?;
+        self.verify(side, b"proof", &proof)
+    }
+
+    fn verify(&self, side: Side, payload: &[u8], tag: &[u8]) -> Result<()> {
+        let mut context = Vec::with_capacity(payload.len() + 2 * NONCE_LEN + 16);
+        context.extend_from_slice(side.label());
+        context.extend_from_slice(&self.connecting);
+        context.extend_from_slice(&self.accepting);
+        context.extend_from_slice(payload);
+        hmac::verify(self.key, &context, tag)
+            .map_err(|_| DiscoveryError::security("Gossip peer does not know the shared secret"))
+    }
+
+    /// Append a tag to a service list
+    fn seal(&self, side: Side, mut payload: Vec<u8>) -> Vec<u8> {
+        let tag = self.tag(side, &payload);
+        payload.extend_from_slice(tag.as_ref());
+        payload
+    }
+
+    /// Check and strip the tag of a service list
+    fn open(&self, side: Side, mut frame: Vec<u8>) -> Result<Vec<u8>> {
+        if frame.len() < TAG_LEN {
+            return Err(DiscoveryError::security("Gossip message is missing its tag"));
+        }
+        let tag = frame.split_off(frame.len() - TAG_LEN);
+        self.verify(side, &frame, &tag)?;
+        Ok(frame)
+    }
+}
+
+/// Node exchanging a registry with peers
+pub struct GossipNode {
+    config: GossipConfig,
+    registry: Arc<ServiceRegistry>,
+    node_id: Uuid,
+    key: hmac::Key,
+    random: SystemRandom,
+    /// Services learned from peers, with their hop counts
+    learned: Mutex<HashMap<ServiceId, u8>>,
+}
+
+impl fmt::Debug for GossipNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GossipNode")
+            .field("config", &self.config)
+            .field("node_id", &self.node_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GossipNode {
+    /// Exchange the contents of `registry` with the peers in `config`
+    pub fn new(config: GossipConfig, registry: Arc<ServiceRegistry>) -> Result<Self> {
+        config.validate()?;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, config.secret.as_bytes());
+        Ok(Self {
+            config,
+            registry,
+            node_id: Uuid::new_v4(),
+            key,
+            random: SystemRandom::new(),
+            learned: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Identifier this node introduces itself with
+    pub fn node_id(&self) -> Uuid {
+        self.node_id
+    }
+
+    /// Listen for peers and start exchanging with them every interval
+    pub async fn bind(self) -> Result<GossipHandle> {
+        let listener = TcpListener::bind(self.config.listen).await?;
+        let local_addr = listener.local_addr()?;
+        info!("Gossip node {} listening on {}", self.node_id, local_addr);
+
+        let node = Arc::new(self);
+        let accepting = node.clone();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Gossip node failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let node = accepting.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(node.config.timeout, node.accept(stream)).await {
+                        Ok(Ok(learned)) => debug!("Learned {} services from {}", learned, peer),
+                        Ok(Err(e)) => warn!("Gossip exchange with {} failed: {}", peer, e),
+                        Err(_) => warn!("Gossip exchange with {} timed out", peer),
+                    }
+                });
+            }
+        });
+
+        let syncing = node.clone();
+        let round_task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(syncing.config.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                syncing.sync().await;
+            }
+        });
+
+        Ok(GossipHandle {
+            local_addr,
+            node,
+            tasks: vec![accept_task, round_task],
+        })
+    }
+
+    /// Exchange with every peer once, returning how many services were learned
+    ///
+    /// Failed exchanges are logged and skipped.
+    pub async fn sync(&self) -> usize {
+        let mut learned = 0;
+        for &peer in &self.config.peers {
+            match self.exchange(peer).await {
+                Ok(count) => learned += count,
+                Err(e) => warn!("Gossip exchange with {} failed: {}", peer, e),
+            }
+        }
+        learned
+    }
+
+    /// Exchange with `peer`, returning how many services were learned
+    pub async fn exchange(&self, peer: SocketAddr) -> Result<usize> {
+        tokio::time::timeout(self.config.timeout, async {
+            let stream = TcpStream::connect(peer).await?;
+            self.connect(stream).await
+        })
+        .await
+        .map_err(|_| DiscoveryError::timeout(format!("Gossip exchange with {peer} timed out")))?
+    }
+
+    /// Connecting side of an exchange
+    async fn connect(&self, mut stream: TcpStream) -> Result<usize> {
+        let connecting = self.nonce()?;
+        self.send(&mut stream, &Message::Hello { node: self.node_id, nonce: hex::encode(connecting) })
+            .await?;
+
+        let Message::Challenge { node, nonce, proof } = self.receive(&mut stream).await? else {
+            return Err(DiscoveryError::protocol("Expected a gossip challenge"));
+        };
+        if node == self.node_id {
+            return Err(DiscoveryError::configuration("Gossip node is listed as its own peer"));
+        }
+        let session = Session { key: &self.key, connecting, accepting: parse_nonce(&nonce)? };
+        session.check_proof(Side::Accepting, &proof)?;
+        self.send(&mut stream, &Message::Proof { proof: session.proof(Side::Connecting) })
+            .await?;
+
+        self.send_entries(&mut stream, &session, Side::Connecting).await?;
+        let entries = self.receive_entries(&mut stream, &session, Side::Accepting).await?;
+        Ok(self.absorb(entries).await)
+    }
+
+    /// Accepting side of an exchange
+    async fn accept(&self, mut stream: TcpStream) -> Result<usize> {
+        let Message::Hello { node, nonce } = self.receive(&mut stream).await? else {
+            return Err(DiscoveryError::protocol("Expected a gossip hello"));
+        };
+        if node == self.node_id {
+            return Err(DiscoveryError::configuration("Gossip node is listed as its own peer"));
+        }
+        let accepting = self.nonce()?;
+        let session = Session { key: &self.key, connecting: parse_nonce(&nonce)?, accepting };
+        let challenge = Message::Challenge {
+            node: self.node_id,
+            nonce: hex::encode(accepting),
+            proof: session.proof(Side::Accepting),
+        };
+        self.send(&mut stream, &challenge).await?;
+
+        let Message::Proof { proof } = self.receive(&mut stream).await? else {
+            return Err(DiscoveryError::protocol("Expected a gossip proof"));
+        };
+        session.check_proof(Side::Connecting, &proof)?;
+
+        let entries = self.receive_entries(&mut stream, &session, Side::Connecting).await?;
+        let learned = self.absorb(entries).await;
+        self.send_entries(&mut stream, &session, Side::Accepting).await?;
+        Ok(learned)
+    }
+
+    fn nonce(&self) -> Result<[u8; NONCE_LEN]> {
+        let mut nonce = [0; NONCE_LEN];
+        self.random
+            .fill(&mut nonce)
+            .map_err(|_| DiscoveryError::security("Failed to generate a gossip nonce"))?;
+        Ok(nonce)
+    }
+
+    async fn send(&self, stream: &mut TcpStream, message: &Message) -> Result<()> {
+        let payload = serde_json::to_vec(message)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Failed to encode gossip message: {e}")))?;
+        write_frame(stream, &payload).await
+    }
+
+    async fn receive(&self, stream: &mut TcpStream) -> Result<Message> {
+        let frame = read_frame(stream, self.config.max_message_size).await?;
+        decode(&frame)
+    }
+
+    async fn send_entries(&self, stream: &mut TcpStream, session: &Session<'_>, side: Side) -> Result<()> {
+        let message = Message::Entries { entries: self.outgoing().await };
+        let payload = serde_json::to_vec(&message)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Failed to encode gossip message: {e}")))?;
+        write_frame(stream, &session.seal(side, payload)).await
+    }
+
+    async fn receive_entries(&self, stream: &mut TcpStream, session: &Session<'_>, side: Side) -> Result<Vec<GossipEntry>> {
+        let frame = read_frame(stream, self.config.max_message_size).await?;
+        match decode(&session.open(side, frame)?)? {
+            Message::Entries { entries } => Ok(entries),
+            _ => Err(DiscoveryError::protocol("Expected gossip entries")),
+        }
+    }
+
+    /// Services to send to a peer
+    async fn outgoing(&self) -> Vec<GossipEntry> {
+        let snapshot = self.registry.export_snapshot().await;
+        let mut learned = self.learned.lock().unwrap_or_else(|e| e.into_inner());
+        // Forget learned services that have since left the registry
+        let present: HashSet<ServiceId> = snapshot.entries.iter().map(|entry| entry.service.service_id()).collect();
+        learned.retain(|id, _| present.contains(id));
+
+        snapshot
+            .entries
+            .into_iter()
+            .filter_map(|entry| {
+                let hops = learned.get(&entry.service.service_id()).copied().unwrap_or(0);
+                (hops < self.config.max_hops).then_some(GossipEntry {
+                    service: entry.service,
+                    protocol: entry.protocol,
+                    hops,
+                })
+            })
+            .collect()
+    }
+
+    /// Add services received from a peer, returning how many were taken
+    async fn absorb(&self, entries: Vec<GossipEntry>) -> usize {
+        let mut taken = 0;
+        for entry in entries {
+            let hops = entry.hops.saturating_add(1);
+            let id = entry.service.service_id();
+            let known_hops = self.learned.lock().unwrap_or_else(|e| e.into_inner()).get(&id).copied();
+            if self.registry.contains_service(&id).await {
+                match known_hops {
+                    // Found or registered here; this node's view wins
+                    None => continue,
+                    // Known over a shorter path
+                    Some(known) if known < hops => continue,
+                    Some(_) => {}
+                }
+            }
+            if let Err(e) = self
+                .registry
+                .add_discovered_service(entry.service, entry.protocol, Some(self.config.entry_ttl))
+                .await
+            {
+                debug!("Not adding {} from a gossip peer: {}", id, e);
+                continue;
+            }
+            self.learned.lock().unwrap_or_else(|e| e.into_inner()).insert(id, hops);
+            taken += 1;
+        }
+        taken
+    }
+}
+
+/// A running [`GossipNode`]
+#[derive(Debug)]
+pub struct GossipHandle {
+    local_addr: SocketAddr,
+    node: Arc<GossipNode>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl GossipHandle {
+    /// Address the node is accepting peer connections on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The running node
+    pub fn node(&self) -> &GossipNode {
+        &self.node
+    }
+
+    /// Stop exchanging and accepting connections
+    ///
+    /// Services learned from peers stay in the registry until they expire.
+    pub fn shutdown(self) {
+        for task in self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn parse_nonce(nonce: &str) -> Result<[u8; NONCE_LEN]> {
+    hex::decode(nonce)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .ok_or_else(|| DiscoveryError::protocol("Malformed gossip nonce"))
+}
+
+fn decode(payload: &[u8]) -> Result<Message> {
+    serde_json::from_slice(payload)
+        .map_err(|e| DiscoveryError::invalid_data(format!("Malformed gossip message: {e}")))
+}
+
+async fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| DiscoveryError::invalid_data("Gossip message too large"))?;
+    stream.write_u32(len).await?;
+    stream.write_all(payload).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame(stream: &mut TcpStream, max_size: usize) -> Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > max_size {
+        return Err(DiscoveryError::invalid_data(format!(
+            "Gossip message of {len} bytes exceeds the limit of {max_size}"
+        )));
+    }
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).await?;
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::ServiceFilter;
+
+    fn config(secret: &str) -> GossipConfig {
+        GossipConfig::new(secret)
+            .with_listen("127.0.0.1:0".parse().unwrap())
+            .with_interval(Duration::from_secs(3600))
+            .with_entry_ttl(Duration::from_secs(7200))
+    }
+
+    async fn printer(registry: &ServiceRegistry, name: &str) {
+        let service = ServiceInfo::new(name, "_ipp._tcp", 631, None).unwrap();
+        registry.register_local_service(service, ProtocolType::Mdns).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peers_exchange_registries() {
+        let (east, west) = (Arc::new(ServiceRegistry::new()), Arc::new(ServiceRegistry::new()));
+        printer(&east, "East Printer").await;
+        printer(&west, "West Printer").await;
+
+        let east_node = GossipNode::new(config("mesh secret"), east.clone()).unwrap().bind().await.unwrap();
+        let west_node = GossipNode::new(config("mesh secret").with_peer(east_node.local_addr()), west.clone())
+            .unwrap()
+            .bind()
+            .await
+            .unwrap();
+        // Both sides learn from a single exchange
+        assert_eq!(west_node.node().exchange(east_node.local_addr()).await.unwrap(), 1);
+
+        for registry in [&east, &west] {
+            let names: HashSet<String> = registry
+                .find_services(&ServiceFilter::new())
+                .await
+                .into_iter()
+                .map(|service| service.name().to_string())
+                .collect();
+            assert_eq!(names, HashSet::from(["East Printer".to_string(), "West Printer".to_string()]));
+        }
+        // Local registrations are not overwritten by what peers send back
+        assert!(west.is_local_service(&ServiceInfo::new("West Printer", "_ipp._tcp", 631, None).unwrap().service_id()).await);
+        assert!(!west.is_local_service(&ServiceInfo::new("East Printer", "_ipp._tcp", 631, None).unwrap().service_id()).await);
+
+        // With one hop, west does not pass east's printer on
+        assert_eq!(west_node.node().outgoing().await.len(), 1);
+        east_node.shutdown();
+        west_node.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_secret_is_rejected() {
+        let (east, west) = (Arc::new(ServiceRegistry::new()), Arc::new(ServiceRegistry::new()));
+        printer(&east, "East Printer").await;
+        let east_node = GossipNode::new(config("mesh secret"), east.clone()).unwrap().bind().await.unwrap();
+        let intruder = GossipNode::new(config("guess"), west.clone()).unwrap();
+
+        let error = intruder.exchange(east_node.local_addr()).await.unwrap_err();
+        assert!(matches!(error, DiscoveryError::Security(_)), "{error}");
+        assert!(west.find_services(&ServiceFilter::new()).await.is_empty());
+        assert!(GossipNode::new(GossipConfig::default(), west).is_err());
+    }
+}
@@ -1,42 +1,44 @@
+//! Health monitoring for protocols and verified services
+
 use crate::{
-    config::DiscoveryConfig,
+    clock::{self, Clock},
     error::{DiscoveryError, Result},
     protocols::ProtocolManager,
-    security::tsig::TsigKeyManager,
-    metrics,
-};
-use hyper::{
-    service::{make_service_fn, service_fn},
-    Body, Request, Response, Server,
+    registry::ServiceRegistry,
+    resolver::ResolutionCache,
+    service::{ServiceEvent, ServiceInfo},
+    verification::{ConnectivityVerifier, ServiceVerifier, VerificationResult, HEALTH_PATH_ATTRIBUTE},
 };
-use metrics::{counter, gauge, histogram};
-use serde::Serialize;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{body::Incoming, server::conn::http1, service::service_fn, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    net::SocketAddr,
+    convert::Infallible,
+    fmt,
+    net::{Ipv4Addr, SocketAddr},
     sync::Arc,
     time::{Duration, Instant},
 };
-use tokio::sync::RwLock;
-use tower::{ServiceBuilder, ServiceExt};
-use tower_http::{
-    trace::TraceLayer,
-    compression::CompressionLayer,
-    cors::CorsLayer,
-};
-use tracing::{debug, error, info, warn};
+use tokio::{net::TcpListener, sync::RwLock, task::JoinHandle};
+use tracing::{debug, info, warn};
 
 /// Health status of a component
-#[derive(Debug, Clone, Serialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum HealthStatus {
+    /// Component is working normally
     Healthy,
+    /// Component has recent failures but is still considered usable
     Degraded,
+    /// Component failed too many consecutive checks
     Unhealthy,
 }
 
 /// Health check configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HealthConfig {
     /// Check interval
     pub interval: Duration,
@@ -46,6 +48,21 @@ pub struct HealthConfig {
     pub failure_threshold: u32,
     /// Success threshold to restore health
     pub success_threshold: u32,
+    /// Address the health HTTP server listens on
+    #[serde(default = "default_bind_address")]
+    pub bind_address: SocketAddr,
+    /// Address services by their advertised hostname rather than IP in
+    /// verification requests, for virtual hosts and certificate matching
+    #[serde(default = "default_prefer_hostnames")]
+    pub prefer_hostnames: bool,
+}
+
+fn default_bind_address() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, 9090).into()
+}
+
+fn default_prefer_hostnames() -> bool {
+    true
 }
 
 impl Default for HealthConfig {
@@ -55,7 +72,86 @@ impl Default for HealthConfig {
             timeout: Duration::from_secs(5),
             failure_threshold: 3,
             success_threshold: 2,
+            bind_address: default_bind_address(),
+            prefer_hostnames: default_prefer_hostnames(),
+        }
+    }
+}
+
+impl HealthConfig {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> HealthConfigBuilder {
+        HealthConfigBuilder::default()
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        if self.interval.is_zero() {
+            return Err(DiscoveryError::configuration("Health check interval cannot be zero"));
+        }
+        if self.timeout.is_zero() {
+            return Err(DiscoveryError::configuration("Health check timeout cannot be zero"));
+        }
+        if self.failure_threshold == 0 || self.success_threshold == 0 {
+            return Err(DiscoveryError::configuration("Health check thresholds must be at least 1"));
         }
+        Ok(())
+    }
+}
+
+/// Builder for [`HealthConfig`]
+#[derive(Debug, Clone, Default)]
+pub struct HealthConfigBuilder {
+    config: HealthConfig,
+}
+
+impl HealthConfigBuilder {
+    /// Set the check interval
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.config.interval = interval;
+        self
+    }
+
+    /// Set the check timeout
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Set how many consecutive failures mark a component unhealthy
+    pub fn failure_threshold(mut self, threshold: u32) -> Self {
+        self.config.failure_threshold = threshold;
+        self
+    }
+
+    /// Set how many consecutive successes restore a component
+    pub fn success_threshold(mut self, threshold: u32) -> Self {
+        self.config.success_threshold = threshold;
+        self
+    }
+
+    /// Set whether verification addresses services by hostname
+    pub fn prefer_hostnames(mut self, prefer: bool) -> Self {
+        self.config.prefer_hostnames = prefer;
+        self
+    }
+
+    /// Set the port of the health HTTP server, keeping its address
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.bind_address.set_port(port);
+        self
+    }
+
+    /// Set the address of the health HTTP server
+    pub fn bind_address(mut self, addr: SocketAddr) -> Self {
+        self.config.bind_address = addr;
+        self
+    }
+
+    /// Validate and return the configuration
+    pub fn build(self) -> Result<HealthConfig> {
+        self.config.validate()?;
+        Ok(self.config)
     }
 }
 
@@ -72,11 +168,11 @@ pub struct HealthCheck {
 }
 
 impl HealthCheck {
-    fn new() -> Self {
+    fn new(at: chrono::DateTime<chrono::Utc>) -> Self {
         Self {
             status: HealthStatus::Healthy,
             message: None,
-            last_check: chrono::Utc::now(),
+            last_check: at,
             consecutive_failures: 0,
             consecutive_successes: 0,
             last_failure: None,
@@ -84,11 +180,26 @@ impl HealthCheck {
         }
     }
 
-    fn record_success(&mut self, latency: Duration, config: &HealthConfig) {
+    /// Current status
+    pub fn status(&self) -> HealthStatus {
+        self.status
+    }
+
+    /// Message of the last failure, if any
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Latency of the last successful check
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    fn record_success(&mut self, latency: Duration, config: &HealthConfig, at: chrono::DateTime<chrono::Utc>) {
         self.consecutive_successes += 1;
         self.consecutive_failures = 0;
         self.latency = Some(latency);
-        self.last_check = chrono::Utc::now();
+        self.last_check = at;
 
         if self.consecutive_successes >= config.success_threshold {
             if self.status != HealthStatus::Healthy {
@@ -97,27 +208,31 @@ impl HealthCheck {
             self.status = HealthStatus::Healthy;
         }
 
-        histogram!("health_check_latency", latency);
-        counter!("health_check_success", 1);
+        #[cfg(feature = "metrics")]
+        {
+            metrics::histogram!("health_check_latency").record(latency.as_secs_f64());
+            metrics::counter!("health_check_success").increment(1);
+        }
     }
 
-    fn record_failure(&mut self, message: String, config: &HealthConfig) {
+    fn record_failure(&mut self, message: String, config: &HealthConfig, at: chrono::DateTime<chrono::Utc>) {
         self.consecutive_failures += 1;
         self.consecutive_successes = 0;
-        self.message = Some(message);
-        self.last_check = chrono::Utc::now();
-        self.last_failure = Some(chrono::Utc::now());
+        self.last_check = at;
+        self.last_failure = Some(at);
 
         if self.consecutive_failures >= config.failure_threshold {
-            if self.status == HealthStatus::Healthy {
-                warn!("Component marked as unhealthy: {}", self.message.as_ref().unwrap());
+            if self.status != HealthStatus::Unhealthy {
+                warn!("Component marked as unhealthy: {}", message);
             }
             self.status = HealthStatus::Unhealthy;
         } else if self.status == HealthStatus::Healthy {
             self.status = HealthStatus::Degraded;
         }
+        self.message = Some(message);
 
-        counter!("health_check_failure", 1);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("health_check_failure").increment(1);
     }
 }
 
@@ -126,36 +241,42 @@ impl HealthCheck {
 pub struct HealthReport {
     status: HealthStatus,
     components: HashMap<String, HealthCheck>,
+    services: HashMap<String, HealthCheck>,
     uptime: Duration,
     version: String,
     start_time: chrono::DateTime<chrono::Utc>,
-    memory_usage: Option<u64>,
-    thread_count: Option<u64>,
 }
 
 impl HealthReport {
-    fn new() -> Self {
+    fn new(start_time: chrono::DateTime<chrono::Utc>) -> Self {
         Self {
             status: HealthStatus::Healthy,
             components: HashMap::new(),
+            services: HashMap::new(),
             uptime: Duration::from_secs(0),
             version: env!("CARGO_PKG_VERSION").to_string(),
-            start_time: chrono::Utc::now(),
-            memory_usage: None,
-            thread_count: None,
+            start_time,
         }
     }
 
-    fn update_system_metrics(&mut self) {
-        if let Ok(mem_info) = sys_info::mem_info() {
-            self.memory_usage = Some(mem_info.total - mem_info.avail);
-            gauge!("system_memory_usage", self.memory_usage.unwrap() as f64);
-        }
+    /// Overall status derived from all components
+    pub fn status(&self) -> HealthStatus {
+        self.status
+    }
 
-        if let Ok(proc_stat) = sys_info::proc_stat() {
-            self.thread_count = Some(proc_stat.procs as u64);
-            gauge!("system_thread_count", self.thread_count.unwrap() as f64);
-        }
+    /// Health of internal components such as protocols
+    pub fn components(&self) -> &HashMap<String, HealthCheck> {
+        &self.components
+    }
+
+    /// Health of verified services, keyed by service name
+    pub fn services(&self) -> &HashMap<String, HealthCheck> {
+        &self.services
+    }
+
+    /// Time since the monitor was created
+    pub fn uptime(&self) -> Duration {
+        self.uptime
     }
 
     fn calculate_overall_status(&mut self) {
@@ -170,7 +291,8 @@ impl HealthReport {
             HealthStatus::Healthy
         };
 
-        gauge!("health_status", match self.status {
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("health_status").set(match self.status {
             HealthStatus::Healthy => 2.0,
             HealthStatus::Degraded => 1.0,
             HealthStatus::Unhealthy => 0.0,
@@ -178,223 +300,486 @@ impl HealthReport {
     }
 }
 
+/// How discovered services are checked by [`HealthMonitor::check_services`]
+#[derive(Clone, Default)]
+pub enum HealthProbe {
+    /// Connect over TCP (or probe UDP), and GET the service's advertised
+    /// `health_path` if it has one
+    #[default]
+    Connectivity,
+    /// Connect over TCP and GET this path, expecting a 2xx status
+    Http {
+        /// Request path, e.g. `/health`
+        path: String,
+    },
+    /// Run an application-specific check
+    Custom(Arc<dyn ServiceVerifier>),
+}
+
+impl fmt::Debug for HealthProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connectivity => write!(f, "Connectivity"),
+            Self::Http { path } => f.debug_struct("Http").field("path", path).finish(),
+            Self::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl HealthProbe {
+    pub(crate) async fn check(
+        &self,
+        service: &ServiceInfo,
+        config: &HealthConfig,
+        resolutions: &Arc<ResolutionCache>,
+    ) -> VerificationResult {
+        let timeout = config.timeout;
+        let connectivity = ConnectivityVerifier::from_health_config(config).with_resolution_cache(Arc::clone(resolutions));
+        match self {
+            Self::Connectivity => connectivity.verify(service).await,
+            Self::Http { path } => {
+                let service = service.clone().with_attribute(HEALTH_PATH_ATTRIBUTE, path.clone());
+                connectivity.verify(&service).await
+            }
+            Self::Custom(verifier) => {
+                let start = Instant::now();
+                match tokio::time::timeout(timeout, verifier.verify(service)).await {
+                    Ok(Ok(result)) => result,
+                    Ok(Err(e)) => VerificationResult::failure(start.elapsed(), e.to_string()),
+                    Err(_) => VerificationResult::failure(start.elapsed(), "health check timed out"),
+                }
+            }
+        }
+    }
+}
+
 /// Health monitor service
 pub struct HealthMonitor {
     config: HealthConfig,
+    probe: HealthProbe,
     report: Arc<RwLock<HealthReport>>,
-    protocol_manager: Arc<RwLock<ProtocolManager>>,
-    tsig_manager: Arc<TsigKeyManager>,
+    started: Instant,
+    clock: Arc<dyn Clock>,
 }
 
 impl HealthMonitor {
-    pub fn new(
-        config: HealthConfig,
-        protocol_manager: Arc<RwLock<ProtocolManager>>,
-        tsig_manager: Arc<TsigKeyManager>,
-    ) -> Self {
+    /// Create a new health monitor
+    pub fn new(config: HealthConfig) -> Self {
+        let clock = clock::system();
         Self {
             config,
-            report: Arc::new(RwLock::new(HealthReport::new())),
-            protocol_manager,
-            tsig_manager,
+            probe: HealthProbe::default(),
+            report: Arc::new(RwLock::new(HealthReport::new(clock.system_time().into()))),
+            started: clock.now(),
+            clock,
         }
     }
 
-    /// Start the health monitoring service
-    pub async fn start(self: Arc<Self>, addr: SocketAddr) -> Result<()> {
-        // Start periodic health checks
-        self.start_health_checks().await;
+    /// Measure uptime and timestamp checks by `clock` instead of tokio's clock
+    ///
+    /// Uptime starts over from the clock's current time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started = clock.now();
+        self.report = Arc::new(RwLock::new(HealthReport::new(clock.system_time().into())));
+        self.clock = clock;
+        self
+    }
 
-        // Start HTTP health check endpoint
-        self.start_http_server(addr).await?;
+    fn uptime(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.started)
+    }
 
-        info!("Health monitoring service started on {}", addr);
-        Ok(())
+    fn wall_time(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.system_time().into()
     }
 
-    async fn start_health_checks(self: Arc<Self>) {
-        let config = self.config.clone();
-        let report = self.report.clone();
-        let protocol_manager = self.protocol_manager.clone();
-        let tsig_manager = self.tsig_manager.clone();
+    /// Check discovered services with `probe`
+    pub fn with_probe(mut self, probe: HealthProbe) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Get the monitor configuration
+    pub fn config(&self) -> &HealthConfig {
+        &self.config
+    }
 
+    /// Start periodic protocol health checks against the given manager
+    pub fn start_health_checks(self: Arc<Self>, protocol_manager: ProtocolManager) -> JoinHandle<()> {
         tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.interval);
             loop {
-                let start = Instant::now();
-                let mut report = report.write().await;
-
-                // Update system metrics
-                report.update_system_metrics();
-                report.uptime = start.duration_since(report.start_time.into());
-
-                // Check protocol manager
-                Self::check_protocol_manager(&mut report, &protocol_manager, &config).await;
+                ticker.tick().await;
+                self.check_protocols(&protocol_manager).await;
+            }
+        })
+    }
 
-                // Check security components
-                Self::check_security_components(&mut report, &tsig_manager, &config).await;
+    /// Run one round of protocol health checks
+    pub async fn check_protocols(&self, protocol_manager: &ProtocolManager) {
+        let start = Instant::now();
+        let statuses = protocol_manager.health_check().await;
+        let latency = start.elapsed();
+
+        let mut report = self.report.write().await;
+        report.uptime = self.uptime();
+        let now = self.wall_time();
+
+        for (protocol, health) in statuses {
+            let check = report
+                .components
+                .entry(format!("protocol_{protocol}"))
+                .or_insert_with(|| HealthCheck::new(now));
+            if health.is_healthy() {
+                check.record_success(latency, &self.config, now);
+            } else if !health.available {
+                check.record_failure(format!("{protocol} is not available"), &self.config, now);
+            } else {
+                check.record_failure(format!("{protocol} circuit breaker is {}", health.circuit), &self.config, now);
+            }
+        }
 
-                // Calculate overall status
-                report.calculate_overall_status();
+        report.calculate_overall_status();
+    }
 
-                histogram!("health_check_duration", start.elapsed());
-                tokio::time::sleep(config.interval).await;
+    /// Start periodic checks of the services discovered into `registry`
+    pub fn start_service_checks(self: Arc<Self>, registry: Arc<ServiceRegistry>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.interval);
+            loop {
+                ticker.tick().await;
+                self.check_services(&registry).await;
             }
-        });
+        })
     }
 
-    async fn check_protocol_manager(
-        report: &mut HealthReport,
-        protocol_manager: &Arc<RwLock<ProtocolManager>>,
-        config: &HealthConfig,
-    ) {
-        let start = Instant::now();
-        let manager = protocol_manager.read().await;
-
-        match manager.get_protocol_status().await {
-            Ok(status) => {
-                let check = report.components.entry("protocol_manager".to_string())
-                    .or_insert_with(HealthCheck::new);
-                check.record_success(start.elapsed(), config);
-                
-                // Record protocol-specific metrics
-                for (protocol, is_healthy) in status {
-                    let metric_name = format!("protocol_{}_healthy", protocol);
-                    gauge!(&metric_name, if is_healthy { 1.0 } else { 0.0 });
-                }
+    /// Probe every discovered service in `registry` once
+    ///
+    /// Each service's health is stored in the registry, and a
+    /// [`ServiceEvent::VerificationFailed`] is published whenever a service is
+    /// demoted to degraded or unhealthy.
+    pub async fn check_services(&self, registry: &ServiceRegistry) {
+        let services = registry.get_discovered_services().await;
+        let results = futures::future::join_all(
+            services
+                .iter()
+                .map(|service| self.probe.check(service, &self.config, registry.resolution_cache())),
+        )
+        .await;
+
+        for (service, result) in services.into_iter().zip(results) {
+            let status = self.record_service_result(&service, &result).await;
+            let service_id = service.service_id();
+            if result.healthy {
+                registry.set_latency(&service_id, result.latency).await;
             }
-            Err(e) => {
-                let check = report.components.entry("protocol_manager".to_string())
-                    .or_insert_with(HealthCheck::new);
-                check.record_failure(e.to_string(), config);
+            let Some(previous) = registry.set_health(&service_id, status).await else {
+                continue;
+            };
+            if status != previous && status != HealthStatus::Healthy {
+                warn!("Service {} is now {:?}", service.name(), status);
+                registry.publish(ServiceEvent::verification_failed(service)).await;
             }
         }
     }
 
-    async fn check_security_components(
-        report: &mut HealthReport,
-        tsig_manager: &Arc<TsigKeyManager>,
-        config: &HealthConfig,
-    ) {
-        let start = Instant::now();
-        
-        match tsig_manager.verify_keys().await {
-            Ok(_) => {
-                let check = report.components.entry("security_manager".to_string())
-                    .or_insert_with(HealthCheck::new);
-                check.record_success(start.elapsed(), config);
-            }
-            Err(e) => {
-                let check = report.components.entry("security_manager".to_string())
-                    .or_insert_with(HealthCheck::new);
-                check.record_failure(e.to_string(), config);
-            }
+    /// Record the outcome of a service verification and return the
+    /// service's resulting status
+    pub async fn record_service_result(&self, service: &ServiceInfo, result: &VerificationResult) -> HealthStatus {
+        let mut report = self.report.write().await;
+        report.uptime = self.uptime();
+        let now = self.wall_time();
+
+        let check = report
+            .services
+            .entry(service.name().to_string())
+            .or_insert_with(|| HealthCheck::new(now));
+        if result.healthy {
+            check.record_success(result.latency, &self.config, now);
+        } else {
+            let message = result
+                .detail
+                .clone()
+                .unwrap_or_else(|| format!("{} failed verification", service.name()));
+            check.record_failure(message, &self.config, now);
         }
+        check.status
+    }
+
+    /// Get the health status of a verified service
+    pub async fn service_status(&self, service_name: &str) -> Option<HealthStatus> {
+        self.report.read().await.services.get(service_name).map(|c| c.status)
     }
 
-    async fn start_http_server(self: Arc<Self>, addr: SocketAddr) -> Result<()> {
-        let report = self.report.clone();
-
-        let make_svc = make_service_fn(move |_conn| {
-            let report = report.clone();
-            async move {
-                Ok::<_, hyper::Error>(service_fn(move |_req: Request<Body>| {
-                    let report = report.clone();
-                    async move {
-                        let report = report.read().await;
-                        let json = serde_json::to_string(&*report)
-                            .map_err(|e| DiscoveryError::Internal(e.to_string()))?;
-                        
-                        Ok::<_, DiscoveryError>(Response::builder()
-                            .status(if report.status == HealthStatus::Healthy { 200 } else { 503 })
-                            .header("Content-Type", "application/json")
-                            .body(Body::from(json))
-                            .unwrap())
+    /// Get the current health report
+    pub async fn get_report(&self) -> HealthReport {
+        let mut report = self.report.read().await.clone();
+        report.uptime = self.uptime();
+        report
+    }
+}
+
+/// HTTP server exposing health endpoints
+///
+/// - `/healthz`: liveness, `200` while the process is serving requests
+/// - `/readyz`: readiness, `200` once at least one protocol is available,
+///   `503` otherwise
+/// - `/metrics`: Prometheus exposition, when a metrics handle is attached
+pub struct HealthServer {
+    monitor: Arc<HealthMonitor>,
+    protocol_manager: ProtocolManager,
+    #[cfg(feature = "metrics")]
+    metrics: Option<metrics_exporter_prometheus::PrometheusHandle>,
+}
+
+impl HealthServer {
+    /// Serve health of `monitor` and readiness of the protocols in `protocol_manager`
+    pub fn new(monitor: Arc<HealthMonitor>, protocol_manager: ProtocolManager) -> Self {
+        Self {
+            monitor,
+            protocol_manager,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+
+    /// Serve `/metrics` from a Prometheus recorder's handle
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, handle: metrics_exporter_prometheus::PrometheusHandle) -> Self {
+        self.metrics = Some(handle);
+        self
+    }
+
+    /// Listen on the address from the monitor's [`HealthConfig`]
+    pub async fn serve(self) -> Result<HealthServerHandle> {
+        let addr = self.monitor.config().bind_address;
+        self.bind(addr).await
+    }
+
+    /// Listen on `addr`; port `0` picks a free port
+    pub async fn bind(self, addr: SocketAddr) -> Result<HealthServerHandle> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        info!("Health server listening on {}", local_addr);
+
+        let server = Arc::new(self);
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Health server failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(|request| {
+                        let server = server.clone();
+                        async move { Ok::<_, Infallible>(server.respond(request).await) }
+                    });
+                    if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                        debug!("Health connection from {} failed: {}", peer, e);
                     }
-                }))
+                });
             }
         });
 
-        let server = Server::bind(&addr).serve(make_svc);
-        tokio::spawn(server);
+        Ok(HealthServerHandle { local_addr, task })
+    }
 
-        Ok(())
+    async fn respond(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        if request.method() != Method::GET {
+            return text(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+        }
+
+        match request.uri().path() {
+            "/healthz" => text(StatusCode::OK, "ok"),
+            "/readyz" => {
+                let health = self.protocol_manager.health_check().await;
+                if health.values().any(|protocol| protocol.available) {
+                    text(StatusCode::OK, "ready")
+                } else {
+                    text(StatusCode::SERVICE_UNAVAILABLE, "no protocol available")
+                }
+            }
+            #[cfg(feature = "metrics")]
+            "/metrics" if self.metrics.is_some() => response(
+                StatusCode::OK,
+                "text/plain; version=0.0.4",
+                self.metrics.as_ref().map(|handle| handle.render()).unwrap_or_default(),
+            ),
+            _ => text(StatusCode::NOT_FOUND, "not found"),
+        }
     }
+}
 
-    /// Get the current health report
-    pub async fn get_report(&self) -> HealthReport {
-        self.report.read().await.clone()
+fn response(status: StatusCode, content_type: &str, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    if let Ok(value) = content_type.parse() {
+        response.headers_mut().insert(hyper::header::CONTENT_TYPE, value);
+    }
+    response
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Full<Bytes>> {
+    response(status, "text/plain", body.to_string())
+}
+
+/// A running [`HealthServer`]
+#[derive(Debug)]
+pub struct HealthServerHandle {
+    local_addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl HealthServerHandle {
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections
+    pub fn shutdown(self) {
+        self.task.abort();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use hyper::Client;
-
-    #[tokio::test]
-    async fn test_health_check_server() {
-        let config = HealthConfig::builder()
-            .port(0) // Random port
-            .build();
 
-        let protocol_manager = Arc::new(ProtocolManager::new(&DiscoveryConfig::default()).unwrap());
-        let health_manager = Arc::new(HealthManager::new(
-            config,
-            protocol_manager,
-            None,
-        ));
+    fn result(healthy: bool) -> VerificationResult {
+        VerificationResult {
+            healthy,
+            latency: Duration::from_millis(5),
+            detail: (!healthy).then(|| "connection refused".to_string()),
+        }
+    }
 
-        // Start server
-        let server_handle = tokio::spawn(health_manager.clone().start());
+    #[tokio::test]
+    async fn test_service_status_transitions() {
+        let monitor = HealthMonitor::new(HealthConfig::default());
+        let service = ServiceInfo::new("web", "_http._tcp", 8080, None).unwrap();
 
-        // Wait for server to start
-        tokio::time::sleep(Duration::from_millis(100)).await;
+        monitor.record_service_result(&service, &result(false)).await;
+        assert_eq!(monitor.service_status("web").await, Some(HealthStatus::Degraded));
 
-        // Make request
-        let client = Client::new();
-        let uri = format!("http://127.0.0.1:{}/health", health_manager.config.port);
-        let response = client.get(uri.parse().unwrap()).await.unwrap();
+        monitor.record_service_result(&service, &result(false)).await;
+        monitor.record_service_result(&service, &result(false)).await;
+        assert_eq!(monitor.service_status("web").await, Some(HealthStatus::Unhealthy));
 
-        assert_eq!(response.status(), 200);
+        monitor.record_service_result(&service, &result(true)).await;
+        monitor.record_service_result(&service, &result(true)).await;
+        assert_eq!(monitor.service_status("web").await, Some(HealthStatus::Healthy));
+    }
 
-        // Parse response
-        let body_bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let report: HealthReport = serde_json::from_slice(&body_bytes).unwrap();
+    #[tokio::test]
+    async fn test_uptime_follows_clock() {
+        let clock = crate::clock::ManualClock::new();
+        let monitor = HealthMonitor::new(HealthConfig::default()).with_clock(Arc::new(clock.clone()));
+        assert_eq!(monitor.get_report().await.uptime(), Duration::ZERO);
 
-        assert!(matches!(report.status, HealthStatus::Healthy));
-        assert!(!report.components.is_empty());
+        clock.advance(Duration::from_secs(86_400));
+        assert_eq!(monitor.get_report().await.uptime(), Duration::from_secs(86_400));
+    }
 
-        // Cleanup
-        server_handle.abort();
+    #[test]
+    fn test_config_builder() {
+        let config = HealthConfig::builder()
+            .interval(Duration::from_secs(10))
+            .failure_threshold(5)
+            .port(8081)
+            .build()
+            .unwrap();
+        assert_eq!(config.interval, Duration::from_secs(10));
+        assert_eq!(config.failure_threshold, 5);
+        assert_eq!(config.success_threshold, HealthConfig::default().success_threshold);
+        assert_eq!(config.bind_address, "127.0.0.1:8081".parse().unwrap());
+
+        assert!(HealthConfig::builder().interval(Duration::ZERO).build().is_err());
+        assert!(HealthConfig::builder().success_threshold(0).build().is_err());
     }
 
     #[tokio::test]
-    async fn test_component_health_tracking() {
-        let config = HealthConfig::builder()
-            .check_interval(Duration::from_millis(100))
-            .build();
+    async fn test_protocol_checks() {
+        let manager = ProtocolManager::new(crate::config::DiscoveryConfig::new()).await.unwrap();
+        let monitor = HealthMonitor::new(HealthConfig::default());
 
-        let protocol_manager = Arc::new(ProtocolManager::new(&DiscoveryConfig::default()).unwrap());
-        let health_manager = Arc::new(HealthManager::new(
-            config,
-            protocol_manager.clone(),
-            None,
-        ));
+        monitor.check_protocols(&manager).await;
 
-        // Start health checks
-        health_manager.clone().start_health_checks().await;
+        let report = monitor.get_report().await;
+        assert_eq!(report.components().len(), manager.protocol_types().len());
+        assert_eq!(report.status(), HealthStatus::Healthy);
+    }
 
-        // Wait for checks to run
-        tokio::time::sleep(Duration::from_millis(200)).await;
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-        // Verify checks were performed
-        let checks = health_manager.health_checks.read().await;
-        assert!(!checks.is_empty());
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
 
-        // Verify timestamps are recent
-        let now = chrono::Utc::now();
-        for check in checks.values() {
-            assert!(now.signed_duration_since(check.last_check).num_seconds() < 2);
-        }
+    #[tokio::test]
+    async fn test_health_server_endpoints() {
+        let manager = ProtocolManager::new(crate::config::DiscoveryConfig::new()).await.unwrap();
+        let monitor = Arc::new(HealthMonitor::new(HealthConfig::default()));
+        let server = HealthServer::new(monitor, manager)
+            .bind((Ipv4Addr::LOCALHOST, 0).into())
+            .await
+            .unwrap();
+        let addr = server.local_addr();
+
+        assert!(get(addr, "/healthz").await.starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/readyz").await.starts_with("HTTP/1.1 200"));
+        assert!(get(addr, "/missing").await.starts_with("HTTP/1.1 404"));
+
+        let empty = ProtocolManager::new(crate::config::DiscoveryConfig::new().with_protocols(Default::default()))
+            .await
+            .unwrap();
+        let unready = HealthServer::new(Arc::new(HealthMonitor::new(HealthConfig::default())), empty)
+            .bind((Ipv4Addr::LOCALHOST, 0).into())
+            .await
+            .unwrap();
+        assert!(get(unready.local_addr(), "/readyz").await.starts_with("HTTP/1.1 503"));
+
+        server.shutdown();
+        unready.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_service_checks_demote_unreachable_services() {
+        let registry = ServiceRegistry::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up = ServiceInfo::new("up", "_http._tcp", listener.local_addr().unwrap().port(), None)
+            .unwrap()
+            .with_address(Ipv4Addr::LOCALHOST.into());
+        let closed_port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let down = ServiceInfo::new("down", "_http._tcp", closed_port, None)
+            .unwrap()
+            .with_address(Ipv4Addr::LOCALHOST.into());
+        registry.add_discovered_service(up.clone(), crate::types::ProtocolType::Mdns, None).await.unwrap();
+        registry.add_discovered_service(down.clone(), crate::types::ProtocolType::Mdns, None).await.unwrap();
+        let mut events = registry.subscribe();
+
+        let monitor = HealthMonitor::new(HealthConfig::default());
+        monitor.check_services(&registry).await;
+        assert_eq!(registry.health(&up.service_id()).await, Some(HealthStatus::Healthy));
+        assert_eq!(registry.health(&down.service_id()).await, Some(HealthStatus::Degraded));
+        let event = events.try_recv().unwrap();
+        assert_eq!(event, ServiceEvent::verification_failed(down.clone()).with_timestamp(event.origin().timestamp));
+
+        // Staying degraded doesn't repeat the event; becoming unhealthy does
+        monitor.check_services(&registry).await;
+        assert!(events.try_recv().is_err());
+        monitor.check_services(&registry).await;
+        assert_eq!(registry.health(&down.service_id()).await, Some(HealthStatus::Unhealthy));
+        let event = events.try_recv().unwrap();
+        assert_eq!(event, ServiceEvent::verification_failed(down).with_timestamp(event.origin().timestamp));
     }
 }
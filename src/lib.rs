@@ -105,7 +105,6 @@
 //!     Ok(())
 //! }
 //! ```
-//! ```
 //!
 //! ## Error Handling
 //!
@@ -176,23 +175,61 @@
 //! See the `examples/` directory for more complete examples.
 
 #![warn(missing_docs)]
-#![forbid(unsafe_code)]
+#![cfg_attr(not(any(feature = "ffi", feature = "windows-dns")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "ffi", feature = "windows-dns"), deny(unsafe_code))]
 
+pub mod attributes;
+pub mod audit;  // Append-only log of registrations, queries, verifications and security events
+pub mod cache;
+pub mod clock;  // Time sources that tests can control
 pub mod config;
+pub mod container;  // Detection of container networks without multicast
 pub mod discovery;
 pub mod error;
+pub mod events;  // Bounded event delivery with overflow policies
+pub mod fleet;  // Registration of many instances from a service template
+pub mod gateway;  // Discovery over HTTP/JSON and gRPC for clients without multicast
+pub mod health;
+pub mod linked;  // One service announced on several ports as linked instances
+pub mod logging;  // Operation spans and redaction of sensitive attributes
+pub mod naming;  // Instance names derived from the service and host
 pub mod protocols;
+pub mod query;  // Per-call query options, paging and ordering of query results
+pub mod ranking;  // Ordering of discovered services by preference
+pub mod registration;  // Status of live registrations and handles on them
 pub mod registry;  // Service registry for managing discovered and registered services
+pub mod remote;  // Client for a remote discovery agent
+pub mod report;  // JSON, CSV and table inventories of services
+pub mod resolver;  // Cached hostname resolution with negative caching
+pub mod rng;  // Seedable randomness for jitter and load balancing
+pub mod safety;
+pub mod schema;
 pub mod service;
 pub mod simple;  // Simple API for common use cases
 pub mod types;
 pub mod utils;
+pub mod verification;
 #[cfg(feature = "secure")]
 pub mod security;
+#[cfg(feature = "gossip")]
+pub mod gossip;  // Registry exchange between discovery nodes
+#[cfg(any(feature = "axum", feature = "actix"))]
+pub mod web;  // Self-registration of axum and actix-web servers
+#[cfg(feature = "tower")]
+pub mod tower;  // Discovered instances as a tower::discover::Discover for client load balancing
+#[cfg(feature = "testing")]
+pub mod testing;  // In-memory protocols for testing without a network
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;  // Entry points for the cargo-fuzz targets in fuzz/
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]  // C ABI; unsafe is confined to this module
+pub mod ffi;
 
 // Re-export main types for convenience
 pub use config::DiscoveryConfig;
-pub use discovery::ServiceDiscovery;
+pub use discovery::{ServiceDiscovery, ServiceDiscoveryBuilder};
 pub use error::{DiscoveryError, Result};
-pub use service::{ServiceInfo, ServiceEvent};
+pub use service::{ServiceId, ServiceInfo, ServiceEvent};
+pub use verification::{ServiceVerifier, VerificationResult};
 pub use types::{ServiceType, ProtocolType};
@@ -0,0 +1,309 @@
+//! One logical service announced on several ports
+//!
+//! A server often listens on more than one port, such as HTTP on 8080 and
+//! HTTPS on 8443. [`ServiceDiscovery::register_linked`] announces each
+//! [`Endpoint`] of a [`LinkedService`] as its own instance and marks them as
+//! variants of one service with the [`LINK_ATTRIBUTE`], [`VARIANT_ATTRIBUTE`]
+//! and [`SECURE_ATTRIBUTE`] attributes. Discoverers that understand them use
+//! [`prefer_secure`] to keep one variant per service, the secure one when
+//! there is one; others see ordinary instances.
+//!
+//! ```rust,no_run
+//! use auto_discovery::{ServiceDiscovery, config::DiscoveryConfig, linked::{self, Endpoint, LinkedService}};
+//!
+//! # async fn example() -> auto_discovery::Result<()> {
+//! let discovery = ServiceDiscovery::new(DiscoveryConfig::new()).await?;
+//! let api = LinkedService::new("Inventory API")
+//!     .with_endpoint(Endpoint::new("http", "_http._tcp", 8080)?)
+//!     .with_endpoint(Endpoint::new("https", "_http._tcp", 8443)?.secure());
+//! let registration = discovery.register_linked(&api).await?;
+//!
+//! // Elsewhere
+//! let services = linked::prefer_secure(discovery.discover_services(None).await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{DiscoveryError, Result},
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Attribute holding the identifier shared by the variants of a service
+pub const LINK_ATTRIBUTE: &str = "link";
+
+/// Attribute naming the variant, e.g. `https`
+pub const VARIANT_ATTRIBUTE: &str = "variant";
+
+/// Attribute set to `true` on variants reached over TLS
+pub const SECURE_ATTRIBUTE: &str = "secure";
+
+/// One port a [`LinkedService`] listens on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    label: String,
+    service_type: ServiceType,
+    port: u16,
+    secure: bool,
+    attributes: HashMap<String, String>,
+}
+
+impl Endpoint {
+    /// Create an endpoint labelled `label`, e.g. `https`, for `service_type`
+    /// on `port`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the label is empty, the service type is invalid
+    /// or the port is 0
+    pub fn new(label: impl Into<String>, service_type: impl Into<String>, port: u16) -> Result<Self> {
+        let label = label.into();
+        if label.trim().is_empty() {
+            return Err(DiscoveryError::configuration("Endpoint label cannot be empty"));
+        }
+        if port == 0 {
+            return Err(DiscoveryError::InvalidServiceInfo {
+                field: "port".to_string(),
+                reason: "Port cannot be zero".to_string(),
+            });
+        }
+        Ok(Self {
+            label,
+            service_type: ServiceType::new(service_type)?,
+            port,
+            secure: false,
+            attributes: HashMap::new(),
+        })
+    }
+
+    /// Mark the endpoint as reached over TLS
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    /// Add an attribute advertised by this endpoint only
+    pub fn with_attribute<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Label of the endpoint
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Service type of the endpoint
+    pub fn service_type(&self) -> &ServiceType {
+        &self.service_type
+    }
+
+    /// Port of the endpoint
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Whether the endpoint is reached over TLS
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+}
+
+/// A service listening on several [`Endpoint`]s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedService {
+    name: String,
+    link: String,
+    endpoints: Vec<Endpoint>,
+    attributes: HashMap<String, String>,
+    protocol_type: ProtocolType,
+}
+
+impl LinkedService {
+    /// Create a service named `name` with a fresh link identifier
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            link: Uuid::new_v4().simple().to_string(),
+            endpoints: Vec::new(),
+            attributes: HashMap::new(),
+            protocol_type: ProtocolType::default(),
+        }
+    }
+
+    /// Use `link` to identify the service instead of a random identifier,
+    /// so it stays the same across restarts
+    pub fn with_link<S: Into<String>>(mut self, link: S) -> Self {
+        self.link = link.into();
+        self
+    }
+
+    /// Add an endpoint
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    /// Add an attribute advertised by every endpoint
+    pub fn with_attribute<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Register the endpoints with `protocol_type`
+    pub fn with_protocol_type(mut self, protocol_type: ProtocolType) -> Self {
+        self.protocol_type = protocol_type;
+        self
+    }
+
+    /// Name of the service
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Identifier shared by the endpoints' instances
+    pub fn link(&self) -> &str {
+        &self.link
+    }
+
+    /// The endpoints
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// One instance per endpoint, carrying the linkage attributes
+    ///
+    /// Instances take the service's name, followed by the endpoint label in
+    /// parentheses where several endpoints share a service type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no endpoints, two share a label, or an
+    /// instance is invalid
+    pub fn instances(&self) -> Result<Vec<ServiceInfo>> {
+        if self.endpoints.is_empty() {
+            return Err(DiscoveryError::configuration(format!("Linked service '{}' has no endpoints", self.name)));
+        }
+        let mut labels = HashSet::new();
+        if let Some(endpoint) = self.endpoints.iter().find(|endpoint| !labels.insert(endpoint.label.as_str())) {
+            return Err(DiscoveryError::configuration(format!(
+                "Linked service '{}' has two endpoints labelled '{}'",
+                self.name, endpoint.label
+            )));
+        }
+
+        self.endpoints
+            .iter()
+            .map(|endpoint| {
+                let shared_type = self
+                    .endpoints
+                    .iter()
+                    .filter(|other| other.service_type == endpoint.service_type)
+                    .count()
+                    > 1;
+                let name = if shared_type {
+                    format!("{} ({})", self.name, endpoint.label)
+                } else {
+                    self.name.clone()
+                };
+                let mut service = ServiceInfo::new(name, endpoint.service_type.clone(), endpoint.port, None)?
+                    .with_protocol_type(self.protocol_type);
+                service.attributes.extend(self.attributes.clone());
+                service.attributes.extend(endpoint.attributes.clone());
+                service.attributes.insert(LINK_ATTRIBUTE.to_string(), self.link.clone());
+                service.attributes.insert(VARIANT_ATTRIBUTE.to_string(), endpoint.label.clone());
+                service.attributes.insert(SECURE_ATTRIBUTE.to_string(), endpoint.secure.to_string());
+                Ok(service)
+            })
+            .collect()
+    }
+}
+
+/// The link identifier of `service`, if it is a variant of a linked service
+pub fn link_of(service: &ServiceInfo) -> Option<&str> {
+    service.attributes.get(LINK_ATTRIBUTE).map(String::as_str)
+}
+
+/// Whether `service` says it is reached over TLS
+pub fn is_secure(service: &ServiceInfo) -> bool {
+    service
+        .attributes
+        .get(SECURE_ATTRIBUTE)
+        .is_some_and(|secure| secure.eq_ignore_ascii_case("true"))
+}
+
+/// Keep one variant of each linked service, the first secure one if any
+///
+/// The kept variant takes the place of the first variant found; services
+/// without a link are kept as they are, in their order.
+pub fn prefer_secure(services: Vec<ServiceInfo>) -> Vec<ServiceInfo> {
+    let mut chosen: HashMap<String, usize> = HashMap::new();
+    let mut kept: Vec<ServiceInfo> = Vec::with_capacity(services.len());
+    for service in services {
+        let Some(link) = link_of(&service).map(str::to_string) else {
+            kept.push(service);
+            continue;
+        };
+        match chosen.get(&link) {
+            Some(&index) => {
+                if is_secure(&service) && !is_secure(&kept[index]) {
+                    kept[index] = service;
+                }
+            }
+            None => {
+                chosen.insert(link, kept.len());
+                kept.push(service);
+            }
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api() -> LinkedService {
+        LinkedService::new("API")
+            .with_link("inventory")
+            .with_attribute("version", "2")
+            .with_endpoint(Endpoint::new("http", "_http._tcp", 8080).unwrap())
+            .with_endpoint(Endpoint::new("https", "_http._tcp", 8443).unwrap().secure())
+            .with_endpoint(Endpoint::new("grpc", "_grpc._tcp", 9090).unwrap().with_attribute("proto", "grpc"))
+    }
+
+    #[test]
+    fn test_linked_instances() {
+        let instances = api().instances().unwrap();
+        let names: Vec<&str> = instances.iter().map(ServiceInfo::name).collect();
+        assert_eq!(names, ["API (http)", "API (https)", "API"]);
+        assert!(instances.iter().all(|service| link_of(service) == Some("inventory")));
+        assert!(instances.iter().all(|service| service.attributes["version"] == "2"));
+        assert!(is_secure(&instances[1]) && !is_secure(&instances[0]));
+        assert_eq!(instances[2].attributes["proto"], "grpc");
+
+        assert!(LinkedService::new("Empty").instances().is_err());
+        let twice = LinkedService::new("API")
+            .with_endpoint(Endpoint::new("http", "_http._tcp", 8080).unwrap())
+            .with_endpoint(Endpoint::new("http", "_http._tcp", 8081).unwrap());
+        assert!(twice.instances().is_err());
+    }
+
+    #[test]
+    fn test_prefer_secure() {
+        let unlinked = ServiceInfo::new("Printer", "_ipp._tcp", 631, None).unwrap();
+        let instances = api().instances().unwrap();
+        let other = LinkedService::new("Plain")
+            .with_endpoint(Endpoint::new("http", "_http._tcp", 80).unwrap())
+            .instances()
+            .unwrap();
+        let services = [vec![unlinked], instances, other].concat();
+
+        let kept = prefer_secure(services);
+        let names: Vec<&str> = kept.iter().map(ServiceInfo::name).collect();
+        assert_eq!(names, ["Printer", "API (https)", "Plain"]);
+    }
+}
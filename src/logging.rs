@@ -0,0 +1,307 @@
+//! Structured logging and redaction of sensitive attributes
+//!
+//! Every operation the [`ProtocolManager`](crate::protocols::ProtocolManager)
+//! hands to a protocol runs inside an `info` span with target
+//! [`OPERATION_TARGET`]. The span carries `operation` and `protocol` fields,
+//! plus `service` and `service_type` when the operation concerns them, so
+//! events logged by protocols are tagged with them by any subscriber that
+//! records span fields.
+//!
+//! TXT attributes often hold credentials. Attributes are only ever logged
+//! through a [`Redactor`], which replaces the values of keys matching
+//! [`LoggingConfig::sensitive_keys`] with [`REDACTED`].
+//!
+//! ```
+//! use auto_discovery::logging::{LoggingConfig, Redactor};
+//! use auto_discovery::service::ServiceInfo;
+//!
+//! let service = ServiceInfo::new(
+//!     "API",
+//!     "_http._tcp",
+//!     8080,
+//!     Some(vec![("version", "1.2"), ("auth_token", "hunter2")]),
+//! )?;
+//! let redactor = Redactor::new(&LoggingConfig::default());
+//! assert_eq!(
+//!     redactor.attributes(&service.attributes).to_string(),
+//!     "{auth_token=[redacted], version=1.2}",
+//! );
+//! # Ok::<(), auto_discovery::error::DiscoveryError>(())
+//! ```
+
+use crate::{
+    error::{DiscoveryError, Result},
+    safety::Operation,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceAttributes, ServiceType},
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tracing::{field, Span};
+
+/// Target of the spans wrapping protocol operations
+pub const OPERATION_TARGET: &str = "auto_discovery::operation";
+
+/// Replacement for the values of sensitive attributes
+pub const REDACTED: &str = "[redacted]";
+
+/// Keys treated as sensitive by default
+pub const DEFAULT_SENSITIVE_KEYS: &str = r"(?i)token|secret|passw(or)?d|api[-_]?key|credential";
+
+/// How attributes are logged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Replace the values of sensitive attributes before logging them
+    pub redact: bool,
+    /// Regular expression matched against attribute keys to find sensitive ones
+    ///
+    /// Matches anywhere in the key; anchor it to match whole keys.
+    pub sensitive_keys: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            redact: true,
+            sensitive_keys: DEFAULT_SENSITIVE_KEYS.to_string(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Create a logging configuration with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        Regex::new(&self.sensitive_keys).map_err(|e| {
+            DiscoveryError::configuration(format!("Invalid sensitive key pattern: {e}"))
+        })?;
+        Ok(())
+    }
+}
+
+/// Which attribute values a [`Redactor`] hides
+#[derive(Debug, Clone)]
+enum Sensitive {
+    Nothing,
+    Matching(Regex),
+    Everything,
+}
+
+/// Hides the values of sensitive attributes in log output
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    sensitive: Sensitive,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new(&LoggingConfig::default())
+    }
+}
+
+impl Redactor {
+    /// Create a redactor from a logging configuration
+    ///
+    /// An invalid key pattern redacts every value rather than none;
+    /// [`LoggingConfig::validate`] reports it up front.
+    pub fn new(config: &LoggingConfig) -> Self {
+        let sensitive = if !config.redact {
+            Sensitive::Nothing
+        } else {
+            match Regex::new(&config.sensitive_keys) {
+                Ok(pattern) => Sensitive::Matching(pattern),
+                Err(_) => Sensitive::Everything,
+            }
+        };
+        Self { sensitive }
+    }
+
+    /// Whether the value of attribute `key` must not be logged
+    pub fn is_sensitive(&self, key: &str) -> bool {
+        match &self.sensitive {
+            Sensitive::Nothing => false,
+            Sensitive::Matching(pattern) => pattern.is_match(key),
+            Sensitive::Everything => true,
+        }
+    }
+
+    /// `value`, or [`REDACTED`] if attribute `key` is sensitive
+    pub fn value<'a>(&self, key: &str, value: &'a str) -> &'a str {
+        if self.is_sensitive(key) { REDACTED } else { value }
+    }
+
+    /// Displays `attributes` with sensitive values redacted, sorted by key
+    pub fn attributes<'a>(&'a self, attributes: &'a ServiceAttributes) -> RedactedAttributes<'a> {
+        RedactedAttributes { redactor: self, attributes }
+    }
+
+    /// Displays `service` with its attributes, sensitive values redacted
+    pub fn service<'a>(&'a self, service: &'a ServiceInfo) -> RedactedService<'a> {
+        RedactedService { redactor: self, service }
+    }
+}
+
+/// Attributes displayed with sensitive values redacted
+///
+/// Created by [`Redactor::attributes`].
+pub struct RedactedAttributes<'a> {
+    redactor: &'a Redactor,
+    attributes: &'a ServiceAttributes,
+}
+
+impl fmt::Display for RedactedAttributes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys: Vec<_> = self.attributes.keys().collect();
+        keys.sort();
+        f.write_str("{")?;
+        for (i, key) in keys.into_iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", key, self.redactor.value(key, &self.attributes[key]))?;
+        }
+        f.write_str("}")
+    }
+}
+
+/// A service displayed with its attributes, sensitive values redacted
+///
+/// Created by [`Redactor::service`].
+pub struct RedactedService<'a> {
+    redactor: &'a Redactor,
+    service: &'a ServiceInfo,
+}
+
+impl fmt::Display for RedactedService<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.service, self.redactor.attributes(&self.service.attributes))
+    }
+}
+
+/// Span wrapping one protocol operation
+///
+/// `service` is recorded when the operation concerns a single service; the
+/// service type comes from it, or from `service_types` when there is exactly
+/// one.
+pub(crate) fn operation_span(
+    operation: Operation,
+    protocol: ProtocolType,
+    service: Option<&ServiceInfo>,
+    service_types: &[ServiceType],
+) -> Span {
+    let span = tracing::info_span!(
+        target: OPERATION_TARGET,
+        "operation",
+        operation = %operation,
+        protocol = %protocol,
+        service = field::Empty,
+        service_type = field::Empty,
+    );
+    if let Some(service) = service {
+        span.record("service", field::display(&service.name));
+        span.record("service_type", field::display(service.service_type()));
+    } else if let [service_type] = service_types {
+        span.record("service_type", field::display(service_type));
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Capture {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn service() -> ServiceInfo {
+        ServiceInfo::new(
+            "API",
+            "_http._tcp",
+            8080,
+            Some(vec![("version", "1.2"), ("DB_Password", "hunter2"), ("apiKey", "abc")]),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_default_keys_are_sensitive() {
+        let redactor = Redactor::default();
+        for key in ["token", "auth_token", "Secret", "password", "passwd", "api-key", "apikey", "credentials"] {
+            assert!(redactor.is_sensitive(key), "{key}");
+        }
+        for key in ["version", "path", "txtvers"] {
+            assert!(!redactor.is_sensitive(key), "{key}");
+        }
+        assert_eq!(
+            redactor.service(&service()).to_string(),
+            "API (_http._tcp) at 127.0.0.1:8080 via mDNS {DB_Password=[redacted], apiKey=[redacted], version=1.2}"
+        );
+    }
+
+    #[test]
+    fn test_redaction_configurable() {
+        let disabled = Redactor::new(&LoggingConfig { redact: false, ..LoggingConfig::default() });
+        assert_eq!(disabled.value("token", "abc"), "abc");
+
+        let config = LoggingConfig { sensitive_keys: "^version$".into(), ..LoggingConfig::default() };
+        let custom = Redactor::new(&config);
+        assert_eq!(custom.value("version", "1.2"), REDACTED);
+        assert_eq!(custom.value("password", "hunter2"), "hunter2");
+
+        let invalid = LoggingConfig { sensitive_keys: "(".into(), ..LoggingConfig::default() };
+        assert!(invalid.validate().is_err());
+        assert!(Redactor::new(&invalid).is_sensitive("version"));
+    }
+
+    #[test]
+    fn test_operation_span_tags_events() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(capture.clone())
+            .with_ansi(false)
+            .finish();
+        let service = service();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = operation_span(Operation::Registration, ProtocolType::Upnp, Some(&service), &[]);
+            let _entered = span.enter();
+            tracing::info!("registered");
+        });
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("operation=registration"), "{output}");
+        assert!(output.contains("protocol=UPnP"), "{output}");
+        assert!(output.contains("service=API"), "{output}");
+        assert!(output.contains("service_type=_http._tcp"), "{output}");
+        assert!(!output.contains("hunter2"), "{output}");
+    }
+}
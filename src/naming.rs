@@ -0,0 +1,101 @@
+//! Instance names for registered services
+//!
+//! A [`NamingStrategy`] in the [`RegistrationConfig`](crate::config::RegistrationConfig)
+//! derives the name a service is announced under from the name it was
+//! given, so callers do not have to tell instances on different hosts apart
+//! themselves. Whatever the strategy, the name is made a valid RFC 6763
+//! instance name: control characters are dropped and it is cut to 63 bytes,
+//! shortening the given name rather than the part the strategy added. Names
+//! that still collide are resolved by the
+//! [`ConflictPolicy`](crate::config::ConflictPolicy), which appends a counter.
+
+use crate::{
+    service::ServiceInfo,
+    utils::string::{suffixed_instance_name, valid_instance_name},
+};
+use std::{fmt, sync::Arc};
+
+/// Function computing an instance name for a service
+pub type NameFn = dyn Fn(&ServiceInfo) -> String + Send + Sync;
+
+/// How the name a service is announced under is derived
+#[derive(Clone, Default)]
+pub enum NamingStrategy {
+    /// The service's own name
+    #[default]
+    AsGiven,
+    /// The service's name followed by the host's: "Orders API @ web-01"
+    Hostname,
+    /// The service's name followed by the first eight hex digits of its id:
+    /// "Orders API-3f2a9c1e"
+    UuidSuffix,
+    /// A name computed from the service
+    Custom(Arc<NameFn>),
+}
+
+impl NamingStrategy {
+    /// Name services with `name`
+    pub fn custom<F>(name: F) -> Self
+    where
+        F: Fn(&ServiceInfo) -> String + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(name))
+    }
+
+    /// The instance name of `service` on host `hostname`
+    ///
+    /// Only the first label of `hostname` is used.
+    pub fn instance_name(&self, service: &ServiceInfo, hostname: &str) -> String {
+        match self {
+            Self::AsGiven => valid_instance_name(&service.name),
+            Self::Hostname => {
+                let host = hostname.split('.').next().unwrap_or(hostname);
+                suffixed_instance_name(&service.name, &format!(" @ {host}"))
+            }
+            Self::UuidSuffix => {
+                let id = service.id.simple().to_string();
+                suffixed_instance_name(&service.name, &format!("-{}", &id[..8]))
+            }
+            Self::Custom(name) => valid_instance_name(&name(service)),
+        }
+    }
+}
+
+impl fmt::Debug for NamingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AsGiven => f.write_str("AsGiven"),
+            Self::Hostname => f.write_str("Hostname"),
+            Self::UuidSuffix => f.write_str("UuidSuffix"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_names() {
+        let service = ServiceInfo::new("Orders API", "_http._tcp", 8080, None).unwrap();
+        let id = service.id.simple().to_string();
+
+        assert_eq!(NamingStrategy::AsGiven.instance_name(&service, "web-01.local."), "Orders API");
+        assert_eq!(NamingStrategy::Hostname.instance_name(&service, "web-01.local."), "Orders API @ web-01");
+        assert_eq!(
+            NamingStrategy::UuidSuffix.instance_name(&service, "web-01"),
+            format!("Orders API-{}", &id[..8])
+        );
+        let custom = NamingStrategy::custom(|service| format!("{}\n:{}", service.name, service.port));
+        assert_eq!(custom.instance_name(&service, "web-01"), "Orders API:8080");
+    }
+
+    #[test]
+    fn test_long_names_keep_their_suffix() {
+        let service = ServiceInfo::new("a".repeat(80), "_http._tcp", 8080, None).unwrap();
+        let name = NamingStrategy::Hostname.instance_name(&service, "web-01");
+        assert_eq!(name.len(), 63);
+        assert!(name.ends_with("a @ web-01"));
+    }
+}
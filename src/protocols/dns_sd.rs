@@ -1,32 +1,104 @@
 //! DNS-SD (DNS Service Discovery) protocol implementation
+//!
+//! Services are browsed over unicast DNS (RFC 6763): a PTR query for the
+//! service type lists the instances, whose SRV, TXT and address records are
+//! then resolved. Besides the configured zone, additional browse domains can
+//! be configured, and the domains the zone recommends through
+//! `b._dns-sd._udp` and `db._dns-sd._udp` are browsed as well, so wide-area
+//! DNS-SD works beyond `.local`.
+//!
+//! Services are registered with RFC 2136 dynamic updates to the zone, signed
+//! with the TSIG or SIG(0) key named in [`DnsSdConfig`] when one is
+//! configured. Each
+//! update carries an update lease (EDNS0 option 2) and is repeated at half
+//! the lease for as long as the service stays registered.
+//!
+//! Addresses of SRV targets are kept in the registry's
+//! [`ResolutionCache`] for the TTL of their records, and targets that do not
+//! exist for the negative TTL of the zone, so browsing the same hosts again
+//! does not query them again.
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use async_trait::async_trait;
 use governor::{
     state::keyed::DefaultKeyedStateStore,
     clock::DefaultClock,
+    Quota,
     RateLimiter, 
 };
-use trust_dns_client::client::AsyncClient;
+use tokio::{
+    net::{TcpStream, UdpSocket},
+    task::JoinHandle,
+};
+use trust_dns_client::{
+    client::{AsyncClient, ClientHandle, Signer},
+    op::{Edns, Message, MessageType, OpCode, Query, ResponseCode, UpdateMessage},
+    rr::{
+        rdata::{opt::EdnsOption, A, AAAA, PTR, SRV, TXT},
+        DNSClass, Name, RData, Record, RecordType,
+    },
+    tcp::TcpClientStream,
+    udp::UdpClientStream,
+};
+use trust_dns_proto::{
+    iocompat::AsyncIoTokioAsStd,
+    xfer::{DnsHandle, DnsRequest, DnsRequestOptions, FirstAnswer},
+};
+use tracing::{debug, warn};
+#[cfg(feature = "secure")]
+use crate::security::{sig0::Sig0Key, tsig::TsigKeyManager};
 use crate::{
-    config::DiscoveryConfig,
+    config::{DiscoveryConfig, DnsSdConfig, DnsTransport},
     error::{DiscoveryError, Result},
-    protocols::DiscoveryProtocol,
+    protocols::{Capabilities, DiscoveryProtocol},
     registry::ServiceRegistry,
+    resolver::{Resolution, ResolutionCache},
     service::ServiceInfo,
     types::{ProtocolType, ServiceType},
 };
 
+/// Browse domain enumeration names, queried under the zone (RFC 6763 §11)
+const BROWSE_DOMAIN_QUERIES: [&str; 2] = ["b._dns-sd._udp", "db._dns-sd._udp"];
+
+/// Service type enumeration name, queried under each browse domain (RFC 6763 §9)
+const SERVICE_TYPE_ENUMERATION: &str = "_services._dns-sd._udp";
+
+/// EDNS0 option code of the update lease (draft-ietf-dnssd-update-lease)
+const UPDATE_LEASE_OPTION: u16 = 2;
+
+/// What DNS-SD can do
+///
+/// Liveness is left to connectivity checks; the zone only says what is
+/// registered.
+pub(super) const CAPABILITIES: Capabilities = Capabilities {
+    supports_verification: false,
+    supports_ipv6: true,
+    wide_area: true,
+    ..Capabilities::LINK_LOCAL
+};
+
 /// DNS-SD (DNS Service Discovery) protocol implementation
 pub struct DnsSdProtocol {
     #[allow(dead_code)]
     config: DiscoveryConfig,
-    #[allow(dead_code)]
+    /// DNS-SD specific settings
+    dns_sd: DnsSdConfig,
     client: Arc<AsyncClient>,
     #[allow(dead_code)]
     rate_limiter: Arc<RateLimiter<String, DefaultKeyedStateStore<String>, DefaultClock>>,
     #[allow(dead_code)]
     registry: Option<Arc<ServiceRegistry>>,
+    updater: Updater,
+    /// Lease refresh tasks of registered services, by instance name
+    refreshes: Mutex<HashMap<Name, JoinHandle<()>>>,
+    /// Addresses of SRV targets, shared with the registry once it is set
+    resolutions: Arc<ResolutionCache>,
 }
 
 #[async_trait]
@@ -36,25 +108,96 @@ impl DiscoveryProtocol for DnsSdProtocol {
     }
 
     fn set_registry(&mut self, registry: Arc<ServiceRegistry>) {
+        self.resolutions = Arc::clone(registry.resolution_cache());
         self.registry = Some(registry);
     }
 
     async fn discover_services(
         &self,
-        _service_types: Vec<ServiceType>,
-        _timeout: Option<Duration>
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>
     ) -> Result<Vec<ServiceInfo>> {
-        // Basic implementation
-        Ok(Vec::new())
+        let timeout = timeout.unwrap_or_else(|| self.config.protocol_timeout());
+        let browse = async {
+            let domains = self.browse_domains().await;
+            let browses = service_types.iter().flat_map(|service_type| {
+                // A type naming its own domain is only looked up there
+                let domains = match service_type.domain() {
+                    Some(domain) => vec![fqdn(domain)],
+                    None => domains.clone(),
+                };
+                domains.into_iter().map(move |domain| self.browse(service_type, domain))
+            });
+            futures::future::join_all(browses).await.into_iter().flatten().collect::<Vec<_>>()
+        };
+        tokio::time::timeout(timeout, browse)
+            .await
+            .map_err(|_| DiscoveryError::timeout(format!("DNS-SD browse did not finish within {timeout:?}")))
     }
 
-    async fn register_service(&self, _service: ServiceInfo) -> Result<()> {
-        // Basic implementation
+    async fn enumerate_service_types(&self, timeout: Duration) -> Result<Vec<ServiceType>> {
+        let enumerate = async {
+            let mut service_types = Vec::new();
+            for domain in self.browse_domains().await {
+                let name = format!("{SERVICE_TYPE_ENUMERATION}.{domain}");
+                // Answers are `_type._proto.<domain>`; drop the domain
+                for target in self.lookup_ptr(&name).await.unwrap_or_default() {
+                    let labels: Vec<String> = target
+                        .iter()
+                        .take(2)
+                        .map(|label| String::from_utf8_lossy(label).into_owned())
+                        .collect();
+                    if let Ok(service_type) = ServiceType::new(labels.join(".")) {
+                        service_types.push(service_type);
+                    }
+                }
+            }
+            service_types
+        };
+        tokio::time::timeout(timeout, enumerate)
+            .await
+            .map_err(|_| DiscoveryError::timeout(format!("DNS-SD type enumeration did not finish within {timeout:?}")))
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        let registration = Arc::new(Registration::new(&service, self.dns_sd.zone())?);
+        let lease = self.dns_sd.lease();
+        self.updater.send(registration.add(lease)).await?;
+        debug!("Registered {} in zone {} for {:?}", registration.instance, registration.zone, lease);
+
+        let updater = self.updater.clone();
+        let refreshed = registration.clone();
+        let refresh = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(lease / 2).await;
+                if let Err(e) = updater.send(refreshed.add(lease)).await {
+                    warn!("Failed to refresh DNS-SD registration of {}: {}", refreshed.instance, e);
+                }
+            }
+        });
+        let previous = self
+            .refreshes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(registration.instance.clone(), refresh);
+        if let Some(previous) = previous {
+            previous.abort();
+        }
         Ok(())
     }
 
-    async fn unregister_service(&self, _service: &ServiceInfo) -> Result<()> {
-        // Basic implementation
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        let registration = Registration::new(service, self.dns_sd.zone())?;
+        let refresh = self
+            .refreshes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&registration.instance);
+        if let Some(refresh) = refresh {
+            refresh.abort();
+        }
+        self.updater.send(registration.delete()).await?;
+        debug!("Removed {} from zone {}", registration.instance, registration.zone);
         Ok(())
     }
 
@@ -67,6 +210,10 @@ impl DiscoveryProtocol for DnsSdProtocol {
         // Basic check if DNS-SD is available
         true
     }
+
+    fn capabilities(&self) -> Capabilities {
+        CAPABILITIES
+    }
 }
 
 impl DnsSdProtocol {
@@ -74,13 +221,895 @@ impl DnsSdProtocol {
     /// 
     /// # Arguments
     /// 
-    /// * `_config` - The discovery configuration (currently unused)
+    /// * `config` - The discovery configuration; the DNS server, transport,
+    ///   zone and update signing key names are taken from its [`DnsSdConfig`]
+    ///   section
     /// 
     /// # Errors
     /// 
-    /// Returns an error if the DNS client cannot be initialized
-    pub async fn new(_config: &DiscoveryConfig) -> Result<Self> {
-        // TODO: Implement proper initialization
-        Err(DiscoveryError::protocol("DNS-SD protocol not yet implemented"))
+    /// Returns an error if no DNS server is configured, if the selected
+    /// transport is not compiled in, or if the DNS client cannot be initialized
+    pub async fn new(config: &DiscoveryConfig) -> Result<Self> {
+        let dns_sd = config.dns_sd().clone();
+        dns_sd.validate()?;
+        let server = dns_sd.dns_server().ok_or_else(|| {
+            DiscoveryError::configuration("DNS-SD requires a DNS server (see DnsSdConfig::with_dns_server)")
+        })?;
+
+        let client = Self::connect(&dns_sd, server, config.protocol_timeout(), None).await?;
+
+        debug!(
+            "DNS-SD client connected to {} over {:?} for zone {}",
+            server,
+            dns_sd.transport(),
+            dns_sd.zone()
+        );
+
+        let quota = Quota::per_second(NonZeroU32::new(10).expect("non-zero quota"));
+        Ok(Self {
+            config: config.clone(),
+            dns_sd,
+            client: Arc::new(client),
+            rate_limiter: Arc::new(RateLimiter::keyed(quota)),
+            registry: None,
+            updater: Updater {
+                dns_sd: config.dns_sd().clone(),
+                server,
+                timeout: config.protocol_timeout(),
+                #[cfg(feature = "secure")]
+                tsig_keys: None,
+                #[cfg(feature = "secure")]
+                sig0_keys: Vec::new(),
+            },
+            refreshes: Mutex::new(HashMap::new()),
+            resolutions: Arc::new(ResolutionCache::new(config.resolver().clone())),
+        })
+    }
+
+    /// Sign updates with keys from `keys`
+    ///
+    /// The key named by [`DnsSdConfig::tsig_key_name`] is looked up for each
+    /// update, so keys can be rotated while services stay registered.
+    #[cfg(feature = "secure")]
+    pub fn with_tsig_keys(mut self, keys: Arc<TsigKeyManager>) -> Self {
+        self.updater.tsig_keys = Some(keys);
+        self
+    }
+
+    /// Add a key for signing updates with SIG(0)
+    ///
+    /// Updates are signed with the key named by
+    /// [`DnsSdConfig::sig0_key_name`].
+    #[cfg(feature = "secure")]
+    pub fn with_sig0_key(mut self, key: Sig0Key) -> Self {
+        self.updater.sig0_keys.push(key);
+        self
+    }
+
+    /// Connect a DNS client over the configured transport, signing updates
+    /// with `signer` if given
+    async fn connect(
+        dns_sd: &DnsSdConfig,
+        server: SocketAddr,
+        timeout: Duration,
+        signer: Option<Arc<Signer>>,
+    ) -> Result<AsyncClient> {
+        let connected = match dns_sd.transport() {
+            DnsTransport::Udp => {
+                let stream = UdpClientStream::<UdpSocket, Signer>::with_timeout_and_signer(server, timeout, signer);
+                AsyncClient::connect(stream).await.map(|(client, bg)| (client, tokio::spawn(bg)))
+            }
+            DnsTransport::Tcp => {
+                let (stream, handle) =
+                    TcpClientStream::<AsyncIoTokioAsStd<TcpStream>>::with_timeout(server, timeout);
+                AsyncClient::with_timeout(stream, handle, timeout, signer)
+                    .await
+                    .map(|(client, bg)| (client, tokio::spawn(bg)))
+            }
+            #[cfg(feature = "dns-over-tls")]
+            DnsTransport::Tls => {
+                let (stream, handle) = trust_dns_proto::rustls::tls_client_connect::<AsyncIoTokioAsStd<TcpStream>>(
+                    server,
+                    Self::tls_server_name(dns_sd)?,
+                    tls_client_config(),
+                );
+                AsyncClient::with_timeout(stream, handle, timeout, signer)
+                    .await
+                    .map(|(client, bg)| (client, tokio::spawn(bg)))
+            }
+            #[cfg(feature = "dns-over-https")]
+            DnsTransport::Https => {
+                if signer.is_some() {
+                    return Err(DiscoveryError::configuration(
+                        "Signed DNS updates are not supported over DNS-over-HTTPS",
+                    ));
+                }
+                let stream = trust_dns_proto::https::HttpsClientStreamBuilder::with_client_config(tls_client_config())
+                    .build::<AsyncIoTokioAsStd<TcpStream>>(server, Self::tls_server_name(dns_sd)?);
+                AsyncClient::connect(stream).await.map(|(client, bg)| (client, tokio::spawn(bg)))
+            }
+            #[allow(unreachable_patterns)]
+            transport => {
+                return Err(DiscoveryError::configuration(format!(
+                    "DNS transport {transport:?} is not available; enable the dns-over-tls or dns-over-https feature"
+                )));
+            }
+        };
+
+        connected
+            .map(|(client, _background)| client)
+            .map_err(|e| DiscoveryError::dns_sd(format!("Failed to connect to DNS server {server}: {e}")))
+    }
+
+    /// Domains to browse: the zone, the configured browse domains and, if
+    /// enabled, the browse domains the zone advertises
+    pub async fn browse_domains(&self) -> Vec<String> {
+        let zone = fqdn(self.dns_sd.zone());
+        let mut domains = vec![zone.clone()];
+        domains.extend(self.dns_sd.browse_domains().iter().map(|domain| fqdn(domain)));
+        if self.dns_sd.enumerates_browse_domains() {
+            for query in BROWSE_DOMAIN_QUERIES {
+                match self.lookup_ptr(&format!("{query}.{zone}")).await {
+                    Ok(names) => domains.extend(names.iter().map(|name| fqdn(&name.to_ascii()))),
+                    Err(e) => debug!("Browse domain enumeration {query}.{zone} failed: {e}"),
+                }
+            }
+        }
+        let mut seen = std::collections::HashSet::new();
+        domains.retain(|domain| seen.insert(domain.to_ascii_lowercase()));
+        domains
+    }
+
+    /// Browse one service type in one domain, skipping instances that fail
+    /// to resolve
+    async fn browse(&self, service_type: &ServiceType, domain: String) -> Vec<ServiceInfo> {
+        let name = format!("{}{}.{domain}", service_type.service_name(), service_type.protocol());
+        let instances = match self.lookup_ptr(&name).await {
+            Ok(instances) => instances,
+            Err(e) => {
+                debug!("Browsing {name} failed: {e}");
+                return Vec::new();
+            }
+        };
+
+        let resolved = futures::future::join_all(instances.iter().map(|instance| self.resolve(instance, service_type))).await;
+        instances
+            .iter()
+            .zip(resolved)
+            .filter_map(|(instance, result)| match result {
+                Ok(service) => service,
+                Err(e) => {
+                    warn!("Failed to resolve DNS-SD instance {instance}: {e}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Resolve an instance name into a service from its SRV, TXT and address records
+    async fn resolve(&self, instance: &Name, service_type: &ServiceType) -> Result<Option<ServiceInfo>> {
+        let srv_records = self.query(instance.clone(), RecordType::SRV).await?;
+        let Some((srv, ttl, additionals)) = srv_records.answers.iter().find_map(|record| match record.data() {
+            Some(RData::SRV(srv)) => Some((srv.clone(), record.ttl(), &srv_records.additionals)),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        // RFC 2782: a target of "." means the service is decidedly not available
+        if srv.target().is_root() || srv.port() == 0 {
+            return Ok(None);
+        }
+
+        let Some(address) = self.resolve_host(srv.target(), additionals).await? else {
+            return Ok(None);
+        };
+
+        let attributes = self
+            .query(instance.clone(), RecordType::TXT)
+            .await?
+            .answers
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::TXT(txt)) => Some(txt),
+                _ => None,
+            })
+            .flat_map(|txt| txt.iter().map(|entry| parse_txt_entry(entry)))
+            .collect::<HashMap<_, _>>();
+
+        let instance_name = instance
+            .iter()
+            .next()
+            .map(|label| String::from_utf8_lossy(label).into_owned())
+            .unwrap_or_default();
+        let service = ServiceInfo::new(instance_name, service_type.to_string(), srv.port(), None)?
+            .with_protocol_type(ProtocolType::DnsSd)
+            .with_address(address)
+            .with_host(srv.target().to_ascii())
+            .with_srv(srv.priority(), srv.weight())
+            .with_attributes(attributes)
+            .with_ttl(Duration::from_secs(u64::from(ttl)));
+        Ok(Some(service))
+    }
+
+    /// First address of `host`, from `additionals`, the resolution cache or
+    /// its A and AAAA records, in that order
+    ///
+    /// Addresses are cached for the shortest TTL of their records. A host
+    /// without addresses is cached as missing for the negative TTL of the
+    /// zone, if the server sent its SOA record.
+    async fn resolve_host(&self, host: &Name, additionals: &[Record]) -> Result<Option<IpAddr>> {
+        let key = host.to_ascii();
+        let (addresses, ttl) = address_records(additionals, host);
+        if let Some(&address) = addresses.first() {
+            self.resolutions.insert(&key, addresses, ttl);
+            return Ok(Some(address));
+        }
+        match self.resolutions.get(&key) {
+            Some(Resolution::Found(addresses)) => return Ok(addresses.first().copied()),
+            Some(Resolution::NotFound) => return Ok(None),
+            None => {}
+        }
+
+        let mut negative_ttl = None;
+        for record_type in [RecordType::A, RecordType::AAAA] {
+            let answers = self.query(host.clone(), record_type).await?;
+            let (addresses, ttl) = address_records(&answers.answers, host);
+            if let Some(&address) = addresses.first() {
+                self.resolutions.insert(&key, addresses, ttl);
+                return Ok(Some(address));
+            }
+            negative_ttl = answers.negative_ttl.or(negative_ttl);
+        }
+        debug!("{} has no address records", host);
+        self.resolutions.insert_negative(&key, negative_ttl.map(|ttl| Duration::from_secs(u64::from(ttl))));
+        Ok(None)
+    }
+
+    /// Targets of the PTR records at `name`
+    async fn lookup_ptr(&self, name: &str) -> Result<Vec<Name>> {
+        let name = Name::from_ascii(name).map_err(|e| DiscoveryError::dns_sd(format!("Invalid name {name}: {e}")))?;
+        Ok(self
+            .query(name, RecordType::PTR)
+            .await?
+            .answers
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::PTR(ptr)) => Some(ptr.0.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn query(&self, name: Name, record_type: RecordType) -> Result<Answers> {
+        let mut client = AsyncClient::clone(&self.client);
+        let response = client
+            .query(name.clone(), DNSClass::IN, record_type)
+            .await
+            .map_err(|e| DiscoveryError::dns_sd(format!("{record_type} query for {name} failed: {e}")))?;
+        match response.response_code() {
+            ResponseCode::NoError | ResponseCode::NXDomain => {}
+            code => return Err(DiscoveryError::dns_sd(format!("{record_type} query for {name} failed: {code}"))),
+        }
+        // RFC 2308 §5: negative answers live for the lesser of the SOA's TTL and minimum
+        let negative_ttl = response.name_servers().iter().find_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some(record.ttl().min(soa.minimum())),
+            _ => None,
+        });
+        Ok(Answers {
+            answers: response.answers().to_vec(),
+            additionals: response.additionals().to_vec(),
+            negative_ttl,
+        })
+    }
+
+    #[cfg(feature = "dns-over-tls")]
+    fn tls_server_name(dns_sd: &DnsSdConfig) -> Result<String> {
+        dns_sd
+            .tls_server_name()
+            .map(str::to_string)
+            .ok_or_else(|| DiscoveryError::configuration("Encrypted DNS transports require a TLS server name"))
+    }
+}
+
+impl Drop for DnsSdProtocol {
+    fn drop(&mut self) {
+        for (_, refresh) in self.refreshes.get_mut().unwrap_or_else(|e| e.into_inner()).drain() {
+            refresh.abort();
+        }
+    }
+}
+
+/// Sends dynamic updates to the DNS server
+#[derive(Clone)]
+struct Updater {
+    dns_sd: DnsSdConfig,
+    server: SocketAddr,
+    timeout: Duration,
+    #[cfg(feature = "secure")]
+    tsig_keys: Option<Arc<TsigKeyManager>>,
+    #[cfg(feature = "secure")]
+    sig0_keys: Vec<Sig0Key>,
+}
+
+impl Updater {
+    /// Signer for the configured TSIG or SIG(0) key, if any
+    async fn signer(&self) -> Result<Option<Arc<Signer>>> {
+        let (kind, key_name) = match (self.dns_sd.tsig_key_name(), self.dns_sd.sig0_key_name()) {
+            (Some(key_name), _) => ("TSIG", key_name),
+            (None, Some(key_name)) => ("SIG(0)", key_name),
+            (None, None) => return Ok(None),
+        };
+        #[cfg(feature = "secure")]
+        {
+            if kind == "TSIG" {
+                let keys = self.tsig_keys.as_ref().ok_or_else(|| {
+                    DiscoveryError::configuration(format!(
+                        "TSIG key {key_name} is configured but no keys were provided (see DnsSdProtocol::with_tsig_keys)"
+                    ))
+                })?;
+                let key = keys.find_key(Some(key_name)).await?;
+                return Ok(Some(Arc::new(Signer::from(key.signer()?))));
+            }
+            let name = Name::from_ascii(key_name)
+                .map_err(|e| DiscoveryError::configuration(format!("Invalid SIG(0) key name {key_name}: {e}")))?;
+            let key = self.sig0_keys.iter().find(|key| *key.name() == name).ok_or_else(|| {
+                DiscoveryError::configuration(format!(
+                    "SIG(0) key {key_name} is configured but was not provided (see DnsSdProtocol::with_sig0_key)"
+                ))
+            })?;
+            Ok(Some(Arc::new(Signer::from(key.signer()?))))
+        }
+        #[cfg(not(feature = "secure"))]
+        Err(DiscoveryError::configuration(format!(
+            "Signing updates with {kind} key {key_name} requires the secure feature"
+        )))
+    }
+
+    /// Send `update` over a fresh connection and check the server accepted it
+    async fn send(&self, update: Message) -> Result<()> {
+        let mut client = DnsSdProtocol::connect(&self.dns_sd, self.server, self.timeout, self.signer().await?).await?;
+        let zone = update.queries().first().map(|zone| zone.name().clone()).unwrap_or_default();
+        let response = client
+            .send(DnsRequest::new(update, DnsRequestOptions::default()))
+            .first_answer()
+            .await
+            .map_err(|e| DiscoveryError::dns_sd(format!("Update of zone {zone} failed: {e}")))?;
+        match response.response_code() {
+            ResponseCode::NoError => Ok(()),
+            code => Err(DiscoveryError::dns_sd(format!("Update of zone {zone} was refused: {code}"))),
+        }
+    }
+}
+
+/// Records registering one service instance (RFC 6763 §4)
+struct Registration {
+    zone: Name,
+    instance: Name,
+    /// `<type>.<domain>` PTR to the instance
+    ptr: Record,
+    /// SRV and TXT records of the instance
+    instance_records: Vec<Record>,
+    /// Address record of the target host, if the host lies in the zone
+    address: Option<Record>,
+}
+
+impl Registration {
+    fn new(service: &ServiceInfo, zone: &str) -> Result<Self> {
+        let invalid = |e| DiscoveryError::dns_sd(format!("Cannot register {} in zone {zone}: {e}", service.name));
+        let zone = Name::from_ascii(fqdn(zone)).map_err(invalid)?;
+        let domain = match service.service_type.domain() {
+            Some(domain) => Name::from_ascii(fqdn(domain)).map_err(invalid)?,
+            None => zone.clone(),
+        };
+        let service_type = Name::from_ascii(format!(
+            "{}{}",
+            service.service_type.service_name(),
+            service.service_type.protocol()
+        ))
+        .and_then(|service_type| service_type.append_domain(&domain))
+        .map_err(invalid)?;
+        // Instance names are free text, so take them as a raw label
+        let instance = Name::from_labels(vec![service.name.as_bytes()])
+            .and_then(|instance| instance.append_domain(&service_type))
+            .map_err(invalid)?;
+        let target = match service.hostname() {
+            Some(host) => Name::from_ascii(fqdn(host)).map_err(invalid)?,
+            None => Name::from_ascii(host_label(&service.name))
+                .and_then(|host| host.append_domain(&zone))
+                .map_err(invalid)?,
+        };
+
+        let ttl = service.ttl().as_secs().clamp(1, u64::from(u32::MAX)) as u32;
+        let mut txt: Vec<String> = service
+            .attributes
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        txt.sort();
+        if txt.is_empty() {
+            // A TXT record needs at least one (empty) string (RFC 6763 §6.1)
+            txt.push(String::new());
+        }
+        let address = (zone.zone_of(&target) && !service.address.is_unspecified()).then(|| {
+            let rdata = match service.address {
+                IpAddr::V4(address) => RData::A(A(address)),
+                IpAddr::V6(address) => RData::AAAA(AAAA(address)),
+            };
+            Record::from_rdata(target.clone(), ttl, rdata)
+        });
+
+        Ok(Self {
+            ptr: Record::from_rdata(service_type, ttl, RData::PTR(PTR(instance.clone()))),
+            instance_records: vec![
+                Record::from_rdata(
+                    instance.clone(),
+                    ttl,
+                    RData::SRV(SRV::new(service.priority, service.weight, service.port, target)),
+                ),
+                Record::from_rdata(instance.clone(), ttl, RData::TXT(TXT::new(txt))),
+            ],
+            zone,
+            instance,
+            address,
+        })
+    }
+
+    /// Update replacing the instance's records and requesting `lease`
+    fn add(&self, lease: Duration) -> Message {
+        let mut message = self.update();
+        // Drop stale SRV and TXT data left by an earlier registration
+        for record_type in [RecordType::SRV, RecordType::TXT] {
+            let mut delete = Record::with(self.instance.clone(), record_type, 0);
+            delete.set_dns_class(DNSClass::ANY);
+            message.add_update(delete);
+        }
+        message.add_update(self.ptr.clone());
+        message.add_updates(self.instance_records.clone());
+        if let Some(address) = &self.address {
+            message.add_update(address.clone());
+        }
+
+        let lease = u32::try_from(lease.as_secs()).unwrap_or(u32::MAX);
+        let mut edns = Edns::new();
+        edns.set_max_payload(1232);
+        edns.options_mut()
+            .insert(EdnsOption::Unknown(UPDATE_LEASE_OPTION, lease.to_be_bytes().to_vec()));
+        message.set_edns(edns);
+        message
+    }
+
+    /// Update removing everything [`Self::add`] created
+    fn delete(&self) -> Message {
+        let mut message = self.update();
+        message.add_update(delete_by_rdata(&self.ptr));
+        let mut delete = Record::with(self.instance.clone(), RecordType::ANY, 0);
+        delete.set_dns_class(DNSClass::ANY);
+        message.add_update(delete);
+        if let Some(address) = &self.address {
+            message.add_update(delete_by_rdata(address));
+        }
+        message
+    }
+
+    /// Empty update of the zone
+    fn update(&self) -> Message {
+        let mut zone = Query::new();
+        zone.set_name(self.zone.clone())
+            .set_query_class(DNSClass::IN)
+            .set_query_type(RecordType::SOA);
+        let mut message = Message::new();
+        message
+            .set_id(rand::random())
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Update)
+            .set_recursion_desired(false);
+        message.add_zone(zone);
+        message
+    }
+}
+
+/// Update record deleting exactly `record` (RFC 2136 §2.5.4)
+fn delete_by_rdata(record: &Record) -> Record {
+    let mut delete = record.clone();
+    delete.set_dns_class(DNSClass::NONE).set_ttl(0);
+    delete
+}
+
+/// Host label derived from an instance name, e.g. `My Printer` → `my-printer`
+fn host_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .take(63)
+        .collect();
+    match label.trim_matches('-') {
+        "" => "host".to_string(),
+        label => label.to_string(),
+    }
+}
+
+/// Records of a DNS response
+struct Answers {
+    answers: Vec<Record>,
+    additionals: Vec<Record>,
+    /// How long the absence of the queried records may be cached
+    negative_ttl: Option<u32>,
+}
+
+/// Fully qualify a domain name
+fn fqdn(domain: &str) -> String {
+    if domain.ends_with('.') {
+        domain.to_string()
+    } else {
+        format!("{domain}.")
+    }
+}
+
+/// Addresses of `host` among `records`, with the shortest of their TTLs
+fn address_records(records: &[Record], host: &Name) -> (Vec<IpAddr>, Duration) {
+    let mut ttl = u32::MAX;
+    let addresses = records
+        .iter()
+        .filter(|record| record.name() == host)
+        .filter_map(|record| {
+            let address = match record.data() {
+                Some(RData::A(a)) => IpAddr::V4(a.0),
+                Some(RData::AAAA(aaaa)) => IpAddr::V6(aaaa.0),
+                _ => return None,
+            };
+            ttl = ttl.min(record.ttl());
+            Some(address)
+        })
+        .collect();
+    (addresses, Duration::from_secs(u64::from(ttl)))
+}
+
+/// Split a TXT entry into key and value; a bare key has an empty value
+fn parse_txt_entry(entry: &[u8]) -> (String, String) {
+    let entry = String::from_utf8_lossy(entry);
+    match entry.split_once('=') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => (entry.into_owned(), String::new()),
+    }
+}
+
+/// TLS client configuration trusting the webpki root certificates
+#[cfg(feature = "dns-over-tls")]
+fn tls_client_config() -> Arc<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_client::rr::rdata::opt::EdnsCode;
+    use trust_dns_proto::rr::dnssec::tsig::TSigner;
+
+    fn record(name: &str, rdata: RData) -> Record {
+        Record::from_rdata(Name::from_ascii(name).unwrap(), 120, rdata)
+    }
+
+    fn ptr(name: &str, target: &str) -> Record {
+        record(name, RData::PTR(PTR(Name::from_ascii(target).unwrap())))
+    }
+
+    /// Answer queries from `records` over UDP
+    async fn dns_server(records: Vec<Record>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries()[0].clone();
+                let answers: Vec<Record> = records
+                    .iter()
+                    .filter(|r| r.name() == query.name() && r.record_type() == query.query_type())
+                    .cloned()
+                    .collect();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Query)
+                    .set_response_code(if answers.is_empty() { ResponseCode::NXDomain } else { ResponseCode::NoError });
+                response.add_query(query);
+                response.add_answers(answers);
+                socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_browse_wide_area_domains() {
+        let server = dns_server(vec![
+            ptr("b._dns-sd._udp.example.com.", "wide.example.net."),
+            ptr("_http._tcp.example.com.", "Web._http._tcp.example.com."),
+            record(
+                "Web._http._tcp.example.com.",
+                RData::SRV(SRV::new(1, 5, 8080, Name::from_ascii("web.example.com.").unwrap())),
+            ),
+            record("Web._http._tcp.example.com.", RData::TXT(TXT::new(vec!["path=/".into(), "secure".into()]))),
+            record("web.example.com.", RData::A(A("192.0.2.10".parse().unwrap()))),
+            ptr("_http._tcp.wide.example.net.", "Far._http._tcp.wide.example.net."),
+            record(
+                "Far._http._tcp.wide.example.net.",
+                RData::SRV(SRV::new(0, 0, 9090, Name::from_ascii("far.example.net.").unwrap())),
+            ),
+            record("far.example.net.", RData::AAAA(AAAA("2001:db8::1".parse().unwrap()))),
+            ptr("_services._dns-sd._udp.wide.example.net.", "_ipp._tcp.wide.example.net."),
+        ])
+        .await;
+        let dns_sd = DnsSdConfig::new().with_dns_server(server).with_zone("example.com");
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd).with_dns_sd(dns_sd);
+        let protocol = DnsSdProtocol::new(&config).await.unwrap();
+
+        assert_eq!(protocol.browse_domains().await, ["example.com.", "wide.example.net."]);
+
+        let mut services = protocol
+            .discover_services(vec![ServiceType::new("_http._tcp").unwrap()], Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(services.len(), 2);
+        assert_eq!((services[0].name.as_str(), services[0].port), ("Far", 9090));
+        assert_eq!(services[0].address, "2001:db8::1".parse::<IpAddr>().unwrap());
+        let web = &services[1];
+        assert_eq!((web.name.as_str(), web.port, web.priority, web.weight), ("Web", 8080, 1, 5));
+        assert_eq!(web.address, "192.0.2.10".parse::<IpAddr>().unwrap());
+        assert_eq!(web.hostname(), Some("web.example.com"));
+        assert_eq!(web.get_attribute("path").map(String::as_str), Some("/"));
+        assert_eq!(web.get_bool("secure").unwrap(), Some(true));
+
+        let service_types = protocol.enumerate_service_types(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(service_types, [ServiceType::new("_ipp._tcp").unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_target_addresses_are_cached() {
+        let server = dns_server(vec![
+            ptr("_http._tcp.example.com.", "Web._http._tcp.example.com."),
+            record(
+                "Web._http._tcp.example.com.",
+                RData::SRV(SRV::new(0, 0, 8080, Name::from_ascii("web.example.com.").unwrap())),
+            ),
+            record("web.example.com.", RData::A(A("192.0.2.10".parse().unwrap()))),
+            ptr("_http._tcp.example.com.", "Lost._http._tcp.example.com."),
+            record(
+                "Lost._http._tcp.example.com.",
+                RData::SRV(SRV::new(0, 0, 8080, Name::from_ascii("lost.example.com.").unwrap())),
+            ),
+        ])
+        .await;
+        let dns_sd = DnsSdConfig::new().with_dns_server(server).with_zone("example.com");
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd).with_dns_sd(dns_sd);
+        let mut protocol = DnsSdProtocol::new(&config).await.unwrap();
+        let registry = Arc::new(ServiceRegistry::new());
+        protocol.set_registry(registry.clone());
+
+        let services = protocol
+            .discover_services(vec![ServiceType::new("_http._tcp").unwrap()], Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(services.len(), 1);
+
+        let cache = registry.resolution_cache();
+        assert_eq!(
+            cache.get("web.example.com"),
+            Some(Resolution::Found(vec!["192.0.2.10".parse().unwrap()]))
+        );
+        assert_eq!(cache.get("lost.example.com."), Some(Resolution::NotFound));
+    }
+
+    /// Accept updates over UDP, forwarding each to the returned channel
+    ///
+    /// With a `key`, requests must be signed with it and responses are
+    /// signed in turn; anything else is answered with NOTAUTH.
+    async fn update_server(key: Option<TSigner>) -> (SocketAddr, tokio::sync::mpsc::UnboundedReceiver<Message>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (updates, received) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Update);
+                match key.as_ref().map(|key| key.verify_message_byte(None, &buf[..len], true)) {
+                    None => {
+                        response.set_response_code(ResponseCode::NoError);
+                    }
+                    Some(Ok((request_mac, _, _))) => {
+                        response.set_response_code(ResponseCode::NoError);
+                        sign_response(key.as_ref().unwrap(), &mut response, &request_mac);
+                    }
+                    Some(Err(_)) => {
+                        response.set_response_code(ResponseCode::NotAuth);
+                    }
+                }
+                updates.send(request).unwrap();
+                socket.send_to(&response.to_vec().unwrap(), peer).await.unwrap();
+            }
+        });
+        (addr, received)
+    }
+
+    /// Sign a response to a request with MAC `request_mac` (RFC 8945 §4.3.3)
+    fn sign_response(key: &TSigner, response: &mut Message, request_mac: &[u8]) {
+        use trust_dns_proto::rr::dnssec::rdata::tsig::{make_tsig_record, message_tbs, TSIG};
+
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let pre_tsig = TSIG::new(key.algorithm().clone(), now, key.fudge(), Vec::new(), response.id(), 0, Vec::new());
+        let tbs = message_tbs(Some(request_mac), response, &pre_tsig, key.signer_name()).unwrap();
+        let mac = key.sign(&tbs).unwrap();
+        response.add_tsig(make_tsig_record(key.signer_name().clone(), pre_tsig.set_mac(mac)));
+    }
+
+    fn printer() -> ServiceInfo {
+        ServiceInfo::new("My Printer", "_ipp._tcp", 631, Some(vec![("rp", "ipp/print"), ("color", "T")]))
+            .unwrap()
+            .with_address("192.0.2.7".parse().unwrap())
+            .with_srv(1, 5)
+            .with_ttl(Duration::from_secs(120))
+    }
+
+    fn dns_sd_config(server: SocketAddr) -> DiscoveryConfig {
+        let dns_sd = DnsSdConfig::new()
+            .with_dns_server(server)
+            .with_zone("example.com")
+            .with_lease(Duration::from_secs(600));
+        DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd).with_dns_sd(dns_sd)
+    }
+
+    #[tokio::test]
+    async fn test_register_with_dynamic_update() {
+        let (server, mut updates) = update_server(None).await;
+        let protocol = DnsSdProtocol::new(&dns_sd_config(server)).await.unwrap();
+
+        protocol.register_service(printer()).await.unwrap();
+        let update = updates.recv().await.unwrap();
+        assert_eq!(update.op_code(), OpCode::Update);
+        assert_eq!(update.zones()[0].name(), &Name::from_ascii("example.com.").unwrap());
+        let lease = update.extensions().as_ref().unwrap().option(EdnsCode::UL).cloned();
+        assert_eq!(lease, Some(EdnsOption::Unknown(UPDATE_LEASE_OPTION, 600u32.to_be_bytes().to_vec())));
+
+        let instance = Name::from_labels(vec!["My Printer".as_bytes(), b"_ipp", b"_tcp", b"example", b"com"]).unwrap();
+        let added: Vec<&Record> = update.updates().iter().filter(|r| r.dns_class() == DNSClass::IN).collect();
+        assert_eq!(added.len(), 4, "{added:?}");
+        assert!(added.iter().all(|r| r.ttl() == 120));
+        assert_eq!(added[0].name(), &Name::from_ascii("_ipp._tcp.example.com.").unwrap());
+        assert_eq!(added[0].data(), Some(&RData::PTR(PTR(instance.clone()))));
+        let target = Name::from_ascii("my-printer.example.com.").unwrap();
+        assert_eq!(added[1].data(), Some(&RData::SRV(SRV::new(1, 5, 631, target.clone()))));
+        assert_eq!(
+            added[2].data(),
+            Some(&RData::TXT(TXT::new(vec!["color=T".into(), "rp=ipp/print".into()])))
+        );
+        assert_eq!((added[3].name(), added[3].data()), (&target, Some(&RData::A(A("192.0.2.7".parse().unwrap())))));
+
+        protocol.unregister_service(&printer()).await.unwrap();
+        let update = updates.recv().await.unwrap();
+        let removed: Vec<(DNSClass, RecordType)> =
+            update.updates().iter().map(|r| (r.dns_class(), r.record_type())).collect();
+        assert_eq!(
+            removed,
+            [
+                (DNSClass::NONE, RecordType::PTR),
+                (DNSClass::ANY, RecordType::ANY),
+                (DNSClass::NONE, RecordType::A),
+            ]
+        );
+        assert_eq!(update.updates()[1].name(), &instance);
+        assert!(protocol.refreshes.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_registration_is_refreshed() {
+        let (server, mut updates) = update_server(None).await;
+        let dns_sd = dns_sd_config(server).dns_sd().clone().with_lease(Duration::from_secs(2));
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd).with_dns_sd(dns_sd);
+        let protocol = DnsSdProtocol::new(&config).await.unwrap();
+
+        protocol.register_service(printer()).await.unwrap();
+        updates.recv().await.unwrap();
+        let refresh = tokio::time::timeout(Duration::from_secs(3), updates.recv()).await.unwrap().unwrap();
+        assert_eq!(refresh.updates().len(), 6);
+    }
+
+    #[cfg(feature = "secure")]
+    #[tokio::test]
+    async fn test_updates_are_signed_with_tsig() {
+        use crate::security::tsig::{TsigAlgorithm, TsigKey, TsigKeyManager};
+
+        let key = TsigKey::new("update.example.com.", TsigAlgorithm::HmacSha256, b"zone secret", None).unwrap();
+        let (server, mut updates) = update_server(Some(key.signer().unwrap())).await;
+        let dns_sd = dns_sd_config(server).dns_sd().clone().with_tsig_key_name("update.example.com");
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd).with_dns_sd(dns_sd);
+
+        // The key must be provided
+        let protocol = DnsSdProtocol::new(&config).await.unwrap();
+        assert!(matches!(
+            protocol.register_service(printer()).await,
+            Err(DiscoveryError::Configuration(_))
+        ));
+
+        let keys = Arc::new(TsigKeyManager::new(Duration::from_secs(60)));
+        keys.add_key(TsigKey::new("update.example.com.", TsigAlgorithm::HmacSha256, b"wrong", None).unwrap())
+            .await;
+        let protocol = DnsSdProtocol::new(&config).await.unwrap().with_tsig_keys(keys.clone());
+        assert!(protocol.register_service(printer()).await.is_err());
+        updates.recv().await.unwrap();
+
+        // A rotated-in key replaces the old one
+        keys.add_key(key).await;
+        protocol.register_service(printer()).await.unwrap();
+        let update = updates.recv().await.unwrap();
+        assert_eq!(update.signature()[0].name(), &Name::from_ascii("update.example.com.").unwrap());
+    }
+
+    #[cfg(feature = "secure")]
+    #[tokio::test]
+    async fn test_updates_are_signed_with_sig0() {
+        use crate::security::sig0::{Sig0Algorithm, Sig0Key};
+        use trust_dns_proto::rr::dnssec::{rdata::DNSSECRData, Verifier};
+
+        let key = Sig0Key::generate("host.example.com.", Sig0Algorithm::EcdsaP256Sha256).unwrap();
+        let (server, mut updates) = update_server(None).await;
+        let dns_sd = dns_sd_config(server).dns_sd().clone().with_sig0_key_name("host.example.com");
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd).with_dns_sd(dns_sd.clone());
+        let protocol = DnsSdProtocol::new(&config).await.unwrap().with_sig0_key(key.clone());
+
+        protocol.register_service(printer()).await.unwrap();
+        let mut update = updates.recv().await.unwrap();
+        let Some(RData::DNSSEC(DNSSECRData::SIG(sig))) = update.take_signature()[0].data().cloned() else {
+            panic!("update is not signed with SIG(0)");
+        };
+        assert!(key.key_record().unwrap().verify_message(&update, sig.sig(), &sig).is_ok());
+
+        assert!(dns_sd.with_tsig_key_name("update.example.com").validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_requires_dns_server() {
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::DnsSd);
+        assert!(matches!(
+            DnsSdProtocol::new(&config).await,
+            Err(DiscoveryError::Configuration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_transport_requires_server_name() {
+        let dns_sd = DnsSdConfig::new()
+            .with_dns_server("127.0.0.1:853".parse().unwrap())
+            .with_transport(DnsTransport::Tls);
+        let config = DiscoveryConfig::new()
+            .with_protocol(ProtocolType::DnsSd)
+            .with_dns_sd(dns_sd.clone());
+        assert!(matches!(
+            DnsSdProtocol::new(&config).await,
+            Err(DiscoveryError::Configuration(_))
+        ));
+
+        assert!(dns_sd.with_tls_server_name("dns.example.com").validate().is_ok());
     }
 }
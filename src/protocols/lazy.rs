@@ -0,0 +1,360 @@
+//! Protocols started on first use
+//!
+//! A [`ProtocolFactory`] knows how to create a protocol without creating it.
+//! Wrapped in a [`LazyProtocol`], the protocol is only created when an
+//! operation first needs it, so building a
+//! [`ServiceDiscovery`](crate::ServiceDiscovery) does not wait for sockets to
+//! be bound or DNS servers to be reached. When creation fails, the error is
+//! returned to the operation and creation is retried in the background with
+//! exponential backoff.
+//!
+//! Built-in protocols are started this way when
+//! [`DiscoveryConfig::with_lazy_protocols`](crate::config::DiscoveryConfig::with_lazy_protocols)
+//! is set; custom ones are added with
+//! [`ServiceDiscoveryBuilder::with_protocol_factory`](crate::ServiceDiscoveryBuilder::with_protocol_factory).
+
+use super::{Capabilities, DiscoveryProtocol};
+use crate::{
+    error::{DiscoveryError, Result},
+    registry::ServiceRegistry,
+    rng::SharedRng,
+    safety::RetryConfig,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+/// Creates a protocol when it is first needed
+#[async_trait]
+pub trait ProtocolFactory: Send + Sync {
+    /// Type of the protocol created
+    fn protocol_type(&self) -> ProtocolType;
+
+    /// What the protocol can do, known before it is created
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Create the protocol
+    async fn create(&self) -> Result<Box<dyn DiscoveryProtocol + Send + Sync>>;
+}
+
+/// Creation state shared with the background retry task
+#[derive(Default)]
+struct LazyState {
+    protocol: Option<Arc<dyn DiscoveryProtocol + Send + Sync>>,
+    last_error: Option<String>,
+    retry: Option<JoinHandle<()>>,
+}
+
+impl LazyState {
+    fn is_retrying(&self) -> bool {
+        self.retry.as_ref().is_some_and(|retry| !retry.is_finished())
+    }
+}
+
+/// A protocol created by a [`ProtocolFactory`] on first use
+///
+/// Operations other than unregistration create the protocol if it does not
+/// exist yet; concurrent first uses create it once. While a failed creation
+/// is being retried in the background, operations fail straight away with the
+/// latest error. Once the retries run out, the next operation starts over.
+///
+/// Until the protocol exists it is reported unavailable and its capabilities
+/// are the factory's.
+pub struct LazyProtocol {
+    factory: Arc<dyn ProtocolFactory>,
+    registry: Option<Arc<ServiceRegistry>>,
+    retry: RetryConfig,
+    rng: SharedRng,
+    state: Arc<Mutex<LazyState>>,
+}
+
+impl LazyProtocol {
+    /// Wrap `factory`, retrying failed creations on the `retry` schedule
+    pub fn new(factory: Arc<dyn ProtocolFactory>, retry: RetryConfig) -> Self {
+        Self {
+            factory,
+            registry: None,
+            retry,
+            rng: SharedRng::new(),
+            state: Arc::new(Mutex::new(LazyState::default())),
+        }
+    }
+
+    /// Jitter retry delays with `rng`
+    pub fn with_rng(mut self, rng: SharedRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Whether the protocol has been created
+    pub async fn is_started(&self) -> bool {
+        self.state.lock().await.protocol.is_some()
+    }
+
+    /// The protocol, created now if needed
+    pub async fn get(&self) -> Result<Arc<dyn DiscoveryProtocol + Send + Sync>> {
+        let mut state = self.state.lock().await;
+        if let Some(protocol) = &state.protocol {
+            return Ok(Arc::clone(protocol));
+        }
+        let protocol_type = self.factory.protocol_type();
+        if state.is_retrying() {
+            return Err(DiscoveryError::protocol(format!(
+                "{protocol_type} is not started yet: {}",
+                state.last_error.as_deref().unwrap_or("retrying")
+            )));
+        }
+
+        match create(self.factory.as_ref(), self.registry.as_ref()).await {
+            Ok(protocol) => {
+                info!("Started {}", protocol_type);
+                state.protocol = Some(Arc::clone(&protocol));
+                Ok(protocol)
+            }
+            Err(e) => {
+                warn!("Failed to start {}, retrying in the background: {}", protocol_type, e);
+                state.last_error = Some(e.to_string());
+                state.retry = Some(tokio::spawn(retry(
+                    Arc::clone(&self.factory),
+                    self.registry.clone(),
+                    self.retry,
+                    self.rng.clone(),
+                    Arc::downgrade(&self.state),
+                )));
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for LazyProtocol {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.try_lock()
+            && let Some(retry) = state.retry.take()
+        {
+            retry.abort();
+        }
+    }
+}
+
+async fn create(
+    factory: &dyn ProtocolFactory,
+    registry: Option<&Arc<ServiceRegistry>>,
+) -> Result<Arc<dyn DiscoveryProtocol + Send + Sync>> {
+    let mut protocol = factory.create().await?;
+    if let Some(registry) = registry {
+        protocol.set_registry(Arc::clone(registry));
+    }
+    Ok(Arc::from(protocol))
+}
+
+/// Retry creation until it succeeds, the schedule runs out or the
+/// [`LazyProtocol`] is dropped
+async fn retry(
+    factory: Arc<dyn ProtocolFactory>,
+    registry: Option<Arc<ServiceRegistry>>,
+    schedule: RetryConfig,
+    rng: SharedRng,
+    state: Weak<Mutex<LazyState>>,
+) {
+    let protocol_type = factory.protocol_type();
+    for delay in schedule.delays_with(rng) {
+        tokio::time::sleep(delay).await;
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        match create(factory.as_ref(), registry.as_ref()).await {
+            Ok(protocol) => {
+                info!("Started {} after retrying", protocol_type);
+                state.lock().await.protocol = Some(protocol);
+                return;
+            }
+            Err(e) => {
+                debug!("Retry of {} failed: {}", protocol_type, e);
+                state.lock().await.last_error = Some(e.to_string());
+            }
+        }
+    }
+    warn!("Gave up retrying {} until it is next used", protocol_type);
+}
+
+#[async_trait]
+impl DiscoveryProtocol for LazyProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        self.factory.protocol_type()
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        self.get().await?.discover_services(service_types, timeout).await
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        self.get().await?.register_service(service).await
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        // Nothing was registered with a protocol that never started
+        let protocol = self.state.lock().await.protocol.clone();
+        match protocol {
+            Some(protocol) => protocol.unregister_service(service).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        self.get().await?.verify_service(service).await
+    }
+
+    async fn is_available(&self) -> bool {
+        let protocol = self.state.lock().await.protocol.clone();
+        match protocol {
+            Some(protocol) => protocol.is_available().await,
+            None => false,
+        }
+    }
+
+    fn set_registry(&mut self, registry: Arc<ServiceRegistry>) {
+        self.registry = Some(registry);
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        let mut state = self.state.lock().await;
+        if let Some(retry) = state.retry.take() {
+            retry.abort();
+        }
+        match state.protocol.take() {
+            Some(protocol) => protocol.shutdown().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn enumerate_service_types(&self, timeout: Duration) -> Result<Vec<ServiceType>> {
+        self.get().await?.enumerate_service_types(timeout).await
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        match self.state.try_lock() {
+            Ok(state) => state
+                .protocol
+                .as_ref()
+                .map_or_else(|| self.factory.capabilities(), |protocol| protocol.capabilities()),
+            Err(_) => self.factory.capabilities(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Creates a protocol that finds nothing, after failing `failures` times
+    struct FlakyFactory {
+        failures: u32,
+        attempts: Arc<AtomicU32>,
+    }
+
+    struct Quiet;
+
+    #[async_trait]
+    impl DiscoveryProtocol for Quiet {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn discover_services(&self, _: Vec<ServiceType>, _: Option<Duration>) -> Result<Vec<ServiceInfo>> {
+            Ok(Vec::new())
+        }
+
+        async fn register_service(&self, _: ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unregister_service(&self, _: &ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn verify_service(&self, _: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _: Arc<ServiceRegistry>) {}
+    }
+
+    #[async_trait]
+    impl ProtocolFactory for FlakyFactory {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn create(&self) -> Result<Box<dyn DiscoveryProtocol + Send + Sync>> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(DiscoveryError::network("socket busy"));
+            }
+            Ok(Box::new(Quiet))
+        }
+    }
+
+    fn lazy(failures: u32) -> (LazyProtocol, Arc<AtomicU32>) {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let factory = FlakyFactory { failures, attempts: Arc::clone(&attempts) };
+        let retry = RetryConfig {
+            max_retries: 5,
+            initial_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(20),
+            jitter: false,
+        };
+        (LazyProtocol::new(Arc::new(factory), retry), attempts)
+    }
+
+    #[tokio::test]
+    async fn test_created_on_first_use() {
+        let (protocol, attempts) = lazy(0);
+        assert!(!protocol.is_available().await);
+        let service = ServiceInfo::new("API", "_http._tcp", 8080, None).unwrap();
+        protocol.unregister_service(&service).await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+
+        protocol.discover_services(Vec::new(), None).await.unwrap();
+        protocol.discover_services(Vec::new(), None).await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(protocol.is_available().await);
+    }
+
+    #[tokio::test]
+    async fn test_failed_creation_retried_in_background() {
+        let (protocol, attempts) = lazy(2);
+        let err = protocol.discover_services(Vec::new(), None).await.unwrap_err();
+        assert!(err.to_string().contains("socket busy"), "{err}");
+
+        // Operations do not wait for the retries
+        let err = protocol.discover_services(Vec::new(), None).await.unwrap_err();
+        assert!(err.to_string().contains("not started yet"), "{err}");
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while !protocol.is_started().await {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        protocol.discover_services(Vec::new(), None).await.unwrap();
+    }
+}
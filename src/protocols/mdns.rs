@@ -1,27 +1,64 @@
 //! mDNS (Multicast DNS) protocol implementation
+//!
+//! Each service type is browsed continuously once it has been asked for,
+//! rather than with a fresh query per discovery call. This lets the mdns-sd
+//! daemon follow the RFC 6762 query schedule: retransmissions back off
+//! exponentially (1 s, 2 s, 4 s, ... up to an hour) and carry the records it
+//! has already cached as Known Answers, so responders stay quiet about them.
+//! The first query for a type is delayed by a random 20-120 ms so many hosts
+//! starting at once don't query in lockstep.
 
 use crate::{
-    config::DiscoveryConfig,
+    config::{DiscoveryConfig, SocketOptions},
     error::{DiscoveryError, Result},
     registry::ServiceRegistry,
+    rng::SharedRng,
     service::ServiceInfo,
     types::{ProtocolType, ServiceType},
+    utils::network,
+    verification::ConnectivityVerifier,
 };
 use async_trait::async_trait;
-use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo as MdnsServiceInfo};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo as MdnsServiceInfo, UnregisterStatus};
 use std::{
     collections::HashMap,
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+/// DNS-SD meta-query name listing the service types on the link (RFC 6763 §9)
+const SERVICE_TYPE_ENUMERATION: &str = "_services._dns-sd._udp.local.";
+
+/// Range of the random delay before the first query for a service type (RFC 6762 §5.2)
+const INITIAL_QUERY_DELAY_MS: std::ops::RangeInclusive<u64> = 20..=120;
+
+/// How long unregistering waits for the daemon to send the goodbye packets
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What mDNS can do
+pub(super) const CAPABILITIES: super::Capabilities = super::Capabilities {
+    supports_ipv6: true,
+    ..super::Capabilities::LINK_LOCAL
+};
+
+/// A continuous browse for one service type
+#[derive(Clone)]
+struct Browse {
+    /// Resolved instances by full name
+    services: Arc<Mutex<HashMap<String, ServiceInfo>>>,
+    started: Instant,
+}
+
 /// mDNS protocol implementation for service discovery
 pub struct MdnsProtocol {
     daemon: Arc<ServiceDaemon>,
-    #[allow(dead_code)]
     config: DiscoveryConfig,
     /// Service registry for managing discovered and registered services
     registry: Option<Arc<ServiceRegistry>>,
+    /// Continuous browses by mDNS service type
+    browses: Arc<tokio::sync::Mutex<HashMap<String, Browse>>>,
+    /// Source of the initial query delays
+    rng: SharedRng,
 }
 
 impl MdnsProtocol {
@@ -38,6 +75,19 @@ impl MdnsProtocol {
         // Try to create daemon with a retry mechanism
         let daemon = Self::create_daemon_with_retry().await?;
 
+        // Restrict the daemon to the selected interfaces, if any, and to
+        // those on allowed networks
+        let selected = config.mdns().interfaces().or(config.interfaces());
+        if !config.allowed_networks().is_empty() {
+            let interfaces = network::scoped_interfaces(selected, config.allowed_networks())?;
+            daemon.disable_interface(IfKind::All)?;
+            daemon.enable_interface(interfaces.iter().map(|iface| &iface.name).collect::<Vec<_>>())?;
+        } else if let Some(interfaces) = selected {
+            daemon.disable_interface(IfKind::All)?;
+            daemon.enable_interface(interfaces.iter().collect::<Vec<_>>())?;
+        }
+        Self::apply_socket_options(&daemon, config.mdns().socket_options())?;
+
         // Create with default registry if one isn't set later
         let registry = Some(Arc::new(ServiceRegistry::new()));
 
@@ -45,9 +95,28 @@ impl MdnsProtocol {
             daemon: Arc::new(daemon),
             config: config.clone(),
             registry,
+            browses: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+            rng: SharedRng::from_seed(config.seed()),
         })
     }
 
+    /// Apply the socket options the daemon exposes and warn about the rest
+    fn apply_socket_options(daemon: &ServiceDaemon, options: &SocketOptions) -> Result<()> {
+        if let Some(enable) = options.multicast_loop() {
+            daemon.set_multicast_loop_v4(enable)?;
+            daemon.set_multicast_loop_v6(enable)?;
+        }
+        // The daemon sends on every interface it listens on
+        if let Some(interface) = options.outgoing_interface() {
+            daemon.disable_interface(IfKind::All)?;
+            daemon.enable_interface(interface)?;
+        }
+        if options.multicast_ttl().is_some() || options.recv_buffer_size().is_some() || options.reuse_port() {
+            tracing::warn!("The mdns-sd backend ignores the multicast TTL, receive buffer and SO_REUSEPORT options");
+        }
+        Ok(())
+    }
+
     /// Create mDNS daemon with retry logic
     async fn create_daemon_with_retry() -> Result<ServiceDaemon> {
         // Try multiple times with increasing delays
@@ -69,12 +138,76 @@ impl MdnsProtocol {
         Err(DiscoveryError::mdns("Unexpected error in daemon creation"))
     }
 
-    #[allow(dead_code)]
-    fn convert_to_service_info(&self, mdns_info: MdnsServiceInfo) -> Result<ServiceInfo> {
+    /// Get the browse for a service type, starting one if needed
+    async fn browse(&self, service_type: &str) -> Result<Browse> {
+        let mut browses = self.browses.lock().await;
+        if let Some(browse) = browses.get(service_type) {
+            return Ok(browse.clone());
+        }
+
+        tokio::time::sleep(Duration::from_millis(self.rng.random_range(INITIAL_QUERY_DELAY_MS))).await;
+        let receiver = self.daemon.browse(service_type)
+            .map_err(|e| DiscoveryError::mdns(format!("Failed to browse services: {e}")))?;
+
+        let browse = Browse {
+            services: Arc::new(Mutex::new(HashMap::new())),
+            started: Instant::now(),
+        };
+        browses.insert(service_type.to_string(), browse.clone());
+
+        let services = Arc::clone(&browse.services);
+        let all_browses = Arc::clone(&self.browses);
+        let service_type = service_type.to_string();
+        tokio::spawn(async move {
+            while let Ok(event) = receiver.recv_async().await {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let fullname = info.get_fullname().to_string();
+                        if let Ok(service_info) = Self::convert_to_service_info(info) {
+                            tracing::debug!("Discovered service: {}", service_info.name());
+                            services.lock().unwrap_or_else(|e| e.into_inner()).insert(fullname, service_info);
+                        }
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        services.lock().unwrap_or_else(|e| e.into_inner()).remove(&fullname);
+                    }
+                    ServiceEvent::SearchStopped(_) => {
+                        tracing::debug!("mDNS search stopped");
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            all_browses.lock().await.remove(&service_type);
+        });
+
+        Ok(browse)
+    }
+
+    /// Stop all continuous browses
+    pub async fn stop_browsing(&self) {
+        for service_type in self.browses.lock().await.keys() {
+            if let Err(e) = self.daemon.stop_browse(service_type) {
+                tracing::debug!("Failed to stop browsing {}: {}", service_type, e);
+            }
+        }
+    }
+
+    /// Counters kept by the mDNS daemon, e.g. `browse` and `known-answer-suppression`
+    pub async fn daemon_metrics(&self) -> Result<HashMap<String, i64>> {
+        let receiver = self.daemon.get_metrics()?;
+        receiver
+            .recv_async()
+            .await
+            .map_err(|e| DiscoveryError::mdns(format!("Failed to read daemon metrics: {e}")))
+    }
+
+    fn convert_to_service_info(mdns_info: MdnsServiceInfo) -> Result<ServiceInfo> {
         let host = mdns_info.get_hostname().to_string();
         let service_type = ServiceType::new(mdns_info.get_type())?;
         let addresses = mdns_info.get_addresses();
         let port = mdns_info.get_port();
+        let (priority, weight) = (mdns_info.get_priority(), mdns_info.get_weight());
 
         if addresses.is_empty() {
             return Err(DiscoveryError::mdns("Service has no addresses"));
@@ -84,7 +217,7 @@ impl MdnsProtocol {
         let attributes: HashMap<String, String> = HashMap::new(); // For now, skip TXT record parsing
 
         let mut service = ServiceInfo::new(
-            host,
+            host.clone(),
             service_type,
             port,
             None,
@@ -93,7 +226,9 @@ impl MdnsProtocol {
         service = service
             .with_protocol_type(ProtocolType::Mdns)
             .with_address(*addresses.iter().next().unwrap())
-            .with_attributes(attributes);
+            .with_attributes(attributes)
+            .with_srv(priority, weight)
+            .with_host(host);
 
         Ok(service)
     }
@@ -109,14 +244,47 @@ impl super::DiscoveryProtocol for MdnsProtocol {
         self.registry = Some(registry);
     }
 
+    async fn enumerate_service_types(&self, timeout: Duration) -> Result<Vec<ServiceType>> {
+        let receiver = self
+            .daemon
+            .browse(SERVICE_TYPE_ENUMERATION)
+            .map_err(|e| DiscoveryError::mdns(format!("Failed to browse service types: {e}")))?;
+
+        // Answers name the types, e.g. `_http._tcp.local.`
+        let mut names = Vec::new();
+        let deadline = tokio::time::Instant::now() + timeout;
+        while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, receiver.recv_async()).await {
+            if let ServiceEvent::ServiceFound(_, name) = event {
+                names.push(name);
+            }
+        }
+        if let Err(e) = self.daemon.stop_browse(SERVICE_TYPE_ENUMERATION) {
+            tracing::debug!("Failed to stop service type enumeration: {}", e);
+        }
+
+        let mut service_types: Vec<ServiceType> = names
+            .iter()
+            .filter_map(|name| ServiceType::new(name.trim_end_matches('.').trim_end_matches(".local")).ok())
+            .collect();
+        // Our own registrations may not be answered back to us
+        if let Some(registry) = &self.registry {
+            for service in registry.get_local_services().await {
+                if service.protocol_type == ProtocolType::Mdns {
+                    service_types.push(service.service_type);
+                }
+            }
+        }
+        Ok(service_types)
+    }
+
     async fn discover_services(
         &self,
         service_types: Vec<ServiceType>,
         timeout: Option<Duration>,
     ) -> Result<Vec<ServiceInfo>> {
-        let mut discovered_services = Vec::new();
         let discovery_timeout = timeout.unwrap_or(Duration::from_secs(5));
-        
+
+        let mut browses = Vec::with_capacity(service_types.len());
         for service_type in &service_types {
             // Format service type for mDNS - ensure it ends with .local.
             let service_type_str = if service_type.to_string().ends_with(".local.") {
@@ -124,48 +292,25 @@ impl super::DiscoveryProtocol for MdnsProtocol {
             } else {
                 format!("{service_type}.local.")
             };
-            
-            let receiver = self.daemon.browse(&service_type_str)
-                .map_err(|e| DiscoveryError::mdns(format!("Failed to browse services: {e}")))?;
-
-            // Collect services with timeout
-            let mut services = Vec::new();
-            let start_time = std::time::Instant::now();
-            let per_attempt_timeout = std::cmp::min(discovery_timeout, Duration::from_millis(500));
-            
-            while start_time.elapsed() < discovery_timeout {
-                match receiver.recv_timeout(per_attempt_timeout) {
-                    Ok(event) => {
-                        match event {
-                            ServiceEvent::ServiceResolved(info) => {
-                                if let Ok(service_info) = self.convert_to_service_info(info) {
-                                    services.push(service_info);
-                                    tracing::debug!("Discovered service: {}", services.last().unwrap().name());
-                                }
-                            },
-                            ServiceEvent::SearchStopped(_) => {
-                                tracing::debug!("mDNS search stopped");
-                                break;
-                            },
-                            _ => {
-                                // Continue for other events
-                                continue;
-                            }
-                        }
-                    },
-                    Err(_) => {
-                        // Timeout - check if we should continue
-                        if start_time.elapsed() >= discovery_timeout {
-                            break;
-                        }
-                        continue;
-                    }
-                }
-            }
-            
-            discovered_services.extend(services);
+            browses.push(self.browse(&service_type_str).await?);
         }
 
+        // New browses collect answers for the whole timeout; browses that have
+        // been running longer already know the current set of services
+        let remaining = browses
+            .iter()
+            .map(|browse| discovery_timeout.saturating_sub(browse.started.elapsed()))
+            .max()
+            .unwrap_or_default();
+        tokio::time::sleep(remaining).await;
+
+        let mut discovered_services: Vec<ServiceInfo> = browses
+            .iter()
+            .flat_map(|browse| {
+                browse.services.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect::<Vec<_>>()
+            })
+            .collect();
+
         // Also include locally registered services that match the requested types
         if let Some(registry) = &self.registry {
             let local_services = registry.get_local_services().await;
@@ -205,8 +350,12 @@ impl super::DiscoveryProtocol for MdnsProtocol {
             format!("{}.local.", service.service_type)
         };
 
-        // Create hostname for the service
-        let hostname = format!("{}.local.", service.name);
+        // Use the service's own hostname, the configured one, or derive one
+        // from the instance name
+        let hostname = match service.hostname().or(self.config.mdns().hostname()) {
+            Some(hostname) => format!("{}.", hostname.trim_end_matches('.')),
+            None => format!("{}.local.", service.name),
+        };
 
         // Use address directly since mdns-sd expects AsIpAddrs
         let mdns_info = MdnsServiceInfo::new(
@@ -218,6 +367,14 @@ impl super::DiscoveryProtocol for MdnsProtocol {
             txt_records.as_slice(),
         ).map_err(|e| DiscoveryError::mdns(format!("Failed to create mDNS service info: {e}")))?;
 
+        if (service.priority, service.weight) != (0, 0) {
+            // mdns-sd always announces priority and weight 0
+            tracing::debug!(
+                "Announcing {} over mDNS without its SRV priority {} and weight {}",
+                service.name, service.priority, service.weight
+            );
+        }
+
         self.daemon.register(mdns_info)
             .map_err(|e| DiscoveryError::mdns(format!("Failed to register service: {e}")))?;
 
@@ -239,31 +396,45 @@ impl super::DiscoveryProtocol for MdnsProtocol {
         
         let full_service_name = format!("{}.{}", service.name, service_type_str);
         
-        self.daemon.unregister(&full_service_name)
+        let status = self.daemon.unregister(&full_service_name)
             .map_err(|e| DiscoveryError::mdns(format!("Failed to unregister service: {e}")))?;
-        
+
+        // The daemon answers once the goodbye packets (TTL 0) are on the wire
+        match tokio::time::timeout(GOODBYE_TIMEOUT, status.recv_async()).await {
+            Ok(Ok(UnregisterStatus::OK)) => {}
+            Ok(Ok(UnregisterStatus::NotFound)) => {
+                tracing::debug!("mDNS service {} was not registered", full_service_name);
+            }
+            _ => tracing::warn!("No confirmation that goodbyes for {} were sent", full_service_name),
+        }
+
         // Remove from registry
         if let Some(registry) = &self.registry {
-            let service_id = format!("{}:{}:{}", service.name, service.service_type, service.port);
-            registry.unregister_local_service(&service_id).await?;
+            registry.unregister_local_service(&service.service_id()).await?;
         }
         
         Ok(())
     }
 
     async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
-        // Check if service is in our registry of registered services
-        if let Some(registry) = &self.registry {
-            let local_services = registry.get_local_services().await;
-            Ok(local_services.iter().any(|s| s.name == service.name))
-        } else {
-            Ok(false)
-        }
+        let result = ConnectivityVerifier::from_health_config(self.config.health())
+            .verify(service)
+            .await;
+        Ok(result.healthy)
     }
 
     async fn is_available(&self) -> bool {
         true
     }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.daemon.shutdown()?;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> super::Capabilities {
+        CAPABILITIES
+    }
 }
 
 #[cfg(test)]
@@ -317,4 +488,25 @@ mod tests {
         // Unregister service
         protocol.unregister_service(&service).await.unwrap();
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_continuous_browse_is_reused() {
+        use crate::protocols::DiscoveryProtocol;
+
+        let protocol = MdnsProtocol::new(&crate::config::DiscoveryConfig::new()).await.unwrap();
+        let service_types = vec![ServiceType::new("_browsetest._tcp").unwrap()];
+        let timeout = Some(Duration::from_secs(1));
+
+        let start = Instant::now();
+        protocol.discover_services(service_types.clone(), timeout).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // The browse keeps running, so a repeat call doesn't query again
+        let start = Instant::now();
+        protocol.discover_services(service_types, timeout).await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(protocol.browses.lock().await.len(), 1);
+
+        protocol.stop_browsing().await;
+    }
+}
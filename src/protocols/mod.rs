@@ -1,19 +1,48 @@
 //! Protocol implementations for service discovery
 
 use crate::{
-    config::DiscoveryConfig,
-    error::{DiscoveryError, Result},
+    config::{ContainerConfig, DiscoveryConfig, MdnsBackend},
+    container::{self, MulticastCheck},
+    error::{DiscoveryError, ErrorContext, Result},
+    logging::{operation_span, Redactor},
     registry::ServiceRegistry,
+    remote::RemoteDiscovery,
+    rng::SharedRng,
+    safety::{concurrency::ConcurrencyLimiter, CircuitState, Operation, SafetyManager},
     service::ServiceInfo,
     types::{ProtocolType, ServiceType},
+    utils::network,
+    verification::ConnectivityVerifier,
 };
 use async_trait::async_trait;
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tracing::warn;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn, Instrument};
 
 pub mod mdns;
 pub mod upnp;
 pub mod dns_sd;
+pub mod lazy;
+pub mod port_mapping;
+pub mod reflector;
+pub mod remote;
+#[cfg(feature = "quic")]
+pub mod quic;  // Announcements through a rendezvous server over QUIC
+#[cfg(feature = "platform-discovery")]
+pub mod platform;  // mDNS through NsdManager, Bonjour and other OS APIs
+#[cfg(all(windows, feature = "windows-dns"))]
+#[allow(unsafe_code)]  // Calls into dnsapi.dll
+pub mod windows_dns;
+mod peer_limit;
+
+use lazy::{LazyProtocol, ProtocolFactory};
 
 // #[cfg(feature = "simple-mdns")]
 // pub mod simple_mdns; // Disabled due to API incompatibilities
@@ -45,63 +74,598 @@ pub trait DiscoveryProtocol: Send + Sync {
 
     /// Set the service registry for this protocol
     fn set_registry(&mut self, registry: Arc<ServiceRegistry>);
+
+    /// Enumerate the service types present on the network
+    ///
+    /// Protocols without a way to enumerate types return an empty list.
+    async fn enumerate_service_types(&self, _timeout: Duration) -> Result<Vec<ServiceType>> {
+        Ok(Vec::new())
+    }
+
+    /// Stop listening and release the protocol's sockets
+    ///
+    /// Called when the protocol is disabled at runtime. A protocol handed to
+    /// the builder is reused if it is enabled again, so it should be able to
+    /// start over afterwards. Protocols whose resources go away when dropped
+    /// need not implement it.
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// What the protocol can do
+    ///
+    /// The [`ProtocolManager`] only routes operations to protocols that
+    /// support them. Defaults to a link-local, IPv4-only protocol that can
+    /// register, browse and verify.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// What a protocol can do, as reported by [`DiscoveryProtocol::capabilities`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// Services can be registered for advertisement
+    pub supports_registration: bool,
+    /// Services can be discovered
+    pub supports_browsing: bool,
+    /// The protocol can check whether a discovered service is alive
+    pub supports_verification: bool,
+    /// The protocol works over IPv6
+    pub supports_ipv6: bool,
+    /// The protocol reaches beyond the local link
+    pub wide_area: bool,
+}
+
+impl Capabilities {
+    /// A link-local, IPv4-only protocol that can register, browse and verify
+    pub const LINK_LOCAL: Self = Self {
+        supports_registration: true,
+        supports_browsing: true,
+        supports_verification: true,
+        supports_ipv6: false,
+        wide_area: false,
+    };
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::LINK_LOCAL
+    }
+}
+
+/// Per-protocol outcome of an operation run with several protocols
+///
+/// Returned by the [`ProtocolManager`] methods that fan out to every enabled
+/// protocol, so callers can see which protocols succeeded and why the others
+/// failed. Simple callers can turn it into a plain [`Result`] with
+/// [`into_result`](Self::into_result), which only fails when no protocol
+/// succeeded.
+#[derive(Debug, Clone)]
+pub struct MultiProtocolResult<T> {
+    successes: Vec<(ProtocolType, T)>,
+    failures: Vec<(ProtocolType, DiscoveryError)>,
+}
+
+impl<T> Default for MultiProtocolResult<T> {
+    fn default() -> Self {
+        Self {
+            successes: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+impl<T> MultiProtocolResult<T> {
+    /// Create an empty result
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome for one protocol
+    pub fn push(&mut self, protocol_type: ProtocolType, result: Result<T>) {
+        match result {
+            Ok(value) => self.successes.push((protocol_type, value)),
+            Err(e) => self.failures.push((protocol_type, e)),
+        }
+    }
+
+    /// Values of the protocols that succeeded
+    pub fn successes(&self) -> &[(ProtocolType, T)] {
+        &self.successes
+    }
+
+    /// Errors of the protocols that failed
+    pub fn failures(&self) -> &[(ProtocolType, DiscoveryError)] {
+        &self.failures
+    }
+
+    /// Protocols that succeeded
+    pub fn succeeded(&self) -> Vec<ProtocolType> {
+        self.successes.iter().map(|(protocol_type, _)| *protocol_type).collect()
+    }
+
+    /// Protocols that failed
+    pub fn failed(&self) -> Vec<ProtocolType> {
+        self.failures.iter().map(|(protocol_type, _)| *protocol_type).collect()
+    }
+
+    /// Whether at least one protocol took part and none failed
+    pub fn is_complete(&self) -> bool {
+        !self.successes.is_empty() && self.failures.is_empty()
+    }
+
+    /// The successful values, or an error if every protocol failed
+    ///
+    /// A single failure is returned as is; several are summarised in one
+    /// protocol error. No protocol taking part at all is not a failure.
+    pub fn into_result(self) -> Result<Vec<T>> {
+        if !self.successes.is_empty() || self.failures.is_empty() {
+            return Ok(self.successes.into_iter().map(|(_, value)| value).collect());
+        }
+        let mut failures = self.failures;
+        if failures.len() == 1 {
+            return Err(failures.remove(0).1);
+        }
+        let summary = failures
+            .iter()
+            .map(|(protocol_type, e)| format!("{protocol_type}: {e}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        Err(DiscoveryError::protocol(format!("All protocols failed ({summary})")))
+    }
+}
+
+impl<T> MultiProtocolResult<Vec<T>> {
+    /// The successful values merged into one list, or an error if every
+    /// protocol failed
+    pub fn flatten(self) -> Result<Vec<T>> {
+        self.into_result().map(|lists| lists.into_iter().flatten().collect())
+    }
+}
+
+impl<T> From<MultiProtocolResult<T>> for Result<Vec<T>> {
+    fn from(result: MultiProtocolResult<T>) -> Self {
+        result.into_result()
+    }
+}
+
+/// Context for errors from `protocol_type` during `operation`
+///
+/// The service type is only recorded when the operation concerned exactly one.
+fn error_context(protocol_type: ProtocolType, operation: Operation, service_types: &[ServiceType]) -> ErrorContext {
+    let context = ErrorContext::new().protocol(protocol_type).operation(operation);
+    match service_types {
+        [service_type] => context.service_type(service_type.clone()),
+        _ => context,
+    }
+}
+
+/// Extra time protocols get to hand back results after the discovery timeout
+///
+/// Protocols browse for the whole timeout and need a moment afterwards to
+/// return what they collected.
+pub const COLLECTION_GRACE: Duration = Duration::from_secs(1);
+
+/// Fill in the interface of services whose protocol didn't record one,
+/// using the interface whose network contains the service address
+fn tag_interfaces(services: &mut [ServiceInfo]) {
+    if services.iter().all(|service| service.interface.is_some()) {
+        return;
+    }
+    let interfaces = match network::get_network_interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            debug!("Cannot tag services with interfaces: {}", e);
+            return;
+        }
+    };
+    for service in services.iter_mut().filter(|service| service.interface.is_none()) {
+        service.interface = network::interface_for_address(&interfaces, &service.address);
+    }
+}
+
+/// Health of a single protocol as reported by [`ProtocolManager::health_check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProtocolHealth {
+    /// Whether the protocol reports itself as available
+    pub available: bool,
+    /// State of the protocol's circuit breaker
+    pub circuit: CircuitState,
+}
+
+impl ProtocolHealth {
+    /// Available and not cut off by an open circuit breaker
+    pub fn is_healthy(&self) -> bool {
+        self.available && self.circuit != CircuitState::Open
+    }
+}
+
+/// Which protocols a [`ProtocolManager`] started, and why the others did not
+///
+/// A protocol that fails to start, for example because its socket cannot be
+/// bound, is left out of the manager and listed here with the error.
+/// Protocols handed to the manager ready-made always count as started.
+/// Built-in protocols started lazily, see [`lazy`], are listed as deferred.
+#[derive(Debug, Clone, Default)]
+pub struct InitReport {
+    started: Vec<ProtocolType>,
+    deferred: Vec<ProtocolType>,
+    failures: Vec<(ProtocolType, String)>,
+}
+
+impl InitReport {
+    /// Protocols that started
+    pub fn started(&self) -> &[ProtocolType] {
+        &self.started
+    }
+
+    /// Protocols that will be started on first use
+    pub fn deferred(&self) -> &[ProtocolType] {
+        &self.deferred
+    }
+
+    /// Protocols that failed to start, with the reason
+    pub fn failures(&self) -> &[(ProtocolType, String)] {
+        &self.failures
+    }
+
+    /// Whether no protocol started or is waiting to be started
+    pub fn is_empty(&self) -> bool {
+        self.started.is_empty() && self.deferred.is_empty()
+    }
+
+    fn fail(&mut self, protocol_type: ProtocolType, error: DiscoveryError) {
+        warn!("Failed to start {}: {}", protocol_type, error);
+        self.failures.push((protocol_type, error.to_string()));
+    }
+}
+
+impl fmt::Display for InitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let started: Vec<String> = self.started.iter().map(ToString::to_string).collect();
+        if started.is_empty() {
+            f.write_str("no protocol started")?;
+        } else {
+            write!(f, "started {}", started.join(", "))?;
+        }
+        if !self.deferred.is_empty() {
+            let deferred: Vec<String> = self.deferred.iter().map(ToString::to_string).collect();
+            write!(f, "; deferred {}", deferred.join(", "))?;
+        }
+        for (protocol_type, reason) in &self.failures {
+            write!(f, "; {protocol_type} failed: {reason}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the built-in implementation of a protocol type
+struct BuiltinFactory {
+    protocol_type: ProtocolType,
+    config: DiscoveryConfig,
+}
+
+#[async_trait]
+impl ProtocolFactory for BuiltinFactory {
+    fn protocol_type(&self) -> ProtocolType {
+        self.protocol_type
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        match self.protocol_type {
+            ProtocolType::Mdns => mdns::CAPABILITIES,
+            ProtocolType::DnsSd => dns_sd::CAPABILITIES,
+            ProtocolType::Upnp => Capabilities::default(),
+        }
+    }
+
+    async fn create(&self) -> Result<Box<dyn DiscoveryProtocol + Send + Sync>> {
+        match self.protocol_type {
+            ProtocolType::Mdns if self.config.mdns().backend() == MdnsBackend::WindowsDnsApi => {
+                windows_dns_backend(&self.config)
+            }
+            #[cfg(not(feature = "simple-mdns"))]
+            ProtocolType::Mdns => Ok(Box::new(mdns::MdnsProtocol::new(&self.config).await?)),
+            #[cfg(all(feature = "simple-mdns", not(feature = "mdns")))]
+            ProtocolType::Mdns => Ok(Box::new(simple_mdns::SimpleMdnsProtocol::new(&self.config).await?)),
+            // The simple-mdns implementation is disabled due to API incompatibilities
+            #[cfg(all(feature = "simple-mdns", feature = "mdns"))]
+            ProtocolType::Mdns => Err(DiscoveryError::configuration("The simple-mdns backend is disabled")),
+            ProtocolType::Upnp => Ok(Box::new(upnp::SsdpProtocol::new(self.config.clone())?)),
+            ProtocolType::DnsSd => Ok(Box::new(dns_sd::DnsSdProtocol::new(&self.config).await?)),
+        }
+    }
+}
+
+/// mDNS through the Windows DNS API
+#[cfg(all(windows, feature = "windows-dns"))]
+fn windows_dns_backend(config: &DiscoveryConfig) -> Result<Box<dyn DiscoveryProtocol + Send + Sync>> {
+    Ok(Box::new(windows_dns::WindowsDnsProtocol::new(config)?))
+}
+
+/// mDNS through the Windows DNS API, which is not built here
+#[cfg(not(all(windows, feature = "windows-dns")))]
+fn windows_dns_backend(_config: &DiscoveryConfig) -> Result<Box<dyn DiscoveryProtocol + Send + Sync>> {
+    Err(DiscoveryError::configuration(
+        "The Windows DNS API mDNS backend needs Windows and the windows-dns feature",
+    ))
+}
+
+/// Start the built-in implementation of `protocol_type`
+///
+/// The protocol is handed `registry`. Returns whether starting it was
+/// deferred to its first use, as the configuration may ask for.
+async fn start_builtin(
+    config: &DiscoveryConfig,
+    registry: &Arc<ServiceRegistry>,
+    protocol_type: ProtocolType,
+) -> Result<(Arc<dyn DiscoveryProtocol + Send + Sync>, bool)> {
+    let factory = BuiltinFactory { protocol_type, config: config.clone() };
+    if config.lazy_protocols() {
+        let mut protocol = LazyProtocol::new(Arc::new(factory), config.safety().retry)
+            .with_rng(SharedRng::from_seed(config.seed()));
+        protocol.set_registry(Arc::clone(registry));
+        return Ok((Arc::new(protocol), true));
+    }
+    let mut protocol = factory.create().await?;
+    protocol.set_registry(Arc::clone(registry));
+    Ok((Arc::from(protocol), false))
+}
+
+/// Replace the multicast protocols in `wanted` with the fallbacks of
+/// container mode if multicast does not work here
+///
+/// DNS-SD is enabled in `config` when a DNS server is configured for it. A
+/// gateway takes the place of the first multicast protocol in `protocols`;
+/// the others are recorded as failed in `report`.
+async fn apply_container_mode(
+    config: &mut DiscoveryConfig,
+    container: &ContainerConfig,
+    wanted: &mut Vec<ProtocolType>,
+    protocols: &mut HashMap<ProtocolType, Arc<dyn DiscoveryProtocol + Send + Sync>>,
+    report: &mut InitReport,
+) -> Result<()> {
+    let multicast: Vec<ProtocolType> = wanted
+        .iter()
+        .copied()
+        .filter(|protocol_type| matches!(protocol_type, ProtocolType::Mdns | ProtocolType::Upnp))
+        .collect();
+    if multicast.is_empty() {
+        return Ok(());
+    }
+    let check = match container.multicast_available() {
+        Some(true) => MulticastCheck::Available,
+        Some(false) => MulticastCheck::Unavailable("configured as unavailable".to_string()),
+        None => container::check_multicast(container.probe_timeout()).await,
+    };
+    let MulticastCheck::Unavailable(reason) = check else {
+        debug!("Container mode: multicast is available");
+        return Ok(());
+    };
+    wanted.retain(|protocol_type| !multicast.contains(protocol_type));
+
+    let mut fallbacks = Vec::new();
+    if let Some(server) = config.dns_sd().dns_server() {
+        if !wanted.contains(&ProtocolType::DnsSd) && !protocols.contains_key(&ProtocolType::DnsSd) {
+            wanted.push(ProtocolType::DnsSd);
+        }
+        config.enable_protocol(ProtocolType::DnsSd);
+        fallbacks.push(format!("unicast DNS-SD via {server}"));
+    }
+    let mut unreplaced = multicast.as_slice();
+    if let Some(url) = container.gateway_url() {
+        let protocol_type = multicast[0];
+        protocols.insert(protocol_type, Arc::new(remote::RemoteProtocol::new(RemoteDiscovery::new(url)?, protocol_type)));
+        unreplaced = &multicast[1..];
+        fallbacks.push(format!("the discovery gateway at {url} in place of {protocol_type}"));
+    }
+
+    let disabled: Vec<String> = multicast.iter().map(ToString::to_string).collect();
+    if fallbacks.is_empty() {
+        error!(
+            "Multicast is unavailable ({}), so {} will find nothing. Configure a DNS server for DNS-SD \
+             or a gateway URL for container mode, or give the container host networking",
+            reason,
+            disabled.join(" and ")
+        );
+    } else {
+        warn!(
+            "Multicast is unavailable ({}); not starting {}, falling back to {}",
+            reason,
+            disabled.join(" and "),
+            fallbacks.join(" and ")
+        );
+    }
+    for protocol_type in unreplaced {
+        report.fail(*protocol_type, DiscoveryError::network(format!("Multicast is unavailable: {reason}")));
+    }
+    Ok(())
 }
 
 /// Manager for all discovery protocols
 #[derive(Clone)]
 pub struct ProtocolManager {
-    #[allow(dead_code)]
     config: DiscoveryConfig,
     protocols: HashMap<ProtocolType, Arc<dyn DiscoveryProtocol + Send + Sync>>,
+    registry: Arc<ServiceRegistry>,
+    safety: SafetyManager,
+    reflector: Option<Arc<reflector::MdnsReflector>>,
+    redactor: Redactor,
+    init_report: InitReport,
+    /// Slots for discoveries and verifications in flight
+    concurrency: ConcurrencyLimiter,
 }
 
 impl ProtocolManager {
     /// Create a new protocol manager
     pub async fn new(config: DiscoveryConfig) -> Result<Self> {
-        let mut protocols: HashMap<ProtocolType, Arc<dyn DiscoveryProtocol + Send + Sync>> = HashMap::new();
+        let safety = SafetyManager::new(config.safety().clone());
+        Self::with_parts(config, Arc::new(ServiceRegistry::new()), safety, Vec::new()).await
+    }
+
+    /// Create a protocol manager around an existing registry and safety manager
+    ///
+    /// Built-in protocols are handed `registry` before use. Each protocol in
+    /// `custom` is used instead of the built-in implementation of its type,
+    /// whether or not the configuration enables that type, and is expected to
+    /// have been given its registry already.
+    pub async fn with_parts(
+        mut config: DiscoveryConfig,
+        registry: Arc<ServiceRegistry>,
+        safety: SafetyManager,
+        custom: Vec<Arc<dyn DiscoveryProtocol + Send + Sync>>,
+    ) -> Result<Self> {
+        let mut protocols: HashMap<ProtocolType, Arc<dyn DiscoveryProtocol + Send + Sync>> = custom
+            .into_iter()
+            .map(|protocol| (protocol.protocol_type(), protocol))
+            .collect();
+        let mut wanted: Vec<ProtocolType> = [ProtocolType::Mdns, ProtocolType::DnsSd, ProtocolType::Upnp]
+            .into_iter()
+            .filter(|protocol_type| config.has_protocol(*protocol_type) && !protocols.contains_key(protocol_type))
+            .collect();
+        let mut report = InitReport::default();
+        if let Some(container) = config.container_mode().cloned() {
+            apply_container_mode(&mut config, &container, &mut wanted, &mut protocols, &mut report).await?;
+        }
 
         // Initialize protocols based on config
-        if config.has_protocol(ProtocolType::Mdns) {
-            #[cfg(all(feature = "simple-mdns", not(feature = "mdns")))]
-            {
-                if let Ok(mdns) = simple_mdns::SimpleMdnsProtocol::new(&config).await {
-                    protocols.insert(ProtocolType::Mdns, Arc::new(mdns) as Arc<dyn DiscoveryProtocol + Send + Sync>);
-                }
-            }
-            #[cfg(not(feature = "simple-mdns"))]
-            {
-                if let Ok(mdns) = mdns::MdnsProtocol::new(&config).await {
-                    protocols.insert(ProtocolType::Mdns, Arc::new(mdns) as Arc<dyn DiscoveryProtocol + Send + Sync>);
+        for protocol_type in wanted {
+            match start_builtin(&config, &registry, protocol_type).await {
+                Ok((protocol, deferred)) => {
+                    protocols.insert(protocol_type, protocol);
+                    if deferred {
+                        report.deferred.push(protocol_type);
+                    }
                 }
+                Err(e) => report.fail(protocol_type, e),
             }
         }
 
-        if config.has_protocol(ProtocolType::Upnp) {
-            if let Ok(ssdp) = upnp::SsdpProtocol::new(config.clone()) {
-                protocols.insert(ProtocolType::Upnp, Arc::new(ssdp) as Arc<dyn DiscoveryProtocol + Send + Sync>);
-            }
+        report.started = [ProtocolType::Mdns, ProtocolType::DnsSd, ProtocolType::Upnp]
+            .into_iter()
+            .filter(|protocol_type| protocols.contains_key(protocol_type) && !report.deferred.contains(protocol_type))
+            .collect();
+        if report.is_empty() && config.require_at_least_one_protocol() {
+            return Err(DiscoveryError::configuration(format!("No discovery protocol could be started ({report})")));
         }
 
-        if config.has_protocol(ProtocolType::DnsSd) {
-            if let Ok(dns_sd) = dns_sd::DnsSdProtocol::new(&config).await {
-                protocols.insert(ProtocolType::DnsSd, Arc::new(dns_sd) as Arc<dyn DiscoveryProtocol + Send + Sync>);
+        let reflector = match config.mdns().reflector() {
+            Some(reflector) if config.has_protocol(ProtocolType::Mdns) => {
+                Some(Arc::new(reflector::MdnsReflector::with_socket_options(
+                    reflector,
+                    config.mdns().socket_options(),
+                )?))
             }
+            _ => None,
+        };
+
+        let redactor = Redactor::new(config.logging());
+        let concurrency = ConcurrencyLimiter::new(&safety.config().concurrency);
+        Ok(Self {
+            config,
+            protocols,
+            registry,
+            safety,
+            reflector,
+            redactor,
+            init_report: report,
+            concurrency,
+        })
+    }
+
+    /// Create a protocol manager using exactly `protocols`
+    ///
+    /// No built-in protocol is started, whatever the configuration enables,
+    /// and nothing touches the network unless the given protocols do. Meant
+    /// for tests with in-memory implementations, such as the
+    /// `testing::MockProtocol` of the `testing` feature.
+    pub fn with_protocols(config: DiscoveryConfig, protocols: Vec<Arc<dyn DiscoveryProtocol + Send + Sync>>) -> Self {
+        let safety = SafetyManager::new(config.safety().clone());
+        let redactor = Redactor::new(config.logging());
+        let concurrency = ConcurrencyLimiter::new(&config.safety().concurrency);
+        let init_report = InitReport {
+            started: protocols.iter().map(|protocol| protocol.protocol_type()).collect(),
+            ..InitReport::default()
+        };
+        Self {
+            config,
+            protocols: protocols
+                .into_iter()
+                .map(|protocol| (protocol.protocol_type(), protocol))
+                .collect(),
+            registry: Arc::new(ServiceRegistry::new()),
+            safety,
+            reflector: None,
+            redactor,
+            init_report,
+            concurrency,
         }
+    }
 
-        // simple-mdns implementation is disabled due to API incompatibilities
-        // #[cfg(feature = "simple-mdns")]
-        // {
-        //     if config.has_protocol(ProtocolType::Mdns) {
-        //         if let Ok(simple_mdns) = simple_mdns::SimpleMdnsProtocol::new(&config).await {
-        //             protocols.insert(
-        //                 ProtocolType::Mdns,
-        //                 Arc::new(simple_mdns) as Arc<dyn DiscoveryProtocol + Send + Sync>,
-        //             );
-        //         }
-        //     }
-        // }
+    /// Which protocols started, and why the others did not
+    pub fn init_report(&self) -> &InitReport {
+        &self.init_report
+    }
 
-        Ok(Self { config, protocols })
+    /// The configuration in effect, including protocols container mode enabled
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+
+    /// Start a protocol on a running manager
+    ///
+    /// `protocol` is used if given; otherwise the built-in implementation is
+    /// started, on first use if the configuration asks for lazy protocols.
+    /// Does nothing if the protocol is running already. A failure to start is
+    /// returned and recorded in the [`InitReport`].
+    pub async fn enable_protocol(
+        &mut self,
+        protocol_type: ProtocolType,
+        protocol: Option<Arc<dyn DiscoveryProtocol + Send + Sync>>,
+    ) -> Result<()> {
+        if self.protocols.contains_key(&protocol_type) {
+            return Ok(());
+        }
+        self.init_report.failures.retain(|(failed, _)| *failed != protocol_type);
+        let (protocol, deferred) = match protocol {
+            Some(protocol) => (protocol, false),
+            None => match start_builtin(&self.config, &self.registry, protocol_type).await {
+                Ok(started) => started,
+                Err(e) => {
+                    self.init_report.fail(protocol_type, e.clone());
+                    return Err(e);
+                }
+            },
+        };
+        self.config.enable_protocol(protocol_type);
+        self.protocols.insert(protocol_type, protocol);
+        if deferred {
+            self.init_report.deferred.push(protocol_type);
+        } else {
+            self.init_report.started.push(protocol_type);
+        }
+        info!("Enabled {}", protocol_type);
+        Ok(())
+    }
+
+    /// Stop a protocol on a running manager, returning whether it was running
+    ///
+    /// Services still registered with the protocol are not withdrawn; use
+    /// [`unregister_from`](Self::unregister_from) first. The protocol is shut
+    /// down once removed, unless clones of this manager still hold it.
+    pub async fn disable_protocol(&mut self, protocol_type: ProtocolType) -> bool {
+        self.config.disable_protocol(protocol_type);
+        self.init_report.started.retain(|started| *started != protocol_type);
+        self.init_report.deferred.retain(|deferred| *deferred != protocol_type);
+        let Some(protocol) = self.protocols.remove(&protocol_type) else {
+            return false;
+        };
+        if let Err(e) = protocol.shutdown().await {
+            warn!("Failed to shut down {}: {}", protocol_type, e);
+        }
+        info!("Disabled {}", protocol_type);
+        true
     }
 
     /// Get enabled protocol types
@@ -109,26 +673,170 @@ impl ProtocolManager {
         self.protocols.keys().copied().collect()
     }
 
+    /// Registry shared by the built-in protocols
+    pub fn registry(&self) -> &Arc<ServiceRegistry> {
+        &self.registry
+    }
+
+    /// Drop services outside the allowed networks and tag the rest with
+    /// their interface
+    fn scope(&self, services: &mut Vec<ServiceInfo>) {
+        let before = services.len();
+        services.retain(|service| self.config.is_address_allowed(&service.address));
+        if services.len() < before {
+            debug!("Dropped {} services outside the allowed networks", before - services.len());
+        }
+        tag_interfaces(services);
+    }
+
+    /// Safety manager holding the per-protocol circuit breakers
+    pub fn safety(&self) -> &SafetyManager {
+        &self.safety
+    }
+
+    /// Limiter of the discoveries and verifications in flight
+    pub fn concurrency(&self) -> &ConcurrencyLimiter {
+        &self.concurrency
+    }
+
+    /// mDNS reflector, if one was configured
+    pub fn reflector(&self) -> Option<&reflector::MdnsReflector> {
+        self.reflector.as_deref()
+    }
+
     /// Discover services with all enabled protocols
+    ///
+    /// Protocols run concurrently, within the configured concurrency limits.
+    /// Those whose circuit breaker is open are skipped, so a protocol that
+    /// keeps failing does not hold up discovery through the others.
     pub async fn discover_services(
         &self,
         service_types: Vec<ServiceType>,
         timeout: Option<Duration>,
-    ) -> Result<Vec<ServiceInfo>> {
-        let mut all_services = Vec::new();
+    ) -> MultiProtocolResult<Vec<ServiceInfo>> {
+        self.discover_services_with_callback(service_types, timeout, |_, _| {}).await
+    }
+
+    /// Discover services with all enabled protocols, reporting each protocol's
+    /// results as soon as it finishes
+    ///
+    /// `on_results` is called once per protocol that succeeds. When `timeout`
+    /// is set, protocols still running [`COLLECTION_GRACE`] after it has
+    /// elapsed are abandoned and reported as timed out, alongside the results
+    /// gathered so far.
+    pub async fn discover_services_with_callback<F>(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+        mut on_results: F,
+    ) -> MultiProtocolResult<Vec<ServiceInfo>>
+    where
+        F: FnMut(ProtocolType, &[ServiceInfo]),
+    {
+        let browsers: Vec<_> = self
+            .protocols
+            .iter()
+            .filter(|(_, protocol)| protocol.capabilities().supports_browsing)
+            .filter(|(protocol_type, _)| self.safety.check_protocol(**protocol_type))
+            .collect();
+        let mut outstanding: HashSet<ProtocolType> = browsers.iter().map(|(protocol_type, _)| **protocol_type).collect();
+        let mut pending: FuturesUnordered<_> = browsers
+            .into_iter()
+            .map(|(protocol_type, protocol)| {
+                let span = operation_span(Operation::Discovery, *protocol_type, None, &service_types);
+                let service_types = service_types.clone();
+                async move {
+                    let _permit = self.concurrency.acquire(Operation::Discovery, *protocol_type).await;
+                    (*protocol_type, protocol.discover_services(service_types, timeout).await)
+                }
+                .instrument(span)
+            })
+            .collect();
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout + COLLECTION_GRACE);
+        let mut outcome = MultiProtocolResult::new();
 
-        for protocol in self.protocols.values() {
-            match protocol.discover_services(service_types.clone(), timeout).await {
-                Ok(services) => all_services.extend(services),
-                Err(e) => warn!(
-                    "Error discovering services with protocol {:?}: {}",
-                    protocol.protocol_type(),
-                    e
-                ),
+        loop {
+            let next = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, pending.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        warn!("{} protocol(s) did not finish before the discovery deadline", pending.len());
+                        break;
+                    }
+                },
+                None => pending.next().await,
+            };
+            let Some((protocol_type, result)) = next else {
+                break;
+            };
+            outstanding.remove(&protocol_type);
+
+            self.safety.record_protocol_result(protocol_type, result.is_ok());
+            match result {
+                Ok(mut services) => {
+                    self.scope(&mut services);
+                    on_results(protocol_type, &services);
+                    outcome.push(protocol_type, Ok(services));
+                }
+                Err(e) => {
+                    warn!(protocol = %protocol_type, "Error discovering services: {}", e);
+                    let context = error_context(protocol_type, Operation::Discovery, &service_types);
+                    outcome.push(protocol_type, Err(e.with_context(context)));
+                }
             }
         }
 
-        Ok(all_services)
+        for protocol_type in outstanding {
+            let context = error_context(protocol_type, Operation::Discovery, &service_types);
+            outcome.push(
+                protocol_type,
+                Err(DiscoveryError::timeout(format!(
+                    "{protocol_type} did not finish before the discovery deadline"
+                ))
+                .with_context(context)),
+            );
+        }
+        outcome
+    }
+
+    /// Enumerate the service types present on the network with all enabled
+    /// protocols
+    ///
+    /// Protocols run concurrently and those whose circuit breaker is open are
+    /// skipped. Each protocol's list is sorted and free of duplicates.
+    pub async fn enumerate_service_types(&self, timeout: Duration) -> MultiProtocolResult<Vec<ServiceType>> {
+        let results = futures::future::join_all(
+            self.protocols
+                .iter()
+                .filter(|(protocol_type, _)| self.safety.check_protocol(**protocol_type))
+                .map(|(protocol_type, protocol)| {
+                    async move {
+                        let _permit = self.concurrency.acquire(Operation::Discovery, *protocol_type).await;
+                        (*protocol_type, protocol.enumerate_service_types(timeout).await)
+                    }
+                    .instrument(operation_span(Operation::Discovery, *protocol_type, None, &[]))
+                }),
+        )
+        .await;
+
+        let mut outcome = MultiProtocolResult::new();
+        for (protocol_type, result) in results {
+            self.safety.record_protocol_result(protocol_type, result.is_ok());
+            if let Err(e) = &result {
+                warn!(protocol = %protocol_type, "Error enumerating service types: {}", e);
+            }
+            let context = error_context(protocol_type, Operation::Discovery, &[]);
+            outcome.push(
+                protocol_type,
+                result.map_err(|e| e.with_context(context)).map(|mut service_types| {
+                    service_types.sort_by_key(|service_type| service_type.to_string());
+                    service_types.dedup();
+                    service_types
+                }),
+            );
+        }
+        outcome
     }
 
     /// Discover services with a specific protocol
@@ -138,44 +846,238 @@ impl ProtocolManager {
         service_types: Vec<ServiceType>,
         timeout: Option<Duration>,
     ) -> Result<Vec<ServiceInfo>> {
-        if let Some(protocol) = self.protocols.get(&protocol_type) {
-            return protocol.discover_services(service_types, timeout).await;
+        let context = error_context(protocol_type, Operation::Discovery, &service_types);
+        let Some(protocol) = self.protocols.get(&protocol_type) else {
+            return Err(DiscoveryError::protocol(format!("Protocol {protocol_type:?} not available")).with_context(context));
+        };
+        if !protocol.capabilities().supports_browsing {
+            return Err(DiscoveryError::protocol(format!(
+                "Protocol {protocol_type:?} does not support browsing"
+            ))
+            .with_context(context));
+        }
+        if !self.safety.check_protocol(protocol_type) {
+            let context = match self.safety.protocol_time_until_half_open(protocol_type) {
+                Some(delay) => context.retry_after(delay),
+                None => context,
+            };
+            return Err(DiscoveryError::rate_limited(format!(
+                "{protocol_type} circuit breaker is open"
+            ))
+            .with_context(context));
+        }
+        let span = operation_span(Operation::Discovery, protocol_type, None, &service_types);
+        let _permit = self.concurrency.acquire(Operation::Discovery, protocol_type).await;
+        let result = protocol.discover_services(service_types, timeout).instrument(span).await;
+        self.safety.record_protocol_result(protocol_type, result.is_ok());
+        match result {
+            Ok(mut services) => {
+                self.scope(&mut services);
+                Ok(services)
+            }
+            Err(e) => Err(e.with_context(context)),
         }
-        Err(DiscoveryError::protocol(format!("Protocol {protocol_type:?} not available")))
     }
 
     /// Register a service with the appropriate protocol
     pub async fn register_service(&self, service: ServiceInfo) -> Result<()> {
         let protocol_type = service.protocol_type();
-        if let Some(protocol) = self.protocols.get(&protocol_type) {
-            return protocol.register_service(service).await;
+        let context = error_context(protocol_type, Operation::Registration, std::slice::from_ref(service.service_type()));
+        let protocol = self.registrar(&service).map_err(|e| e.with_context(context.clone()))?;
+        let span = operation_span(Operation::Registration, protocol_type, Some(&service), &[]);
+        span.in_scope(|| debug!("Registering {}", self.redactor.service(&service)));
+        protocol
+            .register_service(service.clone())
+            .instrument(span)
+            .await
+            .map_err(|e| e.with_context(context))?;
+        self.registry.record_registration(&service, protocol_type);
+        Ok(())
+    }
+
+    /// Register a service with every enabled protocol that supports registration
+    ///
+    /// Each protocol advertises its own copy of the service, tagged with that
+    /// protocol's type. Failures with some protocols don't undo the others;
+    /// the result says where the service ended up.
+    pub async fn register_everywhere(&self, service: ServiceInfo) -> MultiProtocolResult<ServiceInfo> {
+        let protocols = self.protocols_supporting(|c| c.supports_registration);
+        self.register_with(service, protocols).await
+    }
+
+    /// Register a service with each of `protocols`
+    ///
+    /// Successes carry the copy each protocol advertises.
+    pub(crate) async fn register_with(
+        &self,
+        service: ServiceInfo,
+        protocols: Vec<ProtocolType>,
+    ) -> MultiProtocolResult<ServiceInfo> {
+        let mut outcome = MultiProtocolResult::new();
+        for protocol_type in protocols {
+            let copy = service.clone().with_protocol_type(protocol_type);
+            let result = self.register_service(copy.clone()).await.map(|()| copy);
+            if let Err(e) = &result {
+                debug!("Failed to register {} with {:?}: {}", service.name(), protocol_type, e);
+            }
+            outcome.push(protocol_type, result);
         }
-        
-        Err(DiscoveryError::protocol(format!(
-            "Protocol {protocol_type:?} not available"
-        )))
+        outcome
+    }
+
+    /// Protocols `service` is currently registered with
+    ///
+    /// The records are kept by the registry, so they outlive the manager.
+    pub fn registered_protocols(&self, service: &ServiceInfo) -> Vec<ProtocolType> {
+        self.registry.registered_protocols(service)
+    }
+
+    /// The protocol `service` is advertised with, if it supports registration
+    fn registrar(&self, service: &ServiceInfo) -> Result<&Arc<dyn DiscoveryProtocol + Send + Sync>> {
+        let protocol_type = service.protocol_type();
+        let Some(protocol) = self.protocols.get(&protocol_type) else {
+            return Err(DiscoveryError::protocol(format!(
+                "Protocol {protocol_type:?} not available"
+            )));
+        };
+        if !protocol.capabilities().supports_registration {
+            return Err(DiscoveryError::protocol(format!(
+                "Protocol {protocol_type:?} does not support registration"
+            )));
+        }
+        Ok(protocol)
+    }
+
+    /// Probe the network for another instance already using the service's name
+    ///
+    /// Returns `true` if a service with the same name and type but different
+    /// address or port answers within `timeout`.
+    pub async fn probe_name_conflict(&self, service: &ServiceInfo, timeout: Duration) -> Result<bool> {
+        let protocol_type = service.protocol_type();
+        let Some(protocol) = self.protocols.get(&protocol_type) else {
+            return Err(DiscoveryError::protocol(format!(
+                "Protocol {protocol_type:?} not available"
+            )));
+        };
+
+        // Without browsing there is no way to see other instances
+        if !protocol.capabilities().supports_browsing {
+            return Ok(false);
+        }
+
+        let span = operation_span(Operation::Discovery, protocol_type, Some(service), &[]);
+        let answers = protocol
+            .discover_services(vec![service.service_type().clone()], Some(timeout))
+            .instrument(span)
+            .await?;
+
+        Ok(answers.iter().any(|other| {
+            other.id != service.id
+                && other.name == service.name
+                && (other.address != service.address || other.port != service.port)
+        }))
     }
 
     /// Unregister a service
+    ///
+    /// The service is withdrawn from exactly the protocols that hold an
+    /// active registration of it, whatever its own protocol type says. All of
+    /// them are attempted even if some fail; the first error is returned, and
+    /// the protocols that failed keep their registration record so the
+    /// withdrawal can be retried.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::ServiceNotFound`] if no protocol holds a
+    /// registration of the service
     pub async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
-        let protocol_type = service.protocol_type();
-        if let Some(protocol) = self.protocols.get(&protocol_type) {
-            return protocol.unregister_service(service).await;
+        let protocols = self.registered_protocols(service);
+        if protocols.is_empty() {
+            return Err(DiscoveryError::service_not_found(service.service_id().to_string()));
         }
-        Err(DiscoveryError::protocol(format!(
-            "Protocol {protocol_type:?} not available"
-        )))
+
+        let mut first_error = None;
+        for protocol_type in protocols {
+            if let Err(e) = self.unregister_from(service, protocol_type).await {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Withdraw a service from one protocol
+    pub async fn unregister_from(&self, service: &ServiceInfo, protocol_type: ProtocolType) -> Result<()> {
+        let copy = service.clone().with_protocol_type(protocol_type);
+        let result = match self.registrar(&copy) {
+            Ok(protocol) => {
+                let span = operation_span(Operation::Registration, protocol_type, Some(&copy), &[]);
+                protocol.unregister_service(&copy).instrument(span).await
+            }
+            Err(e) => Err(e),
+        }
+        .map_err(|e| {
+            e.with_context(error_context(
+                protocol_type,
+                Operation::Registration,
+                std::slice::from_ref(service.service_type()),
+            ))
+        });
+        match &result {
+            Ok(()) => self.registry.forget_registration(service, protocol_type),
+            Err(e) => debug!("Failed to unregister {} from {:?}: {}", service.name(), protocol_type, e),
+        }
+        result
     }
 
     /// Verify a service is still available
+    ///
+    /// Services of protocols that can't verify are checked by connecting to
+    /// them instead. Waits for a slot if the configured number of
+    /// verifications is already in flight.
     pub async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
         let protocol_type = service.protocol_type();
-        if let Some(protocol) = self.protocols.get(&protocol_type) {
-            return protocol.verify_service(service).await;
+        let context = error_context(protocol_type, Operation::Verification, std::slice::from_ref(service.service_type()));
+        let Some(protocol) = self.protocols.get(&protocol_type) else {
+            return Err(DiscoveryError::protocol(format!(
+                "Protocol {protocol_type:?} not available"
+            ))
+            .with_context(context));
+        };
+        let span = operation_span(Operation::Verification, protocol_type, Some(service), &[]);
+        let _permit = self.concurrency.acquire(Operation::Verification, protocol_type).await;
+        if protocol.capabilities().supports_verification {
+            return protocol
+                .verify_service(service)
+                .instrument(span)
+                .await
+                .map_err(|e| e.with_context(context));
         }
-        Err(DiscoveryError::protocol(format!(
-            "Protocol {protocol_type:?} not available"
-        )))
+        let result = ConnectivityVerifier::from_health_config(self.config.health())
+            .with_resolution_cache(Arc::clone(self.registry.resolution_cache()))
+            .verify(service)
+            .instrument(span)
+            .await;
+        Ok(result.healthy)
+    }
+
+    /// What each enabled protocol can do
+    pub fn capabilities(&self) -> HashMap<ProtocolType, Capabilities> {
+        self.protocols
+            .iter()
+            .map(|(protocol_type, protocol)| (*protocol_type, protocol.capabilities()))
+            .collect()
+    }
+
+    /// Enabled protocols whose capabilities satisfy `predicate`
+    pub fn protocols_supporting<F>(&self, predicate: F) -> Vec<ProtocolType>
+    where
+        F: Fn(&Capabilities) -> bool,
+    {
+        self.protocols
+            .iter()
+            .filter(|(_, protocol)| predicate(&protocol.capabilities()))
+            .map(|(protocol_type, _)| *protocol_type)
+            .collect()
     }
 
     /// Get a reference to the protocols map
@@ -184,10 +1086,16 @@ impl ProtocolManager {
     }
 
     /// Perform a health check on all protocols
-    pub async fn health_check(&self) -> HashMap<ProtocolType, bool> {
+    pub async fn health_check(&self) -> HashMap<ProtocolType, ProtocolHealth> {
         let mut statuses = HashMap::new();
         for (protocol_type, protocol) in &self.protocols {
-            statuses.insert(*protocol_type, protocol.is_available().await);
+            statuses.insert(
+                *protocol_type,
+                ProtocolHealth {
+                    available: protocol.is_available().await,
+                    circuit: self.safety.protocol_state(*protocol_type),
+                },
+            );
         }
         statuses
     }
@@ -196,7 +1104,273 @@ impl ProtocolManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::DiscoveryConfig;
+    use crate::{config::DiscoveryConfig, error::ErrorKind};
+
+    /// Protocol that answers with one service after a fixed delay
+    struct DelayedProtocol {
+        protocol_type: ProtocolType,
+        delay: Duration,
+        capabilities: Capabilities,
+    }
+
+    #[async_trait]
+    impl DiscoveryProtocol for DelayedProtocol {
+        fn protocol_type(&self) -> ProtocolType {
+            self.protocol_type
+        }
+
+        async fn discover_services(
+            &self,
+            _service_types: Vec<ServiceType>,
+            _timeout: Option<Duration>,
+        ) -> Result<Vec<ServiceInfo>> {
+            tokio::time::sleep(self.delay).await;
+            Ok(vec![ServiceInfo::new(self.protocol_type.to_string(), "_http._tcp", 80, None)?])
+        }
+
+        async fn register_service(&self, _service: ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unregister_service(&self, _service: &ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn verify_service(&self, _service: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities
+        }
+    }
+
+    #[test]
+    fn test_services_tagged_with_interface() {
+        let mut services = vec![
+            ServiceInfo::new("local", "_http._tcp", 80, None)
+                .unwrap()
+                .with_address("127.0.0.1".parse().unwrap()),
+            ServiceInfo::new("remote", "_http._tcp", 80, None)
+                .unwrap()
+                .with_address("203.0.113.7".parse().unwrap())
+                .with_interface("eth9"),
+        ];
+        tag_interfaces(&mut services);
+        assert_eq!(services[0].interface.as_deref(), Some("lo"));
+        assert_eq!(services[1].interface.as_deref(), Some("eth9"));
+    }
+
+    #[tokio::test]
+    async fn test_services_outside_allowed_networks_are_dropped() {
+        let manager = |network: &str| {
+            let config = DiscoveryConfig::new()
+                .with_protocols([ProtocolType::Upnp].into_iter().collect())
+                .with_allowed_networks(vec![network.parse().unwrap()]);
+            let safety = SafetyManager::new(config.safety().clone());
+            let protocol = Arc::new(DelayedProtocol {
+                protocol_type: ProtocolType::Upnp,
+                delay: Duration::ZERO,
+                capabilities: Capabilities::default(),
+            });
+            ProtocolManager::with_parts(config, Arc::new(ServiceRegistry::new()), safety, vec![protocol])
+        };
+
+        // The protocol answers with a service on loopback
+        let lab_only = manager("10.0.0.0/8").await.unwrap();
+        assert!(lab_only.discover_services(vec![], None).await.flatten().unwrap().is_empty());
+        let found = lab_only.discover_services_with_protocol(ProtocolType::Upnp, vec![], None).await.unwrap();
+        assert!(found.is_empty());
+
+        let loopback = manager("127.0.0.0/8").await.unwrap();
+        assert_eq!(loopback.discover_services(vec![], None).await.flatten().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_slow_protocol_does_not_delay_others() {
+        let mut manager = ProtocolManager::new(DiscoveryConfig::new()).await.unwrap();
+        manager.protocols.clear();
+        for (protocol_type, delay) in [
+            (ProtocolType::Mdns, Duration::from_millis(50)),
+            (ProtocolType::DnsSd, Duration::from_millis(100)),
+            (ProtocolType::Upnp, Duration::from_secs(30)),
+        ] {
+            manager.protocols.insert(
+                protocol_type,
+                Arc::new(DelayedProtocol { protocol_type, delay, capabilities: Capabilities::default() }),
+            );
+        }
+
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        let outcome = manager
+            .discover_services_with_callback(vec![], Some(Duration::from_millis(200)), |protocol, _| {
+                reported.push(protocol)
+            })
+            .await;
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(reported, vec![ProtocolType::Mdns, ProtocolType::DnsSd]);
+        // The abandoned protocol is reported as timed out
+        assert_eq!(outcome.failed(), [ProtocolType::Upnp]);
+        assert_eq!(outcome.failures()[0].1.kind(), ErrorKind::Timeout);
+        assert_eq!(outcome.flatten().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_verifications_limited() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Protocol whose verifications take a while, counting how many overlap
+        #[derive(Default)]
+        struct SlowVerifier {
+            running: AtomicUsize,
+            peak: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl DiscoveryProtocol for SlowVerifier {
+            fn protocol_type(&self) -> ProtocolType {
+                ProtocolType::Upnp
+            }
+
+            async fn discover_services(&self, _: Vec<ServiceType>, _: Option<Duration>) -> Result<Vec<ServiceInfo>> {
+                Ok(Vec::new())
+            }
+
+            async fn register_service(&self, _: ServiceInfo) -> Result<()> {
+                Ok(())
+            }
+
+            async fn unregister_service(&self, _: &ServiceInfo) -> Result<()> {
+                Ok(())
+            }
+
+            async fn verify_service(&self, _: &ServiceInfo) -> Result<bool> {
+                let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+                self.peak.fetch_max(running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                self.running.fetch_sub(1, Ordering::SeqCst);
+                Ok(true)
+            }
+
+            async fn is_available(&self) -> bool {
+                true
+            }
+
+            fn set_registry(&mut self, _: Arc<ServiceRegistry>) {}
+
+            fn capabilities(&self) -> Capabilities {
+                Capabilities { supports_verification: true, ..Capabilities::default() }
+            }
+        }
+
+        let mut safety = crate::safety::SafetyConfig::default();
+        safety.concurrency.max_verifications = Some(2);
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_safety(safety);
+        let protocol = Arc::new(SlowVerifier::default());
+        let manager = ProtocolManager::with_protocols(config, vec![protocol.clone()]);
+
+        let service = ServiceInfo::new("slow", "_http._tcp", 80, None).unwrap().with_protocol_type(ProtocolType::Upnp);
+        let results = futures::future::join_all((0..8).map(|_| manager.verify_service(&service))).await;
+        assert!(results.into_iter().all(|result| result.unwrap()));
+        assert_eq!(protocol.peak.load(Ordering::SeqCst), 2);
+        assert_eq!(manager.concurrency().available(Operation::Verification), Some(2));
+    }
+
+    #[test]
+    fn test_multi_protocol_result_conversion() {
+        let mut partial = MultiProtocolResult::new();
+        partial.push(ProtocolType::Mdns, Ok(1));
+        partial.push(ProtocolType::Upnp, Err(DiscoveryError::network("no route")));
+        assert!(!partial.is_complete());
+        assert_eq!(partial.succeeded(), [ProtocolType::Mdns]);
+        assert_eq!(Result::from(partial).unwrap(), [1]);
+
+        let mut single = MultiProtocolResult::<u8>::new();
+        single.push(ProtocolType::Upnp, Err(DiscoveryError::network("no route")));
+        assert!(matches!(single.into_result(), Err(DiscoveryError::Network(_))));
+
+        let mut total = MultiProtocolResult::<u8>::new();
+        total.push(ProtocolType::Mdns, Err(DiscoveryError::timeout("slow")));
+        total.push(ProtocolType::Upnp, Err(DiscoveryError::network("no route")));
+        let message = total.into_result().unwrap_err().to_string();
+        assert!(message.contains("slow") && message.contains("no route"));
+
+        // Nothing taking part is not a failure
+        assert!(MultiProtocolResult::<u8>::new().into_result().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_operations_routed_by_capabilities() {
+        let mut manager = ProtocolManager::new(DiscoveryConfig::new()).await.unwrap();
+        manager.protocols.clear();
+        let listen_only = Capabilities { supports_registration: false, ..Capabilities::default() };
+        let advertise_only = Capabilities { supports_browsing: false, ..Capabilities::default() };
+        for (protocol_type, capabilities) in [(ProtocolType::Mdns, listen_only), (ProtocolType::Upnp, advertise_only)] {
+            manager.protocols.insert(
+                protocol_type,
+                Arc::new(DelayedProtocol { protocol_type, delay: Duration::ZERO, capabilities }),
+            );
+        }
+        assert_eq!(manager.capabilities()[&ProtocolType::Upnp], advertise_only);
+        assert_eq!(manager.protocols_supporting(|c| c.supports_registration), vec![ProtocolType::Upnp]);
+
+        // Only the protocol that can browse is asked
+        let services = manager.discover_services(vec![], None).await.flatten().unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, ProtocolType::Mdns.to_string());
+        assert!(manager.discover_services_with_protocol(ProtocolType::Upnp, vec![], None).await.is_err());
+
+        // Nor is there anywhere to register with the listen-only one
+        let service = ServiceInfo::new("Printer", "_ipp._tcp", 631, None).unwrap();
+        let err = manager.register_service(service.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("does not support registration"));
+        manager.register_service(service.with_protocol_type(ProtocolType::Upnp)).await.unwrap();
+    }
+
+    #[test]
+    fn test_builtin_capabilities() {
+        let upnp = upnp::SsdpProtocol::new(DiscoveryConfig::new()).unwrap().capabilities();
+        assert!(upnp.supports_registration && upnp.supports_browsing);
+        assert!(!upnp.wide_area && !upnp.supports_ipv6);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_protocols_share_registry() {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Mdns].into_iter().collect());
+        let registry = Arc::new(ServiceRegistry::new());
+        let safety = SafetyManager::new(config.safety().clone());
+        let manager = ProtocolManager::with_parts(config, registry.clone(), safety, Vec::new()).await.unwrap();
+        assert!(Arc::ptr_eq(manager.registry(), &registry));
+
+        let service = ServiceInfo::new("Shared Registry", "_shared._tcp", 8080, None)
+            .unwrap()
+            .with_address("127.0.0.1".parse().unwrap());
+        manager.register_service(service).await.unwrap();
+        let local = registry.get_local_services().await;
+        assert!(local.iter().any(|s| s.name == "Shared Registry"));
+    }
+
+    #[cfg(not(all(windows, feature = "windows-dns")))]
+    #[tokio::test]
+    async fn test_unavailable_mdns_backend_reported() {
+        let config = DiscoveryConfig::new()
+            .with_mdns(crate::config::MdnsConfig::new().with_backend(MdnsBackend::WindowsDnsApi));
+        let manager = ProtocolManager::new(config).await.unwrap();
+        assert!(manager.protocols.is_empty());
+        let (protocol_type, error) = &manager.init_report().failures()[0];
+        assert_eq!(*protocol_type, ProtocolType::Mdns);
+        assert!(error.contains("windows-dns"), "{error}");
+    }
 
     #[tokio::test]
     async fn test_protocol_manager_creation() {
@@ -223,6 +1397,30 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_open_breaker_reported_in_health_check() {
+        let config = DiscoveryConfig::new().with_protocol(ProtocolType::Mdns);
+        let manager = ProtocolManager::new(config).await.unwrap();
+        let threshold = manager.safety().config().circuit_breaker.failure_threshold;
+        for _ in 0..threshold {
+            manager.safety().record_protocol_result(ProtocolType::Mdns, false);
+        }
+
+        let health = manager.health_check().await[&ProtocolType::Mdns];
+        assert_eq!(health.circuit, CircuitState::Open);
+        assert!(!health.is_healthy());
+
+        let result = manager
+            .discover_services_with_protocol(ProtocolType::Mdns, vec![ServiceType::new("_http._tcp").unwrap()], None)
+            .await;
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
+        let context = error.context().unwrap();
+        assert_eq!(context.protocol, Some(ProtocolType::Mdns));
+        assert_eq!(context.service_type, Some(ServiceType::new("_http._tcp").unwrap()));
+        assert!(manager.discover_services(vec![], None).await.flatten().unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_service_registration() {
         let config = DiscoveryConfig::new().with_protocol(ProtocolType::Mdns);
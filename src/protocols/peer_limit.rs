@@ -0,0 +1,86 @@
+//! Per-source-IP rate limiting for inbound protocol traffic
+
+use governor::{clock::DefaultClock, state::keyed::DefaultKeyedStateStore, Quota, RateLimiter};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+use tracing::debug;
+
+/// Token bucket per peer, so one chatty or malicious host can't keep a
+/// listener busy at the expense of everyone else
+#[derive(Clone)]
+pub(crate) struct PeerRateLimiter {
+    protocol: &'static str,
+    limiter: Arc<RateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock>>,
+    dropped: Arc<Mutex<HashMap<IpAddr, u64>>>,
+}
+
+impl PeerRateLimiter {
+    /// Allow `per_second` packets per peer, with bursts of the same size
+    pub(crate) fn new(protocol: &'static str, per_second: u32) -> Self {
+        let quota = Quota::per_second(NonZeroU32::new(per_second).unwrap_or(NonZeroU32::MIN));
+        Self {
+            protocol,
+            limiter: Arc::new(RateLimiter::keyed(quota)),
+            dropped: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether a packet from `peer` should be processed; counts it as dropped if not
+    pub(crate) fn allow(&self, peer: IpAddr) -> bool {
+        if self.limiter.check_key(&peer).is_ok() {
+            return true;
+        }
+
+        let mut dropped = self.dropped.lock().unwrap_or_else(|e| e.into_inner());
+        let count = dropped.entry(peer).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            debug!("{} rate limit exceeded by {}, dropping packets", self.protocol, peer);
+        }
+
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            "inbound_packets_dropped_total",
+            "protocol" => self.protocol,
+            "peer" => peer.to_string()
+        )
+        .increment(1);
+
+        false
+    }
+
+    /// Number of packets dropped per peer
+    pub(crate) fn dropped(&self) -> HashMap<IpAddr, u64> {
+        self.dropped.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Forget buckets of peers that have gone quiet
+    pub(crate) fn prune(&self) {
+        self.limiter.retain_recent();
+        self.limiter.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_each_peer_independently() {
+        let limiter = PeerRateLimiter::new("ssdp", 3);
+        let noisy: IpAddr = "10.0.0.1".parse().unwrap();
+        let quiet: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let accepted = (0..10).filter(|_| limiter.allow(noisy)).count();
+        assert_eq!(accepted, 3);
+        assert!(limiter.allow(quiet));
+
+        let dropped = limiter.dropped();
+        assert_eq!(dropped.get(&noisy), Some(&7));
+        assert_eq!(dropped.get(&quiet), None);
+    }
+}
@@ -0,0 +1,159 @@
+//! mDNS through the platform's own service discovery API
+//!
+//! Android and iOS restrict the raw multicast sockets the built-in mDNS
+//! backend needs: Android requires a multicast lock, iOS 14+ the multicast
+//! entitlement. Both offer DNS-SD in the OS instead, through `NsdManager`
+//! and Bonjour (`NWBrowser`/`NWListener`, or `DNSServiceBrowse`).
+//!
+//! This crate does not bind those APIs itself. The app implements
+//! [`PlatformDiscovery`] on top of them — in Kotlin or Swift when the crate
+//! is used through uniffi or the C ABI, or in Rust with `jni` or `objc2` —
+//! and hands it to [`PlatformProtocol`], which puts it behind the same
+//! [`DiscoveryProtocol`] interface as the other backends:
+//!
+//! ```rust,ignore
+//! let discovery = ServiceDiscovery::builder(config)
+//!     .with_protocol(PlatformProtocol::new(Arc::new(NsdManagerBridge::new(context))))
+//!     .build()
+//!     .await?;
+//! ```
+//!
+//! Requires the `platform-discovery` feature.
+
+use super::{mdns, Capabilities, DiscoveryProtocol};
+use crate::{
+    error::Result,
+    registry::ServiceRegistry,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+
+/// How long a browse runs when the caller gives no timeout
+const DEFAULT_BROWSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A platform service discovery API, such as `NsdManager` or Bonjour
+#[async_trait]
+pub trait PlatformDiscovery: Send + Sync {
+    /// Name of the API, for logs and errors
+    fn name(&self) -> &str;
+
+    /// Browse for `service_type` for `timeout` and return the resolved services
+    async fn browse(&self, service_type: &ServiceType, timeout: Duration) -> Result<Vec<ServiceInfo>>;
+
+    /// Advertise `service`
+    async fn register(&self, service: &ServiceInfo) -> Result<()>;
+
+    /// Stop advertising `service`
+    async fn unregister(&self, service: &ServiceInfo) -> Result<()>;
+}
+
+/// mDNS backed by a [`PlatformDiscovery`]
+pub struct PlatformProtocol {
+    platform: Arc<dyn PlatformDiscovery>,
+}
+
+impl PlatformProtocol {
+    /// Discover and advertise through `platform`
+    pub fn new(platform: Arc<dyn PlatformDiscovery>) -> Self {
+        Self { platform }
+    }
+}
+
+#[async_trait]
+impl DiscoveryProtocol for PlatformProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::Mdns
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        let timeout = timeout.unwrap_or(DEFAULT_BROWSE_TIMEOUT);
+        let mut services = Vec::new();
+        for service_type in &service_types {
+            let found = self.platform.browse(service_type, timeout).await?;
+            tracing::debug!("{} found {} {} services", self.platform.name(), found.len(), service_type);
+            services.extend(found.into_iter().map(|service| service.with_protocol_type(ProtocolType::Mdns)));
+        }
+        Ok(services)
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        self.platform.register(&service).await
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        self.platform.unregister(service).await
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        let services = self.platform.browse(&service.service_type, DEFAULT_BROWSE_TIMEOUT).await?;
+        Ok(services.iter().any(|found| found.service_id() == service.service_id()))
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    /// The platform keeps its own registry
+    fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+
+    fn capabilities(&self) -> Capabilities {
+        mdns::CAPABILITIES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Platform API that finds what was registered with it
+    #[derive(Default)]
+    struct LoopbackPlatform {
+        services: Mutex<Vec<ServiceInfo>>,
+    }
+
+    #[async_trait]
+    impl PlatformDiscovery for LoopbackPlatform {
+        fn name(&self) -> &str {
+            "loopback"
+        }
+
+        async fn browse(&self, service_type: &ServiceType, _timeout: Duration) -> Result<Vec<ServiceInfo>> {
+            let services = self.services.lock().unwrap();
+            Ok(services.iter().filter(|service| &service.service_type == service_type).cloned().collect())
+        }
+
+        async fn register(&self, service: &ServiceInfo) -> Result<()> {
+            self.services.lock().unwrap().push(service.clone());
+            Ok(())
+        }
+
+        async fn unregister(&self, service: &ServiceInfo) -> Result<()> {
+            self.services.lock().unwrap().retain(|registered| registered.id != service.id);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_platform_protocol() {
+        let protocol = PlatformProtocol::new(Arc::new(LoopbackPlatform::default()));
+        let service = ServiceInfo::new("Phone", "_http._tcp", 8080, None)
+            .unwrap()
+            .with_protocol_type(ProtocolType::Upnp);
+        protocol.register_service(service.clone()).await.unwrap();
+        assert!(protocol.verify_service(&service).await.unwrap());
+
+        let found = protocol.discover_services(vec![service.service_type.clone()], None).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].protocol_type, ProtocolType::Mdns);
+
+        protocol.unregister_service(&service).await.unwrap();
+        assert!(!protocol.verify_service(&service).await.unwrap());
+    }
+}
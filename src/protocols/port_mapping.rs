@@ -0,0 +1,550 @@
+//! Port mapping on the local gateway
+//!
+//! Asks the gateway to forward an external port to this host so services
+//! announced on the LAN can also be reached from outside. PCP (RFC 6887) is
+//! tried first, then NAT-PMP (RFC 6886), which PCP servers fall back to, and
+//! finally UPnP IGD `AddPortMapping` when the `upnp` feature is enabled.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    utils::network,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time::Instant};
+use tracing::debug;
+
+/// Port PCP and NAT-PMP servers listen on
+const PCP_PORT: u16 = 5351;
+
+/// First retransmission interval; doubled after every attempt (RFC 6886 §3.1)
+const INITIAL_RETRY: Duration = Duration::from_millis(250);
+
+const NAT_PMP_VERSION: u8 = 0;
+const PCP_VERSION: u8 = 2;
+const PCP_OPCODE_MAP: u8 = 1;
+const PCP_UNSUPP_VERSION: u8 = 1;
+
+/// Transport protocol of a port mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportProtocol {
+    /// TCP
+    Tcp,
+    /// UDP
+    Udp,
+}
+
+impl TransportProtocol {
+    fn nat_pmp_opcode(self) -> u8 {
+        match self {
+            Self::Udp => 1,
+            Self::Tcp => 2,
+        }
+    }
+
+    fn iana_number(self) -> u8 {
+        match self {
+            Self::Tcp => 6,
+            Self::Udp => 17,
+        }
+    }
+}
+
+impl fmt::Display for TransportProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "TCP"),
+            Self::Udp => write!(f, "UDP"),
+        }
+    }
+}
+
+/// How a mapping was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingMethod {
+    /// Port Control Protocol
+    Pcp,
+    /// NAT Port Mapping Protocol
+    NatPmp,
+    /// UPnP Internet Gateway Device
+    UpnpIgd,
+}
+
+impl fmt::Display for MappingMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pcp => write!(f, "PCP"),
+            Self::NatPmp => write!(f, "NAT-PMP"),
+            Self::UpnpIgd => write!(f, "UPnP IGD"),
+        }
+    }
+}
+
+/// A port forwarded by the gateway
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    /// Address peers outside the NAT connect to
+    pub external: SocketAddr,
+    /// Local port the traffic is forwarded to
+    pub internal_port: u16,
+    /// Transport protocol
+    pub protocol: TransportProtocol,
+    /// Lease granted by the gateway; zero means permanent
+    pub lifetime: Duration,
+    /// Protocol used to create the mapping
+    pub method: MappingMethod,
+}
+
+/// Creates and releases port mappings on the default gateway
+#[derive(Debug)]
+pub struct PortMapper {
+    gateway: Option<IpAddr>,
+    port: u16,
+    timeout: Duration,
+    #[cfg(feature = "upnp")]
+    igd: tokio::sync::Mutex<Option<crate::protocols::upnp::igd::InternetGateway>>,
+}
+
+impl Default for PortMapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortMapper {
+    /// Create a mapper for the system's default gateway
+    pub fn new() -> Self {
+        Self {
+            gateway: None,
+            port: PCP_PORT,
+            timeout: Duration::from_secs(3),
+            #[cfg(feature = "upnp")]
+            igd: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Talk to this gateway instead of the default one
+    pub fn with_gateway(mut self, gateway: IpAddr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Time allowed for each mapping method
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn gateway(&self) -> Option<IpAddr> {
+        self.gateway.or_else(|| network::default_gateway().map(IpAddr::V4))
+    }
+
+    /// Forward an external port to `internal_port` on this host for `lease`
+    ///
+    /// The gateway picks the external port, preferring `internal_port`, and
+    /// may grant a shorter lease than requested.
+    pub async fn request(
+        &self,
+        internal_port: u16,
+        protocol: TransportProtocol,
+        lease: Duration,
+    ) -> Result<PortMapping> {
+        let mut errors = Vec::new();
+
+        match self.gateway() {
+            Some(gateway) => {
+                let socket = self.socket(gateway).await?;
+                match self.pcp_map(&socket, internal_port, protocol, lease).await {
+                    Ok(mapping) => return Ok(mapping),
+                    Err(e) => {
+                        debug!("PCP mapping via {} failed: {}", gateway, e);
+                        errors.push(format!("PCP: {e}"));
+                    }
+                }
+                match self.nat_pmp_map(&socket, internal_port, protocol, lease).await {
+                    Ok(mapping) => return Ok(mapping),
+                    Err(e) => {
+                        debug!("NAT-PMP mapping via {} failed: {}", gateway, e);
+                        errors.push(format!("NAT-PMP: {e}"));
+                    }
+                }
+            }
+            None => errors.push("no default gateway".to_string()),
+        }
+
+        #[cfg(feature = "upnp")]
+        match self.igd_map(internal_port, protocol, lease).await {
+            Ok(mapping) => return Ok(mapping),
+            Err(e) => {
+                debug!("UPnP IGD mapping failed: {}", e);
+                errors.push(format!("UPnP IGD: {e}"));
+            }
+        }
+
+        Err(DiscoveryError::network(format!(
+            "Could not map {protocol} port {internal_port}: {}",
+            errors.join("; ")
+        )))
+    }
+
+    /// Remove a mapping before its lease runs out
+    pub async fn release(&self, mapping: &PortMapping) -> Result<()> {
+        match mapping.method {
+            MappingMethod::Pcp | MappingMethod::NatPmp => {
+                let gateway = self.gateway().ok_or_else(no_gateway)?;
+                let socket = self.socket(gateway).await?;
+                if mapping.method == MappingMethod::Pcp {
+                    self.pcp_map(&socket, mapping.internal_port, mapping.protocol, Duration::ZERO)
+                        .await
+                        .map(|_| ())
+                } else {
+                    self.nat_pmp_map(&socket, mapping.internal_port, mapping.protocol, Duration::ZERO)
+                        .await
+                        .map(|_| ())
+                }
+            }
+            #[cfg(feature = "upnp")]
+            MappingMethod::UpnpIgd => {
+                let gateway = self.internet_gateway().await?;
+                gateway
+                    .delete_port_mapping(mapping.protocol, mapping.external.port(), self.timeout)
+                    .await
+            }
+            #[cfg(not(feature = "upnp"))]
+            MappingMethod::UpnpIgd => Err(DiscoveryError::configuration("UPnP support is not enabled")),
+        }
+    }
+
+    async fn socket(&self, gateway: IpAddr) -> Result<UdpSocket> {
+        let local: IpAddr = match gateway {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0)).await?;
+        socket.connect((gateway, self.port)).await?;
+        Ok(socket)
+    }
+
+    async fn pcp_map(
+        &self,
+        socket: &UdpSocket,
+        internal_port: u16,
+        protocol: TransportProtocol,
+        lease: Duration,
+    ) -> Result<PortMapping> {
+        let client = socket.local_addr()?.ip();
+        let nonce: [u8; 12] = rand::random();
+        let request = encode_pcp_map(client, nonce, internal_port, protocol, lease);
+
+        let response = transact(socket, &request, self.timeout, |response| {
+            // A NAT-PMP-only server answers with its own version and an error
+            (response.len() >= 60 && response[0] == PCP_VERSION && response[1] == 0x80 | PCP_OPCODE_MAP)
+                || (response.len() >= 4 && response[0] == NAT_PMP_VERSION)
+        })
+        .await?;
+        decode_pcp_map(&response, nonce, internal_port, protocol)
+    }
+
+    async fn nat_pmp_map(
+        &self,
+        socket: &UdpSocket,
+        internal_port: u16,
+        protocol: TransportProtocol,
+        lease: Duration,
+    ) -> Result<PortMapping> {
+        let address = transact(socket, &[NAT_PMP_VERSION, 0], self.timeout, |response| {
+            response.len() >= 12 && response[0] == NAT_PMP_VERSION && response[1] == 128
+        })
+        .await?;
+        let external_ip = decode_nat_pmp_address(&address)?;
+
+        let request = encode_nat_pmp_map(internal_port, protocol, lease);
+        let opcode = 128 + protocol.nat_pmp_opcode();
+        let response = transact(socket, &request, self.timeout, |response| {
+            response.len() >= 16 && response[0] == NAT_PMP_VERSION && response[1] == opcode
+        })
+        .await?;
+        let (external_port, lifetime) = decode_nat_pmp_map(&response)?;
+
+        Ok(PortMapping {
+            external: SocketAddr::new(external_ip.into(), external_port),
+            internal_port,
+            protocol,
+            lifetime,
+            method: MappingMethod::NatPmp,
+        })
+    }
+
+    /// UPnP gateway on the local network, discovered on first use
+    #[cfg(feature = "upnp")]
+    pub async fn internet_gateway(&self) -> Result<crate::protocols::upnp::igd::InternetGateway> {
+        let mut cached = self.igd.lock().await;
+        if let Some(gateway) = cached.as_ref() {
+            return Ok(gateway.clone());
+        }
+        let gateway = crate::protocols::upnp::igd::InternetGateway::discover(self.timeout).await?;
+        *cached = Some(gateway.clone());
+        Ok(gateway)
+    }
+
+    /// Public IP address reported by the UPnP gateway
+    ///
+    /// A gateway that stops answering is forgotten so the next call
+    /// discovers it again.
+    #[cfg(feature = "upnp")]
+    pub async fn external_address(&self) -> Result<IpAddr> {
+        let gateway = self.internet_gateway().await?;
+        let result = gateway.external_ip(self.timeout).await;
+        if result.is_err() {
+            self.igd.lock().await.take();
+        }
+        result
+    }
+
+    #[cfg(feature = "upnp")]
+    async fn igd_map(&self, internal_port: u16, protocol: TransportProtocol, lease: Duration) -> Result<PortMapping> {
+        let gateway = self.internet_gateway().await?;
+        let local = gateway.local_address().await?;
+        gateway
+            .add_port_mapping(
+                protocol,
+                internal_port,
+                SocketAddr::new(local, internal_port),
+                lease,
+                "auto-discovery",
+                self.timeout,
+            )
+            .await?;
+        let external_ip = gateway.external_ip(self.timeout).await?;
+
+        Ok(PortMapping {
+            external: SocketAddr::new(external_ip, internal_port),
+            internal_port,
+            protocol,
+            lifetime: lease,
+            method: MappingMethod::UpnpIgd,
+        })
+    }
+}
+
+fn no_gateway() -> DiscoveryError {
+    DiscoveryError::network("No default gateway found")
+}
+
+/// Send `request` until a response accepted by `valid` arrives, doubling the
+/// retransmission interval each time
+async fn transact(
+    socket: &UdpSocket,
+    request: &[u8],
+    timeout: Duration,
+    valid: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut interval = INITIAL_RETRY;
+    let mut buf = [0u8; 1100];
+
+    while Instant::now() < deadline {
+        socket.send(request).await?;
+        let retry_at = (Instant::now() + interval).min(deadline);
+        while let Ok(received) = tokio::time::timeout_at(retry_at, socket.recv(&mut buf)).await {
+            let len = match received {
+                Ok(len) => len,
+                // ICMP port unreachable: nothing is listening on the gateway
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    return Err(DiscoveryError::network("Gateway refused the request"));
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if valid(&buf[..len]) {
+                return Ok(buf[..len].to_vec());
+            }
+        }
+        interval *= 2;
+    }
+
+    Err(DiscoveryError::timeout("Gateway did not respond"))
+}
+
+fn lifetime_secs(lease: Duration) -> u32 {
+    u32::try_from(lease.as_secs()).unwrap_or(u32::MAX)
+}
+
+fn mapped_address(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn unmapped_address(ip: Ipv6Addr) -> IpAddr {
+    ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4)
+}
+
+fn encode_pcp_map(
+    client: IpAddr,
+    nonce: [u8; 12],
+    internal_port: u16,
+    protocol: TransportProtocol,
+    lease: Duration,
+) -> [u8; 60] {
+    let mut request = [0u8; 60];
+    request[0] = PCP_VERSION;
+    request[1] = PCP_OPCODE_MAP;
+    request[4..8].copy_from_slice(&lifetime_secs(lease).to_be_bytes());
+    request[8..24].copy_from_slice(&mapped_address(client).octets());
+    request[24..36].copy_from_slice(&nonce);
+    request[36] = protocol.iana_number();
+    request[40..42].copy_from_slice(&internal_port.to_be_bytes());
+    // Suggest the same external port; leave the external address unspecified
+    request[42..44].copy_from_slice(&internal_port.to_be_bytes());
+    let any: IpAddr = match client {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    request[44..60].copy_from_slice(&mapped_address(any).octets());
+    request
+}
+
+fn decode_pcp_map(
+    response: &[u8],
+    nonce: [u8; 12],
+    internal_port: u16,
+    protocol: TransportProtocol,
+) -> Result<PortMapping> {
+    if response[0] != PCP_VERSION {
+        return Err(DiscoveryError::protocol("Gateway does not support PCP"));
+    }
+    match response[3] {
+        0 => {}
+        PCP_UNSUPP_VERSION => return Err(DiscoveryError::protocol("Gateway does not support PCP")),
+        code => return Err(DiscoveryError::protocol(format!("PCP request failed with result code {code}"))),
+    }
+    if response[24..36] != nonce {
+        return Err(DiscoveryError::protocol("PCP response nonce does not match the request"));
+    }
+
+    let lifetime = u32::from_be_bytes([response[4], response[5], response[6], response[7]]);
+    let external_port = u16::from_be_bytes([response[42], response[43]]);
+    let mut external_ip = [0u8; 16];
+    external_ip.copy_from_slice(&response[44..60]);
+
+    Ok(PortMapping {
+        external: SocketAddr::new(unmapped_address(Ipv6Addr::from(external_ip)), external_port),
+        internal_port,
+        protocol,
+        lifetime: Duration::from_secs(lifetime.into()),
+        method: MappingMethod::Pcp,
+    })
+}
+
+fn nat_pmp_result(response: &[u8]) -> Result<()> {
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(()),
+        code => Err(DiscoveryError::protocol(format!("NAT-PMP request failed with result code {code}"))),
+    }
+}
+
+fn decode_nat_pmp_address(response: &[u8]) -> Result<Ipv4Addr> {
+    nat_pmp_result(response)?;
+    Ok(Ipv4Addr::new(response[8], response[9], response[10], response[11]))
+}
+
+fn encode_nat_pmp_map(internal_port: u16, protocol: TransportProtocol, lease: Duration) -> [u8; 12] {
+    let mut request = [0u8; 12];
+    request[0] = NAT_PMP_VERSION;
+    request[1] = protocol.nat_pmp_opcode();
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&internal_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime_secs(lease).to_be_bytes());
+    request
+}
+
+fn decode_nat_pmp_map(response: &[u8]) -> Result<(u16, Duration)> {
+    nat_pmp_result(response)?;
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+    Ok((external_port, Duration::from_secs(lifetime.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pcp_map_round_trip() {
+        let nonce = [7u8; 12];
+        let request = encode_pcp_map(
+            "192.168.1.20".parse().unwrap(),
+            nonce,
+            8080,
+            TransportProtocol::Tcp,
+            Duration::from_secs(3600),
+        );
+        assert_eq!(request[..2], [PCP_VERSION, PCP_OPCODE_MAP]);
+        assert_eq!(request[36], 6);
+
+        // Server echoes the payload, filling in the assigned address and port
+        let mut response = request;
+        response[1] = 0x80 | PCP_OPCODE_MAP;
+        response[4..8].copy_from_slice(&1800u32.to_be_bytes());
+        response[42..44].copy_from_slice(&40000u16.to_be_bytes());
+        response[44..60].copy_from_slice(&Ipv4Addr::new(203, 0, 113, 5).to_ipv6_mapped().octets());
+
+        let mapping = decode_pcp_map(&response, nonce, 8080, TransportProtocol::Tcp).unwrap();
+        assert_eq!(mapping.external, "203.0.113.5:40000".parse().unwrap());
+        assert_eq!(mapping.lifetime, Duration::from_secs(1800));
+        assert_eq!(mapping.method, MappingMethod::Pcp);
+
+        assert!(decode_pcp_map(&response, [0u8; 12], 8080, TransportProtocol::Tcp).is_err());
+    }
+
+    /// Minimal NAT-PMP server that rejects PCP like a NAT-PMP-only gateway
+    async fn fake_nat_pmp_gateway() -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1100];
+            while let Ok((len, peer)) = socket.recv_from(&mut buf).await {
+                let response = match (buf[0], buf[1]) {
+                    (PCP_VERSION, _) => vec![NAT_PMP_VERSION, 0x80 | buf[1], 0, 1],
+                    (NAT_PMP_VERSION, 0) => vec![0, 128, 0, 0, 0, 0, 0, 1, 198, 51, 100, 7],
+                    (NAT_PMP_VERSION, opcode) if len >= 12 => {
+                        let mut response = vec![0, 128 + opcode, 0, 0, 0, 0, 0, 1];
+                        response.extend_from_slice(&buf[4..6]);
+                        response.extend_from_slice(&50000u16.to_be_bytes());
+                        response.extend_from_slice(&buf[8..12]);
+                        response
+                    }
+                    _ => continue,
+                };
+                let _ = socket.send_to(&response, peer).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_nat_pmp() {
+        let mapper = PortMapper {
+            port: fake_nat_pmp_gateway().await,
+            ..PortMapper::new().with_gateway(Ipv4Addr::LOCALHOST.into()).with_timeout(Duration::from_secs(2))
+        };
+
+        let mapping = mapper
+            .request(8080, TransportProtocol::Udp, Duration::from_secs(600))
+            .await
+            .unwrap();
+        assert_eq!(mapping.external, "198.51.100.7:50000".parse().unwrap());
+        assert_eq!(mapping.internal_port, 8080);
+        assert_eq!(mapping.lifetime, Duration::from_secs(600));
+        assert_eq!(mapping.method, MappingMethod::NatPmp);
+
+        mapper.release(&mapping).await.unwrap();
+    }
+}
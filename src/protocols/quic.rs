@@ -0,0 +1,570 @@
+//! Discovery through a rendezvous server over QUIC
+//!
+//! Some networks forbid advertising services in plaintext multicast. There,
+//! a [`RendezvousServer`] takes the place of the multicast group: clients
+//! connect to it over QUIC, announce their services to it and browse the
+//! services others announced. [`QuicProtocol`] is the client, put behind the
+//! [`DiscoveryProtocol`] interface so it can stand in for a multicast
+//! protocol through
+//! [`ServiceDiscoveryBuilder::with_protocol`](crate::ServiceDiscoveryBuilder::with_protocol).
+//!
+//! Connections use TLS 1.3 with mutual authentication: the server and every
+//! client present a certificate issued by a CA they share, configured with a
+//! [`QuicTlsConfig`]. Announcements last as long as the connection that made
+//! them, so services of a client that goes away disappear with it; clients
+//! announce their services again when they reconnect.
+//!
+//! Requires the `quic` feature.
+
+use super::DiscoveryProtocol;
+use crate::{
+    error::{DiscoveryError, Result},
+    registry::ServiceRegistry,
+    service::{ServiceId, ServiceInfo},
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore,
+    },
+    Connection, Endpoint, TransportConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+/// ALPN protocol identifier of the rendezvous protocol
+pub const ALPN: &[u8] = b"autodiscovery-rendezvous/1";
+
+/// Largest request or response accepted, in bytes
+const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Interval of keep-alives that hold idle client connections open
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Certificates for mutually authenticated TLS 1.3
+pub struct QuicTlsConfig {
+    /// CAs that issue the certificates of the other side
+    roots: Vec<CertificateDer<'static>>,
+    /// Our certificate, followed by any intermediates
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl Clone for QuicTlsConfig {
+    fn clone(&self) -> Self {
+        Self {
+            roots: self.roots.clone(),
+            chain: self.chain.clone(),
+            key: self.key.clone_key(),
+        }
+    }
+}
+
+impl fmt::Debug for QuicTlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuicTlsConfig")
+            .field("roots", &self.roots.len())
+            .field("chain", &self.chain.len())
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl QuicTlsConfig {
+    /// Load the CA certificates, our certificate chain and our private key
+    /// from PEM
+    pub fn from_pem(ca: &[u8], chain: &[u8], key: &[u8]) -> Result<Self> {
+        let certificates = |pem: &[u8], what: &str| {
+            let certificates = CertificateDer::pem_slice_iter(pem)
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| DiscoveryError::configuration(format!("Invalid {what} PEM: {e}")))?;
+            if certificates.is_empty() {
+                return Err(DiscoveryError::configuration(format!("No certificates in the {what} PEM")));
+            }
+            Ok(certificates)
+        };
+        Ok(Self {
+            roots: certificates(ca, "CA")?,
+            chain: certificates(chain, "certificate chain")?,
+            key: PrivateKeyDer::from_pem_slice(key)
+                .map_err(|e| DiscoveryError::configuration(format!("Invalid private key PEM: {e}")))?,
+        })
+    }
+
+    /// Load the CA certificates, our certificate chain and our private key
+    /// from PEM files
+    pub fn from_pem_files(ca: impl AsRef<Path>, chain: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self> {
+        Self::from_pem(&std::fs::read(ca)?, &std::fs::read(chain)?, &std::fs::read(key)?)
+    }
+
+    fn root_store(&self) -> Result<Arc<RootCertStore>> {
+        let mut roots = RootCertStore::empty();
+        for root in &self.roots {
+            roots
+                .add(root.clone())
+                .map_err(|e| DiscoveryError::configuration(format!("Invalid CA certificate: {e}")))?;
+        }
+        Ok(Arc::new(roots))
+    }
+
+    fn server_config(&self) -> Result<quinn::ServerConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = WebPkiClientVerifier::builder_with_provider(self.root_store()?, provider.clone())
+            .build()
+            .map_err(|e| DiscoveryError::configuration(format!("Invalid client verifier: {e}")))?;
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(tls_error)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(self.chain.clone(), self.key.clone_key())
+            .map_err(tls_error)?;
+        config.alpn_protocols = vec![ALPN.to_vec()];
+        let crypto = QuicServerConfig::try_from(config)
+            .map_err(|e| DiscoveryError::configuration(format!("Unusable TLS configuration: {e}")))?;
+        Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+    }
+
+    fn client_config(&self) -> Result<quinn::ClientConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(tls_error)?
+            .with_root_certificates(self.root_store()?)
+            .with_client_auth_cert(self.chain.clone(), self.key.clone_key())
+            .map_err(tls_error)?;
+        config.alpn_protocols = vec![ALPN.to_vec()];
+        let crypto = QuicClientConfig::try_from(config)
+            .map_err(|e| DiscoveryError::configuration(format!("Unusable TLS configuration: {e}")))?;
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        let mut client = quinn::ClientConfig::new(Arc::new(crypto));
+        client.transport_config(Arc::new(transport));
+        Ok(client)
+    }
+}
+
+/// Request sent on its own bidirectional stream
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    /// Make a service visible to other clients
+    Announce { service: ServiceInfo },
+    /// Stop announcing a service
+    Withdraw { service: ServiceInfo },
+    /// List announced services of the given types, or of all types
+    Browse { service_types: Vec<ServiceType> },
+}
+
+/// Answer to a [`Request`]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    Done,
+    Services { services: Vec<ServiceInfo> },
+    Failed { message: String },
+}
+
+/// Announced services, with the connection that announced each
+type Announcements = StdMutex<HashMap<ServiceId, (usize, ServiceInfo)>>;
+
+/// Server clients announce services to and browse them from
+#[derive(Debug)]
+pub struct RendezvousServer {
+    tls: QuicTlsConfig,
+}
+
+impl RendezvousServer {
+    /// Accept clients with certificates issued by the CAs in `tls`
+    pub fn new(tls: QuicTlsConfig) -> Self {
+        Self { tls }
+    }
+
+    /// Listen on `addr`; port `0` picks a free port
+    pub async fn bind(self, addr: SocketAddr) -> Result<RendezvousHandle> {
+        let endpoint = Endpoint::server(self.tls.server_config()?, addr)?;
+        let local_addr = endpoint.local_addr()?;
+        info!("Rendezvous server listening on {}", local_addr);
+
+        let announcements: Arc<Announcements> = Arc::default();
+        let accepting = endpoint.clone();
+        let task = tokio::spawn(async move {
+            while let Some(incoming) = accepting.accept().await {
+                let announcements = announcements.clone();
+                tokio::spawn(async move {
+                    let connection = match incoming.await {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            debug!("Rendezvous handshake failed: {}", e);
+                            return;
+                        }
+                    };
+                    serve(&connection, &announcements).await;
+                    // Services leave with the client that announced them
+                    let id = connection.stable_id();
+                    announcements
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .retain(|_, (owner, _)| *owner != id);
+                });
+            }
+        });
+
+        Ok(RendezvousHandle { local_addr, endpoint, task })
+    }
+}
+
+/// Answer the requests of one client until it disconnects
+async fn serve(connection: &Connection, announcements: &Announcements) {
+    let peer = connection.remote_address();
+    debug!("Rendezvous client connected from {}", peer);
+    loop {
+        let (mut send, mut recv) = match connection.accept_bi().await {
+            Ok(streams) => streams,
+            Err(e) => {
+                debug!("Rendezvous client {} disconnected: {}", peer, e);
+                return;
+            }
+        };
+        let response = match recv.read_to_end(MAX_MESSAGE_SIZE).await {
+            Ok(request) => match serde_json::from_slice(&request) {
+                Ok(request) => answer(connection.stable_id(), request, announcements),
+                Err(e) => Response::Failed { message: format!("Malformed request: {e}") },
+            },
+            Err(e) => Response::Failed { message: format!("Failed to read request: {e}") },
+        };
+        let written = match serde_json::to_vec(&response) {
+            Ok(response) => send.write_all(&response).await.is_ok() && send.finish().is_ok(),
+            Err(_) => false,
+        };
+        if !written {
+            warn!("Failed to answer rendezvous client {}", peer);
+        }
+    }
+}
+
+fn answer(connection: usize, request: Request, announcements: &Announcements) -> Response {
+    let mut announcements = announcements.lock().unwrap_or_else(|e| e.into_inner());
+    match request {
+        Request::Announce { service } => {
+            announcements.insert(service.service_id(), (connection, service));
+            Response::Done
+        }
+        Request::Withdraw { service } => {
+            let id = service.service_id();
+            if announcements.get(&id).is_some_and(|(owner, _)| *owner == connection) {
+                announcements.remove(&id);
+                Response::Done
+            } else {
+                Response::Failed { message: format!("{id} was not announced on this connection") }
+            }
+        }
+        Request::Browse { service_types } => Response::Services {
+            services: announcements
+                .values()
+                .map(|(_, service)| service)
+                .filter(|service| service_types.is_empty() || service_types.contains(&service.service_type))
+                .cloned()
+                .collect(),
+        },
+    }
+}
+
+/// A running [`RendezvousServer`]
+#[derive(Debug)]
+pub struct RendezvousHandle {
+    local_addr: SocketAddr,
+    endpoint: Endpoint,
+    task: JoinHandle<()>,
+}
+
+impl RendezvousHandle {
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Close every connection and stop accepting new ones
+    pub fn shutdown(self) {
+        self.endpoint.close(0u32.into(), b"shutting down");
+        self.task.abort();
+    }
+}
+
+/// A rendezvous server standing in for a protocol
+///
+/// Connects on first use and again after the connection is lost, announcing
+/// its services anew each time.
+pub struct QuicProtocol {
+    endpoint: Endpoint,
+    server: SocketAddr,
+    server_name: String,
+    protocol_type: ProtocolType,
+    connection: Mutex<Option<Connection>>,
+    /// Services to announce again after reconnecting
+    announced: StdMutex<HashMap<ServiceId, ServiceInfo>>,
+}
+
+impl QuicProtocol {
+    /// Use the rendezvous server at `server`, whose certificate is issued
+    /// for `server_name`, in place of mDNS
+    pub fn new(server: SocketAddr, server_name: impl Into<String>, tls: &QuicTlsConfig) -> Result<Self> {
+        let local = match server {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(tls.client_config()?);
+        Ok(Self {
+            endpoint,
+            server,
+            server_name: server_name.into(),
+            protocol_type: ProtocolType::Mdns,
+            connection: Mutex::new(None),
+            announced: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    /// Stand in for `protocol_type` rather than mDNS
+    pub fn with_protocol_type(mut self, protocol_type: ProtocolType) -> Self {
+        self.protocol_type = protocol_type;
+        self
+    }
+
+    /// The open connection, connecting first if there is none
+    async fn connection(&self) -> Result<Connection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(open) = connection.as_ref().filter(|open| open.close_reason().is_none()) {
+            return Ok(open.clone());
+        }
+        let connecting = self
+            .endpoint
+            .connect(self.server, &self.server_name)
+            .map_err(|e| DiscoveryError::network(format!("Failed to connect to {}: {e}", self.server)))?;
+        let open = connecting
+            .await
+            .map_err(|e| DiscoveryError::network(format!("Failed to connect to {}: {e}", self.server)))?;
+        debug!("Connected to rendezvous server {}", self.server);
+
+        let services: Vec<ServiceInfo> = self
+            .announced
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect();
+        for service in services {
+            call(&open, &Request::Announce { service }).await?;
+        }
+        *connection = Some(open.clone());
+        Ok(open)
+    }
+
+    async fn request(&self, request: &Request) -> Result<Response> {
+        call(&self.connection().await?, request).await
+    }
+
+    async fn browse(&self, service_types: Vec<ServiceType>) -> Result<Vec<ServiceInfo>> {
+        match self.request(&Request::Browse { service_types }).await? {
+            Response::Services { services } => Ok(services
+                .into_iter()
+                .map(|service| service.with_protocol_type(self.protocol_type))
+                .collect()),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// Send `request` on a new stream and wait for the response
+async fn call(connection: &Connection, request: &Request) -> Result<Response> {
+    let (mut send, mut recv) = connection
+        .open_bi()
+        .await
+        .map_err(|e| DiscoveryError::network(format!("Rendezvous connection lost: {e}")))?;
+    let request = serde_json::to_vec(request)
+        .map_err(|e| DiscoveryError::invalid_data(format!("Failed to encode rendezvous request: {e}")))?;
+    send.write_all(&request)
+        .await
+        .map_err(|e| DiscoveryError::network(format!("Failed to send rendezvous request: {e}")))?;
+    send.finish()
+        .map_err(|e| DiscoveryError::network(format!("Failed to send rendezvous request: {e}")))?;
+    let response = recv
+        .read_to_end(MAX_MESSAGE_SIZE)
+        .await
+        .map_err(|e| DiscoveryError::network(format!("Failed to read rendezvous response: {e}")))?;
+    serde_json::from_slice(&response)
+        .map_err(|e| DiscoveryError::invalid_data(format!("Malformed rendezvous response: {e}")))
+}
+
+fn unexpected(response: Response) -> DiscoveryError {
+    match response {
+        Response::Failed { message } => DiscoveryError::protocol(message),
+        response => DiscoveryError::protocol(format!("Unexpected rendezvous response: {response:?}")),
+    }
+}
+
+fn tls_error(error: rustls::Error) -> DiscoveryError {
+    DiscoveryError::configuration(format!("Invalid TLS configuration: {error}"))
+}
+
+#[async_trait]
+impl DiscoveryProtocol for QuicProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        self.protocol_type
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.browse(service_types))
+                .await
+                .map_err(|_| DiscoveryError::timeout(format!("Rendezvous server {} did not answer", self.server)))?,
+            None => self.browse(service_types).await,
+        }
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        self.announced
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(service.service_id(), service.clone());
+        match self.request(&Request::Announce { service }).await? {
+            Response::Done => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        self.announced
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&service.service_id());
+        match self.request(&Request::Withdraw { service: service.clone() }).await? {
+            Response::Done => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        let services = self.browse(vec![service.service_type.clone()]).await?;
+        Ok(services.iter().any(|found| found.service_id() == service.service_id()))
+    }
+
+    async fn is_available(&self) -> bool {
+        self.browse(Vec::new()).await.is_ok()
+    }
+
+    /// The rendezvous server keeps its own list of services
+    fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+
+    /// A CA and a function issuing certificates from it
+    struct Authority {
+        certificate: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    impl Authority {
+        fn new() -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Self { certificate: params.self_signed(&key).unwrap(), key }
+        }
+
+        fn issue(&self, name: &str, purpose: ExtendedKeyUsagePurpose) -> QuicTlsConfig {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.extended_key_usages = vec![purpose];
+            let certificate = params.signed_by(&key, &self.certificate, &self.key).unwrap();
+            QuicTlsConfig::from_pem(
+                self.certificate.pem().as_bytes(),
+                certificate.pem().as_bytes(),
+                key.serialize_pem().as_bytes(),
+            )
+            .unwrap()
+        }
+    }
+
+    async fn server(authority: &Authority) -> RendezvousHandle {
+        let tls = authority.issue("rendezvous.test", ExtendedKeyUsagePurpose::ServerAuth);
+        RendezvousServer::new(tls).bind("127.0.0.1:0".parse().unwrap()).await.unwrap()
+    }
+
+    fn client(authority: &Authority, server: &RendezvousHandle) -> QuicProtocol {
+        let tls = authority.issue("client.test", ExtendedKeyUsagePurpose::ClientAuth);
+        QuicProtocol::new(server.local_addr(), "rendezvous.test", &tls).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_announce_and_browse() {
+        let authority = Authority::new();
+        let server = server(&authority).await;
+        let (printer, laptop) = (client(&authority, &server), client(&authority, &server));
+
+        let service = ServiceInfo::new("Office Printer", "_ipp._tcp", 631, None).unwrap();
+        printer.register_service(service.clone()).await.unwrap();
+        let found = laptop.discover_services(vec![service.service_type.clone()], None).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].service_id(), service.service_id());
+        assert!(laptop.verify_service(&service).await.unwrap());
+        assert!(laptop.discover_services(vec![ServiceType::new("_http._tcp").unwrap()], None).await.unwrap().is_empty());
+
+        // Only the announcing client can withdraw a service
+        assert!(laptop.unregister_service(&service).await.is_err());
+        printer.unregister_service(&service).await.unwrap();
+        assert!(!laptop.verify_service(&service).await.unwrap());
+
+        // Services leave with the connection that announced them
+        printer.register_service(service.clone()).await.unwrap();
+        assert!(laptop.verify_service(&service).await.unwrap());
+        drop(printer);
+        let mut gone = false;
+        for _ in 0..50 {
+            if !laptop.verify_service(&service).await.unwrap() {
+                gone = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(gone);
+        server.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_clients_need_a_certificate_from_the_ca() {
+        let authority = Authority::new();
+        let server = server(&authority).await;
+        // Trusts the server, but its own certificate comes from another CA
+        let trusted = authority.issue("client.test", ExtendedKeyUsagePurpose::ClientAuth);
+        let rogue = Authority::new().issue("client.test", ExtendedKeyUsagePurpose::ClientAuth);
+        let tls = QuicTlsConfig { roots: trusted.roots, ..rogue };
+        let intruder = QuicProtocol::new(server.local_addr(), "rendezvous.test", &tls).unwrap();
+
+        assert!(!intruder.is_available().await);
+        let service = ServiceInfo::new("Rogue", "_ipp._tcp", 631, None).unwrap();
+        assert!(intruder.register_service(service).await.is_err());
+        server.shutdown();
+    }
+}
@@ -0,0 +1,441 @@
+//! mDNS reflector
+//!
+//! mDNS is link-local: queries and answers never leave the subnet they were
+//! sent on. The reflector listens for mDNS traffic on several interfaces and
+//! repeats it on the others, so clients on one subnet can browse services
+//! advertised on another. Only records of the allowed service types, and the
+//! address records of the hosts offering them, are repeated.
+//!
+//! Packets are repeated rather than re-registered with a local responder:
+//! a responder only announces addresses on the link it sends on, while the
+//! point of reflecting is to hand out the advertiser's own address. IPv4 only.
+//!
+//! Loop protection works on three levels. Packets sent from this host's own
+//! addresses are ignored, as are packets from sources outside the networks of
+//! the configured interfaces. A packet identical to one repeated moments ago
+//! is not repeated again, which stops two reflectors bridging the same links
+//! from echoing each other's traffic forever.
+
+use crate::{
+    config::{ReflectorConfig, SocketOptions},
+    error::{DiscoveryError, Result},
+    types::{NetworkInterface, ServiceType},
+    utils::network,
+};
+use ipnet::IpNet;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tracing::{debug, info, warn};
+use trust_dns_proto::{
+    op::{Message, Query},
+    rr::{Name, RData, Record},
+};
+
+/// mDNS IPv4 multicast group (RFC 6762 §3)
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// mDNS port
+const MDNS_PORT: u16 = 5353;
+
+/// How long a repeated packet is remembered for duplicate suppression
+///
+/// Echoes arrive within milliseconds, while responders space their own
+/// repeated announcements at least a second apart.
+const DUPLICATE_WINDOW: Duration = Duration::from_millis(500);
+
+/// Largest mDNS packet accepted (RFC 6762 §17)
+const MAX_PACKET: usize = 9000;
+
+/// An interface the reflector repeats traffic on
+#[derive(Debug, Clone)]
+struct Link {
+    name: String,
+    /// Address multicast is sent from
+    address: Ipv4Addr,
+    interface: NetworkInterface,
+}
+
+/// Repeats mDNS traffic for selected service types between interfaces
+///
+/// Started by the [`ProtocolManager`](super::ProtocolManager) when the mDNS
+/// configuration has a [`ReflectorConfig`]. Reflection stops when the
+/// reflector is dropped.
+#[derive(Debug)]
+pub struct MdnsReflector {
+    interfaces: Vec<String>,
+    task: JoinHandle<()>,
+}
+
+impl MdnsReflector {
+    /// Start reflecting between the configured interfaces
+    ///
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is invalid, an interface is
+    /// missing or has no IPv4 address, or the mDNS socket cannot be opened.
+    pub fn new(config: &ReflectorConfig) -> Result<Self> {
+        Self::with_socket_options(config, &SocketOptions::default())
+    }
+
+    /// Start reflecting, applying `options` to the mDNS socket
+    ///
+    /// The options override the reflector's own TTL of 255 and disabled
+    /// loopback. Each packet is sent out of its link's interface whatever
+    /// the outgoing interface option says.
+    ///
+    /// # Errors
+    ///
+    /// As for [`new`](Self::new), or if an option is rejected.
+    pub fn with_socket_options(config: &ReflectorConfig, options: &SocketOptions) -> Result<Self> {
+        config.validate()?;
+        let links = Self::links(config.interfaces())?;
+        let socket = Self::bind(&links, options)?;
+        let filter = Filter::new(config.service_types());
+
+        info!(
+            "mDNS reflector started on {}",
+            config.interfaces().join(", ")
+        );
+        let task = tokio::spawn(Self::run(socket, links, filter));
+
+        Ok(Self {
+            interfaces: config.interfaces().to_vec(),
+            task,
+        })
+    }
+
+    /// Interfaces reflected between
+    pub fn interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
+    /// Look up the configured interfaces
+    fn links(names: &[String]) -> Result<Vec<Link>> {
+        let interfaces = network::get_network_interfaces()?;
+        names
+            .iter()
+            .map(|name| {
+                let interface = interfaces
+                    .iter()
+                    .find(|interface| &interface.name == name)
+                    .ok_or_else(|| DiscoveryError::configuration(format!("Unknown interface {name}")))?;
+                let address = interface
+                    .networks
+                    .iter()
+                    .find_map(|network| match network {
+                        IpNet::V4(network) => Some(network.addr()),
+                        IpNet::V6(_) => None,
+                    })
+                    .ok_or_else(|| DiscoveryError::configuration(format!("Interface {name} has no IPv4 address")))?;
+                Ok(Link { name: name.clone(), address, interface: interface.clone() })
+            })
+            .collect()
+    }
+
+    /// Open the mDNS socket and join the group on every link
+    fn bind(links: &[Link], options: &SocketOptions) -> Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        // Responders ignore packets whose TTL isn't 255 (RFC 6762 §11)
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_multicast_loop_v4(false)?;
+        network::apply_socket_options(&socket, Domain::IPV4, options)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, MDNS_PORT)).into())?;
+        for link in links {
+            socket.join_multicast_v4(&MDNS_GROUP, &link.address)?;
+        }
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    /// Repeat packets until the socket fails or the task is aborted
+    async fn run(socket: UdpSocket, links: Vec<Link>, filter: Filter) {
+        let mut buf = vec![0u8; MAX_PACKET];
+        let mut recent: HashMap<u64, Instant> = HashMap::new();
+
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("mDNS reflector stopped: {}", e);
+                    break;
+                }
+            };
+            // Legacy unicast queries and their answers can't be repeated
+            if from.port() != MDNS_PORT {
+                continue;
+            }
+            let Some(origin) = source_link(&links, &from.ip()) else {
+                continue;
+            };
+            let Some(packet) = filter.apply(&buf[..len]) else {
+                continue;
+            };
+
+            let now = Instant::now();
+            recent.retain(|_, seen| now.duration_since(*seen) < DUPLICATE_WINDOW);
+            let mut hasher = DefaultHasher::new();
+            packet.hash(&mut hasher);
+            if recent.insert(hasher.finish(), now).is_some() {
+                debug!("Not repeating an echoed mDNS packet from {}", from);
+                continue;
+            }
+
+            for (_, link) in links.iter().enumerate().filter(|(index, _)| *index != origin) {
+                match Self::send(&socket, link, &packet).await {
+                    Ok(()) => {
+                        #[cfg(feature = "metrics")]
+                        metrics::counter!("mdns_reflected_packets_total", "interface" => link.name.clone()).increment(1);
+                    }
+                    Err(e) => debug!("Failed to repeat mDNS packet on {}: {}", link.name, e),
+                }
+            }
+        }
+    }
+
+    /// Multicast `packet` out of `link`
+    async fn send(socket: &UdpSocket, link: &Link, packet: &[u8]) -> Result<()> {
+        SockRef::from(socket).set_multicast_if_v4(&link.address)?;
+        socket.send_to(packet, (MDNS_GROUP, MDNS_PORT)).await?;
+        Ok(())
+    }
+}
+
+impl Drop for MdnsReflector {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Index of the link a packet from `source` arrived on
+///
+/// Packets from this host's own addresses and from sources outside every
+/// link's networks give `None`.
+fn source_link(links: &[Link], source: &IpAddr) -> Option<usize> {
+    if links.iter().any(|link| IpAddr::V4(link.address) == *source) {
+        return None;
+    }
+    links.iter().position(|link| link.interface.contains(source))
+}
+
+/// Strips mDNS packets down to the allowed service types
+#[derive(Debug, Clone)]
+struct Filter {
+    /// Allowed types as lowercase names without the trailing dot,
+    /// e.g. `_http._tcp.local`
+    types: Vec<String>,
+}
+
+impl Filter {
+    fn new(service_types: &[ServiceType]) -> Self {
+        let types = service_types
+            .iter()
+            .map(|service_type| {
+                let name = service_type.to_string().to_ascii_lowercase();
+                let name = name.trim_end_matches('.');
+                if name.ends_with(".local") {
+                    name.to_string()
+                } else {
+                    format!("{name}.local")
+                }
+            })
+            .collect();
+        Self { types }
+    }
+
+    /// Whether `name` is an allowed type, or an instance or subtype of one
+    fn allows(&self, name: &Name) -> bool {
+        let name = name.to_ascii().to_ascii_lowercase();
+        let name = name.trim_end_matches('.');
+        self.types.iter().any(|service_type| {
+            name.strip_suffix(service_type.as_str())
+                .is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+        })
+    }
+
+    /// Whether a record is about an allowed type
+    ///
+    /// Besides records named after the type or its instances, this covers
+    /// service type enumeration answers pointing at the type.
+    fn allows_record(&self, record: &Record) -> bool {
+        self.allows(record.name())
+            || matches!(record.data(), Some(RData::PTR(target)) if self.allows(&target.0))
+    }
+
+    /// The part of `packet` that may be repeated, re-encoded
+    ///
+    /// Keeps the questions and records about allowed types, and the records
+    /// of hosts their SRV records point at. Returns `None` if nothing is left
+    /// or the packet isn't valid DNS.
+    fn apply(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        let mut message = Message::from_vec(packet).ok()?;
+
+        let queries: Vec<Query> = message
+            .take_queries()
+            .into_iter()
+            .filter(|query| self.allows(query.name()))
+            .map(|mut query| {
+                // Answers must come back through the reflector as multicast
+                query.set_mdns_unicast_response(false);
+                query
+            })
+            .collect();
+        let mut sections = [
+            message.take_answers(),
+            message.take_name_servers(),
+            message.take_additionals(),
+        ];
+        let hosts: HashSet<Name> = sections
+            .iter()
+            .flatten()
+            .filter(|record| self.allows_record(record))
+            .filter_map(|record| match record.data() {
+                Some(RData::SRV(srv)) => Some(srv.target().to_lowercase()),
+                _ => None,
+            })
+            .collect();
+        for section in &mut sections {
+            section.retain(|record| self.allows_record(record) || hosts.contains(&record.name().to_lowercase()));
+        }
+
+        if queries.is_empty() && sections.iter().all(Vec::is_empty) {
+            return None;
+        }
+        let [answers, name_servers, additionals] = sections;
+        message.add_queries(queries);
+        message.insert_answers(answers);
+        message.insert_name_servers(name_servers);
+        message.insert_additionals(additionals);
+        message.to_vec().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_proto::{
+        op::MessageType,
+        rr::{
+            rdata::{A, PTR, SRV, TXT},
+            RecordType,
+        },
+    };
+
+    fn name(name: &str) -> Name {
+        Name::from_ascii(name).unwrap()
+    }
+
+    fn filter() -> Filter {
+        Filter::new(&[ServiceType::new("_ipp._tcp").unwrap()])
+    }
+
+    #[test]
+    fn test_filter_keeps_allowed_services_and_their_hosts() {
+        let mut response = Message::new();
+        response.set_message_type(MessageType::Response);
+        response.add_answer(Record::from_rdata(
+            name("_ipp._tcp.local."),
+            120,
+            RData::PTR(PTR(name("Office._ipp._tcp.local."))),
+        ));
+        response.add_answer(Record::from_rdata(
+            name("_ssh._tcp.local."),
+            120,
+            RData::PTR(PTR(name("Laptop._ssh._tcp.local."))),
+        ));
+        response.add_additional(Record::from_rdata(
+            name("Office._ipp._tcp.local."),
+            120,
+            RData::SRV(SRV::new(0, 0, 631, name("printer.local."))),
+        ));
+        response.add_additional(Record::from_rdata(
+            name("Office._ipp._tcp.local."),
+            120,
+            RData::TXT(TXT::new(vec!["rp=ipp/print".to_string()])),
+        ));
+        response.add_additional(Record::from_rdata(
+            name("printer.local."),
+            120,
+            RData::A(A::new(192, 168, 1, 20)),
+        ));
+        response.add_additional(Record::from_rdata(
+            name("laptop.local."),
+            120,
+            RData::A(A::new(192, 168, 1, 30)),
+        ));
+
+        let packet = filter().apply(&response.to_vec().unwrap()).unwrap();
+        let repeated = Message::from_vec(&packet).unwrap();
+
+        assert_eq!(repeated.answers().len(), 1);
+        assert_eq!(repeated.answers()[0].name(), &name("_ipp._tcp.local."));
+        let additionals: Vec<_> = repeated
+            .additionals()
+            .iter()
+            .map(|record| (record.name().to_ascii(), record.record_type()))
+            .collect();
+        assert_eq!(
+            additionals,
+            vec![
+                ("Office._ipp._tcp.local.".to_string(), RecordType::SRV),
+                ("Office._ipp._tcp.local.".to_string(), RecordType::TXT),
+                ("printer.local.".to_string(), RecordType::A),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filter_drops_unrelated_packets() {
+        let mut query = Message::new();
+        query.add_query(Query::query(name("_ssh._tcp.local."), RecordType::PTR));
+        assert!(filter().apply(&query.to_vec().unwrap()).is_none());
+
+        // A type merely sharing a suffix is not allowed
+        let mut query = Message::new();
+        query.add_query(Query::query(name("_xipp._tcp.local."), RecordType::PTR));
+        assert!(filter().apply(&query.to_vec().unwrap()).is_none());
+
+        assert!(filter().apply(b"not dns").is_none());
+    }
+
+    #[test]
+    fn test_filter_repeats_queries_as_multicast() {
+        let mut question = Query::query(name("_universal._sub._ipp._tcp.local."), RecordType::PTR);
+        question.set_mdns_unicast_response(true);
+        let mut query = Message::new();
+        query.add_query(question);
+        query.add_query(Query::query(name("_ssh._tcp.local."), RecordType::PTR));
+
+        let packet = filter().apply(&query.to_vec().unwrap()).unwrap();
+        let repeated = Message::from_vec(&packet).unwrap();
+
+        assert_eq!(repeated.queries().len(), 1);
+        assert!(!repeated.queries()[0].mdns_unicast_response());
+    }
+
+    #[test]
+    fn test_source_link_ignores_own_and_foreign_addresses() {
+        let link = |name: &str, address: [u8; 4]| {
+            let address = Ipv4Addr::from(address);
+            Link {
+                name: name.to_string(),
+                address,
+                interface: NetworkInterface::new(name)
+                    .with_network(IpNet::new(address.into(), 24).unwrap()),
+            }
+        };
+        let links = [link("eth0", [192, 168, 1, 1]), link("eth1", [10, 0, 0, 1])];
+
+        assert_eq!(source_link(&links, &"10.0.0.7".parse().unwrap()), Some(1));
+        assert_eq!(source_link(&links, &"192.168.1.1".parse().unwrap()), None);
+        assert_eq!(source_link(&links, &"172.16.0.7".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,79 @@
+//! Discovery delegated to a gateway
+//!
+//! [`RemoteProtocol`] puts a [`RemoteDiscovery`] client behind the
+//! [`DiscoveryProtocol`] interface, so a discovery gateway can stand in for a
+//! multicast protocol that cannot work, as in
+//! [container mode](crate::container).
+
+use super::DiscoveryProtocol;
+use crate::{
+    error::Result,
+    registry::ServiceRegistry,
+    remote::RemoteDiscovery,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use std::{sync::Arc, time::Duration};
+
+/// A discovery gateway standing in for a protocol
+///
+/// Services found through the gateway keep the protocol the gateway found
+/// them with. Registrations are made on the gateway's network on our behalf.
+pub struct RemoteProtocol {
+    client: RemoteDiscovery,
+    protocol_type: ProtocolType,
+}
+
+impl RemoteProtocol {
+    /// Use the gateway behind `client` in place of `protocol_type`
+    pub fn new(client: RemoteDiscovery, protocol_type: ProtocolType) -> Self {
+        Self { client, protocol_type }
+    }
+}
+
+#[async_trait]
+impl DiscoveryProtocol for RemoteProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        self.protocol_type
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        let client = match timeout {
+            Some(timeout) => self.client.clone().with_timeout(timeout),
+            None => self.client.clone(),
+        };
+        if service_types.is_empty() {
+            return client.discover_services(None).await;
+        }
+        let mut services = Vec::new();
+        for service_type in &service_types {
+            services.extend(client.discover_services(Some(service_type)).await?);
+        }
+        Ok(services)
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        self.client.register_service(&service).await
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        self.client.unregister_service(service).await
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        let services = self.client.discover_services(Some(&service.service_type)).await?;
+        Ok(services.iter().any(|found| found.service_id() == service.service_id()))
+    }
+
+    async fn is_available(&self) -> bool {
+        self.client.discover_services(None).await.is_ok()
+    }
+
+    /// The gateway keeps its own registry
+    fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+}
@@ -319,8 +319,7 @@ impl super::DiscoveryProtocol for SimpleMdnsProtocol {
 
     async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
         if let Some(registry) = &self.registry {
-            let service_id = format!("{}:{}:{}", service.name, service.service_type.to_string(), service.port);
-            registry.unregister_local_service(&service_id).await?;
+            registry.unregister_local_service(&service.service_id()).await?;
         }
         
         tracing::info!("Service unregistered locally: {}", service.name);
@@ -1,18 +1,22 @@
 //! UPnP (Universal Plug and Play) and SSDP protocol implementation with real multicast support
 
 use crate::{
-    config::DiscoveryConfig,
+    config::{DiscoveryConfig, UpnpConfig},
     error::Result,
+    logging::Redactor,
     registry::ServiceRegistry,
-    service::ServiceInfo,
+    service::{ServiceEvent, ServiceId, ServiceInfo},
     types::{ServiceType, ProtocolType},
-    protocols::DiscoveryProtocol,
+    protocols::{peer_limit::PeerRateLimiter, DiscoveryProtocol},
+    utils::network,
+    verification::ConnectivityVerifier,
 };
 use async_trait::async_trait;
+use socket2::{Domain, Protocol, Socket, Type};
 use std::{
     collections::HashMap,
-    net::SocketAddr,
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex as StdMutex},
     time::{Duration, Instant},
 };
 use tokio::{
@@ -20,36 +24,63 @@ use tokio::{
     sync::{oneshot, RwLock},
     task::JoinHandle,
 };
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+#[cfg(feature = "upnp")]
+pub mod igd;
+pub(crate) mod validation;
+
+use validation::{ResponseValidator, Verdict};
+
+/// SSDP multicast group
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
 
 /// SSDP (Simple Service Discovery Protocol) implementation for UPnP discovery
 pub struct SsdpProtocol {
     registry: Arc<ServiceRegistry>,
-    #[allow(dead_code)]
     config: DiscoveryConfig,
+    /// SSDP-specific settings
+    upnp: UpnpConfig,
     /// Background listener task handle
     listener_handle: Option<JoinHandle<()>>,
     /// Shutdown channel sender
     shutdown_tx: Option<oneshot::Sender<()>>,
     /// Registered services for responding to search requests
-    registered_services: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+    registered_services: Arc<RwLock<HashMap<ServiceId, ServiceInfo>>>,
+    /// Per-source-IP limit on inbound packets handled by the listener
+    peer_limiter: PeerRateLimiter,
+    /// Hides sensitive attributes of logged services
+    redactor: Redactor,
 }
 
 impl SsdpProtocol {
     /// Create a new SSDP protocol instance
     pub fn new(config: DiscoveryConfig) -> Result<Self> {
+        let upnp = config.upnp().clone();
+        upnp.validate()?;
+
         let registry = Arc::new(ServiceRegistry::new());
         let registered_services = Arc::new(RwLock::new(HashMap::new()));
+        let peer_limiter = PeerRateLimiter::new("ssdp", upnp.peer_rate_limit());
+        let redactor = Redactor::new(config.logging());
 
         Ok(Self {
             registry,
             config,
+            upnp,
             listener_handle: None,
             shutdown_tx: None,
             registered_services,
+            peer_limiter,
+            redactor,
         })
     }
 
+    /// Number of inbound packets dropped per peer by the listener's rate limit
+    pub fn dropped_packets(&self) -> HashMap<IpAddr, u64> {
+        self.peer_limiter.dropped()
+    }
+
     /// Start the SSDP listener
     pub async fn start_listener(&mut self) -> Result<()> {
         if self.listener_handle.is_some() {
@@ -60,8 +91,11 @@ impl SsdpProtocol {
         self.shutdown_tx = Some(shutdown_tx);
 
         let registered_services = self.registered_services.clone();
+        let upnp = self.upnp.clone();
+        let peer_limiter = self.peer_limiter.clone();
+        let interfaces = self.ssdp_interfaces().into_iter().map(|(_, addr)| addr).collect();
         let handle = tokio::spawn(async move {
-            if let Err(e) = Self::run_listener(registered_services, shutdown_rx).await {
+            if let Err(e) = Self::run_listener(registered_services, upnp, peer_limiter, interfaces, shutdown_rx).await {
                 error!("SSDP listener error: {}", e);
             }
         });
@@ -74,30 +108,46 @@ impl SsdpProtocol {
 
     /// Start the SSDP listener in the background
     async fn run_listener(
-        registered_services: Arc<RwLock<HashMap<String, ServiceInfo>>>,
+        registered_services: Arc<RwLock<HashMap<ServiceId, ServiceInfo>>>,
+        upnp: UpnpConfig,
+        peer_limiter: PeerRateLimiter,
+        interfaces: Vec<Ipv4Addr>,
         mut shutdown_rx: oneshot::Receiver<()>,
     ) -> Result<()> {
-        let socket = UdpSocket::bind("0.0.0.0:1900").await?;
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
         socket.set_broadcast(true)?;
-        
-        socket.join_multicast_v4("239.255.255.250".parse().unwrap(), "0.0.0.0".parse().unwrap())?;
+        network::apply_socket_options(&socket, Domain::IPV4, upnp.socket_options())?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 1900)).into())?;
+        let socket = UdpSocket::from_std(socket.into())?;
+
+        for interface in interfaces {
+            if let Err(e) = socket.join_multicast_v4(SSDP_GROUP, interface) {
+                warn!("Failed to join SSDP group on {}: {}", interface, e);
+            }
+        }
         
         let mut buf = [0u8; 1024];
+        let mut prune = tokio::time::interval(Duration::from_secs(60));
         
         loop {
             tokio::select! {
                 _ = &mut shutdown_rx => break,
+                _ = prune.tick() => peer_limiter.prune(),
                 result = socket.recv_from(&mut buf) => {
                     match result {
+                        Ok((_, addr)) if !peer_limiter.allow(addr.ip()) => continue,
                         Ok((len, addr)) => {
                             let message = String::from_utf8_lossy(&buf[..len]);
                             if message.contains("M-SEARCH") {
                                 // Handle M-SEARCH request
-                                let search_target = Self::parse_search_target(&message);
+                                let Some(search_target) = Self::parse_search_target(&message) else {
+                                    continue;
+                                };
                                 let services = registered_services.read().await;
                                 for service in services.values() {
                                     if Self::service_matches_search(&search_target, service) {
-                                        let _ = Self::send_response(&socket, addr, service).await;
+                                        let _ = Self::send_response(&socket, addr, service, &upnp).await;
                                     }
                                 }
                             }
@@ -112,17 +162,24 @@ impl SsdpProtocol {
     }
 
     /// Parse search target from M-SEARCH message
-    fn parse_search_target(message: &str) -> String {
+    ///
+    /// A message without an ST header searches for everything. A target
+    /// longer than any real one gives `None`, and the search goes
+    /// unanswered.
+    pub(crate) fn parse_search_target(message: &str) -> Option<String> {
         for line in message.lines() {
-            if let Some(stripped) = line.strip_prefix("ST:") {
-                return stripped.trim().to_string();
+            if let Some((name, value)) = line.split_once(':')
+                && name.trim().eq_ignore_ascii_case("ST")
+            {
+                let value = value.trim();
+                return (value.len() <= validation::MAX_HEADER_LEN).then(|| value.to_string());
             }
         }
-        "ssdp:all".to_string()
+        Some("ssdp:all".to_string())
     }
 
     /// Check if a service matches the search target
-    fn service_matches_search(search_target: &str, service: &ServiceInfo) -> bool {
+    pub(crate) fn service_matches_search(search_target: &str, service: &ServiceInfo) -> bool {
         match search_target {
             "ssdp:all" | "upnp:rootdevice" => true,
             target => {
@@ -133,65 +190,170 @@ impl SsdpProtocol {
         }
     }
 
+    /// LOCATION URL advertised for a service
+    fn location_for(service: &ServiceInfo, upnp: &UpnpConfig) -> String {
+        match upnp.location_url() {
+            Some(url) => url.to_string(),
+            None => format!("http://{}:{}/", service.address, service.port),
+        }
+    }
+
     /// Send a response to an M-SEARCH request
-    async fn send_response(socket: &UdpSocket, addr: SocketAddr, service: &ServiceInfo) -> Result<()> {
+    async fn send_response(socket: &UdpSocket, addr: SocketAddr, service: &ServiceInfo, upnp: &UpnpConfig) -> Result<()> {
         let response = format!(
             "HTTP/1.1 200 OK\r\n\
-            CACHE-CONTROL: max-age=1800\r\n\
+            CACHE-CONTROL: max-age={}\r\n\
             DATE: {}\r\n\
             EXT:\r\n\
-            LOCATION: http://{}:{}/\r\n\
+            LOCATION: {}\r\n\
             SERVER: AutoDiscovery/1.0 UPnP/1.0\r\n\
             ST: upnp:rootdevice\r\n\
             USN: uuid:{}::upnp:rootdevice\r\n\
+            X-SERVICE-NAME: {}\r\n\
             \r\n",
+            upnp.max_age().as_secs(),
             chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S GMT"),
-            service.address,
-            service.port,
-            service.id
+            Self::location_for(service, upnp),
+            service.id,
+            service.name
         );
         
         socket.send_to(response.as_bytes(), addr).await?;
         Ok(())
     }
 
-    /// Send an SSDP search request
-    async fn send_search_request(service_type: &str, timeout_secs: u64) -> Result<UdpSocket> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    /// Interfaces to run SSDP on, as name and IPv4 address
+    ///
+    /// Uses the configured interfaces, or every multicast-capable interface if
+    /// none are configured, leaving out those not on an allowed network.
+    /// Falls back to the unspecified address, with no name, when no interface
+    /// qualifies.
+    fn ssdp_interfaces(&self) -> Vec<(Option<String>, Ipv4Addr)> {
+        let selected = self.config.interfaces();
+        match network::scoped_interfaces(selected, self.config.allowed_networks()) {
+            Ok(interfaces) => {
+                let targets: Vec<_> = interfaces
+                    .into_iter()
+                    .filter(|iface| iface.is_up && (iface.supports_multicast || selected.is_some()))
+                    .filter_map(|iface| iface.ipv4_addresses.first().map(|addr| (Some(iface.name.clone()), *addr)))
+                    .collect();
+                if !targets.is_empty() {
+                    return targets;
+                }
+            }
+            Err(e) => warn!("Failed to enumerate network interfaces: {}", e),
+        }
+        vec![(None, Ipv4Addr::UNSPECIFIED)]
+    }
+
+    /// Open a socket bound to `local` that sends multicast out of that interface
+    fn multicast_socket(local: Ipv4Addr, upnp: &UpnpConfig) -> Result<UdpSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
         socket.set_broadcast(true)?;
-        
+        network::apply_socket_options(&socket, Domain::IPV4, upnp.socket_options())?;
+        if !local.is_unspecified() {
+            socket.set_multicast_if_v4(&local)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::new(local.into(), 0).into())?;
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    /// Send an SSDP search request
+    async fn send_search_request(socket: &UdpSocket, service_type: &str, mx: u8) -> Result<()> {
         let search_msg = format!(
             "M-SEARCH * HTTP/1.1\r\n\
             HOST: 239.255.255.250:1900\r\n\
             MAN: \"ssdp:discover\"\r\n\
             ST: {service_type}\r\n\
-            MX: {timeout_secs}\r\n\
+            MX: {mx}\r\n\
             \r\n"
         );
-        
-        let multicast_addr: SocketAddr = "239.255.255.250:1900".parse().unwrap();
-        socket.send_to(search_msg.as_bytes(), multicast_addr).await?;
-        
-        Ok(socket)
+
+        socket.send_to(search_msg.as_bytes(), (SSDP_GROUP, 1900)).await?;
+        Ok(())
+    }
+
+    /// Search on one interface and collect responses until `deadline`
+    ///
+    /// Responses go through `validator`, shared by the searches on all
+    /// interfaces so a device answering on several is reported once.
+    async fn search_interface(
+        &self,
+        interface: Option<String>,
+        local: Ipv4Addr,
+        service_types: &[ServiceType],
+        mx: u8,
+        deadline: Instant,
+        validator: &StdMutex<ResponseValidator>,
+    ) -> Result<Vec<ServiceInfo>> {
+        let socket = Self::multicast_socket(local, &self.upnp)?;
+        for service_type in service_types {
+            Self::send_search_request(&socket, &service_type.to_string(), mx).await?;
+        }
+
+        let mut services = Vec::new();
+        let mut buf = [0u8; 2048];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+
+            match tokio::time::timeout(remaining, socket.recv_from(&mut buf)).await {
+                Ok(Ok((len, addr))) => {
+                    let response = String::from_utf8_lossy(&buf[..len]);
+                    let verdict = validator.lock().unwrap_or_else(|e| e.into_inner()).check(&response, addr);
+                    let mut service = match verdict {
+                        Verdict::Accept(service) => service,
+                        Verdict::Duplicate => continue,
+                        Verdict::Rejected(reason) => {
+                            debug!("Ignoring SSDP response from {}: {}", addr, reason);
+                            continue;
+                        }
+                        Verdict::Suspicious(service, reason) => {
+                            let quarantined = self.upnp.strict_validation();
+                            warn!("Suspicious SSDP response from {}: {}", addr, reason);
+                            #[cfg(feature = "metrics")]
+                            metrics::counter!("ssdp_suspicious_responses_total").increment(1);
+                            self.registry.publish(ServiceEvent::security_alert(
+                                ProtocolType::Upnp,
+                                addr.ip(),
+                                reason,
+                                quarantined,
+                            )).await;
+                            if quarantined {
+                                continue;
+                            }
+                            service
+                        }
+                    };
+                    service.interface = interface.clone();
+                    debug!("Discovered UPnP service: {}", self.redactor.service(&service));
+                    services.push(service);
+                }
+                Ok(Err(_)) | Err(_) => break,
+            }
+        }
+        Ok(services)
     }
 
     /// Send an SSDP announcement
-    async fn send_announcement(service: &ServiceInfo, notification_type: &str) -> Result<()> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.set_broadcast(true)?;
-        
+    async fn send_announcement(service: &ServiceInfo, notification_type: &str, upnp: &UpnpConfig) -> Result<()> {
+        let socket = Self::multicast_socket(Ipv4Addr::UNSPECIFIED, upnp)?;
+
         let announcement = format!(
             "NOTIFY * HTTP/1.1\r\n\
             HOST: 239.255.255.250:1900\r\n\
-            CACHE-CONTROL: max-age=1800\r\n\
-            LOCATION: http://{}:{}/\r\n\
+            CACHE-CONTROL: max-age={}\r\n\
+            LOCATION: {}\r\n\
             NT: upnp:rootdevice\r\n\
             NTS: {}\r\n\
             USN: uuid:{}::upnp:rootdevice\r\n\
             SERVER: AutoDiscovery/1.0 UPnP/1.0\r\n\
             \r\n",
-            service.address,
-            service.port,
+            upnp.max_age().as_secs(),
+            Self::location_for(service, upnp),
             notification_type,
             service.id
         );
@@ -201,39 +363,6 @@ impl SsdpProtocol {
         
         Ok(())
     }
-
-    /// Parse service information from SSDP response
-    fn parse_service_from_response(response: &str, addr: SocketAddr) -> Option<ServiceInfo> {
-        let mut location = None;
-        let mut usn = None;
-        
-        for line in response.lines() {
-            if let Some(stripped) = line.strip_prefix("LOCATION:") {
-                location = Some(stripped.trim().to_string());
-            } else if let Some(stripped) = line.strip_prefix("USN:") {
-                usn = Some(stripped.trim().to_string());
-            }
-        }
-        
-        if let (Some(location), Some(usn)) = (location, usn) {
-            let service_id = usn.split("::").next().unwrap_or("unknown").to_string();
-            let mut service = ServiceInfo::new(
-                service_id,
-                "upnp._tcp",
-                addr.port(),
-                Some(vec![
-                    ("location", &location),
-                    ("usn", &usn),
-                ])
-            ).ok()?;
-            
-            service.address = addr.ip();
-            
-            Some(service)
-        } else {
-            None
-        }
-    }
 }
 
 #[async_trait]
@@ -248,36 +377,65 @@ impl DiscoveryProtocol for SsdpProtocol {
         service_types: Vec<ServiceType>,
         timeout: Option<Duration>,
     ) -> Result<Vec<ServiceInfo>> {
-        let mut services = Vec::new();
         let timeout_duration = timeout.unwrap_or(Duration::from_secs(10)).min(Duration::from_secs(30));
-        let start_time = Instant::now();
+        let deadline = Instant::now() + timeout_duration;
+        // Never ask responders to wait longer than we are willing to listen
+        let mx = self.upnp.mx().min(timeout_duration.as_secs().clamp(1, 5) as u8);
 
         debug!("Starting UPnP discovery for service types: {:?}", service_types);
 
-        // Send search request for each service type
-        for service_type in service_types {
-            let socket = Self::send_search_request(&service_type.to_string(), timeout_duration.as_secs()).await?;
-
-            let mut buf = [0u8; 2048];
-            while start_time.elapsed() < timeout_duration {
-                let remaining = timeout_duration - start_time.elapsed();
-                if remaining.is_zero() {
-                    break;
+        let local_networks = match network::get_network_interfaces() {
+            Ok(interfaces) => interfaces.into_iter().flat_map(|iface| iface.networks).collect(),
+            Err(e) => {
+                warn!("Failed to enumerate network interfaces: {}", e);
+                Vec::new()
+            }
+        };
+        let validator = StdMutex::new(ResponseValidator::new(
+            &service_types,
+            local_networks,
+            self.config.allowed_networks().to_vec(),
+        ));
+
+        // One socket per interface, so every response is tagged with the
+        // interface it arrived on
+        let mut interfaces = self.ssdp_interfaces();
+        if let Some(outgoing) = self.upnp.socket_options().outgoing_interface() {
+            interfaces.retain(|(interface, _)| interface.as_deref() == Some(outgoing));
+            if interfaces.is_empty() {
+                // The socket options pick the interface, or report it missing
+                interfaces.push((Some(outgoing.to_string()), Ipv4Addr::UNSPECIFIED));
+            }
+        }
+        let searches = interfaces.into_iter().map(|(interface, local)| {
+            let service_types = &service_types;
+            let validator = &validator;
+            async move {
+                let result = self
+                    .search_interface(interface.clone(), local, service_types, mx, deadline, validator)
+                    .await;
+                if let Err(e) = &result {
+                    warn!("SSDP search on {} failed: {}", interface.as_deref().unwrap_or("default interface"), e);
                 }
+                result
+            }
+        });
 
-                match tokio::time::timeout(remaining, socket.recv_from(&mut buf)).await {
-                    Ok(Ok((len, addr))) => {
-                        let response = String::from_utf8_lossy(&buf[..len]);
-                        if let Some(service) = Self::parse_service_from_response(&response, addr) {
-                            debug!("Discovered UPnP service: {:?}", service);
-                            services.push(service);
-                        }
-                    }
-                    Ok(Err(_)) => break,
-                    Err(_) => break,
+        let mut services = Vec::new();
+        let mut last_error = None;
+        let mut any_succeeded = false;
+        for result in futures::future::join_all(searches).await {
+            match result {
+                Ok(found) => {
+                    any_succeeded = true;
+                    services.extend(found);
                 }
+                Err(e) => last_error = Some(e),
             }
         }
+        if let (false, Some(e)) = (any_succeeded, last_error) {
+            return Err(e);
+        }
 
         info!("UPnP discovery found {} services", services.len());
         Ok(services)
@@ -286,23 +444,21 @@ impl DiscoveryProtocol for SsdpProtocol {
     async fn register_service(&self, service: ServiceInfo) -> Result<()> {
         // Store in our registered services for responding to searches
         let mut services = self.registered_services.write().await;
-        services.insert(service.id.to_string(), service.clone());
+        services.insert(service.service_id(), service.clone());
 
         // Send announcement
-        Self::send_announcement(&service, "ssdp:alive").await?;
+        Self::send_announcement(&service, "ssdp:alive", &self.upnp).await?;
 
         info!("Registered UPnP service: {} ({}:{})", service.name, service.address, service.port);
         Ok(())
     }
 
     async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
-        let service_id = service.id.to_string();
-        
         // Remove from our registered services
         let mut services = self.registered_services.write().await;
-        if let Some(service) = services.remove(&service_id) {
+        if let Some(service) = services.remove(&service.service_id()) {
             // Send byebye announcement
-            Self::send_announcement(&service, "ssdp:byebye").await?;
+            Self::send_announcement(&service, "ssdp:byebye", &self.upnp).await?;
             info!("Unregistered UPnP service: {} ({}:{})", service.name, service.address, service.port);
         }
 
@@ -310,17 +466,15 @@ impl DiscoveryProtocol for SsdpProtocol {
     }
 
     async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
-        // For UPnP, check if the service is in our registered services
-        let services = self.registered_services.read().await;
-        let is_registered = services.contains_key(&service.id.to_string());
-        
-        if is_registered {
-            debug!("UPnP service verified: {} ({}:{})", service.name, service.address, service.port);
-            Ok(true)
-        } else {
-            debug!("UPnP service not found in registered services: {} ({}:{})", service.name, service.address, service.port);
-            Ok(false)
-        }
+        let result = ConnectivityVerifier::from_health_config(self.config.health())
+            .with_resolution_cache(Arc::clone(self.registry.resolution_cache()))
+            .verify(service)
+            .await;
+        debug!(
+            "UPnP service {} ({}:{}) verification: {}",
+            service.name, service.address, service.port, result.healthy
+        );
+        Ok(result.healthy)
     }
 
     async fn is_available(&self) -> bool {
@@ -345,11 +499,29 @@ mod tests {
         assert!(protocol.is_ok());
     }
 
+    #[test]
+    fn test_location_from_config() {
+        let service = ServiceInfo::new("test-service", "upnp._tcp", 8080, None).unwrap();
+        assert_eq!(
+            SsdpProtocol::location_for(&service, &UpnpConfig::default()),
+            "http://127.0.0.1:8080/"
+        );
+
+        let upnp = UpnpConfig::new().with_location_url("http://10.0.0.2/device.xml");
+        assert_eq!(SsdpProtocol::location_for(&service, &upnp), "http://10.0.0.2/device.xml");
+    }
+
     #[tokio::test]
     async fn test_search_target_parsing() {
         let message = "M-SEARCH * HTTP/1.1\r\nST: upnp:rootdevice\r\n\r\n";
         let target = SsdpProtocol::parse_search_target(message);
-        assert_eq!(target, "upnp:rootdevice");
+        assert_eq!(target.as_deref(), Some("upnp:rootdevice"));
+
+        let lowercase = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nst:ssdp:all\r\n\r\n";
+        assert_eq!(SsdpProtocol::parse_search_target(lowercase).as_deref(), Some("ssdp:all"));
+        assert_eq!(SsdpProtocol::parse_search_target("M-SEARCH * HTTP/1.1\r\n\r\n").as_deref(), Some("ssdp:all"));
+        let oversized = format!("M-SEARCH * HTTP/1.1\r\nST: {}\r\n\r\n", "x".repeat(validation::MAX_HEADER_LEN + 1));
+        assert_eq!(SsdpProtocol::parse_search_target(&oversized), None);
     }
 
     #[tokio::test]
@@ -0,0 +1,344 @@
+//! UPnP Internet Gateway Device (IGD) client
+//!
+//! Finds a gateway through SSDP, reads its device description to locate the
+//! WANIPConnection (or WANPPPConnection) control URL and talks to it with
+//! SOAP actions.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    protocols::port_mapping::TransportProtocol,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+use tracing::debug;
+use url::Url;
+
+/// SSDP search target for gateways
+const IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+/// Services that can create port mappings, in order of preference
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// A gateway's WAN connection service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternetGateway {
+    /// URL of the device description
+    pub location: Url,
+    /// SOAP control URL of the WAN connection service
+    pub control_url: Url,
+    /// Service type of the WAN connection service
+    pub service_type: String,
+}
+
+impl InternetGateway {
+    /// Find the first gateway that answers within `timeout`
+    pub async fn discover(timeout: Duration) -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        let mx = timeout.as_secs().clamp(1, 5);
+        let search = format!(
+            "M-SEARCH * HTTP/1.1\r\n\
+            HOST: 239.255.255.250:1900\r\n\
+            MAN: \"ssdp:discover\"\r\n\
+            ST: {IGD_SEARCH_TARGET}\r\n\
+            MX: {mx}\r\n\
+            \r\n"
+        );
+        socket.send_to(search.as_bytes(), "239.255.255.250:1900").await?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 2048];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let (len, addr) = match tokio::time::timeout(remaining, socket.recv_from(&mut buf)).await {
+                Ok(result) => result?,
+                Err(_) => return Err(DiscoveryError::timeout("No Internet Gateway Device answered")),
+            };
+
+            let response = String::from_utf8_lossy(&buf[..len]);
+            let Some(location) = header(&response, "LOCATION") else {
+                continue;
+            };
+            match Self::from_location(location, remaining).await {
+                Ok(gateway) => return Ok(gateway),
+                Err(e) => debug!("Ignoring gateway candidate {} at {}: {}", location, addr, e),
+            }
+        }
+    }
+
+    /// Read a gateway's device description
+    pub async fn from_location(location: &str, timeout: Duration) -> Result<Self> {
+        let location = Url::parse(location).map_err(|e| DiscoveryError::upnp(format!("Invalid location: {e}")))?;
+        let description = http_client(timeout)?
+            .get(location.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| DiscoveryError::upnp(format!("Failed to fetch device description: {e}")))?
+            .text()
+            .await
+            .map_err(|e| DiscoveryError::upnp(format!("Failed to read device description: {e}")))?;
+
+        let (service_type, control_path) = find_wan_service(&description)
+            .ok_or_else(|| DiscoveryError::upnp("Device has no WAN connection service"))?;
+        let base = match element_text(&description, "URLBase") {
+            Some(base) => Url::parse(base).unwrap_or_else(|_| location.clone()),
+            None => location.clone(),
+        };
+        let control_url = base
+            .join(&control_path)
+            .map_err(|e| DiscoveryError::upnp(format!("Invalid control URL: {e}")))?;
+
+        Ok(Self { location, control_url, service_type })
+    }
+
+    /// Address of the gateway host
+    pub fn host(&self) -> Option<IpAddr> {
+        self.control_url.host_str()?.parse().ok()
+    }
+
+    /// Local address used to reach the gateway
+    pub async fn local_address(&self) -> Result<IpAddr> {
+        let port = self.control_url.port_or_known_default().unwrap_or(80);
+        let host = self
+            .host()
+            .ok_or_else(|| DiscoveryError::upnp("Gateway control URL has no IP address"))?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.connect(SocketAddr::new(host, port)).await?;
+        Ok(socket.local_addr()?.ip())
+    }
+
+    /// Query the gateway's external IP address
+    pub async fn external_ip(&self, timeout: Duration) -> Result<IpAddr> {
+        let response = self.call("GetExternalIPAddress", "", timeout).await?;
+        element_text(&response, "NewExternalIPAddress")
+            .and_then(|ip| ip.trim().parse().ok())
+            .ok_or_else(|| DiscoveryError::upnp("Gateway returned no external IP address"))
+    }
+
+    /// Forward `external_port` on the gateway to `internal` for `lease`
+    ///
+    /// A zero lease asks for a permanent mapping.
+    pub async fn add_port_mapping(
+        &self,
+        protocol: TransportProtocol,
+        external_port: u16,
+        internal: SocketAddr,
+        lease: Duration,
+        description: &str,
+        timeout: Duration,
+    ) -> Result<()> {
+        let arguments = format!(
+            "<NewRemoteHost></NewRemoteHost>\
+            <NewExternalPort>{external_port}</NewExternalPort>\
+            <NewProtocol>{protocol}</NewProtocol>\
+            <NewInternalPort>{}</NewInternalPort>\
+            <NewInternalClient>{}</NewInternalClient>\
+            <NewEnabled>1</NewEnabled>\
+            <NewPortMappingDescription>{}</NewPortMappingDescription>\
+            <NewLeaseDuration>{}</NewLeaseDuration>",
+            internal.port(),
+            internal.ip(),
+            escape(description),
+            lease.as_secs(),
+        );
+        self.call("AddPortMapping", &arguments, timeout).await.map(|_| ())
+    }
+
+    /// Remove a port mapping
+    pub async fn delete_port_mapping(
+        &self,
+        protocol: TransportProtocol,
+        external_port: u16,
+        timeout: Duration,
+    ) -> Result<()> {
+        let arguments = format!(
+            "<NewRemoteHost></NewRemoteHost>\
+            <NewExternalPort>{external_port}</NewExternalPort>\
+            <NewProtocol>{protocol}</NewProtocol>"
+        );
+        self.call("DeletePortMapping", &arguments, timeout).await.map(|_| ())
+    }
+
+    /// Invoke a SOAP action and return the response body
+    async fn call(&self, action: &str, arguments: &str, timeout: Duration) -> Result<String> {
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+            <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+            s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+            <s:Body><u:{action} xmlns:u=\"{service}\">{arguments}</u:{action}></s:Body>\
+            </s:Envelope>",
+            service = self.service_type,
+        );
+
+        let response = http_client(timeout)?
+            .post(self.control_url.clone())
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#{}\"", self.service_type, action))
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| DiscoveryError::upnp(format!("{action} request failed: {e}")))?;
+
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| DiscoveryError::upnp(format!("Failed to read {action} response: {e}")))?;
+        if !status.is_success() {
+            let reason = element_text(&text, "errorDescription").unwrap_or(status.as_str());
+            return Err(DiscoveryError::upnp(format!("{action} failed: {reason}")));
+        }
+        Ok(text)
+    }
+}
+
+fn http_client(timeout: Duration) -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|e| DiscoveryError::upnp(format!("Failed to create HTTP client: {e}")))
+}
+
+/// Value of an HTTP header in an SSDP response
+fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Text of the first element named `tag`, ignoring any namespace prefix
+fn element_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    while let Some(open) = rest.find('<') {
+        rest = &rest[open + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next().unwrap_or_default();
+        let local = name.rsplit(':').next().unwrap_or(name);
+        rest = &rest[end + 1..];
+        if local == tag && !name.starts_with('/') {
+            let close = rest.find("</")?;
+            return Some(rest[..close].trim());
+        }
+    }
+    None
+}
+
+/// Preferred WAN connection service and its control URL
+fn find_wan_service(description: &str) -> Option<(String, String)> {
+    let services: Vec<(&str, &str)> = description
+        .split("<service>")
+        .skip(1)
+        .filter_map(|block| Some((element_text(block, "serviceType")?, element_text(block, "controlURL")?)))
+        .collect();
+
+    WAN_SERVICES.iter().find_map(|wanted| {
+        services
+            .iter()
+            .find(|(service_type, _)| service_type == wanted)
+            .map(|(service_type, control_url)| (service_type.to_string(), control_url.to_string()))
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+    /// Serve the device description and answer every SOAP call with an
+    /// external IP address, one request per connection
+    async fn fake_gateway() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                // Read the whole request so closing the connection doesn't reset it
+                loop {
+                    let len = stream.read(&mut buf).await.unwrap_or(0);
+                    request.extend_from_slice(&buf[..len]);
+                    let text = String::from_utf8_lossy(&request);
+                    let complete = text.find("\r\n\r\n").is_some_and(|end| {
+                        let body_len = header(&text, "Content-Length").and_then(|l| l.parse().ok()).unwrap_or(0);
+                        request.len() >= end + 4 + body_len
+                    });
+                    if len == 0 || complete {
+                        break;
+                    }
+                }
+
+                let body = if request.starts_with(b"GET") {
+                    DESCRIPTION.to_string()
+                } else {
+                    "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+                        <NewExternalIPAddress>203.0.113.9</NewExternalIPAddress>\
+                        </u:GetExternalIPAddressResponse></s:Body></s:Envelope>"
+                        .to_string()
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{addr}/rootDesc.xml")
+    }
+
+    #[tokio::test]
+    async fn test_external_ip_from_gateway() {
+        let location = fake_gateway().await;
+        let gateway = InternetGateway::from_location(&location, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(gateway.control_url.path(), "/ctl/IPConn");
+        assert_eq!(gateway.host(), Some(Ipv4Addr::LOCALHOST.into()));
+
+        let external = gateway.external_ip(Duration::from_secs(2)).await.unwrap();
+        assert_eq!(external, "203.0.113.9".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_device_description() {
+        let (service_type, control_url) = find_wan_service(DESCRIPTION).unwrap();
+        assert_eq!(service_type, "urn:schemas-upnp-org:service:WANIPConnection:1");
+        assert_eq!(control_url, "/ctl/IPConn");
+
+        let response = "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+            <NewExternalIPAddress>203.0.113.9</NewExternalIPAddress>\
+            </u:GetExternalIPAddressResponse></s:Body></s:Envelope>";
+        assert_eq!(element_text(response, "NewExternalIPAddress"), Some("203.0.113.9"));
+
+        let ssdp = "HTTP/1.1 200 OK\r\nlocation: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+        assert_eq!(header(ssdp, "LOCATION"), Some("http://192.168.1.1:5000/rootDesc.xml"));
+    }
+}
@@ -0,0 +1,246 @@
+//! Checks on SSDP search responses
+//!
+//! Anyone on the link can answer an M-SEARCH, and with anything. Responses
+//! are checked before they become services: headers are size-capped, the
+//! search target must be one that was asked for, the LOCATION must point at
+//! an address on a local network or an allowed one, and a device answering
+//! several times (once per MX window, or on several interfaces) is only
+//! reported once.
+
+use crate::{service::ServiceInfo, types::ServiceType};
+use ipnet::IpNet;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+};
+use url::{Host, Url};
+
+/// Longest header value accepted in a response
+pub(crate) const MAX_HEADER_LEN: usize = 512;
+
+/// Most headers accepted in a response
+const MAX_HEADERS: usize = 32;
+
+/// What to do with a search response
+#[derive(Debug)]
+pub(crate) enum Verdict {
+    /// A new, plausible answer
+    Accept(ServiceInfo),
+    /// A plausible answer from a device already reported
+    Duplicate,
+    /// An answer that fails a spoofing check, with the reason
+    Suspicious(ServiceInfo, String),
+    /// Not a usable answer: malformed, oversized or not a success
+    Rejected(String),
+}
+
+/// Validates the responses to one search
+#[derive(Debug)]
+pub(crate) struct ResponseValidator {
+    /// Search targets asked for, lowercase
+    search_targets: Vec<String>,
+    /// Networks of the local interfaces
+    local_networks: Vec<IpNet>,
+    /// Networks configured as allowed
+    allowed_networks: Vec<IpNet>,
+    /// USNs already accepted
+    seen: HashSet<String>,
+}
+
+impl ResponseValidator {
+    pub(crate) fn new(search_targets: &[ServiceType], local_networks: Vec<IpNet>, allowed_networks: Vec<IpNet>) -> Self {
+        Self {
+            search_targets: search_targets
+                .iter()
+                .map(|target| target.to_string().to_ascii_lowercase())
+                .collect(),
+            local_networks,
+            allowed_networks,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Judge a response received from `source`
+    pub(crate) fn check(&mut self, response: &str, source: SocketAddr) -> Verdict {
+        let headers = match parse_headers(response) {
+            Ok(headers) => headers,
+            Err(reason) => return Verdict::Rejected(reason),
+        };
+        let (Some(location), Some(usn)) = (headers.get("LOCATION"), headers.get("USN")) else {
+            return Verdict::Rejected("missing LOCATION or USN".to_string());
+        };
+
+        let service = match build_service(&headers, location, usn, source) {
+            Some(service) => service,
+            None => return Verdict::Rejected("unusable service name".to_string()),
+        };
+        if let Err(reason) = self.check_search_target(headers.get("ST").map(String::as_str)) {
+            return Verdict::Suspicious(service, reason);
+        }
+        if let Err(reason) = self.check_location(location) {
+            return Verdict::Suspicious(service, reason);
+        }
+        if !self.seen.insert(usn.clone()) {
+            return Verdict::Duplicate;
+        }
+        Verdict::Accept(service)
+    }
+
+    /// The answer must be for something that was searched for
+    fn check_search_target(&self, target: Option<&str>) -> Result<(), String> {
+        let Some(target) = target else {
+            return Err("response has no ST".to_string());
+        };
+        let target = target.to_ascii_lowercase();
+        if self.search_targets.contains(&target) {
+            Ok(())
+        } else {
+            Err(format!("ST {target} was not searched for"))
+        }
+    }
+
+    /// The description must be served from a local or allowed network
+    fn check_location(&self, location: &str) -> Result<(), String> {
+        let url = Url::parse(location).map_err(|e| format!("invalid LOCATION: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("LOCATION uses scheme {}", url.scheme()));
+        }
+        let host: IpAddr = match url.host() {
+            Some(Host::Ipv4(addr)) => addr.into(),
+            Some(Host::Ipv6(addr)) => addr.into(),
+            Some(Host::Domain(domain)) => return Err(format!("LOCATION names host {domain} instead of an address")),
+            None => return Err("LOCATION has no host".to_string()),
+        };
+        let reachable = self
+            .local_networks
+            .iter()
+            .chain(&self.allowed_networks)
+            .any(|network| network.contains(&host));
+        if reachable {
+            Ok(())
+        } else {
+            Err(format!("LOCATION host {host} is neither on-link nor on an allowed network"))
+        }
+    }
+}
+
+/// Headers of a `200 OK` response, keyed by uppercase name
+fn parse_headers(response: &str) -> Result<HashMap<String, String>, String> {
+    let mut lines = response.lines();
+    let status = lines.next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("status {status:?}"));
+    }
+
+    let mut headers = HashMap::new();
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.len() > MAX_HEADER_LEN {
+            return Err(format!("header name of {} bytes", name.len()));
+        }
+        if value.len() > MAX_HEADER_LEN {
+            return Err(format!("{name} header of {} bytes", value.len()));
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(format!("more than {MAX_HEADERS} headers"));
+        }
+        headers.insert(name.to_ascii_uppercase(), value.to_string());
+    }
+    Ok(headers)
+}
+
+/// Service described by a response
+fn build_service(headers: &HashMap<String, String>, location: &str, usn: &str, source: SocketAddr) -> Option<ServiceInfo> {
+    // Prefer the advertised instance name, falling back to the device UUID
+    let name = headers
+        .get("X-SERVICE-NAME")
+        .cloned()
+        .unwrap_or_else(|| usn.split("::").next().unwrap_or("unknown").to_string());
+    let mut service = ServiceInfo::new(
+        name,
+        "upnp._tcp",
+        source.port(),
+        Some(vec![("location", location), ("usn", usn)]),
+    )
+    .ok()?;
+    service.address = source.ip();
+    Some(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator() -> ResponseValidator {
+        ResponseValidator::new(
+            &[ServiceType::new("urn:schemas-upnp-org:device:MediaRenderer:1").unwrap()],
+            vec!["192.168.1.0/24".parse().unwrap()],
+            vec!["10.20.0.0/16".parse().unwrap()],
+        )
+    }
+
+    fn response(st: &str, location: &str, usn: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nCache-Control: max-age=1800\r\nST: {st}\r\nLocation: {location}\r\nUSN: {usn}\r\n\r\n"
+        )
+    }
+
+    fn source() -> SocketAddr {
+        "192.168.1.30:1900".parse().unwrap()
+    }
+
+    #[test]
+    fn test_plausible_responses_are_accepted_once() {
+        let mut validator = validator();
+        let answer = response("urn:schemas-upnp-org:device:MediaRenderer:1", "http://192.168.1.30:49152/desc.xml", "uuid:tv::upnp:rootdevice");
+
+        match validator.check(&answer, source()) {
+            Verdict::Accept(service) => {
+                assert_eq!(service.name, "uuid:tv");
+                assert_eq!(service.address, source().ip());
+                assert_eq!(service.attributes.get("location").map(String::as_str), Some("http://192.168.1.30:49152/desc.xml"));
+            }
+            other => panic!("unexpected verdict {other:?}"),
+        }
+        assert!(matches!(validator.check(&answer, source()), Verdict::Duplicate));
+
+        // Allowed networks count as reachable too
+        let remote = response("URN:SCHEMAS-UPNP-ORG:DEVICE:MEDIARENDERER:1", "http://10.20.3.4/desc.xml", "uuid:nas::upnp:rootdevice");
+        assert!(matches!(validator.check(&remote, source()), Verdict::Accept(_)));
+    }
+
+    #[test]
+    fn test_spoofing_checks() {
+        let mut validator = validator();
+        let verdict = |validator: &mut ResponseValidator, st: &str, location: &str| {
+            match validator.check(&response(st, location, "uuid:x::upnp:rootdevice"), source()) {
+                Verdict::Suspicious(_, reason) => reason,
+                other => panic!("unexpected verdict {other:?}"),
+            }
+        };
+
+        assert!(verdict(&mut validator, "urn:schemas-upnp-org:device:Printer:1", "http://192.168.1.30/").contains("not searched for"));
+        assert!(verdict(&mut validator, "urn:schemas-upnp-org:device:MediaRenderer:1", "http://203.0.113.9/evil.xml").contains("neither on-link"));
+        assert!(verdict(&mut validator, "urn:schemas-upnp-org:device:MediaRenderer:1", "http://evil.example/desc.xml").contains("instead of an address"));
+        assert!(verdict(&mut validator, "urn:schemas-upnp-org:device:MediaRenderer:1", "file:///etc/passwd").contains("scheme"));
+    }
+
+    #[test]
+    fn test_malformed_responses_are_rejected() {
+        let mut validator = validator();
+        let oversized = response("urn:schemas-upnp-org:device:MediaRenderer:1", "http://192.168.1.30/desc.xml", &"u".repeat(MAX_HEADER_LEN + 1));
+        assert!(matches!(validator.check(&oversized, source()), Verdict::Rejected(_)));
+
+        let not_ok = "HTTP/1.1 500 Internal Server Error\r\nLOCATION: http://192.168.1.30/\r\nUSN: uuid:x\r\n\r\n";
+        assert!(matches!(validator.check(not_ok, source()), Verdict::Rejected(_)));
+
+        let incomplete = "HTTP/1.1 200 OK\r\nST: urn:schemas-upnp-org:device:MediaRenderer:1\r\n\r\n";
+        assert!(matches!(validator.check(incomplete, source()), Verdict::Rejected(_)));
+
+        let long_name = format!("HTTP/1.1 200 OK\r\n{}: x\r\n\r\n", "X".repeat(MAX_HEADER_LEN + 1));
+        assert!(matches!(validator.check(&long_name, source()), Verdict::Rejected(_)));
+    }
+}
@@ -0,0 +1,471 @@
+//! mDNS through the Windows DNS API
+//!
+//! Windows 10 and later run their own mDNS responder, which holds UDP port
+//! 5353. The built-in backend then competes with it for packets, and
+//! services registered by either are invisible to the other. This backend
+//! instead asks the system responder to browse, resolve and register, with
+//! `DnsServiceBrowse`, `DnsServiceResolve` and `DnsServiceRegister` from
+//! `dnsapi.dll`.
+//!
+//! Selected with [`MdnsBackend::WindowsDnsApi`](crate::config::MdnsBackend::WindowsDnsApi);
+//! requires the `windows-dns` feature.
+//!
+//! Each request hands Windows a context that the completion callback takes
+//! back ownership of. Callbacks run on a Windows thread pool and report to
+//! the async side through channels. Cancelled requests complete with
+//! `ERROR_CANCELLED`, so a context is never freed while Windows may still
+//! use it.
+
+use super::{mdns, Capabilities, DiscoveryProtocol};
+use crate::{
+    config::DiscoveryConfig,
+    error::{DiscoveryError, Result},
+    registry::ServiceRegistry,
+    service::{ServiceId, ServiceInfo},
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ptr,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+use windows_sys::Win32::{
+    Foundation::{DNS_REQUEST_PENDING, ERROR_CANCELLED, ERROR_SUCCESS},
+    NetworkManagement::Dns::{
+        DnsFree, DnsFreeRecordList, DnsServiceBrowse, DnsServiceBrowseCancel, DnsServiceConstructInstance,
+        DnsServiceDeRegister, DnsServiceFreeInstance, DnsServiceRegister, DnsServiceResolve, DnsServiceResolveCancel,
+        DNS_QUERY_REQUEST_VERSION1, DNS_RECORDW, DNS_SERVICE_BROWSE_REQUEST, DNS_SERVICE_BROWSE_REQUEST_0,
+        DNS_SERVICE_CANCEL, DNS_SERVICE_INSTANCE, DNS_SERVICE_REGISTER_REQUEST, DNS_SERVICE_RESOLVE_REQUEST,
+        DNS_TYPE_PTR, IP6_ADDRESS,
+    },
+};
+
+/// How long a browse runs when the caller gives no timeout
+const DEFAULT_BROWSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long registration waits for the responder to confirm
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A service instance owned by the DNS API, freed on drop
+struct Instance(*mut DNS_SERVICE_INSTANCE);
+
+// The instance is only read by the DNS API and freed once
+unsafe impl Send for Instance {}
+unsafe impl Sync for Instance {}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: the pointer came from the DNS API and is freed only here
+            unsafe { DnsServiceFreeInstance(self.0) };
+        }
+    }
+}
+
+/// Handle for cancelling a pending browse or resolve
+struct Cancel(DNS_SERVICE_CANCEL);
+
+// The handle is an opaque token the DNS API accepts from any thread
+unsafe impl Send for Cancel {}
+
+/// mDNS backed by the Windows DNS API
+pub struct WindowsDnsProtocol {
+    config: DiscoveryConfig,
+    /// Instances registered with the responder, kept to deregister them
+    registrations: StdMutex<HashMap<ServiceId, Arc<Instance>>>,
+}
+
+impl WindowsDnsProtocol {
+    /// Create the backend
+    ///
+    /// Nothing is sent until the first operation.
+    pub fn new(config: &DiscoveryConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            registrations: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    /// Instance names of `service_type` seen within `timeout`
+    async fn browse(&self, service_type: &ServiceType, timeout: Duration) -> Result<Vec<String>> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let query = wide(&format!("{}.local", service_type.to_string().trim_end_matches('.').trim_end_matches(".local")));
+        let cancel = {
+            let context = Box::into_raw(Box::new(tx));
+            let request = DNS_SERVICE_BROWSE_REQUEST {
+                Version: DNS_QUERY_REQUEST_VERSION1,
+                InterfaceIndex: 0,
+                QueryName: query.as_ptr(),
+                Anonymous: DNS_SERVICE_BROWSE_REQUEST_0 { pBrowseCallback: Some(browse_callback) },
+                pQueryContext: context.cast(),
+            };
+            let mut cancel = DNS_SERVICE_CANCEL { reserved: ptr::null_mut() };
+            // SAFETY: the request and query outlive the call; the callback
+            // owns `context` once the browse is pending
+            let status = unsafe { DnsServiceBrowse(&request, &mut cancel) };
+            if status != DNS_REQUEST_PENDING {
+                // SAFETY: the browse did not start, so the callback never runs
+                drop(unsafe { Box::from_raw(context) });
+                return Err(DiscoveryError::mdns(format!("DnsServiceBrowse failed with status {status}")));
+            }
+            Cancel(cancel)
+        };
+
+        let mut names = Vec::new();
+        let _ = tokio::time::timeout(timeout, async {
+            while let Some(name) = rx.recv().await {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        })
+        .await;
+        // SAFETY: `cancel` was filled in by the pending browse
+        unsafe { DnsServiceBrowseCancel(&cancel.0) };
+        Ok(names)
+    }
+
+    /// Resolve an instance name to its host, addresses, port and TXT record
+    async fn resolve(&self, instance: &str, timeout: Duration) -> Result<ServiceInfo> {
+        let (tx, rx) = oneshot::channel();
+        let mut query = wide(instance);
+        let cancel = {
+            let context = Box::into_raw(Box::new(tx));
+            let request = DNS_SERVICE_RESOLVE_REQUEST {
+                Version: DNS_QUERY_REQUEST_VERSION1,
+                InterfaceIndex: 0,
+                QueryName: query.as_mut_ptr(),
+                pResolveCompletionCallback: Some(resolve_callback),
+                pQueryContext: context.cast(),
+            };
+            let mut cancel = DNS_SERVICE_CANCEL { reserved: ptr::null_mut() };
+            // SAFETY: as for the browse
+            let status = unsafe { DnsServiceResolve(&request, &mut cancel) };
+            if status != DNS_REQUEST_PENDING {
+                // SAFETY: the resolve did not start, so the callback never runs
+                drop(unsafe { Box::from_raw(context) });
+                return Err(DiscoveryError::mdns(format!("DnsServiceResolve failed with status {status}")));
+            }
+            Cancel(cancel)
+        };
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(resolved)) => resolved,
+            Ok(Err(_)) => Err(DiscoveryError::mdns(format!("Resolving {instance} was abandoned"))),
+            Err(_) => {
+                // SAFETY: the resolve is pending; its callback frees the context
+                unsafe { DnsServiceResolveCancel(&cancel.0) };
+                Err(DiscoveryError::timeout(format!("Resolving {instance} timed out")))
+            }
+        }
+    }
+
+    /// Send a register or deregister request for `instance` and wait for it
+    async fn submit(&self, instance: &Instance, register: bool) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let context = Box::into_raw(Box::new(tx));
+            let request = DNS_SERVICE_REGISTER_REQUEST {
+                Version: DNS_QUERY_REQUEST_VERSION1,
+                InterfaceIndex: 0,
+                pServiceInstance: instance.0,
+                pRegisterCompletionCallback: Some(register_callback),
+                pQueryContext: context.cast(),
+                hCredentials: ptr::null_mut(),
+                unicastEnabled: 0,
+            };
+            // SAFETY: the request and instance outlive the call; the callback
+            // owns `context` once the request is pending
+            let status = unsafe {
+                if register {
+                    DnsServiceRegister(&request, ptr::null_mut())
+                } else {
+                    DnsServiceDeRegister(&request, ptr::null_mut())
+                }
+            };
+            if status != DNS_REQUEST_PENDING as u32 {
+                // SAFETY: the request did not start, so the callback never runs
+                drop(unsafe { Box::from_raw(context) });
+                return Err(DiscoveryError::mdns(format!("DNS service registration failed with status {status}")));
+            }
+        }
+        match tokio::time::timeout(REGISTER_TIMEOUT, rx).await {
+            Ok(Ok(ERROR_SUCCESS)) => Ok(()),
+            Ok(Ok(status)) => Err(DiscoveryError::mdns(format!("DNS service registration failed with status {status}"))),
+            Ok(Err(_)) => Err(DiscoveryError::mdns("DNS service registration was abandoned")),
+            Err(_) => Err(DiscoveryError::timeout("The mDNS responder did not confirm the registration")),
+        }
+    }
+
+    /// Build the DNS API instance describing `service`
+    fn construct_instance(&self, service: &ServiceInfo) -> Result<Instance> {
+        let name = wide(&instance_name(service));
+        let hostname = match service.hostname().or(self.config.mdns().hostname()) {
+            Some(hostname) => hostname.trim_end_matches('.').to_string(),
+            None => format!("{}.local", service.name),
+        };
+        let hostname = wide(&hostname);
+        let (ip4, ip6) = match service.address {
+            IpAddr::V4(address) => (Some(u32::from_ne_bytes(address.octets())), None),
+            IpAddr::V6(address) => (None, Some(IP6_ADDRESS { IP6Byte: address.octets() })),
+        };
+        let (keys, values): (Vec<Vec<u16>>, Vec<Vec<u16>>) =
+            service.attributes.iter().map(|(key, value)| (wide(key), wide(value))).unzip();
+        let key_ptrs: Vec<*const u16> = keys.iter().map(|key| key.as_ptr()).collect();
+        let value_ptrs: Vec<*const u16> = values.iter().map(|value| value.as_ptr()).collect();
+
+        // SAFETY: every pointer refers to a buffer alive for the call; the
+        // DNS API copies what it keeps
+        let instance = unsafe {
+            DnsServiceConstructInstance(
+                name.as_ptr(),
+                hostname.as_ptr(),
+                ip4.as_ref().map_or(ptr::null(), |ip4| ip4 as *const u32),
+                ip6.as_ref().map_or(ptr::null(), |ip6| ip6 as *const IP6_ADDRESS),
+                service.port,
+                service.priority,
+                service.weight,
+                key_ptrs.len() as u32,
+                key_ptrs.as_ptr(),
+                value_ptrs.as_ptr(),
+            )
+        };
+        if instance.is_null() {
+            return Err(DiscoveryError::mdns(format!("Cannot describe {} to the DNS API", service.name)));
+        }
+        Ok(Instance(instance))
+    }
+}
+
+#[async_trait]
+impl DiscoveryProtocol for WindowsDnsProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        ProtocolType::Mdns
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        let timeout = timeout.unwrap_or(DEFAULT_BROWSE_TIMEOUT);
+        let mut services = Vec::new();
+        for service_type in &service_types {
+            for instance in self.browse(service_type, timeout).await? {
+                match self.resolve(&instance, timeout).await {
+                    Ok(service) => services.push(service),
+                    Err(e) => debug!("Skipping {}: {}", instance, e),
+                }
+            }
+        }
+        Ok(services)
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        let instance = Arc::new(self.construct_instance(&service)?);
+        self.submit(&instance, true).await?;
+        self.registrations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(service.service_id(), instance);
+        Ok(())
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        let instance = self
+            .registrations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&service.service_id());
+        match instance {
+            Some(instance) => self.submit(&instance, false).await,
+            None => {
+                debug!("{} was not registered with the Windows DNS API", service.name);
+                Ok(())
+            }
+        }
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        Ok(self.resolve(&instance_name(service), DEFAULT_BROWSE_TIMEOUT).await.is_ok())
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Services live in the system responder, not in a registry
+    fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+
+    async fn shutdown(&self) -> Result<()> {
+        let instances: Vec<_> = self
+            .registrations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain()
+            .map(|(_, instance)| instance)
+            .collect();
+        for instance in instances {
+            if let Err(e) = self.submit(&instance, false).await {
+                warn!("Failed to deregister a service on shutdown: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        mdns::CAPABILITIES
+    }
+}
+
+/// Full instance name of `service`, e.g. `Printer._ipp._tcp.local`
+fn instance_name(service: &ServiceInfo) -> String {
+    let service_type = service.service_type.to_string();
+    let service_type = service_type.trim_end_matches('.').trim_end_matches(".local");
+    format!("{}.{}.local", service.name, service_type)
+}
+
+/// Null-terminated UTF-16 copy of `s`
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Read a null-terminated UTF-16 string
+///
+/// # Safety
+///
+/// `s` must be null or point to a null-terminated UTF-16 string.
+unsafe fn from_wide(s: *const u16) -> Option<String> {
+    if s.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    // SAFETY: the string is null-terminated
+    while unsafe { *s.add(len) } != 0 {
+        len += 1;
+    }
+    // SAFETY: `len` elements before the terminator are readable
+    Some(String::from_utf16_lossy(unsafe { std::slice::from_raw_parts(s, len) }))
+}
+
+/// Browse results: forwards the instance names of PTR answers
+unsafe extern "system" fn browse_callback(status: u32, context: *const c_void, records: *const DNS_RECORDW) {
+    let tx = context as *mut mpsc::UnboundedSender<String>;
+    if status == ERROR_CANCELLED {
+        // SAFETY: the final call of a cancelled browse owns the context
+        drop(unsafe { Box::from_raw(tx) });
+        return;
+    }
+    let mut record = records;
+    while !record.is_null() {
+        // SAFETY: the list is valid until freed below, and the context until
+        // the browse is cancelled
+        unsafe {
+            if (*record).wType == DNS_TYPE_PTR
+                && let Some(name) = from_wide((*record).Data.PTR.pNameHost)
+            {
+                let _ = (*tx).send(name);
+            }
+            record = (*record).pNext;
+        }
+    }
+    if !records.is_null() {
+        // SAFETY: the callback is responsible for freeing the record list
+        unsafe { DnsFree(records.cast(), DnsFreeRecordList) };
+    }
+}
+
+/// Resolve result: converts the instance and sends it on
+unsafe extern "system" fn resolve_callback(status: u32, context: *const c_void, instance: *const DNS_SERVICE_INSTANCE) {
+    // SAFETY: a resolve calls back exactly once and hands over the context
+    let tx = unsafe { Box::from_raw(context as *mut oneshot::Sender<Result<ServiceInfo>>) };
+    let instance = Instance(instance as *mut DNS_SERVICE_INSTANCE);
+    if status == ERROR_CANCELLED {
+        return;
+    }
+    let result = if status != ERROR_SUCCESS || instance.0.is_null() {
+        Err(DiscoveryError::mdns(format!("DnsServiceResolve completed with status {status}")))
+    } else {
+        // SAFETY: the instance is valid until `instance` is dropped
+        unsafe { to_service_info(&*instance.0) }
+    };
+    let _ = tx.send(result);
+}
+
+/// Registration result: sends the status on
+unsafe extern "system" fn register_callback(status: u32, context: *const c_void, instance: *const DNS_SERVICE_INSTANCE) {
+    // SAFETY: a registration calls back exactly once and hands over the context
+    let tx = unsafe { Box::from_raw(context as *mut oneshot::Sender<u32>) };
+    // The callback owns the instance it is given, a copy of ours
+    drop(Instance(instance as *mut DNS_SERVICE_INSTANCE));
+    let _ = tx.send(status);
+}
+
+/// Convert a resolved instance
+///
+/// # Safety
+///
+/// The instance's pointers must be valid, as they are in a resolve callback.
+unsafe fn to_service_info(instance: &DNS_SERVICE_INSTANCE) -> Result<ServiceInfo> {
+    // SAFETY: the strings are null-terminated or null
+    let full_name = unsafe { from_wide(instance.pszInstanceName) }
+        .ok_or_else(|| DiscoveryError::mdns("Resolved instance has no name"))?;
+    let host = unsafe { from_wide(instance.pszHostName) };
+    let (name, service_type) = split_instance_name(&full_name)
+        .ok_or_else(|| DiscoveryError::mdns(format!("Unexpected instance name {full_name}")))?;
+
+    let address = if !instance.ip4Address.is_null() {
+        // SAFETY: checked for null above
+        IpAddr::V4(Ipv4Addr::from(unsafe { *instance.ip4Address }.to_ne_bytes()))
+    } else if !instance.ip6Address.is_null() {
+        // SAFETY: checked for null above
+        IpAddr::V6(Ipv6Addr::from(unsafe { (*instance.ip6Address).IP6Byte }))
+    } else {
+        return Err(DiscoveryError::mdns(format!("{full_name} has no address")));
+    };
+
+    let mut attributes = HashMap::new();
+    for i in 0..instance.dwPropertyCount as usize {
+        // SAFETY: `keys` and `values` hold `dwPropertyCount` strings each
+        let (key, value) = unsafe { (from_wide(*instance.keys.add(i)), from_wide(*instance.values.add(i))) };
+        if let Some(key) = key {
+            attributes.insert(key, value.unwrap_or_default());
+        }
+    }
+
+    let mut service = ServiceInfo::new(name, service_type, instance.wPort, None)?
+        .with_protocol_type(ProtocolType::Mdns)
+        .with_address(address)
+        .with_attributes(attributes)
+        .with_srv(instance.wPriority, instance.wWeight);
+    if let Some(host) = host {
+        service = service.with_host(host);
+    }
+    Ok(service)
+}
+
+/// Split `Printer._ipp._tcp.local` into `Printer` and `_ipp._tcp`
+fn split_instance_name(full_name: &str) -> Option<(&str, &str)> {
+    let full_name = full_name.trim_end_matches('.').trim_end_matches(".local");
+    let protocol = full_name.rfind("._tcp").or_else(|| full_name.rfind("._udp"))?;
+    let service = full_name[..protocol].rfind("._")?;
+    Some((&full_name[..service], &full_name[service + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_instance_name() {
+        assert_eq!(split_instance_name("Printer._ipp._tcp.local"), Some(("Printer", "_ipp._tcp")));
+        assert_eq!(split_instance_name("Office.Printer._ipp._tcp.local."), Some(("Office.Printer", "_ipp._tcp")));
+        assert_eq!(split_instance_name("nothing.local"), None);
+    }
+}
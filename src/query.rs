@@ -0,0 +1,298 @@
+//! Per-call query options, paging and ordering of query results
+//!
+//! [`DiscoveryOptions`] run a one-off query with
+//! [`ServiceDiscovery::discover_once`](crate::ServiceDiscovery::discover_once),
+//! for service types, protocols, a timeout or a filter other than the
+//! configured ones.
+//!
+//! On a large network a query can match hundreds of services. Passing
+//! [`QueryOptions`] to
+//! [`ServiceDiscovery::discover_services_page`](crate::ServiceDiscovery::discover_services_page)
+//! or [`ServiceRegistry::find_services_page`](crate::registry::ServiceRegistry::find_services_page)
+//! returns one [`Page`] at a time, with a [`PageToken`] for the next.
+//!
+//! Pages are positions in the sorted results, not snapshots: services that
+//! appear or go away between calls shift the pages after them.
+
+use crate::{
+    error::DiscoveryError,
+    service::ServiceInfo,
+    types::{DiscoveryFilter, ProtocolType, ServiceType},
+};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, fmt, str::FromStr, time::Duration};
+
+/// Settings of one discovery call that override the configuration
+///
+/// Anything left unset falls back to the
+/// [`DiscoveryConfig`](crate::config::DiscoveryConfig) of the discovery
+/// instance.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryOptions {
+    service_types: Vec<ServiceType>,
+    protocols: Vec<ProtocolType>,
+    timeout: Option<Duration>,
+    filter: Option<DiscoveryFilter>,
+}
+
+impl DiscoveryOptions {
+    /// Options that change nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look for `service_type` instead of the configured service types;
+    /// call again to look for several
+    pub fn with_service_type(mut self, service_type: ServiceType) -> Self {
+        self.service_types.push(service_type);
+        self
+    }
+
+    /// Query `protocol` instead of every enabled protocol; call again to
+    /// query several
+    pub fn with_protocol(mut self, protocol: ProtocolType) -> Self {
+        self.protocols.push(protocol);
+        self
+    }
+
+    /// Wait up to `timeout` for each protocol instead of the configured
+    /// protocol timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Keep the services matching `filter` instead of the configured filter
+    pub fn with_filter(mut self, filter: DiscoveryFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Service types to look for; empty for the configured ones
+    pub fn service_types(&self) -> &[ServiceType] {
+        &self.service_types
+    }
+
+    /// Protocols to query; empty for every enabled protocol
+    pub fn protocols(&self) -> &[ProtocolType] {
+        &self.protocols
+    }
+
+    /// Timeout per protocol, if overridden
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Filter results must match, if overridden
+    pub fn filter(&self) -> Option<&DiscoveryFilter> {
+        self.filter.as_ref()
+    }
+}
+
+/// Order of services in query results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Best first: as ranked by discovery, by name in the registry
+    #[default]
+    Ranked,
+    /// By name, then service type
+    Name,
+    /// Most recently discovered first
+    Newest,
+}
+
+/// Where the next page of results starts
+///
+/// Tokens print as plain text and parse back with [`FromStr`], so they can
+/// be handed to a UI and returned with its next request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PageToken {
+    offset: usize,
+}
+
+impl PageToken {
+    /// Token for the page starting at `offset`
+    pub fn new(offset: usize) -> Self {
+        Self { offset }
+    }
+
+    /// Position of the first service of the page
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for PageToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.offset)
+    }
+}
+
+impl FromStr for PageToken {
+    type Err = DiscoveryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Self::new)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Invalid page token '{s}': {e}")))
+    }
+}
+
+/// Limit, offset and order of one query
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryOptions {
+    limit: Option<usize>,
+    offset: usize,
+    sort: SortOrder,
+}
+
+impl QueryOptions {
+    /// Return every result in ranked order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return at most `limit` results
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip the first `offset` results
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Continue where the page that returned `token` ended
+    pub fn with_continuation(self, token: PageToken) -> Self {
+        self.with_offset(token.offset)
+    }
+
+    /// Sort results in `sort` order
+    pub fn with_sort(mut self, sort: SortOrder) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Largest number of results returned
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Number of results skipped
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Order of the results
+    pub fn sort(&self) -> SortOrder {
+        self.sort
+    }
+
+    /// Sort `services` and cut out the requested page
+    ///
+    /// [`SortOrder::Ranked`] keeps the order `services` come in.
+    pub fn paginate(&self, mut services: Vec<ServiceInfo>) -> Page {
+        match self.sort {
+            SortOrder::Ranked => {}
+            SortOrder::Name => services.sort_by_cached_key(|service| (service.name.clone(), service.service_type.to_string())),
+            SortOrder::Newest => services.sort_by_key(|service| Reverse(service.discovered_at)),
+        }
+
+        let total = services.len();
+        let start = self.offset.min(total);
+        let end = self.limit.map_or(total, |limit| start.saturating_add(limit).min(total));
+        let items: Vec<ServiceInfo> = services.drain(start..end).collect();
+        Page {
+            items,
+            total,
+            next: (end < total).then(|| PageToken::new(end)),
+        }
+    }
+}
+
+/// One page of query results
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    items: Vec<ServiceInfo>,
+    total: usize,
+    next: Option<PageToken>,
+}
+
+impl Page {
+    /// Services on this page
+    pub fn items(&self) -> &[ServiceInfo] {
+        &self.items
+    }
+
+    /// Take the services on this page
+    pub fn into_items(self) -> Vec<ServiceInfo> {
+        self.items
+    }
+
+    /// Number of results across all pages
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Token for the next page, `None` on the last one
+    pub fn next_token(&self) -> Option<PageToken> {
+        self.next
+    }
+
+    /// Whether this is the last page
+    pub fn is_last(&self) -> bool {
+        self.next.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn services() -> Vec<ServiceInfo> {
+        ["delta", "alpha", "charlie", "bravo", "echo"]
+            .into_iter()
+            .enumerate()
+            .map(|(age, name)| {
+                let mut service = ServiceInfo::new(name, "_http._tcp", 8080, None).unwrap();
+                service.discovered_at = SystemTime::UNIX_EPOCH + Duration::from_secs(100 + age as u64);
+                service
+            })
+            .collect()
+    }
+
+    fn names(page: &Page) -> Vec<&str> {
+        page.items().iter().map(ServiceInfo::name).collect()
+    }
+
+    #[test]
+    fn test_paging() {
+        let options = QueryOptions::new().with_limit(2).with_sort(SortOrder::Name);
+        let first = options.paginate(services());
+        assert_eq!(names(&first), ["alpha", "bravo"]);
+        assert_eq!(first.total(), 5);
+
+        let token: PageToken = first.next_token().unwrap().to_string().parse().unwrap();
+        let second = options.clone().with_continuation(token).paginate(services());
+        assert_eq!(names(&second), ["charlie", "delta"]);
+
+        let last = options.clone().with_continuation(second.next_token().unwrap()).paginate(services());
+        assert_eq!(names(&last), ["echo"]);
+        assert!(last.is_last());
+
+        let beyond = options.with_offset(10).paginate(services());
+        assert!(beyond.items().is_empty() && beyond.is_last());
+        assert!("next".parse::<PageToken>().is_err());
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        assert_eq!(names(&QueryOptions::new().paginate(services())), ["delta", "alpha", "charlie", "bravo", "echo"]);
+        let newest = QueryOptions::new().with_sort(SortOrder::Newest).paginate(services());
+        assert_eq!(names(&newest), ["echo", "bravo", "charlie", "alpha", "delta"]);
+    }
+}
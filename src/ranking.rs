@@ -0,0 +1,200 @@
+//! Ordering of discovered services by preference
+//!
+//! [`ServiceDiscovery::discover_services`](crate::ServiceDiscovery::discover_services)
+//! returns services best first, as ordered by a [`ServiceRanker`]. The
+//! [`DefaultRanker`] prefers verified services, then services on one of our
+//! own subnets, then lower health check latency, then newer versions. Apps
+//! that pick a single service from the results and need a different notion of
+//! "best" supply their own ranker with
+//! [`ServiceDiscovery::with_ranker`](crate::ServiceDiscovery::with_ranker).
+
+use crate::service::ServiceInfo;
+use ipnet::IpNet;
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+/// Attribute the [`DefaultRanker`] reads versions from
+pub const VERSION_ATTRIBUTE: &str = "version";
+
+/// What a ranker knows besides the services themselves
+#[derive(Debug, Clone, Default)]
+pub struct RankingContext {
+    local_networks: Vec<IpNet>,
+    latencies: HashMap<String, Duration>,
+}
+
+impl RankingContext {
+    /// Create an empty context
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the networks this host is attached to
+    pub fn with_local_networks(mut self, networks: Vec<IpNet>) -> Self {
+        self.local_networks = networks;
+        self
+    }
+
+    /// Set the latency of the last successful health check of each service,
+    /// keyed by service name
+    pub fn with_latencies(mut self, latencies: HashMap<String, Duration>) -> Self {
+        self.latencies = latencies;
+        self
+    }
+
+    /// Networks this host is attached to
+    pub fn local_networks(&self) -> &[IpNet] {
+        &self.local_networks
+    }
+
+    /// Whether `address` is on one of our networks
+    pub fn is_local(&self, address: IpAddr) -> bool {
+        self.local_networks.iter().any(|network| network.contains(&address))
+    }
+
+    /// Latency of the last successful health check of `service`, if any
+    pub fn latency(&self, service: &ServiceInfo) -> Option<Duration> {
+        self.latencies.get(&service.name).copied()
+    }
+}
+
+/// Orders discovered services by preference
+pub trait ServiceRanker: Send + Sync {
+    /// Score `service`; services with higher scores come first
+    fn score(&self, service: &ServiceInfo, context: &RankingContext) -> f64;
+
+    /// Sort `services` best first
+    ///
+    /// Sorts by descending [`score`](Self::score), keeping the discovery
+    /// order of services that score the same. Override to compare services
+    /// with each other rather than score them one at a time.
+    fn rank(&self, services: &mut [ServiceInfo], context: &RankingContext) {
+        let mut scored: Vec<(f64, ServiceInfo)> = services
+            .iter()
+            .map(|service| (self.score(service, context), service.clone()))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        for (slot, (_, service)) in services.iter_mut().zip(scored) {
+            *slot = service;
+        }
+    }
+}
+
+/// Ranks verified, nearby, fast and up-to-date services first
+///
+/// Each preference outweighs all those after it: verification, then an
+/// address on one of our subnets, then health check latency, then the
+/// version in the [`VERSION_ATTRIBUTE`] attribute. Services without a known
+/// latency or a valid version rank below those with one.
+#[derive(Debug, Clone)]
+pub struct DefaultRanker {
+    version_attribute: String,
+}
+
+impl Default for DefaultRanker {
+    fn default() -> Self {
+        Self { version_attribute: VERSION_ATTRIBUTE.to_string() }
+    }
+}
+
+impl DefaultRanker {
+    /// Create a ranker reading versions from [`VERSION_ATTRIBUTE`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read versions from `attribute` instead
+    pub fn with_version_attribute<S: Into<String>>(mut self, attribute: S) -> Self {
+        self.version_attribute = attribute.into();
+        self
+    }
+
+    /// Attribute versions are read from
+    pub fn version_attribute(&self) -> &str {
+        &self.version_attribute
+    }
+}
+
+impl ServiceRanker for DefaultRanker {
+    /// Verification and locality; latency adds less than one point
+    fn score(&self, service: &ServiceInfo, context: &RankingContext) -> f64 {
+        let mut score = 0.0;
+        if service.verified {
+            score += 2.0;
+        }
+        if context.is_local(service.address) {
+            score += 1.0;
+        }
+        if let Some(latency) = context.latency(service) {
+            score += 1.0 / (2.0 + latency.as_secs_f64() * 1000.0);
+        }
+        score
+    }
+
+    fn rank(&self, services: &mut [ServiceInfo], context: &RankingContext) {
+        services.sort_by(|a, b| {
+            self.score(b, context)
+                .total_cmp(&self.score(a, context))
+                .then_with(|| {
+                    let version = |service: &ServiceInfo| service.get_version(&self.version_attribute).ok().flatten();
+                    // `None` sorts before any version, so newer versions come first
+                    version(b).cmp(&version(a))
+                })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, address: &str) -> ServiceInfo {
+        ServiceInfo::new(name, "_http._tcp", 8080, None)
+            .unwrap()
+            .with_address(address.parse().unwrap())
+    }
+
+    #[test]
+    fn test_default_ranker_preferences() {
+        let context = RankingContext::new()
+            .with_local_networks(vec!["192.168.1.0/24".parse().unwrap()])
+            .with_latencies(HashMap::from([
+                ("fast".to_string(), Duration::from_millis(2)),
+                ("slow".to_string(), Duration::from_millis(200)),
+            ]));
+
+        let mut verified = service("verified", "10.0.0.5");
+        verified.verified = true;
+        let mut services = vec![
+            service("remote", "10.0.0.9"),
+            service("old", "192.168.1.20").with_attribute("version", "1.2.0"),
+            service("slow", "192.168.1.30"),
+            service("new", "192.168.1.21").with_attribute("version", "1.10.0"),
+            service("fast", "192.168.1.31"),
+            verified,
+        ];
+        DefaultRanker::new().rank(&mut services, &context);
+
+        let names: Vec<&str> = services.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["verified", "fast", "slow", "new", "old", "remote"]);
+    }
+
+    #[test]
+    fn test_custom_ranker() {
+        /// Prefers the lowest port
+        struct LowestPort;
+
+        impl ServiceRanker for LowestPort {
+            fn score(&self, service: &ServiceInfo, _context: &RankingContext) -> f64 {
+                -f64::from(service.port)
+            }
+        }
+
+        let mut services: Vec<ServiceInfo> = [("a", 9000), ("b", 80), ("c", 9000)]
+            .into_iter()
+            .map(|(name, port)| ServiceInfo::new(name, "_http._tcp", port, None).unwrap())
+            .collect();
+        LowestPort.rank(&mut services, &RankingContext::new());
+        let names: Vec<&str> = services.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["b", "a", "c"]);
+    }
+}
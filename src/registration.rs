@@ -0,0 +1,450 @@
+//! Status of live registrations
+//!
+//! [`ServiceDiscovery`] keeps a [`RegistrationStatus`] for every service it
+//! registered: which protocols accepted it, when it was last announced and
+//! how its refreshes are going. Registrations made with
+//! [`RegistrationConfig::auto_refresh`](crate::config::RegistrationConfig::auto_refresh)
+//! set are announced again every
+//! [`refresh_interval`](crate::config::RegistrationConfig::refresh_interval)
+//! so peers never see their records expire.
+//!
+//! A [`RegistrationHandle`], from [`ServiceDiscovery::register`], reads the
+//! status of one registration, changes its attributes and withdraws it.
+//!
+//! Registrations made with
+//! [`RegistrationConfig::auto_deregister_on_unhealthy`](crate::config::RegistrationConfig::auto_deregister_on_unhealthy)
+//! set are probed like discovered services. After the configured number of
+//! consecutive failures the service is withdrawn from every protocol
+//! advertising it and [`ServiceEvent::Withdrawn`] is published; the first
+//! successful probe announces it again with the same protocols and publishes
+//! [`ServiceEvent::Reinstated`]. Refreshes and attribute updates leave a
+//! withdrawn service unannounced.
+
+use crate::{
+    discovery::ServiceDiscovery,
+    error::{DiscoveryError, Result},
+    health::{HealthConfig, HealthProbe},
+    protocols::{MultiProtocolResult, ProtocolManager},
+    registry::ServiceRegistry,
+    service::{AttributeUpdate, ServiceEvent, ServiceId, ServiceInfo},
+    types::ProtocolType,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+/// Whether a protocol advertises a registered service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolStatus {
+    /// The protocol accepted the latest announcement
+    Registered,
+    /// The protocol rejected the latest announcement, with the error
+    Failed(String),
+}
+
+/// State of one live registration
+#[derive(Debug, Clone)]
+pub struct RegistrationStatus {
+    service: ServiceInfo,
+    protocols: HashMap<ProtocolType, ProtocolStatus>,
+    registered_at: SystemTime,
+    last_announced: SystemTime,
+    announcements: u64,
+    refresh_interval: Option<Duration>,
+    /// Protocols the service was withdrawn from while unhealthy
+    withdrawn: Option<Vec<ProtocolType>>,
+}
+
+impl RegistrationStatus {
+    /// The service as currently advertised
+    pub fn service(&self) -> &ServiceInfo {
+        &self.service
+    }
+
+    /// Outcome of the latest announcement with each protocol
+    pub fn protocols(&self) -> &HashMap<ProtocolType, ProtocolStatus> {
+        &self.protocols
+    }
+
+    /// Whether at least one protocol advertises the service
+    pub fn is_registered(&self) -> bool {
+        !self.is_withdrawn() && self.protocols.values().any(|status| *status == ProtocolStatus::Registered)
+    }
+
+    /// Whether the service is withdrawn until it passes a health probe
+    pub fn is_withdrawn(&self) -> bool {
+        self.withdrawn.is_some()
+    }
+
+    /// When the service was registered
+    pub fn registered_at(&self) -> SystemTime {
+        self.registered_at
+    }
+
+    /// When the service was last announced, by registration, refresh or update
+    pub fn last_announced(&self) -> SystemTime {
+        self.last_announced
+    }
+
+    /// Number of times the service has been announced
+    pub fn announcements(&self) -> u64 {
+        self.announcements
+    }
+
+    /// How often the service is refreshed, if it is
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_interval
+    }
+
+    /// When the next automatic refresh is due, if the service is refreshed
+    pub fn next_refresh(&self) -> Option<SystemTime> {
+        self.refresh_interval.map(|interval| self.last_announced + interval)
+    }
+}
+
+/// How a registration made with auto-deregistration is probed
+#[derive(Clone)]
+pub(crate) struct HealthWatch {
+    /// Consecutive failed probes before the service is withdrawn
+    pub(crate) threshold: u32,
+    /// Time between probes
+    pub(crate) interval: Duration,
+    pub(crate) probe: HealthProbe,
+    pub(crate) config: HealthConfig,
+}
+
+/// Registration statuses and their tasks, shared with the tasks
+#[derive(Clone, Default)]
+pub(crate) struct Registrations {
+    statuses: Arc<StdMutex<HashMap<ServiceId, RegistrationStatus>>>,
+    refreshes: Arc<StdMutex<HashMap<ServiceId, JoinHandle<()>>>>,
+    health_watches: Arc<StdMutex<HashMap<ServiceId, JoinHandle<()>>>>,
+}
+
+impl Registrations {
+    /// Record a new registration with the protocols that accepted it
+    pub(crate) fn registered(
+        &self,
+        service: &ServiceInfo,
+        outcome: &MultiProtocolResult<ServiceInfo>,
+        refresh_interval: Option<Duration>,
+    ) {
+        let now = SystemTime::now();
+        let status = RegistrationStatus {
+            service: service.clone(),
+            protocols: protocol_statuses(outcome),
+            registered_at: now,
+            last_announced: now,
+            announcements: 1,
+            refresh_interval,
+            withdrawn: None,
+        };
+        self.statuses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(service.service_id(), status);
+    }
+
+    /// Record another announcement of a registered service
+    pub(crate) fn announced(&self, service: &ServiceInfo, outcome: &MultiProtocolResult<ServiceInfo>) {
+        let mut statuses = self.statuses.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(status) = statuses.get_mut(&service.service_id()) {
+            status.service = service.clone();
+            status.protocols.extend(protocol_statuses(outcome));
+            status.last_announced = SystemTime::now();
+            status.announcements += 1;
+        }
+    }
+
+    /// Status of the registration of `id`
+    pub(crate) fn get(&self, id: &ServiceId) -> Option<RegistrationStatus> {
+        self.statuses.lock().unwrap_or_else(|e| e.into_inner()).get(id).cloned()
+    }
+
+    /// Record `service` as the one to reinstate, if its registration is withdrawn
+    fn hold_if_withdrawn(&self, service: &ServiceInfo) -> Option<RegistrationStatus> {
+        let mut statuses = self.statuses.lock().unwrap_or_else(|e| e.into_inner());
+        let status = statuses.get_mut(&service.service_id()).filter(|status| status.is_withdrawn())?;
+        status.service = service.clone();
+        Some(status.clone())
+    }
+
+    /// Record whether `id` is withdrawn, and from which protocols
+    fn set_withdrawn(&self, id: &ServiceId, withdrawn: Option<Vec<ProtocolType>>) {
+        if let Some(status) = self.statuses.lock().unwrap_or_else(|e| e.into_inner()).get_mut(id) {
+            status.withdrawn = withdrawn;
+        }
+    }
+
+    /// Forget the registration of `id` and stop its tasks
+    pub(crate) fn remove(&self, id: &ServiceId) {
+        self.statuses.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+        if let Some(refresh) = self.refreshes.lock().unwrap_or_else(|e| e.into_inner()).remove(id) {
+            refresh.abort();
+        }
+        if let Some(watch) = self.health_watches.lock().unwrap_or_else(|e| e.into_inner()).remove(id) {
+            watch.abort();
+        }
+    }
+
+    /// Announce `id` again every `interval` until it is removed
+    pub(crate) fn start_refresh(
+        &self,
+        id: ServiceId,
+        interval: Duration,
+        protocol_manager: ProtocolManager,
+        registered_services: Arc<Mutex<HashMap<ServiceId, ServiceInfo>>>,
+    ) {
+        let registrations = self.clone();
+        let task_id = id.clone();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match reannounce(&protocol_manager, &registered_services, &registrations, &task_id).await {
+                    Ok(_) => debug!("Refreshed registration of {}", task_id),
+                    Err(DiscoveryError::ServiceNotFound(_)) => return,
+                    Err(e) => warn!("Failed to refresh registration of {}: {}", task_id, e),
+                }
+            }
+        });
+        if let Some(previous) = self.refreshes.lock().unwrap_or_else(|e| e.into_inner()).insert(id, task) {
+            previous.abort();
+        }
+    }
+
+    /// Probe `id` as `watch` says, withdrawing and reinstating it, until it is removed
+    pub(crate) fn start_health_watch(
+        &self,
+        id: ServiceId,
+        watch: HealthWatch,
+        protocol_manager: ProtocolManager,
+        registered_services: Arc<Mutex<HashMap<ServiceId, ServiceInfo>>>,
+    ) {
+        let registrations = self.clone();
+        let task_id = id.clone();
+        let task = tokio::spawn(async move {
+            let registry = Arc::clone(protocol_manager.registry());
+            let mut failures = 0;
+            let mut ticker = tokio::time::interval(watch.interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(service) = registered_services.lock().await.get(&task_id).cloned() else {
+                    return;
+                };
+                let result = watch.probe.check(&service, &watch.config, registry.resolution_cache()).await;
+                let withdrawn = registrations.get(&task_id).and_then(|status| status.withdrawn);
+                if result.healthy {
+                    failures = 0;
+                    if let Some(protocols) = withdrawn {
+                        reinstate(&protocol_manager, &registry, &registrations, service, protocols).await;
+                    }
+                    continue;
+                }
+                failures += 1;
+                debug!(
+                    "Health probe {} of {} failed: {}",
+                    failures,
+                    task_id,
+                    result.detail.as_deref().unwrap_or("unhealthy")
+                );
+                if failures >= watch.threshold && withdrawn.is_none() {
+                    withdraw(&protocol_manager, &registry, &registrations, service, failures).await;
+                }
+            }
+        });
+        if let Some(previous) = self.health_watches.lock().unwrap_or_else(|e| e.into_inner()).insert(id, task) {
+            previous.abort();
+        }
+    }
+
+    /// Stop every refresh task and health watch
+    pub(crate) fn stop_tasks(&self) {
+        for (_, refresh) in self.refreshes.lock().unwrap_or_else(|e| e.into_inner()).drain() {
+            refresh.abort();
+        }
+        for (_, watch) in self.health_watches.lock().unwrap_or_else(|e| e.into_inner()).drain() {
+            watch.abort();
+        }
+    }
+}
+
+/// Stop advertising an unhealthy service, keeping its registration
+async fn withdraw(
+    protocol_manager: &ProtocolManager,
+    registry: &ServiceRegistry,
+    registrations: &Registrations,
+    service: ServiceInfo,
+    failures: u32,
+) {
+    let mut withdrawn = Vec::new();
+    for protocol_type in protocol_manager.registered_protocols(&service) {
+        match protocol_manager.unregister_from(&service, protocol_type).await {
+            Ok(()) => withdrawn.push(protocol_type),
+            Err(e) => warn!("Failed to withdraw unhealthy {} from {}: {}", service.name(), protocol_type, e),
+        }
+    }
+    warn!("Withdrew {} after {} failed health probes", service.name(), failures);
+    registrations.set_withdrawn(&service.service_id(), Some(withdrawn));
+    registry.publish(ServiceEvent::withdrawn(service, failures)).await;
+}
+
+/// Announce a withdrawn service again with the protocols it was withdrawn from
+async fn reinstate(
+    protocol_manager: &ProtocolManager,
+    registry: &ServiceRegistry,
+    registrations: &Registrations,
+    service: ServiceInfo,
+    protocols: Vec<ProtocolType>,
+) {
+    let outcome = protocol_manager.register_with(service.clone(), protocols).await;
+    if outcome.successes().is_empty()
+        && let Some((_, e)) = outcome.failures().first()
+    {
+        warn!("Failed to reinstate {}, trying again after the next probe: {}", service.name(), e);
+        return;
+    }
+    info!("Reinstated {} after a successful health probe", service.name());
+    registrations.set_withdrawn(&service.service_id(), None);
+    registrations.announced(&service, &outcome);
+    registry.publish(ServiceEvent::reinstated(service)).await;
+}
+
+fn protocol_statuses(outcome: &MultiProtocolResult<ServiceInfo>) -> HashMap<ProtocolType, ProtocolStatus> {
+    let registered = outcome
+        .successes()
+        .iter()
+        .map(|(protocol, _)| (*protocol, ProtocolStatus::Registered));
+    let failed = outcome
+        .failures()
+        .iter()
+        .map(|(protocol, e)| (*protocol, ProtocolStatus::Failed(e.to_string())));
+    registered.chain(failed).collect()
+}
+
+/// Announce a registered service again with the protocols advertising it
+///
+/// # Errors
+///
+/// Returns [`DiscoveryError::ServiceNotFound`] if the service is not
+/// registered, or the first protocol error if no protocol accepted the
+/// announcement
+pub(crate) async fn reannounce(
+    protocol_manager: &ProtocolManager,
+    registered_services: &Mutex<HashMap<ServiceId, ServiceInfo>>,
+    registrations: &Registrations,
+    id: &ServiceId,
+) -> Result<RegistrationStatus> {
+    let service = registered_services
+        .lock()
+        .await
+        .get(id)
+        .cloned()
+        .ok_or_else(|| DiscoveryError::service_not_found(format!("{id} is not registered")))?;
+    announce(protocol_manager, registrations, service).await
+}
+
+/// Announce `service` with the protocols advertising it and record the outcome
+///
+/// A withdrawn service is not announced; it is announced as it is now when
+/// it is reinstated.
+pub(crate) async fn announce(
+    protocol_manager: &ProtocolManager,
+    registrations: &Registrations,
+    service: ServiceInfo,
+) -> Result<RegistrationStatus> {
+    if let Some(status) = registrations.hold_if_withdrawn(&service) {
+        debug!("Not announcing {} while it is withdrawn", service.name());
+        return Ok(status);
+    }
+    let protocols = protocol_manager.registered_protocols(&service);
+    let protocols = if protocols.is_empty() { vec![service.protocol_type()] } else { protocols };
+    let outcome = protocol_manager.register_with(service.clone(), protocols).await;
+    registrations.announced(&service, &outcome);
+    outcome.into_result()?;
+    registrations
+        .get(&service.service_id())
+        .ok_or_else(|| DiscoveryError::service_not_found(format!("{} is not registered", service.name())))
+}
+
+/// A live registration made with [`ServiceDiscovery::register`]
+///
+/// Dropping the handle leaves the service registered; it is withdrawn by
+/// [`unregister`](Self::unregister) or when the discovery instance closes.
+pub struct RegistrationHandle<'a> {
+    discovery: &'a ServiceDiscovery,
+    id: ServiceId,
+}
+
+impl<'a> RegistrationHandle<'a> {
+    pub(crate) fn new(discovery: &'a ServiceDiscovery, id: ServiceId) -> Self {
+        Self { discovery, id }
+    }
+
+    /// Identity of the registered service
+    pub fn service_id(&self) -> &ServiceId {
+        &self.id
+    }
+
+    /// Current state of the registration, or `None` once it is withdrawn
+    pub fn status(&self) -> Option<RegistrationStatus> {
+        self.discovery.registration_status(&self.id)
+    }
+
+    /// Announce the service again now
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service is no longer registered or no
+    /// protocol accepts the announcement
+    pub async fn refresh(&self) -> Result<RegistrationStatus> {
+        self.discovery.refresh_registration(&self.id).await
+    }
+
+    /// Set attributes of the service and announce the change
+    ///
+    /// See [`ServiceDiscovery::update_attributes`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service is no longer registered or no
+    /// protocol accepts the announcement
+    pub async fn update_attributes<I, K, V>(&self, attributes: I) -> Result<RegistrationStatus>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.discovery.update_attributes(&self.id, attributes).await
+    }
+
+    /// Change the attributes or port of the service and announce the change
+    ///
+    /// See [`ServiceDiscovery::update_service`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service is no longer registered, the update is
+    /// invalid or no protocol accepts the announcement
+    pub async fn update(&self, update: AttributeUpdate) -> Result<RegistrationStatus> {
+        self.discovery.update_service(&self.id, update).await
+    }
+
+    /// Withdraw the service
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a protocol fails to withdraw it
+    pub async fn unregister(self) -> Result<()> {
+        let status = self
+            .status()
+            .ok_or_else(|| DiscoveryError::service_not_found(format!("{} is not registered", self.id)))?;
+        self.discovery.unregister_service(status.service()).await
+    }
+}
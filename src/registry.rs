@@ -5,17 +5,35 @@
 //! services discovered from the network.
 
 use crate::{
+    clock::{self, Clock},
     error::{DiscoveryError, Result},
-    service::ServiceInfo,
+    events::{Changes, EventBus, EventChannelConfig, EventReceiver},
+    health::HealthStatus,
+    query::{Page, QueryOptions, SortOrder},
+    resolver::ResolutionCache,
+    service::{ServiceChange, ServiceEvent, ServiceId, ServiceInfo},
     types::{ServiceType, ProtocolType},
 };
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+mod index;
+
+use index::ServiceIndex;
+
+/// Capacity of the registry event channel
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Events kept for [`ServiceRegistry::changes_since`] by default
+pub const DEFAULT_CHANGE_LOG_CAPACITY: usize = 4096;
 
 /// Entry in the service registry with metadata
 #[derive(Debug, Clone)]
@@ -30,6 +48,12 @@ pub struct ServiceEntry {
     pub ttl: Option<Duration>,
     /// The protocol that discovered/registered this service
     pub protocol: ProtocolType,
+    /// Result of the latest health checks
+    pub health: HealthStatus,
+    /// Round trip time of the latest successful verification or health check
+    pub latency: Option<Duration>,
+    /// Number of times the service was rediscovered with a changed advertisement
+    pub revision: u64,
 }
 
 impl ServiceEntry {
@@ -41,6 +65,9 @@ impl ServiceEntry {
             is_local: true,
             ttl: None, // Local services don't expire
             protocol,
+            health: HealthStatus::Healthy,
+            latency: None,
+            revision: 0,
         }
     }
 
@@ -52,21 +79,30 @@ impl ServiceEntry {
             is_local: false,
             ttl,
             protocol,
+            health: HealthStatus::Healthy,
+            latency: None,
+            revision: 0,
         }
     }
 
     /// Check if this service entry has expired
     pub fn is_expired(&self) -> bool {
-        if let Some(ttl) = self.ttl {
-            self.timestamp.elapsed() > ttl
-        } else {
-            false
-        }
+        self.is_expired_at(Instant::now())
     }
 
-    /// Get the service ID for indexing
-    pub fn service_id(&self) -> String {
-        format!("{}:{}:{}", self.service.name(), self.service.service_type(), self.service.port())
+    /// Check if this service entry has expired at `now`
+    pub fn is_expired_at(&self, now: Instant) -> bool {
+        self.ttl.is_some_and(|ttl| self.age_at(now) > ttl)
+    }
+
+    /// Time since the entry was added or last refreshed, as of `now`
+    pub fn age_at(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.timestamp)
+    }
+
+    /// Get the service ID the entry is indexed under
+    pub fn service_id(&self) -> ServiceId {
+        self.service.service_id()
     }
 }
 
@@ -79,12 +115,18 @@ pub struct ServiceFilter {
     pub protocols: Option<Vec<ProtocolType>>,
     /// Filter by service name (contains)
     pub name_contains: Option<String>,
+    /// Filter by exact service name
+    pub name: Option<String>,
     /// Include only local services
     pub local_only: bool,
     /// Include only discovered services
     pub discovered_only: bool,
     /// Maximum age of services to include
     pub max_age: Option<Duration>,
+    /// Filter by the interface a service was found on
+    pub interface: Option<String>,
+    /// Include only services carrying every one of these tags
+    pub tags: Vec<String>,
 }
 
 
@@ -113,6 +155,12 @@ impl ServiceFilter {
         self
     }
 
+    /// Filter by exact service name
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
     /// Include only local services
     pub fn local_only(mut self) -> Self {
         self.local_only = true;
@@ -131,18 +179,42 @@ impl ServiceFilter {
         self
     }
 
+    /// Filter by the network interface a service was found on
+    pub fn with_interface(mut self, interface: impl Into<String>) -> Self {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    /// Include only services carrying every one of `tags`
+    ///
+    /// Tag lookups use the registry's tag index instead of scanning every
+    /// service.
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
     /// Check if a service entry matches this filter
     pub fn matches(&self, entry: &ServiceEntry) -> bool {
+        self.matches_at(entry, Instant::now())
+    }
+
+    /// Check if a service entry matches this filter at `now`
+    pub fn matches_at(&self, entry: &ServiceEntry, now: Instant) -> bool {
         // Check if expired
-        if entry.is_expired() {
+        if entry.is_expired_at(now) {
             return false;
         }
 
         // Check max age
-        if let Some(max_age) = self.max_age {
-            if entry.timestamp.elapsed() > max_age {
-                return false;
-            }
+        if let Some(max_age) = self.max_age
+            && entry.age_at(now) > max_age
+        {
+            return false;
         }
 
         // Check local/discovered filter
@@ -154,24 +226,43 @@ impl ServiceFilter {
         }
 
         // Check service types
-        if let Some(ref types) = self.service_types {
-            if !types.iter().any(|t| t.to_string() == entry.service.service_type().to_string()) {
-                return false;
-            }
+        if let Some(ref types) = self.service_types
+            && !types.contains(entry.service.service_type())
+        {
+            return false;
         }
 
         // Check protocols
-        if let Some(ref protocols) = self.protocols {
-            if !protocols.contains(&entry.protocol) {
-                return false;
-            }
+        if let Some(ref protocols) = self.protocols
+            && !protocols.contains(&entry.protocol)
+        {
+            return false;
+        }
+
+        // Check exact name
+        if let Some(ref name) = self.name
+            && entry.service.name() != name
+        {
+            return false;
         }
 
         // Check name contains
-        if let Some(ref name) = self.name_contains {
-            if !entry.service.name().contains(name) {
-                return false;
-            }
+        if let Some(ref name) = self.name_contains
+            && !entry.service.name().contains(name)
+        {
+            return false;
+        }
+
+        // Check interface
+        if let Some(ref interface) = self.interface
+            && entry.service.interface.as_ref() != Some(interface)
+        {
+            return false;
+        }
+
+        // Check tags
+        if !self.tags.iter().all(|tag| entry.service.has_tag(tag)) {
+            return false;
         }
 
         true
@@ -181,35 +272,188 @@ impl ServiceFilter {
 /// Centralized service registry for managing discovered and registered services
 pub struct ServiceRegistry {
     /// All services indexed by service ID
-    services: Arc<RwLock<HashMap<String, ServiceEntry>>>,
+    services: Arc<RwLock<ServiceIndex>>,
     /// Default TTL for discovered services
     default_ttl: Duration,
     /// Maximum number of services to store
     max_services: usize,
+    /// Subscribers to registry change events
+    events: Arc<EventBus>,
+    /// Hostname resolutions shared by the protocols and verification
+    resolutions: Arc<ResolutionCache>,
+    /// Time source for entry ages and expiry
+    clock: Arc<dyn Clock>,
+    /// Protocols each registered service is advertised with, by service UUID
+    registrations: StdMutex<HashMap<Uuid, Vec<ProtocolType>>>,
 }
 
 impl ServiceRegistry {
     /// Create a new service registry
     pub fn new() -> Self {
-        Self {
-            services: Arc::new(RwLock::new(HashMap::new())),
-            default_ttl: Duration::from_secs(300), // 5 minutes
-            max_services: 1000,
-        }
+        Self::with_settings(Duration::from_secs(300), 1000) // 5 minutes
     }
 
     /// Create a new service registry with custom settings
     pub fn with_settings(default_ttl: Duration, max_services: usize) -> Self {
         Self {
-            services: Arc::new(RwLock::new(HashMap::new())),
+            services: Arc::new(RwLock::new(ServiceIndex::default())),
             default_ttl,
             max_services,
+            events: Arc::new(EventBus::new(EVENT_CHANNEL_CAPACITY, DEFAULT_CHANGE_LOG_CAPACITY)),
+            resolutions: Arc::new(ResolutionCache::default()),
+            clock: clock::system(),
+            registrations: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keep the latest `capacity` events for [`changes_since`](Self::changes_since)
+    ///
+    /// Replaces the event channels, so call it before subscribing.
+    pub fn with_change_log_capacity(mut self, capacity: usize) -> Self {
+        self.events = Arc::new(EventBus::new(EVENT_CHANNEL_CAPACITY, capacity));
+        self
+    }
+
+    /// Age and expire entries by `clock` instead of tokio's clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The time source entries are aged by
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Share `cache` for hostname resolutions instead of a default one
+    pub fn with_resolution_cache(mut self, cache: Arc<ResolutionCache>) -> Self {
+        self.resolutions = cache;
+        self
+    }
+
+    /// Hostname resolutions of the protocols and verification using this registry
+    pub fn resolution_cache(&self) -> &Arc<ResolutionCache> {
+        &self.resolutions
+    }
+
+    /// Subscribe to registry change events
+    ///
+    /// A receiver that falls more than 256 events behind skips the oldest
+    /// ones; use [`subscribe_bounded`](Self::subscribe_bounded) to choose
+    /// what happens instead.
+    pub fn subscribe(&self) -> broadcast::Receiver<ServiceEvent> {
+        self.events.subscribe()
+    }
+
+    /// Subscribe to registry change events through a queue of its own
+    ///
+    /// When the queue is full, events are handled as `config.overflow` says.
+    /// With [`OverflowPolicy::Block`](crate::events::OverflowPolicy::Block)
+    /// publishers wait for the receiver, so it must keep reading.
+    pub fn subscribe_bounded(&self, config: EventChannelConfig) -> EventReceiver {
+        self.events.subscribe_bounded(config)
+    }
+
+    /// Events dropped by bounded subscriptions because their queues were full
+    pub fn dropped_events(&self) -> u64 {
+        self.events.dropped()
+    }
+
+    /// The events published after `sequence`, for replicating the registry
+    ///
+    /// Start from 0, or from the [`RegistrySnapshot::sequence`] of a
+    /// snapshot, and pass [`Changes::latest`] to the next call. When
+    /// [`Changes::truncated`] is set the log no longer reaches back to
+    /// `sequence`, or the registry was recreated since; start over from a
+    /// fresh snapshot.
+    pub fn changes_since(&self, sequence: u64) -> Changes {
+        self.events.changes_since(sequence)
+    }
+
+    /// Sequence number of the latest event, 0 before the first
+    pub fn latest_sequence(&self) -> u64 {
+        self.events.latest_sequence()
+    }
+
+    /// Publish an event to all subscribers
+    ///
+    /// May wait for a blocking subscriber, so no registry lock may be held.
+    /// The event is timestamped by the registry's clock.
+    pub(crate) async fn publish(&self, event: ServiceEvent) {
+        self.events.publish(event.with_timestamp(self.clock.system_time())).await;
+    }
+
+    /// Start a background task that periodically evicts expired services
+    ///
+    /// Every `interval` the task removes expired entries, emits a
+    /// [`ServiceEvent::Removed`] for each of them and refreshes the registry
+    /// gauges. The task runs until the returned token is cancelled.
+    pub fn start_cleanup_task(&self, interval: Duration) -> CancellationToken {
+        let token = CancellationToken::new();
+        let services = self.services.clone();
+        let events = self.events.clone();
+        let clock = Arc::clone(&self.clock);
+        let task_token = token.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = task_token.cancelled() => {
+                        debug!("Registry cleanup task stopped");
+                        break;
+                    }
+                    _ = ticker.tick() => {
+                        let removed = Self::evict_expired(&mut *services.write().await, clock.now());
+                        for service in removed {
+                            events.publish(ServiceEvent::removed(service).with_timestamp(clock.system_time())).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        token
+    }
+
+    /// Protocols that hold an active registration of `service`
+    ///
+    /// In the order the registrations were made; empty if the service is not
+    /// registered anywhere.
+    pub fn registered_protocols(&self, service: &ServiceInfo) -> Vec<ProtocolType> {
+        self.registrations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&service.id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Record that `protocol` advertises `service`
+    pub(crate) fn record_registration(&self, service: &ServiceInfo, protocol: ProtocolType) {
+        let mut registrations = self.registrations.lock().unwrap_or_else(|e| e.into_inner());
+        let protocols = registrations.entry(service.id).or_default();
+        if !protocols.contains(&protocol) {
+            protocols.push(protocol);
+        }
+    }
+
+    /// Record that `protocol` no longer advertises `service`
+    pub(crate) fn forget_registration(&self, service: &ServiceInfo, protocol: ProtocolType) {
+        let mut registrations = self.registrations.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(protocols) = registrations.get_mut(&service.id) {
+            protocols.retain(|registered| *registered != protocol);
+            if protocols.is_empty() {
+                registrations.remove(&service.id);
+            }
         }
     }
 
     /// Register a local service
     pub async fn register_local_service(&self, service: ServiceInfo, protocol: ProtocolType) -> Result<()> {
-        let entry = ServiceEntry::new_local(service, protocol);
+        let mut entry = ServiceEntry::new_local(service, protocol);
+        entry.timestamp = self.clock.now();
         let service_id = entry.service_id();
         
         let mut services = self.services.write().await;
@@ -220,24 +464,39 @@ impl ServiceRegistry {
     }
 
     /// Unregister a local service
-    pub async fn unregister_local_service(&self, service_id: &str) -> Result<()> {
+    pub async fn unregister_local_service(&self, service_id: &ServiceId) -> Result<()> {
         let mut services = self.services.write().await;
         if services.remove(service_id).is_some() {
             info!("Unregistered local service: {}", service_id);
             Ok(())
         } else {
             warn!("Attempted to unregister unknown service: {}", service_id);
-            Err(DiscoveryError::service_not_found(service_id))
+            Err(DiscoveryError::service_not_found(service_id.to_string()))
         }
     }
 
     /// Add a discovered service
     pub async fn add_discovered_service(&self, service: ServiceInfo, protocol: ProtocolType, ttl: Option<Duration>) -> Result<()> {
         let ttl = ttl.unwrap_or(self.default_ttl);
-        let entry = ServiceEntry::new_discovered(service, protocol, Some(ttl));
+        let mut entry = ServiceEntry::new_discovered(service, protocol, Some(ttl));
+        entry.timestamp = self.clock.now();
         let service_id = entry.service_id();
         
         let mut services = self.services.write().await;
+        // Rediscovery refreshes the entry but not its health
+        let event = match services.get(&service_id) {
+            Some(existing) => {
+                entry.health = existing.health;
+                entry.latency = existing.latency;
+                entry.revision = existing.revision;
+                let changes = ServiceChange::between(&existing.service, &entry.service);
+                (!changes.is_empty()).then(|| {
+                    entry.revision += 1;
+                    ServiceEvent::updated(entry.service.clone(), changes).with_protocol(protocol)
+                })
+            }
+            None => Some(ServiceEvent::new(entry.service.clone()).with_protocol(protocol)),
+        };
         
         // Check if we're at capacity
         if services.len() >= self.max_services {
@@ -251,21 +510,88 @@ impl ServiceRegistry {
         }
         
         services.insert(service_id.clone(), entry);
+        drop(services);
         debug!("Added discovered service: {}", service_id);
+        if let Some(event) = event {
+            self.publish(event).await;
+        }
         Ok(())
     }
 
+    /// Remove a discovered service whose owner said goodbye
+    ///
+    /// Subscribers get a [`ServiceEvent::Removed`]. Local services are left
+    /// alone; returns the removed service, if there was one.
+    pub async fn remove_discovered_service(&self, service_id: &ServiceId) -> Option<ServiceInfo> {
+        let mut services = self.services.write().await;
+        if services.get(service_id).is_none_or(|entry| entry.is_local) {
+            return None;
+        }
+        let entry = services.remove(service_id)?;
+        drop(services);
+        debug!("Removed discovered service: {}", service_id);
+        self.publish(ServiceEvent::removed(entry.service.clone()).with_protocol(entry.protocol)).await;
+        Some(entry.service)
+    }
+
+    /// Record the health of a service, returning its previous health
+    ///
+    /// Returns `None` if the service is not in the registry.
+    pub async fn set_health(&self, service_id: &ServiceId, health: HealthStatus) -> Option<HealthStatus> {
+        let mut services = self.services.write().await;
+        let entry = services.get_mut(service_id)?;
+        Some(std::mem::replace(&mut entry.health, health))
+    }
+
+    /// Record the round trip time measured while checking a service
+    pub async fn set_latency(&self, service_id: &ServiceId, latency: Duration) {
+        if let Some(entry) = self.services.write().await.get_mut(service_id) {
+            entry.latency = Some(latency);
+        }
+    }
+
+    /// Health of a service from its latest checks
+    pub async fn health(&self, service_id: &ServiceId) -> Option<HealthStatus> {
+        self.services.read().await.get(service_id).map(|entry| entry.health)
+    }
+
     /// Find services matching the given filter
     pub async fn find_services(&self, filter: &ServiceFilter) -> Vec<ServiceInfo> {
         let services = self.services.read().await;
+        let now = self.clock.now();
         
         services
-            .values()
-            .filter(|entry| filter.matches(entry))
+            .candidates(filter)
+            .into_iter()
+            .filter(|entry| filter.matches_at(entry, now))
             .map(|entry| entry.service.clone())
             .collect()
     }
 
+    /// Find one page of the services matching the given filter
+    ///
+    /// The registry keeps services in no particular order, so
+    /// [`SortOrder::Ranked`] sorts them by name.
+    pub async fn find_services_page(&self, filter: &ServiceFilter, options: &QueryOptions) -> Page {
+        let mut services = self.find_services(filter).await;
+        if options.sort() == SortOrder::Ranked {
+            services.sort_by_cached_key(|service| (service.name.clone(), service.service_type.to_string()));
+        }
+        options.paginate(services)
+    }
+
+    /// Find entries, with their metadata, matching the given filter
+    pub async fn find_entries(&self, filter: &ServiceFilter) -> Vec<ServiceEntry> {
+        let services = self.services.read().await;
+        let now = self.clock.now();
+        services
+            .candidates(filter)
+            .into_iter()
+            .filter(|entry| filter.matches_at(entry, now))
+            .cloned()
+            .collect()
+    }
+
     /// Get all locally registered services
     pub async fn get_local_services(&self) -> Vec<ServiceInfo> {
         let filter = ServiceFilter::new().local_only();
@@ -284,6 +610,20 @@ impl ServiceRegistry {
         self.find_services(&filter).await
     }
 
+    /// Get services with exactly this name
+    pub async fn get_services_by_name(&self, name: &str) -> Vec<ServiceInfo> {
+        self.find_services(&ServiceFilter::new().with_name(name)).await
+    }
+
+    /// Get services carrying every one of `tags`
+    pub async fn get_services_by_tags<I, S>(&self, tags: I) -> Vec<ServiceInfo>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.find_services(&ServiceFilter::new().with_tags(tags)).await
+    }
+
     /// Get services by protocol
     pub async fn get_services_by_protocol(&self, protocol: ProtocolType) -> Vec<ServiceInfo> {
         let filter = ServiceFilter::new().with_protocols(vec![protocol]);
@@ -291,32 +631,63 @@ impl ServiceRegistry {
     }
 
     /// Check if a service is registered locally
-    pub async fn is_local_service(&self, service_id: &str) -> bool {
+    pub async fn is_local_service(&self, service_id: &ServiceId) -> bool {
         let services = self.services.read().await;
         services.get(service_id).map(|entry| entry.is_local).unwrap_or(false)
     }
 
     /// Check if a service exists in the registry
-    pub async fn contains_service(&self, service_id: &str) -> bool {
+    pub async fn contains_service(&self, service_id: &ServiceId) -> bool {
         let services = self.services.read().await;
         services.contains_key(service_id)
     }
 
     /// Clean up expired services
     pub async fn cleanup_expired(&self) -> usize {
-        let mut services = self.services.write().await;
-        let initial_count = services.len();
-        
-        services.retain(|_, entry| !entry.is_expired());
-        
-        let removed_count = initial_count - services.len();
-        if removed_count > 0 {
-            debug!("Cleaned up {} expired services", removed_count);
+        let removed = Self::evict_expired(&mut *self.services.write().await, self.clock.now());
+        let removed_count = removed.len();
+        for service in removed {
+            self.publish(ServiceEvent::removed(service)).await;
         }
-        
         removed_count
     }
 
+    /// Remove expired entries, returning the removed services so that
+    /// subscribers can be notified once the lock is released
+    fn evict_expired(services: &mut ServiceIndex, now: Instant) -> Vec<ServiceInfo> {
+        let expired: Vec<ServiceId> = services
+            .iter()
+            .filter(|(_, entry)| entry.is_expired_at(now))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let removed: Vec<ServiceInfo> = expired
+            .iter()
+            .filter_map(|id| services.remove(id))
+            .map(|entry| entry.service)
+            .collect();
+
+        if !removed.is_empty() {
+            debug!("Cleaned up {} expired services", removed.len());
+        }
+        Self::update_gauges(services, removed.len());
+
+        removed
+    }
+
+    /// Publish registry gauges
+    #[cfg(feature = "metrics")]
+    fn update_gauges(services: &ServiceIndex, removed: usize) {
+        let local = services.values().filter(|entry| entry.is_local).count();
+        metrics::gauge!("registry_services_total").set(services.len() as f64);
+        metrics::gauge!("registry_local_services").set(local as f64);
+        metrics::gauge!("registry_discovered_services").set((services.len() - local) as f64);
+        metrics::counter!("registry_expired_services_total").increment(removed as u64);
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn update_gauges(_services: &ServiceIndex, _removed: usize) {}
+
     /// Get registry statistics
     pub async fn stats(&self) -> RegistryStats {
         let services = self.services.read().await;
@@ -324,6 +695,7 @@ impl ServiceRegistry {
         let mut local_count = 0;
         let mut discovered_count = 0;
         let mut expired_count = 0;
+        let now = self.clock.now();
         
         for entry in services.values() {
             if entry.is_local {
@@ -332,7 +704,7 @@ impl ServiceRegistry {
                 discovered_count += 1;
             }
             
-            if entry.is_expired() {
+            if entry.is_expired_at(now) {
                 expired_count += 1;
             }
         }
@@ -345,11 +717,103 @@ impl ServiceRegistry {
         }
     }
 
+    /// Copy the registry's current contents
+    ///
+    /// Expired entries that have not been evicted yet are left out. Entry ages
+    /// are recorded relative to [`RegistrySnapshot::taken_at`], so TTLs keep
+    /// running across an export and a later import.
+    pub async fn export_snapshot(&self) -> RegistrySnapshot {
+        // Events are published after the change they describe, so the
+        // snapshot reflects every event up to this one
+        let sequence = self.events.latest_sequence();
+        let services = self.services.read().await;
+        let now = self.clock.now();
+        let entries = services
+            .values()
+            .filter(|entry| !entry.is_expired_at(now))
+            .map(|entry| SnapshotEntry {
+                service: entry.service.clone(),
+                is_local: entry.is_local,
+                protocol: entry.protocol,
+                ttl: entry.ttl,
+                age: entry.age_at(now),
+                health: entry.health,
+                latency: entry.latency,
+                revision: entry.revision,
+            })
+            .collect();
+        RegistrySnapshot {
+            taken_at: self.clock.system_time(),
+            sequence,
+            entries,
+        }
+    }
+
+    /// Load the entries of a snapshot, returning how many were imported
+    ///
+    /// Entries replace those with the same ID. Services that were local to
+    /// the exporting registry are imported as discovered ones with the
+    /// default TTL, since this registry did not register them. Entries that
+    /// have expired since the snapshot was taken are skipped, as are entries
+    /// beyond the registry's capacity. A [`ServiceEvent::New`] is published
+    /// for each service that was not known before.
+    pub async fn import_snapshot(&self, snapshot: RegistrySnapshot) -> usize {
+        // Time spent in transit counts towards the age of every entry
+        let since_taken = self.clock.system_time()
+            .duration_since(snapshot.taken_at)
+            .unwrap_or_default();
+        let now = self.clock.now();
+
+        let mut services = self.services.write().await;
+        let mut imported = 0;
+        let mut added = Vec::new();
+        for entry in snapshot.entries {
+            let (ttl, age) = if entry.is_local {
+                (Some(self.default_ttl), Duration::ZERO)
+            } else {
+                (entry.ttl, entry.age + since_taken)
+            };
+            if ttl.is_some_and(|ttl| age > ttl) {
+                continue;
+            }
+            let imported_entry = ServiceEntry {
+                service: entry.service,
+                timestamp: now.checked_sub(age).unwrap_or(now),
+                is_local: false,
+                ttl,
+                protocol: entry.protocol,
+                health: entry.health,
+                latency: entry.latency,
+                revision: entry.revision,
+            };
+            let service_id = imported_entry.service_id();
+            let known = services.contains_key(&service_id);
+            if !known && services.len() >= self.max_services {
+                warn!("Service registry at capacity, not importing {}", service_id);
+                continue;
+            }
+            if !known {
+                added.push(imported_entry.service.clone());
+            }
+            services.insert(service_id, imported_entry);
+            imported += 1;
+        }
+        Self::update_gauges(&services, 0);
+        drop(services);
+        for service in added {
+            self.publish(ServiceEvent::new(service)).await;
+        }
+
+        info!("Imported {} services from a registry snapshot", imported);
+        imported
+    }
+
     /// Find the oldest expired service for cleanup
-    fn find_oldest_expired(&self, services: &HashMap<String, ServiceEntry>) -> Option<String> {
+    fn find_oldest_expired(&self, services: &ServiceIndex) -> Option<ServiceId> {
+        let now = self.clock.now();
         services
             .iter()
-            .filter(|(_, entry)| entry.is_expired())
+            .filter(|(_, entry)| entry.is_expired_at(now))
             .min_by_key(|(_, entry)| entry.timestamp)
             .map(|(id, _)| id.clone())
     }
@@ -368,6 +832,46 @@ pub struct RegistryStats {
     pub expired_services: usize,
 }
 
+/// Serializable copy of a registry's contents
+///
+/// Created by [`ServiceRegistry::export_snapshot`] and loaded with
+/// [`ServiceRegistry::import_snapshot`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrySnapshot {
+    /// When the snapshot was taken
+    pub taken_at: SystemTime,
+    /// Sequence number of the latest event reflected in the entries
+    ///
+    /// Changes since it may already be reflected too; replaying them is
+    /// harmless.
+    #[serde(default)]
+    pub sequence: u64,
+    /// The registry entries
+    pub entries: Vec<SnapshotEntry>,
+}
+
+/// One registry entry in a [`RegistrySnapshot`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// The service information
+    pub service: ServiceInfo,
+    /// Whether the service was registered by the exporting registry
+    pub is_local: bool,
+    /// The protocol that discovered/registered the service
+    pub protocol: ProtocolType,
+    /// Time-to-live of the entry
+    pub ttl: Option<Duration>,
+    /// Age of the entry when the snapshot was taken
+    pub age: Duration,
+    /// Result of the latest health checks
+    pub health: HealthStatus,
+    /// Round trip time of the latest successful check
+    pub latency: Option<Duration>,
+    /// Revision of the entry
+    #[serde(default)]
+    pub revision: u64,
+}
+
 impl Default for ServiceRegistry {
     fn default() -> Self {
         Self::new()
@@ -377,6 +881,7 @@ impl Default for ServiceRegistry {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
     use std::net::{IpAddr, Ipv4Addr};
     use tokio::time::{sleep, Duration};
 
@@ -410,6 +915,48 @@ mod tests {
         assert_eq!(discovered_services[0].name(), service.name());
     }
 
+    #[tokio::test]
+    async fn test_discovery_events() {
+        let clock = ManualClock::new();
+        let registry = ServiceRegistry::new().with_clock(Arc::new(clock.clone()));
+        let mut events = registry.subscribe();
+        let service = ServiceInfo::new("printer", "_ipp._tcp", 631, None).unwrap();
+
+        // Events are stamped by the registry's clock
+        registry.add_discovered_service(service.clone(), ProtocolType::Mdns, None).await.unwrap();
+        let event = events.try_recv().unwrap();
+        assert_eq!(event, ServiceEvent::new(service.clone()).with_timestamp(clock.system_time()));
+        assert_eq!(event.origin().protocol, Some(ProtocolType::Mdns));
+
+        // Rediscovery is only news if the advertisement changed
+        registry.add_discovered_service(service.clone(), ProtocolType::Mdns, None).await.unwrap();
+        assert!(events.try_recv().is_err());
+        let mut moved = service
+            .clone()
+            .with_address(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7)))
+            .with_attribute("note", "2nd floor");
+        moved.port = 632;
+        registry.add_discovered_service(moved.clone(), ProtocolType::Mdns, None).await.unwrap();
+        let changes = vec![
+            ServiceChange::Address { previous: service.address, current: moved.address },
+            ServiceChange::Port { previous: 631, current: 632 },
+            ServiceChange::Attribute { key: "note".into(), previous: None, current: Some("2nd floor".into()) },
+        ];
+        assert_eq!(
+            events.try_recv().unwrap(),
+            ServiceEvent::updated(moved.clone(), changes).with_timestamp(clock.system_time())
+        );
+
+        // Every change bumps the revision
+        let entries = registry.find_entries(&ServiceFilter::new()).await;
+        assert_eq!(entries[0].revision, 1);
+        let retuned = moved.with_attribute("note", "3rd floor");
+        registry.add_discovered_service(retuned, ProtocolType::Mdns, None).await.unwrap();
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.to_string(), format!("Updated service: {} (changed attribute note)", event.service().unwrap()));
+        assert_eq!(registry.find_entries(&ServiceFilter::new()).await[0].revision, 2);
+    }
+
     #[tokio::test]
     async fn test_service_filter() {
         let registry = ServiceRegistry::new();
@@ -439,11 +986,41 @@ mod tests {
         let local_services = registry.get_local_services().await;
         assert_eq!(local_services.len(), 1);
         assert_eq!(local_services[0].name(), "web");
+
+        // Test interface filter
+        let printer = ServiceInfo::new("printer", "_ipp._tcp", 631, None)
+            .unwrap()
+            .with_interface("eth1");
+        registry.add_discovered_service(printer.clone(), ProtocolType::Mdns, None).await.unwrap();
+        let on_eth1 = registry.find_services(&ServiceFilter::new().with_interface("eth1")).await;
+        assert_eq!(on_eth1.len(), 1);
+        assert_eq!(on_eth1[0].name(), "printer");
+
+        // Test tag filter, alone and with other criteria
+        let payments = ServiceInfo::new("ledger", "_http._tcp", 8081, None)
+            .unwrap()
+            .with_tags(["team=payments", "tier=gold"]);
+        registry.register_local_service(payments, ProtocolType::Upnp).await.unwrap();
+        let tagged = registry.get_services_by_tags(["team=payments"]).await;
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].name(), "ledger");
+        let filter = ServiceFilter::new().with_tags(["team=payments"]).with_protocols(vec![ProtocolType::Mdns]);
+        assert!(registry.find_services(&filter).await.is_empty());
+        assert!(registry.get_services_by_tags(["team=payments", "tier=silver"]).await.is_empty());
+
+        // Health survives rediscovery
+        let printer_id = printer.service_id();
+        assert_eq!(registry.set_health(&printer_id, HealthStatus::Degraded).await, Some(HealthStatus::Healthy));
+        registry.add_discovered_service(printer, ProtocolType::Mdns, None).await.unwrap();
+        assert_eq!(registry.health(&printer_id).await, Some(HealthStatus::Degraded));
+        let missing = ServiceId::new("missing", &ServiceType::new("_ipp._tcp").unwrap());
+        assert_eq!(registry.set_health(&missing, HealthStatus::Healthy).await, None);
     }
 
     #[tokio::test]
     async fn test_service_expiration() {
-        let registry = ServiceRegistry::new();
+        let clock = ManualClock::new();
+        let registry = ServiceRegistry::new().with_clock(Arc::new(clock.clone()));
         
         let service = ServiceInfo::new("temp", "_http._tcp", 8080, None)
             .unwrap()
@@ -457,7 +1034,7 @@ mod tests {
         assert_eq!(services.len(), 1);
         
         // Wait for expiration
-        sleep(Duration::from_millis(100)).await;
+        clock.advance(Duration::from_millis(100));
         
         // Should not find expired service
         let services = registry.get_discovered_services().await;
@@ -467,4 +1044,109 @@ mod tests {
         let removed = registry.cleanup_expired().await;
         assert_eq!(removed, 1);
     }
+
+    #[tokio::test]
+    async fn test_cleanup_task_emits_removed_events() {
+        let registry = ServiceRegistry::new();
+        let service = ServiceInfo::new("short-lived", "_http._tcp", 8080, None).unwrap();
+        registry.add_discovered_service(service.clone(), ProtocolType::Mdns, Some(Duration::from_millis(20))).await.unwrap();
+        let mut events = registry.subscribe();
+
+        let token = registry.start_cleanup_task(Duration::from_millis(10));
+
+        let event = tokio::time::timeout(Duration::from_secs(1), events.recv())
+            .await
+            .expect("cleanup task should emit an event")
+            .unwrap();
+        assert_eq!(event, ServiceEvent::removed(service).with_timestamp(event.origin().timestamp));
+        assert_eq!(registry.stats().await.total_services, 0);
+
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_blocking_subscriber_does_not_hold_registry_lock() {
+        let registry = Arc::new(ServiceRegistry::new());
+        let config = EventChannelConfig::new(1, crate::events::OverflowPolicy::Block);
+        let mut events = registry.subscribe_bounded(config);
+        let first = ServiceInfo::new("first", "_http._tcp", 8080, None).unwrap();
+        let second = ServiceInfo::new("second", "_http._tcp", 8081, None).unwrap();
+        registry.add_discovered_service(first, ProtocolType::Mdns, None).await.unwrap();
+
+        let adding = tokio::spawn({
+            let registry = Arc::clone(&registry);
+            async move { registry.add_discovered_service(second, ProtocolType::Mdns, None).await }
+        });
+        sleep(Duration::from_millis(50)).await;
+        assert!(!adding.is_finished());
+        // The entry is in while its event waits for the subscriber
+        assert_eq!(registry.stats().await.total_services, 2);
+
+        assert!(events.recv().await.is_some());
+        adding.await.unwrap().unwrap();
+        assert!(events.recv().await.is_some());
+        assert_eq!(registry.dropped_events(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_entries_expire_in_paused_time() {
+        let registry = ServiceRegistry::new();
+        let service = ServiceInfo::new("hourly", "_http._tcp", 8080, None).unwrap();
+        registry.add_discovered_service(service, ProtocolType::Mdns, Some(Duration::from_secs(3600))).await.unwrap();
+
+        tokio::time::advance(Duration::from_secs(3599)).await;
+        assert_eq!(registry.cleanup_expired().await, 0);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(registry.cleanup_expired().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_goodbye_removes_only_discovered_services() {
+        let registry = ServiceRegistry::new();
+        let remote = ServiceInfo::new("remote", "_http._tcp", 8080, None).unwrap();
+        let own = ServiceInfo::new("own", "_http._tcp", 8081, None).unwrap();
+        registry.add_discovered_service(remote.clone(), ProtocolType::Mdns, None).await.unwrap();
+        registry.register_local_service(own.clone(), ProtocolType::Mdns).await.unwrap();
+        let mut events = registry.subscribe();
+
+        let removed = registry.remove_discovered_service(&remote.service_id()).await;
+        assert_eq!(removed, Some(remote.clone()));
+        let event = events.try_recv().unwrap();
+        assert_eq!(event, ServiceEvent::removed(remote).with_timestamp(event.origin().timestamp));
+        assert_eq!(registry.remove_discovered_service(&own.service_id()).await, None);
+        assert!(registry.is_local_service(&own.service_id()).await);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let source = ServiceRegistry::new();
+        let printer = ServiceInfo::new("printer", "_ipp._tcp", 631, None).unwrap();
+        let own = ServiceInfo::new("own", "_http._tcp", 8080, None).unwrap();
+        let stale = ServiceInfo::new("stale", "_http._tcp", 8081, None).unwrap();
+        source.add_discovered_service(printer.clone(), ProtocolType::Upnp, Some(Duration::from_secs(60))).await.unwrap();
+        source.register_local_service(own.clone(), ProtocolType::Mdns).await.unwrap();
+        source.add_discovered_service(stale, ProtocolType::Mdns, Some(Duration::from_millis(1))).await.unwrap();
+        source.set_health(&printer.service_id(), HealthStatus::Degraded).await;
+        sleep(Duration::from_millis(10)).await;
+
+        let snapshot = source.export_snapshot().await;
+        assert_eq!(snapshot.entries.len(), 2);
+        assert_eq!(snapshot.sequence, source.latest_sequence());
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: RegistrySnapshot = serde_json::from_str(&json).unwrap();
+
+        let target = ServiceRegistry::new();
+        let mut events = target.subscribe();
+        assert_eq!(target.import_snapshot(snapshot).await, 2);
+        assert!(matches!(events.try_recv().unwrap(), ServiceEvent::New { .. }));
+
+        let entries = target.find_entries(&ServiceFilter::new()).await;
+        let imported = entries.iter().find(|entry| entry.service.name == "printer").unwrap();
+        assert_eq!(imported.protocol, ProtocolType::Upnp);
+        assert_eq!(imported.health, HealthStatus::Degraded);
+        assert!(imported.timestamp.elapsed() >= Duration::from_millis(10));
+        // Someone else's local service is only discovered here
+        assert!(target.get_local_services().await.is_empty());
+        assert!(target.contains_service(&own.service_id()).await);
+    }
 }
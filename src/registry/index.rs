@@ -0,0 +1,191 @@
+//! Registry storage with secondary indexes
+//!
+//! Entries are keyed by service ID. Indexes from service type, protocol,
+//! name and tag to IDs are kept in step on every insert and removal, so
+//! queries only visit the services the most selective of their indexed
+//! criteria allows.
+
+use super::{ServiceEntry, ServiceFilter};
+use crate::{
+    service::ServiceId,
+    types::{ProtocolType, ServiceType},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// IDs of the entries with each value of an indexed field
+type Index<K> = HashMap<K, HashSet<ServiceId>>;
+
+fn index<K: Hash + Eq>(index: &mut Index<K>, key: K, service_id: &ServiceId) {
+    index.entry(key).or_default().insert(service_id.clone());
+}
+
+fn unindex<K: Hash + Eq>(index: &mut Index<K>, key: &K, service_id: &ServiceId) {
+    if let Some(ids) = index.get_mut(key) {
+        ids.remove(service_id);
+        if ids.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+/// Registry entries and their indexes
+#[derive(Debug, Default)]
+pub(super) struct ServiceIndex {
+    entries: HashMap<ServiceId, ServiceEntry>,
+    by_type: Index<ServiceType>,
+    by_protocol: Index<ProtocolType>,
+    by_name: Index<String>,
+    by_tag: Index<String>,
+}
+
+impl ServiceIndex {
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(super) fn get(&self, service_id: &ServiceId) -> Option<&ServiceEntry> {
+        self.entries.get(service_id)
+    }
+
+    /// Entry for changes that leave its service untouched, such as health
+    pub(super) fn get_mut(&mut self, service_id: &ServiceId) -> Option<&mut ServiceEntry> {
+        self.entries.get_mut(service_id)
+    }
+
+    pub(super) fn contains_key(&self, service_id: &ServiceId) -> bool {
+        self.entries.contains_key(service_id)
+    }
+
+    pub(super) fn values(&self) -> impl Iterator<Item = &ServiceEntry> {
+        self.entries.values()
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = (&ServiceId, &ServiceEntry)> {
+        self.entries.iter()
+    }
+
+    /// Add or replace an entry, returning the one it replaced
+    pub(super) fn insert(&mut self, service_id: ServiceId, entry: ServiceEntry) -> Option<ServiceEntry> {
+        let previous = self.remove(&service_id);
+        index(&mut self.by_type, entry.service.service_type.clone(), &service_id);
+        index(&mut self.by_protocol, entry.protocol, &service_id);
+        index(&mut self.by_name, entry.service.name.clone(), &service_id);
+        for tag in &entry.service.tags {
+            index(&mut self.by_tag, tag.clone(), &service_id);
+        }
+        self.entries.insert(service_id, entry);
+        previous
+    }
+
+    pub(super) fn remove(&mut self, service_id: &ServiceId) -> Option<ServiceEntry> {
+        let entry = self.entries.remove(service_id)?;
+        unindex(&mut self.by_type, &entry.service.service_type, service_id);
+        unindex(&mut self.by_protocol, &entry.protocol, service_id);
+        unindex(&mut self.by_name, &entry.service.name, service_id);
+        for tag in &entry.service.tags {
+            unindex(&mut self.by_tag, tag, service_id);
+        }
+        Some(entry)
+    }
+
+    /// Entries that can match `filter`, narrowed down with the indexes
+    ///
+    /// Candidates still have to be checked with [`ServiceFilter::matches`].
+    pub(super) fn candidates(&self, filter: &ServiceFilter) -> Vec<&ServiceEntry> {
+        // Each indexed criterion admits the union of some index sets
+        let mut criteria: Vec<Vec<&HashSet<ServiceId>>> = Vec::new();
+        if let Some(types) = &filter.service_types {
+            criteria.push(types.iter().filter_map(|service_type| self.by_type.get(service_type)).collect());
+        }
+        if let Some(protocols) = &filter.protocols {
+            criteria.push(protocols.iter().filter_map(|protocol| self.by_protocol.get(protocol)).collect());
+        }
+        if let Some(name) = &filter.name {
+            criteria.push(self.by_name.get(name).into_iter().collect());
+        }
+        for tag in &filter.tags {
+            criteria.push(self.by_tag.get(tag).into_iter().collect());
+        }
+
+        let narrowest = criteria
+            .into_iter()
+            .min_by_key(|sets| sets.iter().map(|ids| ids.len()).sum::<usize>());
+        match narrowest {
+            Some(sets) => sets
+                .into_iter()
+                .flatten()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .filter_map(|id| self.entries.get(id))
+                .collect(),
+            None => self.entries.values().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{service::ServiceInfo, types::ProtocolType};
+
+    fn entry(name: &str, tags: &[&str]) -> (ServiceId, ServiceEntry) {
+        let service = ServiceInfo::new(name, "_http._tcp", 8080, None)
+            .unwrap()
+            .with_tags(tags.iter().copied());
+        let entry = ServiceEntry::new_local(service, ProtocolType::Mdns);
+        (entry.service_id(), entry)
+    }
+
+    #[test]
+    fn test_tag_index_follows_inserts_and_removals() {
+        let mut index = ServiceIndex::default();
+        let (a, entry_a) = entry("a", &["team=payments", "tier=gold"]);
+        let (b, entry_b) = entry("b", &["team=payments"]);
+        index.insert(a.clone(), entry_a);
+        index.insert(b.clone(), entry_b);
+
+        let names = |index: &ServiceIndex, filter: &ServiceFilter| {
+            let mut names: Vec<_> = index.candidates(filter).iter().map(|entry| entry.service.name.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(&index, &ServiceFilter::new().with_tags(["team=payments"])), ["a", "b"]);
+        assert_eq!(names(&index, &ServiceFilter::new().with_tags(["team=payments", "tier=gold"])), ["a"]);
+        assert!(names(&index, &ServiceFilter::new().with_tags(["team=search"])).is_empty());
+
+        // Re-inserting with other tags moves the entry between index sets
+        let (_, retagged) = entry("a", &["team=search"]);
+        index.insert(a.clone(), retagged);
+        assert_eq!(names(&index, &ServiceFilter::new().with_tags(["team=payments"])), ["b"]);
+        assert_eq!(names(&index, &ServiceFilter::new().with_tags(["team=search"])), ["a"]);
+
+        index.remove(&a);
+        index.remove(&b);
+        assert!(index.by_tag.is_empty());
+        assert!(index.by_type.is_empty() && index.by_protocol.is_empty() && index.by_name.is_empty());
+    }
+
+    #[test]
+    fn test_narrowest_criterion_picks_candidates() {
+        let mut index = ServiceIndex::default();
+        for i in 0..20 {
+            let (id, entry) = entry(&format!("web-{i}"), &[]);
+            index.insert(id, entry);
+        }
+        let printer = ServiceInfo::new("printer", "_ipp._tcp", 631, None).unwrap();
+        let printer = ServiceEntry::new_local(printer, ProtocolType::Mdns);
+        index.insert(printer.service_id(), printer);
+
+        let filter = ServiceFilter::new()
+            .with_protocols(vec![ProtocolType::Mdns])
+            .with_service_types(vec![ServiceType::new("_ipp._tcp").unwrap()]);
+        assert_eq!(index.candidates(&filter).len(), 1);
+        let filter = ServiceFilter::new().with_name("web-3");
+        assert_eq!(index.candidates(&filter).len(), 1);
+        assert!(index.candidates(&ServiceFilter::new().with_name("nobody")).is_empty());
+        assert_eq!(index.candidates(&ServiceFilter::new().local_only()).len(), 21);
+    }
+}
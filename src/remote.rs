@@ -0,0 +1,343 @@
+//! Client for a remote discovery agent
+//!
+//! Where multicast is unavailable — browsers, containers on bridge
+//! networks — discovery can be delegated to an agent running this crate
+//! natively on the LAN. [`RemoteDiscovery`] talks to such an agent over
+//! HTTP/JSON and hands back the same [`ServiceInfo`] and [`ServiceEvent`]
+//! types the local API uses.
+//!
+//! The agent API, all paths relative to the agent URL:
+//!
+//! | Request | Meaning |
+//! |---|---|
+//! | `GET /v1/services[?type=_http._tcp]` | JSON array of services |
+//! | `POST /v1/services` | Register the JSON service in the body |
+//! | `DELETE /v1/services/{id}` | Unregister the service with that ID |
+//! | `GET /v1/events` | Server-sent events, one JSON [`ServiceEvent`] per `data:` line |
+//! | `GET /v1/changes[?since=42]` | JSON [`Changes`] after a sequence number |
+//! | `GET /v1/snapshot` | JSON [`RegistrySnapshot`] of the agent's services |
+//!
+//! Only plain `http://` agents are supported. The client runs on tokio; the
+//! rest of the crate does not build for `wasm32` targets yet.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    events::Changes,
+    registry::RegistrySnapshot,
+    service::{ServiceEvent, ServiceInfo},
+    types::ServiceType,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use futures::Stream;
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Incoming, client::conn::http1, header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::debug;
+use url::{Host, Url};
+
+/// Version prefix of the agent API
+pub const API_PREFIX: &str = "/v1";
+
+/// Discovery through a remote agent
+#[derive(Debug, Clone)]
+pub struct RemoteDiscovery {
+    host: String,
+    port: u16,
+    authority: String,
+    base_path: String,
+    timeout: Duration,
+}
+
+impl RemoteDiscovery {
+    /// Create a client for the agent at `url`, e.g. `http://agent.lan:8380`
+    pub fn new(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url)
+            .map_err(|e| DiscoveryError::configuration(format!("Invalid agent URL {url}: {e}")))?;
+        if parsed.scheme() != "http" {
+            return Err(DiscoveryError::configuration(format!(
+                "Unsupported agent URL scheme '{}', expected http",
+                parsed.scheme()
+            )));
+        }
+        let host = match parsed.host() {
+            Some(Host::Domain(domain)) => domain.to_string(),
+            Some(Host::Ipv4(ip)) => ip.to_string(),
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            None => return Err(DiscoveryError::configuration(format!("Agent URL {url} has no host"))),
+        };
+        Ok(Self {
+            host,
+            port: parsed.port_or_known_default().unwrap_or(80),
+            authority: parsed.authority().to_string(),
+            base_path: parsed.path().trim_end_matches('/').to_string(),
+            timeout: Duration::from_secs(10),
+        })
+    }
+
+    /// Set the timeout of each request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// List the services the agent knows, optionally only of one type
+    pub async fn discover_services(&self, service_type: Option<&ServiceType>) -> Result<Vec<ServiceInfo>> {
+        let path = match service_type {
+            Some(service_type) => format!("/services?type={}", service_type),
+            None => "/services".to_string(),
+        };
+        let response = self.send(Method::GET, &path, None).await?;
+        decode(response, self.timeout).await
+    }
+
+    /// Have the agent register a service
+    pub async fn register_service(&self, service: &ServiceInfo) -> Result<()> {
+        let body = serde_json::to_vec(service)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Cannot encode service: {e}")))?;
+        let response = self.send(Method::POST, "/services", Some(body)).await?;
+        expect_success(response, self.timeout).await
+    }
+
+    /// Have the agent unregister a service it registered for this client
+    pub async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        let response = self.send(Method::DELETE, &format!("/services/{}", service.id), None).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(DiscoveryError::service_not_found(format!(
+                "Agent has no registered service {}",
+                service.name
+            )));
+        }
+        expect_success(response, self.timeout).await
+    }
+
+    /// The agent's events after `sequence`
+    ///
+    /// Poll with the [`Changes::latest`] of the previous answer to keep a
+    /// copy of the agent's state; see
+    /// [`ServiceRegistry::changes_since`](crate::registry::ServiceRegistry::changes_since).
+    pub async fn changes_since(&self, sequence: u64) -> Result<Changes> {
+        let response = self.send(Method::GET, &format!("/changes?since={sequence}"), None).await?;
+        decode(response, self.timeout).await
+    }
+
+    /// The agent's services, with the sequence number to follow changes from
+    pub async fn snapshot(&self) -> Result<RegistrySnapshot> {
+        let response = self.send(Method::GET, "/snapshot", None).await?;
+        decode(response, self.timeout).await
+    }
+
+    /// Follow the agent's service events
+    ///
+    /// The stream ends when the agent closes the connection.
+    pub async fn events(&self) -> Result<impl Stream<Item = ServiceEvent> + Send + 'static> {
+        let response = self.send(Method::GET, "/events", None).await?;
+        if !response.status().is_success() {
+            return Err(status_error(response, self.timeout).await);
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut body = response.into_body();
+        tokio::spawn(async move {
+            let mut buffer = BytesMut::new();
+            while let Some(frame) = body.frame().await {
+                let data = match frame {
+                    Ok(frame) => match frame.into_data() {
+                        Ok(data) => data,
+                        Err(_) => continue,
+                    },
+                    Err(e) => {
+                        debug!("Agent event stream failed: {}", e);
+                        break;
+                    }
+                };
+                buffer.extend_from_slice(&data);
+                while let Some(event) = next_event(&mut buffer) {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(UnboundedReceiverStream::new(receiver))
+    }
+
+    /// Send a request and wait for the response head
+    async fn send(&self, method: Method, path: &str, body: Option<Vec<u8>>) -> Result<Response<Incoming>> {
+        let uri = format!("{}{}{}", self.base_path, API_PREFIX, path);
+        let mut request = Request::builder()
+            .method(method)
+            .uri(&uri)
+            .header(header::HOST, &self.authority);
+        if body.is_some() {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body.unwrap_or_default())))
+            .map_err(|e| DiscoveryError::configuration(format!("Invalid agent request {uri}: {e}")))?;
+
+        let exchange = async {
+            let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            let (mut sender, connection) = http1::handshake(TokioIo::new(stream))
+                .await
+                .map_err(|e| DiscoveryError::network(format!("HTTP handshake with agent failed: {e}")))?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    debug!("Agent connection closed: {}", e);
+                }
+            });
+            sender
+                .send_request(request)
+                .await
+                .map_err(|e| DiscoveryError::network(format!("Agent request failed: {e}")))
+        };
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| DiscoveryError::timeout(format!("Agent did not answer {uri} within {:?}", self.timeout)))?
+    }
+}
+
+async fn read_body(response: Response<Incoming>, timeout: Duration) -> Result<Bytes> {
+    let collect = response.into_body().collect();
+    match tokio::time::timeout(timeout, collect).await {
+        Ok(Ok(body)) => Ok(body.to_bytes()),
+        Ok(Err(e)) => Err(DiscoveryError::network(format!("Failed to read agent response: {e}"))),
+        Err(_) => Err(DiscoveryError::timeout("Agent response body timed out")),
+    }
+}
+
+async fn status_error(response: Response<Incoming>, timeout: Duration) -> DiscoveryError {
+    let status = response.status();
+    let body = read_body(response, timeout).await.unwrap_or_default();
+    DiscoveryError::network(format!("Agent answered {}: {}", status, String::from_utf8_lossy(&body).trim()))
+}
+
+async fn expect_success(response: Response<Incoming>, timeout: Duration) -> Result<()> {
+    if !response.status().is_success() {
+        return Err(status_error(response, timeout).await);
+    }
+    Ok(())
+}
+
+async fn decode<T: DeserializeOwned>(response: Response<Incoming>, timeout: Duration) -> Result<T> {
+    if !response.status().is_success() {
+        return Err(status_error(response, timeout).await);
+    }
+    let body = read_body(response, timeout).await?;
+    serde_json::from_slice(&body)
+        .map_err(|e| DiscoveryError::invalid_data(format!("Invalid agent response: {e}")))
+}
+
+/// Take the next complete server-sent event off `buffer`
+///
+/// Events without JSON data (comments, keep-alives) are skipped.
+fn next_event(buffer: &mut BytesMut) -> Option<ServiceEvent> {
+    loop {
+        let end = buffer.windows(2).position(|window| window == b"\n\n")?;
+        let block = buffer.split_to(end);
+        buffer.advance(2);
+        let data: String = String::from_utf8_lossy(&block)
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(str::trim_start)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if data.is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&data) {
+            Ok(event) => return Some(event),
+            Err(e) => debug!("Skipping malformed agent event: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use hyper::{server::conn::http1 as server_http1, service::service_fn};
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+    use tokio::net::TcpListener;
+
+    /// Agent answering from canned services, recording registrations
+    async fn agent(services: Vec<ServiceInfo>) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let services = services.clone();
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request: Request<Incoming>| {
+                        let services = services.clone();
+                        let log = log.clone();
+                        async move {
+                            let line = format!("{} {}", request.method(), request.uri());
+                            let body = request.into_body().collect().await.unwrap().to_bytes();
+                            log.lock().unwrap().push(format!("{} {}", line, String::from_utf8_lossy(&body)).trim().to_string());
+                            let (status, body) = match line.as_str() {
+                                "GET /agent/v1/services?type=_http._tcp" => {
+                                    (StatusCode::OK, serde_json::to_string(&services).unwrap())
+                                }
+                                "POST /agent/v1/services" => (StatusCode::CREATED, String::new()),
+                                "GET /agent/v1/events" => {
+                                    let mut events = ": keep-alive\n\n".to_string();
+                                    for service in &services {
+                                        let event = serde_json::to_string(&ServiceEvent::new(service.clone())).unwrap();
+                                        events.push_str(&format!("event: service\ndata: {event}\n\n"));
+                                    }
+                                    (StatusCode::OK, events)
+                                }
+                                _ => (StatusCode::NOT_FOUND, "unknown service".to_string()),
+                            };
+                            let mut response = Response::new(Full::new(Bytes::from(body)));
+                            *response.status_mut() = status;
+                            Ok::<_, Infallible>(response)
+                        }
+                    });
+                    let _ = server_http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn test_remote_discovery() {
+        let service = ServiceInfo::new("web", "_http._tcp", 8080, Some(vec![("path", "/")])).unwrap();
+        let (addr, requests) = agent(vec![service.clone()]).await;
+        let remote = RemoteDiscovery::new(&format!("http://{addr}/agent/")).unwrap();
+
+        let services = remote.discover_services(Some(&ServiceType::new("_http._tcp").unwrap())).await.unwrap();
+        assert_eq!(services, vec![service.clone()]);
+
+        remote.register_service(&service).await.unwrap();
+        assert!(requests.lock().unwrap().last().unwrap().starts_with("POST /agent/v1/services {"));
+
+        let error = remote.unregister_service(&service).await.unwrap_err();
+        assert!(matches!(error, DiscoveryError::ServiceNotFound(_)), "{error}");
+
+        let events: Vec<_> = remote.events().await.unwrap().collect().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0], ServiceEvent::new(service).with_timestamp(events[0].origin().timestamp));
+    }
+
+    #[test]
+    fn test_agent_url_validation() {
+        assert!(RemoteDiscovery::new("https://agent.lan").is_err());
+        assert!(RemoteDiscovery::new("not a url").is_err());
+        let remote = RemoteDiscovery::new("http://[::1]:8380").unwrap();
+        assert_eq!((remote.host.as_str(), remote.port), ("::1", 8380));
+    }
+}
@@ -0,0 +1,335 @@
+//! Inventory reports of discovered services
+//!
+//! A [`ServiceReport`] renders services as JSON, CSV or an aligned text
+//! table, so command line tools and scheduled jobs can produce inventories
+//! without formatting them by hand.
+//!
+//! ```
+//! use auto_discovery::{report::{ReportFormat, ServiceReport}, ServiceInfo};
+//!
+//! # fn main() -> auto_discovery::Result<()> {
+//! let printer = ServiceInfo::new("Office Printer", "_ipp._tcp", 631, Some(vec![("rp", "ipp/print")]))?;
+//! let report = ServiceReport::from_services(&[printer]);
+//! println!("{}", report.render(ReportFormat::Table)?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{DiscoveryError, Result},
+    health::HealthStatus,
+    registry::{ServiceEntry, ServiceFilter, ServiceRegistry},
+    service::ServiceInfo,
+    types::ProtocolType,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, net::IpAddr, str::FromStr};
+
+/// Output format of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// Pretty-printed JSON array
+    Json,
+    /// Comma-separated values with a header row
+    Csv,
+    /// Aligned columns for reading in a terminal
+    #[default]
+    Table,
+}
+
+impl FromStr for ReportFormat {
+    type Err = DiscoveryError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "table" | "text" => Ok(Self::Table),
+            other => Err(DiscoveryError::configuration(format!(
+                "Unknown report format '{other}', expected json, csv or table"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Table => "table",
+        })
+    }
+}
+
+/// One service in a report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReportRow {
+    /// Instance name
+    pub name: String,
+    /// Service type
+    pub service_type: String,
+    /// Address the service is reachable at
+    pub address: IpAddr,
+    /// Port
+    pub port: u16,
+    /// Advertised host name
+    pub host: Option<String>,
+    /// Protocol the service was found or registered with
+    pub protocol: ProtocolType,
+    /// Interface the service was seen on
+    pub interface: Option<String>,
+    /// Whether the service was registered locally rather than discovered,
+    /// when known
+    pub local: Option<bool>,
+    /// Latest health, when known
+    pub health: Option<HealthStatus>,
+    /// Advertised attributes, sorted by key
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl ReportRow {
+    fn from_service(service: &ServiceInfo) -> Self {
+        Self {
+            name: service.name.clone(),
+            service_type: service.service_type.to_string(),
+            address: service.address,
+            port: service.port,
+            host: service.host.clone(),
+            protocol: service.protocol_type,
+            interface: service.interface.clone(),
+            local: None,
+            health: None,
+            attributes: service.attributes.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+        }
+    }
+
+    fn from_entry(entry: &ServiceEntry) -> Self {
+        Self {
+            protocol: entry.protocol,
+            local: Some(entry.is_local),
+            health: Some(entry.health),
+            ..Self::from_service(&entry.service)
+        }
+    }
+
+    /// Where the service came from, as shown in CSV and tables
+    fn source(&self) -> &'static str {
+        match self.local {
+            Some(true) => "local",
+            Some(false) => "discovered",
+            None => "",
+        }
+    }
+
+    fn health_label(&self) -> &'static str {
+        match self.health {
+            Some(HealthStatus::Healthy) => "healthy",
+            Some(HealthStatus::Degraded) => "degraded",
+            Some(HealthStatus::Unhealthy) => "unhealthy",
+            None => "",
+        }
+    }
+
+    /// Attributes as `key=value` pairs separated by `;`
+    fn attribute_list(&self) -> String {
+        self.attributes
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    fn cells(&self) -> [String; 10] {
+        [
+            self.name.clone(),
+            self.service_type.clone(),
+            self.address.to_string(),
+            self.port.to_string(),
+            self.host.clone().unwrap_or_default(),
+            self.protocol.to_string(),
+            self.interface.clone().unwrap_or_default(),
+            self.source().to_string(),
+            self.health_label().to_string(),
+            self.attribute_list(),
+        ]
+    }
+}
+
+/// Column headers shared by CSV and tables
+const COLUMNS: [&str; 10] = [
+    "name",
+    "service_type",
+    "address",
+    "port",
+    "host",
+    "protocol",
+    "interface",
+    "source",
+    "health",
+    "attributes",
+];
+
+/// A set of services ready to be rendered
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct ServiceReport {
+    rows: Vec<ReportRow>,
+}
+
+impl ServiceReport {
+    /// Report on the given services
+    pub fn from_services(services: &[ServiceInfo]) -> Self {
+        Self::from_rows(services.iter().map(ReportRow::from_service).collect())
+    }
+
+    /// Report on everything in a registry, with source and health
+    pub async fn from_registry(registry: &ServiceRegistry) -> Self {
+        let entries = registry.find_entries(&ServiceFilter::new()).await;
+        Self::from_rows(entries.iter().map(ReportRow::from_entry).collect())
+    }
+
+    /// Rows are ordered by service type, then name, so reports diff cleanly
+    fn from_rows(mut rows: Vec<ReportRow>) -> Self {
+        rows.sort_by(|a, b| (&a.service_type, &a.name, a.port).cmp(&(&b.service_type, &b.name, b.port)));
+        Self { rows }
+    }
+
+    /// The rows of the report
+    pub fn rows(&self) -> &[ReportRow] {
+        &self.rows
+    }
+
+    /// Render the report in `format`
+    pub fn render(&self, format: ReportFormat) -> Result<String> {
+        match format {
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Csv => Ok(self.to_csv()),
+            ReportFormat::Table => Ok(self.to_table()),
+        }
+    }
+
+    /// Pretty-printed JSON array of the rows
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Cannot serialize report: {e}")))
+    }
+
+    /// CSV with a header row, quoted as described in RFC 4180
+    pub fn to_csv(&self) -> String {
+        let mut out = COLUMNS.join(",");
+        out.push_str("\r\n");
+        for row in &self.rows {
+            let cells: Vec<String> = row.cells().iter().map(|cell| csv_field(cell)).collect();
+            out.push_str(&cells.join(","));
+            out.push_str("\r\n");
+        }
+        out
+    }
+
+    /// Aligned text columns with a header row
+    ///
+    /// Columns that are empty in every row, such as source and health for
+    /// services not taken from a registry, are left out.
+    pub fn to_table(&self) -> String {
+        let rows: Vec<[String; 10]> = self.rows.iter().map(ReportRow::cells).collect();
+        let shown: Vec<usize> = (0..COLUMNS.len())
+            .filter(|&column| column == 0 || rows.iter().any(|cells| !cells[column].is_empty()))
+            .collect();
+        let widths: Vec<usize> = shown
+            .iter()
+            .map(|&column| {
+                rows.iter()
+                    .map(|cells| cells[column].chars().count())
+                    .chain([COLUMNS[column].len()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let line = |cells: Vec<&str>| {
+            let padded: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect();
+            format!("{}\n", padded.join("  ").trim_end())
+        };
+        let header: Vec<String> = shown.iter().map(|&column| COLUMNS[column].to_uppercase()).collect();
+        let mut out = line(header.iter().map(String::as_str).collect());
+        for cells in &rows {
+            out.push_str(&line(shown.iter().map(|&column| cells[column].as_str()).collect()));
+        }
+        out
+    }
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn services() -> Vec<ServiceInfo> {
+        vec![
+            ServiceInfo::new("Web, Main", "_http._tcp", 80, Some(vec![("path", "/"), ("note", "say \"hi\"")]))
+                .unwrap()
+                .with_protocol_type(ProtocolType::Upnp),
+            ServiceInfo::new("Office Printer", "_ipp._tcp", 631, None).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_csv_quotes_and_orders_rows() {
+        let csv = ServiceReport::from_services(&services()).to_csv();
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert!(lines[1].starts_with("\"Web, Main\",_http._tcp,127.0.0.1,80,,UPnP,"));
+        assert!(lines[1].ends_with(",\"note=say \"\"hi\"\";path=/\""));
+        assert!(lines[2].starts_with("Office Printer,_ipp._tcp"));
+    }
+
+    #[test]
+    fn test_json_and_table() {
+        let report = ServiceReport::from_services(&services());
+        let json: serde_json::Value = serde_json::from_str(&report.render(ReportFormat::Json).unwrap()).unwrap();
+        assert_eq!(json[0]["protocol"], "Upnp");
+        assert_eq!(json[0]["attributes"]["path"], "/");
+
+        let table = report.to_table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("NAME            SERVICE_TYPE"));
+        assert!(!lines[0].contains("HEALTH"));
+        assert!(lines[2].starts_with("Office Printer  _ipp._tcp"));
+    }
+
+    #[tokio::test]
+    async fn test_registry_report_includes_source_and_health() {
+        let registry = ServiceRegistry::new();
+        let [web, printer] = services().try_into().unwrap();
+        registry.register_local_service(web, ProtocolType::Upnp).await.unwrap();
+        registry
+            .add_discovered_service(printer, ProtocolType::Mdns, Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+
+        let report = ServiceReport::from_registry(&registry).await;
+        let sources: Vec<Option<bool>> = report.rows().iter().map(|row| row.local).collect();
+        assert_eq!(sources, [Some(true), Some(false)]);
+        assert!(report.to_table().lines().nth(2).unwrap().contains("discovered  healthy"));
+    }
+
+    #[test]
+    fn test_format_parsing() {
+        assert_eq!("CSV".parse::<ReportFormat>().unwrap(), ReportFormat::Csv);
+        assert!("yaml".parse::<ReportFormat>().is_err());
+    }
+}
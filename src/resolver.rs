@@ -0,0 +1,325 @@
+//! Cached hostname resolution
+//!
+//! Health checks run every few seconds against every discovered service, and
+//! DNS-SD resolves the SRV target of each instance it browses. Without a cache
+//! each of them asks the resolver again for a host it looked up moments ago.
+//! The [`ResolutionCache`] keeps hostname→address answers for as long as their
+//! records live, clamped to [`ResolverConfig::min_ttl`] and
+//! [`ResolverConfig::max_ttl`], and remembers names that do not exist
+//! (NXDOMAIN) for [`ResolverConfig::negative_ttl`] so a missing host is not
+//! queried on every check either.
+//!
+//! The cache of a [`ServiceDiscovery`](crate::ServiceDiscovery) is held by its
+//! [`ServiceRegistry`](crate::registry::ServiceRegistry) and shared by DNS-SD
+//! and service verification.
+
+use crate::error::{DiscoveryError, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// How hostname resolutions are cached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResolverConfig {
+    /// Whether answers are cached at all
+    pub enabled: bool,
+    /// Shortest time an answer is kept, even if its records say less
+    pub min_ttl: Duration,
+    /// Longest time an answer is kept, even if its records say more
+    pub max_ttl: Duration,
+    /// How long a name that does not exist is remembered
+    ///
+    /// DNS answers that carry an SOA record are kept for its negative TTL
+    /// when that is shorter (RFC 2308).
+    pub negative_ttl: Duration,
+    /// How long answers of the system resolver are kept, as it does not
+    /// report record TTLs
+    pub system_ttl: Duration,
+    /// Largest number of hostnames cached
+    pub max_entries: usize,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_ttl: Duration::from_secs(5),
+            max_ttl: Duration::from_secs(3600),
+            negative_ttl: Duration::from_secs(30),
+            system_ttl: Duration::from_secs(60),
+            max_entries: 1024,
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Create a resolver configuration with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        if self.min_ttl > self.max_ttl {
+            return Err(DiscoveryError::configuration(format!(
+                "Resolver min_ttl {:?} exceeds max_ttl {:?}",
+                self.min_ttl, self.max_ttl
+            )));
+        }
+        if self.enabled && self.max_entries == 0 {
+            return Err(DiscoveryError::configuration("Resolver cache needs room for at least one entry"));
+        }
+        Ok(())
+    }
+}
+
+/// A cached answer for a hostname
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The host has these addresses
+    Found(Vec<IpAddr>),
+    /// The host does not exist or has no addresses
+    NotFound,
+}
+
+/// Counters of cache lookups
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolutionStats {
+    /// Lookups answered with addresses from the cache
+    pub hits: u64,
+    /// Lookups answered from the cache with a remembered NXDOMAIN
+    pub negative_hits: u64,
+    /// Lookups that had to go to the resolver
+    pub misses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    resolution: Resolution,
+    expires: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    stats: ResolutionStats,
+}
+
+/// Cache of hostname→address resolutions with negative caching
+#[derive(Debug, Default)]
+pub struct ResolutionCache {
+    config: ResolverConfig,
+    state: Mutex<State>,
+}
+
+impl ResolutionCache {
+    /// Create a cache with `config`
+    pub fn new(config: ResolverConfig) -> Self {
+        Self { config, state: Mutex::default() }
+    }
+
+    /// The cache settings
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// The cached answer for `host`, if there is a live one
+    pub fn get(&self, host: &str) -> Option<Resolution> {
+        if !self.config.enabled {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let key = normalize(host);
+        let resolution = match state.entries.get(&key) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.resolution.clone()),
+            Some(_) => {
+                state.entries.remove(&key);
+                None
+            }
+            None => None,
+        };
+        match &resolution {
+            Some(Resolution::Found(_)) => state.stats.hits += 1,
+            Some(Resolution::NotFound) => state.stats.negative_hits += 1,
+            None => state.stats.misses += 1,
+        }
+        resolution
+    }
+
+    /// Cache `addresses` for `host` for `ttl`, clamped to the configured
+    /// bounds; no addresses is cached as a negative answer
+    pub fn insert(&self, host: &str, addresses: Vec<IpAddr>, ttl: Duration) {
+        if addresses.is_empty() {
+            self.insert_negative(host, Some(ttl));
+            return;
+        }
+        let ttl = ttl.clamp(self.config.min_ttl, self.config.max_ttl);
+        self.store(host, Resolution::Found(addresses), ttl);
+    }
+
+    /// Remember that `host` does not exist, for `ttl` if the answer said how
+    /// long, but never longer than the configured negative TTL
+    pub fn insert_negative(&self, host: &str, ttl: Option<Duration>) {
+        let ttl = ttl.map_or(self.config.negative_ttl, |ttl| ttl.min(self.config.negative_ttl));
+        self.store(host, Resolution::NotFound, ttl);
+    }
+
+    /// Forget the answer for `host`
+    pub fn invalidate(&self, host: &str) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .remove(&normalize(host));
+    }
+
+    /// Forget every answer
+    pub fn clear(&self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).entries.clear();
+    }
+
+    /// Number of cached hostnames, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).entries.len()
+    }
+
+    /// Whether nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lookup counters since the cache was created
+    pub fn stats(&self) -> ResolutionStats {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).stats
+    }
+
+    /// Addresses of `host` from the cache or the system resolver
+    ///
+    /// The system resolver does not tell why a lookup failed, so every
+    /// failure is remembered as a negative answer. A lookup that runs out of
+    /// `timeout` is not cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::ServiceNotFound`] if the host does not
+    /// resolve, or a timeout error if the resolver does not answer in time
+    pub async fn resolve(&self, host: &str, timeout: Duration) -> Result<Vec<IpAddr>> {
+        match self.get(host) {
+            Some(Resolution::Found(addresses)) => return Ok(addresses),
+            Some(Resolution::NotFound) => {
+                return Err(DiscoveryError::service_not_found(format!("Host {host} does not resolve (cached)")));
+            }
+            None => {}
+        }
+
+        let lookup = tokio::net::lookup_host((host.trim_end_matches('.'), 0));
+        let addresses: Vec<IpAddr> = match tokio::time::timeout(timeout, lookup).await {
+            Ok(Ok(addrs)) => addrs.map(|addr| addr.ip()).collect(),
+            Ok(Err(e)) => {
+                debug!("Resolving {} failed: {}", host, e);
+                self.insert_negative(host, None);
+                return Err(DiscoveryError::service_not_found(format!("Host {host} does not resolve: {e}")));
+            }
+            Err(_) => return Err(DiscoveryError::timeout(format!("Resolving {host} timed out after {timeout:?}"))),
+        };
+        self.insert(host, addresses.clone(), self.config.system_ttl);
+        if addresses.is_empty() {
+            return Err(DiscoveryError::service_not_found(format!("Host {host} has no addresses")));
+        }
+        Ok(addresses)
+    }
+
+    fn store(&self, host: &str, resolution: Resolution, ttl: Duration) {
+        if !self.config.enabled {
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let key = normalize(host);
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.config.max_entries {
+            state.entries.retain(|_, entry| entry.expires > now);
+            if state.entries.len() >= self.config.max_entries
+                && let Some(soonest) = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(host, _)| host.clone())
+            {
+                state.entries.remove(&soonest);
+            }
+        }
+        state.entries.insert(key, Entry { resolution, expires: now + ttl });
+    }
+}
+
+/// Cache key of a hostname: lowercase, without the trailing root dot
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ttl_and_negative_caching() {
+        let config = ResolverConfig { min_ttl: Duration::ZERO, ..ResolverConfig::default() };
+        let cache = ResolutionCache::new(config);
+
+        cache.insert("Web.Example.com.", vec![address("192.0.2.10")], Duration::from_secs(120));
+        assert_eq!(cache.get("web.example.com"), Some(Resolution::Found(vec![address("192.0.2.10")])));
+
+        cache.insert("gone.example.com", vec![address("192.0.2.11")], Duration::ZERO);
+        assert_eq!(cache.get("gone.example.com"), None);
+
+        cache.insert_negative("missing.example.com", Some(Duration::from_secs(3600)));
+        assert_eq!(cache.get("missing.example.com"), Some(Resolution::NotFound));
+
+        assert_eq!(cache.stats(), ResolutionStats { hits: 1, negative_hits: 1, misses: 1 });
+        cache.invalidate("missing.example.com");
+        assert_eq!(cache.get("missing.example.com"), None);
+    }
+
+    #[test]
+    fn test_capacity_and_disabled_cache() {
+        let config = ResolverConfig { max_entries: 2, ..ResolverConfig::default() };
+        let cache = ResolutionCache::new(config);
+        cache.insert("a", vec![address("192.0.2.1")], Duration::from_secs(60));
+        cache.insert("b", vec![address("192.0.2.2")], Duration::from_secs(600));
+        cache.insert("c", vec![address("192.0.2.3")], Duration::from_secs(600));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("a"), None);
+
+        let disabled = ResolutionCache::new(ResolverConfig { enabled: false, ..ResolverConfig::default() });
+        disabled.insert("a", vec![address("192.0.2.1")], Duration::from_secs(60));
+        assert!(disabled.is_empty() && disabled.get("a").is_none());
+
+        let inverted = ResolverConfig { min_ttl: Duration::from_secs(10), max_ttl: Duration::from_secs(1), ..ResolverConfig::default() };
+        assert!(inverted.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_through_cache() {
+        let cache = ResolutionCache::default();
+        let addresses = cache.resolve("localhost", Duration::from_secs(5)).await.unwrap();
+        assert!(addresses.iter().all(IpAddr::is_loopback));
+        assert_eq!(cache.resolve("localhost", Duration::from_secs(5)).await.unwrap(), addresses);
+        assert_eq!(cache.stats().hits, 1);
+
+        cache.insert_negative("nowhere.invalid", None);
+        assert!(matches!(
+            cache.resolve("nowhere.invalid", Duration::from_secs(5)).await,
+            Err(DiscoveryError::ServiceNotFound(_))
+        ));
+    }
+}
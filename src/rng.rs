@@ -0,0 +1,88 @@
+//! Randomness for retry jitter, load balancing and query delays
+//!
+//! Retry delays, [`Random`](crate::safety::load_balancer::LoadBalancingStrategy::Random)
+//! load balancing and the initial mDNS query delay draw from a [`SharedRng`].
+//! By default that is the thread-local generator. Seeding it, through
+//! [`DiscoveryConfig::with_seed`](crate::DiscoveryConfig::with_seed) or
+//! [`SharedRng::seeded`], makes simulations and tests reproducible: the same
+//! seed and the same sequence of calls give the same choices.
+
+use rand::{
+    distr::{uniform::{SampleRange, SampleUniform}, Distribution, StandardUniform},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
+use std::sync::{Arc, Mutex};
+
+/// Source of random numbers that can be seeded and shared
+///
+/// Clones draw from the same sequence.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRng {
+    /// `None` for the thread-local generator
+    seeded: Option<Arc<Mutex<StdRng>>>,
+}
+
+impl SharedRng {
+    /// The thread-local generator, seeded from the operating system
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A generator that always produces the same sequence for `seed`
+    pub fn seeded(seed: u64) -> Self {
+        Self { seeded: Some(Arc::new(Mutex::new(StdRng::seed_from_u64(seed)))) }
+    }
+
+    /// A seeded generator if `seed` is set, the thread-local one otherwise
+    pub fn from_seed(seed: Option<u64>) -> Self {
+        seed.map_or_else(Self::new, Self::seeded)
+    }
+
+    /// Whether the generator was seeded
+    pub fn is_seeded(&self) -> bool {
+        self.seeded.is_some()
+    }
+
+    /// A random value of `T`
+    pub fn random<T>(&self) -> T
+    where
+        StandardUniform: Distribution<T>,
+    {
+        match &self.seeded {
+            Some(rng) => rng.lock().unwrap_or_else(|e| e.into_inner()).random(),
+            None => rand::rng().random(),
+        }
+    }
+
+    /// A random value in `range`
+    pub fn random_range<T, R>(&self, range: R) -> T
+    where
+        T: SampleUniform,
+        R: SampleRange<T>,
+    {
+        match &self.seeded {
+            Some(rng) => rng.lock().unwrap_or_else(|e| e.into_inner()).random_range(range),
+            None => rand::rng().random_range(range),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequences_repeat() {
+        let draw = |rng: &SharedRng| (0..8).map(|_| rng.random_range(0..1000u32)).collect::<Vec<_>>();
+        let rng = SharedRng::seeded(42);
+        let sequence = draw(&rng);
+        assert_eq!(draw(&SharedRng::seeded(42)), sequence);
+        assert_ne!(draw(&SharedRng::seeded(43)), sequence);
+
+        // Clones continue the same sequence rather than repeating it
+        let clone = rng.clone();
+        assert_ne!(draw(&clone), sequence);
+        assert!(!SharedRng::from_seed(None).is_seeded());
+    }
+}
@@ -1,111 +1,368 @@
 //! Production safety features including rate limiting, timeouts, circuit breakers, and error recovery.
 
-use std::{sync::Arc, time::Duration};
+use crate::{
+    clock::{self, Clock},
+    error::{DiscoveryError, ErrorContext, Result},
+    rng::SharedRng,
+    types::ProtocolType,
+};
 use governor::{
-    clock::DefaultClock,
-    state::{InMemoryState, NotKeyed, keyed::DefaultKeyedStateStore},
-    RateLimiter,
-    Quota,
+    middleware::NoOpMiddleware,
+    state::{InMemoryState, NotKeyed},
+    Quota, RateLimiter,
 };
-use tokio_retry::{
-    strategy::{ExponentialBackoff, jitter},
-    Retry,
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use metrics::{counter, gauge, histogram};
-use parking_lot::RwLock;
-use tracing::{debug, error, info, warn};
-use crate::{error::Result, service::ServiceInfo};
-
-/// Default rate limits (operations per second)
-const DEFAULT_DISCOVERY_RATE: u32 = 10;
-const DEFAULT_REGISTRATION_RATE: u32 = 5;
-const DEFAULT_VERIFICATION_RATE: u32 = 20;
-
-/// Default timeouts
-const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
-const DEFAULT_VERIFICATION_TIMEOUT: Duration = Duration::from_secs(2);
-const DEFAULT_REGISTRATION_TIMEOUT: Duration = Duration::from_secs(3);
-
-/// Default retry settings
-const MAX_RETRIES: u32 = 3;
-const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
-const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
-
-/// Circuit breaker settings
-const CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
-const CIRCUIT_BREAKER_RESET_TIMEOUT: Duration = Duration::from_secs(30);
+use tracing::{debug, info, warn};
+
+pub mod concurrency;
+pub mod load_balancer;
+
+use concurrency::ConcurrencyConfig;
+
+/// Rate limit and timeout for one kind of operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationQuota {
+    /// Operations allowed per second
+    pub per_second: u32,
+    /// Time allowed for a single attempt
+    pub timeout: Duration,
+}
+
+/// Circuit breaker thresholds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open the breaker
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before letting a trial operation through
+    pub reset_timeout: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            reset_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Exponential backoff schedule for retried operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial_delay: Duration,
+    /// Upper bound on any single delay
+    pub max_delay: Duration,
+    /// Randomize each delay to avoid synchronized retries
+    pub jitter: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Delays to wait before each retry
+    pub fn delays(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.delays_with(SharedRng::new())
+    }
+
+    /// Delays to wait before each retry, jittered with `rng`
+    pub fn delays_with(&self, rng: SharedRng) -> impl Iterator<Item = Duration> + '_ {
+        (0..self.max_retries).map(move |attempt| {
+            let delay = self
+                .initial_delay
+                .saturating_mul(2u32.saturating_pow(attempt))
+                .min(self.max_delay);
+            if self.jitter {
+                delay.mul_f64(rng.random_range(0.5..=1.0))
+            } else {
+                delay
+            }
+        })
+    }
+}
+
+/// Tunable limits used by [`SafetyManager`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafetyConfig {
+    /// Limits for discovery operations
+    pub discovery: OperationQuota,
+    /// Limits for registration operations
+    pub registration: OperationQuota,
+    /// Limits for verification operations
+    pub verification: OperationQuota,
+    /// Circuit breaker thresholds, applied to each operation and each protocol
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Retry schedule for [`SafetyManager::execute_with_safety`]
+    pub retry: RetryConfig,
+    /// Discoveries and verifications allowed in flight at once
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            discovery: OperationQuota { per_second: 10, timeout: Duration::from_secs(5) },
+            registration: OperationQuota { per_second: 5, timeout: Duration::from_secs(3) },
+            verification: OperationQuota { per_second: 20, timeout: Duration::from_secs(2) },
+            circuit_breaker: CircuitBreakerConfig::default(),
+            retry: RetryConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+        }
+    }
+}
+
+impl SafetyConfig {
+    /// Create a safety configuration with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits for an operation
+    pub fn quota(&self, operation: Operation) -> &OperationQuota {
+        match operation {
+            Operation::Discovery => &self.discovery,
+            Operation::Registration => &self.registration,
+            Operation::Verification => &self.verification,
+        }
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        for operation in Operation::ALL {
+            let quota = self.quota(operation);
+            if quota.per_second == 0 || quota.timeout.is_zero() {
+                return Err(DiscoveryError::configuration(format!(
+                    "{operation} rate and timeout must be greater than 0"
+                )));
+            }
+        }
+        if self.circuit_breaker.failure_threshold == 0 {
+            return Err(DiscoveryError::configuration(
+                "Circuit breaker failure threshold must be greater than 0",
+            ));
+        }
+        if self.retry.initial_delay > self.retry.max_delay {
+            return Err(DiscoveryError::configuration(
+                "Retry initial delay must not exceed the maximum delay",
+            ));
+        }
+        self.concurrency.validate()?;
+        Ok(())
+    }
+}
+
+/// Operations guarded by the safety manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Service discovery
+    Discovery,
+    /// Service registration
+    Registration,
+    /// Service verification
+    Verification,
+}
+
+impl Operation {
+    const ALL: [Operation; 3] = [Self::Discovery, Self::Registration, Self::Verification];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Discovery => "discovery",
+            Self::Registration => "registration",
+            Self::Verification => "verification",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 /// Circuit breaker states
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CircuitState {
+    /// Operations flow normally
     Closed,
+    /// Operations are rejected until the reset timeout elapses
     Open,
+    /// A trial operation is allowed to test recovery
     HalfOpen,
 }
 
+impl CircuitState {
+    /// Numeric value reported by the `protocol_circuit_state` gauge
+    pub fn as_gauge(&self) -> f64 {
+        match self {
+            Self::Closed => 0.0,
+            Self::HalfOpen => 1.0,
+            Self::Open => 2.0,
+        }
+    }
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half-open",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    state: CircuitState,
+    failures: u32,
+    last_state_change: Instant,
+}
+
 /// Circuit breaker for handling operation failures
+#[derive(Debug)]
 pub struct CircuitBreaker {
-    state: RwLock<CircuitState>,
-    failures: RwLock<u32>,
-    threshold: u32,
-    reset_timeout: Duration,
-    last_state_change: RwLock<std::time::Instant>,
+    inner: Mutex<BreakerState>,
+    config: CircuitBreakerConfig,
+    clock: Arc<dyn Clock>,
 }
 
 impl CircuitBreaker {
-    pub fn new() -> Self {
+    /// Create a closed circuit breaker
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        let clock = clock::system();
         Self {
-            state: RwLock::new(CircuitState::Closed),
-            failures: RwLock::new(0),
-            threshold: CIRCUIT_BREAKER_THRESHOLD,
-            reset_timeout: CIRCUIT_BREAKER_RESET_TIMEOUT,
-            last_state_change: RwLock::new(std::time::Instant::now()),
+            inner: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                failures: 0,
+                last_state_change: clock.now(),
+            }),
+            config,
+            clock,
         }
     }
 
+    /// Time the reset timeout by `clock` instead of tokio's clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.lock().last_state_change = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a failed operation
     pub fn record_failure(&self) {
-        let mut failures = self.failures.write();
-        *failures += 1;
-
-        if *failures >= self.threshold {
-            let mut state = self.state.write();
-            if *state == CircuitState::Closed {
-                *state = CircuitState::Open;
-                *self.last_state_change.write() = std::time::Instant::now();
-                warn!("Circuit breaker opened after {} failures", failures);
-                counter!("circuit_breaker_opens_total", 1);
-            }
+        let mut inner = self.lock();
+        inner.failures += 1;
+
+        let trip = match inner.state {
+            CircuitState::Closed => inner.failures >= self.config.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if trip {
+            inner.state = CircuitState::Open;
+            inner.last_state_change = self.clock.now();
+            warn!("Circuit breaker opened after {} failures", inner.failures);
+            #[cfg(feature = "metrics")]
+            metrics::counter!("circuit_breaker_opens_total").increment(1);
         }
     }
 
+    /// Record a successful operation
     pub fn record_success(&self) {
-        let mut state = self.state.write();
-        if *state == CircuitState::HalfOpen {
-            *state = CircuitState::Closed;
-            *self.failures.write() = 0;
-            *self.last_state_change.write() = std::time::Instant::now();
+        let mut inner = self.lock();
+        inner.failures = 0;
+        if inner.state != CircuitState::Closed {
+            inner.state = CircuitState::Closed;
+            inner.last_state_change = self.clock.now();
             info!("Circuit breaker closed after successful operation");
-            counter!("circuit_breaker_closes_total", 1);
+            #[cfg(feature = "metrics")]
+            metrics::counter!("circuit_breaker_closes_total").increment(1);
         }
     }
 
+    /// Whether an operation may proceed, moving an expired open breaker to half-open
     pub fn is_closed(&self) -> bool {
-        let state = self.state.read();
-        match *state {
-            CircuitState::Closed => true,
+        let mut inner = self.lock();
+        let now = self.clock.now();
+        match inner.state {
+            CircuitState::Closed | CircuitState::HalfOpen => true,
+            CircuitState::Open if now.saturating_duration_since(inner.last_state_change) >= self.config.reset_timeout => {
+                inner.state = CircuitState::HalfOpen;
+                inner.last_state_change = now;
+                debug!("Circuit breaker entering half-open state");
+                true
+            }
+            CircuitState::Open => false,
+        }
+    }
+
+    /// Current state
+    pub fn state(&self) -> CircuitState {
+        self.lock().state
+    }
+
+    /// Time left until an open breaker lets a trial operation through
+    pub fn time_until_half_open(&self) -> Option<Duration> {
+        let inner = self.lock();
+        match inner.state {
             CircuitState::Open => {
-                let last_change = self.last_state_change.read();
-                if last_change.elapsed() >= self.reset_timeout {
-                    drop(last_change);
-                    drop(state);
-                    *self.state.write() = CircuitState::HalfOpen;
-                    debug!("Circuit breaker entering half-open state");
-                    true
-                } else {
-                    false
-                }
+                let elapsed = self.clock.now().saturating_duration_since(inner.last_state_change);
+                Some(self.config.reset_timeout.saturating_sub(elapsed))
             }
-            CircuitState::HalfOpen => true,
+            CircuitState::Closed | CircuitState::HalfOpen => None,
+        }
+    }
+}
+
+/// Lets the rate limiters read a [`Clock`]
+#[derive(Debug, Clone)]
+struct RateClock(Arc<dyn Clock>);
+
+impl governor::clock::Clock for RateClock {
+    type Instant = Instant;
+
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+}
+
+struct Guard {
+    limiter: RateLimiter<NotKeyed, InMemoryState, RateClock, NoOpMiddleware<Instant>>,
+    breaker: CircuitBreaker,
+}
+
+impl Guard {
+    fn new(quota: &OperationQuota, breaker: CircuitBreakerConfig, clock: &Arc<dyn Clock>) -> Self {
+        let per_second = NonZeroU32::new(quota.per_second).unwrap_or(NonZeroU32::MIN);
+        Self {
+            limiter: RateLimiter::direct_with_clock(Quota::per_second(per_second), RateClock(Arc::clone(clock))),
+            breaker: CircuitBreaker::new(breaker).with_clock(Arc::clone(clock)),
         }
     }
 }
@@ -113,300 +370,354 @@ impl CircuitBreaker {
 /// Rate limiter for service discovery operations with integrated circuit breakers
 #[derive(Clone)]
 pub struct SafetyManager {
-    discovery_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
-    registration_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
-    verification_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
-    discovery_breaker: Arc<CircuitBreaker>,
-    registration_breaker: Arc<CircuitBreaker>,
-    verification_breaker: Arc<CircuitBreaker>,
+    config: SafetyConfig,
+    discovery: Arc<Guard>,
+    registration: Arc<Guard>,
+    verification: Arc<Guard>,
+    protocols: Arc<HashMap<ProtocolType, CircuitBreaker>>,
+    clock: Arc<dyn Clock>,
+    rng: SharedRng,
 }
 
 impl SafetyManager {
-    /// Create a new safety manager with rate limiters and circuit breakers
-    pub fn new() -> Self {
+    /// Create a safety manager with rate limiters and circuit breakers from `config`
+    pub fn new(config: SafetyConfig) -> Self {
+        Self::build(config, clock::system(), SharedRng::new())
+    }
+
+    /// Time rate limits and circuit breakers by `clock` instead of tokio's
+    /// clock
+    ///
+    /// The limiters and breakers start afresh.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self::build(self.config, clock, self.rng)
+    }
+
+    /// Jitter retry delays with `rng`
+    pub fn with_rng(mut self, rng: SharedRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// The time source of the rate limiters and circuit breakers
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    fn build(config: SafetyConfig, clock: Arc<dyn Clock>, rng: SharedRng) -> Self {
+        let guard = |quota| Arc::new(Guard::new(quota, config.circuit_breaker, &clock));
         Self {
-            discovery_limiter: Arc::new(RateLimiter::direct(Quota::per_second(DEFAULT_DISCOVERY_RATE.into()))),
-            registration_limiter: Arc::new(RateLimiter::direct(Quota::per_second(DEFAULT_REGISTRATION_RATE.into()))),
-            verification_limiter: Arc::new(RateLimiter::direct(Quota::per_second(DEFAULT_VERIFICATION_RATE.into()))),
-            discovery_breaker: Arc::new(CircuitBreaker::new()),
-            registration_breaker: Arc::new(CircuitBreaker::new()),
-            verification_breaker: Arc::new(CircuitBreaker::new()),
+            discovery: guard(&config.discovery),
+            registration: guard(&config.registration),
+            verification: guard(&config.verification),
+            protocols: Arc::new(
+                [ProtocolType::Mdns, ProtocolType::DnsSd, ProtocolType::Upnp]
+                    .into_iter()
+                    .map(|protocol| {
+                        (protocol, CircuitBreaker::new(config.circuit_breaker).with_clock(Arc::clone(&clock)))
+                    })
+                    .collect(),
+            ),
+            config,
+            clock,
+            rng,
         }
     }
 
-    /// Check if discovery operation is allowed
-    pub fn check_discovery(&self) -> bool {
-        if !self.discovery_breaker.is_closed() {
-            counter!("safety_discovery_blocked_by_circuit_breaker", 1);
-            return false;
+    /// The configuration in use
+    pub fn config(&self) -> &SafetyConfig {
+        &self.config
+    }
+
+    fn guard(&self, operation: Operation) -> &Guard {
+        match operation {
+            Operation::Discovery => &self.discovery,
+            Operation::Registration => &self.registration,
+            Operation::Verification => &self.verification,
         }
+    }
 
-        match self.discovery_limiter.check() {
-            Ok(_) => true,
-            Err(_) => {
-                counter!("safety_discovery_rate_limited", 1);
-                false
-            }
+    /// Check whether an operation is allowed right now
+    pub fn check(&self, operation: Operation) -> Result<()> {
+        let guard = self.guard(operation);
+        let context = ErrorContext::new().operation(operation);
+        if !guard.breaker.is_closed() {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("safety_blocked_by_circuit_breaker", "operation" => operation.as_str()).increment(1);
+            let context = match guard.breaker.time_until_half_open() {
+                Some(delay) => context.retry_after(delay),
+                None => context,
+            };
+            return Err(DiscoveryError::rate_limited(format!("{operation} circuit breaker is open")).with_context(context));
+        }
+        if let Err(not_until) = guard.limiter.check() {
+            #[cfg(feature = "metrics")]
+            metrics::counter!("safety_rate_limited", "operation" => operation.as_str()).increment(1);
+            let delay = not_until.wait_time_from(self.clock.now());
+            return Err(DiscoveryError::rate_limited(format!("{operation} rate limit exceeded"))
+                .with_context(context.retry_after(delay)));
         }
+        Ok(())
+    }
+
+    /// Check if discovery operation is allowed
+    pub fn check_discovery(&self) -> bool {
+        self.check(Operation::Discovery).is_ok()
     }
 
     /// Check if registration operation is allowed
     pub fn check_registration(&self) -> bool {
-        if !self.registration_breaker.is_closed() {
-            counter!("safety_registration_blocked_by_circuit_breaker", 1);
-            return false;
-        }
-
-        match self.registration_limiter.check() {
-            Ok(_) => true,
-            Err(_) => {
-                counter!("safety_registration_rate_limited", 1);
-                false
-            }
-        }
+        self.check(Operation::Registration).is_ok()
     }
 
     /// Check if verification operation is allowed
     pub fn check_verification(&self) -> bool {
-        if !self.verification_breaker.is_closed() {
-            counter!("safety_verification_blocked_by_circuit_breaker", 1);
-            return false;
-        }
-
-        match self.verification_limiter.check() {
-            Ok(_) => true,
-            Err(_) => {
-                counter!("safety_verification_rate_limited", 1);
-                false
-            }
-        }
+        self.check(Operation::Verification).is_ok()
     }
 
     /// Record operation success
-    pub fn record_success(&self, operation: &str) {
-        match operation {
-            "discovery" => self.discovery_breaker.record_success(),
-            "registration" => self.registration_breaker.record_success(),
-            "verification" => self.verification_breaker.record_success(),
-            _ => (),
-        }
-        counter!("safety_operation_success", 1, "operation" => operation.to_string());
+    pub fn record_success(&self, operation: Operation) {
+        self.guard(operation).breaker.record_success();
+        #[cfg(feature = "metrics")]
+        metrics::counter!("safety_operation_success", "operation" => operation.as_str()).increment(1);
     }
 
     /// Record operation failure
-    pub fn record_failure(&self, operation: &str) {
-        match operation {
-            "discovery" => self.discovery_breaker.record_failure(),
-            "registration" => self.registration_breaker.record_failure(),
-            "verification" => self.verification_breaker.record_failure(),
-            _ => (),
-        }
-        counter!("safety_operation_failure", 1, "operation" => operation.to_string());
+    pub fn record_failure(&self, operation: Operation) {
+        self.guard(operation).breaker.record_failure();
+        #[cfg(feature = "metrics")]
+        metrics::counter!("safety_operation_failure", "operation" => operation.as_str()).increment(1);
     }
 
-    /// Get retry strategy for an operation
-    pub fn get_retry_strategy(&self) -> impl Iterator<Item = Duration> {
-        ExponentialBackoff::from_millis(MIN_RETRY_DELAY.as_millis() as u64)
-            .max_delay(MAX_RETRY_DELAY)
-            .map(jitter)
-            .take(MAX_RETRIES as usize)
-    }
-
-    /// Execute an operation with retries and safety checks
-    pub async fn execute_with_safety<F, T>(&self, operation: &str, f: F) -> Result<T>
+    /// Execute an operation with safety checks, a per-attempt timeout and retries
+    ///
+    /// Only errors for which [`DiscoveryError::is_retryable`] holds are retried.
+    pub async fn execute_with_safety<F, Fut, T>(&self, operation: Operation, mut f: F) -> Result<T>
     where
-        F: Fn() -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<T>> + Send>>,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
     {
-        let retry_strategy = self.get_retry_strategy();
-        let allowed = match operation {
-            "discovery" => self.check_discovery(),
-            "registration" => self.check_registration(),
-            "verification" => self.check_verification(),
-            _ => true,
+        self.check(operation)?;
+
+        let timeout = self.config.quota(operation).timeout;
+        let mut delays = self.config.retry.delays_with(self.rng.clone());
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+
+        let result = loop {
+            let attempt = match tokio::time::timeout(timeout, f()).await {
+                Ok(result) => result,
+                Err(_) => Err(DiscoveryError::timeout(format!("{operation} timed out after {timeout:?}"))),
+            };
+            match attempt {
+                Err(e) if e.is_retryable() => match delays.next() {
+                    Some(delay) => {
+                        debug!("Retrying {} in {:?} after error: {}", operation, delay, e);
+                        tokio::time::sleep(delay).await;
+                    }
+                    None => break Err(e),
+                },
+                other => break other,
+            }
         };
 
-        if !allowed {
-            return Err(crate::error::DiscoveryError::RateLimit(
-                format!("Operation {} not allowed by safety checks", operation)
-            ));
-        }
-
-        let start = std::time::Instant::now();
-        let result = Retry::spawn(retry_strategy, f).await;
-        histogram!("safety_operation_duration", start.elapsed(), "operation" => operation.to_string());
+        #[cfg(feature = "metrics")]
+        metrics::histogram!("safety_operation_duration", "operation" => operation.as_str())
+            .record(start.elapsed().as_secs_f64());
 
         match &result {
             Ok(_) => self.record_success(operation),
             Err(_) => self.record_failure(operation),
         }
-
         result
     }
 
     /// Get current circuit breaker states
-    pub fn get_circuit_breaker_states(&self) -> Vec<(String, CircuitState)> {
-        vec![
-            ("discovery".to_string(), *self.discovery_breaker.state.read()),
-            ("registration".to_string(), *self.registration_breaker.state.read()),
-            ("verification".to_string(), *self.verification_breaker.state.read()),
-        ]
+    pub fn get_circuit_breaker_states(&self) -> Vec<(Operation, CircuitState)> {
+        Operation::ALL
+            .into_iter()
+            .map(|operation| (operation, self.guard(operation).breaker.state()))
+            .collect()
     }
-}
-
-/// Retry strategy for fallible operations
-pub struct RetryStrategy {
-    max_retries: u32,
-    strategy: ExponentialBackoff,
-}
 
-impl RetryStrategy {
-    /// Create a new retry strategy with default settings
-    pub fn new() -> Self {
-        Self {
-            max_retries: MAX_RETRIES,
-            strategy: ExponentialBackoff::from_millis(MIN_RETRY_DELAY.as_millis() as u64)
-                .max_delay(MAX_RETRY_DELAY)
-                .map(jitter),
+    /// Whether discovery may use `protocol`, i.e. its circuit breaker is not open
+    pub fn check_protocol(&self, protocol: ProtocolType) -> bool {
+        let Some(breaker) = self.protocols.get(&protocol) else {
+            return true;
+        };
+        let allowed = breaker.is_closed();
+        Self::publish_protocol_state(protocol, breaker.state());
+        if !allowed {
+            debug!("Skipping {} while its circuit breaker is open", protocol);
         }
+        allowed
     }
 
-    /// Execute an operation with retries
-    pub async fn retry<F, T, E>(&self, operation: F) -> Result<T>
-    where
-        F: Fn() -> std::pin::Pin<Box<dyn std::future::Future<Output = std::result::Result<T, E>> + Send>>,
-        E: std::fmt::Display,
-    {
-        let retry_strategy = self.strategy.clone();
-        let max_retries = self.max_retries;
-
-        Retry::spawn(retry_strategy.take(max_retries as usize), operation)
-            .await
-            .map_err(|e| crate::error::DiscoveryError::retry(e.to_string()))
-    }
-}
-
-/// Service health monitoring
-#[derive(Clone)]
-pub struct HealthMonitor {
-    services: Arc<RwLock<dashmap::DashMap<String, ServiceHealth>>>,
-}
-
-#[derive(Debug, Clone)]
-struct ServiceHealth {
-    last_seen: std::time::Instant,
-    status: ServiceStatus,
-    failure_count: u32,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum ServiceStatus {
-    Healthy,
-    Degraded,
-    Unhealthy,
-}
-
-impl HealthMonitor {
-    /// Create a new health monitor
-    pub fn new() -> Self {
-        Self {
-            services: Arc::new(RwLock::new(dashmap::DashMap::new())),
-        }
+    /// Time left until an open protocol breaker lets a trial operation through
+    pub fn protocol_time_until_half_open(&self, protocol: ProtocolType) -> Option<Duration> {
+        self.protocols.get(&protocol)?.time_until_half_open()
     }
 
-    /// Update service health status
-    pub fn update_service(&self, service: &ServiceInfo, healthy: bool) {
-        let mut services = self.services.write();
-        let entry = services.entry(service.id().to_string()).or_insert_with(|| ServiceHealth {
-            last_seen: std::time::Instant::now(),
-            status: ServiceStatus::Healthy,
-            failure_count: 0,
-        });
-
-        if healthy {
-            entry.failure_count = 0;
-            entry.status = ServiceStatus::Healthy;
-        } else {
-            entry.failure_count += 1;
-            if entry.failure_count > 3 {
-                entry.status = ServiceStatus::Unhealthy;
-            } else if entry.failure_count > 1 {
-                entry.status = ServiceStatus::Degraded;
+    /// Record the outcome of a protocol operation
+    pub fn record_protocol_result(&self, protocol: ProtocolType, success: bool) {
+        if let Some(breaker) = self.protocols.get(&protocol) {
+            if success {
+                breaker.record_success();
+            } else {
+                breaker.record_failure();
             }
+            Self::publish_protocol_state(protocol, breaker.state());
         }
+    }
 
-        entry.last_seen = std::time::Instant::now();
-
-        // Update metrics
-        gauge!("service_health", entry.status as i64, "service" => service.name().to_string());
-        histogram!("service_failure_count", entry.failure_count as f64, "service" => service.name().to_string());
+    /// Circuit breaker state of a protocol
+    pub fn protocol_state(&self, protocol: ProtocolType) -> CircuitState {
+        self.protocols
+            .get(&protocol)
+            .map_or(CircuitState::Closed, CircuitBreaker::state)
     }
 
-    /// Get service health status
-    pub fn get_service_status(&self, service_id: &str) -> Option<ServiceStatus> {
-        self.services.read().get(service_id).map(|h| h.status)
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn publish_protocol_state(protocol: ProtocolType, state: CircuitState) {
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("protocol_circuit_state", "protocol" => protocol.to_string()).set(state.as_gauge());
     }
+}
 
-    /// Clean up stale service entries
-    pub fn cleanup_stale(&self, max_age: Duration) {
-        let mut services = self.services.write();
-        services.retain(|_, health| health.last_seen.elapsed() < max_age);
+impl Default for SafetyManager {
+    fn default() -> Self {
+        Self::new(SafetyConfig::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio;
+    use crate::clock::ManualClock;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use crate::error::ErrorKind;
 
-    #[tokio::test]
-    async fn test_rate_limiter() {
-        let limiter = DiscoveryRateLimiter::new();
-        
-        // Test that rate limiting works
-        for _ in 0..5 {
-            limiter.check_discovery().await;
-        }
+    #[test]
+    fn test_configured_rate_limit() {
+        let mut config = SafetyConfig::default();
+        config.discovery.per_second = 2;
+        let safety = SafetyManager::new(config);
+
+        assert!(safety.check_discovery());
+        assert!(safety.check_discovery());
+        let error = safety.check(Operation::Discovery).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
+        assert_eq!(error.context().and_then(|context| context.operation), Some(Operation::Discovery));
+        assert!(error.retry_after().is_some_and(|delay| delay <= Duration::from_millis(500)));
+        assert!(safety.check_registration());
     }
 
-    #[tokio::test]
-    async fn test_retry_strategy() {
-        let retry = RetryStrategy::new();
-        
-        let success = retry.retry(|| Box::pin(async { Ok::<_, String>("success") })).await;
-        assert!(success.is_ok());
-        
-        let mut attempts = 0;
-        let failure = retry.retry(|| Box::pin(async move {
-            attempts += 1;
-            if attempts < 3 {
-                Err("retry")?
-            }
-            Ok("success")
-        })).await;
-        assert!(failure.is_ok());
+    #[test]
+    fn test_circuit_breaker_threshold_and_reset() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            reset_timeout: Duration::ZERO,
+        });
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        assert!(breaker.is_closed());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        assert!(breaker.is_closed());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
     }
 
     #[test]
-    fn test_health_monitor() {
-        let monitor = HealthMonitor::new();
-        let service = ServiceInfo::new(
-            "test_service",
-            "_test._tcp",
-            8080,
-            None,
-        );
+    fn test_breakers_and_limits_follow_clock() {
+        let clock = ManualClock::new();
+        let mut config = SafetyConfig {
+            circuit_breaker: CircuitBreakerConfig {
+                failure_threshold: 1,
+                reset_timeout: Duration::from_secs(60),
+            },
+            ..SafetyConfig::default()
+        };
+        config.discovery.per_second = 1;
+        let safety = SafetyManager::new(config).with_clock(Arc::new(clock.clone()));
+
+        safety.record_protocol_result(ProtocolType::Upnp, false);
+        clock.advance(Duration::from_secs(45));
+        assert!(!safety.check_protocol(ProtocolType::Upnp));
+        assert_eq!(safety.protocol_time_until_half_open(ProtocolType::Upnp), Some(Duration::from_secs(15)));
+        clock.advance(Duration::from_secs(15));
+        assert!(safety.check_protocol(ProtocolType::Upnp));
+
+        assert!(safety.check_discovery());
+        assert!(!safety.check_discovery());
+        clock.advance(Duration::from_secs(1));
+        assert!(safety.check_discovery());
+    }
 
-        // Test health status updates
-        monitor.update_service(&service, true);
-        assert_eq!(monitor.get_service_status(&service.id().to_string()), Some(ServiceStatus::Healthy));
+    #[test]
+    fn test_protocol_breakers_are_independent() {
+        let config = SafetyConfig {
+            circuit_breaker: CircuitBreakerConfig {
+                failure_threshold: 2,
+                reset_timeout: Duration::from_secs(60),
+            },
+            ..SafetyConfig::default()
+        };
+        let safety = SafetyManager::new(config);
 
-        // Test degradation
-        monitor.update_service(&service, false);
-        monitor.update_service(&service, false);
-        assert_eq!(monitor.get_service_status(&service.id().to_string()), Some(ServiceStatus::Degraded));
+        safety.record_protocol_result(ProtocolType::Upnp, false);
+        safety.record_protocol_result(ProtocolType::Upnp, false);
 
-        // Test cleanup
-        monitor.cleanup_stale(Duration::from_secs(0));
-        assert_eq!(monitor.get_service_status(&service.id().to_string()), None);
+        assert!(!safety.check_protocol(ProtocolType::Upnp));
+        assert_eq!(safety.protocol_state(ProtocolType::Upnp), CircuitState::Open);
+        assert!(safety.check_protocol(ProtocolType::Mdns));
+        assert!(safety.check_protocol(ProtocolType::DnsSd));
+        assert!(safety.check_discovery());
+    }
+
+    #[tokio::test]
+    async fn test_retry_schedule() {
+        let config = SafetyConfig {
+            retry: RetryConfig {
+                max_retries: 2,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(2),
+                jitter: false,
+            },
+            ..SafetyConfig::default()
+        };
+        assert_eq!(
+            config.retry.delays().collect::<Vec<_>>(),
+            vec![Duration::from_millis(1), Duration::from_millis(2)]
+        );
+        let jittered = RetryConfig { jitter: true, ..config.retry };
+        let delays = |seed| jittered.delays_with(SharedRng::seeded(seed)).collect::<Vec<_>>();
+        assert_eq!(delays(1), delays(1));
+        assert!(delays(1).iter().all(|delay| *delay <= Duration::from_millis(2)));
+
+        let safety = SafetyManager::new(config);
+        let attempts = AtomicU32::new(0);
+        let result = safety
+            .execute_with_safety(Operation::Discovery, || async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(DiscoveryError::network("unreachable")),
+                    _ => Ok("found"),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "found");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let result: Result<()> = safety
+            .execute_with_safety(Operation::Discovery, || async { Err(DiscoveryError::configuration("bad")) })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
     }
 }
@@ -0,0 +1,184 @@
+//! Limits on the number of operations in flight at once
+//!
+//! Rate limits bound how often operations start, not how many run at once:
+//! verifying every service of a large registry together would still open a
+//! socket per service. A [`ConcurrencyLimiter`] holds the
+//! [`ProtocolManager`](crate::protocols::ProtocolManager)'s discoveries and
+//! verifications to the limits of a [`ConcurrencyConfig`], across all
+//! protocols and for each protocol on its own; callers over a limit wait for
+//! a slot.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    safety::Operation,
+    types::ProtocolType,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// How many discoveries and verifications may run at once
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    /// Discoveries in flight across all protocols, `None` for no limit
+    pub max_discoveries: Option<usize>,
+    /// Verifications in flight across all protocols, `None` for no limit
+    pub max_verifications: Option<usize>,
+    /// Discoveries and verifications in flight with each listed protocol
+    pub per_protocol: HashMap<ProtocolType, usize>,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_discoveries: Some(32),
+            max_verifications: Some(64),
+            per_protocol: HashMap::new(),
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    /// Create concurrency limits with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits that let everything run at once
+    pub fn unlimited() -> Self {
+        Self {
+            max_discoveries: None,
+            max_verifications: None,
+            per_protocol: HashMap::new(),
+        }
+    }
+
+    /// Validate the configuration
+    pub fn validate(&self) -> Result<()> {
+        let zero_global = [self.max_discoveries, self.max_verifications].contains(&Some(0));
+        if zero_global || self.per_protocol.values().any(|&limit| limit == 0) {
+            return Err(DiscoveryError::configuration(
+                "Concurrency limits must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Semaphores enforcing a [`ConcurrencyConfig`]
+///
+/// Clones share their slots.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter {
+    discovery: Option<Arc<Semaphore>>,
+    verification: Option<Arc<Semaphore>>,
+    protocols: HashMap<ProtocolType, Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a limiter enforcing `config`
+    pub fn new(config: &ConcurrencyConfig) -> Self {
+        let semaphore = |limit: usize| Arc::new(Semaphore::new(limit));
+        Self {
+            discovery: config.max_discoveries.map(semaphore),
+            verification: config.max_verifications.map(semaphore),
+            protocols: config
+                .per_protocol
+                .iter()
+                .map(|(protocol_type, limit)| (*protocol_type, semaphore(*limit)))
+                .collect(),
+        }
+    }
+
+    /// Wait for a slot to run `operation` with `protocol_type`
+    ///
+    /// The slot is held until the returned permit is dropped. Registrations
+    /// are not limited.
+    pub async fn acquire(&self, operation: Operation, protocol_type: ProtocolType) -> ConcurrencyPermit {
+        let (global, protocol) = match operation {
+            Operation::Registration => (None, None),
+            Operation::Discovery => (self.discovery.as_ref(), self.protocols.get(&protocol_type)),
+            Operation::Verification => (self.verification.as_ref(), self.protocols.get(&protocol_type)),
+        };
+        // Always global before per-protocol, so waiters cannot deadlock
+        ConcurrencyPermit {
+            _global: acquire(global).await,
+            _protocol: acquire(protocol).await,
+        }
+    }
+
+    /// Free slots for `operation` across all protocols, `None` if unlimited
+    pub fn available(&self, operation: Operation) -> Option<usize> {
+        match operation {
+            Operation::Registration => None,
+            Operation::Discovery => self.discovery.as_ref().map(|s| s.available_permits()),
+            Operation::Verification => self.verification.as_ref().map(|s| s.available_permits()),
+        }
+    }
+}
+
+impl Default for ConcurrencyLimiter {
+    fn default() -> Self {
+        Self::new(&ConcurrencyConfig::default())
+    }
+}
+
+async fn acquire(semaphore: Option<&Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match semaphore {
+        Some(semaphore) => Some(
+            Arc::clone(semaphore)
+                .acquire_owned()
+                .await
+                .expect("concurrency semaphores are never closed"),
+        ),
+        None => None,
+    }
+}
+
+/// A slot held for one operation, released on drop
+#[derive(Debug)]
+pub struct ConcurrencyPermit {
+    _global: Option<OwnedSemaphorePermit>,
+    _protocol: Option<OwnedSemaphorePermit>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_limits_global_and_per_protocol() {
+        let config = ConcurrencyConfig {
+            max_verifications: Some(2),
+            per_protocol: HashMap::from([(ProtocolType::Upnp, 1)]),
+            ..ConcurrencyConfig::default()
+        };
+        let limiter = ConcurrencyLimiter::new(&config);
+        let blocked = |operation, protocol_type| {
+            let limiter = limiter.clone();
+            async move {
+                tokio::time::timeout(Duration::from_millis(50), limiter.acquire(operation, protocol_type))
+                    .await
+                    .is_err()
+            }
+        };
+
+        let upnp = limiter.acquire(Operation::Verification, ProtocolType::Upnp).await;
+        assert!(blocked(Operation::Verification, ProtocolType::Upnp).await);
+        let mdns = limiter.acquire(Operation::Verification, ProtocolType::Mdns).await;
+        assert_eq!(limiter.available(Operation::Verification), Some(0));
+        assert!(blocked(Operation::Verification, ProtocolType::Mdns).await);
+        // Discovery has its own slots, registration none
+        assert!(!blocked(Operation::Discovery, ProtocolType::Mdns).await);
+        assert!(!blocked(Operation::Registration, ProtocolType::Upnp).await);
+
+        drop((upnp, mdns));
+        assert!(!blocked(Operation::Verification, ProtocolType::Upnp).await);
+        assert_eq!(limiter.available(Operation::Verification), Some(2));
+
+        assert!(ConcurrencyConfig { max_discoveries: Some(0), ..ConcurrencyConfig::default() }.validate().is_err());
+        assert!(ConcurrencyConfig::unlimited().validate().is_ok());
+    }
+}
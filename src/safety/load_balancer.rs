@@ -1,202 +1,449 @@
-use std::sync::Arc;
-use parking_lot::RwLock;
-use tower::load::PeakEwma;
-use tower::discover::Change;
-use futures::Stream;
-use std::pin::Pin;
-use std::task::{Context, Poll};
-use tokio::sync::mpsc;
-use crate::service::ServiceInfo;
-use crate::error::Result;
+//! Client-side load balancing across discovered instances of a service
+//!
+//! A [`LoadBalancer`] tracks the instances of one service type together with
+//! their load, response times and health, and picks an instance per request
+//! according to its [`LoadBalancingStrategy`]. Unhealthy instances are never
+//! picked, and degraded ones only when no healthy instance is left.
+//!
+//! [`LoadBalancingStrategy::ConsistentHash`] uses rendezvous hashing: each
+//! client key goes to the instance with the highest hash of key and instance
+//! ID, so when an instance leaves only the keys it served move elsewhere.
+
+use crate::{
+    health::HealthStatus,
+    rng::SharedRng,
+    service::{ServiceId, ServiceInfo},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
 
 /// Load balancing strategy
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum LoadBalancingStrategy {
+    /// Take turns between instances
     RoundRobin,
+    /// Pick the instance reporting the lowest load
     LeastLoaded,
+    /// Pick randomly, favouring lightly loaded instances
     Random,
+    /// Take turns in proportion to SRV weight, within the lowest SRV priority
+    ///
+    /// Instances with weight 0 are only used when every instance of that
+    /// priority has weight 0.
+    WeightedRoundRobin,
+    /// Send each client key to the same instance while that instance is usable
+    ///
+    /// Selections without a key fall back to round robin.
+    ConsistentHash,
+    /// Pick the instance with the lowest measured round trip time
+    ///
+    /// Instances without a measurement yet count as closest, so each one
+    /// gets measured.
+    LowestLatency,
 }
 
 /// Load balancer configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoadBalancerConfig {
+    /// How instances are picked
     pub strategy: LoadBalancingStrategy,
-    pub decay_time: std::time::Duration,
-    pub rtt_threshold: std::time::Duration,
 }
 
 impl Default for LoadBalancerConfig {
     fn default() -> Self {
         Self {
             strategy: LoadBalancingStrategy::LeastLoaded,
-            decay_time: std::time::Duration::from_secs(10),
-            rtt_threshold: std::time::Duration::from_millis(100),
         }
     }
 }
 
-/// Service load statistics
+/// Load statistics of one instance
 #[derive(Debug, Clone)]
 pub struct ServiceLoad {
+    /// The instance
     pub service: ServiceInfo,
+    /// Load reported for the instance; lower is better
     pub current_load: f64,
-    pub response_time: std::time::Duration,
+    /// Round trip time of the most recent request, verification or health check
+    pub response_time: Duration,
+    /// Share of recent requests that succeeded
     pub success_rate: f64,
+    /// Health from the latest health checks
+    pub health: HealthStatus,
+    /// Smooth weighted round robin credit
+    credit: i64,
+}
+
+impl ServiceLoad {
+    fn new(service: ServiceInfo) -> Self {
+        Self {
+            service,
+            current_load: 0.0,
+            response_time: Duration::ZERO,
+            success_rate: 1.0,
+            health: HealthStatus::Healthy,
+            credit: 0,
+        }
+    }
 }
 
-/// Load balancer for service discovery
+/// Picks instances of a service according to a [`LoadBalancingStrategy`]
+///
+/// Instances are keyed by [`ServiceInfo::service_id`].
+#[derive(Debug)]
 pub struct LoadBalancer {
     config: LoadBalancerConfig,
-    services: Arc<RwLock<Vec<ServiceLoad>>>,
-    load_metrics: Arc<RwLock<dashmap::DashMap<String, PeakEwma>>>,
-    changes_tx: mpsc::Sender<Change<String, ServiceLoad>>,
-    changes_rx: mpsc::Receiver<Change<String, ServiceLoad>>,
+    services: RwLock<HashMap<ServiceId, ServiceLoad>>,
+    next: AtomicUsize,
+    rng: SharedRng,
 }
 
 impl LoadBalancer {
-    /// Create a new load balancer
+    /// Create an empty load balancer
     pub fn new(config: LoadBalancerConfig) -> Self {
-        let (changes_tx, changes_rx) = mpsc::channel(100);
-        
         Self {
             config,
-            services: Arc::new(RwLock::new(Vec::new())),
-            load_metrics: Arc::new(RwLock::new(dashmap::DashMap::new())),
-            changes_tx,
-            changes_rx,
+            services: RwLock::new(HashMap::new()),
+            next: AtomicUsize::new(0),
+            rng: SharedRng::new(),
         }
     }
 
-    /// Add or update a service
-    pub async fn update_service(&self, service: ServiceInfo, load: f64) -> Result<()> {
-        let service_load = ServiceLoad {
-            service: service.clone(),
-            current_load: load,
-            response_time: std::time::Duration::default(),
-            success_rate: 1.0,
-        };
+    /// Make random picks with `rng`
+    pub fn with_rng(mut self, rng: SharedRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// The balancer configuration
+    pub fn config(&self) -> &LoadBalancerConfig {
+        &self.config
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<ServiceId, ServiceLoad>> {
+        self.services.read().unwrap_or_else(|e| e.into_inner())
+    }
 
-        // Update load metrics
-        self.load_metrics.write().entry(service.id().to_string())
-            .or_insert_with(|| PeakEwma::new(
-                self.config.decay_time,
-                self.config.rtt_threshold,
-            ));
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<ServiceId, ServiceLoad>> {
+        self.services.write().unwrap_or_else(|e| e.into_inner())
+    }
 
-        // Send change notification
-        self.changes_tx.send(Change::Insert(service.id().to_string(), service_load)).await
-            .map_err(|e| crate::error::DiscoveryError::other(format!("Failed to send change: {}", e)))?;
+    /// Add an instance, or refresh its details while keeping its statistics
+    pub fn update_service(&self, service: ServiceInfo) {
+        let id = service.service_id();
+        let mut services = self.write();
+        match services.get_mut(&id) {
+            Some(load) => load.service = service,
+            None => {
+                services.insert(id, ServiceLoad::new(service));
+            }
+        }
+    }
 
-        Ok(())
+    /// Remove an instance
+    pub fn remove_service(&self, service_id: &ServiceId) -> bool {
+        self.write().remove(service_id).is_some()
     }
 
-    /// Remove a service
-    pub async fn remove_service(&self, service_id: &str) -> Result<()> {
-        self.load_metrics.write().remove(service_id);
-        self.changes_tx.send(Change::Remove(service_id.to_string())).await
-            .map_err(|e| crate::error::DiscoveryError::other(format!("Failed to send removal: {}", e)))?;
-        Ok(())
+    /// Keep only the instances whose IDs satisfy `keep`
+    pub fn retain(&self, mut keep: impl FnMut(&ServiceId) -> bool) {
+        self.write().retain(|id, _| keep(id));
     }
 
-    /// Select the best service based on the configured strategy
+    /// Set the load reported by an instance
+    pub fn set_load(&self, service_id: &ServiceId, load: f64) {
+        if let Some(service) = self.write().get_mut(service_id) {
+            service.current_load = load;
+        }
+    }
+
+    /// Set the measured round trip time of an instance
+    pub fn set_response_time(&self, service_id: &ServiceId, response_time: Duration) {
+        if let Some(service) = self.write().get_mut(service_id) {
+            service.response_time = response_time;
+        }
+    }
+
+    /// Set the health of an instance
+    pub fn set_health(&self, service_id: &ServiceId, health: HealthStatus) {
+        if let Some(service) = self.write().get_mut(service_id) {
+            service.health = health;
+        }
+    }
+
+    /// Current statistics of all instances
+    pub fn services(&self) -> Vec<ServiceLoad> {
+        self.read().values().cloned().collect()
+    }
+
+    /// Number of instances
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Whether no instances are known
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Select the best instance based on the configured strategy
     pub fn select_service(&self) -> Option<ServiceInfo> {
-        let services = self.services.read();
-        if services.is_empty() {
+        self.select(None)
+    }
+
+    /// Select an instance for `client_key`
+    ///
+    /// With [`LoadBalancingStrategy::ConsistentHash`] the same key keeps
+    /// getting the same instance; other strategies ignore the key.
+    pub fn select_service_for(&self, client_key: &str) -> Option<ServiceInfo> {
+        self.select(Some(client_key))
+    }
+
+    fn select(&self, client_key: Option<&str>) -> Option<ServiceInfo> {
+        let mut services = self.write();
+        let preferred = if services.values().any(|s| s.health == HealthStatus::Healthy) {
+            HealthStatus::Healthy
+        } else {
+            HealthStatus::Degraded
+        };
+        let mut candidates: Vec<(&ServiceId, &mut ServiceLoad)> =
+            services.iter_mut().filter(|(_, s)| s.health == preferred).collect();
+        if candidates.is_empty() {
             return None;
         }
+        // Map order is arbitrary; keep selection independent of it
+        candidates.sort_by_key(|(id, _)| *id);
 
-        match self.config.strategy {
-            LoadBalancingStrategy::RoundRobin => {
-                // Simple round-robin selection
-                let next_index = rand::random::<usize>() % services.len();
-                Some(services[next_index].service.clone())
+        match (self.config.strategy, client_key) {
+            (LoadBalancingStrategy::ConsistentHash, Some(key)) => candidates
+                .iter()
+                .max_by_key(|(id, _)| {
+                    let mut hasher = DefaultHasher::new();
+                    (key, id.as_str()).hash(&mut hasher);
+                    hasher.finish()
+                })
+                .map(|(_, s)| s.service.clone()),
+            (LoadBalancingStrategy::RoundRobin | LoadBalancingStrategy::ConsistentHash, _) => {
+                let next_index = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                Some(candidates[next_index].1.service.clone())
             }
-            LoadBalancingStrategy::LeastLoaded => {
-                // Select service with lowest load
-                services.iter()
-                    .min_by(|a, b| a.current_load.partial_cmp(&b.current_load).unwrap())
-                    .map(|s| s.service.clone())
-            }
-            LoadBalancingStrategy::Random => {
+            (LoadBalancingStrategy::LeastLoaded, _) => candidates
+                .iter()
+                .min_by(|(_, a), (_, b)| a.current_load.total_cmp(&b.current_load))
+                .map(|(_, s)| s.service.clone()),
+            (LoadBalancingStrategy::LowestLatency, _) => candidates
+                .iter()
+                .min_by_key(|(_, s)| s.response_time)
+                .map(|(_, s)| s.service.clone()),
+            (LoadBalancingStrategy::Random, _) => {
                 // Random selection weighted by inverse load
-                let total_inverse_load: f64 = services.iter()
-                    .map(|s| 1.0 / (s.current_load + 1.0))
-                    .sum();
-
-                let mut random = rand::random::<f64>() * total_inverse_load;
-                for service in services.iter() {
-                    let inverse_load = 1.0 / (service.current_load + 1.0);
-                    if random <= inverse_load {
+                let weight = |s: &ServiceLoad| 1.0 / (s.current_load.max(0.0) + 1.0);
+                let total: f64 = candidates.iter().map(|(_, s)| weight(s)).sum();
+                let mut random = self.rng.random::<f64>() * total;
+                for (_, service) in &candidates {
+                    if random <= weight(service) {
                         return Some(service.service.clone());
                     }
-                    random -= inverse_load;
+                    random -= weight(service);
                 }
-                None
+                candidates.last().map(|(_, s)| s.service.clone())
             }
-        }
-    }
-
-    /// Update service metrics based on request result
-    pub fn record_request(&self, service_id: &str, duration: std::time::Duration, success: bool) {
-        if let Some(mut metric) = self.load_metrics.write().get_mut(service_id) {
-            metric.record_rtt(duration);
-        }
+            (LoadBalancingStrategy::WeightedRoundRobin, _) => {
+                let priority = candidates.iter().map(|(_, s)| s.service.priority).min()?;
+                candidates.retain(|(_, s)| s.service.priority == priority);
+                if candidates.iter().any(|(_, s)| s.service.weight > 0) {
+                    candidates.retain(|(_, s)| s.service.weight > 0);
+                }
+                let weight = |s: &ServiceLoad| i64::from(s.service.weight.max(1));
+                let total: i64 = candidates.iter().map(|(_, s)| weight(s)).sum();
 
-        if let Some(services) = self.services.try_write() {
-            if let Some(service) = services.iter_mut().find(|s| s.service.id() == service_id) {
-                service.response_time = duration;
-                if !success {
-                    service.success_rate *= 0.95; // Decay success rate on failure
+                // Smooth weighted round robin: every instance earns its
+                // weight, the richest is picked and pays the total
+                for (_, service) in candidates.iter_mut() {
+                    service.credit += weight(service);
                 }
+                let (_, picked) = candidates
+                    .into_iter()
+                    .rev()
+                    .max_by_key(|(_, s)| s.credit)?;
+                picked.credit -= total;
+                Some(picked.service.clone())
             }
         }
-
-        // Update prometheus metrics
-        metrics::histogram!("service_response_time", duration.as_secs_f64(),
-            "service_id" => service_id.to_string()
-        );
-        metrics::counter!("service_request_total",
-            "service_id" => service_id.to_string(),
-            "success" => success.to_string()
-        ).increment(1);
     }
-}
 
-impl Stream for LoadBalancer {
-    type Item = Change<String, ServiceLoad>;
+    /// Record the outcome of a request to an instance
+    pub fn record_request(&self, service_id: &ServiceId, duration: Duration, success: bool) {
+        if let Some(service) = self.write().get_mut(service_id) {
+            service.response_time = duration;
+            // Exponentially weighted, so old failures fade out
+            service.success_rate = service.success_rate * 0.95 + if success { 0.05 } else { 0.0 };
+        }
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        self.changes_rx.poll_recv(cx)
+        #[cfg(feature = "metrics")]
+        {
+            metrics::histogram!("service_response_time", "service_id" => service_id.to_string())
+                .record(duration.as_secs_f64());
+            metrics::counter!(
+                "service_request_total",
+                "service_id" => service_id.to_string(),
+                "success" => success.to_string()
+            )
+            .increment(1);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio;
 
-    #[tokio::test]
-    async fn test_load_balancer() {
-        let config = LoadBalancerConfig::default();
-        let balancer = LoadBalancer::new(config);
+    #[test]
+    fn test_load_balancer() {
+        let balancer = LoadBalancer::new(LoadBalancerConfig::default());
+        let service1 = ServiceInfo::new("service1", "_test._tcp", 8080, None).unwrap();
+        let service2 = ServiceInfo::new("service2", "_test._tcp", 8081, None).unwrap();
+        let id1 = service1.service_id();
+        let id2 = service2.service_id();
+
+        balancer.update_service(service1.clone());
+        balancer.update_service(service2.clone());
+        balancer.set_load(&id1, 0.5);
+        balancer.set_load(&id2, 1.0);
+        assert_eq!(balancer.select_service().unwrap().name, "service1");
+
+        // Refreshing an instance keeps its statistics
+        balancer.update_service(service1.clone());
+        assert_eq!(balancer.select_service().unwrap().name, "service1");
+
+        // Degraded instances are only used when nothing healthy is left
+        balancer.set_health(&id1, HealthStatus::Degraded);
+        assert_eq!(balancer.select_service().unwrap().name, "service2");
+        balancer.set_health(&id2, HealthStatus::Unhealthy);
+        assert_eq!(balancer.select_service().unwrap().name, "service1");
+        balancer.set_health(&id1, HealthStatus::Unhealthy);
+        assert!(balancer.select_service().is_none());
+
+        balancer.record_request(&id2, Duration::from_millis(100), false);
+        assert!(balancer.services().iter().any(|s| s.success_rate < 1.0));
+
+        assert!(balancer.remove_service(&id1));
+        assert_eq!(balancer.len(), 1);
+    }
+
+    fn balancer_with(strategy: LoadBalancingStrategy, services: &[ServiceInfo]) -> LoadBalancer {
+        let balancer = LoadBalancer::new(LoadBalancerConfig { strategy });
+        for service in services {
+            balancer.update_service(service.clone());
+        }
+        balancer
+    }
+
+    fn pick_names(balancer: &LoadBalancer, count: usize) -> Vec<String> {
+        (0..count).map(|_| balancer.select_service().unwrap().name).collect()
+    }
+
+    #[test]
+    fn test_round_robin_takes_turns() {
+        let services: Vec<ServiceInfo> = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ServiceInfo::new(*name, "_test._tcp", 8080 + i as u16, None).unwrap())
+            .collect();
+        let balancer = balancer_with(LoadBalancingStrategy::RoundRobin, &services);
+
+        let picks = pick_names(&balancer, 6);
+        assert_eq!(picks[..3], picks[3..]);
+        let mut first_round = picks[..3].to_vec();
+        first_round.sort();
+        assert_eq!(first_round, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_weighted_round_robin_uses_srv_records() {
+        let services = vec![
+            ServiceInfo::new("heavy", "_test._tcp", 8080, None).unwrap().with_srv(10, 3),
+            ServiceInfo::new("light", "_test._tcp", 8081, None).unwrap().with_srv(10, 1),
+            ServiceInfo::new("idle", "_test._tcp", 8082, None).unwrap().with_srv(10, 0),
+            ServiceInfo::new("backup", "_test._tcp", 8083, None).unwrap().with_srv(20, 100),
+        ];
+        let balancer = balancer_with(LoadBalancingStrategy::WeightedRoundRobin, &services);
 
-        // Add test services
-        let service1 = ServiceInfo::new("service1", "_test._tcp", 8080, None);
-        let service2 = ServiceInfo::new("service2", "_test._tcp", 8081, None);
+        let picks = pick_names(&balancer, 8);
+        assert_eq!(picks.iter().filter(|name| *name == "heavy").count(), 6);
+        assert_eq!(picks.iter().filter(|name| *name == "light").count(), 2);
+        // Smooth: the light instance is not starved for a whole cycle
+        assert_ne!(picks[..4].iter().filter(|name| *name == "heavy").count(), 4);
 
-        balancer.update_service(service1.clone(), 0.5).await.unwrap();
-        balancer.update_service(service2.clone(), 1.0).await.unwrap();
+        // Higher priority values are only used once the lower ones are gone
+        for service in &services[..3] {
+            balancer.set_health(&service.service_id(), HealthStatus::Unhealthy);
+        }
+        assert_eq!(pick_names(&balancer, 2), ["backup", "backup"]);
+    }
 
-        // Test service selection
-        let selected = balancer.select_service().unwrap();
-        assert!(selected.id() == service1.id() || selected.id() == service2.id());
+    #[test]
+    fn test_seeded_random_picks_repeat() {
+        let services: Vec<ServiceInfo> = (0..4)
+            .map(|i| ServiceInfo::new(format!("node{i}"), "_test._tcp", 8080 + i, None).unwrap())
+            .collect();
+        let picks = |seed| {
+            let balancer = balancer_with(LoadBalancingStrategy::Random, &services).with_rng(SharedRng::seeded(seed));
+            pick_names(&balancer, 20)
+        };
 
-        // Test metric recording
-        balancer.record_request(&service1.id(), std::time::Duration::from_millis(50), true);
-        balancer.record_request(&service2.id(), std::time::Duration::from_millis(100), false);
+        assert_eq!(picks(7), picks(7));
+        assert_ne!(picks(7), picks(8));
+    }
 
-        // Test service removal
-        balancer.remove_service(&service1.id()).await.unwrap();
-        assert_eq!(balancer.select_service().unwrap().id(), service2.id());
+    #[test]
+    fn test_lowest_latency() {
+        let near = ServiceInfo::new("near", "_test._tcp", 8080, None).unwrap();
+        let far = ServiceInfo::new("far", "_test._tcp", 8081, None).unwrap();
+        let balancer = balancer_with(LoadBalancingStrategy::LowestLatency, &[near.clone(), far.clone()]);
+
+        balancer.set_response_time(&near.service_id(), Duration::from_millis(2));
+        // Not measured yet, so it gets tried
+        assert_eq!(balancer.select_service().unwrap().name, "far");
+
+        balancer.record_request(&far.service_id(), Duration::from_millis(40), true);
+        assert_eq!(balancer.select_service().unwrap().name, "near");
+    }
+
+    #[test]
+    fn test_consistent_hash_is_sticky() {
+        let services: Vec<ServiceInfo> = (0..5)
+            .map(|i| ServiceInfo::new(format!("node{i}"), "_test._tcp", 8080 + i, None).unwrap())
+            .collect();
+        let balancer = balancer_with(LoadBalancingStrategy::ConsistentHash, &services);
+        let keys: Vec<String> = (0..50).map(|i| format!("client-{i}")).collect();
+
+        let before: Vec<String> = keys
+            .iter()
+            .map(|key| balancer.select_service_for(key).unwrap().name)
+            .collect();
+        for (key, name) in keys.iter().zip(&before) {
+            assert_eq!(&balancer.select_service_for(key).unwrap().name, name);
+        }
+
+        // Only the keys of a failed instance move
+        balancer.set_health(&services[0].service_id(), HealthStatus::Unhealthy);
+        for (key, name) in keys.iter().zip(&before) {
+            let after = balancer.select_service_for(key).unwrap().name;
+            if name == "node0" {
+                assert_ne!(after, "node0");
+            } else {
+                assert_eq!(&after, name);
+            }
+        }
     }
 }
@@ -0,0 +1,282 @@
+//! Validation of service metadata against per-type schemas
+//!
+//! A [`MetadataSchema`] lists the TXT attributes a service type is expected
+//! to advertise: which keys are required and what their values must look
+//! like. Discovery checks services against the schemas in
+//! [`DiscoveryConfig`](crate::config::DiscoveryConfig) and publishes
+//! [`ServiceEvent::SchemaViolation`](crate::service::ServiceEvent::SchemaViolation)
+//! for services that do not conform; depending on the schema's
+//! [`SchemaAction`] they are then dropped or kept.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    service::ServiceInfo,
+    types::ServiceType,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Type an attribute value must parse as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    /// Any text
+    String,
+    /// Signed integer
+    Integer,
+    /// Floating point number
+    Float,
+    /// `true` or `false`
+    Bool,
+}
+
+impl ValueType {
+    fn accepts(self, value: &str) -> bool {
+        match self {
+            Self::String => true,
+            Self::Integer => value.parse::<i64>().is_ok(),
+            Self::Float => value.parse::<f64>().is_ok(),
+            Self::Bool => value.parse::<bool>().is_ok(),
+        }
+    }
+}
+
+/// Constraints on one attribute
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttributeRule {
+    /// Whether the attribute must be present
+    #[serde(default)]
+    pub required: bool,
+    /// Type the value must parse as
+    #[serde(default)]
+    pub value_type: Option<ValueType>,
+    /// Regular expression the whole value must match
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+impl AttributeRule {
+    /// Rule for an attribute that must be present
+    pub fn required() -> Self {
+        Self {
+            required: true,
+            ..Self::default()
+        }
+    }
+
+    /// Rule for an attribute that is checked only when present
+    pub fn optional() -> Self {
+        Self::default()
+    }
+
+    /// Require the value to parse as `value_type`
+    pub fn with_type(mut self, value_type: ValueType) -> Self {
+        self.value_type = Some(value_type);
+        self
+    }
+
+    /// Require the whole value to match `pattern`
+    pub fn with_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+}
+
+/// What happens to services that violate their schema
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaAction {
+    /// Drop the service from discovery results and the registry
+    #[default]
+    Reject,
+    /// Keep the service; only publish the violation event
+    Flag,
+}
+
+/// Expected metadata of a service type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataSchema {
+    /// Service type the schema applies to
+    pub service_type: ServiceType,
+    /// Rules by attribute key
+    #[serde(default)]
+    pub attributes: BTreeMap<String, AttributeRule>,
+    /// What happens to services that violate the schema
+    #[serde(default)]
+    pub action: SchemaAction,
+}
+
+impl MetadataSchema {
+    /// Create an empty schema for `service_type`
+    pub fn new(service_type: ServiceType) -> Self {
+        Self {
+            service_type,
+            attributes: BTreeMap::new(),
+            action: SchemaAction::default(),
+        }
+    }
+
+    /// Add a rule for the attribute `key`
+    pub fn with_attribute(mut self, key: impl Into<String>, rule: AttributeRule) -> Self {
+        self.attributes.insert(key.into(), rule);
+        self
+    }
+
+    /// Set what happens to services that violate the schema
+    pub fn with_action(mut self, action: SchemaAction) -> Self {
+        self.action = action;
+        self
+    }
+}
+
+/// Outcome of checking a service that violates its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaCheck {
+    /// What should happen to the service
+    pub action: SchemaAction,
+    /// Description of each violated rule
+    pub violations: Vec<String>,
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    key: String,
+    required: bool,
+    value_type: Option<ValueType>,
+    pattern: Option<Regex>,
+}
+
+#[derive(Debug)]
+struct CompiledSchema {
+    service_type: ServiceType,
+    rules: Vec<CompiledRule>,
+    action: SchemaAction,
+}
+
+/// Checks services against a set of [`MetadataSchema`]s
+#[derive(Debug, Default)]
+pub struct SchemaValidator {
+    schemas: Vec<CompiledSchema>,
+}
+
+impl SchemaValidator {
+    /// Compile `schemas`
+    ///
+    /// # Errors
+    ///
+    /// Returns a configuration error if a pattern is not a valid regular expression
+    pub fn new(schemas: &[MetadataSchema]) -> Result<Self> {
+        let schemas = schemas
+            .iter()
+            .map(|schema| {
+                let rules = schema
+                    .attributes
+                    .iter()
+                    .map(|(key, rule)| {
+                        let pattern = rule
+                            .pattern
+                            .as_deref()
+                            .map(|pattern| {
+                                Regex::new(&format!("^(?:{pattern})$")).map_err(|e| {
+                                    DiscoveryError::configuration(format!(
+                                        "Invalid pattern for attribute '{key}' of {}: {e}",
+                                        schema.service_type
+                                    ))
+                                })
+                            })
+                            .transpose()?;
+                        Ok(CompiledRule {
+                            key: key.clone(),
+                            required: rule.required,
+                            value_type: rule.value_type,
+                            pattern,
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(CompiledSchema {
+                    service_type: schema.service_type.clone(),
+                    rules,
+                    action: schema.action,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { schemas })
+    }
+
+    /// Whether no schemas are configured
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Check `service` against the schemas for its type
+    ///
+    /// Returns `None` if the service conforms or no schema applies.
+    pub fn check(&self, service: &ServiceInfo) -> Option<SchemaCheck> {
+        let mut action = SchemaAction::Flag;
+        let mut violations = Vec::new();
+        for schema in self.schemas.iter().filter(|s| s.service_type == service.service_type) {
+            let before = violations.len();
+            for rule in &schema.rules {
+                let Some(value) = service.attributes.get(&rule.key) else {
+                    if rule.required {
+                        violations.push(format!("missing required attribute '{}'", rule.key));
+                    }
+                    continue;
+                };
+                if let Some(value_type) = rule.value_type
+                    && !value_type.accepts(value)
+                {
+                    violations.push(format!("attribute '{}' is not a valid {value_type:?}: '{value}'", rule.key));
+                }
+                if let Some(pattern) = &rule.pattern
+                    && !pattern.is_match(value)
+                {
+                    violations.push(format!("attribute '{}' does not match '{}': '{value}'", rule.key, pattern));
+                }
+            }
+            if violations.len() > before && schema.action == SchemaAction::Reject {
+                action = SchemaAction::Reject;
+            }
+        }
+        (!violations.is_empty()).then_some(SchemaCheck { action, violations })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer_schema() -> MetadataSchema {
+        MetadataSchema::new(ServiceType::new("_ipp._tcp").unwrap())
+            .with_attribute("rp", AttributeRule::required())
+            .with_attribute("pages", AttributeRule::optional().with_type(ValueType::Integer))
+            .with_attribute("color", AttributeRule::optional().with_pattern("T|F"))
+    }
+
+    #[test]
+    fn test_schema_check() {
+        let validator = SchemaValidator::new(&[printer_schema()]).unwrap();
+
+        let good = ServiceInfo::new("printer", "_ipp._tcp", 631, Some(vec![("rp", "ipp/print"), ("color", "T")])).unwrap();
+        assert!(validator.check(&good).is_none());
+
+        // Other types are not checked
+        let other = ServiceInfo::new("web", "_http._tcp", 80, None).unwrap();
+        assert!(validator.check(&other).is_none());
+
+        let bad = ServiceInfo::new("printer", "_ipp._tcp", 631, Some(vec![("pages", "many"), ("color", "TF")])).unwrap();
+        let check = validator.check(&bad).unwrap();
+        assert_eq!(check.action, SchemaAction::Reject);
+        assert_eq!(check.violations.len(), 3, "{:?}", check.violations);
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let schema = printer_schema().with_attribute("bad", AttributeRule::optional().with_pattern("("));
+        assert!(matches!(
+            SchemaValidator::new(&[schema]),
+            Err(DiscoveryError::Configuration(_))
+        ));
+    }
+}
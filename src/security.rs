@@ -9,6 +9,11 @@ use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use ring::signature::{self, KeyPair, Ed25519KeyPair};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+pub mod policy;
+pub mod sig0;
+pub mod signing;
+pub mod tsig;
+
 #[allow(dead_code)]
 const SEED_LENGTH: usize = 32;
 
@@ -83,26 +88,7 @@ impl ServiceVerifier {
     }
 
     fn generate_signing_message(&self, service: &ServiceInfo, timestamp: u64) -> Result<String> {
-        let mut sorted_attrs: Vec<_> = service.attributes.iter()
-            .filter(|(k, _)| *k != "signature" && *k != "timestamp")
-            .collect();
-
-        sorted_attrs.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
-
-        let mut message = format!(
-            "{}|{}|{}|{}",
-            service.name,
-            service.service_type.full_name(),
-            service.address,
-            service.port
-        );
-
-        for (k, v) in sorted_attrs {
-            message.push_str(&format!("|{k}={v}"));
-        }
-
-        message.push_str(&format!("|timestamp={timestamp}"));
-        Ok(message)
+        Ok(signing::canonical_message(service, timestamp))
     }
 }
 
@@ -0,0 +1,271 @@
+//! Access-control policies for discovered services
+//!
+//! An [`AccessPolicy`] decides which discovered services reach the caller.
+//! Deny rules always win; if any allow rules are present a service must match
+//! at least one of them, otherwise everything not denied is permitted.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    security::signing::{SignatureStatus, SignatureVerifier},
+    service::ServiceInfo,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, str::FromStr, sync::Arc};
+use tracing::debug;
+
+/// An IP network in CIDR notation, e.g. `192.168.1.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Subnet {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Subnet {
+    /// Create a subnet, validating the prefix length for the address family
+    pub fn new(network: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max {
+            return Err(DiscoveryError::configuration(format!(
+                "Prefix length {prefix_len} is too long for {network}"
+            )));
+        }
+        Ok(Self { network, prefix_len })
+    }
+
+    /// Whether `addr` lies inside this subnet
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*addr) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = DiscoveryError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| DiscoveryError::configuration(format!("Invalid subnet address: {s}")))?;
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| DiscoveryError::configuration(format!("Invalid subnet prefix: {s}")))?,
+            None if network.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(network, prefix_len)
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = DiscoveryError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Subnet> for String {
+    fn from(subnet: Subnet) -> Self {
+        subnet.to_string()
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// A condition a service can match
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    /// Service address is inside the subnet
+    Subnet(Subnet),
+    /// Service type matches, with or without its domain (`_http._tcp`)
+    ServiceType(String),
+    /// TXT attribute is present, optionally with an exact value
+    Attribute {
+        /// Attribute key
+        key: String,
+        /// Required value; any value matches if `None`
+        value: Option<String>,
+    },
+    /// Signature check produced this status
+    Signature(SignatureStatus),
+}
+
+impl PolicyRule {
+    fn matches(&self, service: &ServiceInfo, signature: Option<&SignatureStatus>) -> bool {
+        match self {
+            Self::Subnet(subnet) => subnet.contains(&service.address),
+            Self::ServiceType(service_type) => {
+                let expected = service_type.trim_end_matches('.');
+                let actual = &service.service_type;
+                let without_domain = format!("{}{}", actual.service_name(), actual.protocol());
+                actual.to_string().trim_end_matches('.') == expected || without_domain == expected
+            }
+            Self::Attribute { key, value } => match (service.get_attribute(key), value) {
+                (Some(_), None) => true,
+                (Some(actual), Some(expected)) => actual == expected,
+                (None, _) => false,
+            },
+            Self::Signature(status) => signature == Some(status),
+        }
+    }
+}
+
+/// Allow and deny lists applied to discovered services
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessPolicy {
+    /// Services must match one of these rules, unless the list is empty
+    #[serde(default)]
+    pub allow: Vec<PolicyRule>,
+    /// Services matching any of these rules are dropped
+    #[serde(default)]
+    pub deny: Vec<PolicyRule>,
+}
+
+impl AccessPolicy {
+    /// Create an empty policy that permits everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an allow rule
+    pub fn allow(mut self, rule: PolicyRule) -> Self {
+        self.allow.push(rule);
+        self
+    }
+
+    /// Add a deny rule
+    pub fn deny(mut self, rule: PolicyRule) -> Self {
+        self.deny.push(rule);
+        self
+    }
+
+    /// Whether any rule depends on the signature status
+    pub fn uses_signatures(&self) -> bool {
+        self.allow.iter().chain(&self.deny).any(|r| matches!(r, PolicyRule::Signature(_)))
+    }
+
+    /// Evaluate the policy for a service with a known signature status
+    pub fn permits(&self, service: &ServiceInfo, signature: Option<&SignatureStatus>) -> bool {
+        if self.deny.iter().any(|rule| rule.matches(service, signature)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(service, signature))
+    }
+}
+
+/// Applies an [`AccessPolicy`], checking signatures when the policy needs them
+pub struct PolicyEngine {
+    policy: AccessPolicy,
+    verifier: Arc<SignatureVerifier>,
+}
+
+impl PolicyEngine {
+    /// Create an engine that checks signatures with `verifier`
+    pub fn new(policy: AccessPolicy, verifier: Arc<SignatureVerifier>) -> Self {
+        Self { policy, verifier }
+    }
+
+    /// The policy being enforced
+    pub fn policy(&self) -> &AccessPolicy {
+        &self.policy
+    }
+
+    /// Whether a single service is permitted
+    pub async fn permits(&self, service: &ServiceInfo) -> Result<bool> {
+        let signature = if self.policy.uses_signatures() {
+            Some(self.verifier.check(service).await?)
+        } else {
+            None
+        };
+        Ok(self.policy.permits(service, signature.as_ref()))
+    }
+
+    /// Drop services the policy does not permit
+    pub async fn filter(&self, services: Vec<ServiceInfo>) -> Result<Vec<ServiceInfo>> {
+        let mut permitted = Vec::with_capacity(services.len());
+        for service in services {
+            if self.permits(&service).await? {
+                permitted.push(service);
+            } else {
+                debug!("Access policy rejected service {} at {}", service.name, service.address);
+            }
+        }
+        Ok(permitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::security::signing::{ServiceSigner, TrustPolicy};
+
+    fn service_at(addr: &str) -> ServiceInfo {
+        ServiceInfo::new("svc", "_http._tcp", 80, Some(vec![("env", "prod")]))
+            .unwrap()
+            .with_address(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn test_subnet_contains() {
+        let subnet: Subnet = "10.1.0.0/16".parse().unwrap();
+        assert!(subnet.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!subnet.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!subnet.contains(&"::1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<Subnet>().unwrap().contains(&"8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn test_allow_and_deny_rules() {
+        let policy = AccessPolicy::new()
+            .allow(PolicyRule::Subnet("192.168.0.0/16".parse().unwrap()))
+            .deny(PolicyRule::Attribute { key: "env".into(), value: Some("prod".into()) });
+
+        assert!(!policy.permits(&service_at("192.168.1.10"), None));
+        assert!(!policy.permits(&service_at("10.0.0.1"), None));
+
+        let staging = service_at("192.168.1.10").with_attribute("env", "staging");
+        assert!(policy.permits(&staging, None));
+
+        let by_type = AccessPolicy::new().allow(PolicyRule::ServiceType("_http._tcp".into()));
+        assert!(by_type.permits(&service_at("10.0.0.1"), None));
+    }
+
+    #[tokio::test]
+    async fn test_signature_rules() {
+        let (signer, _) = ServiceSigner::generate().unwrap();
+        let engine = PolicyEngine::new(
+            AccessPolicy::new().allow(PolicyRule::Signature(SignatureStatus::Valid)),
+            Arc::new(SignatureVerifier::new(TrustPolicy::TrustOnFirstUse)),
+        );
+
+        let mut signed = service_at("10.0.0.1");
+        signer.sign(&mut signed).unwrap();
+        let mut unsigned = service_at("10.0.0.2");
+        unsigned.name = "other".into();
+
+        let permitted = engine.filter(vec![signed, unsigned]).await.unwrap();
+        assert_eq!(permitted.len(), 1);
+        assert_eq!(permitted[0].name, "svc");
+    }
+}
@@ -0,0 +1,142 @@
+//! SIG(0) keys for signing DNS updates (RFC 2931)
+//!
+//! SIG(0) authenticates updates with a private key whose public half is
+//! published in the zone as a `KEY` record, so the DNS server never holds a
+//! shared secret. It is the alternative to [`tsig`](super::tsig) where
+//! distributing HMAC secrets is impractical; which one signs updates is
+//! selected in [`DnsSdConfig`](crate::config::DnsSdConfig).
+
+use crate::error::{DiscoveryError, Result};
+use trust_dns_proto::rr::{
+    dnssec::{rdata::KEY, Algorithm, KeyFormat, KeyPair, Private, SigSigner},
+    Name,
+};
+
+/// Signature algorithm of a SIG(0) key
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sig0Algorithm {
+    /// ECDSA with curve P-256 and SHA-256
+    EcdsaP256Sha256,
+    /// ECDSA with curve P-384 and SHA-384
+    EcdsaP384Sha384,
+    /// Ed25519
+    Ed25519,
+}
+
+impl Sig0Algorithm {
+    fn to_dns_algorithm(self) -> Algorithm {
+        match self {
+            Self::EcdsaP256Sha256 => Algorithm::ECDSAP256SHA256,
+            Self::EcdsaP384Sha384 => Algorithm::ECDSAP384SHA384,
+            Self::Ed25519 => Algorithm::ED25519,
+        }
+    }
+}
+
+/// A named SIG(0) private key
+#[derive(Clone)]
+pub struct Sig0Key {
+    name: Name,
+    algorithm: Sig0Algorithm,
+    pkcs8: Vec<u8>,
+}
+
+impl Sig0Key {
+    /// Load a PKCS#8 encoded private key
+    ///
+    /// `name` is the owner of the `KEY` record the server checks signatures
+    /// against.
+    pub fn from_pkcs8(name: &str, algorithm: Sig0Algorithm, pkcs8: &[u8]) -> Result<Self> {
+        let key = Self {
+            name: Name::from_ascii(name)
+                .map_err(|e| DiscoveryError::security(format!("Invalid SIG(0) key name: {}", e)))?,
+            algorithm,
+            pkcs8: pkcs8.to_vec(),
+        };
+        key.key_pair()?;
+        Ok(key)
+    }
+
+    /// Generate a new key
+    pub fn generate(name: &str, algorithm: Sig0Algorithm) -> Result<Self> {
+        let pkcs8 = KeyPair::generate_pkcs8(algorithm.to_dns_algorithm())
+            .map_err(|e| DiscoveryError::security(format!("Failed to generate SIG(0) key: {}", e)))?;
+        Self::from_pkcs8(name, algorithm, &pkcs8)
+    }
+
+    /// Get the key name
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// Get the signature algorithm
+    pub fn algorithm(&self) -> Sig0Algorithm {
+        self.algorithm
+    }
+
+    /// Get the PKCS#8 encoding of the private key, for storing it
+    pub fn to_pkcs8(&self) -> &[u8] {
+        &self.pkcs8
+    }
+
+    /// Data of the `KEY` record to publish at [`Self::name`]
+    pub fn key_record(&self) -> Result<KEY> {
+        self.key_pair()?
+            .to_sig0key(self.algorithm.to_dns_algorithm())
+            .map_err(|e| DiscoveryError::security(format!("Invalid SIG(0) key {}: {}", self.name, e)))
+    }
+
+    /// Create a signer for DNS messages
+    pub fn signer(&self) -> Result<SigSigner> {
+        Ok(SigSigner::sig0(self.key_record()?, self.key_pair()?, self.name.clone()))
+    }
+
+    fn key_pair(&self) -> Result<KeyPair<Private>> {
+        KeyFormat::Pkcs8
+            .decode_key(&self.pkcs8, None, self.algorithm.to_dns_algorithm())
+            .map_err(|e| DiscoveryError::security(format!("Invalid SIG(0) key {}: {}", self.name, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use trust_dns_proto::{
+        op::{Message, OpCode},
+        rr::{
+            dnssec::{rdata::DNSSECRData, Verifier},
+            RData,
+        },
+    };
+
+    #[test]
+    fn test_sig0_signing_and_verification() {
+        for algorithm in [Sig0Algorithm::EcdsaP256Sha256, Sig0Algorithm::EcdsaP384Sha384, Sig0Algorithm::Ed25519] {
+            let key = Sig0Key::generate("host.example.com.", algorithm).unwrap();
+            let key = Sig0Key::from_pkcs8("host.example.com.", algorithm, key.to_pkcs8()).unwrap();
+
+            let mut message = Message::new();
+            message.set_id(1234).set_op_code(OpCode::Update);
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+            message.finalize(&key.signer().unwrap(), now).unwrap();
+
+            // The signature covers the message without its SIG(0) record
+            let mut unsigned = Message::from_vec(&message.to_vec().unwrap()).unwrap();
+            let Some(RData::DNSSEC(DNSSECRData::SIG(sig))) = unsigned.take_signature()[0].data().cloned() else {
+                panic!("message has no SIG(0) record");
+            };
+            assert_eq!(sig.signer_name(), key.name());
+            let record = key.key_record().unwrap();
+            assert!(record.verify_message(&unsigned, sig.sig(), &sig).is_ok());
+
+            let other = Sig0Key::generate("host.example.com.", algorithm).unwrap();
+            assert!(other.key_record().unwrap().verify_message(&unsigned, sig.sig(), &sig).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_key_is_rejected() {
+        assert!(Sig0Key::from_pkcs8("host.example.com.", Sig0Algorithm::Ed25519, b"not a key").is_err());
+    }
+}
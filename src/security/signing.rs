@@ -0,0 +1,306 @@
+//! Ed25519-signed service advertisements
+//!
+//! A [`ServiceSigner`] adds three TXT attributes to a service before it is
+//! announced: [`PUBLIC_KEY_ATTRIBUTE`], [`TIMESTAMP_ATTRIBUTE`] and
+//! [`SIGNATURE_ATTRIBUTE`]. The signature covers the canonical form of the
+//! service (name, type, address, port, remaining attributes and timestamp).
+//!
+//! A [`SignatureVerifier`] checks discovered services against keys that are
+//! either pinned up front or learned on first use.
+
+use crate::{
+    error::{DiscoveryError, Result},
+    service::ServiceInfo,
+    verification::VerificationResult,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ring::{
+    rand::SystemRandom,
+    signature::{self, Ed25519KeyPair, KeyPair},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+
+/// TXT attribute carrying the base64 Ed25519 signature
+pub const SIGNATURE_ATTRIBUTE: &str = "signature";
+/// TXT attribute carrying the signing time in seconds since the Unix epoch
+pub const TIMESTAMP_ATTRIBUTE: &str = "timestamp";
+/// TXT attribute carrying the base64 Ed25519 public key of the signer
+pub const PUBLIC_KEY_ATTRIBUTE: &str = "public_key";
+
+/// An Ed25519 public key identifying a service publisher
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    /// Create a key from its raw 32 bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Parse a base64-encoded key as carried in TXT records
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = BASE64.decode(encoded.as_bytes())?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| DiscoveryError::security("Ed25519 public key must be 32 bytes"))?;
+        Ok(Self(bytes))
+    }
+
+    /// Encode the key as base64
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.0)
+    }
+
+    /// Raw key bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({})", self.to_base64())
+    }
+}
+
+/// Signs service advertisements with an Ed25519 key pair
+pub struct ServiceSigner {
+    key_pair: Ed25519KeyPair,
+}
+
+impl ServiceSigner {
+    /// Generate a new random key pair
+    ///
+    /// Returns the signer together with its PKCS#8 document so the key can be
+    /// persisted and reloaded with [`from_pkcs8`](Self::from_pkcs8).
+    pub fn generate() -> Result<(Self, Vec<u8>)> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())?;
+        let signer = Self::from_pkcs8(pkcs8.as_ref())?;
+        Ok((signer, pkcs8.as_ref().to_vec()))
+    }
+
+    /// Load a key pair from a PKCS#8 document
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        Ok(Self {
+            key_pair: Ed25519KeyPair::from_pkcs8(pkcs8)?,
+        })
+    }
+
+    /// Public key to distribute to discoverers
+    pub fn public_key(&self) -> PublicKey {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(self.key_pair.public_key().as_ref());
+        PublicKey(bytes)
+    }
+
+    /// Add public key, timestamp and signature attributes to a service
+    pub fn sign(&self, service: &mut ServiceInfo) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        service.insert_attribute(PUBLIC_KEY_ATTRIBUTE, self.public_key().to_base64());
+        service.insert_attribute(TIMESTAMP_ATTRIBUTE, timestamp.to_string());
+
+        let signature = self.key_pair.sign(canonical_message(service, timestamp).as_bytes());
+        service.insert_attribute(SIGNATURE_ATTRIBUTE, BASE64.encode(signature.as_ref()));
+        Ok(())
+    }
+}
+
+/// How a [`SignatureVerifier`] decides which public keys to trust
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustPolicy {
+    /// Only keys added with [`SignatureVerifier::pin`] are trusted
+    Pinned,
+    /// The first key seen for a service name is remembered and required afterwards
+    TrustOnFirstUse,
+}
+
+/// Outcome of checking a service's signature
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// Signature is valid and made by a trusted key
+    Valid,
+    /// Service carries no signature attributes
+    Unsigned,
+    /// Signature attributes are malformed or do not match the service data
+    Invalid,
+    /// Signature is older than the verifier's maximum age
+    Expired,
+    /// Signed by a key that is not trusted for this service name
+    UntrustedKey,
+}
+
+/// Verifies signed advertisements against pinned or learned public keys
+pub struct SignatureVerifier {
+    policy: TrustPolicy,
+    keys: RwLock<HashMap<String, PublicKey>>,
+    max_age: Option<Duration>,
+}
+
+impl SignatureVerifier {
+    /// Create a verifier with the given trust policy
+    pub fn new(policy: TrustPolicy) -> Self {
+        Self {
+            policy,
+            keys: RwLock::new(HashMap::new()),
+            max_age: None,
+        }
+    }
+
+    /// Reject signatures older than `max_age`
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Trust `key` for the service instance `service_name`
+    pub async fn pin<S: Into<String>>(&self, service_name: S, key: PublicKey) {
+        self.keys.write().await.insert(service_name.into(), key);
+    }
+
+    /// Key currently trusted for a service instance
+    pub async fn trusted_key(&self, service_name: &str) -> Option<PublicKey> {
+        self.keys.read().await.get(service_name).cloned()
+    }
+
+    /// Check the signature of a service
+    pub async fn check(&self, service: &ServiceInfo) -> Result<SignatureStatus> {
+        let (Some(signature), Some(timestamp), Some(public_key)) = (
+            service.get_attribute(SIGNATURE_ATTRIBUTE),
+            service.get_attribute(TIMESTAMP_ATTRIBUTE),
+            service.get_attribute(PUBLIC_KEY_ATTRIBUTE),
+        ) else {
+            return Ok(SignatureStatus::Unsigned);
+        };
+
+        let (Ok(timestamp), Ok(public_key), Ok(signature)) = (
+            timestamp.parse::<u64>(),
+            PublicKey::from_base64(public_key),
+            BASE64.decode(signature.as_bytes()),
+        ) else {
+            return Ok(SignatureStatus::Invalid);
+        };
+
+        if let Some(max_age) = self.max_age {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            if now.saturating_sub(timestamp) > max_age.as_secs() {
+                return Ok(SignatureStatus::Expired);
+            }
+        }
+
+        let message = canonical_message(service, timestamp);
+        if signature::UnparsedPublicKey::new(&signature::ED25519, public_key.as_bytes())
+            .verify(message.as_bytes(), &signature)
+            .is_err()
+        {
+            return Ok(SignatureStatus::Invalid);
+        }
+
+        let mut keys = self.keys.write().await;
+        match keys.get(service.name()) {
+            Some(trusted) if *trusted == public_key => Ok(SignatureStatus::Valid),
+            Some(_) => Ok(SignatureStatus::UntrustedKey),
+            None => match self.policy {
+                TrustPolicy::Pinned => Ok(SignatureStatus::UntrustedKey),
+                TrustPolicy::TrustOnFirstUse => {
+                    keys.insert(service.name().to_string(), public_key);
+                    Ok(SignatureStatus::Valid)
+                }
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl crate::verification::ServiceVerifier for SignatureVerifier {
+    async fn verify(&self, service: &ServiceInfo) -> Result<VerificationResult> {
+        let start = Instant::now();
+        let status = self.check(service).await?;
+        Ok(match status {
+            SignatureStatus::Valid => VerificationResult::success(start.elapsed()),
+            other => VerificationResult::failure(start.elapsed(), format!("signature check failed: {other:?}")),
+        })
+    }
+}
+
+/// Canonical byte string covered by a service signature
+///
+/// Signature-related attributes are excluded and the remaining attributes
+/// are sorted by key so the message does not depend on TXT record order.
+pub fn canonical_message(service: &ServiceInfo, timestamp: u64) -> String {
+    let mut attrs: Vec<_> = service
+        .attributes
+        .iter()
+        .filter(|(k, _)| {
+            ![SIGNATURE_ATTRIBUTE, TIMESTAMP_ATTRIBUTE, PUBLIC_KEY_ATTRIBUTE].contains(&k.as_str())
+        })
+        .collect();
+    attrs.sort_by_key(|(k, _)| *k);
+
+    let mut message = format!(
+        "{}|{}|{}|{}",
+        service.name,
+        service.service_type.full_name(),
+        service.address,
+        service.port
+    );
+    for (k, v) in attrs {
+        message.push_str(&format!("|{k}={v}"));
+    }
+    message.push_str(&format!("|timestamp={timestamp}"));
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_service(signer: &ServiceSigner) -> ServiceInfo {
+        let mut service = ServiceInfo::new("printer", "_ipp._tcp", 631, Some(vec![("model", "x1")])).unwrap();
+        signer.sign(&mut service).unwrap();
+        service
+    }
+
+    #[tokio::test]
+    async fn test_trust_on_first_use() {
+        let (signer, _) = ServiceSigner::generate().unwrap();
+        let (impostor, _) = ServiceSigner::generate().unwrap();
+        let verifier = SignatureVerifier::new(TrustPolicy::TrustOnFirstUse);
+
+        let service = signed_service(&signer);
+        assert_eq!(verifier.check(&service).await.unwrap(), SignatureStatus::Valid);
+        assert_eq!(verifier.trusted_key("printer").await, Some(signer.public_key()));
+
+        let spoofed = signed_service(&impostor);
+        assert_eq!(verifier.check(&spoofed).await.unwrap(), SignatureStatus::UntrustedKey);
+
+        let mut tampered = service.clone();
+        tampered.port = 9100;
+        assert_eq!(verifier.check(&tampered).await.unwrap(), SignatureStatus::Invalid);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_keys() {
+        let (pkcs8_signer, pkcs8) = ServiceSigner::generate().unwrap();
+        let signer = ServiceSigner::from_pkcs8(&pkcs8).unwrap();
+        assert_eq!(signer.public_key(), pkcs8_signer.public_key());
+
+        let verifier = SignatureVerifier::new(TrustPolicy::Pinned);
+        let service = signed_service(&signer);
+        assert_eq!(verifier.check(&service).await.unwrap(), SignatureStatus::UntrustedKey);
+
+        verifier.pin("printer", PublicKey::from_base64(&signer.public_key().to_base64()).unwrap()).await;
+        assert_eq!(verifier.check(&service).await.unwrap(), SignatureStatus::Valid);
+
+        let unsigned = ServiceInfo::new("printer", "_ipp._tcp", 631, None).unwrap();
+        assert_eq!(verifier.check(&unsigned).await.unwrap(), SignatureStatus::Unsigned);
+    }
+}
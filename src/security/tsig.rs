@@ -1,81 +1,99 @@
+//! TSIG keys for signing DNS updates (RFC 8945)
+//!
+//! A [`TsigKeyManager`] holds the shared secrets used to authenticate
+//! dynamic updates sent to a DNS server. Keys can expire, and several keys
+//! may be active at once while they are being rotated; updates are signed
+//! with the key named in [`DnsSdConfig`](crate::config::DnsSdConfig).
+
 use crate::error::{DiscoveryError, Result};
-use metrics::{counter, histogram};
-use ring::hmac;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use std::{
     sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime},
 };
 use tokio::sync::RwLock;
-use trust_dns_client::proto::rr::dnssec::{Algorithm, SigSigner, TsigSigner};
-use trust_dns_proto::{
-    op::Message,
-    rr::{Name, Record, RecordType},
+use trust_dns_proto::rr::{
+    dnssec::{rdata::tsig::TsigAlgorithm as DnsTsigAlgorithm, tsig::TSigner},
+    Name,
 };
 
-/// TSIG key algorithm type
+/// Allowed clock difference between signer and server, in seconds
+const FUDGE: u16 = 300;
+
+/// HMAC algorithm of a TSIG key
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TsigAlgorithm {
-    HmacSha1,
+    /// `hmac-sha256`
     HmacSha256,
+    /// `hmac-sha384`
     HmacSha384,
+    /// `hmac-sha512`
     HmacSha512,
 }
 
 impl TsigAlgorithm {
-    fn to_dns_algorithm(&self) -> Algorithm {
+    fn to_dns_algorithm(self) -> DnsTsigAlgorithm {
         match self {
-            Self::HmacSha1 => Algorithm::HMAC_SHA1,
-            Self::HmacSha256 => Algorithm::HMAC_SHA256,
-            Self::HmacSha384 => Algorithm::HMAC_SHA384,
-            Self::HmacSha512 => Algorithm::HMAC_SHA512,
-        }
-    }
-
-    fn to_ring_algorithm(&self) -> hmac::Algorithm {
-        match self {
-            Self::HmacSha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
-            Self::HmacSha256 => hmac::HMAC_SHA256,
-            Self::HmacSha384 => hmac::HMAC_SHA384,
-            Self::HmacSha512 => hmac::HMAC_SHA512,
+            Self::HmacSha256 => DnsTsigAlgorithm::HmacSha256,
+            Self::HmacSha384 => DnsTsigAlgorithm::HmacSha384,
+            Self::HmacSha512 => DnsTsigAlgorithm::HmacSha512,
         }
     }
 }
 
-/// TSIG key information with expiry and metadata
+/// A named TSIG shared secret with an optional expiry
 #[derive(Clone)]
 pub struct TsigKey {
     name: Name,
     algorithm: TsigAlgorithm,
     secret: Vec<u8>,
-    created_at: SystemTime,
     expires_at: Option<SystemTime>,
     key_id: String,
 }
 
 impl TsigKey {
-    /// Create a new TSIG key with optional expiry
+    /// Create a key, optionally expiring after `expires_in`
+    ///
+    /// `name` must match the key name known to the DNS server.
     pub fn new(
         name: &str,
         algorithm: TsigAlgorithm,
         secret: &[u8],
         expires_in: Option<Duration>,
     ) -> Result<Self> {
-        let created_at = SystemTime::now();
-        let expires_at = expires_in.map(|duration| created_at + duration);
+        let expires_at = expires_in.map(|duration| SystemTime::now() + duration);
         let key_id = format!("{}_{}", name, uuid::Uuid::new_v4());
 
         Ok(Self {
-            name: Name::from_ascii(name).map_err(|e| {
-                DiscoveryError::SecurityError(format!("Invalid TSIG key name: {}", e))
-            })?,
+            name: Name::from_ascii(name)
+                .map_err(|e| DiscoveryError::security(format!("Invalid TSIG key name: {}", e)))?,
             algorithm,
             secret: secret.to_vec(),
-            created_at,
             expires_at,
             key_id,
         })
     }
 
+    /// Create a key from a base64 secret, as found in BIND `key` statements
+    pub fn from_base64(
+        name: &str,
+        algorithm: TsigAlgorithm,
+        secret: &str,
+        expires_in: Option<Duration>,
+    ) -> Result<Self> {
+        Self::new(name, algorithm, &BASE64.decode(secret.trim())?, expires_in)
+    }
+
+    /// Get the key name
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// Get the HMAC algorithm
+    pub fn algorithm(&self) -> TsigAlgorithm {
+        self.algorithm
+    }
+
     /// Check if the key has expired
     pub fn is_expired(&self) -> bool {
         self.expires_at
@@ -88,17 +106,15 @@ impl TsigKey {
         &self.key_id
     }
 
-    /// Create a TSIG signer
-    pub fn create_signer(&self) -> Result<TsigSigner> {
-        let key = hmac::Key::new(self.algorithm.to_ring_algorithm(), &self.secret);
-        let sig_signer = SigSigner::new(
-            key,
-            self.name.clone(),
+    /// Create a signer for DNS messages
+    pub fn signer(&self) -> Result<TSigner> {
+        TSigner::new(
+            self.secret.clone(),
             self.algorithm.to_dns_algorithm(),
-            Default::default(),
-        );
-        
-        Ok(TsigSigner::new(sig_signer))
+            self.name.clone(),
+            FUDGE,
+        )
+        .map_err(|e| DiscoveryError::security(format!("Cannot sign with TSIG key {}: {}", self.name, e)))
     }
 }
 
@@ -120,7 +136,8 @@ impl TsigKeyManager {
     /// Add a new TSIG key
     pub async fn add_key(&self, key: TsigKey) {
         let mut keys = self.active_keys.write().await;
-        counter!("autodiscovery_tsig_keys_total", 1);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("autodiscovery_tsig_keys_total").increment(1);
         keys.push(key);
     }
 
@@ -130,17 +147,35 @@ impl TsigKeyManager {
         let initial_len = keys.len();
         keys.retain(|key| !key.is_expired());
         let removed = initial_len - keys.len();
-        counter!("autodiscovery_tsig_keys_expired_total", removed as u64);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("autodiscovery_tsig_keys_expired_total").increment(removed as u64);
         removed
     }
 
     /// Get a valid key for signing
     pub async fn get_signing_key(&self) -> Result<TsigKey> {
+        self.find_key(None).await
+    }
+
+    /// Get a valid key named `name`, or any valid key if `name` is `None`
+    ///
+    /// The most recently added key wins, so a rotated-in key takes over
+    /// from the one it replaces.
+    pub async fn find_key(&self, name: Option<&str>) -> Result<TsigKey> {
+        let name = name
+            .map(Name::from_ascii)
+            .transpose()
+            .map_err(|e| DiscoveryError::security(format!("Invalid TSIG key name: {}", e)))?;
         let keys = self.active_keys.read().await;
         keys.iter()
+            .rev()
+            .filter(|key| name.as_ref().is_none_or(|name| key.name == *name))
             .find(|key| !key.is_expired())
             .cloned()
-            .ok_or_else(|| DiscoveryError::SecurityError("No valid TSIG key available".into()))
+            .ok_or_else(|| match name {
+                Some(name) => DiscoveryError::security(format!("No valid TSIG key named {}", name)),
+                None => DiscoveryError::security("No valid TSIG key available"),
+            })
     }
 
     /// Start background key rotation task
@@ -158,59 +193,16 @@ impl TsigKeyManager {
     }
 }
 
-/// Sign a DNS message with TSIG
-pub async fn sign_message(message: &mut Message, key_manager: &TsigKeyManager) -> Result<()> {
-    let key = key_manager.get_signing_key().await?;
-    let start = SystemTime::now();
-
-    let mut signer = key.create_signer()?;
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as u32;
-
-    let result = signer
-        .sign_message(message, now)
-        .map_err(|e| DiscoveryError::SecurityError(format!("Failed to sign message: {}", e)));
-
-    let duration = SystemTime::now().duration_since(start).unwrap();
-    histogram!("autodiscovery_tsig_sign_duration_seconds", duration.as_secs_f64());
-    
-    if result.is_err() {
-        counter!("autodiscovery_tsig_sign_errors_total", 1);
-    }
-
-    result
-}
-
-/// Verify a TSIG-signed DNS message
-pub async fn verify_message(message: &Message, key_manager: &TsigKeyManager) -> Result<bool> {
-    let key = key_manager.get_signing_key().await?;
-    let start = SystemTime::now();
-
-    let signer = key.create_signer()?;
-    let result = signer
-        .verify_message(message)
-        .map_err(|e| DiscoveryError::SecurityError(format!("Failed to verify message: {}", e)));
-
-    let duration = SystemTime::now().duration_since(start).unwrap();
-    histogram!("autodiscovery_tsig_verify_duration_seconds", duration.as_secs_f64());
-
-    if result.is_err() {
-        counter!("autodiscovery_tsig_verify_errors_total", 1);
-    }
-
-    result
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::UNIX_EPOCH;
+    use trust_dns_proto::op::{Message, OpCode};
 
     #[tokio::test]
     async fn test_tsig_key_manager() {
         let manager = Arc::new(TsigKeyManager::new(Duration::from_secs(60)));
-        
+
         // Add a key that expires in 1 second
         let key1 = TsigKey::new(
             "test1.key.",
@@ -221,16 +213,17 @@ mod tests {
         manager.add_key(key1).await;
 
         // Add a key that doesn't expire
-        let key2 = TsigKey::new(
+        let key2 = TsigKey::from_base64(
             "test2.key.",
             TsigAlgorithm::HmacSha256,
-            b"secretkey456",
+            "c2VjcmV0a2V5NDU2",
             None,
         ).unwrap();
         manager.add_key(key2).await;
 
         // Verify we can get a signing key
         assert!(manager.get_signing_key().await.is_ok());
+        assert!(manager.find_key(Some("test1.key")).await.is_ok());
 
         // Wait for first key to expire
         tokio::time::sleep(Duration::from_secs(2)).await;
@@ -239,52 +232,25 @@ mod tests {
 
         // Verify we still have one valid key
         assert!(manager.get_signing_key().await.is_ok());
+        assert!(manager.find_key(Some("test1.key.")).await.is_err());
     }
 
-    #[tokio::test]
-    async fn test_tsig_signing_and_verification() {
-        let manager = Arc::new(TsigKeyManager::new(Duration::from_secs(60)));
-        
-        let key = TsigKey::new(
-            "test.key.",
-            TsigAlgorithm::HmacSha256,
-            b"secretkey123",
-            None,
-        ).unwrap();
-        manager.add_key(key).await;
-
-        let mut message = Message::new();
-        message.set_id(1234);
-
-        assert!(sign_message(&mut message, &manager).await.is_ok());
-        assert!(verify_message(&message, &manager).await.unwrap());
-    }
-
-    #[tokio::test]
-    async fn test_tsig_algorithm_support() {
-        let manager = Arc::new(TsigKeyManager::new(Duration::from_secs(60)));
-        
-        let algorithms = [
-            TsigAlgorithm::HmacSha1,
-            TsigAlgorithm::HmacSha256,
-            TsigAlgorithm::HmacSha384,
-            TsigAlgorithm::HmacSha512,
-        ];
-        
-        for algorithm in &algorithms {
-            let key = TsigKey::new(
-                "test.key.",
-                *algorithm,
-                b"secretkey123",
-                None,
-            ).unwrap();
-            manager.add_key(key).await;
+    #[test]
+    fn test_tsig_signing_and_verification() {
+        for algorithm in [TsigAlgorithm::HmacSha256, TsigAlgorithm::HmacSha384, TsigAlgorithm::HmacSha512] {
+            let key = TsigKey::new("test.key.", algorithm, b"secretkey123", None).unwrap();
+            let signer = key.signer().unwrap();
 
             let mut message = Message::new();
-            message.set_id(1234);
+            message.set_id(1234).set_op_code(OpCode::Update);
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+            message.finalize(&signer, now).unwrap();
+
+            let bytes = message.to_vec().unwrap();
+            assert!(signer.verify_message_byte(None, &bytes, true).is_ok());
 
-            assert!(sign_message(&mut message, &manager).await.is_ok());
-            assert!(verify_message(&message, &manager).await.unwrap());
+            let other = TsigKey::new("test.key.", algorithm, b"otherkey", None).unwrap();
+            assert!(other.signer().unwrap().verify_message_byte(None, &bytes, true).is_err());
         }
     }
 }
@@ -3,9 +3,10 @@
 use crate::types::{ProtocolType, ServiceAttributes, ServiceType};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fmt,
-    net::{IpAddr, Ipv4Addr},
+    collections::{BTreeSet, HashMap},
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    str::FromStr,
     time::{Duration, SystemTime},
 };
 use uuid::Uuid;
@@ -13,7 +14,10 @@ use uuid::Uuid;
 /// ServiceInfo holds information about a discovered or registered service
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ServiceInfo {
-    /// Unique identifier for this service instance
+    /// Nonce telling apart copies of a service created separately
+    ///
+    /// Two announcements of the same service get different nonces; use
+    /// [`service_id`](Self::service_id) to identify the service itself.
     pub id: Uuid,
     /// Human-readable name of the service
     pub name: String,
@@ -21,6 +25,9 @@ pub struct ServiceInfo {
     pub service_type: ServiceType,
     /// IP address of the service
     pub address: IpAddr,
+    /// Hostname the service advertised, e.g. `myhost.local.`
+    #[serde(default)]
+    pub host: Option<String>,
     /// Port number of the service
     pub port: u16,
     /// Additional service attributes
@@ -35,6 +42,25 @@ pub struct ServiceInfo {
     pub verified: bool,
     /// Network interface name where the service was discovered
     pub interface: Option<String>,
+    /// SRV record priority; lower values are preferred
+    #[serde(default)]
+    pub priority: u16,
+    /// SRV record weight among services of equal priority
+    #[serde(default)]
+    pub weight: u16,
+    /// Service types this service needs in order to work
+    ///
+    /// Only kept locally; see
+    /// [`ServiceDiscovery::await_dependencies`](crate::ServiceDiscovery::await_dependencies).
+    #[serde(default)]
+    pub dependencies: Vec<ServiceType>,
+    /// Labels grouping services, such as `team=payments` or `canary`
+    ///
+    /// Unlike attributes, tags are not advertised; they are kept with the
+    /// service locally and indexed by the
+    /// [`ServiceRegistry`](crate::registry::ServiceRegistry).
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
 }
 
 impl ServiceInfo {
@@ -70,6 +96,7 @@ impl ServiceInfo {
             name: name.to_string(),
             service_type,
             address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            host: None,
             port,
             attributes: HashMap::new(),
             protocol_type: ProtocolType::default(),
@@ -77,6 +104,10 @@ impl ServiceInfo {
             ttl: Duration::from_secs(60),
             verified: false,
             interface: None,
+            priority: 0,
+            weight: 0,
+            dependencies: Vec::new(),
+            tags: BTreeSet::new(),
         };
 
         if let Some(attrs) = attributes {
@@ -110,6 +141,47 @@ impl ServiceInfo {
         self
     }
 
+    /// Declare that the service needs a service of `service_type`
+    pub fn with_dependency(mut self, service_type: ServiceType) -> Self {
+        if !self.dependencies.contains(&service_type) {
+            self.dependencies.push(service_type);
+        }
+        self
+    }
+
+    /// Service types the service depends on
+    pub fn dependencies(&self) -> &[ServiceType] {
+        &self.dependencies
+    }
+
+    /// Add a tag
+    pub fn with_tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    /// Add several tags
+    pub fn with_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Whether the service carries `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+
+    /// Set the SRV record priority and weight
+    pub fn with_srv(mut self, priority: u16, weight: u16) -> Self {
+        self.priority = priority;
+        self.weight = weight;
+        self
+    }
+
     /// Check if service has expired
     pub fn is_expired(&self) -> bool {
         match self.discovered_at.elapsed() {
@@ -152,6 +224,70 @@ impl ServiceInfo {
         self.attributes.get(key)
     }
 
+    /// Parse an attribute as `T`
+    ///
+    /// Returns `Ok(None)` if the attribute is absent.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::Attribute`](crate::error::DiscoveryError::Attribute)
+    /// if the value does not parse
+    pub fn get_attribute_as<T>(&self, key: &str) -> Result<Option<T>, crate::error::DiscoveryError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get_attribute(key)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|e| crate::error::DiscoveryError::attribute(key, format!("'{value}': {e}")))
+            })
+            .transpose()
+    }
+
+    /// Read an attribute as a boolean
+    ///
+    /// Accepts `true`/`false`, `yes`/`no`, `on`/`off`, `1`/`0` and their
+    /// initials; a key without a value is true.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::Attribute`](crate::error::DiscoveryError::Attribute)
+    /// for any other value
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, crate::error::DiscoveryError> {
+        self.get_attribute(key)
+            .map(|value| {
+                crate::attributes::parse_bool(value).ok_or_else(|| {
+                    crate::error::DiscoveryError::attribute(key, format!("'{value}' is not a boolean"))
+                })
+            })
+            .transpose()
+    }
+
+    /// Read an attribute as a semantic version
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::Attribute`](crate::error::DiscoveryError::Attribute)
+    /// if the value is not a valid version
+    pub fn get_version(&self, key: &str) -> Result<Option<semver::Version>, crate::error::DiscoveryError> {
+        self.get_attribute_as(key)
+    }
+
+    /// Deserialize the attributes into `T`
+    ///
+    /// See [`Attributes::deserialize_into`](crate::attributes::Attributes::deserialize_into).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::Attribute`](crate::error::DiscoveryError::Attribute)
+    /// if an attribute is missing or does not parse
+    pub fn deserialize_attributes<T: serde::de::DeserializeOwned>(&self) -> Result<T, crate::error::DiscoveryError> {
+        crate::attributes::Attributes::deserialize_into(&self.attributes)
+    }
+
     /// Get service port
     pub fn port(&self) -> u16 {
         self.port
@@ -168,6 +304,96 @@ impl ServiceInfo {
         self
     }
 
+    /// Set the advertised hostname
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Advertised hostname without the trailing root dot, e.g. `myhost.local`
+    pub fn hostname(&self) -> Option<&str> {
+        self.host
+            .as_deref()
+            .map(|host| host.strip_suffix('.').unwrap_or(host))
+            .filter(|host| !host.is_empty())
+    }
+
+    /// Host to connect to or present for TLS SNI and certificate matching
+    ///
+    /// With `prefer_hostname` this is the advertised hostname when known,
+    /// otherwise the IP address.
+    pub fn connect_host(&self, prefer_hostname: bool) -> String {
+        match self.hostname().filter(|_| prefer_hostname) {
+            Some(hostname) => hostname.to_string(),
+            None => self.address.to_string(),
+        }
+    }
+
+    /// `host:port` authority for HTTP `Host` headers and URLs
+    ///
+    /// Picks the host like [`connect_host`](Self::connect_host) and brackets
+    /// IPv6 addresses.
+    pub fn authority(&self, prefer_hostname: bool) -> String {
+        match self.hostname().filter(|_| prefer_hostname) {
+            Some(hostname) => format!("{hostname}:{}", self.port),
+            None => std::net::SocketAddr::new(self.address, self.port).to_string(),
+        }
+    }
+
+    /// Advertised address and port
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    /// Addresses to connect to, resolving the advertised hostname
+    ///
+    /// The advertised address comes first, followed by any other addresses
+    /// the hostname resolves to, so a service that moved since it was
+    /// discovered can still be reached. The result can be passed to
+    /// `tokio::net::TcpStream::connect` as a slice. Failing to resolve the
+    /// hostname is not an error when the service has an address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service has no address and no hostname that
+    /// resolves
+    pub async fn socket_addrs(&self) -> Result<Vec<SocketAddr>, crate::error::DiscoveryError> {
+        let mut addrs: Vec<SocketAddr> = self.advertised_socket_addr().into_iter().collect();
+        if let Some(hostname) = self.hostname() {
+            match tokio::net::lookup_host((hostname, self.port)).await {
+                Ok(resolved) => {
+                    for addr in resolved {
+                        if !addrs.contains(&addr) {
+                            addrs.push(addr);
+                        }
+                    }
+                }
+                Err(e) if addrs.is_empty() => {
+                    return Err(crate::error::DiscoveryError::network(format!(
+                        "{} has no address and {hostname} does not resolve: {e}",
+                        self.name
+                    )));
+                }
+                Err(e) => tracing::debug!("Resolving {} failed, using the advertised address: {}", hostname, e),
+            }
+        }
+        if addrs.is_empty() {
+            return Err(crate::error::DiscoveryError::network(format!("{} has no address", self.name)));
+        }
+        Ok(addrs)
+    }
+
+    /// The advertised socket address, unless the address is unspecified
+    fn advertised_socket_addr(&self) -> Option<SocketAddr> {
+        (!self.address.is_unspecified()).then(|| self.socket_addr())
+    }
+
+    /// Set the network interface the service was found on
+    pub fn with_interface(mut self, interface: impl Into<String>) -> Self {
+        self.interface = Some(interface.into());
+        self
+    }
+
     /// Get service name
     pub fn name(&self) -> &str {
         &self.name
@@ -177,6 +403,94 @@ impl ServiceInfo {
     pub fn service_type(&self) -> &ServiceType {
         &self.service_type
     }
+
+    /// Stable identity of the service, derived from its name, type and domain
+    pub fn service_id(&self) -> ServiceId {
+        ServiceId::new(&self.name, &self.service_type)
+    }
+}
+
+/// Connect to a service with `std::net` sockets, as in `TcpStream::connect(&service)`
+///
+/// Yields the advertised address. Services advertised without one, such as
+/// those announced with an unspecified address, resolve their hostname
+/// instead, blocking like the resolution of a `host:port` string does.
+impl ToSocketAddrs for ServiceInfo {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        if let Some(addr) = self.advertised_socket_addr() {
+            return Ok(vec![addr].into_iter());
+        }
+        match self.hostname() {
+            Some(hostname) => Ok((hostname, self.port).to_socket_addrs()?.collect::<Vec<_>>().into_iter()),
+            None => Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("{} has no address", self.name),
+            )),
+        }
+    }
+}
+
+/// Stable identity of a service
+///
+/// The DNS-SD service instance name: the instance name, with dots and
+/// backslashes escaped, followed by the service type and domain, such as
+/// `Office\.Printer._ipp._tcp.local`. Services without a domain are in
+/// `local`, so a service is identified the same way whether or not its type
+/// spells the domain out. UPnP URN types carry no domain and are used as
+/// they are.
+///
+/// Unlike [`ServiceInfo::id`], the identity does not change when the same
+/// service is announced again, and it is what the
+/// [`ServiceRegistry`](crate::registry::ServiceRegistry) keys services by.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ServiceId(String);
+
+impl ServiceId {
+    /// Derive the identity of instance `name` of `service_type`
+    pub fn new(name: &str, service_type: &ServiceType) -> Self {
+        let mut id = String::with_capacity(name.len() + 32);
+        for c in name.chars() {
+            if matches!(c, '.' | '\\') {
+                id.push('\\');
+            }
+            id.push(c);
+        }
+        id.push('.');
+        if service_type.service_name().starts_with("urn:") {
+            id.push_str(service_type.service_name());
+        } else {
+            let domain = service_type
+                .domain()
+                .map(|domain| domain.trim_end_matches('.'))
+                .filter(|domain| !domain.is_empty())
+                .unwrap_or("local");
+            id.push_str(service_type.service_name());
+            id.push_str(service_type.protocol());
+            id.push('.');
+            id.push_str(domain);
+        }
+        Self(id)
+    }
+
+    /// The identity as a string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ServiceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for ServiceId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
 }
 
 impl fmt::Display for ServiceInfo {
@@ -189,23 +503,330 @@ impl fmt::Display for ServiceInfo {
     }
 }
 
+/// A field of a service's advertisement that changed on rediscovery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceChange {
+    /// The service moved to another address
+    Address {
+        /// Address before the change
+        previous: IpAddr,
+        /// Address after the change
+        current: IpAddr,
+    },
+    /// The service moved to another port
+    Port {
+        /// Port before the change
+        previous: u16,
+        /// Port after the change
+        current: u16,
+    },
+    /// The advertised hostname changed
+    Host {
+        /// Hostname before the change
+        previous: Option<String>,
+        /// Hostname after the change
+        current: Option<String>,
+    },
+    /// The SRV priority or weight changed
+    Srv {
+        /// Priority and weight before the change
+        previous: (u16, u16),
+        /// Priority and weight after the change
+        current: (u16, u16),
+    },
+    /// A TXT attribute was added, removed or changed
+    Attribute {
+        /// The attribute key
+        key: String,
+        /// Value before the change, `None` if it was added
+        previous: Option<String>,
+        /// Value after the change, `None` if it was removed
+        current: Option<String>,
+    },
+}
+
+impl ServiceChange {
+    /// Changes between two advertisements of the same service
+    ///
+    /// Attribute changes come last, ordered by key. Fields that are only kept
+    /// locally, such as tags, are not compared.
+    pub fn between(previous: &ServiceInfo, current: &ServiceInfo) -> Vec<Self> {
+        let mut changes = Vec::new();
+        if previous.address != current.address {
+            changes.push(Self::Address { previous: previous.address, current: current.address });
+        }
+        if previous.port != current.port {
+            changes.push(Self::Port { previous: previous.port, current: current.port });
+        }
+        if previous.host != current.host {
+            changes.push(Self::Host { previous: previous.host.clone(), current: current.host.clone() });
+        }
+        let (before, after) = ((previous.priority, previous.weight), (current.priority, current.weight));
+        if before != after {
+            changes.push(Self::Srv { previous: before, current: after });
+        }
+
+        let keys: BTreeSet<&String> = previous.attributes.keys().chain(current.attributes.keys()).collect();
+        for key in keys {
+            let (before, after) = (previous.attributes.get(key), current.attributes.get(key));
+            if before != after {
+                changes.push(Self::Attribute {
+                    key: key.clone(),
+                    previous: before.cloned(),
+                    current: after.cloned(),
+                });
+            }
+        }
+        changes
+    }
+}
+
+impl fmt::Display for ServiceChange {
+    /// Names the changed field; values are left out since attributes may
+    /// hold credentials
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address { .. } => f.write_str("address"),
+            Self::Port { .. } => f.write_str("port"),
+            Self::Host { .. } => f.write_str("host"),
+            Self::Srv { .. } => f.write_str("srv"),
+            Self::Attribute { key, .. } => write!(f, "attribute {key}"),
+        }
+    }
+}
+
+/// Changes to a registered service's attributes or port
+///
+/// Applied to a live registration by
+/// [`ServiceDiscovery::update_service`](crate::ServiceDiscovery::update_service).
+/// Removals are applied after the attributes set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeUpdate {
+    set: HashMap<String, String>,
+    remove: BTreeSet<String>,
+    port: Option<u16>,
+}
+
+impl AttributeUpdate {
+    /// Create an update that changes nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set attribute `key` to `value`
+    pub fn with_attribute<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.set.insert(key.into(), value.into());
+        self
+    }
+
+    /// Remove attribute `key`
+    pub fn without_attribute<K: Into<String>>(mut self, key: K) -> Self {
+        self.remove.insert(key.into());
+        self
+    }
+
+    /// Move the service to `port`
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Whether the update changes nothing
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.remove.is_empty() && self.port.is_none()
+    }
+
+    /// Apply the update to `service`
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DiscoveryError::InvalidServiceInfo`](crate::error::DiscoveryError::InvalidServiceInfo)
+    /// if the new port is 0
+    pub fn apply(&self, service: &mut ServiceInfo) -> Result<(), crate::error::DiscoveryError> {
+        if self.port == Some(0) {
+            return Err(crate::error::DiscoveryError::InvalidServiceInfo {
+                field: "port".to_string(),
+                reason: "Port cannot be zero".to_string(),
+            });
+        }
+        service.attributes.extend(self.set.clone());
+        service.attributes.retain(|key, _| !self.remove.contains(key));
+        if let Some(port) = self.port {
+            service.port = port;
+        }
+        Ok(())
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for AttributeUpdate {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(attributes: I) -> Self {
+        attributes
+            .into_iter()
+            .fold(Self::new(), |update, (key, value)| update.with_attribute(key, value))
+    }
+}
+
+/// Where and when a [`ServiceEvent`] arose
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventOrigin {
+    /// Protocol that produced the event, if a single one did
+    pub protocol: Option<ProtocolType>,
+    /// Network interface the event was observed on, if known
+    pub interface: Option<String>,
+    /// When the event was published
+    pub timestamp: SystemTime,
+}
+
+impl EventOrigin {
+    /// An origin with no protocol or interface, at the current time
+    pub fn now() -> Self {
+        Self {
+            protocol: None,
+            interface: None,
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    /// The protocol and interface `service` was found with, at the current time
+    pub fn of(service: &ServiceInfo) -> Self {
+        Self {
+            protocol: Some(service.protocol_type),
+            interface: service.interface.clone(),
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
 /// Events that can occur during service discovery
+///
+/// Every event carries an [`EventOrigin`] telling which protocol and
+/// interface produced it and when, so subscribers can tell network segments
+/// apart.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ServiceEvent {
     /// A new service was discovered
-    New(ServiceInfo),
-    /// An existing service was updated
-    Updated(ServiceInfo),
+    New {
+        /// The service
+        service: ServiceInfo,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A known service was rediscovered with a changed advertisement
+    Updated {
+        /// The service as it is now advertised
+        service: ServiceInfo,
+        /// What changed
+        changes: Vec<ServiceChange>,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
     /// A service was removed or expired
-    Removed(ServiceInfo),
+    Removed {
+        /// The service
+        service: ServiceInfo,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
     /// A service failed verification
-    VerificationFailed(ServiceInfo),
+    VerificationFailed {
+        /// The service
+        service: ServiceInfo,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A discovered service's attributes do not conform to its metadata schema
+    SchemaViolation {
+        /// The offending service
+        service: ServiceInfo,
+        /// Description of each violated rule
+        violations: Vec<String>,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A registered service was renamed to resolve a name conflict
+    Renamed {
+        /// The name that was originally requested
+        previous_name: String,
+        /// The service as it was finally registered
+        service: ServiceInfo,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A registered service failed its health probes and is no longer advertised
+    Withdrawn {
+        /// The withdrawn service
+        service: ServiceInfo,
+        /// Consecutive failed probes that led to the withdrawal
+        failures: u32,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A withdrawn service passed a health probe and is advertised again
+    Reinstated {
+        /// The service as announced again
+        service: ServiceInfo,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A protocol received an answer that looks spoofed
+    SecurityAlert {
+        /// Protocol the answer came in over
+        protocol: ProtocolType,
+        /// Sender of the answer
+        source: IpAddr,
+        /// What was wrong with it
+        reason: String,
+        /// Whether the answer was withheld from results
+        quarantined: bool,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// The host's network addresses changed
+    ///
+    /// Registered services have been re-announced, moved to a current
+    /// address where theirs went away, and discovery is being re-run.
+    NetworkChanged {
+        /// Addresses that appeared
+        added: Vec<IpAddr>,
+        /// Addresses that went away
+        removed: Vec<IpAddr>,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// A dependency being waited for is discovered and healthy
+    DependencyReady {
+        /// The dependency that is now satisfied
+        service_type: ServiceType,
+        /// The service satisfying it
+        service: ServiceInfo,
+        /// Dependencies still being waited for
+        pending: Vec<ServiceType>,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
+    /// Services of a type were found, but none had a compatible version
+    ///
+    /// Published by
+    /// [`ServiceDiscovery::find_compatible`](crate::ServiceDiscovery::find_compatible).
+    IncompatibleVersions {
+        /// The service type looked for
+        service_type: ServiceType,
+        /// The version requirement nothing satisfied
+        requirement: String,
+        /// The services found, with missing or unsatisfying versions
+        services: Vec<ServiceInfo>,
+        /// Where and when the event arose
+        origin: EventOrigin,
+    },
     /// Discovery process started
     DiscoveryStarted {
         /// Service types being searched for
         service_types: Vec<ServiceType>,
         /// Protocols being used
         protocols: Vec<ProtocolType>,
+        /// Where and when the event arose
+        origin: EventOrigin,
     },
     /// Discovery process completed
     DiscoveryCompleted {
@@ -213,6 +834,8 @@ pub enum ServiceEvent {
         services_found: usize,
         /// Time taken for discovery
         duration: Duration,
+        /// Where and when the event arose
+        origin: EventOrigin,
     },
     /// Discovery process failed
     DiscoveryFailed {
@@ -220,28 +843,93 @@ pub enum ServiceEvent {
         error: String,
         /// Service types that failed
         service_types: Vec<ServiceType>,
+        /// Where and when the event arose
+        origin: EventOrigin,
     },
 }
 
 impl ServiceEvent {
     /// Create a new service event
     pub fn new(service: ServiceInfo) -> Self {
-        Self::New(service)
+        Self::New { origin: EventOrigin::of(&service), service }
     }
 
     /// Create an updated service event
-    pub fn updated(service: ServiceInfo) -> Self {
-        Self::Updated(service)
+    pub fn updated(service: ServiceInfo, changes: Vec<ServiceChange>) -> Self {
+        Self::Updated { origin: EventOrigin::of(&service), service, changes }
     }
 
     /// Create a removed service event
     pub fn removed(service: ServiceInfo) -> Self {
-        Self::Removed(service)
+        Self::Removed { origin: EventOrigin::of(&service), service }
     }
 
     /// Create a verification failed event
     pub fn verification_failed(service: ServiceInfo) -> Self {
-        Self::VerificationFailed(service)
+        Self::VerificationFailed { origin: EventOrigin::of(&service), service }
+    }
+
+    /// Create a schema violation event
+    pub fn schema_violation(service: ServiceInfo, violations: Vec<String>) -> Self {
+        Self::SchemaViolation { origin: EventOrigin::of(&service), service, violations }
+    }
+
+    /// Create a withdrawn service event
+    pub fn withdrawn(service: ServiceInfo, failures: u32) -> Self {
+        Self::Withdrawn { origin: EventOrigin::of(&service), service, failures }
+    }
+
+    /// Create a reinstated service event
+    pub fn reinstated(service: ServiceInfo) -> Self {
+        Self::Reinstated { origin: EventOrigin::of(&service), service }
+    }
+
+    /// Create a renamed service event
+    pub fn renamed<S: Into<String>>(previous_name: S, service: ServiceInfo) -> Self {
+        Self::Renamed {
+            previous_name: previous_name.into(),
+            origin: EventOrigin::of(&service),
+            service,
+        }
+    }
+
+    /// Create a security alert event
+    pub fn security_alert<S: Into<String>>(protocol: ProtocolType, source: IpAddr, reason: S, quarantined: bool) -> Self {
+        Self::SecurityAlert {
+            protocol,
+            source,
+            reason: reason.into(),
+            quarantined,
+            origin: EventOrigin {
+                protocol: Some(protocol),
+                ..EventOrigin::now()
+            },
+        }
+    }
+
+    /// Create a network changed event
+    pub fn network_changed(added: Vec<IpAddr>, removed: Vec<IpAddr>) -> Self {
+        Self::NetworkChanged { added, removed, origin: EventOrigin::now() }
+    }
+
+    /// Create a dependency ready event
+    pub fn dependency_ready(service_type: ServiceType, service: ServiceInfo, pending: Vec<ServiceType>) -> Self {
+        Self::DependencyReady {
+            service_type,
+            origin: EventOrigin::of(&service),
+            service,
+            pending,
+        }
+    }
+
+    /// Create an incompatible versions event
+    pub fn incompatible_versions<S: Into<String>>(service_type: ServiceType, requirement: S, services: Vec<ServiceInfo>) -> Self {
+        Self::IncompatibleVersions {
+            service_type,
+            requirement: requirement.into(),
+            services,
+            origin: EventOrigin::now(),
+        }
     }
 
     /// Create a discovery started event
@@ -252,6 +940,7 @@ impl ServiceEvent {
         Self::DiscoveryStarted {
             service_types,
             protocols,
+            origin: EventOrigin::now(),
         }
     }
 
@@ -260,6 +949,7 @@ impl ServiceEvent {
         Self::DiscoveryCompleted {
             services_found,
             duration,
+            origin: EventOrigin::now(),
         }
     }
 
@@ -268,30 +958,108 @@ impl ServiceEvent {
         Self::DiscoveryFailed {
             error: error.into(),
             service_types,
+            origin: EventOrigin::now(),
+        }
+    }
+
+    /// Say the event was produced by `protocol`
+    pub fn with_protocol(mut self, protocol: ProtocolType) -> Self {
+        self.origin_mut().protocol = Some(protocol);
+        self
+    }
+
+    /// Say the event was observed on network interface `interface`
+    pub fn with_interface(mut self, interface: impl Into<String>) -> Self {
+        self.origin_mut().interface = Some(interface.into());
+        self
+    }
+
+    /// Say the event was published at `timestamp`
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.origin_mut().timestamp = timestamp;
+        self
+    }
+
+    /// Where and when the event arose
+    pub fn origin(&self) -> &EventOrigin {
+        match self {
+            Self::New { origin, .. }
+            | Self::Updated { origin, .. }
+            | Self::Removed { origin, .. }
+            | Self::VerificationFailed { origin, .. }
+            | Self::SchemaViolation { origin, .. }
+            | Self::Renamed { origin, .. }
+            | Self::Withdrawn { origin, .. }
+            | Self::Reinstated { origin, .. }
+            | Self::SecurityAlert { origin, .. }
+            | Self::NetworkChanged { origin, .. }
+            | Self::DependencyReady { origin, .. }
+            | Self::IncompatibleVersions { origin, .. }
+            | Self::DiscoveryStarted { origin, .. }
+            | Self::DiscoveryCompleted { origin, .. }
+            | Self::DiscoveryFailed { origin, .. } => origin,
+        }
+    }
+
+    fn origin_mut(&mut self) -> &mut EventOrigin {
+        match self {
+            Self::New { origin, .. }
+            | Self::Updated { origin, .. }
+            | Self::Removed { origin, .. }
+            | Self::VerificationFailed { origin, .. }
+            | Self::SchemaViolation { origin, .. }
+            | Self::Renamed { origin, .. }
+            | Self::Withdrawn { origin, .. }
+            | Self::Reinstated { origin, .. }
+            | Self::SecurityAlert { origin, .. }
+            | Self::NetworkChanged { origin, .. }
+            | Self::DependencyReady { origin, .. }
+            | Self::IncompatibleVersions { origin, .. }
+            | Self::DiscoveryStarted { origin, .. }
+            | Self::DiscoveryCompleted { origin, .. }
+            | Self::DiscoveryFailed { origin, .. } => origin,
         }
     }
 
     /// Get the service info if this event contains one
     pub fn service(&self) -> Option<&ServiceInfo> {
         match self {
-            Self::New(service)
-            | Self::Updated(service)
-            | Self::Removed(service)
-            | Self::VerificationFailed(service) => Some(service),
+            Self::New { service, .. }
+            | Self::Updated { service, .. }
+            | Self::Removed { service, .. }
+            | Self::VerificationFailed { service, .. }
+            | Self::SchemaViolation { service, .. }
+            | Self::Renamed { service, .. }
+            | Self::Withdrawn { service, .. }
+            | Self::Reinstated { service, .. }
+            | Self::DependencyReady { service, .. } => Some(service),
             _ => None,
         }
     }
 
     /// Check if this is a positive event (new or updated service)
     pub fn is_positive(&self) -> bool {
-        matches!(self, Self::New(_) | Self::Updated(_) | Self::DiscoveryCompleted { .. })
+        matches!(
+            self,
+            Self::New { .. }
+                | Self::Updated { .. }
+                | Self::Reinstated { .. }
+                | Self::DependencyReady { .. }
+                | Self::DiscoveryCompleted { .. }
+        )
     }
 
     /// Check if this is a negative event (removed service or failure)
     pub fn is_negative(&self) -> bool {
         matches!(
             self,
-            Self::Removed(_) | Self::VerificationFailed(_) | Self::DiscoveryFailed { .. }
+            Self::Removed { .. }
+                | Self::VerificationFailed { .. }
+                | Self::SchemaViolation { .. }
+                | Self::Withdrawn { .. }
+                | Self::SecurityAlert { .. }
+                | Self::IncompatibleVersions { .. }
+                | Self::DiscoveryFailed { .. }
         )
     }
 }
@@ -299,13 +1067,46 @@ impl ServiceEvent {
 impl fmt::Display for ServiceEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::New(service) => write!(f, "New service: {service}"),
-            Self::Updated(service) => write!(f, "Updated service: {service}"),
-            Self::Removed(service) => write!(f, "Removed service: {service}"),
-            Self::VerificationFailed(service) => write!(f, "Verification failed: {service}"),
+            Self::New { service, .. } => write!(f, "New service: {service}"),
+            Self::Updated { service, changes, .. } => {
+                let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+                write!(f, "Updated service: {service} (changed {})", changes.join(", "))
+            }
+            Self::Removed { service, .. } => write!(f, "Removed service: {service}"),
+            Self::VerificationFailed { service, .. } => write!(f, "Verification failed: {service}"),
+            Self::SchemaViolation { service, violations, .. } => {
+                write!(f, "Schema violation by {service}: {}", violations.join("; "))
+            }
+            Self::Renamed { previous_name, service, .. } => {
+                write!(f, "Renamed service '{previous_name}': {service}")
+            }
+            Self::Withdrawn { service, failures, .. } => {
+                write!(f, "Withdrawn after {failures} failed health probes: {service}")
+            }
+            Self::Reinstated { service, .. } => write!(f, "Reinstated service: {service}"),
+            Self::SecurityAlert { protocol, source, reason, quarantined, .. } => write!(
+                f,
+                "Suspicious {protocol} answer from {source}{}: {reason}",
+                if *quarantined { " (quarantined)" } else { "" }
+            ),
+            Self::NetworkChanged { added, removed, .. } => write!(
+                f,
+                "Network changed: {} addresses added, {} removed",
+                added.len(),
+                removed.len()
+            ),
+            Self::DependencyReady { service_type, service, pending, .. } => {
+                write!(f, "Dependency {service_type} ready: {service} ({} pending)", pending.len())
+            }
+            Self::IncompatibleVersions { service_type, requirement, services, .. } => write!(
+                f,
+                "No {service_type} service satisfies version {requirement}: {} incompatible",
+                services.len()
+            ),
             Self::DiscoveryStarted {
                 service_types,
                 protocols,
+                ..
             } => write!(
                 f,
                 "Discovery started for {} service types using {} protocols",
@@ -315,11 +1116,12 @@ impl fmt::Display for ServiceEvent {
             Self::DiscoveryCompleted {
                 services_found,
                 duration,
+                ..
             } => write!(
                 f,
                 "Discovery completed: {services_found} services found in {duration:?}"
             ),
-            Self::DiscoveryFailed { error, service_types } => write!(
+            Self::DiscoveryFailed { error, service_types, .. } => write!(
                 f,
                 "Discovery failed for {} service types: {}",
                 service_types.len(),
@@ -333,6 +1135,24 @@ impl fmt::Display for ServiceEvent {
 mod tests {
     use super::*;
     use crate::types::ProtocolType;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_attribute_update() {
+        let mut service = ServiceInfo::new("API", "_http._tcp", 8080, Some(vec![("version", "1"), ("state", "idle")])).unwrap();
+        let update = AttributeUpdate::new()
+            .with_attribute("version", "2")
+            .without_attribute("state")
+            .with_port(8081);
+        update.apply(&mut service).unwrap();
+        assert_eq!(service.attributes, HashMap::from([("version".to_string(), "2".to_string())]));
+        assert_eq!(service.port, 8081);
+
+        assert!(AttributeUpdate::new().is_empty());
+        assert!(AttributeUpdate::new().with_port(0).apply(&mut service).is_err());
+        let update: AttributeUpdate = [("state", "busy")].into_iter().collect();
+        assert_eq!(update, AttributeUpdate::new().with_attribute("state", "busy"));
+    }
 
     #[test]
     fn test_service_creation() -> Result<(), crate::error::DiscoveryError> {
@@ -350,6 +1170,24 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_service_id_is_stable() -> Result<(), crate::error::DiscoveryError> {
+        let service = ServiceInfo::new("Office.Printer", "_ipp._tcp", 631, None)?;
+        assert_eq!(service.service_id().as_str(), r"Office\.Printer._ipp._tcp.local");
+
+        // Announcing again, on another port or with the domain spelled out
+        // keeps the identity
+        let again = ServiceInfo::new("Office.Printer", "_ipp._tcp.local.", 632, None)?;
+        assert_ne!(again.id, service.id);
+        assert_eq!(again.service_id(), service.service_id());
+
+        let other_domain = ServiceInfo::new("Office.Printer", "_ipp._tcp.example.com", 631, None)?;
+        assert_ne!(other_domain.service_id(), service.service_id());
+        let upnp = ServiceInfo::new("TV", "urn:schemas-upnp-org:service:AVTransport:1", 80, None)?;
+        assert_eq!(upnp.service_id().as_str(), "TV.urn:schemas-upnp-org:service:AVTransport:1");
+        Ok(())
+    }
+
     #[test]
     fn test_service_expiry() -> Result<(), crate::error::DiscoveryError> {
         let mut service = ServiceInfo::new(
@@ -382,6 +1220,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_typed_attributes() -> Result<(), crate::error::DiscoveryError> {
+        let service = ServiceInfo::new("Test Service", "_http._tcp", 8080, None)?
+            .with_attribute("port_alt", "8443")
+            .with_attribute("secure", "yes")
+            .with_attribute("version", "1.2.3-beta.1")
+            .with_attribute("broken", "1.2");
+
+        assert_eq!(service.get_attribute_as::<u16>("port_alt")?, Some(8443));
+        assert_eq!(service.get_attribute_as::<u16>("missing")?, None);
+        assert_eq!(service.get_bool("secure")?, Some(true));
+        assert_eq!(service.get_version("version")?, Some(semver::Version::parse("1.2.3-beta.1").unwrap()));
+        assert!(matches!(
+            service.get_version("broken"),
+            Err(crate::error::DiscoveryError::Attribute { key, .. }) if key == "broken"
+        ));
+        assert!(service.get_bool("port_alt").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_service_protocol() -> Result<(), crate::error::DiscoveryError> {
         let service = ServiceInfo::new("Test Service", "_http._tcp", 8080, None)?
@@ -390,4 +1248,52 @@ mod tests {
         assert_eq!(service.protocol_type(), ProtocolType::Mdns);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_socket_addrs() -> Result<(), crate::error::DiscoveryError> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = ServiceInfo::new("Local", "_http._tcp", port, None)?.with_address(Ipv4Addr::LOCALHOST.into());
+        assert_eq!(service.socket_addr(), listener.local_addr().unwrap());
+        assert!(std::net::TcpStream::connect(&service).is_ok());
+        assert_eq!(service.socket_addrs().await?, [service.socket_addr()]);
+
+        // Without an address the hostname is resolved
+        let unaddressed = ServiceInfo::new("Named", "_http._tcp", port, None)?
+            .with_address(Ipv4Addr::UNSPECIFIED.into())
+            .with_host("localhost.");
+        let addrs = unaddressed.socket_addrs().await?;
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback() && addr.port() == port), "{addrs:?}");
+        assert!(unaddressed.to_socket_addrs().unwrap().any(|addr| addr.ip().is_loopback()));
+
+        let nowhere = ServiceInfo::new("Nowhere", "_http._tcp", port, None)?.with_address(Ipv4Addr::UNSPECIFIED.into());
+        assert!(nowhere.socket_addrs().await.is_err());
+        assert_eq!(nowhere.to_socket_addrs().unwrap_err().kind(), io::ErrorKind::AddrNotAvailable);
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_origin() -> Result<(), crate::error::DiscoveryError> {
+        let service = ServiceInfo::new("Test Service", "_http._tcp", 8080, None)?
+            .with_protocol_type(ProtocolType::DnsSd)
+            .with_interface("eth1");
+        let event = ServiceEvent::removed(service);
+        assert_eq!(event.origin().protocol, Some(ProtocolType::DnsSd));
+        assert_eq!(event.origin().interface.as_deref(), Some("eth1"));
+
+        let event = ServiceEvent::network_changed(Vec::new(), Vec::new()).with_interface("wlan0");
+        assert_eq!(event.origin().protocol, None);
+        assert_eq!(event.origin().interface.as_deref(), Some("wlan0"));
+
+        let alert = ServiceEvent::security_alert(ProtocolType::Upnp, Ipv4Addr::LOCALHOST.into(), "spoofed", false);
+        assert_eq!(alert.origin().protocol, Some(ProtocolType::Upnp));
+
+        // The origin survives serialization
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let event = event.with_timestamp(timestamp);
+        let decoded: ServiceEvent = serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+        assert_eq!(decoded.origin().timestamp, timestamp);
+        assert_eq!(decoded, event);
+        Ok(())
+    }
 }
@@ -4,14 +4,23 @@
 //! service discovery scenarios.
 
 use crate::{
-    config::DiscoveryConfig,
-    error::Result,
-    service::ServiceInfo,
-    types::{ServiceType, ProtocolType},
+    config::{DiscoveryConfig, RegistrationConfig},
+    error::{DiscoveryError, Result},
+    registration::RegistrationStatus,
+    service::{ServiceEvent, ServiceInfo},
+    types::{DiscoveryFilter, ServiceType, ProtocolType},
     ServiceDiscovery,
 };
+use futures::StreamExt;
 use std::time::Duration;
 
+pub mod presets;
+
+pub use presets::{discover_airplay, discover_chromecast, discover_homekit, discover_printers};
+
+/// Longest pause between browses while waiting for a service
+const WAIT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Simple service discovery with sensible defaults
 pub struct SimpleDiscovery {
     inner: ServiceDiscovery,
@@ -91,6 +100,63 @@ impl SimpleDiscovery {
         self.inner.register_service(service).await
     }
 
+    /// Wait until a service of `service_type` whose name contains
+    /// `name_contains` appears, and return it
+    ///
+    /// The network is browsed until the service is found; in between,
+    /// service events published by the registry are watched so a service
+    /// reported from elsewhere is picked up too.
+    ///
+    /// # Errors
+    ///
+    /// Returns a timeout error if no matching service appears within `timeout`
+    pub async fn wait_for_service(
+        &self,
+        service_type: &str,
+        name_contains: &str,
+        timeout: Duration,
+    ) -> Result<ServiceInfo> {
+        let service_type = ServiceType::new(service_type)?;
+        let mut events = self.inner.subscribe();
+        let matches = |service: &ServiceInfo| {
+            service.service_type == service_type && service.name().contains(name_contains)
+        };
+
+        let wait = async {
+            loop {
+                let filter = DiscoveryFilter::new().with_service_type(service_type.clone());
+                let mut services = Box::pin(self.inner.discover_stream(filter));
+                while let Some(service) = services.next().await {
+                    if matches(&service) {
+                        return service;
+                    }
+                }
+
+                // Watch for services announced before the next browse
+                let watch = async {
+                    loop {
+                        match events.recv().await {
+                            Ok(ServiceEvent::New { service, .. } | ServiceEvent::Updated { service, .. }) if matches(&service) => {
+                                return Some(service);
+                            }
+                            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                        }
+                    }
+                };
+                if let Ok(Some(service)) = tokio::time::timeout(WAIT_RETRY_INTERVAL, watch).await {
+                    return service;
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            DiscoveryError::timeout(format!(
+                "No {service_type} service named like '{name_contains}' appeared within {timeout:?}"
+            ))
+        })
+    }
+
     /// Stop all services and cleanup
     pub async fn shutdown(&self) -> Result<()> {
         // Unregister all services
@@ -124,6 +190,35 @@ pub async fn discover_http_services() -> Result<Vec<ServiceInfo>> {
     discovery.discover_http().await
 }
 
+/// Wait until a service of `service_type` whose name contains
+/// `name_contains` appears on the network, and return it
+///
+/// Useful in integration tests and startup orchestration, where one
+/// component must wait for another to come up.
+///
+/// # Example
+/// ```rust,no_run
+/// use auto_discovery::simple::wait_for_service;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let database = wait_for_service("_postgresql._tcp", "primary", Duration::from_secs(30)).await?;
+///     println!("Database is up at {}:{}", database.address, database.port);
+///     Ok(())
+/// }
+/// ```
+pub async fn wait_for_service(service_type: &str, name_contains: &str, timeout: Duration) -> Result<ServiceInfo> {
+    let config = DiscoveryConfig::new()
+        .with_service_type(ServiceType::new(service_type)?)
+        .with_protocol(ProtocolType::Mdns)
+        .with_timeout(timeout);
+    let discovery = SimpleDiscovery {
+        inner: ServiceDiscovery::new(config).await?,
+    };
+    discovery.wait_for_service(service_type, name_contains, timeout).await
+}
+
 /// Register an HTTP service and return a handle for cleanup
 /// 
 /// # Example
@@ -143,7 +238,10 @@ pub async fn discover_http_services() -> Result<Vec<ServiceInfo>> {
 pub async fn register_http_service(name: &str, port: u16) -> Result<ServiceHandle> {
     let discovery = SimpleDiscovery::new().await?;
     let service = ServiceInfo::new(name, "_http._tcp", port, None)?;
-    discovery.inner.register_service(service.clone()).await?;
+    let service = discovery
+        .inner
+        .register_service_with_config(service, &RegistrationConfig::default())
+        .await?;
     Ok(ServiceHandle { 
         discovery: discovery.inner,
         service,
@@ -151,6 +249,11 @@ pub async fn register_http_service(name: &str, port: u16) -> Result<ServiceHandl
 }
 
 /// Handle for managing a registered service
+///
+/// The service is announced again every
+/// [`RegistrationConfig::refresh_interval`] while the handle lives. Dropping
+/// the handle without [`close`](Self::close) still tells peers the service is
+/// gone, on a best-effort basis.
 pub struct ServiceHandle {
     discovery: ServiceDiscovery,
     service: ServiceInfo,
@@ -162,15 +265,118 @@ impl ServiceHandle {
         self.discovery.unregister_service(&self.service).await
     }
 
+    /// Unregister the service and shut down its discovery instance, sending
+    /// the goodbyes before returning
+    pub async fn close(self) -> Result<()> {
+        self.discovery.close().await
+    }
+
     /// Get service information
     pub fn service(&self) -> &ServiceInfo {
         &self.service
     }
+
+    /// Protocols advertising the service, when it was last announced and
+    /// how often it is refreshed
+    ///
+    /// `None` once the service is unregistered.
+    pub fn status(&self) -> Option<RegistrationStatus> {
+        self.discovery.registration_status(&self.service.service_id())
+    }
+
+    /// Set attributes of the service and announce the change
+    ///
+    /// Attributes not mentioned keep their values.
+    pub async fn update_attributes<I, K, V>(&mut self, attributes: I) -> Result<RegistrationStatus>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let status = self
+            .discovery
+            .update_attributes(&self.service.service_id(), attributes)
+            .await?;
+        self.service = status.service().clone();
+        Ok(status)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{protocols::DiscoveryProtocol, registry::ServiceRegistry};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Protocol whose "Office Printer" only shows up on the third browse
+    struct BootingProtocol {
+        browses: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl DiscoveryProtocol for BootingProtocol {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn discover_services(
+            &self,
+            _service_types: Vec<ServiceType>,
+            _timeout: Option<Duration>,
+        ) -> Result<Vec<ServiceInfo>> {
+            let mut services = vec![ServiceInfo::new("Lobby Printer", "_ipp._tcp", 631, None)?];
+            if self.browses.fetch_add(1, Ordering::SeqCst) >= 2 {
+                services.push(ServiceInfo::new("Office Printer", "_ipp._tcp", 631, None)?);
+            }
+            Ok(services.into_iter().map(|s| s.with_protocol_type(ProtocolType::Upnp)).collect())
+        }
+
+        async fn register_service(&self, _service: ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn unregister_service(&self, _service: &ServiceInfo) -> Result<()> {
+            Ok(())
+        }
+
+        async fn verify_service(&self, _service: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_service() {
+        let config = DiscoveryConfig::new()
+            .with_protocols([ProtocolType::Upnp].into_iter().collect())
+            .with_timeout(Duration::from_secs(1));
+        let discovery = SimpleDiscovery {
+            inner: ServiceDiscovery::builder(config)
+                .with_protocol(BootingProtocol { browses: AtomicUsize::new(0) })
+                .build()
+                .await
+                .unwrap(),
+        };
+
+        let service = discovery
+            .wait_for_service("_ipp._tcp", "Office", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(service.name(), "Office Printer");
+
+        assert!(matches!(
+            discovery.wait_for_service("_ipp._tcp", "Basement", Duration::from_millis(300)).await,
+            Err(DiscoveryError::Timeout(_))
+        ));
+    }
 
     #[tokio::test]
     async fn test_simple_discovery() {
@@ -0,0 +1,315 @@
+//! Presets for common device ecosystems
+//!
+//! Each preset browses the service types of one ecosystem over mDNS and
+//! extracts the fields that ecosystem publishes in its TXT records. Fields
+//! a device does not advertise, or advertises in an unexpected form, are
+//! `None`; the full [`ServiceInfo`] is kept alongside for everything else.
+
+use crate::{
+    config::DiscoveryConfig,
+    error::Result,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+    ServiceDiscovery,
+};
+use std::time::Duration;
+
+/// Service types browsed by [`discover_printers`]
+pub const PRINTER_SERVICE_TYPES: [&str; 2] = ["_ipp._tcp", "_pdl-datastream._tcp"];
+/// Service type browsed by [`discover_airplay`]
+pub const AIRPLAY_SERVICE_TYPE: &str = "_airplay._tcp";
+/// Service type browsed by [`discover_chromecast`]
+pub const CHROMECAST_SERVICE_TYPE: &str = "_googlecast._tcp";
+/// Service type browsed by [`discover_homekit`]
+pub const HOMEKIT_SERVICE_TYPE: &str = "_hap._tcp";
+
+/// How a printer accepts jobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrinterProtocol {
+    /// Internet Printing Protocol (`_ipp._tcp`)
+    Ipp,
+    /// Raw page description language stream, e.g. port 9100 (`_pdl-datastream._tcp`)
+    Raw,
+}
+
+/// A printer, as advertised per the Bonjour Printing specification
+#[derive(Debug, Clone)]
+pub struct Printer {
+    /// How the printer accepts jobs
+    pub protocol: PrinterProtocol,
+    /// Make and model (`ty`)
+    pub model: Option<String>,
+    /// PostScript product name (`product`)
+    pub product: Option<String>,
+    /// Queue path of the printer, e.g. `ipp/print` (`rp`)
+    pub resource_path: Option<String>,
+    /// Location of the printer (`note`)
+    pub location: Option<String>,
+    /// Accepted document formats as MIME types (`pdl`)
+    pub formats: Vec<String>,
+    /// Whether the printer prints in color (`Color`)
+    pub color: Option<bool>,
+    /// Whether the printer prints on both sides (`Duplex`)
+    pub duplex: Option<bool>,
+    /// The advertised service
+    pub service: ServiceInfo,
+}
+
+impl Printer {
+    /// Extract printer fields from a discovered service
+    pub fn from_service(service: ServiceInfo) -> Self {
+        let protocol = if service.service_type.service_name().starts_with("_pdl-datastream") {
+            PrinterProtocol::Raw
+        } else {
+            PrinterProtocol::Ipp
+        };
+        Self {
+            protocol,
+            model: text(&service, "ty"),
+            product: text(&service, "product").map(|product| product.trim_matches(['(', ')']).to_string()),
+            resource_path: text(&service, "rp"),
+            location: text(&service, "note"),
+            formats: text(&service, "pdl")
+                .map(|pdl| pdl.split(',').map(str::trim).filter(|f| !f.is_empty()).map(String::from).collect())
+                .unwrap_or_default(),
+            color: flag(&service, "Color"),
+            duplex: flag(&service, "Duplex"),
+            service,
+        }
+    }
+}
+
+/// An AirPlay receiver
+#[derive(Debug, Clone)]
+pub struct AirPlayDevice {
+    /// Device model, e.g. `AppleTV6,2` (`model`)
+    pub model: Option<String>,
+    /// Device ID, usually a MAC address (`deviceid`)
+    pub device_id: Option<String>,
+    /// Supported feature bits (`features`, given as one or two hex words)
+    pub features: Option<u64>,
+    /// AirPlay source version (`srcvers`)
+    pub source_version: Option<String>,
+    /// The advertised service
+    pub service: ServiceInfo,
+}
+
+impl AirPlayDevice {
+    /// Extract AirPlay fields from a discovered service
+    pub fn from_service(service: ServiceInfo) -> Self {
+        Self {
+            model: text(&service, "model"),
+            device_id: text(&service, "deviceid"),
+            features: text(&service, "features").and_then(|features| parse_features(&features)),
+            source_version: text(&service, "srcvers"),
+            service,
+        }
+    }
+}
+
+/// A Google Cast device
+#[derive(Debug, Clone)]
+pub struct Chromecast {
+    /// Unique device ID (`id`)
+    pub id: Option<String>,
+    /// Name the user gave the device (`fn`)
+    pub friendly_name: Option<String>,
+    /// Device model, e.g. `Chromecast Ultra` (`md`)
+    pub model: Option<String>,
+    /// What the device is currently doing, e.g. the running app (`rs`)
+    pub status: Option<String>,
+    /// The advertised service
+    pub service: ServiceInfo,
+}
+
+impl Chromecast {
+    /// Extract Cast fields from a discovered service
+    pub fn from_service(service: ServiceInfo) -> Self {
+        Self {
+            id: text(&service, "id"),
+            friendly_name: text(&service, "fn"),
+            model: text(&service, "md"),
+            status: text(&service, "rs"),
+            service,
+        }
+    }
+}
+
+/// A HomeKit accessory
+#[derive(Debug, Clone)]
+pub struct HomeKitAccessory {
+    /// Device ID in `XX:XX:XX:XX:XX:XX` form (`id`)
+    pub id: Option<String>,
+    /// Model name (`md`)
+    pub model: Option<String>,
+    /// Accessory category identifier, e.g. 5 for a lightbulb (`ci`)
+    pub category: Option<u16>,
+    /// Configuration number, bumped when the accessory's layout changes (`c#`)
+    pub config_number: Option<u32>,
+    /// Whether the accessory is paired with a controller (from `sf`)
+    pub paired: Option<bool>,
+    /// The advertised service
+    pub service: ServiceInfo,
+}
+
+impl HomeKitAccessory {
+    /// Extract HomeKit fields from a discovered service
+    pub fn from_service(service: ServiceInfo) -> Self {
+        Self {
+            id: text(&service, "id"),
+            model: text(&service, "md"),
+            category: number(&service, "ci"),
+            config_number: number(&service, "c#"),
+            // Status flag bit 0 is set while the accessory is not paired
+            paired: number::<u8>(&service, "sf").map(|flags| flags & 1 == 0),
+            service,
+        }
+    }
+}
+
+/// Discover IPP and raw-socket printers
+///
+/// # Example
+/// ```rust,no_run
+/// use auto_discovery::simple::discover_printers;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     for printer in discover_printers().await? {
+///         println!("{} ({:?})", printer.service.name, printer.model);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn discover_printers() -> Result<Vec<Printer>> {
+    discover(&PRINTER_SERVICE_TYPES, Printer::from_service).await
+}
+
+/// Discover AirPlay receivers
+pub async fn discover_airplay() -> Result<Vec<AirPlayDevice>> {
+    discover(&[AIRPLAY_SERVICE_TYPE], AirPlayDevice::from_service).await
+}
+
+/// Discover Google Cast devices
+pub async fn discover_chromecast() -> Result<Vec<Chromecast>> {
+    discover(&[CHROMECAST_SERVICE_TYPE], Chromecast::from_service).await
+}
+
+/// Discover HomeKit accessories
+pub async fn discover_homekit() -> Result<Vec<HomeKitAccessory>> {
+    discover(&[HOMEKIT_SERVICE_TYPE], HomeKitAccessory::from_service).await
+}
+
+async fn discover<T>(service_types: &[&str], parse: fn(ServiceInfo) -> T) -> Result<Vec<T>> {
+    let mut config = DiscoveryConfig::new()
+        .with_protocol(ProtocolType::Mdns)
+        .with_timeout(Duration::from_secs(5));
+    for service_type in service_types {
+        config = config.with_service_type(ServiceType::new(*service_type)?);
+    }
+    let discovery = ServiceDiscovery::new(config).await?;
+    Ok(discovery.discover_services(None).await?.into_iter().map(parse).collect())
+}
+
+/// Non-empty value of an attribute; TXT keys are case-insensitive
+fn text(service: &ServiceInfo, key: &str) -> Option<String> {
+    service
+        .attributes
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim())
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+fn number<T: std::str::FromStr>(service: &ServiceInfo, key: &str) -> Option<T> {
+    text(service, key)?.parse().ok()
+}
+
+fn flag(service: &ServiceInfo, key: &str) -> Option<bool> {
+    crate::attributes::parse_bool(&text(service, key)?)
+}
+
+/// Parse AirPlay features, `0xLOW` or `0xLOW,0xHIGH`
+fn parse_features(features: &str) -> Option<u64> {
+    let mut words = features.split(',').map(|word| {
+        let word = word.trim();
+        u32::from_str_radix(word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")).unwrap_or(word), 16)
+    });
+    let low = words.next()?.ok()?;
+    let high = match words.next() {
+        Some(high) => high.ok()?,
+        None => 0,
+    };
+    Some(u64::from(high) << 32 | u64::from(low))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(service_type: &str, attributes: Vec<(&str, &str)>) -> ServiceInfo {
+        ServiceInfo::new("device", service_type, 1234, Some(attributes)).unwrap()
+    }
+
+    #[test]
+    fn test_printer_fields() {
+        let printer = Printer::from_service(service(
+            "_ipp._tcp",
+            vec![
+                ("ty", "Acme LaserJet 9"),
+                ("product", "(Acme LaserJet)"),
+                ("rp", "ipp/print"),
+                ("note", "2nd floor"),
+                ("pdl", "application/pdf, image/urf"),
+                ("color", "T"),
+                ("Duplex", "F"),
+            ],
+        ));
+        assert_eq!(printer.protocol, PrinterProtocol::Ipp);
+        assert_eq!(printer.model.as_deref(), Some("Acme LaserJet 9"));
+        assert_eq!(printer.product.as_deref(), Some("Acme LaserJet"));
+        assert_eq!(printer.resource_path.as_deref(), Some("ipp/print"));
+        assert_eq!(printer.location.as_deref(), Some("2nd floor"));
+        assert_eq!(printer.formats, ["application/pdf", "image/urf"]);
+        assert_eq!((printer.color, printer.duplex), (Some(true), Some(false)));
+
+        let raw = Printer::from_service(service("_pdl-datastream._tcp", vec![]));
+        assert_eq!(raw.protocol, PrinterProtocol::Raw);
+        assert!(raw.model.is_none() && raw.formats.is_empty() && raw.color.is_none());
+    }
+
+    #[test]
+    fn test_media_device_fields() {
+        let airplay = AirPlayDevice::from_service(service(
+            AIRPLAY_SERVICE_TYPE,
+            vec![("model", "AppleTV6,2"), ("deviceid", "AA:BB:CC:DD:EE:FF"), ("features", "0x5A7FFFF7,0x1E")],
+        ));
+        assert_eq!(airplay.model.as_deref(), Some("AppleTV6,2"));
+        assert_eq!(airplay.features, Some(0x1E_5A7F_FFF7));
+        assert_eq!(parse_features("0x10"), Some(0x10));
+        assert_eq!(parse_features("bogus"), None);
+
+        let cast = Chromecast::from_service(service(
+            CHROMECAST_SERVICE_TYPE,
+            vec![("id", "abc123"), ("fn", "Living Room"), ("md", "Chromecast Ultra"), ("rs", "")],
+        ));
+        assert_eq!(cast.friendly_name.as_deref(), Some("Living Room"));
+        assert_eq!(cast.model.as_deref(), Some("Chromecast Ultra"));
+        assert_eq!(cast.status, None);
+    }
+
+    #[test]
+    fn test_homekit_fields() {
+        let accessory = HomeKitAccessory::from_service(service(
+            HOMEKIT_SERVICE_TYPE,
+            vec![("id", "12:34:56:78:9A:BC"), ("md", "Bulb"), ("ci", "5"), ("c#", "3"), ("sf", "1")],
+        ));
+        assert_eq!(accessory.category, Some(5));
+        assert_eq!(accessory.config_number, Some(3));
+        assert_eq!(accessory.paired, Some(false));
+
+        let paired = HomeKitAccessory::from_service(service(HOMEKIT_SERVICE_TYPE, vec![("sf", "0"), ("ci", "x")]));
+        assert_eq!((paired.paired, paired.category), (Some(true), None));
+    }
+}
@@ -0,0 +1,14 @@
+//! Support for testing code built on the library
+//!
+//! Enabled with the `testing` feature. [`MockProtocol`] stands in for a real
+//! discovery protocol, so discovery logic can be tested deterministically
+//! without a network, and [`NetworkSimulator`] connects several in-process
+//! hosts to see how they behave towards each other. [`stress`] measures how
+//! a protocol holds up under load.
+
+pub mod mock;
+pub mod simulator;
+pub mod stress;
+
+pub use mock::MockProtocol;
+pub use simulator::{NetworkSimulator, SimulatedProtocol, VirtualHost};
@@ -0,0 +1,299 @@
+//! In-memory discovery protocol with programmable behaviour
+//!
+//! A [`MockProtocol`] answers discovery from a list of services it is given,
+//! remembers what is registered with it and can be told to be slow or to
+//! fail. Clones share their state, so a test keeps one clone to program and
+//! inspect while another is handed to the code under test.
+//!
+//! ```
+//! use auto_discovery::{
+//!     protocols::ProtocolManager, testing::MockProtocol, DiscoveryConfig, ProtocolType, ServiceInfo,
+//! };
+//! use std::sync::Arc;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> auto_discovery::Result<()> {
+//! let mock = MockProtocol::new(ProtocolType::Mdns)
+//!     .with_service(ServiceInfo::new("Printer", "_ipp._tcp", 631, None)?);
+//! let manager = ProtocolManager::with_protocols(DiscoveryConfig::new(), vec![Arc::new(mock.clone())]);
+//!
+//! let services = manager.discover_services(vec![], None).await.flatten()?;
+//! assert_eq!(services.len(), 1);
+//! assert_eq!(mock.discovery_calls(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    error::{DiscoveryError, Result},
+    protocols::{Capabilities, DiscoveryProtocol},
+    registry::ServiceRegistry,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+struct MockState {
+    /// Services answering discovery, registered ones included
+    services: Vec<ServiceInfo>,
+    /// Services registered through the protocol
+    registered: Vec<ServiceInfo>,
+    /// Delay before every operation completes
+    latency: Duration,
+    /// Errors for the next operations, in order
+    queued_failures: VecDeque<DiscoveryError>,
+    /// Error for every operation, once the queue is empty
+    failure: Option<DiscoveryError>,
+    unavailable: bool,
+    discovery_calls: usize,
+}
+
+/// Discovery protocol that works entirely in memory
+///
+/// Discovery returns the known services of the requested types, or all of
+/// them when no type is given, tagged with the mock's protocol type.
+/// Registering adds a service to those answers and unregistering removes
+/// it; verification succeeds for services the mock knows.
+#[derive(Debug, Clone)]
+pub struct MockProtocol {
+    protocol_type: ProtocolType,
+    capabilities: Capabilities,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockProtocol {
+    /// Create a mock standing in for `protocol_type`
+    pub fn new(protocol_type: ProtocolType) -> Self {
+        Self {
+            protocol_type,
+            capabilities: Capabilities::default(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Answer discovery with `service`
+    pub fn with_service(self, service: ServiceInfo) -> Self {
+        self.add_service(service);
+        self
+    }
+
+    /// Delay every operation by `latency`
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.set_latency(latency);
+        self
+    }
+
+    /// Report `capabilities` instead of the defaults
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start answering discovery with `service`
+    pub fn add_service(&self, service: ServiceInfo) {
+        let service = service.with_protocol_type(self.protocol_type);
+        let mut state = self.lock();
+        state.services.retain(|known| !same_instance(known, &service));
+        state.services.push(service);
+    }
+
+    /// Stop answering discovery with the services called `name`
+    pub fn remove_service(&self, name: &str) {
+        self.lock().services.retain(|service| service.name != name);
+    }
+
+    /// Change the delay of every operation
+    pub fn set_latency(&self, latency: Duration) {
+        self.lock().latency = latency;
+    }
+
+    /// Fail the next operation with `error`
+    ///
+    /// Several calls queue several failures, used one per operation.
+    pub fn fail_next(&self, error: DiscoveryError) {
+        self.lock().queued_failures.push_back(error);
+    }
+
+    /// Fail every operation with `error`, or stop failing with `None`
+    pub fn fail_always(&self, error: Option<DiscoveryError>) {
+        self.lock().failure = error;
+    }
+
+    /// Change what [`is_available`](DiscoveryProtocol::is_available) reports
+    pub fn set_available(&self, available: bool) {
+        self.lock().unavailable = !available;
+    }
+
+    /// Services currently registered through the protocol
+    pub fn registered(&self) -> Vec<ServiceInfo> {
+        self.lock().registered.clone()
+    }
+
+    /// How often discovery ran
+    pub fn discovery_calls(&self) -> usize {
+        self.lock().discovery_calls
+    }
+
+    /// Wait out the latency, then take the failure due for this operation
+    async fn operation(&self) -> Result<()> {
+        let latency = self.lock().latency;
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        let mut state = self.lock();
+        match state.queued_failures.pop_front().or_else(|| state.failure.clone()) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether two services are the same advertised instance
+fn same_instance(a: &ServiceInfo, b: &ServiceInfo) -> bool {
+    a.name == b.name && a.service_type == b.service_type
+}
+
+#[async_trait]
+impl DiscoveryProtocol for MockProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        self.protocol_type
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        _timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        self.lock().discovery_calls += 1;
+        self.operation().await?;
+        let state = self.lock();
+        Ok(state
+            .services
+            .iter()
+            .filter(|service| service_types.is_empty() || service_types.contains(&service.service_type))
+            .cloned()
+            .collect())
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        self.operation().await?;
+        let service = service.with_protocol_type(self.protocol_type);
+        let mut state = self.lock();
+        state.registered.retain(|known| !same_instance(known, &service));
+        state.registered.push(service.clone());
+        state.services.retain(|known| !same_instance(known, &service));
+        state.services.push(service);
+        Ok(())
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        self.operation().await?;
+        let mut state = self.lock();
+        let before = state.registered.len();
+        state.registered.retain(|known| !same_instance(known, service));
+        if state.registered.len() == before {
+            return Err(DiscoveryError::service_not_found(service.name.clone()));
+        }
+        state.services.retain(|known| !same_instance(known, service));
+        Ok(())
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        self.operation().await?;
+        Ok(self.lock().services.iter().any(|known| same_instance(known, service)))
+    }
+
+    async fn is_available(&self) -> bool {
+        !self.lock().unavailable
+    }
+
+    fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+
+    async fn enumerate_service_types(&self, _timeout: Duration) -> Result<Vec<ServiceType>> {
+        self.operation().await?;
+        let mut service_types: Vec<ServiceType> =
+            self.lock().services.iter().map(|service| service.service_type.clone()).collect();
+        service_types.sort_by_key(|service_type| service_type.to_string());
+        service_types.dedup();
+        Ok(service_types)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{ConflictPolicy, DiscoveryConfig, RegistrationConfig},
+        error::ErrorKind,
+        protocols::ProtocolManager,
+        ServiceDiscovery,
+    };
+
+    fn service(name: &str, service_type: &str) -> ServiceInfo {
+        ServiceInfo::new(name, service_type, 8080, None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_programmed_answers_and_failures() {
+        let mock = MockProtocol::new(ProtocolType::Upnp)
+            .with_service(service("Printer", "_ipp._tcp"))
+            .with_service(service("Web", "_http._tcp"));
+        let manager = ProtocolManager::with_protocols(DiscoveryConfig::new(), vec![Arc::new(mock.clone())]);
+        let ipp = ServiceType::new("_ipp._tcp").unwrap();
+
+        let found = manager.discover_services(vec![ipp.clone()], None).await.flatten().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].protocol_type, ProtocolType::Upnp);
+
+        mock.fail_next(DiscoveryError::network("link down"));
+        let outcome = manager.discover_services(vec![ipp.clone()], None).await;
+        assert_eq!(outcome.failures()[0].1.kind(), ErrorKind::Network);
+        assert_eq!(manager.discover_services(vec![ipp], None).await.flatten().unwrap().len(), 1);
+        assert_eq!(mock.discovery_calls(), 3);
+
+        let types = manager.enumerate_service_types(Duration::from_secs(1)).await.flatten().unwrap();
+        assert_eq!(types.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_slow_protocol_is_abandoned_at_the_deadline() {
+        let mock = MockProtocol::new(ProtocolType::Mdns)
+            .with_service(service("Slow", "_http._tcp"))
+            .with_latency(Duration::from_secs(30));
+        let manager = ProtocolManager::with_protocols(DiscoveryConfig::new(), vec![Arc::new(mock)]);
+
+        let outcome = manager.discover_services(vec![], Some(Duration::from_millis(50))).await;
+        assert_eq!(outcome.failures()[0].1.kind(), ErrorKind::Timeout);
+    }
+
+    #[tokio::test]
+    async fn test_registration_through_service_discovery() {
+        let mock = MockProtocol::new(ProtocolType::Upnp);
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config).with_protocol(mock.clone()).build().await.unwrap();
+
+        let printer = service("Printer", "_ipp._tcp").with_protocol_type(ProtocolType::Upnp);
+        let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::Ignore);
+        discovery.register_service_with_config(printer.clone(), &registration).await.unwrap();
+        assert_eq!(mock.registered().len(), 1);
+        assert!(discovery.verify_service(&printer).await.unwrap());
+
+        discovery.close().await.unwrap();
+        assert!(mock.registered().is_empty());
+        assert!(!discovery.verify_service(&printer).await.unwrap());
+    }
+}
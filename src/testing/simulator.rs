@@ -0,0 +1,655 @@
+//! Virtual networks of in-process hosts
+//!
+//! A [`NetworkSimulator`] is a shared medium that [`VirtualHost`]s attach
+//! to. Each host speaks simulated mDNS, SSDP or DNS-SD through a
+//! [`SimulatedProtocol`]: queries are multicast to every other host on the
+//! medium, hosts answer from the services registered with them, and
+//! registering or unregistering a service multicasts an announcement or a
+//! goodbye that peers apply to their registries. Messages travel over
+//! channels rather than sockets, so registration, name conflicts, TTL expiry
+//! and goodbyes can be tested deterministically on any machine.
+//!
+//! ```
+//! use auto_discovery::{
+//!     config::{ConflictPolicy, RegistrationConfig},
+//!     testing::NetworkSimulator,
+//!     DiscoveryConfig, ProtocolType, ServiceInfo,
+//! };
+//!
+//! # #[tokio::main]
+//! # async fn main() -> auto_discovery::Result<()> {
+//! let network = NetworkSimulator::new();
+//! let config = DiscoveryConfig::new().with_protocols([ProtocolType::Mdns].into_iter().collect());
+//! let office = network.add_host().discovery_builder(config.clone()).build().await?;
+//! let laptop = network.add_host().discovery_builder(config).build().await?;
+//!
+//! let printer = || ServiceInfo::new("Printer", "_ipp._tcp", 631, None);
+//! let registration = RegistrationConfig::new().conflict_policy(ConflictPolicy::AutoRename { max_attempts: 3 });
+//! office.register_service_with_config(printer()?, &registration).await?;
+//!
+//! // The name is taken on the network, so the second printer is renamed
+//! let second = laptop.register_service_with_config(printer()?, &registration).await?;
+//! assert_eq!(second.name, "Printer (2)");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    config::DiscoveryConfig,
+    discovery::{ServiceDiscovery, ServiceDiscoveryBuilder},
+    error::{DiscoveryError, Result},
+    protocols::DiscoveryProtocol,
+    registry::ServiceRegistry,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use async_trait::async_trait;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::debug;
+
+/// Packets the medium buffers for each host before it lags
+const WIRE_CAPACITY: usize = 1024;
+
+/// How long a query waits for answers when no timeout is given
+const DEFAULT_RESPONSE_WINDOW: Duration = Duration::from_secs(1);
+
+/// TTL announcements carry unless configured otherwise, as in mDNS
+const DEFAULT_ANNOUNCE_TTL: Duration = Duration::from_secs(120);
+
+/// A message on the simulated medium
+#[derive(Debug, Clone)]
+pub struct Packet {
+    /// Address of the sending host
+    pub from: IpAddr,
+    /// Protocol the message belongs to
+    pub protocol: ProtocolType,
+    /// What was sent
+    pub message: Message,
+}
+
+/// Messages the simulated protocols exchange
+#[derive(Debug, Clone)]
+pub enum Message {
+    /// Multicast question for services of the given types, or of any type
+    /// when empty
+    Query {
+        /// Identifies the answers to this query
+        id: u64,
+        /// Types asked for
+        service_types: Vec<ServiceType>,
+    },
+    /// Answer to a query, sent by every host that heard it
+    Response {
+        /// The query answered
+        id: u64,
+        /// Host that asked
+        to: IpAddr,
+        /// Matching services registered with the answering host
+        services: Vec<ServiceInfo>,
+    },
+    /// Unsolicited advertisement of a newly registered service
+    Announce {
+        /// The service
+        service: ServiceInfo,
+        /// How long peers may keep the service without hearing from it again
+        ttl: Duration,
+    },
+    /// The service is going away
+    Goodbye {
+        /// The service
+        service: ServiceInfo,
+    },
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The medium shared by all hosts of a simulator
+#[derive(Debug)]
+struct Wire {
+    packets: broadcast::Sender<Packet>,
+    latency: Mutex<Duration>,
+    announce_ttl: Mutex<Duration>,
+    hosts: AtomicU32,
+    queries: AtomicU64,
+    /// Hosts listening, per protocol
+    listeners: Mutex<HashMap<ProtocolType, HashSet<IpAddr>>>,
+    disconnected: Mutex<HashSet<IpAddr>>,
+}
+
+impl Wire {
+    fn is_connected(&self, address: IpAddr) -> bool {
+        !lock(&self.disconnected).contains(&address)
+    }
+
+    /// Connected hosts other than `address` listening for `protocol`
+    fn peers(&self, protocol: ProtocolType, address: IpAddr) -> HashSet<IpAddr> {
+        let disconnected = lock(&self.disconnected);
+        lock(&self.listeners)
+            .get(&protocol)
+            .map(|hosts| {
+                hosts
+                    .iter()
+                    .filter(|&&host| host != address && !disconnected.contains(&host))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Put a packet on the medium, after the configured latency
+    ///
+    /// Packets from disconnected hosts are lost.
+    fn send(&self, packet: Packet) {
+        if !self.is_connected(packet.from) {
+            return;
+        }
+        let latency = *lock(&self.latency);
+        if latency.is_zero() {
+            // Nobody listening is not an error
+            let _ = self.packets.send(packet);
+        } else {
+            let packets = self.packets.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(latency).await;
+                let _ = packets.send(packet);
+            });
+        }
+    }
+}
+
+/// Shared medium for virtual hosts
+///
+/// Hosts get consecutive addresses from `10.0.0.1` on. Packets reach every
+/// connected host, after the configured latency.
+#[derive(Debug, Clone)]
+pub struct NetworkSimulator {
+    wire: Arc<Wire>,
+}
+
+impl Default for NetworkSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkSimulator {
+    /// Create an empty network without latency
+    pub fn new() -> Self {
+        let (packets, _) = broadcast::channel(WIRE_CAPACITY);
+        Self {
+            wire: Arc::new(Wire {
+                packets,
+                latency: Mutex::new(Duration::ZERO),
+                announce_ttl: Mutex::new(DEFAULT_ANNOUNCE_TTL),
+                hosts: AtomicU32::new(0),
+                queries: AtomicU64::new(0),
+                listeners: Mutex::new(HashMap::new()),
+                disconnected: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+
+    /// Delay every packet by `latency`
+    pub fn with_latency(self, latency: Duration) -> Self {
+        self.set_latency(latency);
+        self
+    }
+
+    /// TTL hosts put on their announcements
+    pub fn with_announce_ttl(self, ttl: Duration) -> Self {
+        *lock(&self.wire.announce_ttl) = ttl;
+        self
+    }
+
+    /// Change the delay of every packet
+    pub fn set_latency(&self, latency: Duration) {
+        *lock(&self.wire.latency) = latency;
+    }
+
+    /// Attach a new host to the network
+    pub fn add_host(&self) -> VirtualHost {
+        let index = self.wire.hosts.fetch_add(1, Ordering::Relaxed) + 1;
+        VirtualHost {
+            address: IpAddr::V4(Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + index)),
+            wire: self.wire.clone(),
+            protocols: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cut `host` off: it neither sends nor receives until reconnected
+    ///
+    /// Peers get no goodbyes, as when a machine loses power.
+    pub fn disconnect(&self, host: &VirtualHost) {
+        lock(&self.wire.disconnected).insert(host.address);
+    }
+
+    /// Attach a disconnected host again
+    pub fn reconnect(&self, host: &VirtualHost) {
+        lock(&self.wire.disconnected).remove(&host.address);
+    }
+
+    /// Receive a copy of every packet put on the medium from now on
+    pub fn capture(&self) -> broadcast::Receiver<Packet> {
+        self.wire.packets.subscribe()
+    }
+}
+
+/// A machine on a simulated network
+#[derive(Debug)]
+pub struct VirtualHost {
+    address: IpAddr,
+    wire: Arc<Wire>,
+    protocols: Mutex<HashMap<ProtocolType, SimulatedProtocol>>,
+}
+
+impl VirtualHost {
+    /// Address of the host on the network
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    /// The host's endpoint for `protocol_type`
+    ///
+    /// Every call for the same protocol returns a handle to the same
+    /// endpoint. The first one starts the endpoint's listener, so it must be
+    /// made within a Tokio runtime.
+    pub fn protocol(&self, protocol_type: ProtocolType) -> SimulatedProtocol {
+        lock(&self.protocols)
+            .entry(protocol_type)
+            .or_insert_with(|| SimulatedProtocol::start(self.address, protocol_type, self.wire.clone()))
+            .clone()
+    }
+
+    /// Builder for a discovery instance on this host
+    ///
+    /// Every protocol enabled in `config` is simulated, so the instance
+    /// never touches the real network. One instance per host is expected:
+    /// announcements from peers go to the registry of the last one built.
+    pub fn discovery_builder(&self, config: DiscoveryConfig) -> ServiceDiscoveryBuilder {
+        let protocols: Vec<ProtocolType> = config.protocols().iter().copied().collect();
+        protocols
+            .into_iter()
+            .fold(ServiceDiscovery::builder(config), |builder, protocol_type| {
+                builder.with_protocol(self.protocol(protocol_type))
+            })
+    }
+}
+
+/// State shared between a protocol endpoint and its listener
+struct EndpointState {
+    address: IpAddr,
+    protocol_type: ProtocolType,
+    wire: Arc<Wire>,
+    /// Services registered with this host
+    local: Mutex<Vec<ServiceInfo>>,
+    /// Where announcements and goodbyes from peers are applied
+    registry: Mutex<Option<Arc<ServiceRegistry>>>,
+}
+
+impl EndpointState {
+    fn matching(&self, service_types: &[ServiceType]) -> Vec<ServiceInfo> {
+        lock(&self.local)
+            .iter()
+            .filter(|service| service_types.is_empty() || service_types.contains(&service.service_type))
+            .cloned()
+            .collect()
+    }
+
+    fn send(&self, message: Message) {
+        self.wire.send(Packet {
+            from: self.address,
+            protocol: self.protocol_type,
+            message,
+        });
+    }
+
+    /// React to a packet from the medium
+    async fn handle(&self, packet: Packet) {
+        if packet.protocol != self.protocol_type
+            || packet.from == self.address
+            || !self.wire.is_connected(self.address)
+        {
+            return;
+        }
+        let registry = lock(&self.registry).clone();
+        match packet.message {
+            Message::Query { id, service_types } => {
+                // Even an empty answer tells the asker this host has spoken
+                let services = self.matching(&service_types);
+                self.send(Message::Response { id, to: packet.from, services });
+            }
+            Message::Announce { service, ttl } => {
+                if let Some(registry) = registry
+                    && let Err(e) = registry.add_discovered_service(service, self.protocol_type, Some(ttl)).await
+                {
+                    debug!("{} dropped an announcement from {}: {}", self.address, packet.from, e);
+                }
+            }
+            Message::Goodbye { service } => {
+                if let Some(registry) = registry {
+                    registry.remove_discovered_service(&service.service_id()).await;
+                }
+            }
+            Message::Response { .. } => {}
+        }
+    }
+
+    /// Multicast a query and collect the answers
+    ///
+    /// Returns once every peer connected at the time of the query has
+    /// answered, or when `window` runs out.
+    async fn query(&self, service_types: Vec<ServiceType>, window: Duration) -> Vec<ServiceInfo> {
+        let mut packets = self.wire.packets.subscribe();
+        let peers = if self.wire.is_connected(self.address) {
+            self.wire.peers(self.protocol_type, self.address)
+        } else {
+            HashSet::new()
+        };
+        let id = self.wire.queries.fetch_add(1, Ordering::Relaxed);
+        self.send(Message::Query { id, service_types });
+
+        let mut answered = HashSet::new();
+        let mut services = Vec::new();
+        let collect = async {
+            while answered.len() < peers.len() {
+                match packets.recv().await {
+                    Ok(Packet { from, message: Message::Response { id: answer, to, services: found }, .. })
+                        if answer == id && to == self.address && self.wire.is_connected(self.address) =>
+                    {
+                        if answered.insert(from) {
+                            services.extend(found);
+                        }
+                    }
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        };
+        let _ = tokio::time::timeout(window, collect).await;
+        services
+    }
+}
+
+/// Endpoint of a simulated protocol on a [`VirtualHost`]
+///
+/// Clones are handles to the same endpoint. The endpoint listens for as
+/// long as a handle or its host exists.
+#[derive(Clone)]
+pub struct SimulatedProtocol {
+    endpoint: Arc<Endpoint>,
+}
+
+struct Endpoint {
+    state: Arc<EndpointState>,
+    /// Stops the listener when the endpoint goes away
+    _listener: DropGuard,
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let state = &self.state;
+        if let Some(hosts) = lock(&state.wire.listeners).get_mut(&state.protocol_type) {
+            hosts.remove(&state.address);
+        }
+    }
+}
+
+impl std::fmt::Debug for SimulatedProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulatedProtocol")
+            .field("address", &self.address())
+            .field("protocol_type", &self.protocol_type())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SimulatedProtocol {
+    fn start(address: IpAddr, protocol_type: ProtocolType, wire: Arc<Wire>) -> Self {
+        let state = Arc::new(EndpointState {
+            address,
+            protocol_type,
+            wire: wire.clone(),
+            local: Mutex::new(Vec::new()),
+            registry: Mutex::new(None),
+        });
+        lock(&wire.listeners).entry(protocol_type).or_default().insert(address);
+
+        let token = CancellationToken::new();
+        let mut packets = wire.packets.subscribe();
+        let listener = state.clone();
+        let cancelled = token.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = cancelled.cancelled() => break,
+                    packet = packets.recv() => match packet {
+                        Ok(packet) => listener.handle(packet).await,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            debug!("{} missed {} simulated packets", listener.address, missed);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        });
+
+        Self {
+            endpoint: Arc::new(Endpoint {
+                state,
+                _listener: token.drop_guard(),
+            }),
+        }
+    }
+
+    fn state(&self) -> &EndpointState {
+        &self.endpoint.state
+    }
+
+    /// Address of the host the endpoint belongs to
+    pub fn address(&self) -> IpAddr {
+        self.state().address
+    }
+
+    /// Services registered with the endpoint
+    pub fn registered(&self) -> Vec<ServiceInfo> {
+        lock(&self.state().local).clone()
+    }
+}
+
+/// Whether two services are the same advertised instance
+fn same_instance(a: &ServiceInfo, b: &ServiceInfo) -> bool {
+    a.name == b.name && a.service_type == b.service_type
+}
+
+#[async_trait]
+impl DiscoveryProtocol for SimulatedProtocol {
+    fn protocol_type(&self) -> ProtocolType {
+        self.state().protocol_type
+    }
+
+    async fn discover_services(
+        &self,
+        service_types: Vec<ServiceType>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<ServiceInfo>> {
+        Ok(self
+            .state()
+            .query(service_types, timeout.unwrap_or(DEFAULT_RESPONSE_WINDOW))
+            .await)
+    }
+
+    async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+        let state = self.state();
+        let mut service = service.with_protocol_type(state.protocol_type);
+        // Advertise the host's own address, as a real stack would
+        if service.address.is_loopback() || service.address.is_unspecified() {
+            service.address = state.address;
+        }
+        {
+            let mut local = lock(&state.local);
+            local.retain(|known| !same_instance(known, &service));
+            local.push(service.clone());
+        }
+        let ttl = *lock(&state.wire.announce_ttl);
+        state.send(Message::Announce { service, ttl });
+        Ok(())
+    }
+
+    async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+        let state = self.state();
+        let removed = {
+            let mut local = lock(&state.local);
+            let position = local.iter().position(|known| same_instance(known, service));
+            position.map(|position| local.remove(position))
+        };
+        let Some(service) = removed else {
+            return Err(DiscoveryError::service_not_found(service.name.clone()));
+        };
+        state.send(Message::Goodbye { service });
+        Ok(())
+    }
+
+    async fn verify_service(&self, service: &ServiceInfo) -> Result<bool> {
+        let answers = self
+            .state()
+            .query(vec![service.service_type.clone()], DEFAULT_RESPONSE_WINDOW)
+            .await;
+        Ok(answers.iter().any(|answer| same_instance(answer, service)))
+    }
+
+    async fn is_available(&self) -> bool {
+        self.state().wire.is_connected(self.address())
+    }
+
+    fn set_registry(&mut self, registry: Arc<ServiceRegistry>) {
+        *lock(&self.state().registry) = Some(registry);
+    }
+
+    async fn enumerate_service_types(&self, timeout: Duration) -> Result<Vec<ServiceType>> {
+        let mut service_types: Vec<ServiceType> = self
+            .state()
+            .query(Vec::new(), timeout)
+            .await
+            .into_iter()
+            .map(|service| service.service_type)
+            .collect();
+        service_types.sort_by_key(|service_type| service_type.to_string());
+        service_types.dedup();
+        Ok(service_types)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::ServiceEvent;
+
+    fn mdns() -> DiscoveryConfig {
+        DiscoveryConfig::new().with_protocols([ProtocolType::Mdns].into_iter().collect())
+    }
+
+    fn printer() -> ServiceInfo {
+        ServiceInfo::new("Printer", "_ipp._tcp", 631, None).unwrap()
+    }
+
+    /// Wait for the next event matching `wanted`
+    async fn next_event(
+        events: &mut broadcast::Receiver<ServiceEvent>,
+        wanted: impl Fn(&ServiceEvent) -> bool,
+    ) -> ServiceEvent {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if wanted(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("event did not arrive")
+    }
+
+    #[tokio::test]
+    async fn test_hosts_discover_each_other() {
+        let network = NetworkSimulator::new().with_latency(Duration::from_millis(5));
+        let hosts: Vec<VirtualHost> = (0..3).map(|_| network.add_host()).collect();
+        for (index, host) in hosts.iter().enumerate() {
+            let service = ServiceInfo::new(format!("Node {index}"), "_node._tcp", 7000, None).unwrap();
+            host.protocol(ProtocolType::Mdns).register_service(service).await.unwrap();
+        }
+
+        let mut found = hosts[0]
+            .protocol(ProtocolType::Mdns)
+            .discover_services(vec![ServiceType::new("_node._tcp").unwrap()], Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        found.sort_by(|a, b| a.name.cmp(&b.name));
+        let names: Vec<&str> = found.iter().map(|service| service.name.as_str()).collect();
+        assert_eq!(names, ["Node 1", "Node 2"]);
+        assert_eq!(found[0].address, hosts[1].address());
+
+        // Other protocols do not hear mDNS
+        let ssdp = hosts[0].protocol(ProtocolType::Upnp);
+        assert!(ssdp.discover_services(vec![], None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_goodbyes_and_ttl_expiry_reach_peers() {
+        let network = NetworkSimulator::new().with_announce_ttl(Duration::from_millis(100));
+        let registry = Arc::new(ServiceRegistry::new());
+        let server = network.add_host().discovery_builder(mdns()).build().await.unwrap();
+        let _client = network
+            .add_host()
+            .discovery_builder(mdns())
+            .with_registry(registry.clone())
+            .build()
+            .await
+            .unwrap();
+        let mut events = registry.subscribe();
+
+        server.register_service(printer()).await.unwrap();
+        next_event(&mut events, |event| matches!(event, ServiceEvent::New { .. })).await;
+        server.close().await.unwrap();
+        next_event(&mut events, |event| matches!(event, ServiceEvent::Removed { .. })).await;
+        assert!(registry.get_discovered_services().await.is_empty());
+
+        // Without a goodbye the announcement runs out instead
+        server.register_service(printer()).await.unwrap();
+        next_event(&mut events, |event| matches!(event, ServiceEvent::New { .. })).await;
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(registry.cleanup_expired().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_hosts_are_silent() {
+        let network = NetworkSimulator::new();
+        let server = network.add_host();
+        let client = network.add_host();
+        server.protocol(ProtocolType::Upnp).register_service(printer()).await.unwrap();
+
+        network.disconnect(&server);
+        let window = Some(Duration::from_millis(50));
+        let client_ssdp = client.protocol(ProtocolType::Upnp);
+        assert!(client_ssdp.discover_services(vec![], window).await.unwrap().is_empty());
+        assert!(!server.protocol(ProtocolType::Upnp).is_available().await);
+
+        network.reconnect(&server);
+        assert_eq!(client_ssdp.discover_services(vec![], window).await.unwrap().len(), 1);
+        assert_eq!(
+            client_ssdp.enumerate_service_types(Duration::from_secs(1)).await.unwrap(),
+            [ServiceType::new("_ipp._tcp").unwrap()]
+        );
+    }
+}
@@ -1,49 +1,156 @@
+//! Load tests of a discovery protocol
+//!
+//! [`run_stress_test`] registers a batch of services with one protocol while
+//! concurrent workers keep discovering them. It reports success counts,
+//! latency percentiles, failures by kind and throughput over time. The
+//! results serialize to JSON so runs can be compared between releases.
+//!
+//! ```
+//! use auto_discovery::{
+//!     testing::{stress::{run_stress_test_with, StressTestConfig}, MockProtocol},
+//!     ProtocolType,
+//! };
+//! use std::{sync::Arc, time::Duration};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> auto_discovery::Result<()> {
+//! let config = StressTestConfig {
+//!     service_count: 20,
+//!     test_duration: Duration::from_millis(200),
+//!     ..StressTestConfig::default()
+//! };
+//! let results = run_stress_test_with(Arc::new(MockProtocol::new(ProtocolType::Mdns)), config).await;
+//! println!("{}", results.to_json()?);
+//! # Ok(())
+//! # }
+//! ```
+
 use crate::{
     config::DiscoveryConfig,
-    error::Result,
-    protocols::{DiscoveryProtocol, dns_sd::DnsSdProtocol},
+    error::{DiscoveryError, ErrorKind, Result},
+    protocols::{DiscoveryProtocol, ProtocolManager},
     service::ServiceInfo,
     types::{ProtocolType, ServiceType},
 };
-use dashmap::DashMap;
+use governor::{DefaultDirectRateLimiter, Quota, RateLimiter};
+use serde::Serialize;
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    collections::BTreeMap,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
-use tokio::{
-    sync::{Barrier, Semaphore},
-    task::JoinSet,
-    time::sleep,
-};
 use tracing::{debug, info, warn};
 
+/// Service type the stress test registers and discovers
+const STRESS_SERVICE_TYPE: &str = "_stress._tcp";
+
+/// Registrations in flight at once
+const REGISTRATION_CONCURRENCY: usize = 20;
+
 /// Stress test configuration
+#[derive(Debug, Clone)]
 pub struct StressTestConfig {
+    /// Protocol under test when the test creates it
+    pub protocol: ProtocolType,
     /// Number of concurrent services to register
     pub service_count: usize,
     /// Number of concurrent discovery operations
     pub discovery_concurrency: usize,
     /// Duration to run the test
     pub test_duration: Duration,
+    /// How long each discovery waits for answers
+    pub discovery_timeout: Duration,
     /// Rate limit for operations (per second)
     pub rate_limit: Option<u32>,
+    /// Width of the time buckets throughput is reported in
+    pub bucket_width: Duration,
 }
 
 impl Default for StressTestConfig {
     fn default() -> Self {
         Self {
+            protocol: ProtocolType::Mdns,
             service_count: 100,
             discovery_concurrency: 10,
             test_duration: Duration::from_secs(60),
+            discovery_timeout: Duration::from_secs(5),
             rate_limit: Some(1000),
+            bucket_width: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Distribution of operation latencies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LatencyStats {
+    /// Number of measured operations
+    pub samples: usize,
+    /// Fastest operation
+    pub min: Duration,
+    /// Mean latency
+    pub mean: Duration,
+    /// Median latency
+    pub p50: Duration,
+    /// 90th percentile
+    pub p90: Duration,
+    /// 95th percentile
+    pub p95: Duration,
+    /// 99th percentile
+    pub p99: Duration,
+    /// Slowest operation
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Summarize measured latencies; all zero when there are none
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let (Some(&min), Some(&max)) = (sorted.first(), sorted.last()) else {
+            return Self::default();
+        };
+        let total: Duration = sorted.iter().sum();
+        Self {
+            samples: sorted.len(),
+            min,
+            mean: total / sorted.len() as u32,
+            p50: percentile(&sorted, 50),
+            p90: percentile(&sorted, 90),
+            p95: percentile(&sorted, 95),
+            p99: percentile(&sorted, 99),
+            max,
         }
     }
 }
 
+/// Nearest-rank percentile of sorted, non-empty samples
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Operations completed during one time bucket of a stress test
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ThroughputBucket {
+    /// Start of the bucket, measured from the start of the test
+    pub start: Duration,
+    /// Registrations that succeeded
+    pub registrations: usize,
+    /// Discoveries that succeeded
+    pub discoveries: usize,
+    /// Operations of either kind that failed
+    pub failures: usize,
+}
+
 /// Stress test results
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct StressTestResults {
+    /// Protocol that was tested
+    pub protocol: ProtocolType,
     /// Total number of successful registrations
     pub successful_registrations: usize,
     /// Total number of failed registrations
@@ -52,230 +159,356 @@ pub struct StressTestResults {
     pub successful_discoveries: usize,
     /// Total number of failed discoveries
     pub failed_discoveries: usize,
-    /// Average discovery latency
-    pub avg_discovery_latency: Duration,
-    /// Maximum discovery latency
-    pub max_discovery_latency: Duration,
+    /// Latency of the successful registrations
+    pub registration_latency: LatencyStats,
+    /// Latency of the successful discoveries
+    pub discovery_latency: LatencyStats,
+    /// Failed registrations by kind of error
+    pub registration_errors: BTreeMap<ErrorKind, usize>,
+    /// Failed discoveries by kind of error
+    pub discovery_errors: BTreeMap<ErrorKind, usize>,
+    /// Completed operations per [`bucket_width`](StressTestConfig::bucket_width),
+    /// in order; buckets without operations are included
+    pub throughput: Vec<ThroughputBucket>,
     /// Test duration
     pub test_duration: Duration,
 }
 
-/// Run a stress test on the service discovery system
-pub async fn run_stress_test(config: StressTestConfig) -> Result<StressTestResults> {
-    let start_time = Instant::now();
-    let discovery_config = DiscoveryConfig::default();
-    let protocol = Arc::new(DnsSdProtocol::new(discovery_config).await?);
-
-    // Create rate limiter if configured
-    let rate_limiter = config.rate_limit.map(|limit| {
-        Arc::new(governor::RateLimiter::direct(
-            governor::Quota::per_second(limit.into())
-        ))
-    });
-
-    // Metrics tracking
-    let metrics = Arc::new(DashMap::new());
-    let barrier = Arc::new(Barrier::new(2)); // For registration and discovery tasks
-
-    // Start registration task
-    let registration_metrics = metrics.clone();
-    let reg_barrier = barrier.clone();
-    let reg_protocol = protocol.clone();
-    let registration_handle = tokio::spawn(async move {
-        run_registration_test(
-            reg_protocol,
-            config.service_count,
-            rate_limiter.clone(),
-            registration_metrics,
-        ).await;
-        reg_barrier.wait().await;
-    });
-
-    // Start discovery task
-    let discovery_metrics = metrics.clone();
-    let disc_barrier = barrier.clone();
-    let disc_protocol = protocol.clone();
-    let discovery_handle = tokio::spawn(async move {
-        run_discovery_test(
-            disc_protocol,
-            config.discovery_concurrency,
-            config.test_duration,
-            rate_limiter,
-            discovery_metrics,
-        ).await;
-        disc_barrier.wait().await;
-    });
-
-    // Wait for test completion
-    let _ = tokio::join!(registration_handle, discovery_handle);
-
-    // Calculate results
-    let mut results = StressTestResults {
-        successful_registrations: 0,
-        failed_registrations: 0,
-        successful_discoveries: 0,
-        failed_discoveries: 0,
-        avg_discovery_latency: Duration::default(),
-        max_discovery_latency: Duration::default(),
-        test_duration: start_time.elapsed(),
-    };
+impl StressTestResults {
+    /// Pretty-printed JSON report of the results
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| DiscoveryError::invalid_data(format!("Cannot serialize stress test results: {e}")))
+    }
+}
 
-    // Process metrics
-    let mut total_latency = Duration::default();
-    let mut latency_count = 0;
-
-    for entry in metrics.iter() {
-        match *entry.key() {
-            "successful_registrations" => results.successful_registrations = *entry.value(),
-            "failed_registrations" => results.failed_registrations = *entry.value(),
-            "successful_discoveries" => results.successful_discoveries = *entry.value(),
-            "failed_discoveries" => results.failed_discoveries = *entry.value(),
-            "discovery_latency" => {
-                let latency: Duration = *entry.value();
-                total_latency += latency;
-                latency_count += 1;
-                if latency > results.max_discovery_latency {
-                    results.max_discovery_latency = latency;
-                }
+/// Kind of operation a sample measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Registration,
+    Discovery,
+}
+
+/// One completed operation
+#[derive(Debug)]
+struct Sample {
+    op: Op,
+    /// When the operation completed, from the start of the test
+    finished: Duration,
+    latency: Duration,
+    /// The kind of error, if the operation failed
+    error: Option<ErrorKind>,
+}
+
+/// Samples recorded by the test workers
+struct Tally {
+    start: Instant,
+    samples: Mutex<Vec<Sample>>,
+}
+
+impl Tally {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            samples: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record an operation that started at `started`
+    fn record<T>(&self, op: Op, started: Instant, outcome: &Result<T>) {
+        let sample = Sample {
+            op,
+            finished: self.start.elapsed(),
+            latency: started.elapsed(),
+            error: outcome.as_ref().err().map(DiscoveryError::kind),
+        };
+        self.samples.lock().unwrap_or_else(|e| e.into_inner()).push(sample);
+    }
+
+    /// Results of the recorded samples
+    fn results(&self, protocol: ProtocolType, bucket_width: Duration) -> StressTestResults {
+        let test_duration = self.start.elapsed();
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let latencies = |op: Op| -> Vec<Duration> {
+            samples
+                .iter()
+                .filter(|sample| sample.op == op && sample.error.is_none())
+                .map(|sample| sample.latency)
+                .collect()
+        };
+        let errors = |op: Op| -> BTreeMap<ErrorKind, usize> {
+            let mut errors = BTreeMap::new();
+            for kind in samples.iter().filter(|sample| sample.op == op).filter_map(|sample| sample.error) {
+                *errors.entry(kind).or_default() += 1;
             }
-            _ => {}
+            errors
+        };
+        let registration_latency = latencies(Op::Registration);
+        let discovery_latency = latencies(Op::Discovery);
+        let registration_errors = errors(Op::Registration);
+        let discovery_errors = errors(Op::Discovery);
+
+        StressTestResults {
+            protocol,
+            successful_registrations: registration_latency.len(),
+            failed_registrations: registration_errors.values().sum(),
+            successful_discoveries: discovery_latency.len(),
+            failed_discoveries: discovery_errors.values().sum(),
+            registration_latency: LatencyStats::from_samples(&registration_latency),
+            discovery_latency: LatencyStats::from_samples(&discovery_latency),
+            registration_errors,
+            discovery_errors,
+            throughput: throughput(&samples, test_duration, bucket_width),
+            test_duration,
         }
     }
+}
+
+/// Completed operations per bucket of `width`, covering the whole test
+fn throughput(samples: &[Sample], test_duration: Duration, width: Duration) -> Vec<ThroughputBucket> {
+    let width = width.max(Duration::from_millis(1));
+    let bucket_of = |offset: Duration| (offset.as_nanos() / width.as_nanos()) as usize;
+    let mut buckets: Vec<ThroughputBucket> = (0..=bucket_of(test_duration))
+        .map(|index| ThroughputBucket {
+            start: width * index as u32,
+            ..ThroughputBucket::default()
+        })
+        .collect();
+    for sample in samples {
+        let Some(bucket) = buckets.get_mut(bucket_of(sample.finished)) else {
+            continue;
+        };
+        match (sample.op, sample.error) {
+            (_, Some(_)) => bucket.failures += 1,
+            (Op::Registration, None) => bucket.registrations += 1,
+            (Op::Discovery, None) => bucket.discoveries += 1,
+        }
+    }
+    buckets
+}
 
-    if latency_count > 0 {
-        results.avg_discovery_latency = total_latency / latency_count as u32;
+/// Run a stress test against the built-in implementation of
+/// `config.protocol`
+///
+/// Fails if the protocol cannot be started, for example because the network
+/// does not allow it.
+pub async fn run_stress_test(config: StressTestConfig) -> Result<StressTestResults> {
+    let discovery_config = DiscoveryConfig::new()
+        .with_protocols([config.protocol].into_iter().collect())
+        .with_timeout(config.discovery_timeout);
+    let manager = ProtocolManager::new(discovery_config).await?;
+    let protocol = manager.protocols().get(&config.protocol).cloned().ok_or_else(|| {
+        DiscoveryError::configuration(format!("Protocol {} is not available", config.protocol))
+    })?;
+    Ok(run_stress_test_with(protocol, config).await)
+}
+
+/// Run a stress test against `protocol`
+///
+/// `config.protocol` is ignored; the results name the protocol's own type.
+/// The services registered during the test are unregistered afterwards.
+pub async fn run_stress_test_with(
+    protocol: Arc<dyn DiscoveryProtocol + Send + Sync>,
+    config: StressTestConfig,
+) -> StressTestResults {
+    let rate_limiter = config
+        .rate_limit
+        .and_then(NonZeroU32::new)
+        .map(|limit| Arc::new(RateLimiter::direct(Quota::per_second(limit))));
+    let tally = Arc::new(Tally::new(Instant::now()));
+    info!(
+        "Stress testing {} with {} services for {:?}",
+        protocol.protocol_type(),
+        config.service_count,
+        config.test_duration
+    );
+
+    let registered = tokio::join!(
+        run_registration_test(protocol.clone(), &config, rate_limiter.clone(), tally.clone()),
+        run_discovery_test(protocol.clone(), &config, rate_limiter, tally.clone()),
+    )
+    .0;
+    for service in registered {
+        if let Err(e) = protocol.unregister_service(&service).await {
+            debug!("Failed to unregister {} after the stress test: {}", service.name(), e);
+        }
     }
 
-    Ok(results)
+    tally.results(protocol.protocol_type(), config.bucket_width)
 }
 
+/// Register `config.service_count` services, returning those that succeeded
 async fn run_registration_test(
-    protocol: Arc<DnsSdProtocol>,
-    service_count: usize,
-    rate_limiter: Option<Arc<governor::RateLimiter<governor::state::NotKeyed, governor::state::InMemoryState, governor::clock::DefaultClock>>>,
-    metrics: Arc<DashMap<&'static str, usize>>,
-) {
-    let mut tasks = JoinSet::new();
-    let semaphore = Arc::new(Semaphore::new(20)); // Limit concurrent registrations
-
-    for i in 0..service_count {
-        let protocol = protocol.clone();
-        let rate_limiter = rate_limiter.clone();
-        let metrics = metrics.clone();
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-
-        tasks.spawn(async move {
-            if let Some(limiter) = rate_limiter.as_ref() {
-                limiter.until_ready().await;
-            }
+    protocol: Arc<dyn DiscoveryProtocol + Send + Sync>,
+    config: &StressTestConfig,
+    rate_limiter: Option<Arc<DefaultDirectRateLimiter>>,
+    tally: Arc<Tally>,
+) -> Vec<ServiceInfo> {
+    let next = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..REGISTRATION_CONCURRENCY.min(config.service_count))
+        .map(|_| {
+            let protocol = protocol.clone();
+            let rate_limiter = rate_limiter.clone();
+            let tally = tally.clone();
+            let next = next.clone();
+            let service_count = config.service_count;
+            tokio::spawn(async move {
+                let mut registered = Vec::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= service_count {
+                        break registered;
+                    }
+                    if let Some(limiter) = &rate_limiter {
+                        limiter.until_ready().await;
+                    }
 
-            let service = ServiceInfo {
-                id: format!("stress-test-{}", i),
-                name: format!("Stress Test Service {}", i),
-                service_type: ServiceType {
-                    name: "_test".to_string(),
-                    protocol: "_tcp".to_string(),
-                },
-                address: SocketAddr::new(
-                    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-                    8000 + i as u16,
-                ),
-                txt_records: Default::default(),
-                protocol: ProtocolType::DnsSd,
-                ttl: Duration::from_secs(3600),
-            };
-
-            match protocol.register_service(service).await {
-                Ok(_) => {
-                    metrics.entry("successful_registrations")
-                        .and_modify(|e| *e += 1)
-                        .or_insert(1);
+                    let started = Instant::now();
+                    let service = match ServiceInfo::new(
+                        format!("Stress Test Service {i}"),
+                        STRESS_SERVICE_TYPE,
+                        8000u16.wrapping_add(i as u16),
+                        None,
+                    ) {
+                        Ok(service) => service.with_protocol_type(protocol.protocol_type()),
+                        Err(e) => {
+                            warn!("Cannot build stress test service {}: {}", i, e);
+                            tally.record::<()>(Op::Registration, started, &Err(e));
+                            continue;
+                        }
+                    };
+                    let outcome = protocol.register_service(service.clone()).await;
+                    tally.record(Op::Registration, started, &outcome);
+                    match outcome {
+                        Ok(()) => registered.push(service),
+                        Err(e) => debug!("Stress test registration failed: {}", e),
+                    }
                 }
-                Err(_) => {
-                    metrics.entry("failed_registrations")
-                        .and_modify(|e| *e += 1)
-                        .or_insert(1);
-                }
-            }
+            })
+        })
+        .collect();
 
-            drop(permit);
-        });
+    let mut registered = Vec::new();
+    for worker in workers {
+        match worker.await {
+            Ok(services) => registered.extend(services),
+            Err(e) => warn!("Stress test registration worker failed: {}", e),
+        }
     }
-
-    while tasks.join_next().await.is_some() {}
+    registered
 }
 
+/// Discover the stress test services from concurrent workers until the test
+/// duration is up
 async fn run_discovery_test(
-    protocol: Arc<DnsSdProtocol>,
-    concurrency: usize,
-    duration: Duration,
-    rate_limiter: Option<Arc<governor::RateLimiter<governor::state::NotKeyed, governor::state::InMemoryState, governor::clock::DefaultClock>>>,
-    metrics: Arc<DashMap<&'static str, usize>>,
+    protocol: Arc<dyn DiscoveryProtocol + Send + Sync>,
+    config: &StressTestConfig,
+    rate_limiter: Option<Arc<DefaultDirectRateLimiter>>,
+    tally: Arc<Tally>,
 ) {
-    let end_time = Instant::now() + duration;
-    let mut tasks = JoinSet::new();
-    let semaphore = Arc::new(Semaphore::new(concurrency));
-
-    while Instant::now() < end_time {
-        let protocol = protocol.clone();
-        let rate_limiter = rate_limiter.clone();
-        let metrics = metrics.clone();
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-
-        tasks.spawn(async move {
-            if let Some(limiter) = rate_limiter.as_ref() {
-                limiter.until_ready().await;
-            }
+    let Ok(service_type) = ServiceType::new(STRESS_SERVICE_TYPE) else {
+        return;
+    };
+    let end_time = Instant::now() + config.test_duration;
+    let workers: Vec<_> = (0..config.discovery_concurrency)
+        .map(|_| {
+            let protocol = protocol.clone();
+            let rate_limiter = rate_limiter.clone();
+            let tally = tally.clone();
+            let service_type = service_type.clone();
+            let timeout = config.discovery_timeout;
+            tokio::spawn(async move {
+                while Instant::now() < end_time {
+                    if let Some(limiter) = &rate_limiter {
+                        limiter.until_ready().await;
+                    }
 
-            let start = Instant::now();
-            match protocol.discover_services(
-                vec![ServiceType {
-                    name: "_test".to_string(),
-                    protocol: "_tcp".to_string(),
-                }],
-                Duration::from_secs(5),
-            ).await {
-                Ok(services) => {
-                    let latency = start.elapsed();
-                    metrics.insert("discovery_latency", latency);
-                    metrics.entry("successful_discoveries")
-                        .and_modify(|e| *e += 1)
-                        .or_insert(1);
-                    debug!("Discovered {} services", services.len());
-                }
-                Err(_) => {
-                    metrics.entry("failed_discoveries")
-                        .and_modify(|e| *e += 1)
-                        .or_insert(1);
+                    let started = Instant::now();
+                    let outcome = protocol.discover_services(vec![service_type.clone()], Some(timeout)).await;
+                    tally.record(Op::Discovery, started, &outcome);
+                    match outcome {
+                        Ok(services) => debug!("Discovered {} services", services.len()),
+                        Err(e) => debug!("Stress test discovery failed: {}", e),
+                    }
                 }
-            }
-
-            drop(permit);
-        });
+            })
+        })
+        .collect();
 
-        sleep(Duration::from_millis(100)).await;
+    for worker in workers {
+        if let Err(e) = worker.await {
+            warn!("Stress test discovery worker failed: {}", e);
+        }
     }
-
-    while tasks.join_next().await.is_some() {}
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockProtocol;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let samples: Vec<Duration> = (1..=100).rev().map(Duration::from_millis).collect();
+        let stats = LatencyStats::from_samples(&samples);
+        assert_eq!(stats.samples, 100);
+        assert_eq!((stats.min, stats.max), (Duration::from_millis(1), Duration::from_millis(100)));
+        assert_eq!(stats.p50, Duration::from_millis(50));
+        assert_eq!(stats.p90, Duration::from_millis(90));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+
+        let single = LatencyStats::from_samples(&[Duration::from_millis(7)]);
+        assert_eq!(single.p99, Duration::from_millis(7));
+        assert_eq!(LatencyStats::from_samples(&[]), LatencyStats::default());
+    }
 
     #[tokio::test]
     async fn test_stress_test() {
+        let mock = MockProtocol::new(ProtocolType::Upnp).with_latency(Duration::from_millis(2));
+        mock.fail_next(DiscoveryError::network("dropped"));
+        mock.fail_next(DiscoveryError::timeout("slow"));
         let config = StressTestConfig {
             service_count: 10,
             discovery_concurrency: 2,
-            test_duration: Duration::from_secs(5),
-            rate_limit: Some(100),
+            test_duration: Duration::from_millis(300),
+            rate_limit: Some(200),
+            bucket_width: Duration::from_millis(100),
+            ..StressTestConfig::default()
         };
 
-        let results = run_stress_test(config).await.unwrap();
-        
-        assert!(results.successful_registrations > 0);
+        let results = run_stress_test_with(Arc::new(mock.clone()), config).await;
+
+        assert_eq!(results.protocol, ProtocolType::Upnp);
+        assert_eq!(results.failed_registrations + results.failed_discoveries, 2);
+        assert_eq!(results.successful_registrations + results.failed_registrations, 10);
         assert!(results.successful_discoveries > 0);
-        assert!(results.avg_discovery_latency > Duration::from_millis(0));
+        assert_eq!(results.registration_latency.samples, results.successful_registrations);
+        assert_eq!(results.discovery_latency.samples, results.successful_discoveries);
+        assert!(results.discovery_latency.p50 <= results.discovery_latency.p90);
+        assert!(results.discovery_latency.p90 <= results.discovery_latency.p99);
+        assert!(mock.registered().is_empty());
+
+        let mut errors = results.registration_errors.clone();
+        for (kind, count) in &results.discovery_errors {
+            *errors.entry(*kind).or_default() += count;
+        }
+        assert_eq!(errors, BTreeMap::from([(ErrorKind::Network, 1), (ErrorKind::Timeout, 1)]));
+
+        // Buckets cover the run and account for every operation
+        assert!(results.throughput.len() >= 3);
+        let completed: usize = results
+            .throughput
+            .iter()
+            .map(|bucket| bucket.registrations + bucket.discoveries + bucket.failures)
+            .sum();
+        assert_eq!(completed, results.successful_registrations + results.successful_discoveries + 2);
+        assert_eq!(results.throughput[1].start, Duration::from_millis(100));
+
+        let report: serde_json::Value = serde_json::from_str(&results.to_json().unwrap()).unwrap();
+        assert_eq!(report["protocol"], "Upnp");
+        assert!(report["discovery_latency"]["p95"].is_object());
+        assert!(report["registration_latency"]["p90"].is_object());
     }
 }
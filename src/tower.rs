@@ -0,0 +1,237 @@
+//! Discovered instances as a [`tower::discover::Discover`]
+//!
+//! [`ServiceDiscover`] turns the discovered instances of one service type
+//! into the stream of [`Change`]s that tower's balancers consume, so hyper
+//! and tonic clients can spread requests over whatever is on the network:
+//!
+//! ```rust,ignore
+//! let instances = discovery.tower_discover(&service_type, |service| connect(service));
+//! let client = tower::balance::p2c::Balance::new(instances);
+//! ```
+//!
+//! Instances are keyed by [`ServiceId`]. Everything already in the registry
+//! is inserted first; after that, [`ServiceEvent::New`] and
+//! [`ServiceEvent::Updated`] insert an instance and
+//! [`ServiceEvent::Removed`] removes it. Updates replace the instance under
+//! the same key, which tower treats as a new connection. The registry only
+//! changes when something queries the network, so pair this with
+//! [`ServiceDiscovery::watch_services`](crate::discovery::ServiceDiscovery::watch_services).
+
+use crate::{
+    error::Result,
+    registry::{ServiceFilter, ServiceRegistry},
+    service::{ServiceEvent, ServiceId, ServiceInfo},
+    types::ServiceType,
+};
+use ::tower::discover::Change;
+use futures::Stream;
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::debug;
+
+type Changes<S> = Pin<Box<dyn Stream<Item = Result<Change<ServiceId, S>>> + Send>>;
+
+/// Discovered instances of one service type as a
+/// [`Discover`](::tower::discover::Discover)
+///
+/// Created by
+/// [`ServiceDiscovery::tower_discover`](crate::discovery::ServiceDiscovery::tower_discover).
+/// Ends when the registry goes away.
+pub struct ServiceDiscover<S> {
+    changes: Changes<S>,
+}
+
+impl<S: Send + 'static> ServiceDiscover<S> {
+    /// Follow the instances of `service_type` in `registry`, making a
+    /// service for each with `make`
+    pub fn new<F>(registry: Arc<ServiceRegistry>, service_type: ServiceType, make: F) -> Self
+    where
+        F: Fn(&ServiceInfo) -> S + Send + Sync + 'static,
+    {
+        // Subscribe before the first scan, so nothing falls in between
+        let state = Follow {
+            events: registry.subscribe(),
+            filter: ServiceFilter::new().with_service_types(vec![service_type.clone()]).discovered_only(),
+            registry: Arc::downgrade(&registry),
+            service_type,
+            make,
+            known: HashSet::new(),
+            pending: VecDeque::new(),
+            scanned: false,
+        };
+        let changes = futures::stream::unfold(state, |mut state| async move {
+            let change = state.next().await?;
+            Some((Ok(change), state))
+        });
+        Self { changes: Box::pin(changes) }
+    }
+}
+
+impl<S> Stream for ServiceDiscover<S> {
+    type Item = Result<Change<ServiceId, S>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.changes.as_mut().poll_next(cx)
+    }
+}
+
+impl<S> fmt::Debug for ServiceDiscover<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceDiscover").finish_non_exhaustive()
+    }
+}
+
+/// State behind a [`ServiceDiscover`]
+struct Follow<F, S> {
+    /// Weak, so the stream ends when the registry is dropped
+    registry: Weak<ServiceRegistry>,
+    events: broadcast::Receiver<ServiceEvent>,
+    service_type: ServiceType,
+    filter: ServiceFilter,
+    make: F,
+    /// Instances inserted and not removed since
+    known: HashSet<ServiceId>,
+    pending: VecDeque<Change<ServiceId, S>>,
+    scanned: bool,
+}
+
+impl<F, S> Follow<F, S>
+where
+    F: Fn(&ServiceInfo) -> S,
+{
+    async fn next(&mut self) -> Option<Change<ServiceId, S>> {
+        if !self.scanned {
+            self.scanned = true;
+            self.rescan().await;
+        }
+        loop {
+            if let Some(change) = self.pending.pop_front() {
+                return Some(change);
+            }
+            match self.events.recv().await {
+                Ok(event) => self.apply(event),
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("Tower discovery of {} missed {} events, rescanning", self.service_type, missed);
+                    self.rescan().await;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn apply(&mut self, event: ServiceEvent) {
+        match event {
+            ServiceEvent::New { service, .. } | ServiceEvent::Updated { service, .. }
+                if service.service_type == self.service_type =>
+            {
+                self.insert(&service);
+            }
+            ServiceEvent::Removed { service, .. } if service.service_type == self.service_type => {
+                let id = service.service_id();
+                if self.known.remove(&id) {
+                    self.pending.push_back(Change::Remove(id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Insert every instance in the registry and remove the known ones
+    /// that are no longer there
+    async fn rescan(&mut self) {
+        let Some(registry) = self.registry.upgrade() else {
+            return;
+        };
+        let services = registry.find_services(&self.filter).await;
+        let present: HashSet<ServiceId> = services.iter().map(ServiceInfo::service_id).collect();
+        let mut gone: Vec<ServiceId> = self.known.difference(&present).cloned().collect();
+        gone.sort();
+        for id in gone {
+            self.known.remove(&id);
+            self.pending.push_back(Change::Remove(id));
+        }
+        for service in &services {
+            self.insert(service);
+        }
+    }
+
+    fn insert(&mut self, service: &ServiceInfo) {
+        let id = service.service_id();
+        self.known.insert(id.clone());
+        self.pending.push_back(Change::Insert(id, (self.make)(service)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ProtocolType;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    fn service(name: &str, port: u16) -> ServiceInfo {
+        ServiceInfo::new(name, "_db._tcp", port, None).unwrap()
+    }
+
+    fn describe(change: Change<ServiceId, u16>) -> String {
+        match change {
+            Change::Insert(id, port) => format!("+{id}:{port}"),
+            Change::Remove(id) => format!("-{id}"),
+        }
+    }
+
+    async fn next(instances: &mut ServiceDiscover<u16>) -> String {
+        let change = tokio::time::timeout(Duration::from_secs(1), instances.next()).await.unwrap().unwrap().unwrap();
+        describe(change)
+    }
+
+    fn assert_discover<D: ::tower::discover::Discover<Key = ServiceId>>(_: &D) {}
+
+    #[tokio::test]
+    async fn test_inserts_known_instances_then_follows_the_registry() {
+        let registry = Arc::new(ServiceRegistry::new());
+        let ttl = Some(Duration::from_secs(60));
+        registry.add_discovered_service(service("a", 5432), ProtocolType::Mdns, ttl).await.unwrap();
+        let other = ServiceInfo::new("x", "_http._tcp", 80, None).unwrap();
+        registry.add_discovered_service(other.clone(), ProtocolType::Mdns, ttl).await.unwrap();
+
+        let db = ServiceType::new("_db._tcp").unwrap();
+        let mut instances = ServiceDiscover::new(Arc::clone(&registry), db, |service: &ServiceInfo| service.port);
+        assert_discover(&instances);
+        assert_eq!(next(&mut instances).await, "+a._db._tcp.local:5432");
+
+        registry.add_discovered_service(service("b", 5433), ProtocolType::Mdns, ttl).await.unwrap();
+        assert_eq!(next(&mut instances).await, "+b._db._tcp.local:5433");
+        registry.add_discovered_service(service("a", 6432), ProtocolType::Mdns, ttl).await.unwrap();
+        assert_eq!(next(&mut instances).await, "+a._db._tcp.local:6432");
+
+        registry.remove_discovered_service(&other.service_id()).await;
+        registry.remove_discovered_service(&service("b", 5433).service_id()).await;
+        assert_eq!(next(&mut instances).await, "-b._db._tcp.local");
+    }
+
+    #[tokio::test]
+    async fn test_rescans_after_missing_events() {
+        let registry = Arc::new(ServiceRegistry::new());
+        let ttl = Some(Duration::from_secs(60));
+        registry.add_discovered_service(service("a", 5432), ProtocolType::Mdns, ttl).await.unwrap();
+
+        let db = ServiceType::new("_db._tcp").unwrap();
+        let mut instances = ServiceDiscover::new(Arc::clone(&registry), db, |service: &ServiceInfo| service.port);
+        assert_eq!(next(&mut instances).await, "+a._db._tcp.local:5432");
+
+        // Overflow the event channel so the stream has to look again
+        registry.remove_discovered_service(&service("a", 5432).service_id()).await;
+        for port in 1..=300 {
+            registry.add_discovered_service(service("b", port), ProtocolType::Mdns, ttl).await.unwrap();
+        }
+        assert_eq!(next(&mut instances).await, "-a._db._tcp.local");
+        assert_eq!(next(&mut instances).await, "+b._db._tcp.local:300");
+    }
+}
@@ -2,6 +2,7 @@
 
 use crate::service::ServiceInfo;
 use crate::error::{DiscoveryError, Result};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -10,6 +11,9 @@ use std::{
     str::FromStr,
 };
 
+/// Longest service type accepted, the length limit of a DNS name
+const MAX_SERVICE_TYPE_LEN: usize = 255;
+
 /// Represents a service type for discovery
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ServiceType {
@@ -29,6 +33,13 @@ impl ServiceType {
         if service_type_str.is_empty() {
             return Err(DiscoveryError::invalid_service("Service type cannot be empty"));
         }
+        // Service types arrive from the network too
+        if service_type_str.len() > MAX_SERVICE_TYPE_LEN {
+            return Err(DiscoveryError::invalid_service(format!(
+                "Service type is {} bytes long, at most {MAX_SERVICE_TYPE_LEN} are allowed",
+                service_type_str.len()
+            )));
+        }
 
         // Handle UPnP URN format (urn:schemas-upnp-org:service:ContentDirectory:1)
         if service_type_str.starts_with("urn:") {
@@ -50,10 +61,13 @@ impl ServiceType {
 
         // Extract service name (first part)
         let service_name = parts[0].to_string();
+        if service_name.trim_start_matches('_').is_empty() {
+            return Err(DiscoveryError::invalid_service("Service type has an empty service name"));
+        }
         
         // Extract protocol (second part, should start with _)
         let protocol_part = parts[1];
-        if !protocol_part.starts_with('_') {
+        if !protocol_part.starts_with('_') || protocol_part.len() < 2 {
             return Err(DiscoveryError::invalid_service(
                 "Service type must contain protocol (e.g., '._tcp')",
             ));
@@ -74,34 +88,38 @@ impl ServiceType {
             format!("_{service_name}")
         };
 
-        Ok(ServiceType {
+        let service_type = ServiceType {
             service_name: final_service_name,
             protocol,
             domain,
-        })
+        };
+        // The added underscore must not push the canonical form over the limit,
+        // or it would not parse again
+        if service_type.to_string().len() > MAX_SERVICE_TYPE_LEN {
+            return Err(DiscoveryError::invalid_service(format!(
+                "Service type is longer than {MAX_SERVICE_TYPE_LEN} bytes"
+            )));
+        }
+        Ok(service_type)
     }
 
     /// Create a new service type with specified protocol
+    ///
+    /// Underscores are added where missing, so `("http", "tcp")` gives
+    /// `_http._tcp`.
     pub fn with_protocol<S1: Into<String>, S2: Into<String>>(service: S1, protocol: S2) -> Result<Self> {
-        let mut protocol_str = protocol.into();
-        if &protocol_str[0..1] != "_" {
-            protocol_str = format!("_{protocol_str}");
+        let protocol = protocol.into();
+        let protocol = protocol.trim_start_matches('.');
+        if protocol.starts_with('_') {
+            Self::new(format!("{}.{protocol}", service.into()))
+        } else {
+            Self::new(format!("{}._{protocol}", service.into()))
         }
-
-        Ok(ServiceType {
-            service_name: service.into(),
-            protocol: protocol_str,
-            domain: None,
-        })
     }
 
-    /// Create a new service type with specified domain
+    /// Create a new TCP service type in the specified domain
     pub fn with_domain<S: Into<String>>(service: S, domain: S) -> Result<Self> {
-        Ok(ServiceType {
-            service_name: service.into(),
-            protocol: "_tcp".to_string(),
-            domain: Some(domain.into()),
-        })
+        Self::new(format!("{}._tcp.{}", service.into(), domain.into()))
     }
 
     /// Get the service string
@@ -119,17 +137,13 @@ impl ServiceType {
         self.domain.as_deref()
     }
 
-    /// Convert to a fully qualified service string
+    /// Canonical form of the service type, such as `_http._tcp.local`
+    ///
+    /// The same as the [`Display`](fmt::Display) form, which
+    /// [`ServiceType::new`] parses back into an equal service type. UPnP
+    /// URNs are returned as they are.
     pub fn full_name(&self) -> String {
-        // For UPnP URNs, return the service name as-is since it's already complete
-        if self.service_name.starts_with("urn:") {
-            return self.service_name.clone();
-        }
-        
-        match &self.domain {
-            None => format!("{}_{}", self.service_name, self.protocol),
-            Some(domain) => format!("{}_{}.{}", self.service_name, self.protocol, domain),
-        }
+        self.to_string()
     }
 
     /// Check if the service type is valid
@@ -201,6 +215,9 @@ pub struct NetworkInterface {
     pub is_up: bool,
     /// Whether the interface supports multicast
     pub supports_multicast: bool,
+    /// Networks the interface is attached to, one per address
+    #[serde(default)]
+    pub networks: Vec<IpNet>,
 }
 
 impl NetworkInterface {
@@ -212,7 +229,28 @@ impl NetworkInterface {
             ipv6_addresses: Vec::new(),
             is_up: false,
             supports_multicast: false,
+            networks: Vec::new(),
+        }
+    }
+
+    /// Add an address together with its network, e.g. `192.168.1.10/24`
+    pub fn with_network(mut self, network: IpNet) -> Self {
+        self.add_network(network);
+        self
+    }
+
+    /// Add an address together with its network in place
+    pub fn add_network(&mut self, network: IpNet) {
+        match network.addr() {
+            IpAddr::V4(addr) => self.ipv4_addresses.push(addr),
+            IpAddr::V6(addr) => self.ipv6_addresses.push(addr),
         }
+        self.networks.push(network);
+    }
+
+    /// Whether `addr` is on one of the interface's networks
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(addr))
     }
 
     /// Add an IPv4 address
@@ -255,6 +293,18 @@ pub struct DiscoveryFilter {
     pub protocol_filters: Vec<ProtocolType>,
     /// Custom attribute filter patterns (key-value regex patterns)
     pub attribute_patterns: Vec<(String, String)>,
+    /// Network interface filters
+    #[serde(default)]
+    pub interface_filters: Vec<String>,
+    /// Filters of which at least one must match, if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub any_of: Option<Vec<DiscoveryFilter>>,
+    /// Filters that must all match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub all_of: Vec<DiscoveryFilter>,
+    /// Filters that must not match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub none_of: Vec<DiscoveryFilter>,
 }
 
 impl DiscoveryFilter {
@@ -264,6 +314,37 @@ impl DiscoveryFilter {
             service_type_filters: Vec::new(),
             protocol_filters: Vec::new(),
             attribute_patterns: Vec::new(),
+            interface_filters: Vec::new(),
+            any_of: None,
+            all_of: Vec::new(),
+            none_of: Vec::new(),
+        }
+    }
+
+    /// Filter matching services that match at least one of `filters`
+    ///
+    /// With no filters nothing matches.
+    pub fn any<I: IntoIterator<Item = DiscoveryFilter>>(filters: I) -> Self {
+        Self {
+            any_of: Some(filters.into_iter().collect()),
+            ..Self::new()
+        }
+    }
+
+    /// Filter matching services that match every one of `filters`
+    pub fn all<I: IntoIterator<Item = DiscoveryFilter>>(filters: I) -> Self {
+        Self {
+            all_of: filters.into_iter().collect(),
+            ..Self::new()
+        }
+    }
+
+    /// Filter matching services that do not match `filter`
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(filter: DiscoveryFilter) -> Self {
+        Self {
+            none_of: vec![filter],
+            ..Self::new()
         }
     }
 
@@ -279,6 +360,12 @@ impl DiscoveryFilter {
         self
     }
 
+    /// Add a network interface filter
+    pub fn with_interface<S: Into<String>>(mut self, interface: S) -> Self {
+        self.interface_filters.push(interface.into());
+        self
+    }
+
     /// Add an attribute pattern filter (key regex, value regex)
     pub fn with_attribute_pattern(mut self, key_pattern: String, value_pattern: String) -> Self {
         self.attribute_patterns.push((key_pattern, value_pattern));
@@ -299,6 +386,12 @@ impl DiscoveryFilter {
             return false;
         }
 
+        // Check interface filters
+        if !self.interface_filters.is_empty()
+            && !service.interface.as_ref().is_some_and(|i| self.interface_filters.contains(i)) {
+            return false;
+        }
+
         // Check attribute pattern filters
         for (key_pattern, value_pattern) in &self.attribute_patterns {
             let mut matches = false;
@@ -314,7 +407,11 @@ impl DiscoveryFilter {
             }
         }
 
-        true
+        // Check combined filters
+        if self.any_of.as_ref().is_some_and(|any| !any.iter().any(|f| f.matches(service))) {
+            return false;
+        }
+        self.all_of.iter().all(|f| f.matches(service)) && !self.none_of.iter().any(|f| f.matches(service))
     }
 }
 
@@ -353,6 +450,73 @@ mod tests {
         assert!(ServiceType::new("").is_err());
         assert!(ServiceType::new("invalid").is_err());
         assert!(ServiceType::new("_http").is_err()); // Missing protocol
+        assert!(ServiceType::new("._tcp").is_err());
+        assert!(ServiceType::new("_http._").is_err());
+        assert!(ServiceType::new(format!("_{}._tcp", "x".repeat(MAX_SERVICE_TYPE_LEN))).is_err());
+        assert!(ServiceType::new(format!("urn:{}", "x".repeat(MAX_SERVICE_TYPE_LEN))).is_err());
+    }
+
+    #[test]
+    fn test_constructors_agree_on_canonical_form() -> Result<()> {
+        assert_eq!(ServiceType::with_protocol("http", "tcp")?, ServiceType::new("_http._tcp")?);
+        assert_eq!(ServiceType::with_protocol("_ipp", "._udp")?.to_string(), "_ipp._udp");
+        assert_eq!(ServiceType::with_domain("_http", "example.com")?.full_name(), "_http._tcp.example.com");
+        assert_eq!(ServiceType::new("_http._tcp.local")?.full_name(), "_http._tcp.local");
+        assert!(ServiceType::with_protocol("_http", "").is_err());
+        assert!(ServiceType::with_protocol("", "é").is_err());
+        // One byte under the limit, until the missing underscore is added
+        assert!(ServiceType::new(format!("{}._tcp", "x".repeat(MAX_SERVICE_TYPE_LEN - 5))).is_err());
+        Ok(())
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        /// Service types as they appear in the wild, underscores optional
+        fn service_type() -> impl Strategy<Value = String> {
+            (
+                "_?[a-z][a-z0-9-]{0,14}",
+                "_(tcp|udp)",
+                proptest::option::of("[a-z][a-z0-9-]{0,9}(\\.[a-z][a-z0-9-]{0,9}){0,2}\\.?"),
+            )
+                .prop_map(|(name, protocol, domain)| match domain {
+                    Some(domain) => format!("{name}.{protocol}.{domain}"),
+                    None => format!("{name}.{protocol}"),
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn canonical_form_round_trips(input in service_type()) {
+                let parsed = ServiceType::new(input.as_str()).unwrap();
+                let canonical = parsed.to_string();
+                prop_assert!(canonical.starts_with('_'));
+                prop_assert_eq!(&ServiceType::new(canonical.as_str()).unwrap(), &parsed);
+                prop_assert_eq!(parsed.full_name(), canonical);
+            }
+
+            #[test]
+            fn accepted_input_round_trips(input in any::<String>()) {
+                if let Ok(parsed) = ServiceType::new(input) {
+                    prop_assert_eq!(ServiceType::new(parsed.to_string()).unwrap(), parsed);
+                }
+            }
+
+            #[test]
+            fn urns_are_kept_verbatim(rest in "[a-zA-Z0-9:.-]{1,100}") {
+                let urn = format!("urn:{rest}");
+                prop_assert_eq!(ServiceType::new(urn.as_str()).unwrap().to_string(), urn);
+            }
+
+            #[test]
+            fn with_protocol_matches_new(name in "[a-z][a-z0-9-]{0,14}", protocol in "tcp|udp") {
+                prop_assert_eq!(
+                    ServiceType::with_protocol(name.as_str(), protocol.as_str()).unwrap(),
+                    ServiceType::new(format!("_{name}._{protocol}")).unwrap()
+                );
+            }
+        }
     }
 
     #[test] 
@@ -373,6 +537,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_compound_filters() -> Result<()> {
+        use crate::service::ServiceInfo;
+
+        let attribute = |key: &str, value: &str| DiscoveryFilter::new().with_attribute_pattern(key.into(), value.into());
+        // HTTP services in production that run version 2 or are canaries
+        let filter = DiscoveryFilter::all([
+            DiscoveryFilter::new().with_service_type(ServiceType::new("_http._tcp")?),
+            attribute("env", "production"),
+            DiscoveryFilter::any([attribute("version", "2."), attribute("canary", "true")]),
+        ]);
+        let service = |attributes: Vec<(&str, &str)>| ServiceInfo::new("api", "_http._tcp", 80, Some(attributes));
+
+        assert!(filter.matches(&service(vec![("env", "production"), ("version", "2.1")])?));
+        assert!(filter.matches(&service(vec![("env", "production"), ("version", "1.9"), ("canary", "true")])?));
+        assert!(!filter.matches(&service(vec![("env", "production"), ("version", "1.9")])?));
+        assert!(!filter.matches(&service(vec![("env", "staging"), ("version", "2.0")])?));
+
+        let not_staging = DiscoveryFilter::not(attribute("env", "staging"));
+        assert!(not_staging.matches(&service(vec![("env", "production")])?));
+        assert!(!not_staging.matches(&service(vec![("env", "staging")])?));
+        assert!(!DiscoveryFilter::any([]).matches(&service(vec![])?));
+        Ok(())
+    }
+
     #[test]
     fn test_protocol_type_default() {
         assert_eq!(ProtocolType::default(), ProtocolType::Mdns);
@@ -4,8 +4,9 @@ use crate::{
     error::{DiscoveryError, Result},
     types::NetworkInterface,
 };
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use std::{
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv6Addr},
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tracing::{debug, warn};
@@ -18,66 +19,79 @@ pub mod network {
     pub fn get_network_interfaces() -> Result<Vec<NetworkInterface>> {
         debug!("Enumerating network interfaces");
 
-        // This is a placeholder implementation
-        // In a real implementation, you would use platform-specific APIs
-        // or a cross-platform crate like `pnet` or `nix`
-
-        let mut interfaces = Vec::new();
+        let mut interfaces: Vec<NetworkInterface> = Vec::new();
+        for iface in if_addrs::get_if_addrs()? {
+            let network = match &iface.addr {
+                if_addrs::IfAddr::V4(addr) => Ipv4Net::new(addr.ip, addr.prefixlen).map(IpNet::V4),
+                if_addrs::IfAddr::V6(addr) => Ipv6Net::new(addr.ip, addr.prefixlen).map(IpNet::V6),
+            };
+            let Ok(network) = network else {
+                warn!("Skipping address with invalid prefix on {}", iface.name);
+                continue;
+            };
+
+            match interfaces.iter_mut().find(|i| i.name == iface.name) {
+                Some(existing) => existing.add_network(network),
+                None => interfaces.push(
+                    NetworkInterface::new(iface.name.clone())
+                        .with_status(true, !iface.is_loopback())
+                        .with_network(network),
+                ),
+            }
+        }
 
-        // Create mock interfaces for demonstration
-        let localhost = NetworkInterface::new("lo")
-            .with_ipv4(Ipv4Addr::LOCALHOST)
-            .with_ipv6(Ipv6Addr::LOCALHOST)
-            .with_status(true, false);
-        interfaces.push(localhost);
+        debug!("Found {} network interfaces", interfaces.len());
+        Ok(interfaces)
+    }
 
-        #[cfg(target_os = "windows")]
-        {
-            let ethernet = NetworkInterface::new("Ethernet")
-                .with_ipv4("192.168.1.100".parse().unwrap())
-                .with_ipv6("fe80::1".parse().unwrap())
-                .with_status(true, true);
-            interfaces.push(ethernet);
-
-            let wifi = NetworkInterface::new("Wi-Fi")
-                .with_ipv4("192.168.1.101".parse().unwrap())
-                .with_ipv6("fe80::2".parse().unwrap())
-                .with_status(true, true);
-            interfaces.push(wifi);
-        }
+    /// Name of the interface whose network contains `addr`
+    ///
+    /// When several networks match, the most specific one wins. Loopback
+    /// addresses map to the loopback interface.
+    pub fn interface_for_address(interfaces: &[NetworkInterface], addr: &IpAddr) -> Option<String> {
+        interfaces
+            .iter()
+            .flat_map(|iface| {
+                iface
+                    .networks
+                    .iter()
+                    .filter(|network| network.contains(addr))
+                    .map(move |network| (network.prefix_len(), &iface.name))
+            })
+            .max_by_key(|(prefix_len, _)| *prefix_len)
+            .map(|(_, name)| name.clone())
+    }
 
-        #[cfg(target_os = "linux")]
-        {
-            let eth0 = NetworkInterface::new("eth0")
-                .with_ipv4("192.168.1.100".parse().unwrap())
-                .with_ipv6("fe80::1".parse().unwrap())
-                .with_status(true, true);
-            interfaces.push(eth0);
-
-            let wlan0 = NetworkInterface::new("wlan0")
-                .with_ipv4("192.168.1.101".parse().unwrap())
-                .with_ipv6("fe80::2".parse().unwrap())
-                .with_status(true, true);
-            interfaces.push(wlan0);
-        }
+    /// Interfaces to use, restricted to `selected` names if given
+    pub fn select_interfaces(selected: Option<&std::collections::HashSet<String>>) -> Result<Vec<NetworkInterface>> {
+        Ok(get_network_interfaces()?
+            .into_iter()
+            .filter(|iface| selected.is_none_or(|names| names.contains(&iface.name)))
+            .collect())
+    }
 
-        #[cfg(target_os = "macos")]
-        {
-            let en0 = NetworkInterface::new("en0")
-                .with_ipv4("192.168.1.100".parse().unwrap())
-                .with_ipv6("fe80::1".parse().unwrap())
-                .with_status(true, true);
-            interfaces.push(en0);
-
-            let en1 = NetworkInterface::new("en1")
-                .with_ipv4("192.168.1.101".parse().unwrap())
-                .with_ipv6("fe80::2".parse().unwrap())
-                .with_status(true, true);
-            interfaces.push(en1);
-        }
+    /// Whether `interface` is attached to one of `networks`
+    ///
+    /// An interface qualifies when one of its networks overlaps an allowed
+    /// one, so both a narrower and a wider allowed network select it.
+    pub fn is_on_networks(interface: &NetworkInterface, networks: &[IpNet]) -> bool {
+        interface.networks.iter().any(|attached| {
+            networks
+                .iter()
+                .any(|allowed| allowed.contains(&attached.network()) || attached.contains(&allowed.network()))
+        })
+    }
 
-        debug!("Found {} network interfaces", interfaces.len());
-        Ok(interfaces)
+    /// Interfaces to discover on: the `selected` ones, or all, that are
+    /// attached to one of `networks`, or to any network if it is empty
+    pub fn scoped_interfaces(
+        selected: Option<&std::collections::HashSet<String>>,
+        networks: &[IpNet],
+    ) -> Result<Vec<NetworkInterface>> {
+        Ok(select_interfaces(selected)?
+            .into_iter()
+            .filter(|iface| networks.is_empty() || is_on_networks(iface, networks))
+            .collect())
     }
 
     /// Get interfaces that support multicast
@@ -141,6 +155,100 @@ pub mod network {
         )))
     }
 
+    /// Address of the IPv4 default gateway, if it can be determined
+    ///
+    /// Read from the kernel routing table on Linux; `None` elsewhere.
+    pub fn default_gateway() -> Option<std::net::Ipv4Addr> {
+        #[cfg(target_os = "linux")]
+        {
+            let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+            parse_default_route(&routes)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            None
+        }
+    }
+
+    /// Find the default route in the format of `/proc/net/route`
+    #[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+    pub(crate) fn parse_default_route(routes: &str) -> Option<std::net::Ipv4Addr> {
+        const RTF_GATEWAY: u32 = 0x2;
+        routes.lines().skip(1).find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (destination, gateway, flags) = (fields.get(1)?, fields.get(2)?, fields.get(3)?);
+            let flags = u32::from_str_radix(flags, 16).ok()?;
+            if *destination != "00000000" || flags & RTF_GATEWAY == 0 {
+                return None;
+            }
+            // The kernel prints the address as a native-endian integer
+            let gateway = u32::from_str_radix(gateway, 16).ok()?;
+            Some(std::net::Ipv4Addr::from(gateway.to_ne_bytes()))
+        })
+    }
+
+    /// Apply `options` to a multicast socket of `domain`, before it is bound
+    ///
+    /// SO_REUSEPORT is skipped with a warning where the platform lacks it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an option is rejected or the outgoing interface
+    /// does not exist or has no address of the socket's family.
+    pub fn apply_socket_options(
+        socket: &socket2::Socket,
+        domain: socket2::Domain,
+        options: &crate::config::SocketOptions,
+    ) -> Result<()> {
+        let ipv6 = domain == socket2::Domain::IPV6;
+        if let Some(ttl) = options.multicast_ttl() {
+            if ipv6 {
+                socket.set_multicast_hops_v6(ttl)?;
+            } else {
+                socket.set_multicast_ttl_v4(ttl)?;
+            }
+        }
+        if let Some(enable) = options.multicast_loop() {
+            if ipv6 {
+                socket.set_multicast_loop_v6(enable)?;
+            } else {
+                socket.set_multicast_loop_v4(enable)?;
+            }
+        }
+        if let Some(name) = options.outgoing_interface() {
+            let missing = || DiscoveryError::configuration(format!("Outgoing interface {name} not found"));
+            if ipv6 {
+                let index = if_addrs::get_if_addrs()?
+                    .into_iter()
+                    .find(|iface| iface.name == name)
+                    .ok_or_else(missing)?
+                    .index
+                    .ok_or_else(|| DiscoveryError::configuration(format!("Interface {name} has no index")))?;
+                socket.set_multicast_if_v6(index)?;
+            } else {
+                let address = get_network_interfaces()?
+                    .into_iter()
+                    .find(|iface| iface.name == name)
+                    .ok_or_else(missing)?
+                    .ipv4_addresses
+                    .first()
+                    .copied()
+                    .ok_or_else(|| DiscoveryError::configuration(format!("Interface {name} has no IPv4 address")))?;
+                socket.set_multicast_if_v4(&address)?;
+            }
+        }
+        if let Some(bytes) = options.recv_buffer_size() {
+            socket.set_recv_buffer_size(bytes)?;
+        }
+        if options.reuse_port() {
+            #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))))]
+            socket.set_reuse_port(true)?;
+            #[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin")))))]
+            warn!("SO_REUSEPORT is not supported on this platform; ignoring it");
+        }
+        Ok(())
+    }
+
     /// Check if a port is likely to be available for binding
     pub async fn is_port_available(port: u16) -> bool {
         use tokio::net::TcpListener;
@@ -209,6 +317,12 @@ pub mod string {
     use super::*;
     use std::collections::HashMap;
 
+    /// Most key-value pairs read from a TXT record
+    pub const MAX_TXT_ENTRIES: usize = 256;
+
+    /// Longest instance name RFC 6763 allows, in bytes of UTF-8
+    pub const MAX_INSTANCE_NAME_LEN: usize = 63;
+
     /// Sanitize a service name for use in network protocols
     pub fn sanitize_service_name(name: &str) -> String {
         name.chars()
@@ -222,6 +336,52 @@ pub mod string {
             .collect()
     }
 
+    /// Derive the next candidate instance name after a name conflict
+    ///
+    /// Follows the RFC 6763 convention of appending a counter in parentheses:
+    /// "My Service" becomes "My Service (2)", which in turn becomes "My Service (3)".
+    /// Long names are shortened so the counter still fits.
+    pub fn increment_instance_name(name: &str) -> String {
+        if let Some(stripped) = name.strip_suffix(')')
+            && let Some(open) = stripped.rfind(" (")
+            && let Ok(counter) = stripped[open + 2..].parse::<u32>()
+        {
+            return suffixed_instance_name(&stripped[..open], &format!(" ({})", counter.saturating_add(1)));
+        }
+        suffixed_instance_name(name, " (2)")
+    }
+
+    /// Make `name` a valid RFC 6763 instance name
+    ///
+    /// Control characters are dropped and the name is cut to
+    /// [`MAX_INSTANCE_NAME_LEN`] bytes, at a character boundary.
+    pub fn valid_instance_name(name: &str) -> String {
+        suffixed_instance_name(name, "")
+    }
+
+    /// `name` followed by `suffix` as a valid instance name, shortening
+    /// `name` rather than `suffix` when the two are too long together
+    pub fn suffixed_instance_name(name: &str, suffix: &str) -> String {
+        let suffix = truncate_instance_name(suffix, MAX_INSTANCE_NAME_LEN);
+        let mut base = truncate_instance_name(name, MAX_INSTANCE_NAME_LEN - suffix.len());
+        if !suffix.is_empty() && base.len() < name.len() {
+            base.truncate(base.trim_end().len());
+        }
+        base + &suffix
+    }
+
+    /// `name` without control characters, cut to at most `max` bytes
+    fn truncate_instance_name(name: &str, max: usize) -> String {
+        let mut truncated = String::with_capacity(max.min(name.len()));
+        for c in name.chars().filter(|c| !c.is_control()) {
+            if truncated.len() + c.len_utf8() > max {
+                break;
+            }
+            truncated.push(c);
+        }
+        truncated
+    }
+
     /// Validate a service type string
     pub fn validate_service_type(service_type: &str) -> Result<()> {
         if service_type.is_empty() {
@@ -244,17 +404,21 @@ pub mod string {
     }
 
     /// Parse key-value pairs from a string (e.g., TXT record format)
+    ///
+    /// Pairs without a key are skipped, and only the first
+    /// [`MAX_TXT_ENTRIES`] pairs are read, as the data may come from the
+    /// network.
     pub fn parse_txt_record(txt_data: &str) -> HashMap<String, String> {
         let mut attributes = HashMap::new();
 
-        for pair in txt_data.split(';') {
-            if let Some(eq_pos) = pair.find('=') {
-                let key = pair[..eq_pos].trim().to_string();
-                let value = pair[eq_pos + 1..].trim().to_string();
-                attributes.insert(key, value);
-            } else {
+        for pair in txt_data.split(';').take(MAX_TXT_ENTRIES) {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
                 // Key without value
-                attributes.insert(pair.trim().to_string(), String::new());
+                None => (pair.trim(), ""),
+            };
+            if !key.is_empty() {
+                attributes.insert(key.to_string(), value.to_string());
             }
         }
 
@@ -333,6 +497,16 @@ pub mod validation {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_on_networks() {
+        let interface = NetworkInterface::new("eth0").with_network("10.1.4.2/16".parse().unwrap());
+        let on = |network: &str| network::is_on_networks(&interface, &[network.parse().unwrap()]);
+        assert!(on("10.1.0.0/16"));
+        assert!(on("10.1.4.0/24"));
+        assert!(on("10.0.0.0/8"));
+        assert!(!on("192.168.1.0/24"));
+    }
+
     #[test]
     fn test_get_network_interfaces() {
         let result = network::get_network_interfaces();
@@ -342,6 +516,42 @@ mod tests {
         
         // Should always have at least loopback
         assert!(interfaces.iter().any(|i| i.name == "lo"));
+
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(network::interface_for_address(&interfaces, &localhost).as_deref(), Some("lo"));
+    }
+
+    #[test]
+    fn test_apply_socket_options() {
+        use crate::config::SocketOptions;
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).unwrap();
+        let options = SocketOptions::new()
+            .with_multicast_ttl(8)
+            .with_multicast_loop(false)
+            .with_recv_buffer_size(64 * 1024);
+        network::apply_socket_options(&socket, Domain::IPV4, &options).unwrap();
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 8);
+        assert!(!socket.multicast_loop_v4().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() > 0);
+
+        let missing = SocketOptions::new().with_outgoing_interface("no-such-interface0");
+        assert!(network::apply_socket_options(&socket, Domain::IPV4, &missing).is_err());
+    }
+
+    #[test]
+    fn test_parse_default_route() {
+        let routes = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                      eth0\t0002000A\t00000000\t0001\t0\t0\t0\t00FFFFFF\n\
+                      eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+        let expected = if cfg!(target_endian = "little") {
+            "192.168.1.1".parse().ok()
+        } else {
+            "1.1.168.192".parse().ok()
+        };
+        assert_eq!(network::parse_default_route(routes), expected);
+        assert_eq!(network::parse_default_route("Iface\tDestination\n"), None);
     }
 
     #[test]
@@ -376,6 +586,28 @@ mod tests {
         assert_eq!(string::sanitize_service_name("test-service_1.0"), "test-service_1.0");
     }
 
+    #[test]
+    fn test_increment_instance_name() {
+        assert_eq!(string::increment_instance_name("My Service"), "My Service (2)");
+        assert_eq!(string::increment_instance_name("My Service (2)"), "My Service (3)");
+        assert_eq!(string::increment_instance_name("Printer (x)"), "Printer (x) (2)");
+
+        let long = "x".repeat(string::MAX_INSTANCE_NAME_LEN);
+        let renamed = string::increment_instance_name(&long);
+        assert_eq!(renamed.len(), string::MAX_INSTANCE_NAME_LEN);
+        assert!(renamed.ends_with("x (2)"));
+        assert!(string::increment_instance_name(&renamed).ends_with("x (3)"));
+    }
+
+    #[test]
+    fn test_valid_instance_name() {
+        assert_eq!(string::valid_instance_name("Kitchen\tPrinter\u{7f}"), "KitchenPrinter");
+        // Cut at a character boundary: each 'é' is two bytes
+        let name = string::valid_instance_name(&"é".repeat(40));
+        assert_eq!(name, "é".repeat(31));
+        assert_eq!(string::suffixed_instance_name(&"a".repeat(70), " @ host"), format!("{} @ host", "a".repeat(56)));
+    }
+
     #[test]
     fn test_validate_service_type() {
         assert!(string::validate_service_type("_http._tcp").is_ok());
@@ -393,6 +625,13 @@ mod tests {
         assert_eq!(attrs.get("version"), Some(&"1.0".to_string()));
         assert_eq!(attrs.get("protocol"), Some(&"HTTP".to_string()));
         assert_eq!(attrs.get("enabled"), Some(&"".to_string()));
+
+        let attrs = string::parse_txt_record(";=orphan; ;a==b;");
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(attrs.get("a"), Some(&"=b".to_string()));
+        assert_eq!(string::parse_txt_record(&"k;".repeat(1000)).len(), 1);
+        let many: String = (0..1000).map(|i| format!("k{i}=v;")).collect();
+        assert_eq!(string::parse_txt_record(&many).len(), string::MAX_TXT_ENTRIES);
     }
 
     #[test]
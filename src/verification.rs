@@ -0,0 +1,351 @@
+//! Liveness verification of services through real connectivity checks
+//!
+//! A service is considered alive when its advertised address accepts a TCP
+//! connection (or, for `_udp` services, does not reject a probe datagram).
+//! If the service carries a [`HEALTH_PATH_ATTRIBUTE`] attribute, an HTTP GET
+//! to that path must additionally return a 2xx status. Services advertising
+//! `proto=grpc` are checked with the gRPC health protocol instead, see
+//! [`grpc`].
+//!
+//! With a [`ResolutionCache`], services advertising a hostname are checked
+//! at the address the hostname currently resolves to, so a host that moved
+//! is followed without rediscovering it.
+
+use crate::{error::Result, health::HealthConfig, resolver::ResolutionCache, service::ServiceInfo};
+use async_trait::async_trait;
+use std::{
+    borrow::Cow,
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::timeout,
+};
+use tracing::debug;
+
+pub mod grpc;
+
+/// Service attribute holding an HTTP path to probe, e.g. `/health`
+pub const HEALTH_PATH_ATTRIBUTE: &str = "health_path";
+
+/// Outcome of verifying a single service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationResult {
+    /// Whether the service responded as expected
+    pub healthy: bool,
+    /// Time taken by the check
+    pub latency: Duration,
+    /// Reason for failure, if any
+    pub detail: Option<String>,
+}
+
+impl VerificationResult {
+    /// A passing check
+    pub fn success(latency: Duration) -> Self {
+        Self { healthy: true, latency, detail: None }
+    }
+
+    /// A failing check with a reason
+    pub fn failure(latency: Duration, detail: impl Into<String>) -> Self {
+        Self { healthy: false, latency, detail: Some(detail.into()) }
+    }
+}
+
+/// A check that decides whether a service is alive and usable
+///
+/// Implement this for application-specific checks such as a TLS handshake or
+/// an auth token exchange, and register it with
+/// [`ServiceDiscovery::with_verifier`](crate::ServiceDiscovery::with_verifier).
+#[async_trait]
+pub trait ServiceVerifier: Send + Sync {
+    /// Verify a service
+    ///
+    /// Return `Err` only when the check itself could not be carried out;
+    /// an unreachable or unhealthy service is a failed [`VerificationResult`].
+    async fn verify(&self, service: &ServiceInfo) -> Result<VerificationResult>;
+}
+
+/// Performs connectivity checks using the timeout from a [`HealthConfig`]
+#[derive(Debug, Clone)]
+pub struct ConnectivityVerifier {
+    timeout: Duration,
+    prefer_hostnames: bool,
+    resolutions: Option<Arc<ResolutionCache>>,
+}
+
+impl ConnectivityVerifier {
+    /// Create a verifier with the given per-check timeout
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, prefer_hostnames: true, resolutions: None }
+    }
+
+    /// Create a verifier using the settings of a health configuration
+    pub fn from_health_config(config: &HealthConfig) -> Self {
+        Self::new(config.timeout).with_prefer_hostnames(config.prefer_hostnames)
+    }
+
+    /// Set whether HTTP probes name the service by its advertised hostname
+    /// in the `Host` header
+    ///
+    /// The connection goes to the advertised address unless a
+    /// [resolution cache](Self::with_resolution_cache) is set.
+    pub fn with_prefer_hostnames(mut self, prefer: bool) -> Self {
+        self.prefer_hostnames = prefer;
+        self
+    }
+
+    /// Connect to the current address of a service's advertised hostname,
+    /// looked up through `cache`, when hostnames are preferred
+    ///
+    /// `.local` names are left alone, as mDNS already supplied their
+    /// address. If the hostname does not resolve, the advertised address is
+    /// used.
+    pub fn with_resolution_cache(mut self, cache: Arc<ResolutionCache>) -> Self {
+        self.resolutions = Some(cache);
+        self
+    }
+
+    /// Verify that a service is reachable
+    pub async fn verify(&self, service: &ServiceInfo) -> VerificationResult {
+        let service = self.at_current_address(service).await;
+        let service = service.as_ref();
+        if grpc::is_grpc(service) {
+            return grpc::GrpcHealthVerifier::new(self.timeout)
+                .with_prefer_hostnames(self.prefer_hostnames)
+                .check(service)
+                .await;
+        }
+
+        let addr = service.socket_addr();
+        let start = Instant::now();
+
+        let result = if service.service_type.protocol().ends_with("_udp") {
+            self.probe_udp(addr).await
+        } else {
+            let host = service.authority(self.prefer_hostnames);
+            self.probe_tcp(addr, &host, service.get_attribute(HEALTH_PATH_ATTRIBUTE)).await
+        };
+
+        let latency = start.elapsed();
+        match result {
+            Ok(()) => VerificationResult::success(latency),
+            Err(detail) => {
+                debug!("Verification of {} at {} failed: {}", service.name, addr, detail);
+                VerificationResult::failure(latency, detail)
+            }
+        }
+    }
+
+    /// `service` at the address its hostname resolves to now, if that can
+    /// be found out
+    async fn at_current_address<'a>(&self, service: &'a ServiceInfo) -> Cow<'a, ServiceInfo> {
+        let Some(cache) = self.resolutions.as_ref().filter(|_| self.prefer_hostnames) else {
+            return Cow::Borrowed(service);
+        };
+        let Some(hostname) = service.hostname().filter(|host| !host.ends_with(".local")) else {
+            return Cow::Borrowed(service);
+        };
+        let addresses = match cache.resolve(hostname, self.timeout).await {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                debug!("Checking {} at its advertised address: {}", service.name, e);
+                return Cow::Borrowed(service);
+            }
+        };
+        if addresses.contains(&service.address) {
+            return Cow::Borrowed(service);
+        }
+        // Stay with the advertised address family where possible
+        let address = addresses
+            .iter()
+            .find(|address| address.is_ipv4() == service.address.is_ipv4())
+            .or_else(|| addresses.first());
+        match address {
+            Some(&address) => Cow::Owned(service.clone().with_address(address)),
+            None => Cow::Borrowed(service),
+        }
+    }
+
+    async fn probe_tcp(
+        &self,
+        addr: SocketAddr,
+        host: &str,
+        health_path: Option<&String>,
+    ) -> std::result::Result<(), String> {
+        let mut stream = match timeout(self.timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return Err(format!("connect failed: {e}")),
+            Err(_) => return Err(format!("connect timed out after {:?}", self.timeout)),
+        };
+
+        let Some(path) = health_path else {
+            return Ok(());
+        };
+
+        match timeout(self.timeout, http_get_status(&mut stream, host, path)).await {
+            Ok(Ok(status)) if (200..300).contains(&status) => Ok(()),
+            Ok(Ok(status)) => Err(format!("GET {path} returned {status}")),
+            Ok(Err(e)) => Err(format!("GET {path} failed: {e}")),
+            Err(_) => Err(format!("GET {path} timed out after {:?}", self.timeout)),
+        }
+    }
+
+    /// UDP has no handshake, so a service is assumed alive unless the probe
+    /// is actively refused (ICMP port unreachable).
+    async fn probe_udp(&self, addr: SocketAddr) -> std::result::Result<(), String> {
+        let bind_addr: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind_addr).await.map_err(|e| e.to_string())?;
+        socket.connect(addr).await.map_err(|e| e.to_string())?;
+        socket.send(&[]).await.map_err(|e| format!("probe failed: {e}"))?;
+
+        let mut buf = [0u8; 1];
+        match timeout(self.timeout, socket.recv(&mut buf)).await {
+            Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => {
+                Err("port unreachable".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl ServiceVerifier for ConnectivityVerifier {
+    async fn verify(&self, service: &ServiceInfo) -> Result<VerificationResult> {
+        Ok(ConnectivityVerifier::verify(self, service).await)
+    }
+}
+
+impl Default for ConnectivityVerifier {
+    fn default() -> Self {
+        Self::from_health_config(&HealthConfig::default())
+    }
+}
+
+async fn http_get_status(stream: &mut TcpStream, host: &str, path: &str) -> std::io::Result<u16> {
+    let request = format!("GET {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    let mut buf = [0u8; 512];
+    while !response.windows(2).any(|w| w == b"\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+
+    let status_line = String::from_utf8_lossy(&response);
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "malformed HTTP status line"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::net::TcpListener;
+
+    fn local_service(port: u16) -> ServiceInfo {
+        ServiceInfo::new("probe", "_http._tcp", port, None)
+            .unwrap()
+            .with_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    }
+
+    #[tokio::test]
+    async fn test_tcp_connectivity() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let verifier = ConnectivityVerifier::new(Duration::from_secs(1));
+
+        assert!(verifier.verify(&local_service(port)).await.healthy);
+
+        drop(listener);
+        let result = verifier.verify(&local_service(port)).await;
+        assert!(!result.healthy);
+        assert!(result.detail.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_http_health_path() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            for status in ["200 OK", "503 Service Unavailable"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let verifier = ConnectivityVerifier::new(Duration::from_secs(1));
+        let service = local_service(port).with_attribute(HEALTH_PATH_ATTRIBUTE, "/health");
+
+        assert!(verifier.verify(&service).await.healthy);
+        let result = verifier.verify(&service).await;
+        assert!(!result.healthy);
+        assert!(result.detail.unwrap().contains("503"));
+    }
+
+    #[tokio::test]
+    async fn test_http_probe_names_host() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let mut hosts = Vec::new();
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                hosts.extend(request.lines().find_map(|l| l.strip_prefix("Host: ")).map(str::to_string));
+                socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            }
+            hosts
+        });
+
+        let service = local_service(port)
+            .with_host("printer.local.")
+            .with_attribute(HEALTH_PATH_ATTRIBUTE, "/health");
+        let verifier = ConnectivityVerifier::new(Duration::from_secs(1));
+        assert!(verifier.verify(&service).await.healthy);
+        assert!(verifier.with_prefer_hostnames(false).verify(&service).await.healthy);
+
+        assert_eq!(server.await.unwrap(), [format!("printer.local:{port}"), format!("127.0.0.1:{port}")]);
+    }
+
+    #[tokio::test]
+    async fn test_hostname_resolved_through_cache() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let cache = Arc::new(ResolutionCache::default());
+        cache.insert("web.example.com", vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], Duration::from_secs(60));
+        cache.insert_negative("gone.example.com", None);
+
+        // Advertised at an address nothing listens on; the hostname has moved
+        let moved = local_service(port)
+            .with_address("192.0.2.1".parse().unwrap())
+            .with_host("web.example.com.");
+        let verifier = ConnectivityVerifier::new(Duration::from_secs(1)).with_resolution_cache(cache.clone());
+        assert!(verifier.verify(&moved).await.healthy);
+        assert!(verifier.verify(&moved).await.healthy);
+
+        let gone = local_service(port).with_host("gone.example.com");
+        assert!(verifier.verify(&gone).await.healthy);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.negative_hits, stats.misses), (2, 1, 0));
+    }
+}
@@ -0,0 +1,292 @@
+//! gRPC health checking (`grpc.health.v1.Health/Check`)
+//!
+//! Services advertising `proto=grpc` are verified by calling the standard
+//! health service over plaintext HTTP/2 instead of a bare TCP connect. The
+//! checked service name comes from the [`GRPC_SERVICE_ATTRIBUTE`] attribute;
+//! without it the server's overall health is queried.
+
+use super::{ServiceVerifier, VerificationResult};
+use crate::{error::Result, health::HealthConfig, service::ServiceInfo};
+use async_trait::async_trait;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http_body_util::{BodyExt, Full};
+use hyper::{client::conn::http2, header, Method, Request};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, time::timeout};
+use tracing::debug;
+
+/// Service attribute naming the application protocol, `grpc` for gRPC
+pub const PROTO_ATTRIBUTE: &str = "proto";
+
+/// Service attribute naming the gRPC service whose health is checked
+pub const GRPC_SERVICE_ATTRIBUTE: &str = "grpc_service";
+
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// `HealthCheckResponse.ServingStatus.SERVING`
+const SERVING: u64 = 1;
+
+/// Whether a service advertises itself as a gRPC server
+pub fn is_grpc(service: &ServiceInfo) -> bool {
+    service
+        .get_attribute(PROTO_ATTRIBUTE)
+        .is_some_and(|proto| proto.eq_ignore_ascii_case("grpc"))
+}
+
+/// Verifies services with the gRPC health checking protocol
+#[derive(Debug, Clone)]
+pub struct GrpcHealthVerifier {
+    timeout: Duration,
+    prefer_hostnames: bool,
+}
+
+impl GrpcHealthVerifier {
+    /// Create a verifier with the given per-check timeout
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, prefer_hostnames: true }
+    }
+
+    /// Create a verifier using the settings of a health configuration
+    pub fn from_health_config(config: &HealthConfig) -> Self {
+        Self::new(config.timeout).with_prefer_hostnames(config.prefer_hostnames)
+    }
+
+    /// Set whether requests name the service by its advertised hostname
+    pub fn with_prefer_hostnames(mut self, prefer: bool) -> Self {
+        self.prefer_hostnames = prefer;
+        self
+    }
+
+    /// Check that the service reports `SERVING`
+    pub async fn check(&self, service: &ServiceInfo) -> VerificationResult {
+        let addr = service.socket_addr();
+        let authority = service.authority(self.prefer_hostnames);
+        let name = service.get_attribute(GRPC_SERVICE_ATTRIBUTE).map_or("", String::as_str);
+        let start = Instant::now();
+
+        let result = match timeout(self.timeout, health_check(addr, &authority, name)).await {
+            Ok(Ok(SERVING)) => Ok(()),
+            Ok(Ok(status)) => Err(format!("health status is {}", status_name(status))),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(format!("health check timed out after {:?}", self.timeout)),
+        };
+
+        let latency = start.elapsed();
+        match result {
+            Ok(()) => VerificationResult::success(latency),
+            Err(detail) => {
+                debug!("gRPC health check of {} at {} failed: {}", service.name, addr, detail);
+                VerificationResult::failure(latency, detail)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ServiceVerifier for GrpcHealthVerifier {
+    async fn verify(&self, service: &ServiceInfo) -> Result<VerificationResult> {
+        Ok(self.check(service).await)
+    }
+}
+
+fn status_name(status: u64) -> String {
+    match status {
+        0 => "UNKNOWN".to_string(),
+        1 => "SERVING".to_string(),
+        2 => "NOT_SERVING".to_string(),
+        3 => "SERVICE_UNKNOWN".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Call `Health/Check` and return the serving status
+async fn health_check(addr: SocketAddr, authority: &str, service: &str) -> std::result::Result<u64, String> {
+    let stream = TcpStream::connect(addr).await.map_err(|e| format!("connect failed: {e}"))?;
+    let (mut sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+        .await
+        .map_err(|e| format!("HTTP/2 handshake failed: {e}"))?;
+    let connection = tokio::spawn(connection);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{authority}{HEALTH_CHECK_PATH}"))
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .header(header::TE, "trailers")
+        .body(Full::new(encode_request(service)))
+        .map_err(|e| e.to_string())?;
+    let result = async {
+        let response = sender.send_request(request).await.map_err(|e| format!("request failed: {e}"))?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP status {}", response.status()));
+        }
+        // Errors without a body come as headers only ("Trailers-Only")
+        let mut grpc_status = response.headers().get("grpc-status").cloned();
+        let body = response.into_body().collect().await.map_err(|e| format!("reading response failed: {e}"))?;
+        if let Some(status) = body.trailers().and_then(|trailers| trailers.get("grpc-status")) {
+            grpc_status = Some(status.clone());
+        }
+        match grpc_status.as_ref().map(|status| status.to_str().unwrap_or("?")) {
+            Some("0") => decode_response(body.to_bytes()),
+            Some(status) => Err(format!("gRPC status {status}")),
+            None => Err("response has no gRPC status".to_string()),
+        }
+    }
+    .await;
+
+    connection.abort();
+    result
+}
+
+/// Frame a `HealthCheckRequest { service }` message
+fn encode_request(service: &str) -> Bytes {
+    let mut message = BytesMut::new();
+    if !service.is_empty() {
+        message.put_u8(0x0a); // field 1, length delimited
+        put_varint(&mut message, service.len() as u64);
+        message.put_slice(service.as_bytes());
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    frame.put_u8(0); // not compressed
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(&message);
+    frame.freeze()
+}
+
+/// Read the status from a framed `HealthCheckResponse` message
+fn decode_response(mut frame: Bytes) -> std::result::Result<u64, String> {
+    let malformed = || "malformed health check response".to_string();
+    if frame.remaining() < 5 || frame.get_u8() != 0 {
+        return Err(malformed());
+    }
+    let len = frame.get_u32() as usize;
+    if frame.remaining() < len {
+        return Err(malformed());
+    }
+    let mut message = frame.split_to(len);
+
+    // Absent fields take their default, UNKNOWN
+    let mut status = 0;
+    while message.has_remaining() {
+        let key = get_varint(&mut message).ok_or_else(malformed)?;
+        let skip = match (key >> 3, key & 7) {
+            (1, 0) => {
+                status = get_varint(&mut message).ok_or_else(malformed)?;
+                0
+            }
+            (_, 0) => {
+                get_varint(&mut message).ok_or_else(malformed)?;
+                0
+            }
+            (_, 1) => 8,
+            (_, 2) => get_varint(&mut message).ok_or_else(malformed)? as usize,
+            (_, 5) => 4,
+            _ => return Err(malformed()),
+        };
+        if message.remaining() < skip {
+            return Err(malformed());
+        }
+        message.advance(skip);
+    }
+    Ok(status)
+}
+
+pub(crate) fn put_varint(buf: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+pub(crate) fn get_varint(buf: &mut Bytes) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        if !buf.has_remaining() {
+            return None;
+        }
+        let byte = buf.get_u8();
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::StreamBody;
+    use hyper::{
+        body::{Frame, Incoming},
+        server::conn::http2 as server,
+        service::service_fn,
+        HeaderMap, Response,
+    };
+    use std::{
+        convert::Infallible,
+        net::{IpAddr, Ipv4Addr},
+    };
+    use tokio::net::TcpListener;
+
+    /// Serve `Health/Check`, reporting SERVING only for the service "ok"
+    async fn health_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = service_fn(|request: Request<Incoming>| async move {
+                    assert_eq!(request.uri().path(), HEALTH_CHECK_PATH);
+                    let body = request.into_body().collect().await.unwrap().to_bytes();
+                    let status = if body == encode_request("ok") { 1u8 } else { 2 };
+                    let message = Bytes::from(vec![0, 0, 0, 0, 2, 0x08, status]);
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("grpc-status", "0".parse().unwrap());
+                    let frames = futures::stream::iter([
+                        Ok::<_, Infallible>(Frame::data(message)),
+                        Ok(Frame::trailers(trailers)),
+                    ]);
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header(header::CONTENT_TYPE, "application/grpc")
+                            .body(StreamBody::new(frames))
+                            .unwrap(),
+                    )
+                });
+                tokio::spawn(server::Builder::new(TokioExecutor::new()).serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_grpc_health_check() {
+        let port = health_server().await;
+        let service = |name: &str| {
+            ServiceInfo::new("grpc", "_grpc._tcp", port, Some(vec![(PROTO_ATTRIBUTE, "grpc"), (GRPC_SERVICE_ATTRIBUTE, name)]))
+                .unwrap()
+                .with_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        };
+        let verifier = GrpcHealthVerifier::new(Duration::from_secs(2));
+
+        assert!(is_grpc(&service("ok")));
+        assert!(verifier.check(&service("ok")).await.healthy);
+        let result = verifier.check(&service("down")).await;
+        assert!(!result.healthy);
+        assert_eq!(result.detail.as_deref(), Some("health status is NOT_SERVING"));
+    }
+
+    #[test]
+    fn test_decode_skips_unknown_fields() {
+        // field 2 (string "x") before field 1 = SERVING
+        let frame = Bytes::from(vec![0, 0, 0, 0, 5, 0x12, 1, b'x', 0x08, 1]);
+        assert_eq!(decode_response(frame), Ok(SERVING));
+        assert!(decode_response(Bytes::from(vec![0, 0, 0, 0, 3, 0x08])).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Self-registration of web servers
+//!
+//! A server built with axum (feature `axum`) or actix-web (feature `actix`)
+//! can announce itself while it runs: [`axum::serve`] and [`actix::serve`]
+//! bind the address, register a [`WebService`] on the port that was bound,
+//! and serve until the shutdown signal fires. The registration is withdrawn
+//! as soon as the signal fires, so clients stop arriving while open
+//! connections drain, and in any case before `serve` returns.
+//!
+//! Besides any attributes of its own, the service advertises its health
+//! check path as `health` and its version as
+//! [`version`](crate::ranking::VERSION_ATTRIBUTE) in its TXT record.
+
+use crate::{
+    config::RegistrationConfig,
+    discovery::ServiceDiscovery,
+    error::Result,
+    ranking::VERSION_ATTRIBUTE,
+    service::ServiceInfo,
+    types::{ProtocolType, ServiceType},
+};
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+
+/// Attribute carrying the path of a web service's health check
+pub const HEALTH_PATH_ATTRIBUTE: &str = "health";
+
+/// How a web server is announced while it runs
+#[derive(Debug, Clone)]
+pub struct WebService {
+    name: String,
+    service_type: ServiceType,
+    protocol_type: ProtocolType,
+    health_path: Option<String>,
+    version: Option<String>,
+    attributes: Vec<(String, String)>,
+    registration: RegistrationConfig,
+}
+
+impl WebService {
+    /// Announce the server as instance `name` of `_http._tcp`
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            service_type: ServiceType::new("_http._tcp").expect("_http._tcp is a valid service type"),
+            protocol_type: ProtocolType::default(),
+            health_path: None,
+            version: None,
+            attributes: Vec::new(),
+            registration: RegistrationConfig::default(),
+        }
+    }
+
+    /// Announce under `service_type` instead, such as `_https._tcp`
+    pub fn with_service_type(mut self, service_type: ServiceType) -> Self {
+        self.service_type = service_type;
+        self
+    }
+
+    /// Register with `protocol_type` rather than the default protocol
+    pub fn with_protocol_type(mut self, protocol_type: ProtocolType) -> Self {
+        self.protocol_type = protocol_type;
+        self
+    }
+
+    /// Advertise the path of the server's health check, such as `/healthz`
+    pub fn with_health_path(mut self, path: impl Into<String>) -> Self {
+        self.health_path = Some(path.into());
+        self
+    }
+
+    /// Advertise the version of the server
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Advertise a further TXT attribute
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.push((key.into(), value.into()));
+        self
+    }
+
+    /// Register with `registration` instead of the default configuration
+    pub fn with_registration_config(mut self, registration: RegistrationConfig) -> Self {
+        self.registration = registration;
+        self
+    }
+
+    /// The service to register for a server bound to `addr`
+    pub fn service_info(&self, addr: SocketAddr) -> Result<ServiceInfo> {
+        let mut service = ServiceInfo::new(self.name.as_str(), self.service_type.clone(), addr.port(), None)?
+            .with_address(addr.ip())
+            .with_protocol_type(self.protocol_type);
+        for (key, value) in &self.attributes {
+            service = service.with_attribute(key.as_str(), value.as_str());
+        }
+        if let Some(path) = &self.health_path {
+            service = service.with_attribute(HEALTH_PATH_ATTRIBUTE, path.as_str());
+        }
+        if let Some(version) = &self.version {
+            service = service.with_attribute(VERSION_ATTRIBUTE, version.as_str());
+        }
+        Ok(service)
+    }
+}
+
+/// Registration of a running server, withdrawn once
+#[derive(Clone)]
+struct Announcement {
+    discovery: Arc<ServiceDiscovery>,
+    /// The service as registered, until it is withdrawn
+    registered: Arc<Mutex<Option<ServiceInfo>>>,
+}
+
+impl Announcement {
+    async fn register(discovery: Arc<ServiceDiscovery>, service: &WebService, addr: SocketAddr) -> Result<Self> {
+        let registered = discovery
+            .register_service_with_config(service.service_info(addr)?, &service.registration)
+            .await?;
+        info!("Web server {} registered as '{}'", addr, registered.name);
+        Ok(Self { discovery, registered: Arc::new(Mutex::new(Some(registered))) })
+    }
+
+    /// Unregister the server, unless that already happened
+    async fn withdraw(&self) {
+        let registered = self.registered.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(service) = registered
+            && let Err(e) = self.discovery.unregister_service(&service).await
+        {
+            warn!("Failed to unregister web server '{}': {}", service.name, e);
+        }
+    }
+
+    /// `signal`, withdrawing the registration as soon as it fires
+    fn withdraw_on<F>(&self, signal: F) -> impl Future<Output = ()> + Send + 'static
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let announcement = self.clone();
+        async move {
+            signal.await;
+            announcement.withdraw().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::DiscoveryConfig, protocols::DiscoveryProtocol, registry::ServiceRegistry, types::ProtocolType,
+    };
+    use std::time::Duration;
+
+    /// Protocol keeping the services registered with it
+    #[derive(Clone, Default)]
+    pub(super) struct Announced(pub(super) Arc<Mutex<Vec<ServiceInfo>>>);
+
+    #[async_trait::async_trait]
+    impl DiscoveryProtocol for Announced {
+        fn protocol_type(&self) -> ProtocolType {
+            ProtocolType::Upnp
+        }
+
+        async fn discover_services(
+            &self,
+            _service_types: Vec<ServiceType>,
+            _timeout: Option<Duration>,
+        ) -> Result<Vec<ServiceInfo>> {
+            Ok(Vec::new())
+        }
+
+        async fn register_service(&self, service: ServiceInfo) -> Result<()> {
+            self.0.lock().unwrap().push(service);
+            Ok(())
+        }
+
+        async fn unregister_service(&self, service: &ServiceInfo) -> Result<()> {
+            self.0.lock().unwrap().retain(|announced| announced.name != service.name);
+            Ok(())
+        }
+
+        async fn verify_service(&self, _service: &ServiceInfo) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_available(&self) -> bool {
+            true
+        }
+
+        fn set_registry(&mut self, _registry: Arc<ServiceRegistry>) {}
+    }
+
+    pub(super) async fn discovery(announced: &Announced) -> Arc<ServiceDiscovery> {
+        let config = DiscoveryConfig::new().with_protocols([ProtocolType::Upnp].into_iter().collect());
+        let discovery = ServiceDiscovery::builder(config).with_protocol(announced.clone()).build().await.unwrap();
+        Arc::new(discovery)
+    }
+
+    pub(super) fn web_service() -> WebService {
+        WebService::new("Orders API")
+            .with_protocol_type(ProtocolType::Upnp)
+            .with_health_path("/healthz")
+            .with_version("2.1.0")
+    }
+
+    /// Wait until `announced` holds `count` services
+    pub(super) async fn announced_count(announced: &Announced, count: usize) -> Vec<ServiceInfo> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let services = announced.0.lock().unwrap().clone();
+                if services.len() == count {
+                    return services;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("registrations did not settle")
+    }
+
+    #[test]
+    fn test_service_info_carries_route_metadata() {
+        let service = web_service()
+            .with_attribute("path", "/api")
+            .service_info("10.0.0.5:8080".parse().unwrap())
+            .unwrap();
+        assert_eq!(service.service_type.to_string(), "_http._tcp");
+        assert_eq!(service.socket_addr(), "10.0.0.5:8080".parse().unwrap());
+        assert_eq!(service.get_attribute(HEALTH_PATH_ATTRIBUTE).map(String::as_str), Some("/healthz"));
+        assert_eq!(service.get_attribute(VERSION_ATTRIBUTE).map(String::as_str), Some("2.1.0"));
+        assert_eq!(service.get_attribute("path").map(String::as_str), Some("/api"));
+    }
+}
@@ -0,0 +1,92 @@
+//! Self-registration of actix-web servers
+
+use super::{Announcement, WebService};
+use crate::{discovery::ServiceDiscovery, error::{DiscoveryError, Result}};
+use actix_http::{body::MessageBody, Request, Response};
+use actix_service::{IntoServiceFactory, Service, ServiceFactory};
+use actix_web::{dev::AppConfig, HttpServer};
+use std::{fmt, future::Future, net::SocketAddr, sync::Arc};
+
+/// Serve `server` on `addr`, announced as `service`, until `shutdown`
+/// fires
+///
+/// Port `0` picks a free port, which is the one registered. The service is
+/// unregistered when `shutdown` fires, before workers have finished their
+/// requests. Set up everything but the address on `server` beforehand.
+/// Like the server itself, the future is not `Send`; await it from `main` or
+/// on a [`LocalSet`](tokio::task::LocalSet).
+///
+/// ```rust,ignore
+/// let server = HttpServer::new(|| App::new().route("/healthz", web::get().to(|| async { "ok" })));
+/// let service = WebService::new("Orders API").with_health_path("/healthz").with_version("2.1.0");
+/// auto_discovery::web::actix::serve(discovery, "0.0.0.0:8080".parse()?, server, service, ctrl_c()).await?;
+/// ```
+pub async fn serve<F, I, S, B, G>(
+    discovery: Arc<ServiceDiscovery>,
+    addr: SocketAddr,
+    server: HttpServer<F, I, S, B>,
+    service: WebService,
+    shutdown: G,
+) -> Result<()>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<actix_web::Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+    G: Future<Output = ()> + Send + 'static,
+{
+    let server = server.bind(addr)?;
+    let local_addr = server
+        .addrs()
+        .first()
+        .copied()
+        .ok_or_else(|| DiscoveryError::network(format!("Web server did not bind {addr}")))?;
+    let announcement = Announcement::register(discovery, &service, local_addr).await?;
+    let served = server.shutdown_signal(announcement.withdraw_on(shutdown)).run().await;
+    announcement.withdraw().await;
+    Ok(served?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::{tests::*, HEALTH_PATH_ATTRIBUTE};
+    use actix_web::{web, App};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, sync::oneshot};
+
+    #[tokio::test]
+    async fn test_registered_while_serving() {
+        let announced = Announced::default();
+        let server = HttpServer::new(|| App::new().route("/healthz", web::get().to(|| async { "ok" }))).workers(1);
+        let (stop, stopped) = oneshot::channel::<()>();
+        let discovery = discovery(&announced).await;
+        // Like actix-web's own server, the future is not Send
+        let local = tokio::task::LocalSet::new();
+        let server = local.spawn_local(serve(
+            discovery,
+            "127.0.0.1:0".parse().unwrap(),
+            server,
+            web_service(),
+            async move {
+                let _ = stopped.await;
+            },
+        ));
+
+        let services = local.run_until(announced_count(&announced, 1)).await;
+        assert_eq!(services[0].get_attribute(HEALTH_PATH_ATTRIBUTE).map(String::as_str), Some("/healthz"));
+        let mut stream = TcpStream::connect(services[0].socket_addr()).await.unwrap();
+        stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("ok"), "{response}");
+
+        stop.send(()).unwrap();
+        local.run_until(server).await.unwrap().unwrap();
+        assert!(announced.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+//! Self-registration of axum servers
+
+use super::{Announcement, WebService};
+use crate::{discovery::ServiceDiscovery, error::Result};
+use ::axum::Router;
+use std::{future::Future, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+
+/// Serve `router` on `addr`, announced as `service`, until `shutdown`
+/// fires
+///
+/// Port `0` picks a free port, which is the one registered. The service is
+/// unregistered when `shutdown` fires, before open connections have drained.
+///
+/// ```rust,ignore
+/// let router = Router::new().route("/healthz", get(|| async { "ok" }));
+/// let service = WebService::new("Orders API").with_health_path("/healthz").with_version("2.1.0");
+/// auto_discovery::web::axum::serve(discovery, "0.0.0.0:8080".parse()?, router, service, ctrl_c()).await?;
+/// ```
+pub async fn serve<F>(
+    discovery: Arc<ServiceDiscovery>,
+    addr: SocketAddr,
+    router: Router,
+    service: WebService,
+    shutdown: F,
+) -> Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let announcement = Announcement::register(discovery, &service, local_addr).await?;
+    let served = ::axum::serve(listener, router)
+        .with_graceful_shutdown(announcement.withdraw_on(shutdown))
+        .await;
+    announcement.withdraw().await;
+    Ok(served?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::{tests::*, HEALTH_PATH_ATTRIBUTE};
+    use ::axum::routing::get;
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, sync::oneshot};
+
+    #[tokio::test]
+    async fn test_registered_while_serving() {
+        let announced = Announced::default();
+        let router = Router::new().route("/healthz", get(|| async { "ok" }));
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(
+            discovery(&announced).await,
+            "127.0.0.1:0".parse().unwrap(),
+            router,
+            web_service(),
+            async move {
+                let _ = stopped.await;
+            },
+        ));
+
+        let services = announced_count(&announced, 1).await;
+        assert_eq!(services[0].name, "Orders API");
+        assert_eq!(services[0].get_attribute(HEALTH_PATH_ATTRIBUTE).map(String::as_str), Some("/healthz"));
+        let mut stream = TcpStream::connect(services[0].socket_addr()).await.unwrap();
+        stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200") && response.ends_with("ok"), "{response}");
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(announced.0.lock().unwrap().is_empty());
+    }
+}
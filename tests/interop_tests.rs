@@ -0,0 +1,239 @@
+//! Interoperability with the system's zeroconf stack
+//!
+//! These tests publish services with the Avahi (`avahi-publish`,
+//! `avahi-browse`) or Bonjour (`dns-sd`) command line tools and check that
+//! `MdnsProtocol` finds them, and the other way round, to catch wire format
+//! incompatibilities that tests against ourselves cannot. They need a running
+//! Avahi daemon or mDNSResponder and are skipped when neither tool set is on
+//! the `PATH`.
+//!
+//! Run with `cargo test --features interop-tests --test interop_tests`.
+#![cfg(feature = "interop-tests")]
+
+use auto_discovery::{
+    config::DiscoveryConfig,
+    error::Result,
+    protocols::{mdns::MdnsProtocol, DiscoveryProtocol},
+    service::ServiceInfo,
+    types::ServiceType,
+    utils::network,
+};
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::{Child, Command},
+    time,
+};
+
+/// Service type used by every test, short enough for DNS-SD's 15 characters
+const SERVICE_TYPE: &str = "_adinterop._tcp";
+
+/// How long either side gets to see the other's service
+const DEADLINE: Duration = Duration::from_secs(10);
+
+/// The zeroconf stack found on this machine
+#[derive(Debug, Clone, Copy)]
+enum Responder {
+    Avahi,
+    Bonjour,
+}
+
+/// A service as the system's tools resolved it
+#[derive(Debug)]
+struct Resolved {
+    port: u16,
+    txt: Vec<String>,
+}
+
+impl Responder {
+    fn detect() -> Option<Self> {
+        if on_path("avahi-publish") && on_path("avahi-browse") {
+            Some(Responder::Avahi)
+        } else if on_path("dns-sd") {
+            Some(Responder::Bonjour)
+        } else {
+            eprintln!("Skipping zeroconf interop test: neither avahi-publish/avahi-browse nor dns-sd found");
+            None
+        }
+    }
+
+    /// Publish a service until the returned process is dropped
+    fn publish(self, name: &str, port: u16, txt: &[&str]) -> Child {
+        let mut command = match self {
+            Responder::Avahi => {
+                let mut command = Command::new("avahi-publish");
+                command.args(["-s", name, SERVICE_TYPE, &port.to_string()]);
+                command
+            }
+            Responder::Bonjour => {
+                let mut command = Command::new("dns-sd");
+                command.args(["-R", name, SERVICE_TYPE, "local", &port.to_string()]);
+                command
+            }
+        };
+        command
+            .args(txt)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("failed to run the publishing tool")
+    }
+
+    /// Resolve instance `name` with the system's tools, giving up after `DEADLINE`
+    async fn resolve(self, name: &str) -> Option<Resolved> {
+        let mut command = match self {
+            Responder::Avahi => {
+                let mut command = Command::new("avahi-browse");
+                command.args(["--resolve", "--parsable", SERVICE_TYPE]);
+                command
+            }
+            Responder::Bonjour => {
+                let mut command = Command::new("dns-sd");
+                command.args(["-L", name, SERVICE_TYPE, "local"]);
+                command
+            }
+        };
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("failed to run the browsing tool");
+        let mut lines = BufReader::new(child.stdout.take()?).lines();
+
+        time::timeout(DEADLINE, async {
+            let mut reached = None;
+            while let Ok(Some(line)) = lines.next_line().await {
+                match self {
+                    // =;eth0;IPv4;name;type;domain;host;address;port;"txt" "txt"
+                    Responder::Avahi => {
+                        let fields: Vec<&str> = line.splitn(10, ';').collect();
+                        if fields.len() == 10 && fields[0] == "=" && unescape(fields[3]) == name {
+                            return Some(Resolved {
+                                port: fields[8].parse().ok()?,
+                                txt: fields[9].split_whitespace().map(|t| t.trim_matches('"').to_string()).collect(),
+                            });
+                        }
+                    }
+                    // "<time> <name>._adinterop._tcp.local. can be reached at <host>:<port> (interface 2)"
+                    // followed by a line of TXT strings
+                    Responder::Bonjour => {
+                        if let Some(port) = reached {
+                            return Some(Resolved {
+                                port,
+                                txt: line.split_whitespace().map(str::to_string).collect(),
+                            });
+                        }
+                        if let Some((_, at)) = line.split_once(" can be reached at ") {
+                            let address = at.split_whitespace().next()?;
+                            reached = Some(address.rsplit_once(':')?.1.parse().ok()?);
+                        }
+                    }
+                }
+            }
+            None
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
+
+fn on_path(tool: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| dir.join(tool).is_file() || dir.join(format!("{tool}.exe")).is_file())
+    })
+}
+
+/// Undo avahi-browse's `\DDD` escaping of instance names
+fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        let digits: String = chars.by_ref().take(3).collect();
+        match digits.parse::<u8>() {
+            Ok(byte) => unescaped.push(char::from(byte)),
+            Err(_) => unescaped.push_str(&digits),
+        }
+    }
+    unescaped
+}
+
+/// An instance name no other test run uses
+fn instance_name(test: &str) -> String {
+    format!("autodisc {test} {}", std::process::id())
+}
+
+/// An address the system responder can reach us on
+fn lan_address() -> IpAddr {
+    network::get_network_interfaces()
+        .ok()
+        .into_iter()
+        .flatten()
+        .filter(|interface| interface.is_up && interface.supports_multicast)
+        .flat_map(|interface| interface.ipv4_addresses)
+        .find(|address| !address.is_loopback())
+        .map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V4)
+}
+
+#[tokio::test]
+async fn test_finds_services_published_by_the_system() -> Result<()> {
+    let Some(responder) = Responder::detect() else {
+        return Ok(());
+    };
+    let name = instance_name("published");
+    let _publisher = responder.publish(&name, 48123, &["version=1.0", "interop=yes"]);
+
+    let mdns = MdnsProtocol::new(&DiscoveryConfig::default()).await?;
+    let service_type = ServiceType::new(SERVICE_TYPE)?;
+    let deadline = time::Instant::now() + DEADLINE;
+    let found = loop {
+        let services = mdns.discover_services(vec![service_type.clone()], Some(Duration::from_secs(2))).await?;
+        if let Some(service) = services.into_iter().find(|service| service.name == name) {
+            break service;
+        }
+        assert!(time::Instant::now() < deadline, "{responder:?} published {name:?}, but it was not found");
+    };
+
+    assert_eq!(found.port, 48123);
+    assert_eq!(found.get_attribute("version").map(String::as_str), Some("1.0"));
+    assert_eq!(found.get_attribute("interop").map(String::as_str), Some("yes"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_system_finds_our_services() -> Result<()> {
+    let Some(responder) = Responder::detect() else {
+        return Ok(());
+    };
+    let name = instance_name("registered");
+    let mdns = MdnsProtocol::new(&DiscoveryConfig::default()).await?;
+    let service = ServiceInfo::new(&name, SERVICE_TYPE, 48124, Some(vec![("version", "2.1"), ("interop", "yes")]))?
+        .with_address(lan_address());
+    mdns.register_service(service.clone()).await?;
+
+    let resolved = responder.resolve(&name).await;
+    mdns.unregister_service(&service).await?;
+
+    let resolved = resolved.unwrap_or_else(|| panic!("{responder:?} did not find {name:?}"));
+    assert_eq!(resolved.port, 48124);
+    assert!(resolved.txt.iter().any(|txt| txt == "version=2.1"), "TXT records: {:?}", resolved.txt);
+    assert!(resolved.txt.iter().any(|txt| txt == "interop=yes"), "TXT records: {:?}", resolved.txt);
+    Ok(())
+}
+
+#[test]
+fn test_unescapes_avahi_names() {
+    assert_eq!(unescape(r"autodisc\032published\0321234"), "autodisc published 1234");
+    assert_eq!(unescape(r"a\.b"), "a.b");
+}
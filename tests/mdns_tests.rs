@@ -6,7 +6,7 @@ use auto_discovery::{
     types::{ProtocolType, ServiceType},
 };
 use std::{net::IpAddr, str::FromStr, time::Duration};
-use tokio::time;
+use tokio::{net::TcpListener, time};
 
 #[tokio::test]
 async fn test_mdns_protocol_lifecycle() -> Result<()> {
@@ -66,10 +66,13 @@ async fn test_mdns_service_verification() -> Result<()> {
     let registry = std::sync::Arc::new(auto_discovery::registry::ServiceRegistry::new());
     mdns.set_registry(registry);
     
+    // Verification connects to the service, so give it something to accept on
+    let endpoint = TcpListener::bind("127.0.0.1:0").await?;
+    
     let service = ServiceInfo::new(
         "test-verify-service",
         "_test._tcp",
-        8081,
+        endpoint.local_addr()?.port(),
         Some(vec![("version", "1.0")])
     )?
     .with_address(IpAddr::from_str("127.0.0.1").map_err(|e| auto_discovery::error::DiscoveryError::network(e.to_string()))?)
@@ -80,8 +83,9 @@ async fn test_mdns_service_verification() -> Result<()> {
     // Verify service is alive
     assert!(mdns.verify_service(&service).await?);
     
-    // Unregister and verify it's gone
+    // Unregister, shut the service down and verify it's gone
     mdns.unregister_service(&service).await?;
+    drop(endpoint);
     
     // Allow time for unregistration
     time::sleep(Duration::from_millis(100)).await;
@@ -133,7 +137,7 @@ async fn test_mdns_multiple_services() -> Result<()> {
     let mut services = Vec::new();
     for i in 1..=3 {
         let service = ServiceInfo::new(
-            &format!("test-service-{}", i),
+            format!("test-service-{}", i),
             "_test._tcp",
             8080 + i as u16,
             Some(vec![("instance", &i.to_string())])
@@ -177,10 +181,11 @@ async fn test_mdns_reconnection() -> Result<()> {
     drop(mdns);
     let mdns = MdnsProtocol::new(&config).await?;
     
+    let endpoint = TcpListener::bind("127.0.0.1:0").await?;
     let service = ServiceInfo::new(
         "reconnect-test",
         "_test._tcp",
-        8082,
+        endpoint.local_addr()?.port(),
         None
     )?
     .with_address(IpAddr::from_str("127.0.0.1").map_err(|e| auto_discovery::error::DiscoveryError::network(e.to_string()))?)
@@ -193,3 +198,20 @@ async fn test_mdns_reconnection() -> Result<()> {
     
     Ok(())
 }
+
+#[tokio::test]
+async fn test_mdns_enumerate_service_types() -> Result<()> {
+    let config = DiscoveryConfig::default();
+    let mdns = MdnsProtocol::new(&config).await?;
+
+    let service = ServiceInfo::new("enum-service", "_enumtest._tcp", 8085, None)?
+        .with_address(IpAddr::from_str("127.0.0.1").map_err(|e| auto_discovery::error::DiscoveryError::network(e.to_string()))?)
+        .with_protocol_type(ProtocolType::Mdns);
+    mdns.register_service(service.clone()).await?;
+
+    let service_types = mdns.enumerate_service_types(Duration::from_millis(500)).await?;
+    assert!(service_types.contains(&ServiceType::new("_enumtest._tcp")?));
+
+    mdns.unregister_service(&service).await?;
+    Ok(())
+}